@@ -0,0 +1,2 @@
+DROP INDEX users_username_id_index;
+DROP INDEX users_created_at_id_index;
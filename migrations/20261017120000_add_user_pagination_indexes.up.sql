@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS users_created_at_id_index ON users (created_at, id);
+CREATE INDEX IF NOT EXISTS users_username_id_index ON users (username, id);
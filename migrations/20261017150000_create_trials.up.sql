@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS trials (
+    user_id UUID PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    plan VARCHAR(32) NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    reminder_days INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS trials_ends_at_index ON trials (ends_at);
@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS promo_codes (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_by UUID REFERENCES users (id) ON DELETE SET NULL,
+    code VARCHAR(32) UNIQUE NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    amount INTEGER NOT NULL,
+    currency VARCHAR(3),
+    max_redemptions INTEGER,
+    redemption_count INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ,
+    stripe_coupon_id VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS promo_code_redemptions (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    promo_code_id UUID NOT NULL REFERENCES promo_codes (id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users (id) ON DELETE SET NULL,
+    request_id VARCHAR(128) NOT NULL,
+    client_ip VARCHAR(64),
+    applied_at TIMESTAMPTZ,
+    stripe_subscription_id VARCHAR(255),
+    UNIQUE (promo_code_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS promo_code_redemptions_user_id_index
+    ON promo_code_redemptions (user_id);
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS announcements (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_by UUID REFERENCES users (id) ON DELETE SET NULL,
+    message TEXT NOT NULL,
+    severity VARCHAR(32) NOT NULL,
+    audience VARCHAR(32) NOT NULL,
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS announcements_starts_at_index ON announcements (starts_at);
+
+CREATE TABLE IF NOT EXISTS announcement_dismissals (
+    announcement_id UUID NOT NULL REFERENCES announcements (id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (announcement_id, user_id)
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS feedback (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    user_id UUID REFERENCES users (id) ON DELETE SET NULL,
+    category VARCHAR(32) NOT NULL,
+    message TEXT NOT NULL,
+    metadata TEXT NOT NULL,
+    status VARCHAR(32) NOT NULL DEFAULT 'new',
+    request_id VARCHAR(128) NOT NULL,
+    client_ip VARCHAR(64)
+);
+
+CREATE INDEX IF NOT EXISTS feedback_status_created_at_index ON feedback (status, created_at);
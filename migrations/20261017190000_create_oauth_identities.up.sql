@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS oauth_identities (
+    provider VARCHAR(32) NOT NULL,
+    provider_user_id VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    email VARCHAR(255),
+    PRIMARY KEY (provider, provider_user_id),
+    UNIQUE (user_id, provider)
+);
@@ -0,0 +1,5 @@
+DROP TRIGGER trials_row_change ON trials;
+DROP TRIGGER subscriptions_row_change ON subscriptions;
+DROP TRIGGER user_roles_row_change ON user_roles;
+DROP TRIGGER users_row_change ON users;
+DROP FUNCTION notify_row_change();
@@ -0,0 +1,32 @@
+-- Notifies listeners on the "row_changes" channel whenever a row of a table with this trigger
+-- changes. The trigger's argument names the column identifying the row.
+CREATE OR REPLACE FUNCTION notify_row_change() RETURNS TRIGGER AS $$
+DECLARE
+    changed JSONB;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := to_jsonb(OLD);
+    ELSE
+        changed := to_jsonb(NEW);
+    END IF;
+
+    PERFORM pg_notify(
+        'row_changes',
+        jsonb_build_object(
+            'table', TG_TABLE_NAME,
+            'operation', TG_OP,
+            'key', changed ->> TG_ARGV[0]
+        )::TEXT
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_row_change AFTER INSERT OR UPDATE OR DELETE ON users
+    FOR EACH ROW EXECUTE FUNCTION notify_row_change('id');
+CREATE TRIGGER user_roles_row_change AFTER INSERT OR UPDATE OR DELETE ON user_roles
+    FOR EACH ROW EXECUTE FUNCTION notify_row_change('user_id');
+CREATE TRIGGER subscriptions_row_change AFTER INSERT OR UPDATE OR DELETE ON subscriptions
+    FOR EACH ROW EXECUTE FUNCTION notify_row_change('user_id');
+CREATE TRIGGER trials_row_change AFTER INSERT OR UPDATE OR DELETE ON trials
+    FOR EACH ROW EXECUTE FUNCTION notify_row_change('user_id');
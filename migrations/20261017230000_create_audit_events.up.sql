@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS audit_events (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    kind VARCHAR(64) NOT NULL,
+    -- Not foreign keys, so events outlive the users they are about.
+    user_id UUID,
+    actor_id UUID,
+    metadata TEXT NOT NULL,
+    request_id VARCHAR(128) NOT NULL,
+    client_ip VARCHAR(64),
+    user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS audit_events_created_at_index ON audit_events (created_at);
+CREATE INDEX IF NOT EXISTS audit_events_user_id_created_at_index ON audit_events (user_id, created_at);
+CREATE INDEX IF NOT EXISTS audit_events_kind_created_at_index ON audit_events (kind, created_at);
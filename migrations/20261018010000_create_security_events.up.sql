@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS security_events (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Unlike audit events, these are only shown to the user, so they are deleted along with them.
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    kind VARCHAR(64) NOT NULL,
+    client_ip VARCHAR(64),
+    user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS security_events_user_id_created_at_index ON security_events (user_id, created_at);
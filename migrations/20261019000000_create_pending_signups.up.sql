@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS pending_signups (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    username VARCHAR(255) NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    password_hash VARCHAR(255) NOT NULL,
+    signals TEXT[] NOT NULL,
+    client_ip VARCHAR(64),
+    user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS pending_signups_created_at_index ON pending_signups (created_at);
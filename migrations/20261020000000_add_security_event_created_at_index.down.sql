@@ -0,0 +1 @@
+DROP INDEX security_events_created_at_index;
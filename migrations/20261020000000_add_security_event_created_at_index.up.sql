@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS security_events_created_at_index ON security_events (created_at);
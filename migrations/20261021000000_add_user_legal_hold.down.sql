@@ -0,0 +1,2 @@
+DROP INDEX users_legal_hold_at_index;
+ALTER TABLE users DROP COLUMN legal_hold_at;
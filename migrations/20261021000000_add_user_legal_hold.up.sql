@@ -0,0 +1,3 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS legal_hold_at TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS users_legal_hold_at_index ON users (legal_hold_at)
+    WHERE legal_hold_at IS NOT NULL;
@@ -0,0 +1,3 @@
+DROP TRIGGER users_record_version ON users;
+DROP FUNCTION record_user_version();
+DROP TABLE user_history;
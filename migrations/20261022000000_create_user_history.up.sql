@@ -0,0 +1,34 @@
+-- Every version of each user, so admins can see what an account looked like in the past. A version
+-- is valid from when it was written until it was replaced or the user was permanently deleted.
+-- Password hashes aren't kept, so old passwords can't be cracked from the history.
+CREATE TABLE IF NOT EXISTS user_history (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    -- History is kept after users are deleted, so it doesn't reference them.
+    user_id UUID NOT NULL,
+    data JSONB NOT NULL,
+    valid_from TIMESTAMPTZ NOT NULL,
+    valid_to TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS user_history_user_id_valid_from_index ON user_history (user_id, valid_from);
+
+CREATE OR REPLACE FUNCTION record_user_version() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP <> 'INSERT' THEN
+        UPDATE user_history SET valid_to = NOW() WHERE user_id = OLD.id AND valid_to IS NULL;
+    END IF;
+
+    IF TG_OP <> 'DELETE' THEN
+        INSERT INTO user_history (user_id, data, valid_from)
+        VALUES (NEW.id, to_jsonb(NEW) - 'password_hash', NOW());
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_record_version AFTER INSERT OR UPDATE OR DELETE ON users
+    FOR EACH ROW EXECUTE FUNCTION record_user_version();
+
+-- Users that already exist have looked the way they do now since they were last updated.
+INSERT INTO user_history (user_id, data, valid_from)
+SELECT id, to_jsonb(users) - 'password_hash', updated_at FROM users;
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS pending_actions (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    kind VARCHAR(64) NOT NULL,
+    -- The user that scheduled the action.
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    -- The ID of what the action is done to, such as the user whose account is deleted.
+    target_id UUID NOT NULL,
+    undo_token VARCHAR(64) NOT NULL UNIQUE,
+    execute_at TIMESTAMPTZ NOT NULL,
+    UNIQUE (kind, target_id)
+);
+
+CREATE INDEX IF NOT EXISTS pending_actions_execute_at_index ON pending_actions (execute_at);
+CREATE INDEX IF NOT EXISTS pending_actions_user_id_index ON pending_actions (user_id);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS webhook_events (
+    id UUID PRIMARY KEY,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    provider VARCHAR(32) NOT NULL,
+    -- The ID the provider gave the event, which it resends the event with.
+    event_id VARCHAR(255) NOT NULL,
+    kind VARCHAR(255) NOT NULL,
+    -- The body of the request exactly as it was received.
+    payload TEXT NOT NULL,
+    processed_at TIMESTAMPTZ,
+    -- Why the event was last processed unsuccessfully, if it was.
+    error TEXT,
+    UNIQUE (provider, event_id)
+);
+
+CREATE INDEX IF NOT EXISTS webhook_events_received_at_index ON webhook_events (received_at);
@@ -0,0 +1,4 @@
+DROP TRIGGER users_update_search_document ON users;
+DROP FUNCTION update_user_search_document();
+DROP FUNCTION user_search_document(TEXT, TEXT);
+DROP TABLE user_search_documents;
@@ -0,0 +1,32 @@
+-- The words each user can be found by in full-text searches, weighted so username matches rank
+-- above email address matches. These are kept apart from users, so queries selecting every column
+-- of users and the user history don't carry them.
+CREATE TABLE IF NOT EXISTS user_search_documents (
+    user_id UUID PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    document TSVECTOR NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS user_search_documents_document_index ON user_search_documents USING GIN (document);
+
+-- Usernames and email addresses are indexed whole, and split into the words they are made of, so
+-- searches can match "example" in "jane@example.com" or "doe" in "jane_doe".
+CREATE OR REPLACE FUNCTION user_search_document(username TEXT, email TEXT) RETURNS TSVECTOR AS $$
+    SELECT setweight(to_tsvector('simple', username || ' ' || regexp_replace(username, '[^[:alnum:]]+', ' ', 'g')), 'A')
+        || setweight(to_tsvector('simple', email || ' ' || regexp_replace(email, '[^[:alnum:]]+', ' ', 'g')), 'B');
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION update_user_search_document() RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO user_search_documents (user_id, document)
+    VALUES (NEW.id, user_search_document(NEW.username, NEW.email))
+    ON CONFLICT (user_id) DO UPDATE SET document = EXCLUDED.document;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_update_search_document AFTER INSERT OR UPDATE OF username, email ON users
+    FOR EACH ROW EXECUTE FUNCTION update_user_search_document();
+
+INSERT INTO user_search_documents (user_id, document)
+SELECT id, user_search_document(username, email) FROM users
+ON CONFLICT (user_id) DO NOTHING;
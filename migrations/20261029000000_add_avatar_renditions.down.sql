@@ -0,0 +1,2 @@
+DROP TABLE avatar_jobs;
+ALTER TABLE users DROP COLUMN avatar_sizes;
@@ -0,0 +1,20 @@
+-- The sizes of the square WebP renditions stored for each user's avatar. This is null until the
+-- avatar has been processed, and empty if it couldn't be.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_sizes INTEGER[];
+
+-- Uploaded avatars waiting to be processed into renditions by the avatar worker.
+CREATE TABLE IF NOT EXISTS avatar_jobs (
+    avatar_path VARCHAR(255) PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    run_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS avatar_jobs_run_at_index ON avatar_jobs (run_at);
+CREATE INDEX IF NOT EXISTS avatar_jobs_user_id_index ON avatar_jobs (user_id);
+
+-- Avatars uploaded before renditions existed are processed too.
+INSERT INTO avatar_jobs (avatar_path, user_id)
+SELECT avatar_path, id FROM users WHERE avatar_path IS NOT NULL
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,24 @@
+-- Email settings an organization overrides the server's with, for emails sent on its behalf. Unset
+-- columns fall back to the server's settings.
+CREATE TABLE IF NOT EXISTS organization_email_settings (
+    organization_id UUID PRIMARY KEY REFERENCES organizations (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    from_name VARCHAR(64),
+    from_address VARCHAR(255),
+    -- The SMTP server is either fully set or not at all.
+    smtp_host VARCHAR(255),
+    smtp_port INTEGER,
+    smtp_username VARCHAR(255),
+    -- The SMTP password or provider API key, encrypted with the server's email settings key. The
+    -- random nonce it was encrypted with comes first.
+    smtp_password_encrypted BYTEA,
+    logo_url VARCHAR(2048),
+    brand_color VARCHAR(7),
+    CHECK (
+        (smtp_host IS NULL AND smtp_port IS NULL AND smtp_username IS NULL
+            AND smtp_password_encrypted IS NULL)
+        OR (smtp_host IS NOT NULL AND smtp_port IS NOT NULL AND smtp_username IS NOT NULL
+            AND smtp_password_encrypted IS NOT NULL)
+    )
+);
@@ -0,0 +1,22 @@
+-- Custom hostnames organizations serve the API on. A hostname only routes to its organization once
+-- the organization has proven it owns it with a DNS TXT record. Several organizations can claim the
+-- same hostname, but only one of them can verify it.
+CREATE TABLE IF NOT EXISTS organization_domains (
+    organization_id UUID NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    hostname VARCHAR(253) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- The value the organization must publish in the hostname's TXT challenge record.
+    verification_token VARCHAR(64) NOT NULL,
+    verified_at TIMESTAMPTZ,
+    -- When the challenge record was last looked up, and when it will next be. Verified hostnames
+    -- are checked again periodically, and lose their verification once the record is removed.
+    checked_at TIMESTAMPTZ,
+    check_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- The number of lookups in a row that didn't find the record.
+    failed_checks INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (organization_id, hostname)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS organization_domains_verified_hostname_index
+ON organization_domains (hostname) WHERE verified_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS organization_domains_check_at_index ON organization_domains (check_at);
@@ -0,0 +1,71 @@
+use anyhow::{anyhow, Result};
+use sqlx::{query, query_as, PgPool};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::models::User;
+use crate::password_policy::InvalidPassword;
+use crate::roles::Role;
+use crate::schema::validate_password;
+
+/// Create a user with the admin role, so a fresh deployment can be managed without writing SQL by
+/// hand. The user's email address is marked as verified, and no verification email or trial is
+/// started for them. This will return an error if the details are invalid or the username is
+/// taken, including by a deleted user.
+pub async fn create_admin(
+    db: &PgPool,
+    config: &Config,
+    username: &str,
+    email: &str,
+    password: &str,
+) -> Result<User> {
+    if username.is_empty() {
+        return Err(anyhow!("Username cannot be empty."));
+    }
+    if email.is_empty() {
+        return Err(anyhow!("Email cannot be empty."));
+    }
+    if let Some(InvalidPassword { message, .. }) =
+        validate_password(config, password, &[username, email])
+    {
+        return Err(anyhow!(message));
+    }
+
+    let is_taken = query!(
+        r#"SELECT EXISTS (SELECT 1 FROM users WHERE username = $1) AS "exists!""#,
+        username
+    )
+    .fetch_one(db)
+    .await?
+    .exists;
+    if is_taken {
+        return Err(anyhow!("Username is already in use."));
+    }
+
+    let password_hash = bcrypt::hash(password, config.password_hash_cost)?;
+    let mut transaction = db.begin().await?;
+    let user = query_as!(
+        User,
+        "
+        INSERT INTO users (id, username, email, email_verified_at, password_hash)
+        VALUES ($1, $2, $3, NOW(), $4)
+        RETURNING *
+        ",
+        Uuid::new_v4(),
+        username,
+        email,
+        password_hash,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+    query!(
+        "INSERT INTO user_roles (user_id, role) VALUES ($1, $2)",
+        user.id,
+        Role::Admin.as_str(),
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(user)
+}
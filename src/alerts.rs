@@ -0,0 +1,273 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_std::{future, task};
+use serde_json::json;
+use tide::log;
+
+use crate::executor::Executor;
+use crate::graphql::request_id;
+use crate::state::State;
+
+/// Name operators are addressed by in alert emails.
+pub const EMAIL_RECIPIENT_NAME: &str = "operators";
+/// How long to wait for the alert webhook to respond.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Minimum number of responses handled between checks for their error rate to be alerted on, so
+/// a single error on a quiet server doesn't look like an anomaly.
+const MIN_RESPONSES: u64 = 50;
+/// Minimum number of email delivery attempts between checks for their failure rate to be alerted
+/// on.
+const MIN_EMAIL_DELIVERIES: u64 = 10;
+
+/// A condition operators are alerted about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertKind {
+    /// Many login attempts failed across every server instance, such as during a credential
+    /// stuffing attack.
+    FailedLogins,
+    /// Many attempts to deliver emails failed, such as when the SMTP server is down or rejecting
+    /// emails.
+    EmailDeliveryFailures,
+    /// Most of the database connections of a server instance are in use.
+    DatabasePoolSaturation,
+    /// Many requests handled by a server instance failed with a server error.
+    ErrorRate,
+}
+
+impl AlertKind {
+    /// Get the name of the alert, used in Redis keys and sent to operators.
+    pub fn name(self) -> &'static str {
+        match self {
+            AlertKind::FailedLogins => "failed-logins",
+            AlertKind::EmailDeliveryFailures => "email-delivery-failures",
+            AlertKind::DatabasePoolSaturation => "database-pool-saturation",
+            AlertKind::ErrorRate => "error-rate",
+        }
+    }
+}
+
+/// A threshold that was crossed, to be sent to operators.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    /// The value that crossed the threshold, such as a number of failed logins or a percentage.
+    pub value: f64,
+    pub threshold: f64,
+    /// A description of what happened, for operators to read.
+    pub message: String,
+}
+
+/// Thresholds crossing which alerts operators. A threshold of zero disables its alert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlertThresholds {
+    /// The number of failed logins across every server instance between checks.
+    pub failed_logins: u32,
+    /// The percentage of email delivery attempts that failed between checks.
+    pub email_failure_rate_percent: u32,
+    /// The percentage of the database connection pool in use when checked.
+    pub database_pool_saturation_percent: u32,
+    /// The percentage of responses with a server error between checks.
+    pub error_rate_percent: u32,
+}
+
+/// What happened on this server instance since alerts were last checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlertCounts {
+    pub responses: u64,
+    pub server_errors: u64,
+    pub emails_delivered: u64,
+    pub emails_failed: u64,
+}
+
+/// Counts events on this server instance that alerts are checked against. This is cheap to
+/// clone, and clones share the same counts.
+#[derive(Clone, Default)]
+pub struct AlertCounters {
+    inner: Arc<AlertCountersInner>,
+}
+
+#[derive(Default)]
+struct AlertCountersInner {
+    responses: AtomicU64,
+    server_errors: AtomicU64,
+    emails_delivered: AtomicU64,
+    emails_failed: AtomicU64,
+}
+
+impl AlertCounters {
+    /// Count a response sent with an HTTP status.
+    pub fn record_response(&self, status: u16) {
+        self.inner.responses.fetch_add(1, Ordering::Relaxed);
+        if status >= 500 {
+            self.inner.server_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count an attempt to deliver an email.
+    pub fn record_email_delivery(&self, is_delivered: bool) {
+        let counter = if is_delivered {
+            &self.inner.emails_delivered
+        } else {
+            &self.inner.emails_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the counts since they were last taken, resetting them.
+    pub fn take(&self) -> AlertCounts {
+        AlertCounts {
+            responses: self.inner.responses.swap(0, Ordering::Relaxed),
+            server_errors: self.inner.server_errors.swap(0, Ordering::Relaxed),
+            emails_delivered: self.inner.emails_delivered.swap(0, Ordering::Relaxed),
+            emails_failed: self.inner.emails_failed.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Check every threshold against what happened since alerts were last checked, returning an alert
+/// for each threshold that was crossed. Rates are only checked once enough happened for them to
+/// be meaningful.
+pub fn evaluate(
+    thresholds: &AlertThresholds,
+    counts: &AlertCounts,
+    failed_logins: i64,
+    pool_connections_in_use: u32,
+    pool_max_connections: u32,
+) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+
+    let threshold = thresholds.failed_logins;
+    if threshold > 0 && failed_logins >= threshold.into() {
+        alerts.push(Alert {
+            kind: AlertKind::FailedLogins,
+            value: failed_logins as f64,
+            threshold: threshold.into(),
+            message: format!(
+                "{} login attempts failed since the last check, reaching the threshold of {}.",
+                failed_logins, threshold
+            ),
+        });
+    }
+
+    let threshold = thresholds.email_failure_rate_percent;
+    let deliveries = counts.emails_delivered + counts.emails_failed;
+    if threshold > 0 && deliveries >= MIN_EMAIL_DELIVERIES {
+        let rate = percentage(counts.emails_failed, deliveries);
+        if rate >= threshold.into() {
+            alerts.push(Alert {
+                kind: AlertKind::EmailDeliveryFailures,
+                value: rate,
+                threshold: threshold.into(),
+                message: format!(
+                    "{} of {} email delivery attempts ({:.1}%) failed since the last check, \
+                    reaching the threshold of {}%.",
+                    counts.emails_failed, deliveries, rate, threshold
+                ),
+            });
+        }
+    }
+
+    let threshold = thresholds.database_pool_saturation_percent;
+    if threshold > 0 && pool_max_connections > 0 {
+        let saturation = percentage(pool_connections_in_use.into(), pool_max_connections.into());
+        if saturation >= threshold.into() {
+            alerts.push(Alert {
+                kind: AlertKind::DatabasePoolSaturation,
+                value: saturation,
+                threshold: threshold.into(),
+                message: format!(
+                    "{} of {} database connections ({:.1}%) are in use, reaching the threshold \
+                    of {}%.",
+                    pool_connections_in_use, pool_max_connections, saturation, threshold
+                ),
+            });
+        }
+    }
+
+    let threshold = thresholds.error_rate_percent;
+    if threshold > 0 && counts.responses >= MIN_RESPONSES {
+        let rate = percentage(counts.server_errors, counts.responses);
+        if rate >= threshold.into() {
+            alerts.push(Alert {
+                kind: AlertKind::ErrorRate,
+                value: rate,
+                threshold: threshold.into(),
+                message: format!(
+                    "{} of {} responses ({:.1}%) were server errors since the last check, \
+                    reaching the threshold of {}%.",
+                    counts.server_errors, counts.responses, rate, threshold
+                ),
+            });
+        }
+    }
+
+    alerts
+}
+
+/// Get the Redis key set while alerts of a kind are cooling down after one was sent.
+pub fn cooldown_key(kind: AlertKind) -> String {
+    format!("alerts/{}/cooldown", kind.name())
+}
+
+/// Post an alert to a webhook as JSON. The "text" field holds a description of the alert, so it
+/// can be posted to a Slack incoming webhook directly. This will return an error if the webhook
+/// doesn't respond successfully in time.
+pub async fn post_to_webhook(url: &str, sender_name: &str, alert: &Alert) -> Result<()> {
+    let body = json!({
+        "text": format!("[{}] {}", sender_name, alert.message),
+        "alert": alert.kind.name(),
+        "value": alert.value,
+        "threshold": alert.threshold,
+    });
+    let request =
+        surf::post(url).body(surf::Body::from_json(&body).map_err(|error| error.into_inner())?);
+    let response = future::timeout(WEBHOOK_TIMEOUT, request)
+        .await
+        .map_err(|_| anyhow!("Alert webhook timed out"))?
+        .map_err(|error| error.into_inner())?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Alert webhook responded with status {}",
+            response.status()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Get a count as a percentage of a total.
+fn percentage(count: u64, total: u64) -> f64 {
+    count as f64 * 100.0 / total as f64
+}
+
+/// Periodically check whether any alert thresholds were crossed, and alert operators about those
+/// that were. Alerts are disabled if the check interval is zero. Each check is handled as its own request, so the alerts it sends can be traced back
+/// to its log lines. This runs until the server stops.
+pub async fn run_alert_worker(state: State) {
+    let interval = Duration::from_secs(state.config.alert_check_interval_seconds.into());
+    if interval.as_secs() == 0 {
+        log::info!("Alerts are disabled, as the alert check interval is zero.");
+        return;
+    }
+    loop {
+        task::sleep(interval).await;
+
+        let executor = Executor::new(state.clone(), request_id(None), None, None);
+        let counts = state.alert_counters.take();
+        let alerts = match executor.check_alerts(&counts).await {
+            Ok(alerts) => alerts,
+            Err(error) => {
+                log::error!("Failed to check alerts: {}", error);
+                continue;
+            }
+        };
+        for alert in alerts {
+            if let Err(error) = executor.send_alert(&alert).await {
+                log::error!("Failed to send {} alert: {}", alert.kind.name(), error);
+            }
+        }
+    }
+}
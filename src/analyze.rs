@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use sqlx::{query_as, PgPool};
+
+/// Indexes the executor's queries rely on, as the table, the indexed columns in order, and the
+/// queries that need them. Queries filtering on several columns need an index starting with those
+/// columns, and keyset pagination needs an index on the sort column followed by the ID.
+const QUERY_INDEXES: &[(&str, &[&str], &str)] = &[
+    (
+        "users",
+        &["username"],
+        "users are looked up by username when logging in",
+    ),
+    (
+        "users",
+        &["email"],
+        "users are looked up by email address when resetting passwords and linking OAuth accounts",
+    ),
+    (
+        "users",
+        &["created_at", "id"],
+        "users are paginated and exported by creation date",
+    ),
+    (
+        "users",
+        &["username", "id"],
+        "users are paginated by username",
+    ),
+    ("user_roles", &["user_id"], "roles are looked up by user"),
+    (
+        "promo_codes",
+        &["code"],
+        "promo codes are looked up by code when redeemed",
+    ),
+    (
+        "promo_code_redemptions",
+        &["promo_code_id"],
+        "redemptions are listed by promo code",
+    ),
+    (
+        "promo_code_redemptions",
+        &["user_id"],
+        "redemptions are listed by user",
+    ),
+    (
+        "trials",
+        &["ends_at"],
+        "trials ending soon are scanned by the reminder worker",
+    ),
+    (
+        "announcements",
+        &["starts_at"],
+        "announcements are listed by start date",
+    ),
+    (
+        "feedback",
+        &["status", "created_at"],
+        "feedback is paginated by status and creation date",
+    ),
+    (
+        "oauth_identities",
+        &["provider", "provider_user_id"],
+        "OAuth accounts are looked up by provider ID when logging in",
+    ),
+    (
+        "audit_events",
+        &["created_at"],
+        "expired audit events are purged by creation date",
+    ),
+    (
+        "security_events",
+        &["created_at"],
+        "expired security events are purged by creation date",
+    ),
+    (
+        "pending_actions",
+        &["execute_at"],
+        "pending actions are claimed once their grace period is over",
+    ),
+    (
+        "webhook_events",
+        &["received_at"],
+        "webhook events are listed by when they were received",
+    ),
+    (
+        "memberships",
+        &["user_id"],
+        "memberships are listed by user",
+    ),
+    (
+        "user_history",
+        &["user_id", "valid_from"],
+        "versions of users are looked up by user and time",
+    ),
+    (
+        "user_search_documents",
+        &["document"],
+        "users are searched by the words in their usernames and email addresses",
+    ),
+];
+
+/// Every table in the current schema.
+const TABLES_QUERY: &str = "SELECT relname::TEXT
+    FROM pg_class
+    WHERE relkind IN ('r', 'p') AND relnamespace = current_schema()::REGNAMESPACE";
+
+/// The columns of every plain index in the current schema, in order. Partial and expression
+/// indexes are left out, as they can't serve every query on their columns.
+const INDEXES_QUERY: &str = "SELECT table_class.relname::TEXT, ARRAY_AGG(attribute.attname::TEXT ORDER BY key.position)
+    FROM pg_index AS table_index
+    JOIN pg_class AS table_class ON table_class.oid = table_index.indrelid
+    CROSS JOIN LATERAL UNNEST(table_index.indkey::SMALLINT[]) WITH ORDINALITY AS key (number, position)
+    JOIN pg_attribute AS attribute
+        ON attribute.attrelid = table_index.indrelid AND attribute.attnum = key.number
+    WHERE table_class.relnamespace = current_schema()::REGNAMESPACE
+        AND table_index.indpred IS NULL
+        AND table_index.indexprs IS NULL
+    GROUP BY table_index.indexrelid, table_class.relname";
+
+/// The referencing columns of every foreign key in the current schema, in order, along with the
+/// table they reference.
+const FOREIGN_KEYS_QUERY: &str = "SELECT table_class.relname::TEXT, ARRAY_AGG(attribute.attname::TEXT ORDER BY key.position), referenced_class.relname::TEXT
+    FROM pg_constraint AS foreign_key
+    JOIN pg_class AS table_class ON table_class.oid = foreign_key.conrelid
+    JOIN pg_class AS referenced_class ON referenced_class.oid = foreign_key.confrelid
+    CROSS JOIN LATERAL UNNEST(foreign_key.conkey) WITH ORDINALITY AS key (number, position)
+    JOIN pg_attribute AS attribute
+        ON attribute.attrelid = foreign_key.conrelid AND attribute.attnum = key.number
+    WHERE foreign_key.contype = 'f' AND foreign_key.connamespace = current_schema()::REGNAMESPACE
+    GROUP BY foreign_key.oid, table_class.relname, referenced_class.relname";
+
+/// An index a table should have for queries on it to avoid scanning the whole table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexSuggestion {
+    /// The table to index.
+    pub table: String,
+    /// The columns to index, in order.
+    pub columns: Vec<String>,
+    /// Why the index is needed.
+    pub reason: String,
+}
+
+impl IndexSuggestion {
+    /// The statement creating the index. Indexes are created concurrently so they can be added to
+    /// a live database without locking the table against writes.
+    pub fn create_statement(&self) -> String {
+        format!(
+            "CREATE INDEX CONCURRENTLY IF NOT EXISTS {}_{}_index ON {} ({});",
+            self.table,
+            self.columns.join("_"),
+            self.table,
+            self.columns.join(", ")
+        )
+    }
+
+    /// Specifies if an index on the specified columns can serve the queries needing this index.
+    /// Indexes can serve queries on any leading subset of their columns.
+    fn is_covered_by(&self, index: &[String]) -> bool {
+        index.starts_with(&self.columns)
+    }
+}
+
+/// Inspect a database for indexes implied by the executor's queries and by foreign keys that are
+/// missing. Foreign keys need an index on their referencing columns, as deleting a referenced row
+/// scans the referencing table for rows to update. Tables that don't exist are skipped.
+pub async fn find_missing_indexes(db: &PgPool) -> Result<Vec<IndexSuggestion>> {
+    let tables = query_as::<_, (String,)>(TABLES_QUERY)
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|(table,)| table)
+        .collect::<HashSet<_>>();
+    let indexes = query_as::<_, (String, Vec<String>)>(INDEXES_QUERY)
+        .fetch_all(db)
+        .await?;
+    let foreign_keys = query_as::<_, (String, Vec<String>, String)>(FOREIGN_KEYS_QUERY)
+        .fetch_all(db)
+        .await?;
+
+    let required = QUERY_INDEXES
+        .iter()
+        .filter(|(table, _, _)| tables.contains(*table))
+        .map(|(table, columns, reason)| IndexSuggestion {
+            table: table.to_string(),
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            reason: reason.to_string(),
+        })
+        .chain(
+            foreign_keys
+                .into_iter()
+                .map(|(table, columns, referenced)| IndexSuggestion {
+                    reason: format!(
+                        "{} references {}, so deleting from {} scans it",
+                        columns.join(", "),
+                        referenced,
+                        referenced
+                    ),
+                    table,
+                    columns,
+                }),
+        );
+
+    let mut missing: Vec<IndexSuggestion> = Vec::new();
+    for suggestion in required {
+        let is_covered = indexes
+            .iter()
+            .filter(|(table, _)| *table == suggestion.table)
+            .any(|(_, columns)| suggestion.is_covered_by(columns));
+        let is_duplicate = missing
+            .iter()
+            .any(|other| other.table == suggestion.table && other.columns == suggestion.columns);
+        if !is_covered && !is_duplicate {
+            missing.push(suggestion);
+        }
+    }
+
+    Ok(missing)
+}
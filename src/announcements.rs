@@ -0,0 +1,111 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use juniper::GraphQLEnum;
+
+/// Maximum length of an announcement's message.
+pub const MAX_MESSAGE_LENGTH: usize = 1000;
+
+/// How important an announcement is. Clients can use this to decide how to display it.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "How important an announcement is.")]
+pub enum Severity {
+    #[graphql(description = "General information, such as release notes.")]
+    Info,
+    #[graphql(description = "Something users should be aware of, such as planned maintenance.")]
+    Warning,
+    #[graphql(description = "Something affecting users right now, such as an outage.")]
+    Critical,
+}
+
+impl Severity {
+    /// The name the severity is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Severity {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(anyhow!("Unknown severity: {}", value)),
+        }
+    }
+}
+
+/// Who an announcement is shown to.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "Who an announcement is shown to.")]
+pub enum Audience {
+    #[graphql(description = "Everyone, whether they are logged in or not.")]
+    Everyone,
+    #[graphql(description = "Only requests that aren't made by a logged in user.")]
+    Guests,
+    #[graphql(description = "Only logged in users.")]
+    Users,
+    #[graphql(description = "Only admins.")]
+    Admins,
+}
+
+impl Audience {
+    /// The name the audience is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Audience::Everyone => "everyone",
+            Audience::Guests => "guests",
+            Audience::Users => "users",
+            Audience::Admins => "admins",
+        }
+    }
+
+    /// Get the audiences a request belongs to, depending on if it was made by a logged in user and
+    /// if that user is an admin.
+    pub fn of(is_user: bool, is_admin: bool) -> Vec<Self> {
+        let mut audiences = vec![Audience::Everyone];
+        if !is_user {
+            audiences.push(Audience::Guests);
+        } else {
+            audiences.push(Audience::Users);
+            if is_admin {
+                audiences.push(Audience::Admins);
+            }
+        }
+        audiences
+    }
+}
+
+impl Display for Audience {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Audience {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "everyone" => Ok(Audience::Everyone),
+            "guests" => Ok(Audience::Guests),
+            "users" => Ok(Audience::Users),
+            "admins" => Ok(Audience::Admins),
+            _ => Err(anyhow!("Unknown audience: {}", value)),
+        }
+    }
+}
@@ -0,0 +1,3813 @@
+use anyhow::{anyhow, Result};
+use async_graphql::{
+    EmptySubscription, Enum, Error, ErrorExtensions, FieldResult, InputObject, InputValueError,
+    InputValueResult, Object, OneofObject, Scalar, ScalarType, Schema, SimpleObject, Value,
+};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::announcements;
+use crate::audit;
+use crate::auth::{
+    ActiveSession, AuthenticatedUser, LoginMethod, LoginResult, ResendVerificationResult,
+};
+use crate::avatars;
+use crate::billing::{self, CheckoutSession, PlanDetails};
+use crate::concurrency::{
+    LimitedResolver, ResolverPermit, RESOLVER_BUSY_ERROR_CODE, RESOLVER_BUSY_ERROR_MESSAGE,
+};
+use crate::context::Context;
+use crate::deprecations::{DeprecatedFieldClient, DeprecatedFieldUsage};
+use crate::domains;
+use crate::email::outbox::{self, OutboxEmail};
+use crate::email::settings::{self, validate_email_settings};
+use crate::errors::ApiError;
+use crate::feedback;
+use crate::models::{
+    Announcement, AuditEvent, Feedback, Membership, Organization, OrganizationDomain,
+    OrganizationEmailSettings, Passkey, PendingAction, PendingSignup, PromoCode,
+    PromoCodeRedemption, SecurityEvent, Subscription, Trial, User, WebhookEvent,
+};
+use crate::organizations;
+use crate::pagination::{self, PageInfo, PageRequest, UserConnection, UserEdge};
+use crate::password_policy::InvalidPassword;
+use crate::pending_actions;
+use crate::plugin::Plugin;
+use crate::policy::{
+    self, FieldPolicy, UNAUTHORIZED_FIELD_ERROR_CODE, UNAUTHORIZED_FIELD_ERROR_MESSAGE,
+};
+use crate::promo_codes;
+use crate::query_stats::{self, QueryStatistics};
+use crate::retention;
+use crate::roles::{self, Access};
+use crate::scanning::{FILE_REJECTED_ERROR_CODE, FILE_REJECTED_ERROR_MESSAGE};
+use crate::schema::{
+    convert_membership_result, convert_redeem_result, seconds_until, validate_announcement,
+    validate_avatar, validate_feedback, validate_full_text_search, validate_new_promo_code,
+    validate_new_user, validate_organization_name, validate_page, validate_password,
+    validate_profile_update, validate_query_count, validate_user_search,
+    ACCOUNT_DEACTIVATED_ERROR_MESSAGE, ACCOUNT_LOCKED_ERROR_MESSAGE,
+    FEEDBACK_RATE_LIMITED_ERROR_MESSAGE, PASSWORD_RESET_REQUIRED_ERROR_MESSAGE,
+    QUERY_STATISTICS_UNAVAILABLE_ERROR_MESSAGE, READ_ONLY_ERROR_MESSAGE,
+    VERIFICATION_EMAIL_THROTTLED_ERROR_MESSAGE,
+};
+use crate::security_events;
+use crate::signup_screening::{
+    self, SignupEvidence, SignupResult, CAPTCHA_REQUIRED_ERROR_MESSAGE,
+    SIGNUP_PENDING_REVIEW_ERROR_MESSAGE,
+};
+use crate::uploads::{Upload, FILE_NOT_UPLOADED_ERROR_CODE, FILE_NOT_UPLOADED_ERROR_MESSAGE};
+use crate::usage::Usage;
+use crate::user_search::{self, UserSearchHit};
+use crate::webhooks;
+
+/// Type of the executable async-graphql schema. This mirrors the juniper schema exposed by
+/// default. Fields contributed by plugins are juniper fields, so they can't be part of it.
+pub type AsyncSchema = Schema<Query, Mutation, EmptySubscription>;
+
+/// Build the executable async-graphql schema.
+pub fn build_async_schema() -> AsyncSchema {
+    Schema::build(Query, Mutation, EmptySubscription).finish()
+}
+
+/// Check that none of the provided plugins contribute GraphQL fields, which the async-graphql
+/// schema can't include. This will return an error naming the plugins that do, so the server
+/// refuses to start rather than serving a schema that is silently missing their fields.
+pub fn check_plugins(plugins: &[Box<dyn Plugin>]) -> Result<()> {
+    let names = plugins
+        .iter()
+        .filter(|plugin| plugin.query().is_some() || plugin.mutation().is_some())
+        .map(|plugin| plugin.name())
+        .collect::<Vec<_>>();
+    if !names.is_empty() {
+        return Err(anyhow!(
+            "Plugins contributing GraphQL fields can't be used with the \"async-graphql\" \
+            feature: {}",
+            names.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Create a GraphQL error with a stable error code extension.
+fn error(message: &str, code: &'static str) -> Error {
+    Error::new(message).extend_with(|_, extensions| extensions.set("code", code))
+}
+
+/// Create an error for a password rejected by the password policy, with feedback to help the user
+/// choose a better one.
+fn invalid_password(
+    InvalidPassword {
+        message,
+        code,
+        warning,
+        suggestions,
+    }: InvalidPassword,
+) -> Error {
+    Error::new(message).extend_with(|_, extensions| {
+        extensions.set("code", code);
+        extensions.set("warning", warning.clone());
+        extensions.set("suggestions", suggestions.clone());
+    })
+}
+
+/// Convert a generic "anyhow" result into a GraphQL field result.
+fn convert_result<T>(result: Result<T>) -> FieldResult<T> {
+    result.map_err(|error| ApiError::from(error).into())
+}
+
+/// Get the context for the current request.
+fn context<'a>(ctx: &async_graphql::Context<'a>) -> &'a Context {
+    ctx.data_unchecked::<Context>()
+}
+
+/// Create an error for a request that must be made by a logged in user.
+fn unauthenticated() -> Error {
+    error("You must be logged in to do this.", "unauthenticated")
+}
+
+/// Get the user making a request. This will return an error suitable for clients if the request
+/// wasn't made by a logged in user.
+fn require_viewer(ctx: &async_graphql::Context<'_>) -> FieldResult<AuthenticatedUser> {
+    context(ctx).viewer().copied().ok_or_else(unauthenticated)
+}
+
+/// Ensure the user making a request has a role. This will return an error suitable for clients if
+/// the request wasn't made by a logged in user or the user doesn't have the role.
+async fn require_role(ctx: &async_graphql::Context<'_>, role: roles::Role) -> FieldResult<()> {
+    match convert_result(context(ctx).access(role).await)? {
+        Access::Granted => Ok(()),
+        Access::Unauthenticated => Err(unauthenticated()),
+        Access::Forbidden => Err(error("You do not have permission to do this.", "forbidden")),
+    }
+}
+
+/// Ensure the user making a request can make changes. This will return an error suitable for
+/// clients if the request wasn't made by a logged in user or the user only has read-only access
+/// because their trial has ended.
+async fn require_write_access(ctx: &async_graphql::Context<'_>) -> FieldResult<()> {
+    match convert_result(context(ctx).write_access().await)? {
+        Access::Granted => Ok(()),
+        Access::Unauthenticated => Err(unauthenticated()),
+        Access::Forbidden => Err(error(READ_ONLY_ERROR_MESSAGE, "read-only")),
+    }
+}
+
+/// Wait for a slot to call an expensive resolver. This will return an error suitable for clients if
+/// too many calls to the resolver are already running and none finish before the queue timeout.
+async fn acquire_permit(
+    ctx: &async_graphql::Context<'_>,
+    resolver: LimitedResolver,
+) -> FieldResult<ResolverPermit> {
+    context(ctx)
+        .executor()
+        .acquire_permit(resolver)
+        .await
+        .ok_or_else(|| error(RESOLVER_BUSY_ERROR_MESSAGE, RESOLVER_BUSY_ERROR_CODE))
+}
+
+/// Create an error for a login attempt rejected because the user has been deactivated.
+fn account_deactivated() -> Error {
+    error(ACCOUNT_DEACTIVATED_ERROR_MESSAGE, "account-deactivated")
+}
+
+/// Create an error for a login attempt rejected because the user is required to reset their
+/// password.
+fn password_reset_required() -> Error {
+    error(
+        PASSWORD_RESET_REQUIRED_ERROR_MESSAGE,
+        "password-reset-required",
+    )
+}
+
+/// Convert a list of roles into their GraphQL representation.
+fn convert_roles(roles: Option<Vec<roles::Role>>) -> FieldResult<Vec<Role>> {
+    roles
+        .map(|roles| roles.into_iter().map(Into::into).collect())
+        .ok_or_else(|| error("User not found.", "user-not-found"))
+}
+
+/// A UUID scalar, named to match the juniper schema.
+pub struct UuidScalar(Uuid);
+
+/// Uuid
+#[Scalar(name = "Uuid")]
+impl ScalarType for UuidScalar {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(string) => Uuid::parse_str(string)
+                .map(UuidScalar)
+                .map_err(|_| InputValueError::expected_type(value)),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.to_string())
+    }
+}
+
+/// A file upload scalar, named to match the juniper schema. This holds the name of the multipart
+/// field the file was sent in.
+pub struct UploadScalar(Upload);
+
+/// A file sent in a multipart request, as described by the GraphQL multipart request
+/// specification.
+#[Scalar(name = "Upload")]
+impl ScalarType for UploadScalar {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match value {
+            Value::String(name) => Ok(UploadScalar(Upload(name))),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0 .0.clone())
+    }
+}
+
+/// A UTC timestamp scalar, named to match the juniper schema.
+pub struct DateTimeUtc(DateTime<Utc>);
+
+/// DateTime
+#[Scalar(name = "DateTimeUtc")]
+impl ScalarType for DateTimeUtc {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(string) => string
+                .parse()
+                .map(DateTimeUtc)
+                .map_err(|_| InputValueError::expected_type(value)),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.to_rfc3339())
+    }
+}
+
+/// An image format avatar renditions are stored in.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "avatars::AvatarFormat")]
+pub enum AvatarFormat {
+    /// Lossless WebP, which every current browser can show.
+    Webp,
+    /// Lossy AVIF, which is smaller than WebP but can't be shown by older browsers.
+    Avif,
+}
+
+/// Information about a user.
+pub struct UserObject(User);
+
+/// Information about a user.
+#[Object(name = "User")]
+impl UserObject {
+    /// The unique ID of the user.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the user was created.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// Date when the user was last updated.
+    async fn updated_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.updated_at)
+    }
+
+    /// The user's username.
+    async fn username(&self) -> &str {
+        &self.0.username
+    }
+
+    /// The user's email address. This will be null for users other than the user themselves and
+    /// admins.
+    async fn email(&self, ctx: &async_graphql::Context<'_>) -> FieldResult<Option<&str>> {
+        authorize_field(
+            ctx,
+            policy::USER_EMAIL,
+            self.0.id,
+            Some(self.0.email.as_str()),
+        )
+        .await
+    }
+
+    /// Date when the user's email address was last verified. This will be null if the email has
+    /// not been verified yet. Only the user themselves and admins can see this.
+    async fn email_verified_at(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Option<DateTimeUtc>> {
+        let value = self.0.email_verified_at.map(DateTimeUtc);
+        authorize_field(ctx, policy::USER_EMAIL_VERIFIED_AT, self.0.id, value).await
+    }
+
+    /// A URL the user's avatar can be downloaded from until it expires, as a square image. This
+    /// will be null if the user hasn't uploaded an avatar, or if it hasn't been processed yet.
+    async fn avatar_url(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(
+            desc = "The size in pixels the avatar will be shown at. The smallest rendition at least this large is picked, or the largest if none is. By default, the largest rendition is picked."
+        )]
+        size: Option<i32>,
+        #[graphql(desc = "The format of the rendition. Defaults to WebP.")] format: Option<
+            AvatarFormat,
+        >,
+    ) -> Option<String> {
+        let path = self.0.avatar_path.as_ref()?;
+        let size = avatars::closest_size(self.0.avatar_sizes.as_deref()?, size)?;
+        let format = format.unwrap_or(AvatarFormat::Webp).into();
+        context(ctx)
+            .executor()
+            .files()
+            .url(&avatars::rendition_path(path, size, format))
+    }
+
+    /// Date when the user was deactivated. Deactivated users can't log in. This will be null if the
+    /// user is active.
+    async fn deactivated_at(&self) -> Option<DateTimeUtc> {
+        self.0.deactivated_at.map(DateTimeUtc)
+    }
+
+    /// Date when the user was deleted. Deleted users are only returned to admins that ask for them.
+    /// This will be null if the user hasn't been deleted.
+    async fn deleted_at(&self) -> Option<DateTimeUtc> {
+        self.0.deleted_at.map(DateTimeUtc)
+    }
+
+    /// Date when an admin required the user to reset their password. The user can't log in with
+    /// their password until they reset it. This will be null if no reset is required. Only the
+    /// user themselves and admins can see this.
+    async fn password_reset_required_at(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Option<DateTimeUtc>> {
+        let value = self.0.password_reset_required_at.map(DateTimeUtc);
+        authorize_field(
+            ctx,
+            policy::USER_PASSWORD_RESET_REQUIRED_AT,
+            self.0.id,
+            value,
+        )
+        .await
+    }
+
+    /// Date when an admin placed the user under a legal hold, exempting them from retention purges
+    /// and deletion. This will be null if the user isn't held, and is always null for users that
+    /// aren't admins.
+    async fn legal_hold_at(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Option<DateTimeUtc>> {
+        let value = self.0.legal_hold_at.map(DateTimeUtc);
+        authorize_field(ctx, policy::USER_LEGAL_HOLD_AT, self.0.id, value).await
+    }
+}
+
+/// Orderings that can be used when paginating through users.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "pagination::UserOrder")]
+pub enum UserOrder {
+    /// Oldest users first.
+    CreatedAtAsc,
+    /// Newest users first.
+    CreatedAtDesc,
+    /// Users ordered alphabetically by username.
+    UsernameAsc,
+    /// Users ordered reverse alphabetically by username.
+    UsernameDesc,
+}
+
+/// Conditions users must meet to be included in a search. Every condition that is set must be met.
+#[derive(InputObject)]
+pub struct UserFilter {
+    /// Only include users whose username starts with this, ignoring case.
+    username_prefix: Option<String>,
+    /// Only include users whose email address is at this domain, such as "example.com", ignoring
+    /// case.
+    email_domain: Option<String>,
+    /// Only include users that have, or haven't, verified their email.
+    email_verified: Option<bool>,
+    /// Only include users created at or after this time.
+    created_after: Option<DateTimeUtc>,
+    /// Only include users created before this time.
+    created_before: Option<DateTimeUtc>,
+    /// Include users that have been deleted. Defaults to false.
+    include_deleted: Option<bool>,
+}
+
+impl From<UserFilter> for user_search::UserFilter {
+    fn from(filter: UserFilter) -> Self {
+        user_search::UserFilter {
+            username_prefix: filter.username_prefix,
+            email_domain: filter.email_domain,
+            email_verified: filter.email_verified,
+            created_after: filter.created_after.map(|created_after| created_after.0),
+            created_before: filter.created_before.map(|created_before| created_before.0),
+            include_deleted: filter.include_deleted,
+        }
+    }
+}
+
+/// Fields users can be sorted by in a search.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "user_search::UserSortField")]
+pub enum UserSortField {
+    /// When the user was created.
+    CreatedAt,
+    /// The user's username.
+    Username,
+    /// The user's email address.
+    Email,
+    /// When the user verified their email. Users that haven't are sorted last.
+    EmailVerifiedAt,
+}
+
+/// Directions results can be sorted in.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "user_search::SortDirection")]
+pub enum SortDirection {
+    /// Smallest, earliest or alphabetically first values first.
+    Asc,
+    /// Largest, latest or alphabetically last values first.
+    Desc,
+}
+
+/// A field to sort users by, and the direction to sort it in.
+#[derive(InputObject)]
+pub struct UserSort {
+    /// The field to sort by.
+    field: UserSortField,
+    /// The direction to sort in. Defaults to ascending.
+    direction: Option<SortDirection>,
+}
+
+impl From<UserSort> for user_search::UserSort {
+    fn from(sort: UserSort) -> Self {
+        user_search::UserSort {
+            field: sort.field.into(),
+            direction: sort.direction.map(Into::into),
+        }
+    }
+}
+
+/// The credentials of one way of logging in. Exactly one field must be set.
+#[derive(OneofObject)]
+pub enum LoginCredentials {
+    /// Log in with a username and password.
+    Password(PasswordCredentials),
+    /// Log in with a passkey.
+    Passkey(PasskeyCredentials),
+    /// Log in with a ticket issued after logging in with an OAuth provider.
+    #[graphql(name = "oauth")]
+    OAuth(OAuthCredentials),
+}
+
+impl From<LoginCredentials> for LoginMethod {
+    fn from(credentials: LoginCredentials) -> Self {
+        match credentials {
+            LoginCredentials::Password(PasswordCredentials { username, password }) => {
+                LoginMethod::Password { username, password }
+            }
+            LoginCredentials::Passkey(PasskeyCredentials { credential }) => {
+                LoginMethod::Passkey { credential }
+            }
+            LoginCredentials::OAuth(OAuthCredentials { ticket }) => LoginMethod::OAuth { ticket },
+        }
+    }
+}
+
+/// A username and password to log in with.
+#[derive(InputObject)]
+pub struct PasswordCredentials {
+    /// The username of the user to log in as.
+    username: String,
+    /// The user's password.
+    password: String,
+}
+
+/// A passkey credential to log in with.
+#[derive(InputObject)]
+pub struct PasskeyCredentials {
+    /// The JSON-encoded credential returned by "navigator.credentials.get()" for the options from
+    /// "startPasskeyLogin", as serialized by "PublicKeyCredential.toJSON()".
+    credential: String,
+}
+
+/// A ticket issued after logging in with an OAuth provider such as Google or GitHub, to log in
+/// with.
+#[derive(InputObject)]
+#[graphql(name = "OAuthCredentials")]
+pub struct OAuthCredentials {
+    /// The ticket issued after logging in with the provider.
+    ticket: String,
+}
+
+/// A role granting a user access to restricted parts of the API.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "roles::Role")]
+pub enum Role {
+    /// Can manage users and their roles.
+    Admin,
+}
+
+/// A subscription plan.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "billing::Plan")]
+pub enum Plan {
+    /// The plan users are on once their trial ends without subscribing. This only allows
+    /// read-only access.
+    Restricted,
+    /// The plan users without a subscription or trial are on.
+    Free,
+    /// The paid plan.
+    Pro,
+}
+
+/// Details about a plan that can be subscribed to.
+pub struct PlanDetailsObject(PlanDetails);
+
+/// Details about a plan that can be subscribed to.
+#[Object(name = "PlanDetails")]
+impl PlanDetailsObject {
+    /// The plan.
+    async fn plan(&self) -> Plan {
+        self.0.plan.into()
+    }
+
+    /// A human-readable name for the plan.
+    async fn name(&self) -> &str {
+        self.0.plan.name()
+    }
+
+    /// Specifies if the plan can be purchased with a checkout session.
+    async fn is_purchasable(&self) -> bool {
+        self.0.is_purchasable
+    }
+}
+
+/// A user's subscription to a paid plan.
+pub struct SubscriptionObject(Subscription);
+
+/// A user's subscription to a paid plan.
+#[Object(name = "Subscription")]
+impl SubscriptionObject {
+    /// The plan subscribed to.
+    async fn plan(&self) -> FieldResult<Plan> {
+        let plan: billing::Plan = convert_result(self.0.plan.parse())?;
+        Ok(plan.into())
+    }
+
+    /// The status of the subscription, e.g. "active", "past_due" or "canceled".
+    async fn status(&self) -> &str {
+        &self.0.status
+    }
+
+    /// Specifies if the subscription currently entitles the user to its plan.
+    async fn is_active(&self) -> bool {
+        billing::is_active_status(&self.0.status)
+    }
+
+    /// Date when the current billing period ends.
+    async fn current_period_end(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.current_period_end)
+    }
+
+    /// Specifies if the subscription will be canceled at the end of the current billing period.
+    async fn cancel_at_period_end(&self) -> bool {
+        self.0.cancel_at_period_end
+    }
+}
+
+/// A checkout page a user can purchase a subscription on.
+pub struct CheckoutSessionObject(CheckoutSession);
+
+/// A checkout page a user can purchase a subscription on.
+#[Object(name = "CheckoutSession")]
+impl CheckoutSessionObject {
+    /// The ID of the checkout session.
+    async fn id(&self) -> &str {
+        &self.0.id
+    }
+
+    /// The URL of the checkout page the user should be sent to.
+    async fn url(&self) -> &str {
+        &self.0.url
+    }
+}
+
+/// A user's free trial of a paid plan.
+pub struct TrialObject(Trial);
+
+/// A user's free trial of a paid plan.
+#[Object(name = "Trial")]
+impl TrialObject {
+    /// The plan being trialed.
+    async fn plan(&self) -> FieldResult<Plan> {
+        let plan: billing::Plan = convert_result(self.0.plan.parse())?;
+        Ok(plan.into())
+    }
+
+    /// Date when the trial started.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// Date when the trial ends.
+    async fn ends_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.ends_at)
+    }
+
+    /// Specifies if the trial hasn't ended yet. Once it ends, users without a subscription are
+    /// restricted to read-only access.
+    async fn is_active(&self) -> bool {
+        self.0.is_active()
+    }
+}
+
+/// How important an announcement is.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "announcements::Severity")]
+pub enum Severity {
+    /// General information, such as release notes.
+    Info,
+    /// Something users should be aware of, such as planned maintenance.
+    Warning,
+    /// Something affecting users right now, such as an outage.
+    Critical,
+}
+
+/// Who an announcement is shown to.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "announcements::Audience")]
+pub enum Audience {
+    /// Everyone, whether they are logged in or not.
+    Everyone,
+    /// Only requests that aren't made by a logged in user.
+    Guests,
+    /// Only logged in users.
+    Users,
+    /// Only admins.
+    Admins,
+}
+
+/// An announcement shown to users, such as a maintenance notice.
+pub struct AnnouncementObject(Announcement);
+
+/// An announcement shown to users, such as a maintenance notice.
+#[Object(name = "Announcement")]
+impl AnnouncementObject {
+    /// The unique ID of the announcement.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the announcement was created.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// Date when the announcement was last updated.
+    async fn updated_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.updated_at)
+    }
+
+    /// The message to show.
+    async fn message(&self) -> &str {
+        &self.0.message
+    }
+
+    /// How important the announcement is.
+    async fn severity(&self) -> FieldResult<Severity> {
+        let severity: announcements::Severity = convert_result(self.0.severity.parse())?;
+        Ok(severity.into())
+    }
+
+    /// Who the announcement is shown to.
+    async fn audience(&self) -> FieldResult<Audience> {
+        let audience: announcements::Audience = convert_result(self.0.audience.parse())?;
+        Ok(audience.into())
+    }
+
+    /// Date when the announcement starts being shown.
+    async fn starts_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.starts_at)
+    }
+
+    /// Date when the announcement stops being shown. This will be null if it is shown until it is
+    /// deleted.
+    async fn ends_at(&self) -> Option<DateTimeUtc> {
+        self.0.ends_at.map(DateTimeUtc)
+    }
+}
+
+/// Convert announcements into their GraphQL representation.
+fn convert_announcements(announcements: Vec<Announcement>) -> Vec<AnnouncementObject> {
+    announcements.into_iter().map(AnnouncementObject).collect()
+}
+
+/// Create an error for an organization that could not be found, or that the user making the
+/// request isn't a member of.
+fn organization_not_found() -> Error {
+    error("Organization not found.", "organization-not-found")
+}
+
+/// Create an error for a custom hostname that isn't a valid fully qualified domain name.
+fn invalid_hostname() -> Error {
+    error(
+        "Hostnames must be fully qualified domain names, such as \"api.example.com\".",
+        "invalid-hostname",
+    )
+}
+
+/// Create an error for a custom hostname added while organization domains are disabled.
+fn custom_domains_disabled() -> Error {
+    error(
+        "Custom hostnames are not enabled on this server.",
+        "custom-domains-disabled",
+    )
+}
+
+/// Create an error for a custom hostname added to an organization that already has as many as it
+/// can.
+fn too_many_domains() -> Error {
+    error(
+        &format!(
+            "Organizations can't have more than {} custom hostnames.",
+            domains::MAX_DOMAINS_PER_ORGANIZATION
+        ),
+        "too-many-domains",
+    )
+}
+
+/// Ensure the user making a request is an owner or admin of an organization. This will return an
+/// error suitable for clients if they aren't, hiding the organization from users that aren't
+/// members of it.
+async fn require_organization_admin(
+    ctx: &async_graphql::Context<'_>,
+    organization_id: Uuid,
+) -> FieldResult<AuthenticatedUser> {
+    let viewer = require_viewer(ctx)?;
+    let executor = context(ctx).executor();
+    let role = convert_result(
+        executor
+            .find_member_role(organization_id, viewer.user_id)
+            .await,
+    )?
+    .ok_or_else(organization_not_found)?;
+    if role == organizations::OrganizationRole::Member {
+        return Err(error("You do not have permission to do this.", "forbidden"));
+    }
+
+    Ok(viewer)
+}
+
+/// Resolve a field of a record belonging to a user to its value, none, or an "unauthorized" error,
+/// following the field's policy.
+async fn authorize_field<T>(
+    ctx: &async_graphql::Context<'_>,
+    policy: FieldPolicy,
+    owner_id: Uuid,
+    value: Option<T>,
+) -> FieldResult<Option<T>> {
+    let access = convert_result(context(ctx).authorize_field(policy, owner_id).await)?;
+    access.apply(value).map_err(|_| {
+        error(
+            UNAUTHORIZED_FIELD_ERROR_MESSAGE,
+            UNAUTHORIZED_FIELD_ERROR_CODE,
+        )
+    })
+}
+
+/// Create an error for an announcement that could not be found.
+fn announcement_not_found() -> Error {
+    error("Announcement not found.", "announcement-not-found")
+}
+
+/// What feedback is about.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "feedback::FeedbackCategory")]
+pub enum FeedbackCategory {
+    /// Something isn't working as expected.
+    Bug,
+    /// A suggestion for something new.
+    FeatureRequest,
+    /// A question about how something works.
+    Question,
+    /// Anything else.
+    Other,
+}
+
+/// Where feedback is in triage.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "feedback::FeedbackStatus")]
+pub enum FeedbackStatus {
+    /// The feedback hasn't been looked at yet.
+    New,
+    /// The feedback has been looked at and is being acted on.
+    Triaged,
+    /// The feedback has been acted on.
+    Resolved,
+    /// The feedback won't be acted on.
+    Dismissed,
+}
+
+/// A piece of context attached to feedback, such as the app version or the page the user was on.
+#[derive(InputObject)]
+pub struct FeedbackMetadataInput {
+    /// The name of the entry.
+    key: String,
+    /// The value of the entry.
+    value: String,
+}
+
+/// A piece of context attached to feedback.
+#[derive(SimpleObject)]
+pub struct FeedbackMetadataEntry {
+    /// The name of the entry.
+    key: String,
+    /// The value of the entry.
+    value: String,
+}
+
+/// Feedback submitted by a user.
+pub struct FeedbackObject(Feedback);
+
+/// Feedback submitted by a user.
+#[Object(name = "Feedback")]
+impl FeedbackObject {
+    /// The unique ID of the feedback.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the feedback was submitted.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// Date when the feedback was last updated.
+    async fn updated_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.updated_at)
+    }
+
+    /// The ID of the user that submitted the feedback. This will be null if the feedback was
+    /// submitted without logging in or the user was deleted.
+    async fn user_id(&self) -> Option<UuidScalar> {
+        self.0.user_id.map(UuidScalar)
+    }
+
+    /// What the feedback is about.
+    async fn category(&self) -> FieldResult<FeedbackCategory> {
+        let category: feedback::FeedbackCategory = convert_result(self.0.category.parse())?;
+        Ok(category.into())
+    }
+
+    /// The feedback itself.
+    async fn message(&self) -> &str {
+        &self.0.message
+    }
+
+    /// Context attached to the feedback by the client.
+    async fn metadata(&self) -> FieldResult<Vec<FeedbackMetadataEntry>> {
+        let entries = convert_result(self.0.metadata_entries().map_err(Into::into))?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| FeedbackMetadataEntry {
+                key: entry.key,
+                value: entry.value,
+            })
+            .collect())
+    }
+
+    /// Where the feedback is in triage.
+    async fn status(&self) -> FieldResult<FeedbackStatus> {
+        let status: feedback::FeedbackStatus = convert_result(self.0.status.parse())?;
+        Ok(status.into())
+    }
+
+    /// The ID of the request the feedback was submitted in.
+    async fn request_id(&self) -> &str {
+        &self.0.request_id
+    }
+
+    /// The IP address the feedback was submitted from, if known.
+    async fn client_ip(&self) -> Option<&str> {
+        self.0.client_ip.as_deref()
+    }
+}
+
+/// A security-relevant event recorded in the audit log.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "audit::AuditEventKind")]
+pub enum AuditEventKind {
+    /// A user was created.
+    UserCreated,
+    /// A user logged in.
+    Login,
+    /// A login attempt failed.
+    LoginFailed,
+    /// A user logged out.
+    Logout,
+    /// A user changed their password.
+    PasswordChanged,
+    /// A user reset their password with a reset token.
+    PasswordReset,
+    /// An admin required a user to reset their password.
+    PasswordResetForced,
+    /// A user changed their email address.
+    EmailChanged,
+    /// A user verified their email address.
+    EmailVerified,
+    /// A user tried to verify their email address with the wrong code.
+    EmailVerificationFailed,
+    /// A role was granted to a user.
+    RoleGranted,
+    /// A role was revoked from a user.
+    RoleRevoked,
+    /// A session token that was already refreshed was used again, so its session was terminated.
+    SessionTokenReused,
+    /// An admin placed a user under a legal hold.
+    LegalHoldApplied,
+    /// An admin released a user from a legal hold.
+    LegalHoldReleased,
+    /// An admin deactivated a user.
+    UserDeactivated,
+    /// An admin reactivated a deactivated user.
+    UserReactivated,
+    /// A user added a passkey.
+    PasskeyAdded,
+    /// A user removed a passkey.
+    PasskeyRemoved,
+}
+
+/// A detail recorded with an audit event, such as the role that was granted.
+#[derive(SimpleObject)]
+pub struct AuditMetadataEntry {
+    /// The name of the entry.
+    key: String,
+    /// The value of the entry.
+    value: String,
+}
+
+/// A field changed by a sensitive admin action, with its value before and after the change.
+#[derive(SimpleObject)]
+pub struct AuditChange {
+    /// The name of the field, such as "roles".
+    field: String,
+    /// The value of the field before the change, if it had one.
+    before: Option<String>,
+    /// The value of the field after the change, if it has one.
+    after: Option<String>,
+}
+
+/// A security-relevant event recorded in the audit log.
+pub struct AuditEventObject(AuditEvent);
+
+/// A security-relevant event recorded in the audit log.
+#[Object(name = "AuditEvent")]
+impl AuditEventObject {
+    /// The unique ID of the event.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the event happened.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// What happened.
+    async fn kind(&self) -> FieldResult<AuditEventKind> {
+        let kind: audit::AuditEventKind = convert_result(self.0.kind.parse())?;
+        Ok(kind.into())
+    }
+
+    /// The ID of the user the event is about. This will be null for failed logins with a username
+    /// no user has.
+    async fn user_id(&self) -> Option<UuidScalar> {
+        self.0.user_id.map(UuidScalar)
+    }
+
+    /// The ID of the user that caused the event, if known. This differs from the user the event is
+    /// about when an admin acts on another user.
+    async fn actor_id(&self) -> Option<UuidScalar> {
+        self.0.actor_id.map(UuidScalar)
+    }
+
+    /// Details of the event, such as the role that was granted.
+    async fn metadata(&self) -> FieldResult<Vec<AuditMetadataEntry>> {
+        let entries = convert_result(self.0.metadata_entries().map_err(Into::into))?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| AuditMetadataEntry {
+                key: entry.key,
+                value: entry.value,
+            })
+            .collect())
+    }
+
+    /// The fields a sensitive admin action changed, such as the roles of the user before and after
+    /// a role was granted. This is empty for other events.
+    async fn changes(&self) -> FieldResult<Vec<AuditChange>> {
+        let changes = convert_result(self.0.audit_changes().map_err(Into::into))?;
+        Ok(changes
+            .into_iter()
+            .map(|change| AuditChange {
+                field: change.field,
+                before: change.before,
+                after: change.after,
+            })
+            .collect())
+    }
+
+    /// The ID of the request the event happened in.
+    async fn request_id(&self) -> &str {
+        &self.0.request_id
+    }
+
+    /// The IP address the request was sent from, if known.
+    async fn client_ip(&self) -> Option<&str> {
+        self.0.client_ip.as_deref()
+    }
+
+    /// The user agent the request was sent with, if known.
+    async fn user_agent(&self) -> Option<&str> {
+        self.0.user_agent.as_deref()
+    }
+}
+
+/// An event in the security activity of a user's account.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "security_events::SecurityEventKind")]
+pub enum SecurityEventKind {
+    /// The user logged in.
+    Login,
+    /// The user logged in from a device they hadn't logged in from before.
+    NewDevice,
+    /// Someone failed to log in as the user.
+    LoginFailed,
+    /// The user changed their password.
+    PasswordChanged,
+    /// The user reset their password with a reset token.
+    PasswordReset,
+    /// An admin required the user to reset their password.
+    PasswordResetForced,
+    /// The user changed their email address.
+    EmailChanged,
+    /// A session token that was already refreshed was used again, so its session was terminated.
+    SessionTokenReused,
+    /// The user added a passkey.
+    PasskeyAdded,
+    /// The user removed a passkey.
+    PasskeyRemoved,
+}
+
+/// An event in the security activity of a user's account.
+pub struct SecurityEventObject(SecurityEvent);
+
+/// An event in the security activity of a user's account.
+#[Object(name = "SecurityEvent")]
+impl SecurityEventObject {
+    /// The unique ID of the event.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the event happened.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// What happened.
+    async fn kind(&self) -> FieldResult<SecurityEventKind> {
+        let kind: security_events::SecurityEventKind = convert_result(self.0.kind.parse())?;
+        Ok(kind.into())
+    }
+
+    /// The IP address the event came from, if known.
+    async fn client_ip(&self) -> Option<&str> {
+        self.0.client_ip.as_deref()
+    }
+
+    /// The user agent of the device the event came from, such as a browser, if known.
+    async fn user_agent(&self) -> Option<&str> {
+        self.0.user_agent.as_deref()
+    }
+}
+
+/// A destructive action that can be undone until its grace period is over.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "pending_actions::PendingActionKind")]
+pub enum PendingActionKind {
+    /// The user deleted their account.
+    DeleteAccount,
+}
+
+/// A destructive action that can be undone until it is done.
+pub struct PendingActionObject(PendingAction);
+
+/// A destructive action that can be undone until it is done.
+#[Object(name = "PendingAction")]
+impl PendingActionObject {
+    /// What the action does.
+    async fn kind(&self) -> FieldResult<PendingActionKind> {
+        let kind: pending_actions::PendingActionKind = convert_result(self.0.kind.parse())?;
+        Ok(kind.into())
+    }
+
+    /// Date when the grace period is over and the action is done.
+    async fn execute_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.execute_at)
+    }
+
+    /// The token to pass to "undoAction" to undo the action until it is done.
+    async fn undo_token(&self) -> &str {
+        &self.0.undo_token
+    }
+}
+
+/// A service that sends webhook events to the server.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "webhooks::WebhookProvider")]
+pub enum WebhookProvider {
+    /// Stripe, which sends events about subscriptions.
+    Stripe,
+}
+
+/// An event a provider such as Stripe sent to the server's webhook.
+pub struct WebhookEventObject(WebhookEvent);
+
+/// An event a provider such as Stripe sent to the server's webhook.
+#[Object(name = "WebhookEvent")]
+impl WebhookEventObject {
+    /// The unique ID of the webhook event.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the event was first received.
+    async fn received_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.received_at)
+    }
+
+    /// The provider that sent the event.
+    async fn provider(&self) -> FieldResult<WebhookProvider> {
+        let provider: webhooks::WebhookProvider = convert_result(self.0.provider.parse())?;
+        Ok(provider.into())
+    }
+
+    /// The ID the provider gave the event.
+    async fn event_id(&self) -> &str {
+        &self.0.event_id
+    }
+
+    /// What happened according to the provider, such as "customer.subscription.updated".
+    async fn kind(&self) -> &str {
+        &self.0.kind
+    }
+
+    /// The body of the request the event was received in.
+    async fn payload(&self) -> &str {
+        &self.0.payload
+    }
+
+    /// Date when the event was last processed successfully. This will be null if it hasn't been.
+    async fn processed_at(&self) -> Option<DateTimeUtc> {
+        self.0.processed_at.map(DateTimeUtc)
+    }
+
+    /// Why the event was last processed unsuccessfully. This will be null if it succeeded or hasn't
+    /// been processed yet.
+    async fn error(&self) -> Option<&str> {
+        self.0.error.as_deref()
+    }
+}
+
+/// Which part of the outbox an email is in.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "outbox::OutboxStatus")]
+pub enum OutboxStatus {
+    /// The email is waiting to be sent, or to be retried after failing to be delivered.
+    Queued,
+    /// The email couldn't be delivered, and was moved to the dead letter list.
+    Dead,
+}
+
+/// An email in the outbox, with its body redacted.
+pub struct OutboxEmailObject(OutboxEmail);
+
+/// An email in the outbox, with its body redacted.
+#[Object(name = "OutboxEmail")]
+impl OutboxEmailObject {
+    /// The ID distinguishing the email in the outbox.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Which part of the outbox the email is in.
+    async fn status(&self) -> OutboxStatus {
+        self.0.status.into()
+    }
+
+    /// The address the email is sent from.
+    async fn from(&self) -> Option<&str> {
+        self.0.from.as_deref()
+    }
+
+    /// The addresses the email is sent to.
+    async fn to(&self) -> &[String] {
+        &self.0.to
+    }
+
+    /// The subject of the email, if it has one.
+    async fn subject(&self) -> Option<String> {
+        self.0.header("Subject").map(outbox::decode_header)
+    }
+
+    /// The number of failed attempts to deliver the email.
+    async fn attempts(&self) -> i32 {
+        self.0.attempts.min(i32::MAX as u32) as i32
+    }
+
+    /// Date when the email will next be sent. This will be null for dead emails.
+    async fn send_at(&self) -> Option<DateTimeUtc> {
+        self.0.send_at.map(DateTimeUtc)
+    }
+
+    /// The email as it is sent, with only its date, addresses, subject, IDs and content type
+    /// headers. Other headers and the body are redacted, as bodies hold verification codes and
+    /// password reset links.
+    async fn payload(&self) -> String {
+        outbox::payload(&self.0.headers, self.0.body_bytes)
+    }
+}
+
+/// What a member of an organization can do in it.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "organizations::OrganizationRole")]
+pub enum OrganizationRole {
+    /// Can manage every member, including other owners.
+    Owner,
+    /// Can invite, remove and change the roles of members that aren't owners.
+    Admin,
+    /// Can see the organization and its members.
+    Member,
+}
+
+/// An organization users can be members of, such as a team.
+pub struct OrganizationObject(Organization);
+
+/// An organization users can be members of, such as a team.
+#[Object(name = "Organization")]
+impl OrganizationObject {
+    /// The unique ID of the organization.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the organization was created.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// Date when the organization was last updated.
+    async fn updated_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.updated_at)
+    }
+
+    /// The organization's name.
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+}
+
+/// Email settings an organization overrides the server's with, for emails sent on its behalf.
+/// Settings that are null fall back to the server's.
+pub struct OrganizationEmailSettingsObject(OrganizationEmailSettings);
+
+/// Email settings an organization overrides the server's with, for emails sent on its behalf.
+/// Settings that are null fall back to the server's.
+#[Object(name = "OrganizationEmailSettings")]
+impl OrganizationEmailSettingsObject {
+    /// The ID of the organization.
+    async fn organization_id(&self) -> UuidScalar {
+        UuidScalar(self.0.organization_id)
+    }
+
+    /// Date when the settings were last updated.
+    async fn updated_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.updated_at)
+    }
+
+    /// The display name emails are sent from.
+    #[graphql(name = "fromName")]
+    async fn sender_name(&self) -> Option<&str> {
+        self.0.from_name.as_deref()
+    }
+
+    /// The address emails are sent from.
+    async fn from_address(&self) -> Option<&str> {
+        self.0.from_address.as_deref()
+    }
+
+    /// The host name of the SMTP server emails are sent through.
+    async fn smtp_host(&self) -> Option<&str> {
+        self.0.smtp_host.as_deref()
+    }
+
+    /// The port of the SMTP server.
+    async fn smtp_port(&self) -> Option<i32> {
+        self.0.smtp_port
+    }
+
+    /// The username to log in to the SMTP server with.
+    async fn smtp_username(&self) -> Option<&str> {
+        self.0.smtp_username.as_deref()
+    }
+
+    /// Specifies if an SMTP password is set. The password itself can't be read back.
+    async fn has_smtp_password(&self) -> bool {
+        self.0.smtp_password_encrypted.is_some()
+    }
+
+    /// The URL of a logo shown above emails.
+    async fn logo_url(&self) -> Option<&str> {
+        self.0.logo_url.as_deref()
+    }
+
+    /// The color of the bar along the top of emails, as a hex code.
+    async fn brand_color(&self) -> Option<&str> {
+        self.0.brand_color.as_deref()
+    }
+}
+
+/// A custom hostname an organization serves the API on. Requests are only routed to the
+/// organization once it has proven it owns the hostname with a DNS TXT record.
+pub struct OrganizationDomainObject(OrganizationDomain);
+
+/// A custom hostname an organization serves the API on. Requests are only routed to the
+/// organization once it has proven it owns the hostname with a DNS TXT record.
+#[Object(name = "OrganizationDomain")]
+impl OrganizationDomainObject {
+    /// The hostname, such as "api.example.com".
+    async fn hostname(&self) -> &str {
+        &self.0.hostname
+    }
+
+    /// Date when the hostname was added.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// The name of the TXT record to publish to verify the hostname, such as
+    /// "_rust-graphql-server-challenge.api.example.com".
+    async fn challenge_name(&self) -> String {
+        domains::challenge_name(&self.0.hostname)
+    }
+
+    /// The value to publish in the TXT record to verify the hostname.
+    async fn challenge_value(&self) -> &str {
+        &self.0.verification_token
+    }
+
+    /// Date when the hostname was verified. This will be null if it hasn't been yet, or lost its
+    /// verification after its TXT record was removed.
+    async fn verified_at(&self) -> Option<DateTimeUtc> {
+        self.0.verified_at.map(DateTimeUtc)
+    }
+
+    /// Date when the TXT record was last looked up.
+    async fn checked_at(&self) -> Option<DateTimeUtc> {
+        self.0.checked_at.map(DateTimeUtc)
+    }
+
+    /// Date when the TXT record will next be looked up.
+    async fn check_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.check_at)
+    }
+}
+
+/// New email settings for an organization. Settings that aren't set fall back to the server's.
+#[derive(InputObject)]
+pub struct EmailSettingsInput {
+    /// The display name emails are sent from, up to 64 characters.
+    from_name: Option<String>,
+    /// The address emails are sent from. The SMTP server must be allowed to send from it.
+    from_address: Option<String>,
+    /// The host name of the SMTP server emails are sent through. The port, username and password
+    /// must be set along with it.
+    smtp_host: Option<String>,
+    /// The port of the SMTP server. Port 465 uses TLS from the start, and other ports are upgraded
+    /// to TLS with STARTTLS.
+    smtp_port: Option<i32>,
+    /// The username to log in to the SMTP server with.
+    smtp_username: Option<String>,
+    /// The password to log in to the SMTP server with. Email providers that take an API key over
+    /// SMTP, such as SendGrid or Postmark, take it here. This is stored encrypted and can't be
+    /// read back. Leave it null to keep the current password when changing other SMTP settings.
+    smtp_password: Option<String>,
+    /// The HTTPS URL of a logo shown above emails.
+    logo_url: Option<String>,
+    /// The color of the bar along the top of emails, as a hex code such as "#0055ff".
+    brand_color: Option<String>,
+}
+
+impl From<EmailSettingsInput> for settings::EmailSettingsInput {
+    fn from(input: EmailSettingsInput) -> Self {
+        settings::EmailSettingsInput {
+            from_name: input.from_name,
+            from_address: input.from_address,
+            smtp_host: input.smtp_host,
+            smtp_port: input.smtp_port,
+            smtp_username: input.smtp_username,
+            smtp_password: input.smtp_password,
+            logo_url: input.logo_url,
+            brand_color: input.brand_color,
+        }
+    }
+}
+
+/// A user's membership of an organization, or invitation to it.
+pub struct MembershipObject(Membership);
+
+/// A user's membership of an organization, or invitation to it.
+#[Object(name = "Membership")]
+impl MembershipObject {
+    /// The organization.
+    async fn organization(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<OrganizationObject> {
+        let executor = context(ctx).executor();
+        let organization_id = self.0.organization_id;
+        let organization = convert_result(executor.find_organization(organization_id).await)?;
+        convert_result(
+            organization
+                .map(OrganizationObject)
+                .ok_or_else(|| anyhow!("Organization not found: {}", organization_id)),
+        )
+    }
+
+    /// The ID of the member.
+    async fn user_id(&self) -> UuidScalar {
+        UuidScalar(self.0.user_id)
+    }
+
+    /// The member. This will be null if the user has been deleted.
+    async fn user(&self, ctx: &async_graphql::Context<'_>) -> FieldResult<Option<UserObject>> {
+        let user = convert_result(context(ctx).load_user(self.0.user_id).await)?;
+        Ok(user.map(UserObject))
+    }
+
+    /// What the member can do in the organization.
+    async fn role(&self) -> FieldResult<OrganizationRole> {
+        let role: organizations::OrganizationRole = convert_result(self.0.organization_role())?;
+        Ok(role.into())
+    }
+
+    /// Date when the user was invited, or became a member if they created the organization.
+    async fn invited_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// Date when the user accepted their invitation. This will be null if they haven't yet.
+    async fn accepted_at(&self) -> Option<DateTimeUtc> {
+        self.0.accepted_at.map(DateTimeUtc)
+    }
+}
+
+/// A signup waiting for an admin to approve or reject it.
+pub struct PendingSignupObject(PendingSignup);
+
+/// A signup waiting for an admin to approve or reject it.
+#[Object(name = "PendingSignup")]
+impl PendingSignupObject {
+    /// The unique ID of the pending signup.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the signup was made.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// The username the user signed up with.
+    async fn username(&self) -> &str {
+        &self.0.username
+    }
+
+    /// The email address the user signed up with.
+    async fn email(&self) -> &str {
+        &self.0.email
+    }
+
+    /// The signals suggesting the signup was made by a bot, such as "honeypot" or
+    /// "automated-user-agent".
+    async fn signals(&self) -> &[String] {
+        &self.0.signals
+    }
+
+    /// The IP address the signup was sent from, if known.
+    async fn client_ip(&self) -> Option<&str> {
+        self.0.client_ip.as_deref()
+    }
+
+    /// The user agent the signup was sent with, if known.
+    async fn user_agent(&self) -> Option<&str> {
+        self.0.user_agent.as_deref()
+    }
+}
+
+/// The kind of discount a promo code gives.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "promo_codes::PromoCodeKind")]
+pub enum PromoCodeKind {
+    /// Takes a percentage off the price.
+    Percentage,
+    /// Takes a fixed amount off the price.
+    Fixed,
+}
+
+/// A promo code giving a discount on subscriptions.
+pub struct PromoCodeObject(PromoCode);
+
+/// A promo code giving a discount on subscriptions.
+#[Object(name = "PromoCode")]
+impl PromoCodeObject {
+    /// The unique ID of the promo code.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the promo code was created.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// The ID of the admin that created the promo code. This will be null if they were deleted.
+    async fn created_by(&self) -> Option<UuidScalar> {
+        self.0.created_by.map(UuidScalar)
+    }
+
+    /// The code users redeem.
+    async fn code(&self) -> &str {
+        &self.0.code
+    }
+
+    /// The kind of discount the promo code gives.
+    async fn kind(&self) -> FieldResult<PromoCodeKind> {
+        let kind: promo_codes::PromoCodeKind = convert_result(self.0.kind.parse())?;
+        Ok(kind.into())
+    }
+
+    /// The percentage taken off for percentage discounts, or the amount taken off in the smallest
+    /// currency unit, e.g. cents, for fixed discounts.
+    async fn amount(&self) -> i32 {
+        self.0.amount
+    }
+
+    /// The currency of fixed discounts. This will be null for percentage discounts.
+    async fn currency(&self) -> Option<&str> {
+        self.0.currency.as_deref()
+    }
+
+    /// The max number of times the promo code can be redeemed. This will be null if there is no
+    /// limit.
+    async fn max_redemptions(&self) -> Option<i32> {
+        self.0.max_redemptions
+    }
+
+    /// The number of times the promo code has been redeemed.
+    async fn redemption_count(&self) -> i32 {
+        self.0.redemption_count
+    }
+
+    /// Date when the promo code expires. This will be null if it never expires.
+    async fn expires_at(&self) -> Option<DateTimeUtc> {
+        self.0.expires_at.map(DateTimeUtc)
+    }
+}
+
+/// A user redeeming a promo code.
+pub struct PromoCodeRedemptionObject(PromoCodeRedemption);
+
+/// A user redeeming a promo code.
+#[Object(name = "PromoCodeRedemption")]
+impl PromoCodeRedemptionObject {
+    /// The unique ID of the redemption.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the promo code was redeemed.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// The ID of the promo code redeemed.
+    async fn promo_code_id(&self) -> UuidScalar {
+        UuidScalar(self.0.promo_code_id)
+    }
+
+    /// The ID of the user that redeemed the promo code. This will be null if they were deleted.
+    async fn user_id(&self) -> Option<UuidScalar> {
+        self.0.user_id.map(UuidScalar)
+    }
+
+    /// The ID of the request the promo code was redeemed in.
+    async fn request_id(&self) -> &str {
+        &self.0.request_id
+    }
+
+    /// The IP address the promo code was redeemed from, if known.
+    async fn client_ip(&self) -> Option<&str> {
+        self.0.client_ip.as_deref()
+    }
+
+    /// Date when the discount was applied to a subscription. This will be null until the user
+    /// subscribes.
+    async fn applied_at(&self) -> Option<DateTimeUtc> {
+        self.0.applied_at.map(DateTimeUtc)
+    }
+}
+
+/// Orderings that can be used when listing the top queries.
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "query_stats::QueryStatisticsOrder")]
+pub enum QueryStatisticsOrder {
+    /// Queries that spent the most time executing in total first.
+    TotalTime,
+    /// Queries that took the longest to execute on average first.
+    MeanTime,
+    /// The most frequently executed queries first.
+    Calls,
+}
+
+/// Execution statistics of a normalized SQL query.
+pub struct QueryStatisticsObject(QueryStatistics);
+
+/// Execution statistics of a normalized SQL query.
+#[Object(name = "QueryStatistics")]
+impl QueryStatisticsObject {
+    /// The text of the query. Constants are replaced with placeholders, so executions of the same
+    /// query with different values are grouped together.
+    async fn query(&self) -> &str {
+        &self.0.query
+    }
+
+    /// The number of times the query was executed.
+    async fn calls(&self) -> i32 {
+        self.0.calls.min(i32::MAX.into()) as i32
+    }
+
+    /// The total time spent executing the query, in milliseconds.
+    async fn total_time_ms(&self) -> f64 {
+        self.0.total_time_ms
+    }
+
+    /// The mean time spent executing the query, in milliseconds.
+    async fn mean_time_ms(&self) -> f64 {
+        self.0.mean_time_ms
+    }
+
+    /// The total number of rows the query retrieved or affected.
+    async fn rows(&self) -> i32 {
+        self.0.rows.min(i32::MAX.into()) as i32
+    }
+}
+
+/// A client's use of a deprecated field.
+pub struct DeprecatedFieldClientObject(DeprecatedFieldClient);
+
+/// A client's use of a deprecated field.
+#[Object(name = "DeprecatedFieldClient")]
+impl DeprecatedFieldClientObject {
+    /// The user agent of the client, "unknown" if it didn't send one, or "other" for clients past
+    /// the number that are tracked.
+    async fn client(&self) -> &str {
+        &self.0.client
+    }
+
+    /// The number of requests the client used the field in.
+    async fn uses(&self) -> i32 {
+        self.0.uses.min(i32::MAX.into()) as i32
+    }
+
+    /// Date when the client last used the field.
+    async fn last_used_at(&self) -> Option<DateTimeUtc> {
+        self.0.last_used_at.map(DateTimeUtc)
+    }
+}
+
+/// How a deprecated field is being used by clients.
+pub struct DeprecatedFieldUsageObject(DeprecatedFieldUsage);
+
+/// How a deprecated field is being used by clients.
+#[Object(name = "DeprecatedFieldUsage")]
+impl DeprecatedFieldUsageObject {
+    /// The coordinate of the field, such as "User.email".
+    async fn field(&self) -> &str {
+        self.0.deprecation.field
+    }
+
+    /// Date when the field was deprecated.
+    async fn deprecated_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.deprecation.deprecated_at())
+    }
+
+    /// Date after which the field may be removed.
+    async fn sunset_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.deprecation.sunset_at())
+    }
+
+    /// The number of requests that used the field.
+    async fn uses(&self) -> i32 {
+        self.0
+            .clients
+            .iter()
+            .map(|client| client.uses)
+            .sum::<i64>()
+            .min(i32::MAX.into()) as i32
+    }
+
+    /// The clients that used the field, most recent first.
+    async fn clients(&self) -> Vec<DeprecatedFieldClientObject> {
+        self.0
+            .clients
+            .iter()
+            .cloned()
+            .map(DeprecatedFieldClientObject)
+            .collect()
+    }
+}
+
+/// A user's metered usage in the current billing period.
+pub struct UsageObject(Usage);
+
+/// A user's metered usage in the current billing period.
+#[Object(name = "Usage")]
+impl UsageObject {
+    /// The plan the user is on.
+    async fn plan(&self) -> Plan {
+        self.0.plan.into()
+    }
+
+    /// The number of API calls made in the current billing period.
+    async fn api_calls(&self) -> i32 {
+        self.0.api_calls as i32
+    }
+
+    /// The number of API calls after which responses include a warning header. This will be null
+    /// if the plan has no limit on API calls.
+    async fn api_call_soft_limit(&self) -> Option<i32> {
+        self.0.limits.map(|limits| limits.soft as i32)
+    }
+
+    /// The number of API calls after which requests are rejected. This will be null if the plan
+    /// has no limit on API calls.
+    async fn api_call_limit(&self) -> Option<i32> {
+        self.0.limits.map(|limits| limits.hard as i32)
+    }
+
+    /// Date when the current billing period ends and usage is reset.
+    async fn period_end(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.period_end)
+    }
+}
+
+/// An active session of a user.
+pub struct ActiveSessionObject(ActiveSession);
+
+/// An active session of a user.
+#[Object(name = "ActiveSession")]
+impl ActiveSessionObject {
+    /// The ID of the session.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the session was started. This will be null for sessions started before session
+    /// details were recorded.
+    async fn created_at(&self) -> Option<DateTimeUtc> {
+        self.0.created_at.map(DateTimeUtc)
+    }
+
+    /// Date when the session was last used.
+    async fn last_seen_at(&self) -> Option<DateTimeUtc> {
+        self.0.last_seen_at.map(DateTimeUtc)
+    }
+
+    /// The IP address the session was started from, if known.
+    async fn ip(&self) -> Option<&str> {
+        self.0.ip.as_deref()
+    }
+
+    /// The user agent of the client that started the session, if known.
+    async fn user_agent(&self) -> Option<&str> {
+        self.0.user_agent.as_deref()
+    }
+
+    /// Specifies if this is the session making the current request.
+    async fn is_current(&self) -> bool {
+        self.0.is_current
+    }
+}
+
+/// A passkey a user can log in with instead of their password.
+pub struct PasskeyObject(Passkey);
+
+/// A passkey a user can log in with instead of their password.
+#[Object(name = "Passkey")]
+impl PasskeyObject {
+    /// The unique ID of the passkey.
+    async fn id(&self) -> UuidScalar {
+        UuidScalar(self.0.id)
+    }
+
+    /// Date when the passkey was created.
+    async fn created_at(&self) -> DateTimeUtc {
+        DateTimeUtc(self.0.created_at)
+    }
+
+    /// The name the user gave the passkey.
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Date when the passkey was last used to log in. This will be null if it hasn't been used
+    /// yet.
+    async fn last_used_at(&self) -> Option<DateTimeUtc> {
+        self.0.last_used_at.map(DateTimeUtc)
+    }
+}
+
+/// Information about a page of results.
+pub struct PageInfoObject(PageInfo);
+
+/// Information about a page of results.
+#[Object(name = "PageInfo")]
+impl PageInfoObject {
+    /// Specifies if there are more results after this page.
+    async fn has_next_page(&self) -> bool {
+        self.0.has_next_page
+    }
+
+    /// Specifies if there are more results before this page.
+    async fn has_previous_page(&self) -> bool {
+        self.0.has_previous_page
+    }
+
+    /// The cursor of the first result in the page.
+    async fn start_cursor(&self) -> Option<&str> {
+        self.0.start_cursor.as_deref()
+    }
+
+    /// The cursor of the last result in the page.
+    async fn end_cursor(&self) -> Option<&str> {
+        self.0.end_cursor.as_deref()
+    }
+}
+
+/// A user in a page of users, along with its cursor.
+pub struct UserEdgeObject(UserEdge);
+
+/// A user in a page of users, along with its cursor.
+#[Object(name = "UserEdge")]
+impl UserEdgeObject {
+    /// A cursor pointing at the user.
+    async fn cursor(&self) -> &str {
+        &self.0.cursor
+    }
+
+    /// The user.
+    async fn node(&self) -> UserObject {
+        UserObject(self.0.node.clone())
+    }
+}
+
+/// A user matching a full-text search, along with a snippet showing what matched.
+pub struct UserSearchHitObject(UserSearchHit);
+
+/// A user matching a full-text search, along with a snippet showing what matched.
+#[Object(name = "UserSearchHit")]
+impl UserSearchHitObject {
+    /// The user.
+    async fn user(&self) -> UserObject {
+        UserObject(self.0.user.clone())
+    }
+
+    /// The user's username and email address, as "username <email>", with the parts of words that
+    /// matched wrapped in "<mark>" tags. The rest of the snippet is HTML-escaped, so it can be
+    /// shown as HTML.
+    async fn snippet(&self) -> &str {
+        &self.0.snippet
+    }
+}
+
+/// A page of users.
+pub struct UserConnectionObject(UserConnection);
+
+/// A page of users.
+#[Object(name = "UserConnection")]
+impl UserConnectionObject {
+    /// The users in the page.
+    async fn edges(&self) -> Vec<UserEdgeObject> {
+        self.0.edges.iter().cloned().map(UserEdgeObject).collect()
+    }
+
+    /// Information about the page.
+    async fn page_info(&self) -> PageInfoObject {
+        PageInfoObject(self.0.page_info.clone())
+    }
+
+    /// The total number of users across every page.
+    async fn total_count(&self) -> i32 {
+        self.0.total_count as i32
+    }
+}
+
+/// The result of a successful authentication action.
+pub struct AuthResult {
+    session_token: Option<String>,
+}
+
+/// The result of a successful authentication action.
+#[Object]
+impl AuthResult {
+    /// The session token to be used for future requests. This should be sent as a bearer token in
+    /// the 'authorization' header. This will be null when session tokens are delivered as HTTP-only
+    /// cookies, which browsers send along with future requests.
+    async fn session_token(&self) -> Option<&str> {
+        self.session_token.as_deref()
+    }
+}
+
+/// All available GraphQL queries.
+pub struct Query;
+
+/// All available GraphQL queries.
+#[Object]
+impl Query {
+    /// Find a user by their ID. Deleted users are left out unless "includeDeleted" is set.
+    async fn user(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The user's ID.")] id: UuidScalar,
+        #[graphql(
+            desc = "Find the user even if they have been deleted. Only admins can find deleted users."
+        )]
+        include_deleted: Option<bool>,
+    ) -> FieldResult<Option<UserObject>> {
+        let user = if include_deleted == Some(true) {
+            require_role(ctx, roles::Role::Admin).await?;
+            let executor = context(ctx).executor();
+            convert_result(executor.find_user_including_deleted(id.0).await)?
+        } else {
+            convert_result(context(ctx).load_user(id.0).await)?
+        };
+        Ok(user.map(UserObject))
+    }
+
+    /// Find a user by their username. Deleted users are left out unless "includeDeleted" is set.
+    async fn user_by_username(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The user's username.")] username: String,
+        #[graphql(
+            desc = "Find the user even if they have been deleted. Only admins can find deleted users."
+        )]
+        include_deleted: Option<bool>,
+    ) -> FieldResult<Option<UserObject>> {
+        let user = if include_deleted == Some(true) {
+            require_role(ctx, roles::Role::Admin).await?;
+            let executor = context(ctx).executor();
+            convert_result(
+                executor
+                    .find_user_by_username_including_deleted(&username)
+                    .await,
+            )?
+        } else {
+            convert_result(context(ctx).load_user_by_username(&username).await)?
+        };
+        Ok(user.map(UserObject))
+    }
+
+    /// Find what a user looked like at a moment in the past, including deleted users. This will
+    /// return null if the user didn't exist at that moment. Only admins can look at the history of
+    /// users.
+    async fn user_at_time(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The user's ID.")] id: UuidScalar,
+        #[graphql(desc = "The moment to look at the user as of.")] timestamp: DateTimeUtc,
+    ) -> FieldResult<Option<UserObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let executor = context(ctx).executor();
+        let user = convert_result(executor.find_user_at_time(id.0, timestamp.0).await)?;
+        Ok(user.map(UserObject))
+    }
+
+    /// Get the user making the request. This will return null if the request wasn't sent with a
+    /// valid session token.
+    async fn me(&self, ctx: &async_graphql::Context<'_>) -> FieldResult<Option<UserObject>> {
+        let context = context(ctx);
+        match context.viewer() {
+            Some(viewer) => {
+                let user = convert_result(context.load_user(viewer.user_id).await)?;
+                Ok(user.map(UserObject))
+            }
+            None => Ok(None),
+        }
+    }
+    /// List the active sessions of the user making the request, most recently used first.
+    async fn my_sessions(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Vec<ActiveSessionObject>> {
+        let viewer = require_viewer(ctx)?;
+        let sessions = convert_result(context(ctx).executor().find_sessions(viewer).await)?;
+        Ok(sessions.into_iter().map(ActiveSessionObject).collect())
+    }
+
+    /// List the passkeys of the user making the request, oldest first.
+    async fn my_passkeys(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Vec<PasskeyObject>> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let passkeys = convert_result(executor.find_passkeys_by_user(viewer.user_id).await)?;
+        Ok(passkeys.into_iter().map(PasskeyObject).collect())
+    }
+
+    /// Get a token for a signup form being loaded, to be sent back with "createUser". Signups
+    /// without one, or sent too soon after it was issued, look like they were made by a bot.
+    async fn signup_form_token(&self, ctx: &async_graphql::Context<'_>) -> String {
+        context(ctx).executor().signup_form_token()
+    }
+
+    /// List signups that looked like they were made by a bot and are waiting to be approved or
+    /// rejected, oldest first. Only admins can list pending signups.
+    async fn pending_signups(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The number of signups to return. Defaults to 50, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "The number of signups to skip. Defaults to 0.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<PendingSignupObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            signup_screening::DEFAULT_PAGE_SIZE,
+            signup_screening::MAX_PAGE_SIZE,
+        )?;
+
+        let executor = context(ctx).executor();
+        let signups = convert_result(executor.find_pending_signups(limit, offset).await)?;
+        Ok(signups.into_iter().map(PendingSignupObject).collect())
+    }
+
+    /// List users under a legal hold, including deleted users, longest held first. Held users are
+    /// exempt from retention purges and deletion. Only admins can list legal holds.
+    async fn legal_holds(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The number of users to return. Defaults to 50, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "The number of users to skip. Defaults to 0.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<UserObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            retention::LEGAL_HOLD_DEFAULT_PAGE_SIZE,
+            retention::LEGAL_HOLD_MAX_PAGE_SIZE,
+        )?;
+
+        let executor = context(ctx).executor();
+        let users = convert_result(executor.find_legal_holds(limit, offset).await)?;
+        Ok(users.into_iter().map(UserObject).collect())
+    }
+
+    /// List the events providers such as Stripe sent to the server's webhooks, newest first. Only
+    /// admins can list webhook events.
+    async fn webhook_events(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The number of events to return. Defaults to 50, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "The number of events to skip. Defaults to 0.")] offset: Option<i32>,
+        #[graphql(
+            desc = "Only list events that haven't been processed successfully, such as events that failed and need to be replayed."
+        )]
+        unprocessed_only: Option<bool>,
+    ) -> FieldResult<Vec<WebhookEventObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            webhooks::DEFAULT_PAGE_SIZE,
+            webhooks::MAX_PAGE_SIZE,
+        )?;
+
+        let executor = context(ctx).executor();
+        let events = convert_result(
+            executor
+                .find_webhook_events(limit, offset, unprocessed_only.unwrap_or(false))
+                .await,
+        )?;
+        Ok(events.into_iter().map(WebhookEventObject).collect())
+    }
+
+    /// List the emails in part of the outbox, with their bodies redacted. Queued emails are listed
+    /// in the order they will be sent, and dead emails newest first. Only admins can list emails.
+    async fn outbox_emails(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "Whether to list queued or dead emails.")] status: OutboxStatus,
+        #[graphql(desc = "The number of emails to return. Defaults to 50, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "The number of emails to skip. Defaults to 0.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<OutboxEmailObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            outbox::DEFAULT_PAGE_SIZE,
+            outbox::MAX_PAGE_SIZE,
+        )?;
+
+        let executor = context(ctx).executor();
+        let emails = convert_result(
+            executor
+                .find_outbox_emails(status.into(), limit, offset)
+                .await,
+        )?;
+        Ok(emails.into_iter().map(OutboxEmailObject).collect())
+    }
+
+    /// List the security activity of the account of the user making the request, such as logins,
+    /// failed logins and password changes, newest first.
+    async fn my_security_activity(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The number of events to return. Defaults to 50, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "The number of events to skip. Defaults to 0.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<SecurityEventObject>> {
+        let viewer = require_viewer(ctx)?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            security_events::DEFAULT_PAGE_SIZE,
+            security_events::MAX_PAGE_SIZE,
+        )?;
+
+        let executor = context(ctx).executor();
+        let events = convert_result(
+            executor
+                .find_security_events(viewer.user_id, limit, offset)
+                .await,
+        )?;
+        Ok(events.into_iter().map(SecurityEventObject).collect())
+    }
+
+    /// List every plan that can be subscribed to.
+    async fn plans(&self, ctx: &async_graphql::Context<'_>) -> Vec<PlanDetailsObject> {
+        PlanDetails::all(context(ctx).executor().config())
+            .into_iter()
+            .map(PlanDetailsObject)
+            .collect()
+    }
+
+    /// Get the subscription of the user making the request. This will return null if the user has
+    /// never subscribed to a paid plan.
+    async fn my_subscription(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Option<SubscriptionObject>> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let subscription = convert_result(executor.find_subscription(viewer.user_id).await)?;
+        Ok(subscription.map(SubscriptionObject))
+    }
+
+    /// Get the trial of the user making the request. This will return null if the user was never
+    /// given a trial.
+    async fn my_trial(&self, ctx: &async_graphql::Context<'_>) -> FieldResult<Option<TrialObject>> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let trial = convert_result(executor.find_trial(viewer.user_id).await)?;
+        Ok(trial.map(TrialObject))
+    }
+
+    /// Get the metered usage of the user making the request in the current billing period, along
+    /// with the limits of their plan.
+    async fn my_usage(&self, ctx: &async_graphql::Context<'_>) -> FieldResult<UsageObject> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        Ok(UsageObject(convert_result(
+            executor.find_usage(viewer.user_id).await,
+        )?))
+    }
+
+    /// List the organizations the user making the request is a member of or invited to, oldest
+    /// first. Invitations that haven't been accepted have a null "acceptedAt".
+    async fn my_organizations(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Vec<MembershipObject>> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let memberships = convert_result(executor.find_user_memberships(viewer.user_id).await)?;
+        Ok(memberships.into_iter().map(MembershipObject).collect())
+    }
+
+    /// Find an organization by its ID. This will return null if the organization does not exist or
+    /// the user making the request isn't a member of it.
+    async fn organization(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] id: UuidScalar,
+    ) -> FieldResult<Option<OrganizationObject>> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        if convert_result(executor.find_member_role(id.0, viewer.user_id).await)?.is_none() {
+            return Ok(None);
+        }
+
+        let organization = convert_result(executor.find_organization(id.0).await)?;
+        Ok(organization.map(OrganizationObject))
+    }
+
+    /// List the members of an organization, including invited users, in the order they were
+    /// invited. Only members of the organization can list its members.
+    async fn organization_members(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] organization_id: UuidScalar,
+        #[graphql(desc = "The number of members to return. Defaults to 50, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "The number of members to skip. Defaults to 0.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<MembershipObject>> {
+        let viewer = require_viewer(ctx)?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            organizations::DEFAULT_PAGE_SIZE,
+            organizations::MAX_PAGE_SIZE,
+        )?;
+
+        let executor = context(ctx).executor();
+        convert_result(
+            executor
+                .find_member_role(organization_id.0, viewer.user_id)
+                .await,
+        )?
+        .ok_or_else(organization_not_found)?;
+        let memberships = convert_result(
+            executor
+                .find_organization_members(organization_id.0, limit, offset)
+                .await,
+        )?;
+        Ok(memberships.into_iter().map(MembershipObject).collect())
+    }
+
+    /// Find the email settings an organization overrides the server's with, for emails sent on its
+    /// behalf such as invitations. This will return null if it hasn't set any. Only owners and
+    /// admins of the organization can see its email settings.
+    async fn organization_email_settings(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] organization_id: UuidScalar,
+    ) -> FieldResult<Option<OrganizationEmailSettingsObject>> {
+        require_organization_admin(ctx, organization_id.0).await?;
+        let executor = context(ctx).executor();
+        let settings = convert_result(
+            executor
+                .find_organization_email_settings(organization_id.0)
+                .await,
+        )?;
+        Ok(settings.map(OrganizationEmailSettingsObject))
+    }
+
+    /// List the custom hostnames of an organization, verified or not, oldest first. Only owners
+    /// and admins of the organization can list its hostnames.
+    async fn organization_domains(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] organization_id: UuidScalar,
+    ) -> FieldResult<Vec<OrganizationDomainObject>> {
+        require_organization_admin(ctx, organization_id.0).await?;
+        let executor = context(ctx).executor();
+        let hostnames =
+            convert_result(executor.find_organization_domains(organization_id.0).await)?;
+        Ok(hostnames
+            .into_iter()
+            .map(OrganizationDomainObject)
+            .collect())
+    }
+
+    /// Find the organization the request was sent to through one of its verified custom
+    /// hostnames. This will return null if the request was sent to the server's own hostname.
+    async fn current_organization(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Option<OrganizationObject>> {
+        let context = context(ctx);
+        let organization_id = match context.host_organization() {
+            Some(organization_id) => organization_id,
+            None => return Ok(None),
+        };
+        let organization =
+            convert_result(context.executor().find_organization(organization_id).await)?;
+        Ok(organization.map(OrganizationObject))
+    }
+
+    /// Paginate through users. Use "first" and "after" to paginate forward or "last" and "before"
+    /// to paginate backward. Pages default to the first 20 users and cannot be larger than 100
+    /// users. Only admins can list users.
+    #[allow(clippy::too_many_arguments)]
+    async fn users(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The number of users to return after the \"after\" cursor.")]
+        first: Option<i32>,
+        #[graphql(desc = "The cursor to return users after.")] after: Option<String>,
+        #[graphql(desc = "The number of users to return before the \"before\" cursor.")]
+        last: Option<i32>,
+        #[graphql(desc = "The cursor to return users before.")] before: Option<String>,
+        #[graphql(desc = "The order to return users in. Defaults to oldest first.")]
+        order_by: Option<UserOrder>,
+        #[graphql(desc = "Include users that have been deleted. Defaults to false.")]
+        include_deleted: Option<bool>,
+    ) -> FieldResult<UserConnectionObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let order_by = order_by.map(Into::into);
+        let request = PageRequest::new(first, after, last, before, order_by)?;
+
+        let _permit = acquire_permit(ctx, LimitedResolver::Users).await?;
+        let executor = context(ctx).executor();
+        let users = convert_result(
+            executor
+                .find_users(&request, include_deleted.unwrap_or(false))
+                .await,
+        )?;
+        Ok(UserConnectionObject(users))
+    }
+
+    /// Search for users matching a filter, sorted by any number of fields in turn. Users that sort
+    /// the same are ordered by ID, so pages are stable. Only admins can search users.
+    async fn search_users(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "Conditions users must meet. Defaults to every user.")] filter: Option<
+            UserFilter,
+        >,
+        #[graphql(
+            desc = "The fields to sort users by, most significant first. Defaults to sorting by ID."
+        )]
+        sort: Option<Vec<UserSort>>,
+        #[graphql(desc = "The number of users to return. Defaults to 20, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "The number of users to skip. Defaults to 0.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<UserObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let filter = filter.map(Into::into).unwrap_or_default();
+        let sort = sort
+            .unwrap_or_default()
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<_>>();
+        let (limit, offset) = validate_user_search(&filter, &sort, first, offset)?;
+
+        let _permit = acquire_permit(ctx, LimitedResolver::Users).await?;
+        let executor = context(ctx).executor();
+        let users = convert_result(executor.search_users(&filter, &sort, limit, offset).await)?;
+        Ok(users.into_iter().map(UserObject).collect())
+    }
+
+    /// Search for users by the words in their username and email address, most relevant first.
+    /// Each word in the query matches words starting with it, and every word must match. Username
+    /// matches rank above email address matches. Deleted users are left out. Only admins can
+    /// search users.
+    async fn search_users_full_text(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The words to search for, such as \"jane example\".")] query: String,
+        #[graphql(desc = "The number of users to return. Defaults to 20, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "The number of users to skip. Defaults to 0.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<UserSearchHitObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let (terms, limit, offset) = validate_full_text_search(&query, first, offset)?;
+
+        let _permit = acquire_permit(ctx, LimitedResolver::Users).await?;
+        let executor = context(ctx).executor();
+        let hits = convert_result(executor.search_users_full_text(&terms, limit, offset).await)?;
+        Ok(hits.into_iter().map(UserSearchHitObject).collect())
+    }
+
+    /// List the announcements currently being shown to the user making the request, newest first.
+    /// Announcements the user has dismissed are left out.
+    async fn active_announcements(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Vec<AnnouncementObject>> {
+        let context = context(ctx);
+        let is_admin = convert_result(context.access(roles::Role::Admin).await)? == Access::Granted;
+        let user_id = context.viewer().map(|viewer| viewer.user_id);
+        let audiences = announcements::Audience::of(user_id.is_some(), is_admin);
+        let executor = context.executor();
+        Ok(convert_announcements(convert_result(
+            executor
+                .find_active_announcements(user_id, &audiences)
+                .await,
+        )?))
+    }
+
+    /// List every announcement, including those that have ended or haven't started yet, newest
+    /// first. Only admins can list every announcement.
+    async fn announcements(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Vec<AnnouncementObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let executor = context(ctx).executor();
+        Ok(convert_announcements(convert_result(
+            executor.find_announcements().await,
+        )?))
+    }
+
+    /// List feedback, newest first. Only admins can list feedback.
+    async fn feedback(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "Only list feedback with this status.")] status: Option<FeedbackStatus>,
+        #[graphql(desc = "Only list feedback in this category.")] category: Option<
+            FeedbackCategory,
+        >,
+        #[graphql(desc = "The number of submissions to return. Defaults to 50, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "The number of submissions to skip. Defaults to 0.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<FeedbackObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            feedback::DEFAULT_PAGE_SIZE,
+            feedback::MAX_PAGE_SIZE,
+        )?;
+
+        let _permit = acquire_permit(ctx, LimitedResolver::Feedback).await?;
+        let executor = context(ctx).executor();
+        let submissions = convert_result(
+            executor
+                .find_feedback_submissions(
+                    status.map(Into::into),
+                    category.map(Into::into),
+                    limit,
+                    offset,
+                )
+                .await,
+        )?;
+        Ok(submissions.into_iter().map(FeedbackObject).collect())
+    }
+
+    /// List security-relevant events, such as logins and role changes, newest first. Only admins
+    /// can list audit events.
+    async fn audit_events(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "Only list events about this user.")] user_id: Option<UuidScalar>,
+        #[graphql(desc = "Only list events of this kind.")] kind: Option<AuditEventKind>,
+        #[graphql(desc = "The number of events to return. Defaults to 50, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "The number of events to skip. Defaults to 0.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<AuditEventObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            audit::DEFAULT_PAGE_SIZE,
+            audit::MAX_PAGE_SIZE,
+        )?;
+
+        let executor = context(ctx).executor();
+        let events = convert_result(
+            executor
+                .find_audit_events(
+                    user_id.map(|user_id| user_id.0),
+                    kind.map(Into::into),
+                    limit,
+                    offset,
+                )
+                .await,
+        )?;
+        Ok(events.into_iter().map(AuditEventObject).collect())
+    }
+
+    /// List the SQL queries run against the database that are most expensive, as tracked by the
+    /// "pg_stat_statements" Postgres extension. Only admins can list queries.
+    async fn top_queries(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The number of queries to return. Defaults to 20, up to 100.")]
+        first: Option<i32>,
+        #[graphql(desc = "What makes a query expensive. Defaults to total time.")] order_by: Option<
+            QueryStatisticsOrder,
+        >,
+    ) -> FieldResult<Vec<QueryStatisticsObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let limit = validate_query_count(first)?;
+
+        let order = order_by.map_or(query_stats::QueryStatisticsOrder::TotalTime, Into::into);
+        let _permit = acquire_permit(ctx, LimitedResolver::TopQueries).await?;
+        let queries = convert_result(context(ctx).executor().find_top_queries(order, limit).await)?
+            .ok_or_else(|| {
+                error(
+                    QUERY_STATISTICS_UNAVAILABLE_ERROR_MESSAGE,
+                    "query-statistics-unavailable",
+                )
+            })?;
+        Ok(queries.into_iter().map(QueryStatisticsObject).collect())
+    }
+
+    /// List every deprecated field along with the clients still using it, so the field can be
+    /// removed once they stop. Only admins can list deprecated fields.
+    async fn deprecated_field_usage(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Vec<DeprecatedFieldUsageObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let usage = convert_result(context(ctx).executor().find_deprecated_field_usage().await)?;
+        Ok(usage.into_iter().map(DeprecatedFieldUsageObject).collect())
+    }
+
+    /// List every promo code, newest first. Only admins can list promo codes.
+    async fn promo_codes(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Vec<PromoCodeObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let promo_codes = convert_result(context(ctx).executor().find_promo_codes().await)?;
+        Ok(promo_codes.into_iter().map(PromoCodeObject).collect())
+    }
+
+    /// List every redemption of a promo code, oldest first. Only admins can list redemptions.
+    async fn promo_code_redemptions(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the promo code.")] promo_code_id: UuidScalar,
+    ) -> FieldResult<Vec<PromoCodeRedemptionObject>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let executor = context(ctx).executor();
+        let redemptions =
+            convert_result(executor.find_promo_code_redemptions(promo_code_id.0).await)?;
+        Ok(redemptions
+            .into_iter()
+            .map(PromoCodeRedemptionObject)
+            .collect())
+    }
+}
+
+/// All available GraphQL mutations.
+pub struct Mutation;
+
+/// All available GraphQL mutations.
+#[Object]
+impl Mutation {
+    /// Log in using a specified username and password.
+    async fn login(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The username of the user to log in as.")] username: String,
+        #[graphql(desc = "The user's password")] password: String,
+    ) -> FieldResult<AuthResult> {
+        match convert_result(context(ctx).executor().login(&username, &password).await)? {
+            LoginResult::LoggedIn(session_token) => Ok(AuthResult {
+                session_token: context(ctx).deliver_session_token(&session_token),
+            }),
+            LoginResult::InvalidCredentials => {
+                Err(error("Invalid username or password.", "invalid-login"))
+            }
+            LoginResult::Locked(locked_until) => Err(Error::new(ACCOUNT_LOCKED_ERROR_MESSAGE)
+                .extend_with(|_, extensions| {
+                    extensions.set("code", "account-locked");
+                    extensions.set("lockedUntil", locked_until.to_rfc3339());
+                })),
+            LoginResult::Deactivated => Err(account_deactivated()),
+            LoginResult::PasswordResetRequired => Err(password_reset_required()),
+        }
+    }
+
+    /// Log in with any login method, using the credentials it takes. Exactly one kind of
+    /// credentials must be set.
+    async fn login_with(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The credentials to log in with.")] credentials: LoginCredentials,
+    ) -> FieldResult<AuthResult> {
+        let method = LoginMethod::from(credentials);
+        match convert_result(context(ctx).executor().login_with(&method).await)? {
+            LoginResult::LoggedIn(session_token) => Ok(AuthResult {
+                session_token: context(ctx).deliver_session_token(&session_token),
+            }),
+            LoginResult::InvalidCredentials => {
+                let (message, code) = method.invalid_credentials_error();
+                Err(error(message, code))
+            }
+            LoginResult::Locked(locked_until) => Err(Error::new(ACCOUNT_LOCKED_ERROR_MESSAGE)
+                .extend_with(|_, extensions| {
+                    extensions.set("code", "account-locked");
+                    extensions.set("lockedUntil", locked_until.to_rfc3339());
+                })),
+            LoginResult::Deactivated => Err(account_deactivated()),
+            LoginResult::PasswordResetRequired => Err(password_reset_required()),
+        }
+    }
+
+    /// Log in with a one-time ticket issued after logging in with an OAuth provider such as Google
+    /// or GitHub. Tickets are sent to the client as a query parameter once the provider sends the
+    /// user back, and expire shortly after.
+    #[graphql(name = "loginWithOAuth")]
+    async fn login_with_oauth(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ticket issued after logging in with the provider.")] ticket: String,
+    ) -> FieldResult<AuthResult> {
+        match convert_result(context(ctx).executor().redeem_oauth_ticket(&ticket).await)? {
+            LoginResult::LoggedIn(session_token) => Ok(AuthResult {
+                session_token: context(ctx).deliver_session_token(&session_token),
+            }),
+            LoginResult::InvalidCredentials => Err(error(
+                "Invalid or expired login ticket.",
+                "invalid-oauth-ticket",
+            )),
+            LoginResult::Locked(locked_until) => Err(Error::new(ACCOUNT_LOCKED_ERROR_MESSAGE)
+                .extend_with(|_, extensions| {
+                    extensions.set("code", "account-locked");
+                    extensions.set("lockedUntil", locked_until.to_rfc3339());
+                })),
+            LoginResult::Deactivated => Err(account_deactivated()),
+            LoginResult::PasswordResetRequired => Err(password_reset_required()),
+        }
+    }
+
+    /// Start logging in with a passkey. This returns the JSON-encoded options to pass to
+    /// "navigator.credentials.get()", which expire shortly after.
+    async fn start_passkey_login(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(
+            desc = "The username of the user to log in as. If omitted, the authenticator offers \
+            any passkey it has for the server."
+        )]
+        username: Option<String>,
+    ) -> FieldResult<String> {
+        let executor = context(ctx).executor();
+        let options = convert_result(executor.start_passkey_login(username.as_deref()).await)?;
+        Ok(options.to_string())
+    }
+
+    /// Log in with a passkey, using the credential returned by "navigator.credentials.get()" for
+    /// the options from "startPasskeyLogin".
+    async fn finish_passkey_login(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(
+            desc = "The JSON-encoded credential, as serialized by \"PublicKeyCredential.toJSON()\"."
+        )]
+        credential: String,
+    ) -> FieldResult<AuthResult> {
+        match convert_result(
+            context(ctx)
+                .executor()
+                .finish_passkey_login(&credential)
+                .await,
+        )? {
+            LoginResult::LoggedIn(session_token) => Ok(AuthResult {
+                session_token: context(ctx).deliver_session_token(&session_token),
+            }),
+            LoginResult::InvalidCredentials => Err(error(
+                "Invalid or expired passkey login.",
+                "invalid-passkey",
+            )),
+            LoginResult::Locked(locked_until) => Err(Error::new(ACCOUNT_LOCKED_ERROR_MESSAGE)
+                .extend_with(|_, extensions| {
+                    extensions.set("code", "account-locked");
+                    extensions.set("lockedUntil", locked_until.to_rfc3339());
+                })),
+            LoginResult::Deactivated => Err(account_deactivated()),
+            LoginResult::PasswordResetRequired => Err(password_reset_required()),
+        }
+    }
+
+    /// Attempt to refresh an active session using a session token. If successful, the lifespan of
+    /// the session will be extended, the current session token will be invalidated, and a new
+    /// session token will be returned for future authentication.
+    async fn refresh(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(
+            desc = "The session token to refresh. Defaults to the token the request was sent with, \
+            such as the session cookie."
+        )]
+        session_token: Option<String>,
+    ) -> FieldResult<AuthResult> {
+        let context = context(ctx);
+        if let Some(session_token) = session_token.as_deref().or_else(|| context.session_token()) {
+            if let Some(session_token) =
+                convert_result(context.executor().refresh(session_token).await)?
+            {
+                return Ok(AuthResult {
+                    session_token: context.deliver_session_token(&session_token),
+                });
+            }
+        }
+
+        Err(error("Invalid session token.", "invalid-session-token"))
+    }
+
+    /// Terminate the session associated with a specified session token. The token will be
+    /// invalidated so it cannot be used for future authentication. This will return true if the
+    /// specified session token was valid and the log out operation was successful.
+    async fn logout(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(
+            desc = "The session token to invalidate. Defaults to the token the request was sent \
+            with, such as the session cookie."
+        )]
+        session_token: Option<String>,
+    ) -> FieldResult<bool> {
+        let context = context(ctx);
+        context.session_cookies().clear();
+        match session_token.as_deref().or_else(|| context.session_token()) {
+            Some(session_token) => convert_result(context.executor().logout(session_token).await),
+            None => Ok(false),
+        }
+    }
+
+    /// Terminate one of the sessions of the user making the request, such as a session on a lost
+    /// device. This will return true if the session was active and has been terminated.
+    async fn revoke_session(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the session to terminate.")] session_id: UuidScalar,
+    ) -> FieldResult<bool> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(executor.revoke_session(viewer.user_id, session_id.0).await)
+    }
+
+    /// Terminate every session of the user making the request except for the one making the
+    /// request. This will return the number of sessions terminated.
+    async fn revoke_all_other_sessions(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<i32> {
+        let viewer = require_viewer(ctx)?;
+        let count = convert_result(context(ctx).executor().revoke_other_sessions(viewer).await)?;
+        Ok(count as i32)
+    }
+
+    /// Start creating a passkey for the user making the request. This returns the JSON-encoded
+    /// options to pass to "navigator.credentials.create()", which expire shortly after.
+    async fn start_passkey_registration(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<String> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        match convert_result(executor.start_passkey_registration(viewer.user_id).await)? {
+            Some(options) => Ok(options.to_string()),
+            None => Err(error("User not found.", "user-not-found")),
+        }
+    }
+
+    /// Finish creating a passkey for the user making the request, using the credential returned
+    /// by "navigator.credentials.create()" for the options from "startPasskeyRegistration".
+    async fn finish_passkey_registration(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(
+            desc = "The JSON-encoded credential, as serialized by \"PublicKeyCredential.toJSON()\"."
+        )]
+        credential: String,
+        #[graphql(desc = "A name for the passkey, such as the device it was created on.")]
+        name: String,
+    ) -> FieldResult<PasskeyObject> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        match convert_result(
+            executor
+                .finish_passkey_registration(viewer.user_id, &credential, &name)
+                .await,
+        )? {
+            Some(passkey) => Ok(PasskeyObject(passkey)),
+            None => Err(error(
+                "Invalid or expired passkey registration.",
+                "invalid-passkey",
+            )),
+        }
+    }
+
+    /// Remove one of the passkeys of the user making the request, so it can no longer be used to
+    /// log in. This will return true if the passkey was removed.
+    async fn delete_passkey(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the passkey to remove.")] passkey_id: UuidScalar,
+    ) -> FieldResult<bool> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(executor.delete_passkey(viewer.user_id, passkey_id.0).await)
+    }
+
+    /// Attempt to create a new user with the provided username, email and password. Once the user
+    /// is created, an email verification code will be sent to the user's email address.
+    #[allow(clippy::too_many_arguments)]
+    async fn create_user(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The user's username.")] username: String,
+        #[graphql(desc = "The user's email.")] email: String,
+        #[graphql(desc = "The password the user will use to log in.")] password: String,
+        #[graphql(desc = "The value of a form field hidden from people, which bots fill in.")]
+        honeypot: Option<String>,
+        #[graphql(
+            desc = "The token from \"signupFormToken\", fetched when the signup form was loaded."
+        )]
+        form_token: Option<String>,
+        #[graphql(desc = "The token issued by the CAPTCHA provider, if a CAPTCHA was required.")]
+        captcha_token: Option<String>,
+        #[graphql(
+            desc = "Only validate the signup and return the user that would be created, without saving it. Dry runs skip bot screening."
+        )]
+        dry_run: Option<bool>,
+    ) -> FieldResult<UserObject> {
+        let executor = context(ctx).executor();
+        if let Some(invalid) = convert_result(validate_new_user(executor, &username, &email).await)?
+        {
+            return Err(invalid.into());
+        }
+        if let Some(invalid) = validate_password(executor.config(), &password, &[&username, &email])
+        {
+            return Err(invalid_password(invalid));
+        }
+
+        let evidence = SignupEvidence {
+            honeypot,
+            form_token,
+            captcha_token,
+        };
+        let result = convert_result(
+            executor
+                .sign_up(
+                    &username,
+                    &email,
+                    &password,
+                    &evidence,
+                    dry_run.unwrap_or(false),
+                )
+                .await,
+        )?;
+        match result {
+            SignupResult::Created(user) => Ok(UserObject(*user)),
+            SignupResult::CaptchaRequired => {
+                Err(error(CAPTCHA_REQUIRED_ERROR_MESSAGE, "captcha-required"))
+            }
+            SignupResult::PendingReview => Err(error(
+                SIGNUP_PENDING_REVIEW_ERROR_MESSAGE,
+                "signup-pending-review",
+            )),
+        }
+    }
+
+    /// Approve a pending signup, creating its user. Only admins can approve signups.
+    async fn approve_signup(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the pending signup.")] id: UuidScalar,
+    ) -> FieldResult<UserObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let executor = context(ctx).executor();
+        let signup = convert_result(executor.find_pending_signup(id.0).await)?
+            .ok_or_else(|| error("Pending signup not found.", "pending-signup-not-found"))?;
+        // The username may have been taken since the signup was made.
+        if convert_result(
+            executor
+                .find_user_by_username_including_deleted(&signup.username)
+                .await,
+        )?
+        .is_some()
+        {
+            return Err(error("Username is already in use.", "username-taken"));
+        }
+
+        convert_result(executor.approve_pending_signup(id.0).await)?
+            .map(UserObject)
+            .ok_or_else(|| error("Pending signup not found.", "pending-signup-not-found"))
+    }
+
+    /// Reject a pending signup without creating its user. Only admins can reject signups. This
+    /// will return true if the pending signup existed.
+    async fn reject_signup(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the pending signup.")] id: UuidScalar,
+    ) -> FieldResult<bool> {
+        require_role(ctx, roles::Role::Admin).await?;
+        convert_result(context(ctx).executor().reject_pending_signup(id.0).await)
+    }
+
+    /// Request a password reset for the users with the specified email address. Each user will be
+    /// emailed a one-time password reset token. This always returns true, so it can't be used to
+    /// find out which email addresses are registered.
+    async fn request_password_reset(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The email address of the user.")] email: String,
+    ) -> FieldResult<bool> {
+        convert_result(context(ctx).executor().request_password_reset(&email).await)?;
+        Ok(true)
+    }
+
+    /// Reset a user's password using a password reset token. Every active session of the user will
+    /// be terminated. This will return true if the reset token was valid and the password was reset
+    /// successfully.
+    async fn reset_password(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The password reset token that was emailed to the user.")]
+        reset_token: String,
+        #[graphql(desc = "The new password the user will use to log in.")] new_password: String,
+    ) -> FieldResult<bool> {
+        let executor = context(ctx).executor();
+        let user = match convert_result(
+            executor
+                .find_user_by_password_reset_token(&reset_token)
+                .await,
+        )? {
+            Some(user) => user,
+            None => return Ok(false),
+        };
+        if let Some(invalid) = validate_password(
+            executor.config(),
+            &new_password,
+            &[&user.username, &user.email],
+        ) {
+            return Err(invalid_password(invalid));
+        }
+
+        convert_result(executor.reset_password(&reset_token, &new_password).await)
+    }
+
+    /// Update the profile of the user making the request. Details that aren't provided are left
+    /// unchanged. If the email address changes, it will need to be verified again and a new
+    /// verification code will be sent to it.
+    async fn update_profile(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The user's new username.")] username: Option<String>,
+        #[graphql(desc = "The user's new email address.")] email: Option<String>,
+        #[graphql(
+            desc = "Only validate the changes and return the user as they would be, without saving them or sending a verification code."
+        )]
+        dry_run: Option<bool>,
+    ) -> FieldResult<UserObject> {
+        let viewer = require_viewer(ctx)?;
+        require_write_access(ctx).await?;
+        let executor = context(ctx).executor();
+        let (username, email) = (username.as_deref(), email.as_deref());
+        if let Some(invalid) = convert_result(
+            validate_profile_update(executor, viewer.user_id, username, email).await,
+        )? {
+            return Err(invalid.into());
+        }
+
+        convert_result(
+            executor
+                .update_profile(viewer.user_id, username, email, dry_run.unwrap_or(false))
+                .await,
+        )?
+        .map(UserObject)
+        .ok_or_else(|| error("User not found.", "user-not-found"))
+    }
+
+    /// Upload a new avatar for the user making the request, replacing their current one. The
+    /// avatar must be a PNG, JPEG, GIF or WebP image, uploaded in a multipart request.
+    async fn upload_avatar(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The image to use as the avatar.")] file: UploadScalar,
+    ) -> FieldResult<UserObject> {
+        let viewer = require_viewer(ctx)?;
+        require_write_access(ctx).await?;
+        let executor = context(ctx).executor();
+        let file = context(ctx).upload(&file.0).ok_or_else(|| {
+            error(
+                FILE_NOT_UPLOADED_ERROR_MESSAGE,
+                FILE_NOT_UPLOADED_ERROR_CODE,
+            )
+        })?;
+        let format = validate_avatar(executor.config(), file)?;
+        if !convert_result(executor.scan_file(&file.contents).await)? {
+            return Err(error(FILE_REJECTED_ERROR_MESSAGE, FILE_REJECTED_ERROR_CODE));
+        }
+
+        convert_result(
+            executor
+                .update_avatar(viewer.user_id, format, &file.contents)
+                .await,
+        )?
+        .map(UserObject)
+        .ok_or_else(|| error("User not found.", "user-not-found"))
+    }
+
+    /// Change the password of the user making the request. Every other active session of the user
+    /// will be terminated, but the session making the change is kept. This will return true if the
+    /// password was changed successfully.
+    async fn change_password(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The user's current password.")] current_password: String,
+        #[graphql(desc = "The new password the user will use to log in.")] new_password: String,
+        #[graphql(
+            desc = "Only check the current password and the new password against the password policy, without changing the password or terminating sessions."
+        )]
+        dry_run: Option<bool>,
+    ) -> FieldResult<bool> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let user = convert_result(executor.find_user(viewer.user_id).await)?
+            .ok_or_else(|| error("User not found.", "user-not-found"))?;
+        if let Some(invalid) = validate_password(
+            executor.config(),
+            &new_password,
+            &[&user.username, &user.email],
+        ) {
+            return Err(invalid_password(invalid));
+        }
+
+        match convert_result(
+            executor
+                .change_password(
+                    viewer,
+                    &current_password,
+                    &new_password,
+                    dry_run.unwrap_or(false),
+                )
+                .await,
+        )? {
+            true => Ok(true),
+            false => Err(error("Current password is incorrect.", "invalid-password")),
+        }
+    }
+
+    /// Delete the account of the user making the request once the undo grace period is over. Every
+    /// active session of the user will then be terminated, and the user can no longer log in or be
+    /// found. Until then, the deletion can be undone with "undoAction". This will return the
+    /// pending deletion, or null if there is no grace period and the account was deleted right
+    /// away.
+    async fn delete_account(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+    ) -> FieldResult<Option<PendingActionObject>> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let action = convert_result(
+            executor
+                .schedule_action(
+                    pending_actions::PendingActionKind::DeleteAccount,
+                    viewer.user_id,
+                    viewer.user_id,
+                )
+                .await,
+        )?;
+        Ok(action.map(PendingActionObject))
+    }
+
+    /// Undo a destructive action, such as deleting an account, before its grace period is over.
+    /// Logging in isn't required, so deleted accounts can be restored after their sessions end.
+    /// This will return true if the action was undone, or false if it was already done or the undo
+    /// token is invalid.
+    async fn undo_action(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The undo token returned when the action was scheduled.")]
+        undo_token: String,
+    ) -> FieldResult<bool> {
+        convert_result(context(ctx).executor().undo_action(&undo_token).await)
+    }
+
+    /// Create a checkout session for the user making the request to subscribe to a plan with. The
+    /// user should be sent to the returned checkout page, and will be returned to the site once
+    /// they are done.
+    async fn create_checkout_session(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The plan to subscribe to.")] plan: Plan,
+    ) -> FieldResult<CheckoutSessionObject> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(
+            executor
+                .create_checkout_session(viewer.user_id, plan.into())
+                .await,
+        )?
+        .map(CheckoutSessionObject)
+        .ok_or_else(|| error("This plan cannot be purchased.", "plan-unavailable"))
+    }
+
+    /// Create a promo code giving a discount on the first invoice of a subscription. Only admins
+    /// can create promo codes.
+    async fn create_promo_code(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The code users redeem. Codes are case-insensitive.")] code: String,
+        #[graphql(desc = "The kind of discount the promo code gives.")] kind: PromoCodeKind,
+        #[graphql(desc = "The percentage or amount in the smallest currency unit to take off.")]
+        amount: i32,
+        #[graphql(
+            desc = "The max number of times the promo code can be redeemed. Defaults to no limit."
+        )]
+        max_redemptions: Option<i32>,
+        #[graphql(desc = "Date when the promo code expires. Defaults to never.")]
+        expires_at: Option<DateTimeUtc>,
+    ) -> FieldResult<PromoCodeObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        if executor.config().stripe_secret_key.is_none() {
+            return Err(error("Billing is not available.", "billing-unavailable"));
+        }
+
+        let kind = kind.into();
+        let expires_at = expires_at.map(|expires_at| expires_at.0);
+        if let Some(invalid) = convert_result(
+            validate_new_promo_code(executor, &code, kind, amount, max_redemptions, expires_at)
+                .await,
+        )? {
+            return Err(invalid.into());
+        }
+
+        let promo_code = convert_result(
+            executor
+                .create_promo_code(
+                    viewer.user_id,
+                    &code,
+                    kind,
+                    amount,
+                    max_redemptions,
+                    expires_at,
+                )
+                .await,
+        )?;
+        Ok(PromoCodeObject(promo_code))
+    }
+
+    /// Redeem a promo code for the user making the request. If the user has an active
+    /// subscription, the discount is applied to its next invoice. Otherwise, it is applied when
+    /// they subscribe.
+    async fn redeem_promo_code(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The promo code to redeem.")] code: String,
+    ) -> FieldResult<PromoCodeRedemptionObject> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let result = convert_result(executor.redeem_promo_code(viewer.user_id, &code).await)?;
+        convert_redeem_result(result)
+            .map(PromoCodeRedemptionObject)
+            .map_err(Error::from)
+    }
+
+    /// Submit feedback, such as a bug report or feature request. Feedback can be submitted without
+    /// logging in. Submissions are limited per user, or per IP address if the user isn't logged in.
+    async fn submit_feedback(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "What the feedback is about.")] category: FeedbackCategory,
+        #[graphql(desc = "The feedback itself.")] message: String,
+        #[graphql(
+            desc = "Context to attach, such as the app version or the page the user was on."
+        )]
+        metadata: Option<Vec<FeedbackMetadataInput>>,
+    ) -> FieldResult<FeedbackObject> {
+        let metadata = metadata
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| feedback::FeedbackMetadataInput {
+                key: entry.key,
+                value: entry.value,
+            })
+            .collect::<Vec<_>>();
+        if let Some(invalid) = validate_feedback(&message, &metadata) {
+            return Err(invalid.into());
+        }
+
+        let context = context(ctx);
+        let user_id = context.viewer().map(|viewer| viewer.user_id);
+        let metadata = metadata
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect();
+        convert_result(
+            context
+                .executor()
+                .submit_feedback(user_id, category.into(), &message, &metadata)
+                .await,
+        )?
+        .map(FeedbackObject)
+        .ok_or_else(|| error(FEEDBACK_RATE_LIMITED_ERROR_MESSAGE, "rate-limited"))
+    }
+
+    /// Move feedback to another triage status. Only admins can triage feedback.
+    async fn update_feedback_status(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the feedback.")] id: UuidScalar,
+        #[graphql(desc = "The new status.")] status: FeedbackStatus,
+    ) -> FieldResult<FeedbackObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let executor = context(ctx).executor();
+        convert_result(executor.update_feedback_status(id.0, status.into()).await)?
+            .map(FeedbackObject)
+            .ok_or_else(|| error("Feedback not found.", "feedback-not-found"))
+    }
+
+    /// Create an announcement. Only admins can create announcements.
+    async fn create_announcement(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The message to show.")] message: String,
+        #[graphql(desc = "How important the announcement is.")] severity: Severity,
+        #[graphql(desc = "Who the announcement is shown to.")] audience: Audience,
+        #[graphql(desc = "Date when the announcement starts being shown. Defaults to now.")]
+        starts_at: Option<DateTimeUtc>,
+        #[graphql(desc = "Date when the announcement stops being shown. Defaults to never.")]
+        ends_at: Option<DateTimeUtc>,
+    ) -> FieldResult<AnnouncementObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let starts_at = starts_at.map(|starts_at| starts_at.0);
+        let ends_at = ends_at.map(|ends_at| ends_at.0);
+        if let Some(invalid) =
+            validate_announcement(&message, starts_at.unwrap_or_else(Utc::now), ends_at)
+        {
+            return Err(invalid.into());
+        }
+
+        let executor = context(ctx).executor();
+        let announcement = convert_result(
+            executor
+                .create_announcement(
+                    viewer.user_id,
+                    &message,
+                    severity.into(),
+                    audience.into(),
+                    starts_at,
+                    ends_at,
+                )
+                .await,
+        )?;
+        Ok(AnnouncementObject(announcement))
+    }
+
+    /// Update an announcement. Only admins can update announcements. Details that are null are
+    /// left unchanged. To stop showing an announcement early, set its end date to now.
+    #[allow(clippy::too_many_arguments)]
+    async fn update_announcement(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the announcement.")] id: UuidScalar,
+        #[graphql(desc = "The new message to show.")] message: Option<String>,
+        #[graphql(desc = "The new severity.")] severity: Option<Severity>,
+        #[graphql(desc = "The new audience.")] audience: Option<Audience>,
+        #[graphql(desc = "The new date when the announcement starts being shown.")]
+        starts_at: Option<DateTimeUtc>,
+        #[graphql(desc = "The new date when the announcement stops being shown.")] ends_at: Option<
+            DateTimeUtc,
+        >,
+    ) -> FieldResult<AnnouncementObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let executor = context(ctx).executor();
+        let current = convert_result(executor.find_announcement(id.0).await)?
+            .ok_or_else(announcement_not_found)?;
+
+        let starts_at = starts_at.map(|starts_at| starts_at.0);
+        let ends_at = ends_at.map(|ends_at| ends_at.0);
+        if let Some(invalid) = validate_announcement(
+            message.as_deref().unwrap_or(&current.message),
+            starts_at.unwrap_or(current.starts_at),
+            ends_at.or(current.ends_at),
+        ) {
+            return Err(invalid.into());
+        }
+
+        convert_result(
+            executor
+                .update_announcement(
+                    id.0,
+                    message.as_deref(),
+                    severity.map(Into::into),
+                    audience.map(Into::into),
+                    starts_at,
+                    ends_at,
+                )
+                .await,
+        )?
+        .map(AnnouncementObject)
+        .ok_or_else(announcement_not_found)
+    }
+
+    /// Delete an announcement, along with every dismissal of it. Only admins can delete
+    /// announcements. This will return true if the announcement was deleted successfully.
+    async fn delete_announcement(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the announcement.")] id: UuidScalar,
+    ) -> FieldResult<bool> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let executor = context(ctx).executor();
+        if convert_result(executor.delete_announcement(id.0).await)? {
+            Ok(true)
+        } else {
+            Err(announcement_not_found())
+        }
+    }
+
+    /// Dismiss an announcement for the user making the request, so it is no longer returned by
+    /// "activeAnnouncements". This will return true if the announcement was dismissed
+    /// successfully.
+    async fn dismiss_announcement(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the announcement.")] id: UuidScalar,
+    ) -> FieldResult<bool> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        if convert_result(executor.dismiss_announcement(viewer.user_id, id.0).await)? {
+            Ok(true)
+        } else {
+            Err(announcement_not_found())
+        }
+    }
+
+    /// Create an organization. The user making the request becomes its owner.
+    async fn create_organization(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's name, up to 64 characters.")] name: String,
+    ) -> FieldResult<OrganizationObject> {
+        require_write_access(ctx).await?;
+        let viewer = require_viewer(ctx)?;
+        if let Some(invalid) = validate_organization_name(&name) {
+            return Err(invalid.into());
+        }
+
+        let executor = context(ctx).executor();
+        Ok(OrganizationObject(convert_result(
+            executor.create_organization(viewer, name.trim()).await,
+        )?))
+    }
+
+    /// Invite a user to an organization, and email them the invitation. They only become a member
+    /// once they accept it. Only owners and admins can invite users, and only owners can invite
+    /// other owners.
+    async fn invite_member(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] organization_id: UuidScalar,
+        #[graphql(desc = "The username of the user to invite.")] username: String,
+        #[graphql(desc = "The role the user will have once they accept. Defaults to MEMBER.")]
+        role: Option<OrganizationRole>,
+    ) -> FieldResult<MembershipObject> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let role = role.map_or(organizations::OrganizationRole::Member, Into::into);
+        let result = convert_result(
+            executor
+                .invite_member(viewer, organization_id.0, &username, role)
+                .await,
+        )?;
+
+        convert_membership_result(result)
+            .map(MembershipObject)
+            .map_err(Error::from)
+    }
+
+    /// Accept an invitation to an organization, becoming a member of it.
+    async fn accept_invite(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the organization.")] organization_id: UuidScalar,
+    ) -> FieldResult<MembershipObject> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(executor.accept_invite(viewer, organization_id.0).await)?
+            .map(MembershipObject)
+            .ok_or_else(|| error("Invitation not found.", "invite-not-found"))
+    }
+
+    /// Remove a member or invited user from an organization. Users can remove themselves to leave
+    /// an organization or decline an invitation. Otherwise, only owners and admins can remove
+    /// members, and only owners can remove other owners. The last owner can't be removed. This
+    /// will return true once the user has been removed.
+    async fn remove_member(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] organization_id: UuidScalar,
+        #[graphql(desc = "The ID of the user to remove.")] user_id: UuidScalar,
+    ) -> FieldResult<bool> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let result = convert_result(
+            executor
+                .remove_member(viewer, organization_id.0, user_id.0)
+                .await,
+        )?;
+
+        convert_membership_result(result)
+            .map(|_| true)
+            .map_err(Error::from)
+    }
+
+    /// Change the role of a member of or user invited to an organization. Only owners and admins
+    /// can change roles, and only owners can make members owners or change the roles of other
+    /// owners. The last owner can't stop being an owner.
+    async fn set_member_role(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] organization_id: UuidScalar,
+        #[graphql(desc = "The ID of the member.")] user_id: UuidScalar,
+        #[graphql(desc = "The member's new role.")] role: OrganizationRole,
+    ) -> FieldResult<MembershipObject> {
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let result = convert_result(
+            executor
+                .set_member_role(viewer, organization_id.0, user_id.0, role.into())
+                .await,
+        )?;
+
+        convert_membership_result(result)
+            .map(MembershipObject)
+            .map_err(Error::from)
+    }
+
+    /// Replace the email settings of an organization, which override the server's for emails sent
+    /// on its behalf such as invitations. Settings that are null fall back to the server's. Only
+    /// owners and admins of the organization can change its email settings.
+    async fn update_organization_email_settings(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] organization_id: UuidScalar,
+        #[graphql(desc = "The organization's new email settings.")] settings: EmailSettingsInput,
+    ) -> FieldResult<OrganizationEmailSettingsObject> {
+        require_write_access(ctx).await?;
+        require_organization_admin(ctx, organization_id.0).await?;
+        let settings = settings.into();
+        let executor = context(ctx).executor();
+        let current = convert_result(
+            executor
+                .find_organization_email_settings(organization_id.0)
+                .await,
+        )?;
+        let can_encrypt = executor.config().email_settings_key.is_some();
+        if let Some(invalid) = validate_email_settings(&settings, current.as_ref(), can_encrypt) {
+            return Err(invalid.into());
+        }
+
+        Ok(OrganizationEmailSettingsObject(convert_result(
+            executor
+                .update_organization_email_settings(organization_id.0, &settings)
+                .await,
+        )?))
+    }
+
+    /// Delete the email settings of an organization, so emails sent on its behalf use the server's
+    /// settings again. Only owners and admins of the organization can delete its email settings.
+    /// This will return true if it had settings to delete.
+    async fn delete_organization_email_settings(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] organization_id: UuidScalar,
+    ) -> FieldResult<bool> {
+        require_write_access(ctx).await?;
+        require_organization_admin(ctx, organization_id.0).await?;
+        let executor = context(ctx).executor();
+        convert_result(
+            executor
+                .delete_organization_email_settings(organization_id.0)
+                .await,
+        )
+    }
+
+    /// Add a custom hostname to an organization, such as "api.example.com". Requests sent to it are
+    /// routed to the organization once it publishes the returned challenge value in a TXT record
+    /// named by the returned challenge name. Adding a hostname the organization already has
+    /// checks its TXT record again right away. Only owners and admins of the organization can add
+    /// hostnames.
+    async fn add_organization_domain(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] organization_id: UuidScalar,
+        #[graphql(desc = "The hostname to serve the API on for the organization.")]
+        hostname: String,
+    ) -> FieldResult<OrganizationDomainObject> {
+        require_write_access(ctx).await?;
+        require_organization_admin(ctx, organization_id.0).await?;
+        let executor = context(ctx).executor();
+        if !executor.config().organization_domains_enabled {
+            return Err(custom_domains_disabled());
+        }
+        let hostname = domains::normalize_hostname(&hostname).ok_or_else(invalid_hostname)?;
+
+        let domain = convert_result(
+            executor
+                .add_organization_domain(organization_id.0, &hostname)
+                .await,
+        )?;
+        domain
+            .map(OrganizationDomainObject)
+            .ok_or_else(too_many_domains)
+    }
+
+    /// Remove a custom hostname from an organization, so requests sent to it are no longer routed
+    /// to the organization. Only owners and admins of the organization can remove hostnames. This
+    /// will return true if the organization had the hostname.
+    async fn remove_organization_domain(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The organization's ID.")] organization_id: UuidScalar,
+        #[graphql(desc = "The hostname to remove.")] hostname: String,
+    ) -> FieldResult<bool> {
+        require_write_access(ctx).await?;
+        require_organization_admin(ctx, organization_id.0).await?;
+        let hostname = match domains::normalize_hostname(&hostname) {
+            Some(hostname) => hostname,
+            None => return Ok(false),
+        };
+
+        let executor = context(ctx).executor();
+        convert_result(
+            executor
+                .remove_organization_domain(organization_id.0, &hostname)
+                .await,
+        )
+    }
+
+    /// Grant a role to a user. Only admins can grant roles. The user's roles before and after are
+    /// recorded in the audit log and returned in the "changes" response extension. This will
+    /// return the user's roles afterwards.
+    async fn grant_role(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the user to grant the role to.")] user_id: UuidScalar,
+        #[graphql(desc = "The role to grant.")] role: Role,
+    ) -> FieldResult<Vec<Role>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_roles(convert_result(
+            executor.grant_role(viewer, user_id.0, role.into()).await,
+        )?)
+    }
+
+    /// Revoke a role from a user. Only admins can revoke roles. The user's roles before and after
+    /// are recorded in the audit log and returned in the "changes" response extension. This will
+    /// return the user's roles afterwards.
+    async fn revoke_role(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the user to revoke the role from.")] user_id: UuidScalar,
+        #[graphql(desc = "The role to revoke.")] role: Role,
+    ) -> FieldResult<Vec<Role>> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_roles(convert_result(
+            executor.revoke_role(viewer, user_id.0, role.into()).await,
+        )?)
+    }
+
+    /// Unlock login for a user locked out by too many failed login attempts. Only admins can unlock
+    /// users. This will return true if the user was locked.
+    async fn unlock_user(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the user to unlock.")] user_id: UuidScalar,
+    ) -> FieldResult<bool> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let executor = context(ctx).executor();
+        convert_result(executor.unlock_user(user_id.0).await)?
+            .ok_or_else(|| error("User not found.", "user-not-found"))
+    }
+
+    /// Deactivate a user, ending every one of their sessions. Deactivated users can't log in until
+    /// they are reactivated. Only admins can deactivate users. The change is recorded in the audit
+    /// log and returned in the "changes" response extension.
+    async fn deactivate_user(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the user to deactivate.")] user_id: UuidScalar,
+        #[graphql(
+            desc = "Only return the user as they would be once deactivated, and the change in the \"changes\" response extension, without saving it or ending their sessions."
+        )]
+        dry_run: Option<bool>,
+    ) -> FieldResult<UserObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(
+            executor
+                .deactivate_user(viewer, user_id.0, dry_run.unwrap_or(false))
+                .await,
+        )?
+        .map(UserObject)
+        .ok_or_else(|| error("User not found.", "user-not-found"))
+    }
+
+    /// Require a user to reset their password, as is done when their account may have been
+    /// compromised. The user can't log in with their password until they reset it, every one of
+    /// their sessions is ended, and they are emailed a password reset token. Only admins can force
+    /// password resets.
+    async fn force_password_reset(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the user whose password must be reset.")] user_id: UuidScalar,
+    ) -> FieldResult<UserObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(executor.force_password_reset(viewer, user_id.0).await)?
+            .map(UserObject)
+            .ok_or_else(|| error("User not found.", "user-not-found"))
+    }
+
+    /// Reactivate a deactivated user, so they can log in again. Only admins can reactivate users.
+    /// The change is recorded in the audit log and returned in the "changes" response extension.
+    async fn reactivate_user(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the user to reactivate.")] user_id: UuidScalar,
+    ) -> FieldResult<UserObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(executor.reactivate_user(viewer, user_id.0).await)?
+            .map(UserObject)
+            .ok_or_else(|| error("User not found.", "user-not-found"))
+    }
+
+    /// Place a user under a legal hold, exempting them and their events from retention purges and
+    /// deletion until the hold is released. Deleted users can be held too. Holding a user that is
+    /// already held changes nothing. Only admins can apply legal holds.
+    async fn apply_legal_hold(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the user to hold.")] user_id: UuidScalar,
+        #[graphql(
+            desc = "Why the user is held, such as a case number. This is recorded in the audit log."
+        )]
+        reason: Option<String>,
+    ) -> FieldResult<UserObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(
+            executor
+                .apply_legal_hold(viewer, user_id.0, reason.as_deref())
+                .await,
+        )?
+        .map(UserObject)
+        .ok_or_else(|| error("User not found.", "user-not-found"))
+    }
+
+    /// Release a user from a legal hold, so retention purges and deletion apply to them again.
+    /// Only admins can release legal holds.
+    async fn release_legal_hold(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the user to release.")] user_id: UuidScalar,
+        #[graphql(desc = "Why the hold was released. This is recorded in the audit log.")]
+        reason: Option<String>,
+    ) -> FieldResult<UserObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(
+            executor
+                .release_legal_hold(viewer, user_id.0, reason.as_deref())
+                .await,
+        )?
+        .map(UserObject)
+        .ok_or_else(|| error("User not found.", "user-not-found"))
+    }
+
+    /// Process a webhook event again, such as after fixing what made it fail. Events are processed
+    /// again even if they succeeded before, and the returned event records whether the replay
+    /// succeeded. Only admins can replay webhook events.
+    async fn replay_webhook_event(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the webhook event to replay.")] id: UuidScalar,
+    ) -> FieldResult<WebhookEventObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(executor.replay_webhook_event(viewer, id.0).await)?
+            .map(WebhookEventObject)
+            .ok_or_else(|| error("Webhook event not found.", "webhook-event-not-found"))
+    }
+
+    /// Move an email from the dead letter list back to the queue, to be sent right away with a
+    /// fresh set of attempts. Queued emails are already retried on their own. Only admins can retry
+    /// emails.
+    async fn retry_email(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the dead email to retry.")] id: UuidScalar,
+    ) -> FieldResult<OutboxEmailObject> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        convert_result(executor.retry_email(viewer, id.0).await)?
+            .map(OutboxEmailObject)
+            .ok_or_else(|| {
+                error(
+                    "Email not found in the dead letter list.",
+                    "email-not-found",
+                )
+            })
+    }
+
+    /// Delete every email in the dead letter list, returning how many were deleted. Only admins can
+    /// purge dead emails.
+    async fn purge_dead_emails(&self, ctx: &async_graphql::Context<'_>) -> FieldResult<i32> {
+        require_role(ctx, roles::Role::Admin).await?;
+        let viewer = require_viewer(ctx)?;
+        let executor = context(ctx).executor();
+        let count = convert_result(executor.purge_dead_emails(viewer).await)?;
+        Ok(count.min(i32::MAX as u32) as i32)
+    }
+
+    /// Verify the current email address of a user. This will return true if the verification code
+    /// was valid and the email address was verified successfully.
+    async fn verify_user_email_address(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the user to verify.")] user_id: UuidScalar,
+        #[graphql(desc = "The verification code that was emailed to the user.")]
+        verification_code: String,
+    ) -> FieldResult<bool> {
+        convert_result(
+            context(ctx)
+                .executor()
+                .verify_user_email_address(user_id.0, &verification_code)
+                .await,
+        )
+    }
+
+    /// Email a user a new verification code for their current email address, replacing any code
+    /// they were sent before. Resends are throttled per user, and throttled requests fail with
+    /// "retryAt" and "retryAfterSeconds" error extensions. This will return false if the email
+    /// address is already verified.
+    async fn resend_verification_email(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        #[graphql(desc = "The ID of the user to resend the verification email to.")]
+        user_id: UuidScalar,
+    ) -> FieldResult<bool> {
+        let executor = context(ctx).executor();
+        let result = convert_result(executor.resend_verification_email(user_id.0).await)?;
+        match result.ok_or_else(|| error("User not found.", "user-not-found"))? {
+            ResendVerificationResult::Sent => Ok(true),
+            ResendVerificationResult::AlreadyVerified => Ok(false),
+            ResendVerificationResult::Throttled(retry_at) => {
+                let retry_after_seconds = seconds_until(retry_at);
+                Err(
+                    Error::new(VERIFICATION_EMAIL_THROTTLED_ERROR_MESSAGE).extend_with(
+                        |_, extensions| {
+                            extensions.set("code", "rate-limited");
+                            extensions.set("retryAt", retry_at.to_rfc3339());
+                            extensions.set("retryAfterSeconds", retry_after_seconds);
+                        },
+                    ),
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Error};
+use juniper::{GraphQLEnum, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+/// Default number of audit events returned when listing audit events.
+pub const DEFAULT_PAGE_SIZE: i32 = 50;
+/// Maximum number of audit events returned when listing audit events.
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+/// A security-relevant event recorded in the audit log.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "A security-relevant event recorded in the audit log.")]
+pub enum AuditEventKind {
+    #[graphql(description = "A user was created.")]
+    UserCreated,
+    #[graphql(description = "A user logged in.")]
+    Login,
+    #[graphql(description = "A login attempt failed.")]
+    LoginFailed,
+    #[graphql(description = "A user logged out.")]
+    Logout,
+    #[graphql(description = "A user changed their password.")]
+    PasswordChanged,
+    #[graphql(description = "A user reset their password with a reset token.")]
+    PasswordReset,
+    #[graphql(description = "An admin required a user to reset their password.")]
+    PasswordResetForced,
+    #[graphql(description = "A user changed their email address.")]
+    EmailChanged,
+    #[graphql(description = "A user verified their email address.")]
+    EmailVerified,
+    #[graphql(description = "A user tried to verify their email address with the wrong code.")]
+    EmailVerificationFailed,
+    #[graphql(description = "A role was granted to a user.")]
+    RoleGranted,
+    #[graphql(description = "A role was revoked from a user.")]
+    RoleRevoked,
+    #[graphql(
+        description = "A session token that was already refreshed was used again, so its session
+        was terminated."
+    )]
+    SessionTokenReused,
+    #[graphql(description = "An admin placed a user under a legal hold.")]
+    LegalHoldApplied,
+    #[graphql(description = "An admin released a user from a legal hold.")]
+    LegalHoldReleased,
+    #[graphql(description = "An admin deactivated a user.")]
+    UserDeactivated,
+    #[graphql(description = "An admin reactivated a deactivated user.")]
+    UserReactivated,
+    #[graphql(description = "A user added a passkey.")]
+    PasskeyAdded,
+    #[graphql(description = "A user removed a passkey.")]
+    PasskeyRemoved,
+}
+
+impl AuditEventKind {
+    /// The name the kind is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditEventKind::UserCreated => "user-created",
+            AuditEventKind::Login => "login",
+            AuditEventKind::LoginFailed => "login-failed",
+            AuditEventKind::Logout => "logout",
+            AuditEventKind::PasswordChanged => "password-changed",
+            AuditEventKind::PasswordReset => "password-reset",
+            AuditEventKind::PasswordResetForced => "password-reset-forced",
+            AuditEventKind::EmailChanged => "email-changed",
+            AuditEventKind::EmailVerified => "email-verified",
+            AuditEventKind::EmailVerificationFailed => "email-verification-failed",
+            AuditEventKind::RoleGranted => "role-granted",
+            AuditEventKind::RoleRevoked => "role-revoked",
+            AuditEventKind::SessionTokenReused => "session-token-reused",
+            AuditEventKind::LegalHoldApplied => "legal-hold-applied",
+            AuditEventKind::LegalHoldReleased => "legal-hold-released",
+            AuditEventKind::UserDeactivated => "user-deactivated",
+            AuditEventKind::UserReactivated => "user-reactivated",
+            AuditEventKind::PasskeyAdded => "passkey-added",
+            AuditEventKind::PasskeyRemoved => "passkey-removed",
+        }
+    }
+}
+
+impl Display for AuditEventKind {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for AuditEventKind {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "user-created" => Ok(AuditEventKind::UserCreated),
+            "login" => Ok(AuditEventKind::Login),
+            "login-failed" => Ok(AuditEventKind::LoginFailed),
+            "logout" => Ok(AuditEventKind::Logout),
+            "password-changed" => Ok(AuditEventKind::PasswordChanged),
+            "password-reset" => Ok(AuditEventKind::PasswordReset),
+            "password-reset-forced" => Ok(AuditEventKind::PasswordResetForced),
+            "email-changed" => Ok(AuditEventKind::EmailChanged),
+            "email-verified" => Ok(AuditEventKind::EmailVerified),
+            "email-verification-failed" => Ok(AuditEventKind::EmailVerificationFailed),
+            "role-granted" => Ok(AuditEventKind::RoleGranted),
+            "role-revoked" => Ok(AuditEventKind::RoleRevoked),
+            "session-token-reused" => Ok(AuditEventKind::SessionTokenReused),
+            "legal-hold-applied" => Ok(AuditEventKind::LegalHoldApplied),
+            "legal-hold-released" => Ok(AuditEventKind::LegalHoldReleased),
+            "user-deactivated" => Ok(AuditEventKind::UserDeactivated),
+            "user-reactivated" => Ok(AuditEventKind::UserReactivated),
+            "passkey-added" => Ok(AuditEventKind::PasskeyAdded),
+            "passkey-removed" => Ok(AuditEventKind::PasskeyRemoved),
+            _ => Err(anyhow!("Unknown audit event kind: {}", value)),
+        }
+    }
+}
+
+/// A detail recorded with an audit event, such as the role that was granted.
+#[derive(GraphQLObject, Debug, Clone)]
+#[graphql(
+    description = "A detail recorded with an audit event, such as the role that was granted."
+)]
+pub struct AuditMetadataEntry {
+    #[graphql(description = "The name of the entry.")]
+    pub key: String,
+    #[graphql(description = "The value of the entry.")]
+    pub value: String,
+}
+
+/// A field changed by a sensitive admin action, with its value before and after the change.
+#[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[graphql(
+    description = "A field changed by a sensitive admin action, with its value before and after \
+    the change."
+)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditChange {
+    #[graphql(description = "The name of the field, such as \"roles\".")]
+    pub field: String,
+    #[graphql(description = "The value of the field before the change, if it had one.")]
+    pub before: Option<String>,
+    #[graphql(description = "The value of the field after the change, if it has one.")]
+    pub after: Option<String>,
+}
+
+impl AuditChange {
+    /// Describe a change of a field from one value to another.
+    pub fn new(field: &str, before: Option<String>, after: Option<String>) -> Self {
+        AuditChange {
+            field: field.into(),
+            before,
+            after,
+        }
+    }
+}
+
+/// A sensitive admin action taken while handling a request, along with what it changed.
+#[derive(Debug, Clone)]
+pub struct AuditedAction {
+    /// The event the action is recorded as in the audit log.
+    pub kind: AuditEventKind,
+    /// The ID of the user the action was taken on.
+    pub user_id: Uuid,
+    /// Specifies if the action was only previewed, so nothing was changed or recorded.
+    pub dry_run: bool,
+    /// The fields the action changed, or would change in a dry run.
+    pub changes: Vec<AuditChange>,
+}
+
+/// The sensitive admin actions taken while handling a request. Clones share the same actions, so
+/// they can be read after the request's context has been handed to the schema.
+#[derive(Debug, Clone, Default)]
+pub struct AuditedActions(Arc<Mutex<Vec<AuditedAction>>>);
+
+impl AuditedActions {
+    /// Record an action taken while handling the request.
+    pub fn push(&self, action: AuditedAction) {
+        self.0.lock().unwrap().push(action);
+    }
+
+    /// Get the response extensions describing what each action changed, or none if no actions
+    /// were taken.
+    pub fn extensions(&self) -> Option<Value> {
+        let actions = self.0.lock().unwrap();
+        if actions.is_empty() {
+            return None;
+        }
+
+        let changes = actions
+            .iter()
+            .map(|action| {
+                json!({
+                    "event": action.kind.as_str(),
+                    "userId": action.user_id,
+                    "dryRun": action.dry_run,
+                    "changes": action.changes,
+                })
+            })
+            .collect::<Vec<_>>();
+        Some(json!({ "changes": changes }))
+    }
+}
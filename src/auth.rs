@@ -1,12 +1,17 @@
 use std::fmt::{Display, Formatter, Result as FormatResult};
 use std::ops::Deref;
 
+use chrono::{DateTime, Utc};
 use hmac::{Hmac, NewMac};
+use juniper::{graphql_object, GraphQLInputObject};
 use jwt::{SignWithKey, VerifyWithKey};
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use uuid::Uuid;
 
+use crate::schema::InvalidInput;
+use crate::validation;
+
 /// Represents an encoded JWT session token.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SessionToken(String);
@@ -64,3 +69,187 @@ pub struct SessionTokenData {
     /// The ID of the user this session token is associated with.
     pub user_id: Uuid,
 }
+
+/// A user whose session token has been verified against an active session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    /// The ID of the user.
+    pub user_id: Uuid,
+    /// The ID of the session the user authenticated with.
+    pub session_id: Uuid,
+}
+
+/// An active session of a user, with details to help them recognize where it was started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveSession {
+    /// The ID of the session.
+    pub id: Uuid,
+    /// When the session was started. This is none for sessions started before session details
+    /// were recorded.
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the session was last used to authenticate a request.
+    pub last_seen_at: Option<DateTime<Utc>>,
+    /// The IP address the session was started from, if known.
+    pub ip: Option<String>,
+    /// The user agent of the client that started the session, if known.
+    pub user_agent: Option<String>,
+    /// Specifies if this is the session the current request was made with.
+    pub is_current: bool,
+}
+
+#[graphql_object(description = "An active session of a user.")]
+impl ActiveSession {
+    #[graphql(description = "The ID of the session.")]
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    #[graphql(
+        description = "Date when the session was started. This will be null for sessions started
+        before session details were recorded."
+    )]
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        self.created_at
+    }
+
+    #[graphql(description = "Date when the session was last used.")]
+    pub fn last_seen_at(&self) -> Option<DateTime<Utc>> {
+        self.last_seen_at
+    }
+
+    #[graphql(description = "The IP address the session was started from, if known.")]
+    pub fn ip(&self) -> Option<&str> {
+        self.ip.as_deref()
+    }
+
+    #[graphql(description = "The user agent of the client that started the session, if known.")]
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    #[graphql(description = "Specifies if this is the session making the current request.")]
+    pub fn is_current(&self) -> bool {
+        self.is_current
+    }
+}
+
+/// A way of logging in, along with the credentials it takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginMethod {
+    /// Logging in with a username and password.
+    Password { username: String, password: String },
+    /// Logging in with a passkey, using the JSON-encoded credential returned by the browser.
+    Passkey { credential: String },
+    /// Logging in with a one-time ticket issued after logging in with an OAuth provider.
+    OAuth { ticket: String },
+}
+
+impl LoginMethod {
+    /// Get the message and code of the error returned when the credentials are invalid.
+    pub fn invalid_credentials_error(&self) -> (&'static str, &'static str) {
+        match self {
+            LoginMethod::Password { .. } => ("Invalid username or password.", "invalid-login"),
+            LoginMethod::Passkey { .. } => ("Invalid or expired passkey login.", "invalid-passkey"),
+            LoginMethod::OAuth { .. } => {
+                ("Invalid or expired login ticket.", "invalid-oauth-ticket")
+            }
+        }
+    }
+}
+
+/// The credentials of one way of logging in. Exactly one must be set.
+#[derive(GraphQLInputObject, Debug, Clone)]
+#[graphql(description = "The credentials of one way of logging in. Exactly one field must be set.")]
+pub struct LoginCredentials {
+    #[graphql(description = "Log in with a username and password.")]
+    pub password: Option<PasswordCredentials>,
+    #[graphql(description = "Log in with a passkey.")]
+    pub passkey: Option<PasskeyCredentials>,
+    #[graphql(
+        name = "oauth",
+        description = "Log in with a ticket issued after logging in with an OAuth provider."
+    )]
+    pub oauth: Option<OAuthCredentials>,
+}
+
+impl LoginCredentials {
+    /// Get the login method the credentials are for. This will return an error unless exactly one
+    /// kind of credentials is set.
+    pub fn into_method(self) -> Result<LoginMethod, InvalidInput> {
+        validation::one_of([
+            self.password.map(
+                |PasswordCredentials { username, password }| LoginMethod::Password {
+                    username,
+                    password,
+                },
+            ),
+            self.passkey
+                .map(|PasskeyCredentials { credential }| LoginMethod::Passkey { credential }),
+            self.oauth
+                .map(|OAuthCredentials { ticket }| LoginMethod::OAuth { ticket }),
+        ])
+    }
+}
+
+/// A username and password to log in with.
+#[derive(GraphQLInputObject, Debug, Clone)]
+#[graphql(description = "A username and password to log in with.")]
+pub struct PasswordCredentials {
+    #[graphql(description = "The username of the user to log in as.")]
+    pub username: String,
+    #[graphql(description = "The user's password.")]
+    pub password: String,
+}
+
+/// A passkey credential to log in with.
+#[derive(GraphQLInputObject, Debug, Clone)]
+#[graphql(description = "A passkey credential to log in with.")]
+pub struct PasskeyCredentials {
+    #[graphql(
+        description = "The JSON-encoded credential returned by \"navigator.credentials.get()\" for
+        the options from \"startPasskeyLogin\", as serialized by
+        \"PublicKeyCredential.toJSON()\"."
+    )]
+    pub credential: String,
+}
+
+/// A ticket issued after logging in with an OAuth provider, to log in with.
+#[derive(GraphQLInputObject, Debug, Clone)]
+#[graphql(
+    name = "OAuthCredentials",
+    description = "A ticket issued after logging in with an OAuth provider such as Google or
+    GitHub, to log in with."
+)]
+pub struct OAuthCredentials {
+    #[graphql(description = "The ticket issued after logging in with the provider.")]
+    pub ticket: String,
+}
+
+/// The outcome of an attempt to log in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginResult {
+    /// The credentials were valid and a new session was created.
+    LoggedIn(SessionToken),
+    /// The username or password was wrong.
+    InvalidCredentials,
+    /// Too many failed attempts were made for the username or from the IP address, so login is
+    /// locked until the specified time.
+    Locked(DateTime<Utc>),
+    /// The credentials were valid, but the user's account has been deactivated.
+    Deactivated,
+    /// The credentials were valid, but an admin required the user to reset their password before
+    /// logging in with it again.
+    PasswordResetRequired,
+}
+
+/// The outcome of a request to resend a user's email verification code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResendVerificationResult {
+    /// A new verification code was created and emailed to the user.
+    Sent,
+    /// The user's email address is already verified, so no code was sent.
+    AlreadyVerified,
+    /// The verification email was resent too recently or too often, so another can't be resent
+    /// until the specified time.
+    Throttled(DateTime<Utc>),
+}
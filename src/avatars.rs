@@ -0,0 +1,298 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use async_std::task;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{
+    DynamicImage, ExtendedColorType, ImageDecoder, ImageEncoder, ImageReader, ImageResult, Limits,
+};
+use juniper::GraphQLEnum;
+use tide::log;
+
+use crate::executor::Executor;
+use crate::graphql::request_id;
+use crate::state::State;
+
+/// Sizes in pixels of the square renditions each avatar is processed into, smallest first.
+pub const RENDITION_SIZES: &[i32] = &[64, 128, 256, 512];
+/// Max number of avatars processed by a single check.
+pub const PROCESS_BATCH_SIZE: i64 = 10;
+/// Max number of times processing an avatar is attempted before it is given up on.
+pub const MAX_ATTEMPTS: i32 = 5;
+/// How long an avatar is claimed for while it is processed. If processing fails or the server
+/// stops before it finishes, the avatar is processed again once this has passed.
+pub const CLAIM_SECONDS: i64 = 60;
+/// Max width and height in pixels of avatars that are processed. Larger images are rejected
+/// before they are decoded, so a small file can't expand into a huge image in memory.
+const MAX_DIMENSION: u32 = 8192;
+/// Speed AVIF renditions are encoded at, from 1 to 10. Faster speeds compress less.
+const AVIF_SPEED: u8 = 8;
+/// Quality AVIF renditions are encoded at, from 1 to 100.
+const AVIF_QUALITY: u8 = 80;
+/// How long to wait between checks for avatars to process.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// An image format avatar renditions are stored in.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "An image format avatar renditions are stored in.")]
+pub enum AvatarFormat {
+    #[graphql(description = "Lossless WebP, which every current browser can show.")]
+    Webp,
+    #[graphql(
+        description = "Lossy AVIF, which is smaller than WebP but can't be shown by older
+        browsers."
+    )]
+    Avif,
+}
+
+impl AvatarFormat {
+    /// Every format renditions are stored in.
+    pub const ALL: [AvatarFormat; 2] = [AvatarFormat::Webp, AvatarFormat::Avif];
+
+    /// The extension renditions in the format are stored with.
+    pub fn extension(self) -> &'static str {
+        match self {
+            AvatarFormat::Webp => "webp",
+            AvatarFormat::Avif => "avif",
+        }
+    }
+
+    /// The content type of renditions in the format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            AvatarFormat::Webp => "image/webp",
+            AvatarFormat::Avif => "image/avif",
+        }
+    }
+
+    /// Encode an image in the format, from its pixels alone.
+    fn encode(self, image: &DynamicImage) -> ImageResult<Vec<u8>> {
+        let (pixels, color) = if image.color().has_alpha() {
+            (image.to_rgba8().into_raw(), ExtendedColorType::Rgba8)
+        } else {
+            (image.to_rgb8().into_raw(), ExtendedColorType::Rgb8)
+        };
+        let (width, height) = (image.width(), image.height());
+
+        let mut encoded = Vec::new();
+        match self {
+            AvatarFormat::Webp => WebPEncoder::new_lossless(&mut encoded)
+                .write_image(&pixels, width, height, color)?,
+            // Renditions are encoded on a blocking thread already, so the encoder doesn't need
+            // threads of its own.
+            AvatarFormat::Avif => {
+                AvifEncoder::new_with_speed_quality(&mut encoded, AVIF_SPEED, AVIF_QUALITY)
+                    .with_num_threads(Some(1))
+                    .write_image(&pixels, width, height, color)?
+            }
+        }
+        Ok(encoded)
+    }
+}
+
+/// A square rendition of an avatar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendition {
+    /// The width and height of the rendition in pixels.
+    pub size: i32,
+    /// The format the rendition is encoded in.
+    pub format: AvatarFormat,
+    /// The encoded rendition.
+    pub contents: Vec<u8>,
+}
+
+/// Get the path a rendition of an avatar is stored at, next to the avatar itself. For example, the
+/// 128 pixel WebP rendition of "avatars/1/2.png" is stored at "avatars/1/2-128.webp".
+pub fn rendition_path(avatar_path: &str, size: i32, format: AvatarFormat) -> String {
+    let name_start = avatar_path.rfind('/').map_or(0, |index| index + 1);
+    let stem = match avatar_path[name_start..].rfind('.') {
+        Some(extension_start) => &avatar_path[..name_start + extension_start],
+        None => avatar_path,
+    };
+    format!("{}-{}.{}", stem, size, format.extension())
+}
+
+/// Pick the rendition closest to a requested size: the smallest rendition at least as large as
+/// the requested size, so clients don't have to scale it up, or else the largest rendition. The
+/// largest rendition is picked if no size is requested. This will return none if there are no
+/// renditions.
+pub fn closest_size(sizes: &[i32], requested: Option<i32>) -> Option<i32> {
+    let largest = sizes.iter().copied().max()?;
+    let closest = requested.and_then(|requested| {
+        sizes
+            .iter()
+            .copied()
+            .filter(|size| *size >= requested)
+            .min()
+    });
+    Some(closest.unwrap_or(largest))
+}
+
+/// Decode an uploaded avatar and render it into square renditions in every format, rotated
+/// according to its EXIF orientation and cropped around its center. Renditions are encoded from the
+/// decoded pixels alone, so they don't carry the EXIF data or other metadata of the upload. Sizes
+/// larger than the cropped avatar are skipped, except for the smallest one. This is CPU intensive,
+/// so it should be run on a blocking thread. This will return an error if the avatar can't be
+/// decoded.
+pub fn render(contents: &[u8]) -> ImageResult<Vec<Rendition>> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    let mut reader = ImageReader::new(Cursor::new(contents)).with_guessed_format()?;
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let side = image.width().min(image.height());
+    let mut renditions = Vec::new();
+    for (index, size) in RENDITION_SIZES.iter().copied().enumerate() {
+        let pixels = size as u32;
+        if index > 0 && pixels > side {
+            break;
+        }
+
+        let resized = image.resize_to_fill(pixels, pixels, FilterType::Lanczos3);
+        for format in AvatarFormat::ALL.iter().copied() {
+            renditions.push(Rendition {
+                size,
+                format,
+                contents: format.encode(&resized)?,
+            });
+        }
+    }
+
+    Ok(renditions)
+}
+
+/// Periodically process uploaded avatars into renditions. Each check is handled as its own
+/// request, so what it does can be traced back to its log lines. This runs until the server stops.
+pub async fn run_avatar_worker(state: State) {
+    loop {
+        let executor = Executor::new(state.clone(), request_id(None), None, None);
+        loop {
+            match executor.process_avatars().await {
+                Ok(count) if count < PROCESS_BATCH_SIZE as usize => break,
+                Ok(_) => {}
+                Err(error) => {
+                    log::error!("Failed to process avatars: {}", error);
+                    break;
+                }
+            }
+        }
+
+        task::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    /// Encode an image as a JPEG with an EXIF block rotating it a quarter turn clockwise.
+    fn rotated_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, _| {
+            if x < width / 2 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 0, 255])
+            }
+        });
+        let mut jpeg = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+
+        // A little-endian TIFF header with a single orientation entry of 6.
+        let exif: &[u8] =
+            b"Exif\0\0II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0";
+        let mut segment = vec![0xff, 0xe1];
+        segment.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(exif);
+        jpeg.splice(2..2, segment);
+        jpeg
+    }
+
+    /// Get the sizes of the renditions in a format.
+    fn sizes(renditions: &[Rendition], format: AvatarFormat) -> Vec<i32> {
+        renditions
+            .iter()
+            .filter(|rendition| rendition.format == format)
+            .map(|rendition| rendition.size)
+            .collect()
+    }
+
+    #[test]
+    fn rendition_paths_replace_the_extension() {
+        assert_eq!(
+            rendition_path("avatars/1/2.png", 128, AvatarFormat::Webp),
+            "avatars/1/2-128.webp"
+        );
+        assert_eq!(
+            rendition_path("avatars/1.0/2", 64, AvatarFormat::Avif),
+            "avatars/1.0/2-64.avif"
+        );
+    }
+
+    #[test]
+    fn closest_size_avoids_scaling_up() {
+        let sizes = [64, 128, 256];
+        assert_eq!(closest_size(&sizes, Some(100)), Some(128));
+        assert_eq!(closest_size(&sizes, Some(128)), Some(128));
+        assert_eq!(closest_size(&sizes, Some(1000)), Some(256));
+        assert_eq!(closest_size(&sizes, Some(0)), Some(64));
+        assert_eq!(closest_size(&sizes, None), Some(256));
+        assert_eq!(closest_size(&[], Some(64)), None);
+    }
+
+    #[test]
+    fn renditions_are_square_and_oriented() {
+        let renditions = render(&rotated_jpeg(300, 200)).unwrap();
+        assert_eq!(sizes(&renditions, AvatarFormat::Webp), [64, 128]);
+        assert_eq!(sizes(&renditions, AvatarFormat::Avif), [64, 128]);
+
+        for rendition in &renditions {
+            assert!(!rendition
+                .contents
+                .windows(4)
+                .any(|window| window == b"Exif"));
+            let contents = &rendition.contents;
+            match rendition.format {
+                AvatarFormat::Webp => {
+                    assert_eq!(&contents[..4], b"RIFF");
+                    assert_eq!(&contents[8..12], b"WEBP");
+                    assert!(!contents.windows(4).any(|window| window == b"EXIF"));
+                }
+                AvatarFormat::Avif => {
+                    assert_eq!(&contents[4..12], b"ftypavif");
+                    continue;
+                }
+            }
+
+            // The left half of the image was red, so after turning it the top half is.
+            let size = rendition.size as u32;
+            let image = image::load_from_memory(contents).unwrap().into_rgb8();
+            assert_eq!(image.dimensions(), (size, size));
+            let top = image.get_pixel(size / 2, 2);
+            let bottom = image.get_pixel(size / 2, size - 3);
+            assert!(top[0] > 200 && top[2] < 50, "{:?}", top);
+            assert!(bottom[2] > 200 && bottom[0] < 50, "{:?}", bottom);
+        }
+    }
+
+    #[test]
+    fn small_avatars_get_the_smallest_rendition() {
+        let renditions = render(&rotated_jpeg(20, 10)).unwrap();
+        assert_eq!(sizes(&renditions, AvatarFormat::Webp), [64]);
+        assert_eq!(sizes(&renditions, AvatarFormat::Avif), [64]);
+    }
+
+    #[test]
+    fn invalid_avatars_are_rejected() {
+        assert!(render(b"\x89PNG\r\n\x1a\nnot really").is_err());
+    }
+}
@@ -0,0 +1,737 @@
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use axum::body::{to_bytes, Body, Bytes, HttpBody};
+#[cfg(feature = "metrics")]
+use axum::extract::MatchedPath;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Query, Request, State as Extension};
+use axum::http::header::{
+    ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, ETAG,
+    HOST, IF_NONE_MATCH, USER_AGENT, VARY,
+};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::{from_fn_with_state, Next};
+use axum::response::{AppendHeaders, IntoResponse, Response};
+use axum::routing::{get, on, MethodFilter};
+use axum::{Json, Router};
+use futures::{future, stream, AsyncReadExt, FutureExt};
+#[cfg(feature = "metrics")]
+use prometheus::TEXT_FORMAT;
+use serde_json::{json, Value};
+use tide::http::Url;
+use tide::{log, Endpoint, Server};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+use crate::build_state;
+use crate::config::Config;
+use crate::deadlines::{Deadline, REQUEST_DEADLINE_HEADER};
+use crate::graphql::{
+    cache_headers, compress_stream, execute, execute_body, is_not_modified, request_id, user_agent,
+    AllowedOperations, Execution, GetRequest, ResponseBody, REQUEST_ID_HEADER,
+};
+use crate::lifecycle::shutdown_signal;
+use crate::logging;
+#[cfg(feature = "metrics")]
+use crate::metrics::UNMATCHED_ROUTE;
+use crate::middleware::{
+    error_body, error_code, weak_etag, CompressionPolicy, CorsPolicy, ErrorMiddleware,
+    ACCEPT_ENCODING_HEADER, METHOD_NOT_ALLOWED_ERROR_MESSAGE, NOT_FOUND_ERROR_MESSAGE,
+    ORIGIN_HEADER, ORIGIN_NOT_ALLOWED_ERROR_MESSAGE, PREFLIGHT_METHOD_HEADER,
+    TIMEOUT_ERROR_MESSAGE, UNKNOWN_ERROR_MESSAGE,
+};
+use crate::plugin::Plugin;
+use crate::proxies::{self, FORWARDED_FOR_HEADER};
+use crate::query_limits::QueryBudget;
+use crate::routes;
+use crate::session_cookies::{RequestCredentials, CSRF_HEADER};
+use crate::state::State;
+use crate::uploads::{is_multipart, parse_multipart, Uploads, UPLOAD_TOO_LARGE_ERROR_MESSAGE};
+use crate::usage::{QuotaStatus, QUOTA_WARNING_HEADER};
+
+/// Route modules served by a tide server embedded in the axum router, rather than by axum handlers
+/// of their own. Their routes are mounted on the axum router and forwarded to the tide server.
+const EMBEDDED_MODULES: &[&str] = &["billing", "storage", "oauth", "exports"];
+/// The largest request body forwarded to an embedded route, the same as axum's default body limit.
+const EMBEDDED_BODY_MAX_BYTES: usize = 2 * 1024 * 1024;
+/// The size of the chunks the bodies of responses from embedded routes are streamed in.
+const EMBEDDED_BODY_CHUNK_BYTES: usize = 16 * 1024;
+
+/// Build an axum router exposing the GraphQL API, health check and metrics if they are enabled,
+/// along with the routes of the embedded route modules. The router shares the same state, schema
+/// and executor as the tide server. Routes contributed by plugins are not mounted.
+pub fn build_router(state: State) -> Router {
+    // Multipart requests upload files along with the GraphQL request, so they are size limited.
+    let body_limit = DefaultBodyLimit::max(state.config.upload_max_bytes);
+    let mut router = Router::new()
+        .route("/graphql", get(graphql_get).post(graphql).layer(body_limit))
+        .route("/graphql/schema", get(graphql_schema))
+        .route("/health", get(health));
+    #[cfg(feature = "metrics")]
+    if state.config.metrics_enabled {
+        router = router.route("/metrics", get(metrics));
+    }
+
+    let modules = routes::modules()
+        .into_iter()
+        .filter(|module| EMBEDDED_MODULES.contains(&module.name()))
+        .collect::<Vec<_>>();
+    let mut embedded = EmbeddedRoutes::new(state.clone());
+    routes::mount(&mut embedded, &modules, &state.config);
+    for (method, path) in embedded.routes {
+        let filter =
+            match axum_method(method).and_then(|method| MethodFilter::try_from(method).ok()) {
+                Some(filter) => filter,
+                None => {
+                    log::error!("Can't mount {} {} with axum.", method, path);
+                    continue;
+                }
+            };
+        let server = embedded.server.clone();
+        router = router.route(
+            path,
+            on(filter, move |request: Request| async move {
+                forward(&server, request).await
+            }),
+        );
+    }
+
+    router = router
+        .fallback(not_found)
+        .layer(from_fn_with_state(state.clone(), handle_errors));
+    #[cfg(feature = "metrics")]
+    {
+        router = router.layer(from_fn_with_state(state.clone(), record_metrics));
+    }
+    // Layers added last run first, so error responses get CORS headers too.
+    if let Some(policy) = CorsPolicy::new(&state.config, &state.domains) {
+        router = router.layer(from_fn_with_state(policy, handle_cors));
+    }
+    // Responses are compressed outside the layers adding headers to them, so their "Vary" headers
+    // are kept.
+    if let Some(policy) = CompressionPolicy::new(&state.config) {
+        router = router.layer(from_fn_with_state(policy, compress_responses));
+    }
+    // Requests are logged outside the other layers, so lines they log include the request ID.
+    router = router.layer(from_fn_with_state(state.clone(), log_requests));
+
+    router.with_state(state)
+}
+
+/// Build the global server state and serve the API with axum until the process is asked to stop.
+/// This must be run on a tokio runtime.
+pub async fn serve(config: Config, plugins: Vec<Box<dyn Plugin>>) -> Result<()> {
+    let port = config.port;
+    let state = build_state(config, &plugins).await?;
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+
+    log::info!("Serving with axum on port {}.", port);
+    let router = build_router(state.clone());
+    // Stop accepting requests when asked to stop, then shut the server's subsystems down.
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async {
+        if let Err(error) = shutdown_signal().await {
+            log::error!("Failed to listen for shutdown signals: {}", error);
+            future::pending::<()>().await;
+        }
+    })
+    .await?;
+    state.lifecycle.shutdown(&state).await;
+
+    Ok(())
+}
+
+/// Build a response with a GraphQL-shaped error body.
+fn error_response(status: StatusCode, message: &str) -> Response {
+    let code = tide::StatusCode::try_from(status.as_u16())
+        .map(error_code)
+        .unwrap_or("unknown-error");
+    (status, Json(error_body(message, code))).into_response()
+}
+
+/// Middleware converting panics and requests that take too long to handle into GraphQL-shaped
+/// error responses. This mirrors the error middleware used by the tide server.
+async fn handle_errors(
+    Extension(state): Extension<State>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let duration = Duration::from_secs(state.config.request_timeout_seconds.into());
+    let handled = AssertUnwindSafe(next.run(request)).catch_unwind();
+    match timeout(duration, handled).await {
+        Ok(Ok(response)) if response.status() == StatusCode::METHOD_NOT_ALLOWED => error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            METHOD_NOT_ALLOWED_ERROR_MESSAGE,
+        ),
+        Ok(Ok(response)) if response.status() == StatusCode::PAYLOAD_TOO_LARGE => error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            UPLOAD_TOO_LARGE_ERROR_MESSAGE,
+        ),
+        Ok(Ok(response)) => response,
+        Ok(Err(_)) => {
+            log::error!("Panicked while handling a request.");
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, UNKNOWN_ERROR_MESSAGE)
+        }
+        Err(_) => {
+            log::warn!("Timed out while handling a request.");
+            error_response(StatusCode::GATEWAY_TIMEOUT, TIMEOUT_ERROR_MESSAGE)
+        }
+    }
+}
+
+/// Middleware letting browsers call the API from the origins allowed by a CORS policy. This mirrors
+/// the CORS middleware used by the tide server.
+async fn handle_cors(
+    Extension(policy): Extension<CorsPolicy>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let origin = match request.headers().get(ORIGIN_HEADER) {
+        Some(origin) => String::from_utf8_lossy(origin.as_bytes()).into_owned(),
+        None => return next.run(request).await,
+    };
+    let is_preflight = request.method() == Method::OPTIONS
+        && request.headers().contains_key(PREFLIGHT_METHOD_HEADER);
+
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|value| value.to_str().ok());
+    let headers = policy.headers(host, &origin, is_preflight);
+    let mut response = match &headers {
+        Some(_) if is_preflight => StatusCode::NO_CONTENT.into_response(),
+        None if is_preflight => {
+            return error_response(StatusCode::FORBIDDEN, ORIGIN_NOT_ALLOWED_ERROR_MESSAGE)
+        }
+        _ => next.run(request).await,
+    };
+    if let Some(headers) = headers {
+        for (name, value) in headers {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                response.headers_mut().insert(name, value);
+            }
+        }
+        response
+            .headers_mut()
+            .append(VARY, HeaderValue::from_static(ORIGIN_HEADER));
+    }
+
+    response
+}
+
+/// Get the encodings a client accepts from the "Accept-Encoding" headers of its request, joined
+/// into one list.
+fn accept_encoding(headers: &HeaderMap) -> Option<String> {
+    let accepted = headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>();
+    (!accepted.is_empty()).then(|| accepted.join(","))
+}
+
+/// Middleware compressing response bodies with Brotli or gzip. This mirrors the compression
+/// middleware used by the tide server.
+async fn compress_responses(
+    Extension(policy): Extension<CompressionPolicy>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let accept_encoding = accept_encoding(request.headers());
+
+    let response = next.run(request).await;
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|essence| essence.trim().to_ascii_lowercase());
+    let is_encoded = response.headers().contains_key(CONTENT_ENCODING);
+    let len = response
+        .body()
+        .size_hint()
+        .exact()
+        .and_then(|len| usize::try_from(len).ok());
+    if !policy.applies(content_type.as_deref(), is_encoded, len) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static(ACCEPT_ENCODING_HEADER));
+    let encoding = match policy.negotiate(accept_encoding.as_deref()) {
+        Some(encoding) => encoding,
+        None => return Response::from_parts(parts, body),
+    };
+
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(_) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, UNKNOWN_ERROR_MESSAGE),
+    };
+    let compressed = match encoding.compress(&body) {
+        Ok(compressed) if compressed.len() < body.len() => compressed,
+        _ => return Response::from_parts(parts, Body::from(body)),
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    let etag = parts
+        .headers
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .and_then(|etag| HeaderValue::from_str(&weak_etag(etag)).ok());
+    if let Some(etag) = etag {
+        parts.headers.insert(ETAG, etag);
+    }
+
+    Response::from_parts(parts, Body::from(compressed))
+}
+
+/// Middleware assigning every request an ID, then logging it and counting it for alerts once it
+/// has been handled. This mirrors the request logging middleware used by the tide server.
+async fn log_requests(
+    Extension(state): Extension<State>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_id = request_id(
+        request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok()),
+    );
+    // Request IDs only contain visible ASCII characters, so they are always valid header values.
+    let header = HeaderValue::from_str(&request_id).expect("Invalid request ID");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header.clone());
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+
+    logging::scope_request(request_id, async move {
+        let start = Instant::now();
+        let mut response = next.run(request).await;
+        let status = response.status().as_u16();
+        logging::log_request(&method, &path, status, start.elapsed());
+        state.alert_counters.record_response(status);
+        response.headers_mut().insert(REQUEST_ID_HEADER, header);
+        response
+    })
+    .await
+}
+
+/// Middleware recording the count, status and duration of every request. This mirrors the metrics
+/// middleware used by the tide server.
+#[cfg(feature = "metrics")]
+async fn record_metrics(
+    Extension(state): Extension<State>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    // Routes are labelled with the path they were mounted at, like the tide server does.
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    state.metrics.observe_http_request(
+        &method,
+        &route,
+        response.status().as_u16(),
+        start.elapsed(),
+    );
+
+    response
+}
+
+/// Who sent a GraphQL request, taken from its headers and connection.
+struct Caller {
+    request_id: String,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    credentials: RequestCredentials,
+    host_organization: Option<Uuid>,
+    deadline: Deadline,
+    accept_encoding: Option<String>,
+}
+
+impl Caller {
+    /// Identify the sender of a request. The client IP address is the address of the connection,
+    /// or taken from the "X-Forwarded-For" header if the connection is from a trusted proxy, like
+    /// the tide server does. Connection info is only available if the router is served with it.
+    fn new(
+        state: &State,
+        headers: &HeaderMap,
+        connect_info: Option<ConnectInfo<SocketAddr>>,
+    ) -> Self {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        let forwarded_for = headers
+            .get_all(FORWARDED_FOR_HEADER)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        let client_ip = connect_info.map(|ConnectInfo(address)| {
+            let trusted_proxies = &state.config.trusted_proxies;
+            proxies::forwarded_client(address.ip(), Some(&forwarded_for), trusted_proxies)
+                .to_string()
+        });
+
+        Caller {
+            request_id: request_id(header(REQUEST_ID_HEADER)),
+            client_ip,
+            user_agent: user_agent(header(USER_AGENT.as_str())),
+            credentials: RequestCredentials::new(
+                &state.config.session_cookie_policy,
+                header(AUTHORIZATION.as_str()),
+                header(COOKIE.as_str()),
+                header(CSRF_HEADER),
+            ),
+            host_organization: state.domains.organization(header(HOST.as_str())),
+            deadline: Deadline::for_request(
+                header(REQUEST_DEADLINE_HEADER),
+                state.config.request_timeout_seconds,
+            ),
+            accept_encoding: accept_encoding(headers),
+        }
+    }
+}
+
+/// Handle a GraphQL request.
+async fn graphql(
+    Extension(state): Extension<State>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, Response> {
+    let caller = Caller::new(&state, &headers, connect_info);
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let (body, uploads) = match content_type {
+        Some(content_type) if is_multipart(content_type) => {
+            parse_multipart(content_type, body.to_vec())
+                .await
+                .map_err(|error| {
+                    let message = format!("Invalid multipart request: {}", error);
+                    error_response(StatusCode::BAD_REQUEST, &message)
+                })?
+        }
+        _ => (body.to_vec(), Uploads::new()),
+    };
+    let execution = execute_body(
+        &state,
+        caller.request_id.clone(),
+        caller.client_ip,
+        caller.user_agent,
+        caller.credentials.session_token.clone(),
+        caller.host_organization,
+        caller.deadline,
+        &body,
+        uploads,
+        caller.credentials.allowed_operations(),
+    )
+    .await
+    .map_err(invalid_request)?;
+
+    let (status, headers) = respond(&state, caller.request_id, &execution);
+    let accept_encoding = caller.accept_encoding.as_deref();
+    Ok(json_response(
+        &state,
+        status,
+        headers,
+        execution,
+        accept_encoding,
+    ))
+}
+
+/// Handle a GraphQL query sent in the query string of a GET request. Only queries can be sent this
+/// way, and their responses are sent with headers letting clients and CDNs cache them.
+async fn graphql_get(
+    Extension(state): Extension<State>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Query(request): Query<GetRequest>,
+) -> Result<Response, Response> {
+    let caller = Caller::new(&state, &headers, connect_info);
+    let is_authenticated = caller.credentials.session_token.is_some();
+    let body = request.body().map_err(invalid_request)?;
+    let execution = execute(
+        &state,
+        caller.request_id.clone(),
+        caller.client_ip,
+        caller.user_agent,
+        caller.credentials.session_token,
+        caller.host_organization,
+        caller.deadline,
+        &body,
+        Uploads::new(),
+        &mut QueryBudget::default(),
+        AllowedOperations::QueriesOnly,
+    )
+    .await
+    .map_err(invalid_request)?
+    .buffer()
+    .await;
+
+    // The response is buffered, so it has an ETag and the compression layer compresses it.
+    let cache_headers = cache_headers(&execution, is_authenticated, &state.config);
+    let if_none_match = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok());
+    if is_not_modified(if_none_match, &cache_headers) {
+        let mut headers = vec![(REQUEST_ID_HEADER, caller.request_id)];
+        headers.extend(cache_headers);
+        return Ok((StatusCode::NOT_MODIFIED, AppendHeaders(headers)).into_response());
+    }
+
+    let (status, mut headers) = respond(&state, caller.request_id, &execution);
+    headers.extend(cache_headers);
+    let accept_encoding = caller.accept_encoding.as_deref();
+    Ok(json_response(
+        &state,
+        status,
+        headers,
+        execution,
+        accept_encoding,
+    ))
+}
+
+/// Serve the schema in the GraphQL schema language, with headers letting clients and CDNs cache it.
+/// This mirrors the schema route of the tide server.
+async fn graphql_schema(Extension(state): Extension<State>, headers: HeaderMap) -> Response {
+    let document = &state.schema_document;
+    let cache_headers = document.cache_headers(&state.config);
+    let if_none_match = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok());
+    if is_not_modified(if_none_match, &cache_headers) {
+        return (StatusCode::NOT_MODIFIED, AppendHeaders(cache_headers)).into_response();
+    }
+
+    // Strings are sent as plain text.
+    let sdl = document.sdl.clone();
+    (StatusCode::OK, AppendHeaders(cache_headers), sdl).into_response()
+}
+
+/// Respond to a GraphQL request that isn't valid.
+fn invalid_request(error: serde_json::Error) -> Response {
+    let message = format!("Invalid GraphQL request: {}", error);
+    error_response(StatusCode::BAD_REQUEST, &message)
+}
+
+/// Get the status and headers of the response to a GraphQL request from the outcome of executing
+/// it.
+fn respond(
+    state: &State,
+    request_id: String,
+    execution: &Execution,
+) -> (StatusCode, Vec<(&'static str, String)>) {
+    // If we get an error while executing the query, the configured policy decides if a bad request
+    // status is returned.
+    let status = if execution.quota == QuotaStatus::HardLimitExceeded {
+        StatusCode::TOO_MANY_REQUESTS
+    } else if execution.is_success(state.config.error_status_policy) {
+        StatusCode::OK
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+
+    let mut headers = vec![(REQUEST_ID_HEADER, request_id)];
+    if let Some(warning) = execution.quota_warning() {
+        headers.push((QUOTA_WARNING_HEADER, warning.into()));
+    }
+    headers.extend(execution.deprecation_headers.iter().cloned());
+    headers.extend(execution.cookie_headers.iter().cloned());
+    (status, headers)
+}
+
+/// Build the response to a GraphQL request from its status, headers and the outcome of executing
+/// it. Streamed responses are compressed as they are sent if the client accepts it, as the
+/// compression layer only compresses bodies of a known length. This mirrors how the tide server
+/// responds.
+fn json_response(
+    state: &State,
+    status: StatusCode,
+    headers: Vec<(&'static str, String)>,
+    execution: Execution,
+    accept_encoding: Option<&str>,
+) -> Response {
+    let chunks = match execution.body {
+        ResponseBody::Buffered { bytes, .. } => {
+            let mut response = (status, AppendHeaders(headers), bytes).into_response();
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            return response;
+        }
+        ResponseBody::Streamed(chunks) => chunks,
+    };
+
+    let policy = CompressionPolicy::new(&state.config);
+    let encoding = policy
+        .as_ref()
+        .and_then(|policy| policy.negotiate(accept_encoding));
+    let chunks = match encoding {
+        Some(encoding) => compress_stream(chunks, encoding),
+        None => chunks,
+    };
+    let mut response = (status, AppendHeaders(headers), Body::from_stream(chunks)).into_response();
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if policy.is_some() {
+        headers.append(VARY, HeaderValue::from_static(ACCEPT_ENCODING_HEADER));
+    }
+    if let Some(encoding) = encoding {
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    }
+
+    response
+}
+
+/// A tide server serving the routes of route modules that have no axum handlers, and the routes
+/// mounted on it.
+struct EmbeddedRoutes {
+    server: Server<State>,
+    routes: Vec<(tide::http::Method, &'static str)>,
+}
+
+impl EmbeddedRoutes {
+    /// Create a tide server for embedded routes. Only errors are handled by its middleware, as the
+    /// axum router's own layers log, time out and add headers to requests forwarded to it.
+    fn new(state: State) -> Self {
+        let mut server = Server::with_state(state.clone());
+        server.with(ErrorMiddleware::new(state.config.request_timeout_seconds));
+
+        EmbeddedRoutes {
+            server,
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl routes::Router for EmbeddedRoutes {
+    fn route(
+        &mut self,
+        method: tide::http::Method,
+        path: &'static str,
+        endpoint: Arc<dyn Endpoint<State>>,
+    ) {
+        self.routes.push((method, path));
+        routes::Router::route(&mut self.server, method, path, endpoint);
+    }
+}
+
+/// Convert a tide HTTP method to an axum one.
+fn axum_method(method: tide::http::Method) -> Option<Method> {
+    Method::from_bytes(method.to_string().as_bytes()).ok()
+}
+
+/// Handle a request with the tide server serving embedded routes, converting the request to tide
+/// and its response back to axum. Request bodies are read in full before they are forwarded, but
+/// response bodies are streamed as the endpoint produces them.
+async fn forward(server: &Server<State>, request: Request) -> Response {
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, EMBEDDED_BODY_MAX_BYTES).await {
+        Ok(body) => body,
+        Err(_) => {
+            return error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                UPLOAD_TOO_LARGE_ERROR_MESSAGE,
+            )
+        }
+    };
+    let path = parts.uri.path_and_query().map_or("/", |path| path.as_str());
+    let (method, url) = match (
+        parts.method.as_str().parse::<tide::http::Method>(),
+        Url::parse(&format!("http://localhost{}", path)),
+    ) {
+        (Ok(method), Ok(url)) => (method, url),
+        _ => return error_response(StatusCode::BAD_REQUEST, UNKNOWN_ERROR_MESSAGE),
+    };
+
+    let mut forwarded = tide::http::Request::new(method, url);
+    for (name, value) in &parts.headers {
+        if let Ok(value) = value.to_str() {
+            forwarded.append_header(name.as_str(), value);
+        }
+    }
+    if let Some(ConnectInfo(address)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        forwarded.set_peer_addr(Some(*address));
+    }
+    forwarded.set_body(body.to_vec());
+
+    let mut response: tide::http::Response = match server.respond(forwarded).await {
+        Ok(response) => response,
+        Err(error) => {
+            log::error!(
+                "Failed to forward a request to an embedded route: {}",
+                error
+            );
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, UNKNOWN_ERROR_MESSAGE);
+        }
+    };
+
+    let mut builder = Response::builder().status(u16::from(response.status()));
+    for (name, values) in response.iter() {
+        for value in values {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+    }
+    let body = response.take_body();
+    if let Some(length) = body.len() {
+        builder = builder.header(CONTENT_LENGTH, length);
+    }
+    let chunks = stream::try_unfold(body, |mut body| async move {
+        let mut chunk = vec![0; EMBEDDED_BODY_CHUNK_BYTES];
+        let read = body.read(&mut chunk).await?;
+        chunk.truncate(read);
+        Ok::<_, io::Error>((read > 0).then_some((chunk, body)))
+    });
+
+    builder
+        .body(Body::from_stream(chunks))
+        .unwrap_or_else(|error| {
+            log::error!(
+                "Failed to convert a response from an embedded route: {}",
+                error
+            );
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, UNKNOWN_ERROR_MESSAGE)
+        })
+}
+
+/// Respond to requests to unknown routes.
+async fn not_found() -> Response {
+    error_response(StatusCode::NOT_FOUND, NOT_FOUND_ERROR_MESSAGE)
+}
+
+/// Report that the server is up and able to handle requests.
+async fn health() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Report server metrics to a Prometheus scraper.
+#[cfg(feature = "metrics")]
+async fn metrics(Extension(state): Extension<State>) -> Response {
+    match state.metrics.render(&state.db) {
+        Ok(metrics) => ([(CONTENT_TYPE, TEXT_FORMAT)], metrics).into_response(),
+        Err(error) => {
+            log::error!("Failed to render metrics: {}", error);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, UNKNOWN_ERROR_MESSAGE)
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+use juniper::{graphql_object, GraphQLEnum};
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::models::User;
+use crate::promo_codes::PromoCodeKind;
+
+#[cfg(feature = "webhooks")]
+mod webhook;
+
+#[cfg(feature = "webhooks")]
+pub use webhook::BillingModule;
+
+/// Base URL of the Stripe API.
+const STRIPE_API_URL: &str = "https://api.stripe.com/v1";
+/// Subscription statuses that entitle a user to their subscription's plan.
+const ACTIVE_SUBSCRIPTION_STATUSES: &[&str] = &["active", "trialing"];
+
+/// A subscription plan. Plans are ordered, so each plan includes everything the plans before it
+/// include.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[graphql(description = "A subscription plan.")]
+pub enum Plan {
+    #[graphql(
+        description = "The plan users are on once their trial ends without subscribing. This only
+        allows read-only access."
+    )]
+    Restricted,
+    #[graphql(description = "The plan users without a subscription or trial are on.")]
+    Free,
+    #[graphql(description = "The paid plan.")]
+    Pro,
+}
+
+impl Plan {
+    /// Every plan that can be subscribed to, in order.
+    pub const ALL: [Plan; 2] = [Plan::Free, Plan::Pro];
+
+    /// The name the plan is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Plan::Restricted => "restricted",
+            Plan::Free => "free",
+            Plan::Pro => "pro",
+        }
+    }
+
+    /// A human-readable name for the plan.
+    pub fn name(self) -> &'static str {
+        match self {
+            Plan::Restricted => "Restricted",
+            Plan::Free => "Free",
+            Plan::Pro => "Pro",
+        }
+    }
+
+    /// Get the ID of the Stripe price users are charged for the plan. This will return none for
+    /// plans that can't be purchased.
+    pub fn stripe_price_id(self, config: &Config) -> Option<&str> {
+        match self {
+            Plan::Restricted | Plan::Free => None,
+            Plan::Pro => config.stripe_pro_price_id.as_deref(),
+        }
+    }
+
+    /// Get the number of API calls users on the plan can make per billing period. A limit of zero
+    /// means the plan has no limit.
+    pub fn api_call_limit(self, config: &Config) -> u32 {
+        match self {
+            Plan::Restricted | Plan::Free => config.free_plan_api_call_limit,
+            Plan::Pro => config.pro_plan_api_call_limit,
+        }
+    }
+
+    /// Find the plan users are charged a Stripe price for.
+    pub fn from_stripe_price_id(config: &Config, price_id: &str) -> Option<Plan> {
+        Plan::ALL
+            .iter()
+            .copied()
+            .find(|plan| plan.stripe_price_id(config) == Some(price_id))
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Plan {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "restricted" => Ok(Plan::Restricted),
+            "free" => Ok(Plan::Free),
+            "pro" => Ok(Plan::Pro),
+            _ => Err(anyhow!("Unknown plan: {}", value)),
+        }
+    }
+}
+
+/// Get every Stripe subscription status that entitles a user to their subscription's plan.
+pub fn active_statuses() -> Vec<String> {
+    ACTIVE_SUBSCRIPTION_STATUSES
+        .iter()
+        .map(|status| status.to_string())
+        .collect()
+}
+
+/// Specifies if a subscription with a Stripe status entitles its user to the subscription's plan.
+pub fn is_active_status(status: &str) -> bool {
+    ACTIVE_SUBSCRIPTION_STATUSES.contains(&status)
+}
+
+/// Details about a plan that can be subscribed to.
+#[derive(Debug, Clone)]
+pub struct PlanDetails {
+    /// The plan.
+    pub plan: Plan,
+    /// Specifies if the plan can be purchased with a checkout session.
+    pub is_purchasable: bool,
+}
+
+impl PlanDetails {
+    /// Get the details of every plan.
+    pub fn all(config: &Config) -> Vec<Self> {
+        Plan::ALL
+            .iter()
+            .map(|plan| PlanDetails {
+                plan: *plan,
+                is_purchasable: config.stripe_secret_key.is_some()
+                    && plan.stripe_price_id(config).is_some(),
+            })
+            .collect()
+    }
+}
+
+#[graphql_object(description = "Details about a plan that can be subscribed to.")]
+impl PlanDetails {
+    #[graphql(description = "The plan.")]
+    pub fn plan(&self) -> Plan {
+        self.plan
+    }
+
+    #[graphql(description = "A human-readable name for the plan.")]
+    pub fn name(&self) -> &str {
+        self.plan.name()
+    }
+
+    #[graphql(description = "Specifies if the plan can be purchased with a checkout session.")]
+    pub fn is_purchasable(&self) -> bool {
+        self.is_purchasable
+    }
+}
+
+/// A Stripe-hosted checkout page a user can purchase a subscription on.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckoutSession {
+    /// The ID of the checkout session in Stripe.
+    pub id: String,
+    /// The URL of the checkout page.
+    pub url: String,
+}
+
+#[graphql_object(description = "A checkout page a user can purchase a subscription on.")]
+impl CheckoutSession {
+    #[graphql(description = "The ID of the checkout session.")]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    #[graphql(description = "The URL of the checkout page the user should be sent to.")]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+/// Create a Stripe checkout session for a user to subscribe to a plan with. The user is charged
+/// as an existing Stripe customer if a customer ID is provided, or as a new customer otherwise.
+/// If a coupon ID is provided, its discount is applied to the subscription.
+pub async fn create_checkout_session(
+    config: &Config,
+    user: &User,
+    price_id: &str,
+    customer_id: Option<&str>,
+    coupon_id: Option<&str>,
+) -> Result<CheckoutSession> {
+    let Config {
+        billing_success_url,
+        billing_cancel_url,
+        ..
+    } = config;
+
+    let user_id = user.id.to_string();
+    let mut form = vec![
+        ("mode", "subscription"),
+        ("line_items[0][price]", price_id),
+        ("line_items[0][quantity]", "1"),
+        ("success_url", billing_success_url),
+        ("cancel_url", billing_cancel_url),
+        ("client_reference_id", &user_id),
+        // Subscription webhook events identify the user through this metadata.
+        ("subscription_data[metadata][user_id]", &user_id),
+    ];
+    match customer_id {
+        Some(customer_id) => form.push(("customer", customer_id)),
+        None => form.push(("customer_email", &user.email)),
+    }
+    if let Some(coupon_id) = coupon_id {
+        form.push(("discounts[0][coupon]", coupon_id));
+    }
+
+    let body = post_to_stripe(config, "/checkout/sessions", &form).await?;
+    Ok(serde_json::from_value(body)?)
+}
+
+/// Create a Stripe coupon for a promo code. Percentage discounts take the amount as a percentage,
+/// and fixed discounts take it in the smallest unit of the billing currency. The discount applies
+/// to the first invoice of a subscription. This will return the ID of the coupon.
+pub async fn create_coupon(
+    config: &Config,
+    code: &str,
+    kind: PromoCodeKind,
+    amount: i32,
+) -> Result<String> {
+    let amount = amount.to_string();
+    let mut form = vec![("name", code), ("duration", "once")];
+    match kind {
+        PromoCodeKind::Percentage => form.push(("percent_off", &amount)),
+        PromoCodeKind::Fixed => {
+            form.push(("amount_off", &amount));
+            form.push(("currency", &config.billing_currency));
+        }
+    }
+
+    let body = post_to_stripe(config, "/coupons", &form).await?;
+    body["id"]
+        .as_str()
+        .map(Into::into)
+        .ok_or_else(|| anyhow!("Stripe did not return a coupon ID."))
+}
+
+/// Apply the discount of a Stripe coupon to an existing subscription.
+pub async fn apply_coupon(config: &Config, subscription_id: &str, coupon_id: &str) -> Result<()> {
+    let path = format!("/subscriptions/{}", subscription_id);
+    post_to_stripe(config, &path, &[("discounts[0][coupon]", coupon_id)]).await?;
+    Ok(())
+}
+
+/// Send a form to an endpoint of the Stripe API and return the JSON response. This will return an
+/// error if no Stripe secret key is configured or the request fails.
+async fn post_to_stripe(config: &Config, path: &str, form: &[(&str, &str)]) -> Result<Value> {
+    let secret_key = config
+        .stripe_secret_key
+        .as_ref()
+        .ok_or_else(|| anyhow!("A Stripe secret key must be set to call the Stripe API."))?;
+
+    let mut response = surf::post(format!("{}{}", STRIPE_API_URL, path))
+        .header("Authorization", format!("Bearer {}", secret_key))
+        .body(surf::Body::from_form(&form).map_err(|error| error.into_inner())?)
+        .await
+        .map_err(|error| error.into_inner())?;
+
+    let body: Value = response
+        .body_json()
+        .await
+        .map_err(|error| error.into_inner())?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Stripe request to {} failed: {}",
+            path,
+            body["error"]["message"].as_str().unwrap_or("unknown error")
+        ));
+    }
+
+    Ok(body)
+}
+
+/// A Stripe subscription, as sent in webhook events. Only the fields the server uses are included.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripeSubscription {
+    /// The ID of the subscription in Stripe.
+    pub id: String,
+    /// The ID of the Stripe customer paying for the subscription.
+    pub customer: String,
+    /// The status of the subscription, e.g. "active" or "canceled".
+    pub status: String,
+    /// Unix timestamp of the end of the subscription's current billing period.
+    pub current_period_end: i64,
+    /// Specifies if the subscription will be canceled at the end of the current billing period.
+    #[serde(default)]
+    pub cancel_at_period_end: bool,
+    /// Metadata attached to the subscription when it was created.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// The items the subscription charges for.
+    pub items: StripeList<StripeSubscriptionItem>,
+    /// The discount applied to the subscription, if any.
+    #[serde(default)]
+    pub discount: Option<StripeDiscount>,
+}
+
+impl StripeSubscription {
+    /// Get the ID of the user the subscription belongs to.
+    pub fn user_id(&self) -> Option<Uuid> {
+        Uuid::parse_str(self.metadata.get("user_id")?).ok()
+    }
+
+    /// Get the ID of the price the subscription charges for.
+    pub fn price_id(&self) -> Option<&str> {
+        Some(&self.items.data.first()?.price.id)
+    }
+
+    /// Get the ID of the coupon the subscription is discounted with, if any.
+    pub fn coupon_id(&self) -> Option<&str> {
+        Some(&self.discount.as_ref()?.coupon.id)
+    }
+}
+
+/// A discount applied to a Stripe subscription.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripeDiscount {
+    /// The coupon the discount was created from.
+    pub coupon: StripeCoupon,
+}
+
+/// A Stripe coupon.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripeCoupon {
+    /// The ID of the coupon in Stripe.
+    pub id: String,
+}
+
+/// A list of Stripe objects.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripeList<T> {
+    /// The objects in the list.
+    pub data: Vec<T>,
+}
+
+/// An item a Stripe subscription charges for.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripeSubscriptionItem {
+    /// The price the item is charged at.
+    pub price: StripePrice,
+}
+
+/// A Stripe price.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripePrice {
+    /// The ID of the price in Stripe.
+    pub id: String,
+}
@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde_json::json;
+use tide::http::{mime, Method};
+use tide::{Body, Request, Response, StatusCode};
+
+use crate::config::Config;
+use crate::executor::Executor;
+use crate::graphql::{client_ip, request_id, REQUEST_ID_HEADER};
+use crate::routes::{RouteModule, Router};
+use crate::state::State;
+use crate::webhooks::WebhookProvider;
+
+/// Route module receiving Stripe webhook events. This is only enabled if a webhook signing secret
+/// is configured.
+pub struct BillingModule;
+
+impl RouteModule for BillingModule {
+    fn name(&self) -> &'static str {
+        "billing"
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        config.stripe_webhook_secret.is_some()
+    }
+
+    fn mount(&self, router: &mut dyn Router) {
+        router.route(Method::Post, "/billing/webhook", Arc::new(stripe_webhook));
+    }
+}
+
+/// Handle a Stripe webhook event, keeping subscriptions in sync with Stripe. Events are recorded
+/// before they are processed, so events Stripe resends are only processed once. Events the server
+/// doesn't need are acknowledged and ignored.
+async fn stripe_webhook(mut request: Request<State>) -> tide::Result {
+    let provider = WebhookProvider::Stripe;
+    let payload = request.body_string().await?;
+    let state = request.state().clone();
+    let secret = provider.secret(&state.config).unwrap_or_default();
+    let scheme = provider.signature_scheme();
+    let signature = request
+        .header(scheme.header())
+        .map(|values| values.last().as_str())
+        .unwrap_or_default();
+    if !scheme.verify(payload.as_bytes(), signature, secret, Utc::now()) {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "Invalid Stripe signature.",
+        ));
+    }
+
+    let (event_id, kind) = provider.parse_event(payload.as_bytes()).map_err(|error| {
+        tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!("Invalid Stripe event: {}", error),
+        )
+    })?;
+
+    let request_id = request_id(
+        request
+            .header(REQUEST_ID_HEADER)
+            .map(|values| values.last().as_str()),
+    );
+    let executor = Executor::new(state, request_id, client_ip(&request), None);
+    executor
+        .receive_webhook_event(provider, &event_id, &kind, &payload)
+        .await?;
+
+    let response = Response::builder(StatusCode::Ok)
+        .content_type(mime::JSON)
+        .body(Body::from_json(&json!({ "received": true }))?);
+
+    Ok(response.build())
+}
@@ -0,0 +1,114 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use anyhow::{anyhow, Error, Result};
+use tide::log;
+
+/// Message of the error returned when a request fetches more rows than its budget allows.
+pub const ROW_BUDGET_EXCEEDED_ERROR_MESSAGE: &str =
+    "The request fetched too many rows. Ask for smaller pages.";
+/// Error code returned when a request fetches more rows than its budget allows.
+pub const ROW_BUDGET_EXCEEDED_ERROR_CODE: &str = "row-budget-exceeded";
+
+/// What happens to a request once it goes over its budget.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BudgetAction {
+    /// A warning is logged and the request carries on.
+    Log,
+    /// A warning is logged and the request fails with the "row-budget-exceeded" error code.
+    #[default]
+    Reject,
+}
+
+impl FromStr for BudgetAction {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "log" => Ok(BudgetAction::Log),
+            "reject" => Ok(BudgetAction::Reject),
+            _ => Err(anyhow!("Unknown budget action: {}", value)),
+        }
+    }
+}
+
+/// How much work a single GraphQL request can do before it is logged or rejected. This is a
+/// guardrail against resolvers that accidentally scan a whole table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestBudget {
+    /// The max number of rows a request can fetch from the database. Zero means there is no limit.
+    pub max_rows: u64,
+    /// What happens to a request once it goes over its budget.
+    pub action: BudgetAction,
+}
+
+/// The error returned when a request fetches more rows than its budget allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RowBudgetExceeded {
+    /// The number of rows the request had fetched when it went over its budget.
+    pub rows: u64,
+    /// The max number of rows the request could fetch.
+    pub max_rows: u64,
+}
+
+impl Display for RowBudgetExceeded {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(
+            formatter,
+            "Request fetched {} rows, more than its budget of {}.",
+            self.rows, self.max_rows
+        )
+    }
+}
+
+impl std::error::Error for RowBudgetExceeded {}
+
+/// Counts the rows a request fetches from the database against its budget. Counting is atomic,
+/// so resolvers running at the same time can share the count.
+#[derive(Debug)]
+pub struct RowCounter {
+    budget: RequestBudget,
+    rows: AtomicU64,
+    // Set once the budget has been exceeded, so the warning is only logged once per request.
+    exceeded: AtomicBool,
+}
+
+impl RowCounter {
+    /// Create a counter for a request with a budget.
+    pub fn new(budget: RequestBudget) -> Self {
+        RowCounter {
+            budget,
+            rows: AtomicU64::new(0),
+            exceeded: AtomicBool::new(false),
+        }
+    }
+
+    /// Get the number of rows the request has fetched so far.
+    pub fn rows(&self) -> u64 {
+        self.rows.load(Ordering::Relaxed)
+    }
+
+    /// Count rows fetched by the request. This will return an error if the request has now fetched
+    /// more rows than its budget allows and such requests are rejected. A warning is logged the
+    /// first time the budget is exceeded.
+    pub fn add(&self, count: usize) -> Result<(), RowBudgetExceeded> {
+        let rows = self.rows.fetch_add(count as u64, Ordering::Relaxed) + count as u64;
+        let max_rows = self.budget.max_rows;
+        if max_rows == 0 || rows <= max_rows {
+            return Ok(());
+        }
+
+        if !self.exceeded.swap(true, Ordering::Relaxed) {
+            log::warn!(
+                "Request fetched {} rows, more than its budget of {}.",
+                rows,
+                max_rows
+            );
+        }
+        match self.budget.action {
+            BudgetAction::Log => Ok(()),
+            BudgetAction::Reject => Err(RowBudgetExceeded { rows, max_rows }),
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Error, Result};
+use async_trait::async_trait;
+use redis::AsyncCommands;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tide::log;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::events::RowChange;
+use crate::metrics::InstrumentedConnection;
+use crate::models::{Subscription, Trial};
+
+/// How long cached values are kept. Values are invalidated as soon as the rows they were read
+/// from change, so this only limits how long a missed invalidation can go unnoticed.
+const MAX_CACHE_AGE: Duration = Duration::from_secs(60 * 60);
+/// Max number of values cached before the cache is cleared, so it can't grow without bound.
+const MAX_CACHE_ENTRIES: usize = 100_000;
+/// Number of seconds the generation of a cached query is kept for after it was last changed. This
+/// is far longer than any result is cached for, so results of a forgotten generation have expired
+/// by the time it is forgotten.
+pub const GENERATION_TTL_SECONDS: u32 = 7 * 24 * 60 * 60;
+
+/// Decides where cached results are kept. Values that must be shared by every server instance,
+/// such as rate limit counters and verification codes, are kept in the store instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheBackend {
+    /// Values are kept in Redis, where they are shared by every server instance.
+    #[default]
+    Redis,
+    /// Values are kept in the server's memory. This only works for a single server instance.
+    Memory,
+    /// Values aren't kept at all.
+    None,
+}
+
+impl FromStr for CacheBackend {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "redis" => Ok(CacheBackend::Redis),
+            "memory" => Ok(CacheBackend::Memory),
+            "none" => Ok(CacheBackend::None),
+            _ => Err(anyhow!("Unknown cache backend: {}", value)),
+        }
+    }
+}
+
+/// Somewhere short-lived values can be kept until they expire. Implement this to keep them
+/// somewhere other than Redis or the server's memory.
+#[async_trait]
+pub trait Cache: Send + Sync {
+    /// A short name for the cache, used when logging.
+    fn name(&self) -> &'static str;
+
+    /// Get the value at a key. This will return none if there is no value or it has expired.
+    async fn get(&self, key: &str) -> Result<Option<String>>;
+
+    /// Set the value at a key, replacing any value already there, until it expires after a number
+    /// of seconds.
+    async fn set(&self, key: &str, value: &str, ttl_seconds: u32) -> Result<()>;
+
+    /// Delete the value at a key. This will return true if there was a value to delete. Only one
+    /// of several callers deleting the same value sees true, so this can be used to consume
+    /// one-time values.
+    async fn del(&self, key: &str) -> Result<bool>;
+
+    /// Increment the counter at a key, returning its new count. A counter that doesn't exist starts
+    /// at zero and expires after a number of seconds. Incrementing it again doesn't extend that.
+    async fn incr(&self, key: &str, ttl_seconds: u32) -> Result<u64>;
+
+    /// Get the number of seconds until the value at a key expires. This will return none if there
+    /// is no value.
+    async fn ttl(&self, key: &str) -> Result<Option<u32>>;
+}
+
+/// Keeps values in Redis, where they are shared by every server instance.
+pub struct RedisCache {
+    connection: InstrumentedConnection,
+}
+
+impl RedisCache {
+    /// Create a cache keeping values in the Redis database behind a connection.
+    pub fn new(connection: InstrumentedConnection) -> Self {
+        RedisCache { connection }
+    }
+}
+
+#[async_trait]
+impl Cache for RedisCache {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.connection.clone().get(key).await?)
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl_seconds: u32) -> Result<()> {
+        Ok(self
+            .connection
+            .clone()
+            .set_ex(key, value, ttl_seconds as usize)
+            .await?)
+    }
+
+    async fn del(&self, key: &str) -> Result<bool> {
+        Ok(self.connection.clone().del::<_, u32>(key).await? > 0)
+    }
+
+    async fn incr(&self, key: &str, ttl_seconds: u32) -> Result<u64> {
+        let mut connection = self.connection.clone();
+        let count = connection.incr::<_, _, u64>(key, 1).await?;
+        if count == 1 {
+            connection
+                .expire::<_, ()>(key, ttl_seconds as usize)
+                .await?;
+        }
+
+        Ok(count)
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<u32>> {
+        // Redis answers with a negative TTL for keys that don't exist or never expire.
+        let ttl = self.connection.clone().ttl::<_, i64>(key).await?;
+        Ok(if ttl >= 0 { Some(ttl as u32) } else { None })
+    }
+}
+
+/// Keeps values in the server's memory. Values aren't shared between server instances, so this is
+/// only suitable for deployments running a single instance.
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl MemoryCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run a function with the cached entries, once expired entries are removed. Entries are
+    /// removed when read, and every entry is checked whenever the cache has grown large.
+    fn with_entries<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut HashMap<String, (Instant, String)>) -> T,
+    ) -> T {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        if entries.len() >= MAX_CACHE_ENTRIES {
+            entries.retain(|_, (expires_at, _)| *expires_at > now);
+        }
+        if matches!(entries.get(key), Some((expires_at, _)) if *expires_at <= now) {
+            entries.remove(key);
+        }
+
+        f(&mut entries)
+    }
+}
+
+#[async_trait]
+impl Cache for MemoryCache {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.with_entries(key, |entries| {
+            entries.get(key).map(|(_, value)| value.clone())
+        }))
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl_seconds: u32) -> Result<()> {
+        let expires_at = Instant::now() + Duration::from_secs(ttl_seconds.into());
+        self.with_entries(key, |entries| {
+            entries.insert(key.into(), (expires_at, value.into()));
+        });
+        Ok(())
+    }
+
+    async fn del(&self, key: &str) -> Result<bool> {
+        Ok(self.with_entries(key, |entries| entries.remove(key).is_some()))
+    }
+
+    async fn incr(&self, key: &str, ttl_seconds: u32) -> Result<u64> {
+        let expires_at = Instant::now() + Duration::from_secs(ttl_seconds.into());
+        self.with_entries(key, |entries| {
+            let (_, value) = entries
+                .entry(key.into())
+                .or_insert_with(|| (expires_at, "0".into()));
+            let count = value.parse::<u64>()? + 1;
+            *value = count.to_string();
+            Ok(count)
+        })
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<u32>> {
+        Ok(self.with_entries(key, |entries| {
+            entries.get(key).map(|(expires_at, _)| {
+                expires_at
+                    .saturating_duration_since(Instant::now())
+                    .as_secs() as u32
+            })
+        }))
+    }
+}
+
+/// Keeps nothing. Values are never found, so nothing is cached. This must never be used as the
+/// store, as verification codes would never be found and no rate limit would ever be reached.
+pub struct NoCache;
+
+#[async_trait]
+impl Cache for NoCache {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    async fn get(&self, _key: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn set(&self, _key: &str, _value: &str, _ttl_seconds: u32) -> Result<()> {
+        Ok(())
+    }
+
+    async fn del(&self, _key: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    async fn incr(&self, _key: &str, _ttl_seconds: u32) -> Result<u64> {
+        Ok(0)
+    }
+
+    async fn ttl(&self, _key: &str) -> Result<Option<u32>> {
+        Ok(None)
+    }
+}
+
+/// Get the cache defined by the server configuration. The Redis cache sends commands through the
+/// provided connection.
+pub fn cache(config: &Config, redis: InstrumentedConnection) -> Arc<dyn Cache> {
+    match config.cache_backend {
+        CacheBackend::Redis => Arc::new(RedisCache::new(redis)),
+        CacheBackend::Memory => Arc::new(MemoryCache::new()),
+        CacheBackend::None => Arc::new(NoCache),
+    }
+}
+
+/// The billing records deciding a user's plan and usage period.
+#[derive(Debug, Clone)]
+pub struct BillingRecords {
+    pub subscription: Option<Subscription>,
+    pub trial: Option<Trial>,
+}
+
+/// In-process cache of values read from rows belonging to a user. Entries are invalidated when
+/// those rows change, whichever server instance or client changed them, through the row change
+/// listener.
+#[derive(Clone)]
+pub struct UserCache<T> {
+    inner: Arc<Mutex<UserCacheInner<T>>>,
+}
+
+struct UserCacheInner<T> {
+    /// Incremented whenever entries are invalidated.
+    version: u64,
+    entries: HashMap<Uuid, (Instant, T)>,
+}
+
+impl<T: Clone> UserCache<T> {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        UserCache {
+            inner: Arc::new(Mutex::new(UserCacheInner {
+                version: 0,
+                entries: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Get the cached value for a user, if it is cached and hasn't expired.
+    pub fn get(&self, user_id: Uuid) -> Option<T> {
+        match self.inner.lock().unwrap().entries.get(&user_id) {
+            Some((cached_at, value)) if cached_at.elapsed() < MAX_CACHE_AGE => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    /// Get the version of the cache, which must be taken before reading a value to cache.
+    pub fn version(&self) -> u64 {
+        self.inner.lock().unwrap().version
+    }
+
+    /// Cache a value for a user that was read at a version of the cache. The value isn't cached if
+    /// anything was invalidated since, as it may have been read before the change.
+    pub fn insert(&self, user_id: Uuid, version: u64, value: T) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.version != version {
+            return;
+        }
+        if inner.entries.len() >= MAX_CACHE_ENTRIES {
+            inner.entries.clear();
+        }
+        inner.entries.insert(user_id, (Instant::now(), value));
+    }
+
+    /// Remove the cached value for a user.
+    pub fn invalidate(&self, user_id: Uuid) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.version += 1;
+        inner.entries.remove(&user_id);
+    }
+
+    /// Remove every cached value.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.version += 1;
+        inner.entries.clear();
+    }
+}
+
+impl<T: Clone> Default for UserCache<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Caches shared by every request the server handles.
+#[derive(Clone, Default)]
+pub struct Caches {
+    /// The billing records of users, read whenever a user's API call is counted.
+    pub billing: UserCache<BillingRecords>,
+}
+
+impl Caches {
+    /// Remove every cached value read from a changed row.
+    pub fn invalidate(&self, change: &RowChange) {
+        match change.table.as_str() {
+            "subscriptions" | "trials" => self.billing.invalidate(change.key),
+            _ => return,
+        }
+
+        log::debug!(
+            "Invalidated cached {} of user: {}",
+            change.table,
+            change.key
+        );
+    }
+
+    /// Remove every cached value. This is done whenever row changes may have been missed.
+    pub fn clear(&self) {
+        self.billing.clear();
+    }
+}
+
+/// An expensive read whose results can be cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CachedQuery {
+    /// Counting users, which is done for every page of users.
+    UserCount,
+    /// Listing feedback submissions.
+    Feedback,
+    /// Aggregating query statistics from "pg_stat_statements".
+    TopQueries,
+    /// Finding the announcements shown to a user, which most clients do on every page load.
+    ActiveAnnouncements,
+}
+
+impl CachedQuery {
+    /// Get the name of the query, used in cache keys and metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            CachedQuery::UserCount => "user-count",
+            CachedQuery::Feedback => "feedback",
+            CachedQuery::TopQueries => "top-queries",
+            CachedQuery::ActiveAnnouncements => "active-announcements",
+        }
+    }
+}
+
+/// How long the results of each cached query are kept, in seconds. Results are invalidated by the
+/// mutations changing them, so this mostly limits how stale results of reads that depend on time
+/// can get. Zero disables caching for a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResultCacheTtls {
+    pub user_count_seconds: u32,
+    pub feedback_seconds: u32,
+    pub top_queries_seconds: u32,
+    pub active_announcements_seconds: u32,
+}
+
+impl ResultCacheTtls {
+    /// Get how long results of a query are kept, in seconds.
+    pub fn ttl_seconds(&self, query: CachedQuery) -> u32 {
+        match query {
+            CachedQuery::UserCount => self.user_count_seconds,
+            CachedQuery::Feedback => self.feedback_seconds,
+            CachedQuery::TopQueries => self.top_queries_seconds,
+            CachedQuery::ActiveAnnouncements => self.active_announcements_seconds,
+        }
+    }
+}
+
+/// Get the cache key of the generation of a query's results, which is replaced with a random one
+/// whenever every cached result of the query is invalidated. Results are cached under the current
+/// generation, so replacing it leaves older results unread until they expire.
+pub fn generation_key(query: CachedQuery) -> String {
+    format!("result-cache/{}/generation", query.name())
+}
+
+/// Get the cache key a result of a query is cached at, from the generation of the query, the user
+/// the result was read for, if it depends on them, and the variables it was read with.
+pub fn result_key(
+    query: CachedQuery,
+    generation: u64,
+    viewer: Option<Uuid>,
+    variables: &Value,
+) -> String {
+    let viewer = viewer.map_or_else(|| "anyone".to_string(), |viewer| viewer.to_string());
+    let variables = Sha256::digest(variables.to_string().as_bytes());
+    format!(
+        "result-cache/{}/{}/{}/{:x}",
+        query.name(),
+        generation,
+        viewer,
+        variables
+    )
+}
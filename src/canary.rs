@@ -0,0 +1,103 @@
+use std::fmt::Debug;
+
+use anyhow::Result;
+use rand::Rng;
+use tide::log;
+
+/// A new implementation of a read that is run in the shadow of the implementation it replaces for
+/// a percentage of traffic. Results of the new implementation are only compared with those of the
+/// old one, never returned, so refactors can be checked against real traffic before switching
+/// over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Canary {
+    /// Finding the first page of users with the user search query, rather than keyset
+    /// pagination.
+    UserPages,
+}
+
+impl Canary {
+    /// Get the name of the canary, used in logs and metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            Canary::UserPages => "user-pages",
+        }
+    }
+}
+
+/// The percentage of calls each canary is run for. Zero disables a canary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CanaryPercentages {
+    pub user_pages_percent: u32,
+}
+
+impl CanaryPercentages {
+    /// Get the percentage of calls a canary is run for.
+    pub fn percent(&self, canary: Canary) -> u32 {
+        match canary {
+            Canary::UserPages => self.user_pages_percent,
+        }
+    }
+
+    /// Decide whether a canary is run for a call, sampling calls at the canary's percentage.
+    pub fn is_sampled(&self, canary: Canary) -> bool {
+        match self.percent(canary) {
+            0 => false,
+            percent => rand::thread_rng().gen_range(0..100) < percent,
+        }
+    }
+}
+
+/// What happened when a canary was run alongside the implementation it replaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CanaryOutcome {
+    /// Both implementations found the same result.
+    Match,
+    /// The implementations found different results.
+    Mismatch,
+    /// The new implementation failed where the old one didn't.
+    Error,
+}
+
+impl CanaryOutcome {
+    /// Get the name of the outcome, used in metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            CanaryOutcome::Match => "match",
+            CanaryOutcome::Mismatch => "mismatch",
+            CanaryOutcome::Error => "error",
+        }
+    }
+}
+
+/// Compare the result of a canary with the result of the implementation it replaces, logging
+/// mismatches and errors along with the ID of the request they were found in. Results are compared
+/// by a summary of each, such as the IDs of the users found, so nothing sensitive is logged.
+pub fn compare<K: PartialEq + Debug>(
+    canary: Canary,
+    request_id: &str,
+    expected: &K,
+    actual: Result<K>,
+) -> CanaryOutcome {
+    match actual {
+        Ok(actual) if actual == *expected => CanaryOutcome::Match,
+        Ok(actual) => {
+            log::warn!(
+                "Canary {} found a different result in request {}. Expected {:?}, found {:?}.",
+                canary.name(),
+                request_id,
+                expected,
+                actual
+            );
+            CanaryOutcome::Mismatch
+        }
+        Err(error) => {
+            log::warn!(
+                "Canary {} failed in request {}: {}",
+                canary.name(),
+                request_id,
+                error
+            );
+            CanaryOutcome::Error
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_lock::{Semaphore, SemaphoreGuardArc};
+use async_std::future;
+
+/// Message returned for calls to a limited resolver that couldn't start before the queue timeout.
+pub const RESOLVER_BUSY_ERROR_MESSAGE: &str =
+    "Too many requests are using this right now. Try again shortly.";
+/// Error code returned for calls to a limited resolver that couldn't start before the queue
+/// timeout.
+pub const RESOLVER_BUSY_ERROR_CODE: &str = "resolver-busy";
+
+/// An expensive resolver whose parallelism is limited on each server instance, so a burst of
+/// calls to it can't exhaust the database connections shared with every other request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitedResolver {
+    /// Paginating through users, which counts every user matching the page.
+    Users,
+    /// Listing feedback submissions.
+    Feedback,
+    /// Aggregating query statistics from "pg_stat_statements".
+    TopQueries,
+    /// Exporting users as CSV, which holds a database connection until the export is sent.
+    Exports,
+}
+
+impl LimitedResolver {
+    /// Every limited resolver.
+    const ALL: &'static [LimitedResolver] = &[
+        LimitedResolver::Users,
+        LimitedResolver::Feedback,
+        LimitedResolver::TopQueries,
+        LimitedResolver::Exports,
+    ];
+}
+
+/// Limits on how many calls to each expensive resolver run at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConcurrencyLimits {
+    /// The max number of calls to each limited resolver running at once. Zero means there is no
+    /// limit.
+    pub max_concurrent: usize,
+    /// The number of milliseconds calls over the limit wait for another call to finish before
+    /// being rejected. Zero rejects them immediately.
+    pub queue_timeout_ms: u64,
+}
+
+/// A slot held by a call to a limited resolver. The slot is freed once this is dropped.
+pub struct ResolverPermit {
+    _guard: Option<SemaphoreGuardArc>,
+}
+
+/// Limits the parallelism of expensive resolvers. Each resolver has its own semaphore, so a burst
+/// of calls to one doesn't hold up the others. This is cheap to clone, and clones share the same
+/// limits.
+#[derive(Clone)]
+pub struct ConcurrencyLimiter {
+    semaphores: Arc<HashMap<LimitedResolver, Arc<Semaphore>>>,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimiter {
+    /// Create a limiter enforcing the configured limits.
+    pub fn new(limits: &ConcurrencyLimits) -> Self {
+        let semaphores = if limits.max_concurrent > 0 {
+            LimitedResolver::ALL
+                .iter()
+                .map(|resolver| (*resolver, Arc::new(Semaphore::new(limits.max_concurrent))))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        ConcurrencyLimiter {
+            semaphores: Arc::new(semaphores),
+            queue_timeout: Duration::from_millis(limits.queue_timeout_ms),
+        }
+    }
+
+    /// Wait for a slot to call a limited resolver, queuing behind other calls until the queue
+    /// timeout. This will return none if no slot was freed in time, in which case the call should
+    /// be rejected.
+    pub async fn acquire(&self, resolver: LimitedResolver) -> Option<ResolverPermit> {
+        let semaphore = match self.semaphores.get(&resolver) {
+            Some(semaphore) => semaphore,
+            None => return Some(ResolverPermit { _guard: None }),
+        };
+        if let Some(guard) = semaphore.try_acquire_arc() {
+            return Some(ResolverPermit {
+                _guard: Some(guard),
+            });
+        }
+
+        let guard = future::timeout(self.queue_timeout, semaphore.acquire_arc())
+            .await
+            .ok()?;
+        Some(ResolverPermit {
+            _guard: Some(guard),
+        })
+    }
+}
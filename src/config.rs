@@ -1,25 +1,627 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
 use std::str::FromStr;
 
+use anyhow::Result;
 use tide::log;
 
+use crate::alerts::AlertThresholds;
 use crate::auth::{SessionToken, SessionTokenSecret};
+use crate::budgets::RequestBudget;
+use crate::cache::{CacheBackend, ResultCacheTtls};
+use crate::canary::CanaryPercentages;
+use crate::concurrency::ConcurrencyLimits;
+use crate::email::settings::SettingsKey;
+use crate::graphql::ErrorStatusPolicy;
+use crate::hash_cost::{MAX_BCRYPT_COST, MIN_BCRYPT_COST};
+use crate::logging::LogFormat;
+use crate::password_policy::PasswordPolicy;
+use crate::proxies::TrustedProxy;
+use crate::query_limits::QueryLimits;
+use crate::regions::RegionConfig;
+use crate::retention::RetentionPolicy;
+use crate::session_cookies::SessionCookiePolicy;
+use crate::signup_screening::SignupScreeningAction;
+use crate::storage::StorageBackend;
 
-// Names of server-relevant environment variables.
-const PORT_VARIABLE: &str = "PORT";
-const DATABASE_URL_VARIABLE: &str = "DATABASE_URL";
-const DATABASE_MAX_CONNECTION_COUNT_VARIABLE: &str = "DATABASE_MAX_CONNECTION_COUNT";
-const REDIS_URL_VARIABLE: &str = "REDIS_URL";
-const SESSION_TOKEN_SECRET_VARIABLE: &str = "SESSION_TOKEN_SECRET";
-const SESSION_TOKEN_EXPIRATION_SECONDS_VARIABLE: &str = "SESSION_TOKEN_EXPIRATION_SECONDS";
-const PASSWORD_HASH_COST_VARIABLE: &str = "PASSWORD_HASH_COST";
-const EMAIL_SMTP_VARIABLE: &str = "EMAIL_SMTP";
-const EMAIL_SMTP_PORT_VARIABLE: &str = "EMAIL_SMTP_PORT";
-const EMAIL_SMTP_USE_STARTTLS_VARIABLE: &str = "EMAIL_SMTP_USE_STARTTLS";
-const EMAIL_VERIFICATION_EMAIL_ADDRESS_VARIABLE: &str = "EMAIL_VERIFICATION_EMAIL_ADDRESS";
-const EMAIL_VERIFICATION_EMAIL_PASSWORD_VARIABLE: &str = "EMAIL_VERIFICATION_EMAIL_PASSWORD";
-const EMAIL_VERIFICATION_CODE_EXPIRATION_SECONDS_VARIABLE: &str =
-    "EMAIL_VERIFICATION_CODE_EXPIRATION_SECONDS";
-const IS_DOCKER_VARIABLE: &str = "IS_DOCKER";
+// Environment variables the configuration is loaded from.
+const PORT_VARIABLE: Variable = Variable::new("PORT", "The port the server listens on.");
+const DATABASE_URL_VARIABLE: Variable = Variable::secret(
+    "DATABASE_URL",
+    "A connection string for the Postgres database.",
+);
+const DATABASE_MAX_CONNECTION_COUNT_VARIABLE: Variable = Variable::new(
+    "DATABASE_MAX_CONNECTION_COUNT",
+    "The max number of pooled connections kept with the Postgres database.",
+);
+const WARMUP_CONNECTION_COUNT_VARIABLE: Variable = Variable::new(
+    "WARMUP_CONNECTION_COUNT",
+    "The number of database connections opened before the server accepts requests. This is \
+    capped at the max connection count.",
+);
+const DATABASE_LOG_QUERIES_VARIABLE: Variable = Variable::new(
+    "DATABASE_LOG_QUERIES",
+    "Specifies if every database query is logged along with how long it took.",
+);
+const DATABASE_SLOW_QUERY_THRESHOLD_MS_VARIABLE: Variable = Variable::new(
+    "DATABASE_SLOW_QUERY_THRESHOLD_MS",
+    "The number of milliseconds after which a database query is logged as slow. Set to 0 to \
+    disable.",
+);
+const LIFECYCLE_HOOK_TIMEOUT_SECONDS_VARIABLE: Variable = Variable::new(
+    "LIFECYCLE_HOOK_TIMEOUT_SECONDS",
+    "The max number of seconds each subsystem can take to start or shut down.",
+);
+const RUN_MIGRATIONS_ON_STARTUP_VARIABLE: Variable = Variable::new(
+    "RUN_MIGRATIONS_ON_STARTUP",
+    "Specifies if pending database migrations are run when the server starts.",
+);
+const REDIS_URL_VARIABLE: Variable =
+    Variable::secret("REDIS_URL", "A connection string for the Redis database.");
+const REDIS_REGION_VARIABLE: Variable = Variable::new(
+    "REDIS_REGION",
+    "The region the Redis database at REDIS_URL is in, such as \"us-east\".",
+);
+const REDIS_REGION_URLS_VARIABLE: Variable = Variable::secret(
+    "REDIS_REGION_URLS",
+    "The Redis databases of other regions sessions are replicated to, each as a region and a \
+    connection string joined by \"=\".",
+);
+const REDIS_REGION_LATENCY_PROBE_INTERVAL_SECONDS_VARIABLE: Variable = Variable::new(
+    "REDIS_REGION_LATENCY_PROBE_INTERVAL_SECONDS",
+    "The number of seconds between measurements of the round trip time to each other region. \
+    Zero measures it only once, when the server starts.",
+);
+const SESSION_TOKEN_SECRET_VARIABLE: Variable = Variable::secret(
+    "SESSION_TOKEN_SECRET",
+    "The secret session tokens are signed with.",
+);
+const SESSION_TOKEN_EXPIRATION_SECONDS_VARIABLE: Variable = Variable::new(
+    "SESSION_TOKEN_EXPIRATION_SECONDS",
+    "The number of seconds it takes for a session token to expire.",
+);
+const SESSION_COOKIE_ENABLED_VARIABLE: Variable = Variable::new(
+    "SESSION_COOKIE_ENABLED",
+    "Specifies if session tokens are delivered as HTTP-only cookies instead of in response bodies.",
+);
+const SESSION_COOKIE_NAME_VARIABLE: Variable = Variable::new(
+    "SESSION_COOKIE_NAME",
+    "The name of the cookie holding the session token.",
+);
+const SESSION_COOKIE_DOMAIN_VARIABLE: Variable = Variable::new(
+    "SESSION_COOKIE_DOMAIN",
+    "The domain session cookies are sent to. They are only sent to the server's own host if this \
+    is empty.",
+);
+const SESSION_COOKIE_SECURE_VARIABLE: Variable = Variable::new(
+    "SESSION_COOKIE_SECURE",
+    "Specifies if session cookies are only sent over HTTPS.",
+);
+const SESSION_COOKIE_SAME_SITE_VARIABLE: Variable = Variable::new(
+    "SESSION_COOKIE_SAME_SITE",
+    "Decides when browsers send session cookies with requests started by other sites. One of \
+    \"strict\", \"lax\" or \"none\".",
+);
+const PASSWORD_HASH_COST_VARIABLE: Variable = Variable::new(
+    "PASSWORD_HASH_COST",
+    "The bcrypt cost passwords are hashed with.",
+);
+const LOGIN_LOCKOUT_THRESHOLD_VARIABLE: Variable = Variable::new(
+    "LOGIN_LOCKOUT_THRESHOLD",
+    "The number of failed logins after which a username is locked. Zero disables the lockout.",
+);
+const LOGIN_IP_LOCKOUT_THRESHOLD_VARIABLE: Variable = Variable::new(
+    "LOGIN_IP_LOCKOUT_THRESHOLD",
+    "The number of failed logins after which an IP address is locked. Zero disables the lockout.",
+);
+const LOGIN_LOCKOUT_SECONDS_VARIABLE: Variable = Variable::new(
+    "LOGIN_LOCKOUT_SECONDS",
+    "The number of seconds failed logins are counted for and lockouts last.",
+);
+const TRUSTED_PROXIES_VARIABLE: Variable = Variable::new(
+    "TRUSTED_PROXIES",
+    "Addresses or CIDR blocks of proxies whose \"X-Forwarded-For\" headers are trusted to give \
+    the client's IP address.",
+);
+#[cfg(feature = "email")]
+const EMAIL_SMTP_VARIABLE: Variable =
+    Variable::new("EMAIL_SMTP", "The SMTP server emails are sent through.");
+#[cfg(feature = "email")]
+const EMAIL_SMTP_PORT_VARIABLE: Variable =
+    Variable::new("EMAIL_SMTP_PORT", "The port of the SMTP server.");
+#[cfg(feature = "email")]
+const EMAIL_SMTP_USE_STARTTLS_VARIABLE: Variable = Variable::new(
+    "EMAIL_SMTP_USE_STARTTLS",
+    "Specifies if connections to the SMTP server use STARTTLS.",
+);
+#[cfg(feature = "email")]
+const EMAIL_SMTP_MAX_CONNECTIONS_VARIABLE: Variable = Variable::new(
+    "EMAIL_SMTP_MAX_CONNECTIONS",
+    "The max number of pooled connections kept with the SMTP server.",
+);
+#[cfg(feature = "email")]
+const EMAIL_MAX_CONCURRENT_SENDS_VARIABLE: Variable = Variable::new(
+    "EMAIL_MAX_CONCURRENT_SENDS",
+    "The max number of emails sent at once.",
+);
+#[cfg(feature = "email")]
+const EMAIL_RECIPIENT_HOURLY_LIMIT_VARIABLE: Variable = Variable::new(
+    "EMAIL_RECIPIENT_HOURLY_LIMIT",
+    "The max number of emails sent to a single recipient per hour. Zero disables the limit.",
+);
+#[cfg(feature = "email")]
+const EMAIL_MINUTE_LIMIT_VARIABLE: Variable = Variable::new(
+    "EMAIL_MINUTE_LIMIT",
+    "The max number of emails sent per minute. Zero disables the limit.",
+);
+#[cfg(feature = "email")]
+const EMAIL_DAILY_QUOTA_VARIABLE: Variable = Variable::new(
+    "EMAIL_DAILY_QUOTA",
+    "The max number of emails sent per day. Zero disables the limit.",
+);
+#[cfg(feature = "email")]
+const EMAIL_MAX_DELIVERY_ATTEMPTS_VARIABLE: Variable = Variable::new(
+    "EMAIL_MAX_DELIVERY_ATTEMPTS",
+    "The number of times delivering an email is attempted before it is dead-lettered.",
+);
+#[cfg(feature = "email")]
+const EMAIL_DKIM_SELECTOR_VARIABLE: Variable = Variable::new(
+    "EMAIL_DKIM_SELECTOR",
+    "The DKIM selector emails are signed with. Emails are only signed if this is set.",
+);
+#[cfg(feature = "email")]
+const EMAIL_DKIM_DOMAIN_VARIABLE: Variable = Variable::new(
+    "EMAIL_DKIM_DOMAIN",
+    "The domain emails are signed for. Defaults to the domain of the sending address.",
+);
+#[cfg(feature = "email")]
+const EMAIL_DKIM_PRIVATE_KEY_PATH_VARIABLE: Variable = Variable::new(
+    "EMAIL_DKIM_PRIVATE_KEY_PATH",
+    "The path to the PEM file holding the RSA private key emails are signed with.",
+);
+const EMAIL_SENDER_NAME_VARIABLE: Variable = Variable::new(
+    "EMAIL_SENDER_NAME",
+    "The display name emails are sent from.",
+);
+const EMAIL_SETTINGS_KEY_VARIABLE: Variable = Variable::secret(
+    "EMAIL_SETTINGS_KEY",
+    "The base64 encoded 256-bit key organizations' SMTP passwords are encrypted with. \
+    Organizations can only send emails through their own SMTP server if this is set.",
+);
+#[cfg(feature = "email")]
+const EMAIL_VERIFICATION_EMAIL_ADDRESS_VARIABLE: Variable = Variable::new(
+    "EMAIL_VERIFICATION_EMAIL_ADDRESS",
+    "The email account emails are sent from.",
+);
+#[cfg(feature = "email")]
+const EMAIL_VERIFICATION_EMAIL_PASSWORD_VARIABLE: Variable = Variable::secret(
+    "EMAIL_VERIFICATION_EMAIL_PASSWORD",
+    "The password of the email account emails are sent from.",
+);
+const EMAIL_VERIFICATION_CODE_EXPIRATION_SECONDS_VARIABLE: Variable = Variable::new(
+    "EMAIL_VERIFICATION_CODE_EXPIRATION_SECONDS",
+    "The number of seconds it takes for an email verification code to expire.",
+);
+const VERIFICATION_EMAIL_RESEND_COOLDOWN_SECONDS_VARIABLE: Variable = Variable::new(
+    "VERIFICATION_EMAIL_RESEND_COOLDOWN_SECONDS",
+    "The number of seconds a user must wait between verification email resends. Zero disables \
+    the cooldown.",
+);
+const VERIFICATION_EMAIL_HOURLY_RESEND_LIMIT_VARIABLE: Variable = Variable::new(
+    "VERIFICATION_EMAIL_HOURLY_RESEND_LIMIT",
+    "The max number of verification email resends per user per hour. Zero disables the limit.",
+);
+const SIGNUP_SCREENING_ENABLED_VARIABLE: Variable = Variable::new(
+    "SIGNUP_SCREENING_ENABLED",
+    "Specifies if signups are screened for signals suggesting they were made by a bot.",
+);
+const SIGNUP_SCREENING_ACTION_VARIABLE: Variable = Variable::new(
+    "SIGNUP_SCREENING_ACTION",
+    "What happens to suspicious signups. One of \"review\" or \"captcha\".",
+);
+const SIGNUP_MIN_FORM_SECONDS_VARIABLE: Variable = Variable::new(
+    "SIGNUP_MIN_FORM_SECONDS",
+    "The number of seconds a person takes to fill in the signup form at the very least.",
+);
+const SIGNUP_FORM_TOKEN_EXPIRATION_SECONDS_VARIABLE: Variable = Variable::new(
+    "SIGNUP_FORM_TOKEN_EXPIRATION_SECONDS",
+    "The number of seconds it takes for a signup form token to expire.",
+);
+const CAPTCHA_VERIFY_URL_VARIABLE: Variable = Variable::new(
+    "CAPTCHA_VERIFY_URL",
+    "The URL CAPTCHA tokens are verified at, such as hCaptcha's \"siteverify\" endpoint.",
+);
+const CAPTCHA_SECRET_VARIABLE: Variable = Variable::secret(
+    "CAPTCHA_SECRET",
+    "The secret CAPTCHA tokens are verified with. CAPTCHAs can only be passed if this is set.",
+);
+const PASSWORD_MIN_LENGTH_VARIABLE: Variable = Variable::new(
+    "PASSWORD_MIN_LENGTH",
+    "The min number of characters in a password.",
+);
+const PASSWORD_MIN_SCORE_VARIABLE: Variable = Variable::new(
+    "PASSWORD_MIN_SCORE",
+    "The min zxcvbn score of a password, from 0 to 4. Zero disables the check.",
+);
+const PASSWORD_REJECT_COMMON_VARIABLE: Variable = Variable::new(
+    "PASSWORD_REJECT_COMMON",
+    "Specifies if the 10,000 most commonly used passwords are rejected.",
+);
+const PASSWORD_REJECT_USER_DETAILS_VARIABLE: Variable = Variable::new(
+    "PASSWORD_REJECT_USER_DETAILS",
+    "Specifies if passwords containing the user's username or email address are rejected.",
+);
+const EMAIL_MX_LOOKUP_ENABLED_VARIABLE: Variable = Variable::new(
+    "EMAIL_MX_LOOKUP_ENABLED",
+    "Specifies if new email addresses are rejected when their domain can't receive email.",
+);
+const GRAPHQL_ERROR_STATUS_POLICY_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_ERROR_STATUS_POLICY",
+    "Decides the HTTP status of GraphQL responses with errors. One of \"spec\", \"partial\" \
+    or \"strict\".",
+);
+const GRAPHQL_MAX_RESPONSE_BYTES_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_MAX_RESPONSE_BYTES",
+    "The max size of a GraphQL response in bytes. Zero disables the limit.",
+);
+const GRAPHQL_MAX_ROWS_FETCHED_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_MAX_ROWS_FETCHED",
+    "The max number of database rows a single GraphQL request can fetch. Zero disables the \
+    budget.",
+);
+const GRAPHQL_BUDGET_ACTION_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_BUDGET_ACTION",
+    "What happens to GraphQL requests that fetch more rows than their budget allows. One of \
+    \"log\" or \"reject\".",
+);
+const GRAPHQL_MAX_BATCH_SIZE_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_MAX_BATCH_SIZE",
+    "The max number of GraphQL requests sent together in a batch. Zero disables batching.",
+);
+const GRAPHQL_GET_CACHE_MAX_AGE_SECONDS_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_GET_CACHE_MAX_AGE_SECONDS",
+    "The number of seconds responses to GraphQL queries sent in GET requests can be cached \
+    for. Zero makes caches revalidate them every time.",
+);
+const GRAPHQL_SCHEMA_CACHE_MAX_AGE_SECONDS_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_SCHEMA_CACHE_MAX_AGE_SECONDS",
+    "The number of seconds the schema served at /graphql/schema can be cached for. Zero makes \
+    caches revalidate it every time.",
+);
+const GRAPHQL_PERSISTED_QUERIES_ONLY_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_PERSISTED_QUERIES_ONLY",
+    "Specifies if only registered persisted queries can be executed.",
+);
+const GRAPHQL_MAX_ALIASES_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_MAX_ALIASES",
+    "The max number of aliases in documents sent by logged in users. Zero disables the limit.",
+);
+const GRAPHQL_MAX_ROOT_FIELDS_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_MAX_ROOT_FIELDS",
+    "The max number of root fields in documents sent by logged in users. Zero disables the limit.",
+);
+const GRAPHQL_MAX_DIRECTIVES_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_MAX_DIRECTIVES",
+    "The max number of directives in documents sent by logged in users. Zero disables the limit.",
+);
+const GRAPHQL_ANONYMOUS_MAX_ALIASES_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_ANONYMOUS_MAX_ALIASES",
+    "The max number of aliases in documents sent without a session token. Zero disables the limit.",
+);
+const GRAPHQL_ANONYMOUS_MAX_ROOT_FIELDS_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_ANONYMOUS_MAX_ROOT_FIELDS",
+    "The max number of root fields in documents sent without a session token. Zero disables \
+    the limit.",
+);
+const GRAPHQL_ANONYMOUS_MAX_DIRECTIVES_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_ANONYMOUS_MAX_DIRECTIVES",
+    "The max number of directives in documents sent without a session token. Zero disables \
+    the limit.",
+);
+const GRAPHQL_RESOLVER_CONCURRENCY_LIMIT_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_RESOLVER_CONCURRENCY_LIMIT",
+    "The max number of calls to each expensive resolver run at once. Zero disables the limit.",
+);
+const GRAPHQL_RESOLVER_QUEUE_TIMEOUT_MS_VARIABLE: Variable = Variable::new(
+    "GRAPHQL_RESOLVER_QUEUE_TIMEOUT_MS",
+    "The number of milliseconds calls over the concurrency limit wait before being rejected.",
+);
+const CACHE_BACKEND_VARIABLE: Variable = Variable::new(
+    "CACHE_BACKEND",
+    "Where cached results are kept. One of \"redis\", \"memory\" or \"none\". Rate limit \
+    counters, login lockouts and verification codes are always kept in Redis.",
+);
+const RESULT_CACHE_ENABLED_VARIABLE: Variable = Variable::new(
+    "RESULT_CACHE_ENABLED",
+    "Specifies if the results of expensive reads are cached.",
+);
+const RESULT_CACHE_USER_COUNT_TTL_SECONDS_VARIABLE: Variable = Variable::new(
+    "RESULT_CACHE_USER_COUNT_TTL_SECONDS",
+    "The number of seconds the total number of users is cached for. Zero disables caching it.",
+);
+const RESULT_CACHE_FEEDBACK_TTL_SECONDS_VARIABLE: Variable = Variable::new(
+    "RESULT_CACHE_FEEDBACK_TTL_SECONDS",
+    "The number of seconds lists of feedback submissions are cached for. Zero disables caching \
+    them.",
+);
+const RESULT_CACHE_TOP_QUERIES_TTL_SECONDS_VARIABLE: Variable = Variable::new(
+    "RESULT_CACHE_TOP_QUERIES_TTL_SECONDS",
+    "The number of seconds query statistics are cached for. Zero disables caching them.",
+);
+const RESULT_CACHE_ACTIVE_ANNOUNCEMENTS_TTL_SECONDS_VARIABLE: Variable = Variable::new(
+    "RESULT_CACHE_ACTIVE_ANNOUNCEMENTS_TTL_SECONDS",
+    "The number of seconds the announcements shown to each user are cached for. Zero disables \
+    caching them.",
+);
+const CANARY_USER_PAGES_PERCENT_VARIABLE: Variable = Variable::new(
+    "CANARY_USER_PAGES_PERCENT",
+    "The percentage of the first pages of users that are also found with the user search query and \
+    compared, without changing the response. Zero disables the canary.",
+);
+const REQUEST_TIMEOUT_SECONDS_VARIABLE: Variable = Variable::new(
+    "REQUEST_TIMEOUT_SECONDS",
+    "The number of seconds spent handling a request before giving up. Clients can ask for less \
+    with the \"X-Request-Deadline\" header.",
+);
+const RESPONSE_COMPRESSION_ENABLED_VARIABLE: Variable = Variable::new(
+    "RESPONSE_COMPRESSION_ENABLED",
+    "Specifies if responses are compressed with Brotli or gzip for clients that accept it.",
+);
+const RESPONSE_COMPRESSION_MIN_BYTES_VARIABLE: Variable = Variable::new(
+    "RESPONSE_COMPRESSION_MIN_BYTES",
+    "The size in bytes below which responses aren't compressed.",
+);
+const LOG_FORMAT_VARIABLE: Variable = Variable::new(
+    "LOG_FORMAT",
+    "How log lines are written. One of \"text\" or \"json\".",
+);
+const CORS_ALLOWED_ORIGINS_VARIABLE: Variable = Variable::new(
+    "CORS_ALLOWED_ORIGINS",
+    "Origins browsers can call the API from, or \"*\" for any origin.",
+);
+const CORS_ALLOWED_METHODS_VARIABLE: Variable = Variable::new(
+    "CORS_ALLOWED_METHODS",
+    "HTTP methods cross-origin requests can use.",
+);
+const CORS_ALLOWED_HEADERS_VARIABLE: Variable = Variable::new(
+    "CORS_ALLOWED_HEADERS",
+    "Request headers cross-origin requests can send.",
+);
+const CORS_ALLOW_CREDENTIALS_VARIABLE: Variable = Variable::new(
+    "CORS_ALLOW_CREDENTIALS",
+    "Specifies if cross-origin requests can include credentials such as cookies. Can't be set \
+    while any origin is allowed.",
+);
+const ORGANIZATION_DOMAINS_ENABLED_VARIABLE: Variable = Variable::new(
+    "ORGANIZATION_DOMAINS_ENABLED",
+    "Specifies if organizations can serve the API on their own hostnames, once they prove they own \
+    them with a DNS TXT record.",
+);
+const PASSWORD_RESET_TOKEN_EXPIRATION_SECONDS_VARIABLE: Variable = Variable::new(
+    "PASSWORD_RESET_TOKEN_EXPIRATION_SECONDS",
+    "The number of seconds it takes for a password reset token to expire.",
+);
+const STRIPE_SECRET_KEY_VARIABLE: Variable = Variable::secret(
+    "STRIPE_SECRET_KEY",
+    "The secret key the Stripe API is called with. Plans can only be purchased if this is set.",
+);
+#[cfg(feature = "webhooks")]
+const STRIPE_WEBHOOK_SECRET_VARIABLE: Variable = Variable::secret(
+    "STRIPE_WEBHOOK_SECRET",
+    "The secret Stripe signs webhook requests with. Webhook events are only received if this \
+    is set.",
+);
+const STRIPE_PRO_PRICE_ID_VARIABLE: Variable = Variable::new(
+    "STRIPE_PRO_PRICE_ID",
+    "The ID of the Stripe price of the \"pro\" plan.",
+);
+const BILLING_SUCCESS_URL_VARIABLE: Variable = Variable::new(
+    "BILLING_SUCCESS_URL",
+    "The URL users are sent to after purchasing a plan.",
+);
+const BILLING_CANCEL_URL_VARIABLE: Variable = Variable::new(
+    "BILLING_CANCEL_URL",
+    "The URL users are sent to if they leave checkout without purchasing a plan.",
+);
+const BILLING_CURRENCY_VARIABLE: Variable = Variable::new(
+    "BILLING_CURRENCY",
+    "The three-letter ISO code of the currency fixed discounts are given in.",
+);
+const TRIAL_DAYS_VARIABLE: Variable = Variable::new(
+    "TRIAL_DAYS",
+    "The number of days new users can try the \"pro\" plan for. Zero disables trials.",
+);
+const FREE_PLAN_API_CALL_LIMIT_VARIABLE: Variable = Variable::new(
+    "FREE_PLAN_API_CALL_LIMIT",
+    "The number of API calls per billing period on the \"free\" plan. Zero disables the limit.",
+);
+const PRO_PLAN_API_CALL_LIMIT_VARIABLE: Variable = Variable::new(
+    "PRO_PLAN_API_CALL_LIMIT",
+    "The number of API calls per billing period on the \"pro\" plan. Zero disables the limit.",
+);
+const USAGE_SOFT_LIMIT_PERCENT_VARIABLE: Variable = Variable::new(
+    "USAGE_SOFT_LIMIT_PERCENT",
+    "The share of the API call limit after which responses include a warning header.",
+);
+#[cfg(feature = "metrics")]
+const METRICS_ENABLED_VARIABLE: Variable = Variable::new(
+    "METRICS_ENABLED",
+    "Specifies if Prometheus metrics are exposed at \"/metrics\".",
+);
+const ALERT_CHECK_INTERVAL_SECONDS_VARIABLE: Variable = Variable::new(
+    "ALERT_CHECK_INTERVAL_SECONDS",
+    "The number of seconds between checks of whether operators should be alerted. Zero disables alerts.",
+);
+const ALERT_FAILED_LOGINS_THRESHOLD_VARIABLE: Variable = Variable::new(
+    "ALERT_FAILED_LOGINS_THRESHOLD",
+    "The number of failed logins between checks that alerts operators. Zero disables the alert.",
+);
+const ALERT_EMAIL_FAILURE_RATE_PERCENT_VARIABLE: Variable = Variable::new(
+    "ALERT_EMAIL_FAILURE_RATE_PERCENT",
+    "The percentage of failed email deliveries that alerts operators. Zero disables the alert.",
+);
+const ALERT_DATABASE_POOL_SATURATION_PERCENT_VARIABLE: Variable = Variable::new(
+    "ALERT_DATABASE_POOL_SATURATION_PERCENT",
+    "The percentage of database connections in use that alerts operators. Zero disables the alert.",
+);
+const ALERT_ERROR_RATE_PERCENT_VARIABLE: Variable = Variable::new(
+    "ALERT_ERROR_RATE_PERCENT",
+    "The percentage of server error responses that alerts operators. Zero disables the alert.",
+);
+const ALERT_COOLDOWN_SECONDS_VARIABLE: Variable = Variable::new(
+    "ALERT_COOLDOWN_SECONDS",
+    "The number of seconds after an alert is sent before another of its kind can be sent.",
+);
+const ALERT_WEBHOOK_URL_VARIABLE: Variable = Variable::new(
+    "ALERT_WEBHOOK_URL",
+    "A URL alerts are posted to as JSON, such as a Slack incoming webhook.",
+);
+const ALERT_EMAIL_ADDRESS_VARIABLE: Variable = Variable::new(
+    "ALERT_EMAIL_ADDRESS",
+    "An email address alerts are sent to, such as an operations team's mailing list.",
+);
+const RETENTION_INTERVAL_SECONDS_VARIABLE: Variable = Variable::new(
+    "RETENTION_INTERVAL_SECONDS",
+    "The number of seconds between purges of data older than its retention period. Zero \
+    disables purging.",
+);
+const RETENTION_DRY_RUN_VARIABLE: Variable = Variable::new(
+    "RETENTION_DRY_RUN",
+    "Specifies if expired data is only counted and logged instead of purged.",
+);
+const AUDIT_EVENT_RETENTION_DAYS_VARIABLE: Variable = Variable::new(
+    "AUDIT_EVENT_RETENTION_DAYS",
+    "The number of days audit events are kept for. Zero keeps them forever.",
+);
+const SECURITY_EVENT_RETENTION_DAYS_VARIABLE: Variable = Variable::new(
+    "SECURITY_EVENT_RETENTION_DAYS",
+    "The number of days security events are kept for. Zero keeps them forever.",
+);
+const UNVERIFIED_USER_RETENTION_DAYS_VARIABLE: Variable = Variable::new(
+    "UNVERIFIED_USER_RETENTION_DAYS",
+    "The number of days users have to verify their email address before they are deleted. Zero \
+    keeps them forever.",
+);
+const SESSION_METADATA_RETENTION_DAYS_VARIABLE: Variable = Variable::new(
+    "SESSION_METADATA_RETENTION_DAYS",
+    "The number of days the IP address and user agent of a session are kept for. Zero keeps them \
+    for as long as the session.",
+);
+const UNDO_GRACE_PERIOD_SECONDS_VARIABLE: Variable = Variable::new(
+    "UNDO_GRACE_PERIOD_SECONDS",
+    "The number of seconds destructive actions, such as deleting an account, can be undone for \
+    before they are done. Zero does them right away.",
+);
+const FEEDBACK_HOURLY_LIMIT_VARIABLE: Variable = Variable::new(
+    "FEEDBACK_HOURLY_LIMIT",
+    "The max number of feedback submissions per user or IP address per hour. Zero disables \
+    the limit.",
+);
+const STORAGE_BACKEND_VARIABLE: Variable = Variable::new(
+    "STORAGE_BACKEND",
+    "Where private files are stored. One of \"local\" or \"s3\".",
+);
+const STORAGE_PATH_VARIABLE: Variable = Variable::new(
+    "STORAGE_PATH",
+    "The directory private files are stored in on the local disk.",
+);
+const STORAGE_URL_SECRET_VARIABLE: Variable = Variable::secret(
+    "STORAGE_URL_SECRET",
+    "The secret private file download URLs are signed with. Files can only be downloaded if \
+    this is set.",
+);
+const SIGNED_URL_EXPIRATION_SECONDS_VARIABLE: Variable = Variable::new(
+    "SIGNED_URL_EXPIRATION_SECONDS",
+    "The number of seconds it takes for a signed download URL to expire.",
+);
+const S3_ENDPOINT_VARIABLE: Variable = Variable::new(
+    "S3_ENDPOINT",
+    "The URL of the S3-compatible object store private files are stored in.",
+);
+const S3_BUCKET_VARIABLE: Variable = Variable::new(
+    "S3_BUCKET",
+    "The name of the bucket private files are stored in.",
+);
+const S3_REGION_VARIABLE: Variable = Variable::new("S3_REGION", "The region the bucket is in.");
+const S3_ACCESS_KEY_ID_VARIABLE: Variable = Variable::new(
+    "S3_ACCESS_KEY_ID",
+    "The ID of the access key the bucket is accessed with.",
+);
+const S3_SECRET_ACCESS_KEY_VARIABLE: Variable = Variable::secret(
+    "S3_SECRET_ACCESS_KEY",
+    "The secret of the access key the bucket is accessed with.",
+);
+const UPLOAD_MAX_BYTES_VARIABLE: Variable = Variable::new(
+    "UPLOAD_MAX_BYTES",
+    "The max size in bytes of a multipart request uploading files.",
+);
+const AVATAR_MAX_BYTES_VARIABLE: Variable = Variable::new(
+    "AVATAR_MAX_BYTES",
+    "The max size in bytes of an uploaded avatar.",
+);
+const CLAMD_ADDRESS_VARIABLE: Variable = Variable::new(
+    "CLAMD_ADDRESS",
+    "The address of the clamd daemon stored files are scanned with. Files are only scanned if \
+    this is set.",
+);
+const SCAN_ASYNC_THRESHOLD_BYTES_VARIABLE: Variable = Variable::new(
+    "SCAN_ASYNC_THRESHOLD_BYTES",
+    "The size in bytes past which files are scanned in the background after being stored. \
+    Zero scans every file first.",
+);
+const CHANGE_EVENT_TABLES_VARIABLE: Variable = Variable::new(
+    "CHANGE_EVENT_TABLES",
+    "Tables whose row changes are published as domain events.",
+);
+const SERVER_URL_VARIABLE: Variable = Variable::new("SERVER_URL", "The public URL of the server.");
+#[cfg(feature = "oauth")]
+const OAUTH_SUCCESS_URL_VARIABLE: Variable = Variable::new(
+    "OAUTH_SUCCESS_URL",
+    "The client page users are sent to after logging in with an OAuth provider.",
+);
+#[cfg(feature = "oauth")]
+const GOOGLE_OAUTH_CLIENT_ID_VARIABLE: Variable = Variable::new(
+    "GOOGLE_OAUTH_CLIENT_ID",
+    "The client ID the server is registered with at Google.",
+);
+#[cfg(feature = "oauth")]
+const GOOGLE_OAUTH_CLIENT_SECRET_VARIABLE: Variable = Variable::secret(
+    "GOOGLE_OAUTH_CLIENT_SECRET",
+    "The client secret the server is registered with at Google.",
+);
+#[cfg(feature = "oauth")]
+const GITHUB_OAUTH_CLIENT_ID_VARIABLE: Variable = Variable::new(
+    "GITHUB_OAUTH_CLIENT_ID",
+    "The client ID the server is registered with at GitHub.",
+);
+#[cfg(feature = "oauth")]
+const GITHUB_OAUTH_CLIENT_SECRET_VARIABLE: Variable = Variable::secret(
+    "GITHUB_OAUTH_CLIENT_SECRET",
+    "The client secret the server is registered with at GitHub.",
+);
+const WEBAUTHN_RP_ID_VARIABLE: Variable = Variable::new(
+    "WEBAUTHN_RP_ID",
+    "The domain passkeys are registered to, such as \"example.com\". This must be the domain of \
+    WEBAUTHN_ORIGIN or a parent of it.",
+);
+const WEBAUTHN_RP_NAME_VARIABLE: Variable = Variable::new(
+    "WEBAUTHN_RP_NAME",
+    "The name of the app shown to users when they create a passkey.",
+);
+const WEBAUTHN_ORIGIN_VARIABLE: Variable = Variable::new(
+    "WEBAUTHN_ORIGIN",
+    "The origin of the client page passkeys are created and used on.",
+);
+const WEBAUTHN_CHALLENGE_EXPIRATION_SECONDS_VARIABLE: Variable = Variable::new(
+    "WEBAUTHN_CHALLENGE_EXPIRATION_SECONDS",
+    "The number of seconds a client has to finish creating or using a passkey after starting.",
+);
+const IS_DOCKER_VARIABLE: Variable = Variable::new(
+    "IS_DOCKER",
+    "Specifies if the server is running in a Docker container.",
+);
 
 /// Configuration for the server. Each field is derived from an environment variable found on the
 /// host or in local ".env" and ".env.override" files.
@@ -31,34 +633,330 @@ pub struct Config {
     pub database_url: String,
     /// The max number of pooled connections the server will maintain with the database.
     pub database_max_connection_count: u32,
+    /// The number of database connections opened, and the statements most requests need prepared
+    /// on, before the server starts accepting requests. This is capped at the max connection count.
+    pub warmup_connection_count: u32,
+    /// Specifies if every database query run by the executor is logged along with how long it
+    /// took, which is useful for debugging but noisy.
+    pub database_log_queries: bool,
+    /// The number of milliseconds after which a database query run by the executor is logged as a
+    /// warning. Zero disables slow query warnings.
+    pub database_slow_query_threshold_ms: u64,
+    /// The max number of seconds each subsystem's startup or shutdown hook can take. Subsystems
+    /// that take longer to start stop the server from starting, while those that take longer to
+    /// shut down are abandoned.
+    pub lifecycle_hook_timeout_seconds: u32,
+    /// Specifies if pending database migrations are run when the server starts. Production
+    /// deployments may disable this to run migrations separately with the "migrate" command.
+    pub run_migrations_on_startup: bool,
     /// A connection string for a Redis database.
     pub redis_url: String,
+    /// The region this server runs in, and the Redis databases of other regions sessions are
+    /// replicated to.
+    pub region: RegionConfig,
     /// A secret used to generate/validate session tokens.
     pub session_token_secret: SessionTokenSecret,
     /// The number of seconds it takes for a session token to expire.
     pub session_token_expiration_seconds: u32,
+    /// How session tokens are delivered to browsers as cookies, if they are.
+    pub session_cookie_policy: SessionCookiePolicy,
     /// An integer specifying the cost of password hashing algorithm. See the "bcrypt" crate for
     /// more info.
     pub password_hash_cost: u32,
+    /// The number of failed login attempts for a username after which it is locked. Zero disables
+    /// the lockout.
+    pub login_lockout_threshold: u32,
+    /// The number of failed login attempts from an IP address after which it is locked. Zero
+    /// disables the lockout.
+    pub login_ip_lockout_threshold: u32,
+    /// The number of seconds failed login attempts are counted for, and a username or IP address
+    /// stays locked for.
+    pub login_lockout_seconds: u32,
+    /// Addresses or CIDR blocks of the proxies in front of the server, such as "10.0.0.0/8". The
+    /// "X-Forwarded-For" header is only used to find a client's IP address, for lockouts, rate
+    /// limits and audit events, when requests come through one of these proxies. Otherwise the
+    /// address that connected to the server is used, as clients can set the header themselves.
+    pub trusted_proxies: Vec<TrustedProxy>,
     /// The SMTP email server to use for sending emails.
+    #[cfg(feature = "email")]
     pub email_smtp: String,
     /// The port of the SMTP email server to connect to.
+    #[cfg(feature = "email")]
     pub email_smtp_port: u16,
     /// Specifies if the server should use the "STARTTLS" protocol for SMTP.
+    #[cfg(feature = "email")]
     pub email_smtp_use_starttls: bool,
+    /// The max number of pooled connections the server will maintain with the SMTP server.
+    #[cfg(feature = "email")]
+    pub email_smtp_max_connections: u32,
+    /// The max number of emails the server will send at once.
+    #[cfg(feature = "email")]
+    pub email_max_concurrent_sends: usize,
+    /// The max number of emails sent to a single recipient per hour. Zero disables the limit.
+    #[cfg(feature = "email")]
+    pub email_recipient_hourly_limit: u32,
+    /// The max number of emails sent per minute. Zero disables the limit.
+    #[cfg(feature = "email")]
+    pub email_minute_limit: u32,
+    /// The max number of emails sent per day. Zero disables the limit.
+    #[cfg(feature = "email")]
+    pub email_daily_quota: u32,
+    /// The number of times delivering an email is attempted before it is moved to the dead letter
+    /// list.
+    #[cfg(feature = "email")]
+    pub email_max_delivery_attempts: u32,
+    /// The DKIM selector outgoing emails are signed with. Emails are only signed if this is set.
+    #[cfg(feature = "email")]
+    pub email_dkim_selector: Option<String>,
+    /// The domain outgoing emails are signed for. This defaults to the domain of the sending email
+    /// account.
+    #[cfg(feature = "email")]
+    pub email_dkim_domain: Option<String>,
+    /// The path to a PEM file holding the PKCS#1 RSA private key outgoing emails are signed with.
+    #[cfg(feature = "email")]
+    pub email_dkim_private_key_path: Option<String>,
+    /// The display name emails are sent from, which is also shown in the emails themselves.
+    pub email_sender_name: String,
+    /// The key organizations' SMTP passwords are encrypted with. Organizations can only set their
+    /// own SMTP server if this is set.
+    pub email_settings_key: Option<SettingsKey>,
     /// The email account used to send email verification codes.
+    #[cfg(feature = "email")]
     pub email_verification_email_address: String,
     /// The password for the email account used to send email verification codes.
+    #[cfg(feature = "email")]
     pub email_verification_email_password: String,
     /// The number of seconds it takes for an email verification code to expire.
     pub email_verification_code_expiration_seconds: u32,
+    /// The number of seconds a user must wait after a verification email is resent before
+    /// another can be resent. Zero disables the cooldown.
+    pub verification_email_resend_cooldown_seconds: u32,
+    /// The max number of times a verification email can be resent to a user per hour. Zero
+    /// disables the limit.
+    pub verification_email_hourly_resend_limit: u32,
+    /// Specifies if signups are screened for signals suggesting they were made by a bot, such as
+    /// a filled in honeypot field or a form submitted too quickly.
+    pub signup_screening_enabled: bool,
+    /// What happens to signups with signals suggesting they were made by a bot.
+    pub signup_screening_action: SignupScreeningAction,
+    /// The number of seconds a person takes to fill in the signup form at the very least. Forms
+    /// submitted sooner after being loaded are suspicious.
+    pub signup_min_form_seconds: u32,
+    /// The number of seconds after being issued that a signup form token expires.
+    pub signup_form_token_expiration_seconds: u32,
+    /// The URL CAPTCHA tokens are verified at. hCaptcha, reCAPTCHA and Cloudflare Turnstile
+    /// endpoints are supported.
+    pub captcha_verify_url: String,
+    /// The secret CAPTCHA tokens are verified with. CAPTCHAs can only be passed if this is set.
+    pub captcha_secret: Option<String>,
+    /// Rules passwords must follow when users start using them.
+    pub password_policy: PasswordPolicy,
+    /// Specifies if the domains of email addresses users sign up or change their profile with are
+    /// looked up in DNS, rejecting domains without mail servers. Addresses are let through if the
+    /// lookup fails.
+    pub email_mx_lookup_enabled: bool,
+    /// The number of seconds it takes for a password reset token to expire.
+    pub password_reset_token_expiration_seconds: u32,
+    /// Decides the HTTP status of GraphQL responses containing errors. One of "spec", "partial" or
+    /// "strict".
+    pub error_status_policy: ErrorStatusPolicy,
+    /// The max size of a serialized GraphQL response in bytes. Larger responses are replaced with an
+    /// error. Zero means there is no limit.
+    pub max_response_bytes: usize,
+    /// How many rows a single GraphQL request can fetch from the database, and what happens to
+    /// requests that fetch more.
+    pub request_budget: RequestBudget,
+    /// The max number of GraphQL requests that can be sent together in a batch, as an array in a
+    /// single request body. Zero means batches are rejected.
+    pub max_batch_size: usize,
+    /// The number of seconds responses to GraphQL queries sent in GET requests can be cached for.
+    /// Zero means caches must check the response is current with the server every time it is used.
+    pub get_cache_max_age_seconds: u32,
+    /// The number of seconds the schema served in the GraphQL schema language can be cached for
+    /// before caches revalidate it with its ETag.
+    pub schema_cache_max_age_seconds: u32,
+    /// Set to true to only execute persisted queries registered ahead of time, rejecting any other
+    /// query clients send.
+    pub persisted_queries_only: bool,
+    /// Limits on the shape of GraphQL documents sent by logged in users.
+    pub query_limits: QueryLimits,
+    /// Limits on the shape of GraphQL documents sent without a valid session token.
+    pub anonymous_query_limits: QueryLimits,
+    /// Limits on how many calls to each expensive resolver, such as exports, run at once on this
+    /// instance.
+    pub resolver_concurrency_limits: ConcurrencyLimits,
+    /// Where cached results are kept.
+    pub cache_backend: CacheBackend,
+    /// Specifies if the results of expensive reads, such as counting users, are cached.
+    pub result_cache_enabled: bool,
+    /// How long the results of each expensive read are cached for when caching is enabled.
+    pub result_cache_ttls: ResultCacheTtls,
+    /// The percentage of calls each new implementation of a read is run in the shadow of the old
+    /// one for, to compare their results.
+    pub canary_percentages: CanaryPercentages,
+    /// The number of seconds the server will spend handling a request before giving up.
+    pub request_timeout_seconds: u32,
+    /// Specifies if text and JSON responses are compressed with Brotli or gzip for clients that
+    /// accept either.
+    pub response_compression_enabled: bool,
+    /// The size in bytes below which responses aren't compressed, since compressing small
+    /// responses costs more than it saves.
+    pub response_compression_min_bytes: usize,
+    /// Decides how log lines are written. One of "text" or "json".
+    pub log_format: LogFormat,
+    /// Origins browsers are allowed to call the API from, such as "https://example.com", or "*" to
+    /// allow any origin. Cross-origin requests aren't allowed if this is empty.
+    pub cors_allowed_origins: Vec<String>,
+    /// HTTP methods cross-origin requests are allowed to use.
+    pub cors_allowed_methods: Vec<String>,
+    /// Request headers cross-origin requests are allowed to send.
+    pub cors_allowed_headers: Vec<String>,
+    /// Specifies if cross-origin requests are allowed to include credentials such as cookies.
+    pub cors_allow_credentials: bool,
+    /// Specifies if organizations can serve the API on their own hostnames. Requests sent to a
+    /// verified hostname are routed to its organization, get host-only cookies and only allow
+    /// cross-origin requests from the organization's other hostnames.
+    pub organization_domains_enabled: bool,
+    /// The secret key used to call the Stripe API. Plans can only be purchased if this is set.
+    pub stripe_secret_key: Option<String>,
+    /// The secret Stripe signs webhook requests with. Stripe webhook events are only received if
+    /// this is set.
+    #[cfg(feature = "webhooks")]
+    pub stripe_webhook_secret: Option<String>,
+    /// The ID of the Stripe price users are charged for the "pro" plan.
+    pub stripe_pro_price_id: Option<String>,
+    /// The URL users are sent to after purchasing a plan.
+    pub billing_success_url: String,
+    /// The URL users are sent to if they leave the checkout page without purchasing a plan.
+    pub billing_cancel_url: String,
+    /// The three-letter ISO code of the currency fixed discounts are given in, e.g. "usd".
+    pub billing_currency: String,
+    /// The number of days new users can try the "pro" plan for before being restricted to
+    /// read-only access. Zero disables trials.
+    pub trial_days: u32,
+    /// The number of API calls users on the "free" and "restricted" plans can make per billing
+    /// period. Zero means unlimited.
+    pub free_plan_api_call_limit: u32,
+    /// The number of API calls users on the "pro" plan can make per billing period. Zero means
+    /// unlimited.
+    pub pro_plan_api_call_limit: u32,
+    /// The percentage of a plan's API call limit after which responses include a warning header.
+    pub usage_soft_limit_percent: u32,
+    /// Specifies if server metrics are exposed for Prometheus at "/metrics".
+    #[cfg(feature = "metrics")]
+    pub metrics_enabled: bool,
+    /// The number of seconds between checks of whether any alert thresholds were crossed. Zero
+    /// disables alerts.
+    pub alert_check_interval_seconds: u32,
+    /// Thresholds crossing which alerts operators.
+    pub alert_thresholds: AlertThresholds,
+    /// The number of seconds after an alert is sent during which further alerts of the same kind
+    /// are only logged, so operators aren't flooded while a problem persists.
+    pub alert_cooldown_seconds: u32,
+    /// A URL alerts are posted to as JSON with a "text" field, which Slack incoming webhooks
+    /// accept. Alerts are only logged if neither this nor an alert email address is set.
+    pub alert_webhook_url: Option<String>,
+    /// An email address alerts are sent to.
+    pub alert_email_address: Option<String>,
+    /// The number of seconds between purges of data older than its retention period. Zero
+    /// disables purging.
+    pub retention_interval_seconds: u32,
+    /// Specifies if data older than its retention period is only counted and logged instead of
+    /// purged, to preview the effect of a retention policy.
+    pub retention_dry_run: bool,
+    /// The number of days each kind of data is kept for before it is purged.
+    pub retention_policy: RetentionPolicy,
+    /// The number of seconds destructive actions, such as deleting an account, can be undone for
+    /// before they are done. Zero does them right away, without a way to undo them.
+    pub undo_grace_period_seconds: u32,
+    /// The max number of feedback submissions per hour from a single user, or a single IP address
+    /// for requests that aren't made by a logged in user. Zero disables the limit.
+    pub feedback_hourly_limit: u32,
+    /// Where private files, such as uploads and exports, are stored.
+    pub storage_backend: StorageBackend,
+    /// The directory private files are stored in when they are stored on the local disk.
+    pub storage_path: String,
+    /// A secret used to sign private file download URLs. Private files can only be downloaded if
+    /// this is set.
+    pub storage_url_secret: Option<String>,
+    /// The number of seconds it takes for a signed download URL to expire.
+    pub signed_url_expiration_seconds: u32,
+    /// The URL of the S3-compatible object store private files are stored in, such as
+    /// "https://s3.us-east-1.amazonaws.com". This and the other S3 details must be set if private
+    /// files are stored in S3.
+    pub s3_endpoint: Option<String>,
+    /// The name of the bucket private files are stored in.
+    pub s3_bucket: Option<String>,
+    /// The region the bucket is in, such as "us-east-1".
+    pub s3_region: Option<String>,
+    /// The ID of the access key used to access the bucket.
+    pub s3_access_key_id: Option<String>,
+    /// The secret of the access key used to access the bucket.
+    pub s3_secret_access_key: Option<String>,
+    /// The max size in bytes of a multipart GraphQL request uploading files.
+    pub upload_max_bytes: usize,
+    /// The max size in bytes of an image uploaded as a user's avatar.
+    pub avatar_max_bytes: usize,
+    /// The address of a clamd daemon stored files are scanned for viruses with, such as
+    /// "localhost:3310". Files are only scanned if this is set.
+    pub clamd_address: Option<String>,
+    /// The size in bytes past which files are scanned by a background job after they are stored
+    /// instead of before. Zero scans every file before it is stored.
+    pub scan_async_threshold_bytes: usize,
+    /// Tables whose row changes are published as domain events, such as "users" or
+    /// "subscriptions". Row changes are still listened for to invalidate caches if this is empty.
+    pub change_event_tables: Vec<String>,
+    /// The public URL of the server, used to build URLs external services send users back to.
+    pub server_url: String,
+    /// The URL of the client page users are sent to after logging in with an OAuth provider, with
+    /// either a "ticket" or an "error" query parameter.
+    #[cfg(feature = "oauth")]
+    pub oauth_success_url: String,
+    /// The client ID the server is registered with at Google. Users can only log in with Google if
+    /// this and the client secret are set.
+    #[cfg(feature = "oauth")]
+    pub google_oauth_client_id: Option<String>,
+    /// The client secret the server is registered with at Google.
+    #[cfg(feature = "oauth")]
+    pub google_oauth_client_secret: Option<String>,
+    /// The client ID the server is registered with at GitHub. Users can only log in with GitHub if
+    /// this and the client secret are set.
+    #[cfg(feature = "oauth")]
+    pub github_oauth_client_id: Option<String>,
+    /// The client secret the server is registered with at GitHub.
+    #[cfg(feature = "oauth")]
+    pub github_oauth_client_secret: Option<String>,
+    /// The domain passkeys are registered to. Passkeys only work on this domain and its subdomains.
+    pub webauthn_rp_id: String,
+    /// The name of the app shown to users when they create a passkey.
+    pub webauthn_rp_name: String,
+    /// The origin of the client page passkeys are created and used on, e.g.
+    /// "https://example.com".
+    pub webauthn_origin: String,
+    /// The number of seconds a client has to finish creating or using a passkey after starting.
+    pub webauthn_challenge_expiration_seconds: u32,
     /// Set to true if the server is running in a Docker container.
     pub is_docker: bool,
 }
 
 impl Config {
     /// Load server configuration from environment variables and ".env" and ".env.override" files.
+    /// If any variable is missing or invalid, every problem found is logged in a single report and
+    /// the process exits.
     pub async fn load() -> Self {
+        match Config::try_load() {
+            Ok(config) => config,
+            Err(errors) => {
+                log::error!("{}", errors);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Load server configuration from environment variables and ".env" and ".env.override" files.
+    /// Every variable is checked, so this will return every problem found with the configuration
+    /// rather than only the first.
+    pub fn try_load() -> Result<Self, ConfigErrors> {
         if dotenv::from_filename(".env.override").is_ok() {
             log::info!("Loaded environment variables from '.env.override' file.");
         }
@@ -66,46 +964,576 @@ impl Config {
             log::info!("Loaded environment variables from '.env' file.");
         }
 
-        let is_docker = var(IS_DOCKER_VARIABLE);
+        Config::from_variables(&mut Variables::new(|name| std::env::var(name).ok()))
+    }
+
+    /// Describe every environment variable the configuration is loaded from, in the order they are
+    /// read. Descriptions are recorded while loading the configuration from an environment file,
+    /// so they always match what the server reads. The file's values are used as the variables'
+    /// defaults, except for secrets. This will return an error if the file can't be read or any of
+    /// its variables are missing or invalid.
+    pub fn describe_variables(path: &Path) -> Result<Vec<VariableDescription>> {
+        // The file is read without loading it into the environment, which the replacement for
+        // this deprecated function would do.
+        #[allow(deprecated)]
+        let values = dotenv::from_path_iter(path)?.collect::<Result<HashMap<_, _>, _>>()?;
+        let mut variables = Variables::new(move |name| values.get(name).cloned());
+        variables.is_describing = true;
+        Config::from_variables(&mut variables)?;
+
+        Ok(variables.descriptions)
+    }
+
+    /// Load server configuration from a set of environment variables. Every variable is read even
+    /// if an earlier one is invalid, so every problem is found at once.
+    fn from_variables(variables: &mut Variables) -> Result<Self, ConfigErrors> {
+        let is_docker = variables.var(IS_DOCKER_VARIABLE);
         let database_url = if is_docker {
-            var::<String>(DATABASE_URL_VARIABLE).replace("localhost", "host.docker.internal")
+            variables
+                .var::<String>(DATABASE_URL_VARIABLE)
+                .replace("localhost", "host.docker.internal")
         } else {
-            var(DATABASE_URL_VARIABLE)
+            variables.var(DATABASE_URL_VARIABLE)
         };
         let redis_url = if is_docker {
-            var::<String>(REDIS_URL_VARIABLE).replace("localhost", "host.docker.internal")
+            variables
+                .var::<String>(REDIS_URL_VARIABLE)
+                .replace("localhost", "host.docker.internal")
         } else {
-            var(REDIS_URL_VARIABLE)
+            variables.var(REDIS_URL_VARIABLE)
         };
 
-        Config {
-            port: var(PORT_VARIABLE),
+        let config = Config {
+            port: variables.var(PORT_VARIABLE),
             database_url,
-            database_max_connection_count: var(DATABASE_MAX_CONNECTION_COUNT_VARIABLE),
+            database_max_connection_count: variables
+                .ranged_var(DATABASE_MAX_CONNECTION_COUNT_VARIABLE, 1..),
+            warmup_connection_count: variables.var(WARMUP_CONNECTION_COUNT_VARIABLE),
+            database_log_queries: variables.var(DATABASE_LOG_QUERIES_VARIABLE),
+            database_slow_query_threshold_ms: variables
+                .var(DATABASE_SLOW_QUERY_THRESHOLD_MS_VARIABLE),
+            lifecycle_hook_timeout_seconds: variables.var(LIFECYCLE_HOOK_TIMEOUT_SECONDS_VARIABLE),
+            run_migrations_on_startup: variables.var(RUN_MIGRATIONS_ON_STARTUP_VARIABLE),
             redis_url,
-            session_token_secret: SessionToken::secret(&var::<String>(
-                SESSION_TOKEN_SECRET_VARIABLE,
-            )),
-            session_token_expiration_seconds: var(SESSION_TOKEN_EXPIRATION_SECONDS_VARIABLE),
-            password_hash_cost: var(PASSWORD_HASH_COST_VARIABLE),
-            email_smtp: var(EMAIL_SMTP_VARIABLE),
-            email_smtp_port: var(EMAIL_SMTP_PORT_VARIABLE),
-            email_smtp_use_starttls: var(EMAIL_SMTP_USE_STARTTLS_VARIABLE),
-            email_verification_email_address: var(EMAIL_VERIFICATION_EMAIL_ADDRESS_VARIABLE),
-            email_verification_email_password: var(EMAIL_VERIFICATION_EMAIL_PASSWORD_VARIABLE),
-            email_verification_code_expiration_seconds: var(
-                EMAIL_VERIFICATION_CODE_EXPIRATION_SECONDS_VARIABLE,
+            region: RegionConfig {
+                region: variables.var(REDIS_REGION_VARIABLE),
+                remotes: variables.parsed_list_var(REDIS_REGION_URLS_VARIABLE),
+                latency_probe_interval_seconds: variables
+                    .var(REDIS_REGION_LATENCY_PROBE_INTERVAL_SECONDS_VARIABLE),
+            },
+            session_token_secret: SessionToken::secret(
+                &variables.var::<String>(SESSION_TOKEN_SECRET_VARIABLE),
+            ),
+            session_token_expiration_seconds: variables
+                .var(SESSION_TOKEN_EXPIRATION_SECONDS_VARIABLE),
+            session_cookie_policy: SessionCookiePolicy {
+                enabled: variables.var(SESSION_COOKIE_ENABLED_VARIABLE),
+                name: variables.var(SESSION_COOKIE_NAME_VARIABLE),
+                domain: variables.optional_var(SESSION_COOKIE_DOMAIN_VARIABLE),
+                secure: variables.var(SESSION_COOKIE_SECURE_VARIABLE),
+                same_site: variables.var(SESSION_COOKIE_SAME_SITE_VARIABLE),
+            },
+            password_hash_cost: variables.ranged_var(
+                PASSWORD_HASH_COST_VARIABLE,
+                MIN_BCRYPT_COST..=MAX_BCRYPT_COST,
             ),
+            login_lockout_threshold: variables.var(LOGIN_LOCKOUT_THRESHOLD_VARIABLE),
+            login_ip_lockout_threshold: variables.var(LOGIN_IP_LOCKOUT_THRESHOLD_VARIABLE),
+            login_lockout_seconds: variables.var(LOGIN_LOCKOUT_SECONDS_VARIABLE),
+            trusted_proxies: variables.parsed_list_var(TRUSTED_PROXIES_VARIABLE),
+            #[cfg(feature = "email")]
+            email_smtp: variables.var(EMAIL_SMTP_VARIABLE),
+            #[cfg(feature = "email")]
+            email_smtp_port: variables.var(EMAIL_SMTP_PORT_VARIABLE),
+            #[cfg(feature = "email")]
+            email_smtp_use_starttls: variables.var(EMAIL_SMTP_USE_STARTTLS_VARIABLE),
+            #[cfg(feature = "email")]
+            email_smtp_max_connections: variables
+                .ranged_var(EMAIL_SMTP_MAX_CONNECTIONS_VARIABLE, 1..),
+            #[cfg(feature = "email")]
+            email_max_concurrent_sends: variables
+                .ranged_var(EMAIL_MAX_CONCURRENT_SENDS_VARIABLE, 1..),
+            #[cfg(feature = "email")]
+            email_recipient_hourly_limit: variables.var(EMAIL_RECIPIENT_HOURLY_LIMIT_VARIABLE),
+            #[cfg(feature = "email")]
+            email_minute_limit: variables.var(EMAIL_MINUTE_LIMIT_VARIABLE),
+            #[cfg(feature = "email")]
+            email_daily_quota: variables.var(EMAIL_DAILY_QUOTA_VARIABLE),
+            #[cfg(feature = "email")]
+            email_max_delivery_attempts: variables.var(EMAIL_MAX_DELIVERY_ATTEMPTS_VARIABLE),
+            #[cfg(feature = "email")]
+            email_dkim_selector: variables.optional_var(EMAIL_DKIM_SELECTOR_VARIABLE),
+            #[cfg(feature = "email")]
+            email_dkim_domain: variables.optional_var(EMAIL_DKIM_DOMAIN_VARIABLE),
+            #[cfg(feature = "email")]
+            email_dkim_private_key_path: variables
+                .optional_var(EMAIL_DKIM_PRIVATE_KEY_PATH_VARIABLE),
+            email_sender_name: variables.var(EMAIL_SENDER_NAME_VARIABLE),
+            email_settings_key: variables.optional_var(EMAIL_SETTINGS_KEY_VARIABLE),
+            #[cfg(feature = "email")]
+            email_verification_email_address: variables
+                .var(EMAIL_VERIFICATION_EMAIL_ADDRESS_VARIABLE),
+            #[cfg(feature = "email")]
+            email_verification_email_password: variables
+                .var(EMAIL_VERIFICATION_EMAIL_PASSWORD_VARIABLE),
+            email_verification_code_expiration_seconds: variables
+                .var(EMAIL_VERIFICATION_CODE_EXPIRATION_SECONDS_VARIABLE),
+            verification_email_resend_cooldown_seconds: variables
+                .var(VERIFICATION_EMAIL_RESEND_COOLDOWN_SECONDS_VARIABLE),
+            verification_email_hourly_resend_limit: variables
+                .var(VERIFICATION_EMAIL_HOURLY_RESEND_LIMIT_VARIABLE),
+            signup_screening_enabled: variables.var(SIGNUP_SCREENING_ENABLED_VARIABLE),
+            signup_screening_action: variables.var(SIGNUP_SCREENING_ACTION_VARIABLE),
+            signup_min_form_seconds: variables.var(SIGNUP_MIN_FORM_SECONDS_VARIABLE),
+            signup_form_token_expiration_seconds: variables
+                .var(SIGNUP_FORM_TOKEN_EXPIRATION_SECONDS_VARIABLE),
+            captcha_verify_url: variables.var(CAPTCHA_VERIFY_URL_VARIABLE),
+            captcha_secret: variables.optional_var(CAPTCHA_SECRET_VARIABLE),
+            password_policy: PasswordPolicy {
+                min_length: variables.var(PASSWORD_MIN_LENGTH_VARIABLE),
+                min_score: variables.ranged_var(PASSWORD_MIN_SCORE_VARIABLE, 0..=4),
+                reject_common: variables.var(PASSWORD_REJECT_COMMON_VARIABLE),
+                reject_user_details: variables.var(PASSWORD_REJECT_USER_DETAILS_VARIABLE),
+            },
+            email_mx_lookup_enabled: variables.var(EMAIL_MX_LOOKUP_ENABLED_VARIABLE),
+            password_reset_token_expiration_seconds: variables
+                .var(PASSWORD_RESET_TOKEN_EXPIRATION_SECONDS_VARIABLE),
+            error_status_policy: variables.var(GRAPHQL_ERROR_STATUS_POLICY_VARIABLE),
+            max_response_bytes: variables.var(GRAPHQL_MAX_RESPONSE_BYTES_VARIABLE),
+            request_budget: RequestBudget {
+                max_rows: variables.var(GRAPHQL_MAX_ROWS_FETCHED_VARIABLE),
+                action: variables.var(GRAPHQL_BUDGET_ACTION_VARIABLE),
+            },
+            max_batch_size: variables.var(GRAPHQL_MAX_BATCH_SIZE_VARIABLE),
+            get_cache_max_age_seconds: variables.var(GRAPHQL_GET_CACHE_MAX_AGE_SECONDS_VARIABLE),
+            schema_cache_max_age_seconds: variables
+                .var(GRAPHQL_SCHEMA_CACHE_MAX_AGE_SECONDS_VARIABLE),
+            persisted_queries_only: variables.var(GRAPHQL_PERSISTED_QUERIES_ONLY_VARIABLE),
+            query_limits: QueryLimits {
+                max_aliases: variables.var(GRAPHQL_MAX_ALIASES_VARIABLE),
+                max_root_fields: variables.var(GRAPHQL_MAX_ROOT_FIELDS_VARIABLE),
+                max_directives: variables.var(GRAPHQL_MAX_DIRECTIVES_VARIABLE),
+            },
+            anonymous_query_limits: QueryLimits {
+                max_aliases: variables.var(GRAPHQL_ANONYMOUS_MAX_ALIASES_VARIABLE),
+                max_root_fields: variables.var(GRAPHQL_ANONYMOUS_MAX_ROOT_FIELDS_VARIABLE),
+                max_directives: variables.var(GRAPHQL_ANONYMOUS_MAX_DIRECTIVES_VARIABLE),
+            },
+            resolver_concurrency_limits: ConcurrencyLimits {
+                max_concurrent: variables.var(GRAPHQL_RESOLVER_CONCURRENCY_LIMIT_VARIABLE),
+                queue_timeout_ms: variables.var(GRAPHQL_RESOLVER_QUEUE_TIMEOUT_MS_VARIABLE),
+            },
+            cache_backend: variables.var(CACHE_BACKEND_VARIABLE),
+            result_cache_enabled: variables.var(RESULT_CACHE_ENABLED_VARIABLE),
+            result_cache_ttls: ResultCacheTtls {
+                user_count_seconds: variables.var(RESULT_CACHE_USER_COUNT_TTL_SECONDS_VARIABLE),
+                feedback_seconds: variables.var(RESULT_CACHE_FEEDBACK_TTL_SECONDS_VARIABLE),
+                top_queries_seconds: variables.var(RESULT_CACHE_TOP_QUERIES_TTL_SECONDS_VARIABLE),
+                active_announcements_seconds: variables
+                    .var(RESULT_CACHE_ACTIVE_ANNOUNCEMENTS_TTL_SECONDS_VARIABLE),
+            },
+            canary_percentages: CanaryPercentages {
+                user_pages_percent: variables
+                    .ranged_var(CANARY_USER_PAGES_PERCENT_VARIABLE, 0..=100),
+            },
+            request_timeout_seconds: variables.ranged_var(REQUEST_TIMEOUT_SECONDS_VARIABLE, 1..),
+            response_compression_enabled: variables.var(RESPONSE_COMPRESSION_ENABLED_VARIABLE),
+            response_compression_min_bytes: variables.var(RESPONSE_COMPRESSION_MIN_BYTES_VARIABLE),
+            log_format: variables.var(LOG_FORMAT_VARIABLE),
+            cors_allowed_origins: variables.list_var(CORS_ALLOWED_ORIGINS_VARIABLE),
+            cors_allowed_methods: variables.list_var(CORS_ALLOWED_METHODS_VARIABLE),
+            cors_allowed_headers: variables.list_var(CORS_ALLOWED_HEADERS_VARIABLE),
+            cors_allow_credentials: variables.var(CORS_ALLOW_CREDENTIALS_VARIABLE),
+            organization_domains_enabled: variables.var(ORGANIZATION_DOMAINS_ENABLED_VARIABLE),
+            stripe_secret_key: variables.optional_var(STRIPE_SECRET_KEY_VARIABLE),
+            #[cfg(feature = "webhooks")]
+            stripe_webhook_secret: variables.optional_var(STRIPE_WEBHOOK_SECRET_VARIABLE),
+            stripe_pro_price_id: variables.optional_var(STRIPE_PRO_PRICE_ID_VARIABLE),
+            billing_success_url: variables.var(BILLING_SUCCESS_URL_VARIABLE),
+            billing_cancel_url: variables.var(BILLING_CANCEL_URL_VARIABLE),
+            billing_currency: variables.var(BILLING_CURRENCY_VARIABLE),
+            trial_days: variables.var(TRIAL_DAYS_VARIABLE),
+            free_plan_api_call_limit: variables.var(FREE_PLAN_API_CALL_LIMIT_VARIABLE),
+            pro_plan_api_call_limit: variables.var(PRO_PLAN_API_CALL_LIMIT_VARIABLE),
+            usage_soft_limit_percent: variables
+                .ranged_var(USAGE_SOFT_LIMIT_PERCENT_VARIABLE, 0..=100),
+            #[cfg(feature = "metrics")]
+            metrics_enabled: variables.var(METRICS_ENABLED_VARIABLE),
+            alert_check_interval_seconds: variables.var(ALERT_CHECK_INTERVAL_SECONDS_VARIABLE),
+            alert_thresholds: AlertThresholds {
+                failed_logins: variables.var(ALERT_FAILED_LOGINS_THRESHOLD_VARIABLE),
+                email_failure_rate_percent: variables
+                    .ranged_var(ALERT_EMAIL_FAILURE_RATE_PERCENT_VARIABLE, 0..=100),
+                database_pool_saturation_percent: variables
+                    .ranged_var(ALERT_DATABASE_POOL_SATURATION_PERCENT_VARIABLE, 0..=100),
+                error_rate_percent: variables
+                    .ranged_var(ALERT_ERROR_RATE_PERCENT_VARIABLE, 0..=100),
+            },
+            alert_cooldown_seconds: variables.var(ALERT_COOLDOWN_SECONDS_VARIABLE),
+            alert_webhook_url: variables.optional_var(ALERT_WEBHOOK_URL_VARIABLE),
+            alert_email_address: variables.optional_var(ALERT_EMAIL_ADDRESS_VARIABLE),
+            retention_interval_seconds: variables.var(RETENTION_INTERVAL_SECONDS_VARIABLE),
+            retention_dry_run: variables.var(RETENTION_DRY_RUN_VARIABLE),
+            retention_policy: RetentionPolicy {
+                audit_event_days: variables.var(AUDIT_EVENT_RETENTION_DAYS_VARIABLE),
+                security_event_days: variables.var(SECURITY_EVENT_RETENTION_DAYS_VARIABLE),
+                unverified_user_days: variables.var(UNVERIFIED_USER_RETENTION_DAYS_VARIABLE),
+                session_metadata_days: variables.var(SESSION_METADATA_RETENTION_DAYS_VARIABLE),
+            },
+            undo_grace_period_seconds: variables.var(UNDO_GRACE_PERIOD_SECONDS_VARIABLE),
+            feedback_hourly_limit: variables.var(FEEDBACK_HOURLY_LIMIT_VARIABLE),
+            storage_backend: variables.var(STORAGE_BACKEND_VARIABLE),
+            storage_path: variables.var(STORAGE_PATH_VARIABLE),
+            storage_url_secret: variables.optional_var(STORAGE_URL_SECRET_VARIABLE),
+            signed_url_expiration_seconds: variables.var(SIGNED_URL_EXPIRATION_SECONDS_VARIABLE),
+            s3_endpoint: variables.optional_var(S3_ENDPOINT_VARIABLE),
+            s3_bucket: variables.optional_var(S3_BUCKET_VARIABLE),
+            s3_region: variables.optional_var(S3_REGION_VARIABLE),
+            s3_access_key_id: variables.optional_var(S3_ACCESS_KEY_ID_VARIABLE),
+            s3_secret_access_key: variables.optional_var(S3_SECRET_ACCESS_KEY_VARIABLE),
+            upload_max_bytes: variables.var(UPLOAD_MAX_BYTES_VARIABLE),
+            avatar_max_bytes: variables.var(AVATAR_MAX_BYTES_VARIABLE),
+            clamd_address: variables.optional_var(CLAMD_ADDRESS_VARIABLE),
+            scan_async_threshold_bytes: variables.var(SCAN_ASYNC_THRESHOLD_BYTES_VARIABLE),
+            change_event_tables: variables.list_var(CHANGE_EVENT_TABLES_VARIABLE),
+            server_url: variables.var(SERVER_URL_VARIABLE),
+            #[cfg(feature = "oauth")]
+            oauth_success_url: variables.var(OAUTH_SUCCESS_URL_VARIABLE),
+            #[cfg(feature = "oauth")]
+            google_oauth_client_id: variables.optional_var(GOOGLE_OAUTH_CLIENT_ID_VARIABLE),
+            #[cfg(feature = "oauth")]
+            google_oauth_client_secret: variables.optional_var(GOOGLE_OAUTH_CLIENT_SECRET_VARIABLE),
+            #[cfg(feature = "oauth")]
+            github_oauth_client_id: variables.optional_var(GITHUB_OAUTH_CLIENT_ID_VARIABLE),
+            #[cfg(feature = "oauth")]
+            github_oauth_client_secret: variables.optional_var(GITHUB_OAUTH_CLIENT_SECRET_VARIABLE),
+            webauthn_rp_id: variables.var(WEBAUTHN_RP_ID_VARIABLE),
+            webauthn_rp_name: variables.var(WEBAUTHN_RP_NAME_VARIABLE),
+            webauthn_origin: variables.var(WEBAUTHN_ORIGIN_VARIABLE),
+            webauthn_challenge_expiration_seconds: variables
+                .var(WEBAUTHN_CHALLENGE_EXPIRATION_SECONDS_VARIABLE),
             is_docker,
+        };
+
+        // Any website could make credentialed requests as a signed in user if the wildcard were
+        // allowed with credentials, so the combination is rejected rather than echoing origins.
+        if config.cors_allow_credentials
+            && config
+                .cors_allowed_origins
+                .iter()
+                .any(|origin| origin == "*")
+        {
+            variables.errors.push(ConfigError::Conflicting {
+                name: CORS_ALLOWED_ORIGINS_VARIABLE.name,
+                value: "*".to_string(),
+                other_name: CORS_ALLOW_CREDENTIALS_VARIABLE.name,
+                other_value: true.to_string(),
+            });
+        }
+
+        match variables.errors.is_empty() {
+            true => Ok(config),
+            false => Err(ConfigErrors(std::mem::take(&mut variables.errors))),
+        }
+    }
+}
+
+/// A problem found with an environment variable while loading the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A required variable isn't set.
+    Missing { name: &'static str },
+    /// A variable's value can't be parsed as the type it is read as. The value isn't kept for
+    /// secrets, so it can't end up in logs.
+    Unparseable {
+        name: &'static str,
+        value_type: &'static str,
+        value: Option<String>,
+    },
+    /// A variable's value is outside the range of values it can be set to, such as a bcrypt cost
+    /// below 4.
+    OutOfRange {
+        name: &'static str,
+        value: String,
+        range: String,
+    },
+    /// Two variables are set to values that can't be used together, such as allowing any origin
+    /// to make cross-origin requests with credentials.
+    Conflicting {
+        name: &'static str,
+        value: String,
+        other_name: &'static str,
+        other_value: String,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        match self {
+            ConfigError::Missing { name } => write!(formatter, "{} is not set.", name),
+            ConfigError::Unparseable {
+                name,
+                value_type,
+                value,
+            } => {
+                let value_type = match *value_type {
+                    "boolean" => "a boolean",
+                    "integer" => "an integer",
+                    _ => "a valid value",
+                };
+                match value {
+                    Some(value) => write!(
+                        formatter,
+                        "{} is set to \"{}\", which is not {}.",
+                        name, value, value_type
+                    ),
+                    None => write!(formatter, "{} is not {}.", name, value_type),
+                }
+            }
+            ConfigError::OutOfRange { name, value, range } => write!(
+                formatter,
+                "{} is set to {}, but must be {}.",
+                name, value, range
+            ),
+            ConfigError::Conflicting {
+                name,
+                value,
+                other_name,
+                other_value,
+            } => write!(
+                formatter,
+                "{} is set to {}, which can't be used while {} is set to {}.",
+                name, value, other_name, other_value
+            ),
+        }
+    }
+}
+
+/// Every problem found with the configuration, reported together so they can all be fixed at
+/// once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl Display for ConfigErrors {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        let count = self.0.len();
+        write!(
+            formatter,
+            "Found {} problem{} with the configuration:",
+            count,
+            if count == 1 { "" } else { "s" }
+        )?;
+        for error in &self.0 {
+            write!(formatter, "\n  * {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// Describe the values in a range, such as "between 4 and 31" or "at least 1".
+fn describe_range<T: Display>(range: &impl RangeBounds<T>) -> String {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(min), Bound::Included(max)) => format!("between {} and {}", min, max),
+        (Bound::Included(min), _) => format!("at least {}", min),
+        (_, Bound::Included(max)) => format!("at most {}", max),
+        _ => "in range".into(),
+    }
+}
+
+/// An environment variable the configuration is loaded from.
+#[derive(Debug, Clone, Copy)]
+struct Variable {
+    name: &'static str,
+    description: &'static str,
+    is_secret: bool,
+}
+
+impl Variable {
+    /// Describe a variable.
+    const fn new(name: &'static str, description: &'static str) -> Self {
+        Variable {
+            name,
+            description,
+            is_secret: false,
+        }
+    }
+
+    /// Describe a variable holding a secret, such as a password or a signing key.
+    const fn secret(name: &'static str, description: &'static str) -> Self {
+        Variable {
+            name,
+            description,
+            is_secret: true,
         }
     }
 }
 
-/// Get an environment variable and try to parse it as a specified data type. This function will
-// panic if the variable cannot be found or cannot be parsed.
-fn var<T: FromStr>(name: &str) -> T {
-    std::env::var(name)
-        .unwrap_or_else(|_| panic!("Missing environment variable: {}", name))
-        .parse()
-        .unwrap_or_else(|_| panic!("Failed to parse environment variable: {}", name))
+/// How an environment variable is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariableKind {
+    /// The variable must be set.
+    Required,
+    /// The variable can be left unset or empty.
+    Optional,
+    /// The variable holds a comma-separated list, which is empty if it is unset.
+    List,
+}
+
+/// A description of an environment variable the configuration is loaded from.
+#[derive(Debug, Clone)]
+pub struct VariableDescription {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub kind: VariableKind,
+    /// The JSON type the variable's value is parsed as: "boolean", "integer" or "string". This is
+    /// the type of each item for lists.
+    pub value_type: &'static str,
+    pub is_secret: bool,
+    /// The value the variable is set to in the environment file it was described from. This is
+    /// none for secrets and variables that aren't set.
+    pub default: Option<String>,
+}
+
+/// Get the JSON type a value of a type is parsed from.
+fn json_type<T>() -> &'static str {
+    match std::any::type_name::<T>() {
+        "bool" => "boolean",
+        "u8" | "u16" | "u32" | "u64" | "usize" | "i8" | "i16" | "i32" | "i64" | "isize" => {
+            "integer"
+        }
+        _ => "string",
+    }
+}
+
+/// A function returning the value of an environment variable, if it is set.
+type Lookup = Box<dyn Fn(&str) -> Option<String>>;
+
+/// Reads environment variables, recording a description of each one read if describing them.
+/// Problems with variables are recorded rather than stopping at the first one.
+struct Variables {
+    lookup: Lookup,
+    is_describing: bool,
+    descriptions: Vec<VariableDescription>,
+    errors: Vec<ConfigError>,
+}
+
+impl Variables {
+    /// Read variables with a function returning the value of a variable, if it is set.
+    fn new(lookup: impl Fn(&str) -> Option<String> + 'static) -> Self {
+        Variables {
+            lookup: Box::new(lookup),
+            is_describing: false,
+            descriptions: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Get the value of a variable, recording its description if describing variables.
+    fn get(
+        &mut self,
+        variable: Variable,
+        kind: VariableKind,
+        value_type: &'static str,
+    ) -> Option<String> {
+        let value = (self.lookup)(variable.name);
+        if self.is_describing {
+            self.descriptions.push(VariableDescription {
+                name: variable.name,
+                description: variable.description,
+                kind,
+                value_type,
+                is_secret: variable.is_secret,
+                default: value
+                    .clone()
+                    .filter(|value| !value.is_empty() && !variable.is_secret),
+            });
+        }
+
+        value
+    }
+
+    /// Parse the value of a variable as a specified data type. If it can't be parsed, the problem
+    /// is recorded and none is returned.
+    fn parse<T: FromStr>(&mut self, variable: Variable, value: &str) -> Option<T> {
+        let parsed = value.parse().ok();
+        if parsed.is_none() {
+            self.errors.push(ConfigError::Unparseable {
+                name: variable.name,
+                value_type: json_type::<T>(),
+                value: Some(value.to_string()).filter(|_| !variable.is_secret),
+            });
+        }
+
+        parsed
+    }
+
+    /// Get a variable and try to parse it as a specified data type. If the variable cannot be
+    /// found or cannot be parsed, the problem is recorded and a default value is returned in its
+    /// place, which is never used as the configuration fails to load.
+    fn var<T: FromStr + Default>(&mut self, variable: Variable) -> T {
+        match self.get(variable, VariableKind::Required, json_type::<T>()) {
+            Some(value) => self.parse(variable, &value).unwrap_or_default(),
+            None => {
+                self.errors.push(ConfigError::Missing {
+                    name: variable.name,
+                });
+                T::default()
+            }
+        }
+    }
+
+    /// Get a variable, parse it as a specified data type and check it is within a range. Problems
+    /// are recorded like they are for other variables.
+    fn ranged_var<T>(&mut self, variable: Variable, range: impl RangeBounds<T>) -> T
+    where
+        T: FromStr + Default + PartialOrd + Display,
+    {
+        let errors = self.errors.len();
+        let value = self.var(variable);
+        // Values that couldn't be read have already been reported.
+        if self.errors.len() == errors && !range.contains(&value) {
+            self.errors.push(ConfigError::OutOfRange {
+                name: variable.name,
+                value: value.to_string(),
+                range: describe_range(&range),
+            });
+        }
+
+        value
+    }
+
+    /// Get an optional variable and try to parse it as a specified data type. This function will
+    /// return none if the variable cannot be found or is empty. If it cannot be parsed, the problem
+    /// is recorded and none is returned.
+    fn optional_var<T: FromStr>(&mut self, variable: Variable) -> Option<T> {
+        match self.get(variable, VariableKind::Optional, json_type::<T>()) {
+            Some(value) if !value.is_empty() => self.parse(variable, &value),
+            _ => None,
+        }
+    }
+
+    /// Get an optional variable holding a comma-separated list of values. This function will
+    /// return an empty list if the variable cannot be found or is empty.
+    fn list_var(&mut self, variable: Variable) -> Vec<String> {
+        self.get(variable, VariableKind::List, "string")
+            .map(|list| {
+                list.split(',')
+                    .map(|value| value.trim().to_string())
+                    .filter(|value| !value.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get an optional variable holding a comma-separated list of values, and try to parse each
+    /// value as a specified data type. Values that cannot be parsed are recorded as problems and
+    /// left out.
+    fn parsed_list_var<T: FromStr>(&mut self, variable: Variable) -> Vec<T> {
+        self.list_var(variable)
+            .iter()
+            .filter_map(|value| self.parse(variable, value))
+            .collect()
+    }
 }
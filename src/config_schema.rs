@@ -0,0 +1,101 @@
+use serde_json::{json, Map, Value};
+
+use crate::config::{VariableDescription, VariableKind};
+
+/// Title given to the generated JSON schema.
+const SCHEMA_TITLE: &str = "rust-graphql-server configuration";
+
+/// Build a JSON schema describing the environment variables the configuration is loaded from, as
+/// an object with a property for each variable. Values are described by the type they are parsed
+/// as, so lists are arrays of strings. Secrets are marked with an "x-secret" keyword.
+pub fn json_schema(variables: &[VariableDescription]) -> Value {
+    let mut properties = Map::new();
+    for variable in variables {
+        let mut property = match variable.kind {
+            VariableKind::List => json!({ "type": "array", "items": { "type": "string" } }),
+            _ => json!({ "type": variable.value_type }),
+        };
+        property["description"] = variable.description.into();
+        if let Some(default) = &variable.default {
+            property["default"] = typed_value(variable, default);
+        }
+        if variable.is_secret {
+            property["x-secret"] = true.into();
+        }
+        properties.insert(variable.name.into(), property);
+    }
+
+    let required = variables
+        .iter()
+        .filter(|variable| variable.kind == VariableKind::Required)
+        .map(|variable| variable.name)
+        .collect::<Vec<_>>();
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": SCHEMA_TITLE,
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+/// Convert the value of a variable to the JSON type it is parsed as. Values that can't be parsed
+/// are kept as strings.
+fn typed_value(variable: &VariableDescription, value: &str) -> Value {
+    match (variable.kind, variable.value_type) {
+        (VariableKind::List, _) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .collect::<Vec<_>>()
+            .into(),
+        (_, "boolean") => value
+            .parse::<bool>()
+            .map_or_else(|_| value.into(), Value::from),
+        (_, "integer") => value
+            .parse::<i64>()
+            .map_or_else(|_| value.into(), Value::from),
+        _ => value.into(),
+    }
+}
+
+/// Build a markdown document with a table describing the environment variables the configuration
+/// is loaded from.
+pub fn markdown(variables: &[VariableDescription]) -> String {
+    let mut document = String::from(
+        "# Configuration\n\n\
+        The server is configured with the environment variables below, which can also be set in \
+        \".env\" and \".env.override\" files. Defaults are the values in the \".env\" file. This \
+        file is generated from the server's configuration by running \
+        `cargo run generate config-schema`, so it shouldn't be edited by hand.\n\n\
+        | Variable | Type | Required | Default | Secret | Description |\n\
+        | --- | --- | --- | --- | --- | --- |\n",
+    );
+    for variable in variables {
+        let value_type = match variable.kind {
+            VariableKind::List => format!("list of {}s", variable.value_type),
+            _ => variable.value_type.into(),
+        };
+        let default = variable
+            .default
+            .as_ref()
+            .map(|default| format!("`{}`", default))
+            .unwrap_or_default();
+        document.push_str(&format!(
+            "| `{}` | {} | {} | {} | {} | {} |\n",
+            variable.name,
+            value_type,
+            if variable.kind == VariableKind::Required {
+                "yes"
+            } else {
+                "no"
+            },
+            default,
+            if variable.is_secret { "yes" } else { "no" },
+            variable.description.replace('|', "\\|"),
+        ));
+    }
+
+    document
+}
@@ -1,24 +1,317 @@
-use tide::Request;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
 
+use anyhow::{anyhow, Error, Result};
+use async_trait::async_trait;
+use dataloader::cached::Loader;
+use dataloader::BatchFn;
+use tide::log;
+use uuid::Uuid;
+
+use crate::auth::{AuthenticatedUser, SessionToken};
+use crate::billing::Plan;
+use crate::deadlines::Deadline;
+use crate::deprecations::DeprecatedUses;
 use crate::executor::Executor;
+use crate::models::User;
+use crate::policy::{FieldAccess, FieldPolicy};
+use crate::roles::{Access, Role};
+use crate::session_cookies::SessionCookies;
 use crate::state::State;
+use crate::uploads::{Upload, UploadedFile, Uploads};
+
+/// The result of loading a value with a dataloader. Errors are shared between every load in the
+/// batch that failed, so they are reference counted.
+type LoadResult<T> = std::result::Result<Option<T>, Arc<Error>>;
 
 /// Shared data for a single GraphQL request. This context is accessible throughout the schema.
 pub struct Context {
-    executor: Executor,
+    executor: Arc<Executor>,
+    viewer: Option<AuthenticatedUser>,
+    users_by_id: Loader<Uuid, LoadResult<User>, UsersById>,
+    users_by_username: Loader<String, LoadResult<User>, UsersByUsername>,
+    deprecated_uses: DeprecatedUses,
+    session_token: Option<String>,
+    session_cookies: SessionCookies,
+    uploads: Uploads,
+    host_organization: Option<Uuid>,
 }
 
 impl Context {
-    // Create a new context for the specified request.
-    pub async fn new(request: Request<State>) -> Self {
+    // Create a new context for a request handled with the provided global server state. The
+    // request ID is used to correlate side effects of the request with its log lines, the client IP
+    // and user agent describe the client that sent the request, if known, the session token is
+    // the unverified bearer token or session cookie the request was sent with, if any, and work
+    // done for the request is abandoned once the deadline passes.
+    pub async fn new(
+        state: State,
+        request_id: String,
+        client_ip: Option<String>,
+        user_agent: Option<String>,
+        session_token: Option<String>,
+        deadline: Deadline,
+    ) -> Self {
         // Create a new executor for the request, passing it the global server state.
+        let executor = Arc::new(
+            Executor::new(state, request_id, client_ip, user_agent).with_deadline(deadline),
+        );
+
+        // Verify the session token up front so resolvers can rely on the viewer. A request with an
+        // invalid session token is handled as if it had none.
+        let viewer = match &session_token {
+            Some(session_token) => {
+                executor
+                    .authenticate(session_token)
+                    .await
+                    .unwrap_or_else(|error| {
+                        log::error!("Failed to authenticate request: {}", error);
+                        None
+                    })
+            }
+            None => None,
+        };
+
+        // Lookups made through these loaders while resolving the request are batched together and
+        // cached until the request ends.
+        let users_by_id = Loader::new(UsersById(executor.clone()));
+        let users_by_username = Loader::new(UsersByUsername(executor.clone()));
+
         Context {
-            executor: Executor::new(request.state().clone()),
+            executor,
+            viewer,
+            users_by_id,
+            users_by_username,
+            deprecated_uses: DeprecatedUses::default(),
+            session_token,
+            session_cookies: SessionCookies::default(),
+            uploads: Uploads::new(),
+            host_organization: None,
         }
     }
 
+    /// Attach the files uploaded along with the request, so resolvers can read them through their
+    /// file arguments.
+    pub fn with_uploads(mut self, uploads: Uploads) -> Self {
+        self.uploads = uploads;
+        self
+    }
+
+    /// Attach the organization the request was sent to, if it was sent to one of an organization's
+    /// verified custom hostnames.
+    pub fn with_host_organization(mut self, host_organization: Option<Uuid>) -> Self {
+        self.host_organization = host_organization;
+        self
+    }
+
     /// Get the executor for the current request.
     pub fn executor(&self) -> &Executor {
         &self.executor
     }
+
+    /// Get the deadline the request has to be handled by. Resolvers doing work outside the
+    /// executor should abandon it once this passes.
+    pub fn deadline(&self) -> Deadline {
+        self.executor.deadline()
+    }
+
+    /// Get the user making the request. This will return none if the request wasn't sent with a
+    /// valid session token.
+    pub fn viewer(&self) -> Option<&AuthenticatedUser> {
+        self.viewer.as_ref()
+    }
+
+    /// Get the unverified session token the request was sent with, either as a bearer token or in
+    /// the session cookie.
+    pub fn session_token(&self) -> Option<&str> {
+        self.session_token.as_deref()
+    }
+
+    /// Get the ID of the organization the request was sent to through one of its custom hostnames.
+    /// This will return none if the request was sent to the server's own hostname.
+    pub fn host_organization(&self) -> Option<Uuid> {
+        self.host_organization
+    }
+
+    /// Get the changes made to the session cookie while resolving the request.
+    pub fn session_cookies(&self) -> &SessionCookies {
+        &self.session_cookies
+    }
+
+    /// Hand a new session token to the client. When sessions are delivered as cookies, the token
+    /// is stored in the session cookie and none is returned, so scripts on the page can't read it.
+    /// Otherwise the token is returned to be sent in the response body.
+    pub fn deliver_session_token(&self, session_token: &SessionToken) -> Option<String> {
+        if self.executor.config().session_cookie_policy.enabled {
+            self.session_cookies.set(session_token);
+            None
+        } else {
+            Some(session_token.to_string())
+        }
+    }
+
+    /// Load a user by ID. Loads made while resolving the same request are batched into a single
+    /// query, and each user is only queried once per request. This will return none if the user is
+    /// not found.
+    pub async fn load_user(&self, id: Uuid) -> Result<Option<User>> {
+        let user = self.users_by_id.load(id).await.map_err(convert_error)?;
+        if let Some(user) = &user {
+            self.users_by_username
+                .prime(user.username.clone(), Ok(Some(user.clone())))
+                .await;
+        }
+
+        Ok(user)
+    }
+
+    /// Load a user by their username. Loads made while resolving the same request are batched into
+    /// a single query, and each user is only queried once per request. This will return none if no
+    /// user has the specified username.
+    pub async fn load_user_by_username(&self, username: &str) -> Result<Option<User>> {
+        let user = self
+            .users_by_username
+            .load(username.into())
+            .await
+            .map_err(convert_error)?;
+        if let Some(user) = &user {
+            self.users_by_id
+                .prime(user.id, Ok(Some(user.clone())))
+                .await;
+        }
+
+        Ok(user)
+    }
+
+    /// Get the deprecated fields used while resolving the request.
+    pub fn deprecated_uses(&self) -> &DeprecatedUses {
+        &self.deprecated_uses
+    }
+
+    /// Record that the request used a deprecated field, specified by its coordinate in the schema.
+    /// Every resolver of a deprecated field should call this, so the client is warned and its use
+    /// is counted. Each field is only counted once per request.
+    pub async fn record_deprecated_use(&self, field: &str) {
+        if let Some(deprecation) = self.deprecated_uses.insert(field) {
+            if let Err(error) = self.executor.record_deprecated_use(deprecation).await {
+                log::error!("Failed to record use of deprecated field: {}", error);
+            }
+        }
+    }
+
+    /// Get the file uploaded for a file argument. This will return none if the file wasn't sent
+    /// with the request.
+    pub fn upload(&self, upload: &Upload) -> Option<&UploadedFile> {
+        self.uploads.get(&upload.0)
+    }
+
+    /// Check if the user making the request has a role.
+    pub async fn access(&self, role: Role) -> Result<Access> {
+        Ok(match self.viewer() {
+            Some(viewer)
+                if self
+                    .executor
+                    .find_user_roles(viewer.user_id)
+                    .await?
+                    .contains(&role) =>
+            {
+                Access::Granted
+            }
+            Some(_) => Access::Forbidden,
+            None => Access::Unauthenticated,
+        })
+    }
+
+    /// Check if the user making the request can see a field of a record belonging to a user,
+    /// following the field's policy.
+    pub async fn authorize_field(
+        &self,
+        policy: FieldPolicy,
+        owner_id: Uuid,
+    ) -> Result<FieldAccess> {
+        let is_owner = self
+            .viewer()
+            .is_some_and(|viewer| viewer.user_id == owner_id);
+        policy
+            .decide(is_owner, async {
+                Ok(self.access(Role::Admin).await? == Access::Granted)
+            })
+            .await
+    }
+
+    /// Check if the user making the request can make changes. Users on the restricted plan, whose
+    /// trial has ended without subscribing, only have read-only access.
+    pub async fn write_access(&self) -> Result<Access> {
+        self.plan_access(Plan::Free).await
+    }
+
+    /// Check if the user making the request is entitled to a plan. Users on a higher plan are
+    /// entitled to every plan below it.
+    pub async fn plan_access(&self, plan: Plan) -> Result<Access> {
+        Ok(match self.viewer() {
+            Some(viewer) if self.executor.find_user_plan(viewer.user_id).await? >= plan => {
+                Access::Granted
+            }
+            Some(_) => Access::Forbidden,
+            None => Access::Unauthenticated,
+        })
+    }
+}
+
+// Lets objects resolved with the context hand it down to their fields.
+impl juniper::Context for Context {}
+
+/// Convert a shared error from a dataloader back into an error that can be returned.
+fn convert_error(error: Arc<Error>) -> Error {
+    anyhow!("{:#}", error)
+}
+
+/// Group values loaded by a batch function under their keys. Every key is included, so keys that
+/// weren't found are loaded as none and a failed batch is loaded as an error for every key.
+fn group_by_key<K: Eq + Hash + Clone, T>(
+    keys: &[K],
+    values: Result<Vec<T>>,
+    key: impl Fn(&T) -> K,
+) -> HashMap<K, LoadResult<T>> {
+    match values {
+        Ok(values) => {
+            let mut loaded = keys
+                .iter()
+                .map(|key| (key.clone(), Ok(None)))
+                .collect::<HashMap<_, _>>();
+            for value in values {
+                loaded.insert(key(&value), Ok(Some(value)));
+            }
+            loaded
+        }
+        Err(error) => {
+            let error = Arc::new(error);
+            keys.iter()
+                .map(|key| (key.clone(), Err(error.clone())))
+                .collect()
+        }
+    }
+}
+
+/// Batch function loading users by ID.
+struct UsersById(Arc<Executor>);
+
+#[async_trait]
+impl BatchFn<Uuid, LoadResult<User>> for UsersById {
+    async fn load(&mut self, ids: &[Uuid]) -> HashMap<Uuid, LoadResult<User>> {
+        group_by_key(ids, self.0.find_users_by_ids(ids).await, |user| user.id)
+    }
+}
+
+/// Batch function loading users by username.
+struct UsersByUsername(Arc<Executor>);
+
+#[async_trait]
+impl BatchFn<String, LoadResult<User>> for UsersByUsername {
+    async fn load(&mut self, usernames: &[String]) -> HashMap<String, LoadResult<User>> {
+        group_by_key(
+            usernames,
+            self.0.find_users_by_usernames(usernames).await,
+            |user| user.username.clone(),
+        )
+    }
 }
@@ -1,14 +1,18 @@
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use anyhow::{anyhow, Result};
 use async_std::task;
+use chrono::Utc;
 use redis::aio::ConnectionManager;
 use redis::{Client as RedisClient, RedisResult};
-use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::migrate::{Migrate, MigrateError, Migration, Migrator};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Error as SqlxError, PgPool};
 use tide::log;
 
 use crate::config::Config;
+use crate::logging;
 
 const MAX_CONNECTION_RETRIES: u64 = 20;
 const RETRY_POLLING_INTERVAL_SECONDS: u64 = 3;
@@ -27,7 +31,7 @@ pub async fn connect_to_db(
     let mut retries = 0;
     loop {
         match PgPoolOptions::new()
-            .max_connections(*database_max_connection_count as u32)
+            .max_connections(*database_max_connection_count)
             .connect(database_url)
             .await
         {
@@ -47,9 +51,15 @@ pub async fn connect_to_db(
 
 /// Attempt to connect to the Redis database using the provided configuration.
 pub async fn connect_to_redis(Config { redis_url, .. }: &Config) -> RedisResult<ConnectionManager> {
+    connect_to_redis_url(redis_url).await
+}
+
+/// Attempt to connect to the Redis database with the provided connection string, such as the
+/// Redis database of another region.
+pub async fn connect_to_redis_url(redis_url: &str) -> RedisResult<ConnectionManager> {
     let mut retries = 0;
     loop {
-        match ConnectionManager::new(RedisClient::open(redis_url.as_str())?).await {
+        match ConnectionManager::new(RedisClient::open(redis_url)?).await {
             Ok(redis) => break Ok(redis),
             Err(error) => {
                 if retries == MAX_CONNECTION_RETRIES {
@@ -64,8 +74,108 @@ pub async fn connect_to_redis(Config { redis_url, .. }: &Config) -> RedisResult<
     }
 }
 
-/// Run all pending database migrations.
-pub async fn run_migrations(db: &PgPool) -> Result<(), MigrateError> {
+/// Log how long a database query took. Queries slower than `DATABASE_SLOW_QUERY_THRESHOLD_MS`
+/// are logged as warnings, and other queries are only logged if `DATABASE_LOG_QUERIES` is set.
+pub fn log_query(
+    Config {
+        database_log_queries,
+        database_slow_query_threshold_ms,
+        ..
+    }: &Config,
+    name: &str,
+    duration: Duration,
+) {
+    let is_slow = *database_slow_query_threshold_ms > 0
+        && duration >= Duration::from_millis(*database_slow_query_threshold_ms);
+    if is_slow || *database_log_queries {
+        logging::log_query(name, duration, is_slow);
+    }
+}
+
+/// Whether a migration has been applied to the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    /// The migration has been applied.
+    Applied,
+    /// The migration hasn't been applied yet.
+    Pending,
+    /// The migration has been applied, but its SQL has changed since.
+    Modified,
+}
+
+/// A migration known to the server, and whether it has been applied to the database.
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+    /// Specifies if the migration can be reverted.
+    pub is_reversible: bool,
+}
+
+/// Every up migration, including any additional migrations contributed by plugins, sorted by
+/// version.
+fn up_migrations(additional: &[Migration]) -> Vec<&Migration> {
+    let mut migrations = MIGRATOR
+        .iter()
+        .chain(additional)
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .collect::<Vec<_>>();
+    migrations.sort_by_key(|migration| migration.version);
+    migrations
+}
+
+/// Find the migration reverting the migration with a version. Plugin migrations are never
+/// reversible, so only the server's own migrations are searched.
+fn down_migration(version: i64) -> Option<&'static Migration> {
+    MIGRATOR.iter().find(|migration| {
+        migration.version == version && migration.migration_type.is_down_migration()
+    })
+}
+
+/// List every migration, including any additional migrations contributed by plugins, along with
+/// whether it has been applied to the database.
+pub async fn migration_status(
+    db: &PgPool,
+    additional: &[Migration],
+) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let mut connection = db.acquire().await?;
+    connection.ensure_migrations_table().await?;
+
+    let mut statuses = Vec::new();
+    for migration in up_migrations(additional) {
+        let state = match connection.validate(migration).await {
+            Ok(()) => MigrationState::Applied,
+            Err(MigrateError::VersionMissing(_)) => MigrationState::Pending,
+            Err(MigrateError::VersionMismatch(_)) => MigrationState::Modified,
+            Err(error) => return Err(error),
+        };
+        statuses.push(MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            state,
+            is_reversible: down_migration(migration.version).is_some(),
+        });
+    }
+
+    Ok(statuses)
+}
+
+/// Run all pending database migrations, including any additional migrations contributed by
+/// plugins.
+pub async fn run_migrations(db: &PgPool, additional: &[Migration]) -> Result<(), MigrateError> {
+    migrate_up(db, additional, None).await?;
+    Ok(())
+}
+
+/// Run pending database migrations, including any additional migrations contributed by plugins,
+/// up to and including a target version. Every pending migration is run if there is no target.
+/// This will return the versions of the migrations that were run.
+pub async fn migrate_up(
+    db: &PgPool,
+    additional: &[Migration],
+    target: Option<i64>,
+) -> Result<Vec<i64>, MigrateError> {
     // The majority of this function is a workaround until
     // https://github.com/launchbadge/sqlx/pull/1061 is merged. After that's merged we can just
     // replace all of this with "MIGRATOR.run(db).await".
@@ -78,20 +188,91 @@ pub async fn run_migrations(db: &PgPool) -> Result<(), MigrateError> {
         return Err(MigrateError::Dirty(version));
     }
 
-    for migration in MIGRATOR.iter() {
-        // Ignore down migrations.
-        if migration.migration_type.is_down_migration() {
-            continue;
+    let mut applied = Vec::new();
+    for migration in up_migrations(additional) {
+        if target.is_some_and(|target| migration.version > target) {
+            break;
         }
 
-        if migration.version > version {
-            connection.apply(migration).await?;
-        } else {
-            connection.validate(migration).await?;
+        // Plugin migrations may be older than the latest applied migration, so apply any migration
+        // that hasn't been applied yet rather than only those with a newer version.
+        match connection.validate(migration).await {
+            Ok(()) => {}
+            Err(MigrateError::VersionMissing(_)) => {
+                connection.apply(migration).await?;
+                applied.push(migration.version);
+            }
+            Err(error) => return Err(error),
         }
     }
 
     connection.unlock().await?;
 
-    Ok(())
+    Ok(applied)
+}
+
+/// Revert applied database migrations newer than a target version, newest first. Only the latest
+/// applied migration is reverted if there is no target. This will return the versions of the
+/// migrations that were reverted, or an error before reverting anything if one of them can't be
+/// reverted.
+pub async fn migrate_down(
+    db: &PgPool,
+    additional: &[Migration],
+    target: Option<i64>,
+) -> Result<Vec<i64>> {
+    let mut connection = db.acquire().await?;
+    connection.lock().await?;
+    connection.ensure_migrations_table().await?;
+
+    let mut to_revert = Vec::new();
+    for migration in up_migrations(additional).into_iter().rev() {
+        if target.map_or(to_revert.len() == 1, |target| migration.version <= target) {
+            break;
+        }
+
+        match connection.validate(migration).await {
+            Ok(()) | Err(MigrateError::VersionMismatch(_)) => {
+                let down = down_migration(migration.version)
+                    .ok_or_else(|| anyhow!("Migration {} can't be reverted.", migration.version))?;
+                to_revert.push(down);
+            }
+            Err(MigrateError::VersionMissing(_)) => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    let mut reverted = Vec::new();
+    for migration in to_revert {
+        connection.revert(migration).await?;
+        reverted.push(migration.version);
+    }
+
+    connection.unlock().await?;
+
+    Ok(reverted)
+}
+
+/// Create an empty pair of up and down migration files in a directory, named with the current
+/// time and a description such as "add user avatars". This will return the paths of the created
+/// files. The server must be rebuilt to pick up new migrations.
+pub fn create_migration(directory: &Path, description: &str) -> Result<(PathBuf, PathBuf)> {
+    let name = description
+        .trim()
+        .to_lowercase()
+        .split(|character: char| !character.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+    if name.is_empty() {
+        return Err(anyhow!("Migration names must contain a letter or number."));
+    }
+
+    let prefix = format!("{}_{}", Utc::now().format("%Y%m%d%H%M%S"), name);
+    let up = directory.join(format!("{}.up.sql", prefix));
+    let down = directory.join(format!("{}.down.sql", prefix));
+    std::fs::create_dir_all(directory)?;
+    std::fs::write(&up, "-- Add migration script here\n")?;
+    std::fs::write(&down, "-- Add migration script here\n")?;
+
+    Ok((up, down))
 }
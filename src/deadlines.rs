@@ -0,0 +1,218 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use async_std::future;
+use async_trait::async_trait;
+use redis::aio::ConnectionLike;
+use redis::{Cmd, Pipeline, RedisError, RedisFuture, Value};
+use tide::log;
+
+use crate::cache::Cache;
+use crate::metrics::InstrumentedConnection;
+
+/// Header clients can send the time they will give up on a request in, as the number of
+/// milliseconds since the Unix epoch.
+pub const REQUEST_DEADLINE_HEADER: &str = "X-Request-Deadline";
+
+/// Message of the error returned when a request's deadline passes before it finishes.
+pub const DEADLINE_EXCEEDED_ERROR_MESSAGE: &str =
+    "The request took longer than its deadline allows. Try again later.";
+/// Error code returned when a request's deadline passes before it finishes.
+pub const DEADLINE_EXCEEDED_ERROR_CODE: &str = "deadline-exceeded";
+
+/// The point in time a request has to be handled by. Work done for the request, such as database
+/// queries and Redis commands, is abandoned once it passes, as the client has given up on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    at: Option<Instant>,
+}
+
+impl Deadline {
+    /// A deadline that never passes, for work that isn't done on behalf of a client, such as
+    /// background jobs.
+    pub fn none() -> Self {
+        Deadline { at: None }
+    }
+
+    /// A deadline passing once a duration has elapsed.
+    pub fn after(budget: Duration) -> Self {
+        Deadline {
+            at: Some(Instant::now() + budget),
+        }
+    }
+
+    /// The deadline of a request, sent in the "X-Request-Deadline" header if the client has one.
+    /// Requests are never given more than the configured request timeout, which is also their
+    /// deadline if they weren't sent with one. Invalid deadlines are logged and ignored.
+    pub fn for_request(header: Option<&str>, timeout_seconds: u32) -> Self {
+        let timeout = Duration::from_secs(timeout_seconds.into());
+        let budget = match header.map(parse_budget) {
+            Some(Ok(budget)) => budget.min(timeout),
+            Some(Err(error)) => {
+                log::warn!("Ignoring invalid request deadline: {}", error);
+                timeout
+            }
+            None => timeout,
+        };
+
+        Deadline::after(budget)
+    }
+
+    /// Get the time left until the deadline passes. This will return none if there is no deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.at
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Specifies if the deadline has passed.
+    pub fn is_exceeded(&self) -> bool {
+        self.remaining() == Some(Duration::ZERO)
+    }
+
+    /// Shorten a timeout so it ends by the deadline.
+    pub fn limit(&self, timeout: Duration) -> Duration {
+        match self.remaining() {
+            Some(remaining) => remaining.min(timeout),
+            None => timeout,
+        }
+    }
+
+    /// Run work that must finish by the deadline. The work is abandoned and an error is returned
+    /// if the deadline passes first. Work isn't started at all if the deadline has already passed.
+    pub async fn run<T>(&self, work: impl Future<Output = T>) -> Result<T, DeadlineExceeded> {
+        match self.remaining() {
+            None => Ok(work.await),
+            Some(remaining) if remaining.is_zero() => Err(DeadlineExceeded),
+            Some(remaining) => future::timeout(remaining, work)
+                .await
+                .map_err(|_| DeadlineExceeded),
+        }
+    }
+}
+
+/// Parse the deadline sent in the "X-Request-Deadline" header into the time left until it passes.
+/// Deadlines that have already passed leave no time.
+fn parse_budget(header: &str) -> Result<Duration> {
+    let deadline = header
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| anyhow!("Expected milliseconds since the Unix epoch: {}", header))?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    Ok(Duration::from_millis(deadline).saturating_sub(now))
+}
+
+/// The error returned when a request's deadline passes before work done for it finishes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineExceeded;
+
+impl Display for DeadlineExceeded {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(
+            formatter,
+            "Request deadline passed before its work finished."
+        )
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Convert a passed deadline into a Redis error. Redis commands are only abandoned when a
+/// deadline passes, so these are the only timeouts Redis commands fail with.
+fn redis_error(error: DeadlineExceeded) -> RedisError {
+    io::Error::new(io::ErrorKind::TimedOut, error).into()
+}
+
+/// A Redis connection abandoning commands once a request's deadline passes. This can be used
+/// anywhere a Redis connection manager can.
+#[derive(Clone)]
+pub struct DeadlineConnection {
+    connection: InstrumentedConnection,
+    deadline: Deadline,
+}
+
+impl DeadlineConnection {
+    /// Wrap a Redis connection so commands sent through it finish by a deadline.
+    pub fn new(connection: InstrumentedConnection, deadline: Deadline) -> Self {
+        DeadlineConnection {
+            connection,
+            deadline,
+        }
+    }
+}
+
+impl ConnectionLike for DeadlineConnection {
+    fn req_packed_command<'a>(&'a mut self, command: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let deadline = self.deadline;
+            deadline
+                .run(self.connection.req_packed_command(command))
+                .await
+                .map_err(redis_error)?
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        pipeline: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let deadline = self.deadline;
+            deadline
+                .run(self.connection.req_packed_commands(pipeline, offset, count))
+                .await
+                .map_err(redis_error)?
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.connection.get_db()
+    }
+}
+
+/// A cache abandoning reads and writes once a request's deadline passes.
+pub struct DeadlineCache {
+    cache: Arc<dyn Cache>,
+    deadline: Deadline,
+}
+
+impl DeadlineCache {
+    /// Wrap a cache so reads and writes made through it finish by a deadline.
+    pub fn new(cache: Arc<dyn Cache>, deadline: Deadline) -> Self {
+        DeadlineCache { cache, deadline }
+    }
+}
+
+#[async_trait]
+impl Cache for DeadlineCache {
+    fn name(&self) -> &'static str {
+        self.cache.name()
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        self.deadline.run(self.cache.get(key)).await?
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl_seconds: u32) -> Result<()> {
+        self.deadline
+            .run(self.cache.set(key, value, ttl_seconds))
+            .await?
+    }
+
+    async fn del(&self, key: &str) -> Result<bool> {
+        self.deadline.run(self.cache.del(key)).await?
+    }
+
+    async fn incr(&self, key: &str, ttl_seconds: u32) -> Result<u64> {
+        self.deadline.run(self.cache.incr(key, ttl_seconds)).await?
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<u32>> {
+        self.deadline.run(self.cache.ttl(key)).await?
+    }
+}
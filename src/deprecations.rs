@@ -0,0 +1,193 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use juniper::graphql_object;
+use tide::log;
+
+/// Header telling clients a request used deprecated fields, holding when the earliest of them was
+/// deprecated as a Unix timestamp prefixed with "@".
+pub const DEPRECATION_HEADER: &str = "Deprecation";
+/// Header telling clients when the earliest of the deprecated fields a request used may be
+/// removed, as an HTTP date.
+pub const SUNSET_HEADER: &str = "Sunset";
+/// Max number of distinct clients tracked per deprecated field. Uses by clients past this are
+/// counted under "other".
+pub const MAX_TRACKED_CLIENTS: usize = 1000;
+/// Client name deprecated field uses are counted under once too many clients are tracked.
+pub const OTHER_CLIENT: &str = "other";
+/// Client name deprecated field uses are counted under when the client didn't send a user agent.
+pub const UNKNOWN_CLIENT: &str = "unknown";
+
+/// A field or operation that is deprecated and will be removed from the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deprecation {
+    /// The coordinate of the field in the schema, such as "User.email" or "Mutation.login".
+    pub field: &'static str,
+    /// Date when the field was deprecated, as "YYYY-MM-DD".
+    pub deprecated_on: &'static str,
+    /// Date after which the field may be removed, as "YYYY-MM-DD".
+    pub sunset_on: &'static str,
+}
+
+impl Deprecation {
+    /// When the field was deprecated.
+    pub fn deprecated_at(&self) -> DateTime<Utc> {
+        parse_date(self.deprecated_on)
+    }
+
+    /// When the field may be removed.
+    pub fn sunset_at(&self) -> DateTime<Utc> {
+        parse_date(self.sunset_on)
+    }
+}
+
+/// Every deprecated field. To deprecate a field, add it here, mark it as deprecated in both
+/// schemas and have its resolver call "Context::record_deprecated_use", so clients still using it
+/// are warned through response headers and can be found through the "deprecatedFieldUsage" query
+/// before it is removed.
+pub const DEPRECATIONS: &[Deprecation] = &[];
+
+/// Find a deprecated field by its coordinate in the schema.
+pub fn find_deprecation(field: &str) -> Option<&'static Deprecation> {
+    DEPRECATIONS
+        .iter()
+        .find(|deprecation| deprecation.field == field)
+}
+
+/// Parse a date in a deprecation. Deprecations are constants, so an invalid date is a bug.
+fn parse_date(date: &str) -> DateTime<Utc> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .unwrap_or_else(|_| panic!("Invalid deprecation date: {}", date));
+    Utc.from_utc_datetime(&date.and_hms(0, 0, 0))
+}
+
+/// The deprecated fields used while resolving a request. Clones share the same fields, so they can
+/// be read after the request's context has been handed to the schema.
+#[derive(Debug, Clone, Default)]
+pub struct DeprecatedUses(Arc<Mutex<Vec<&'static Deprecation>>>);
+
+impl DeprecatedUses {
+    /// Record a use of a deprecated field. This will return the field's deprecation if it is the
+    /// first use of the field in the request, and none otherwise.
+    pub fn insert(&self, field: &str) -> Option<&'static Deprecation> {
+        let deprecation = match find_deprecation(field) {
+            Some(deprecation) => deprecation,
+            None => {
+                log::error!("Recorded use of a field that isn't deprecated: {}", field);
+                return None;
+            }
+        };
+
+        let mut uses = self.0.lock().unwrap();
+        if uses.contains(&deprecation) {
+            return None;
+        }
+        uses.push(deprecation);
+        Some(deprecation)
+    }
+
+    /// Get the headers warning the client that the request used deprecated fields, if it did.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let uses = self.0.lock().unwrap();
+        let deprecated_at = uses
+            .iter()
+            .map(|deprecation| deprecation.deprecated_at())
+            .min();
+        let sunset_at = uses.iter().map(|deprecation| deprecation.sunset_at()).min();
+        match (deprecated_at, sunset_at) {
+            (Some(deprecated_at), Some(sunset_at)) => vec![
+                (
+                    DEPRECATION_HEADER,
+                    format!("@{}", deprecated_at.timestamp()),
+                ),
+                (
+                    SUNSET_HEADER,
+                    sunset_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+                ),
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Get the Redis key of the hash counting a deprecated field's uses by client.
+pub fn uses_key(field: &str) -> String {
+    format!("deprecations/{}/uses", field)
+}
+
+/// Get the Redis key of the hash holding when each client last used a deprecated field.
+pub fn last_used_key(field: &str) -> String {
+    format!("deprecations/{}/last-used", field)
+}
+
+/// A client's use of a deprecated field.
+#[derive(Debug, Clone)]
+pub struct DeprecatedFieldClient {
+    /// The user agent of the client.
+    pub client: String,
+    /// The number of requests the client used the field in.
+    pub uses: i64,
+    /// When the client last used the field.
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[graphql_object(description = "A client's use of a deprecated field.")]
+impl DeprecatedFieldClient {
+    #[graphql(
+        description = "The user agent of the client, \"unknown\" if it didn't send one, or \"other\"
+        for clients past the number that are tracked."
+    )]
+    pub fn client(&self) -> &str {
+        &self.client
+    }
+
+    #[graphql(description = "The number of requests the client used the field in.")]
+    pub fn uses(&self) -> i32 {
+        self.uses.min(i32::MAX.into()) as i32
+    }
+
+    #[graphql(description = "Date when the client last used the field.")]
+    pub fn last_used_at(&self) -> Option<DateTime<Utc>> {
+        self.last_used_at
+    }
+}
+
+/// How a deprecated field is being used by clients.
+#[derive(Debug, Clone)]
+pub struct DeprecatedFieldUsage {
+    pub deprecation: &'static Deprecation,
+    /// The clients that used the field, most recent first.
+    pub clients: Vec<DeprecatedFieldClient>,
+}
+
+#[graphql_object(description = "How a deprecated field is being used by clients.")]
+impl DeprecatedFieldUsage {
+    #[graphql(description = "The coordinate of the field, such as \"User.email\".")]
+    pub fn field(&self) -> &str {
+        self.deprecation.field
+    }
+
+    #[graphql(description = "Date when the field was deprecated.")]
+    pub fn deprecated_at(&self) -> DateTime<Utc> {
+        self.deprecation.deprecated_at()
+    }
+
+    #[graphql(description = "Date after which the field may be removed.")]
+    pub fn sunset_at(&self) -> DateTime<Utc> {
+        self.deprecation.sunset_at()
+    }
+
+    #[graphql(description = "The number of requests that used the field.")]
+    pub fn uses(&self) -> i32 {
+        self.clients
+            .iter()
+            .map(|client| client.uses)
+            .sum::<i64>()
+            .min(i32::MAX.into()) as i32
+    }
+
+    #[graphql(description = "The clients that used the field, most recent first.")]
+    pub fn clients(&self) -> &[DeprecatedFieldClient] {
+        &self.clients
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_std::task;
+use async_std_resolver::resolver_from_system_conf;
+use tide::http::Url;
+use tide::log;
+use uuid::Uuid;
+
+use crate::executor::Executor;
+use crate::graphql::request_id;
+use crate::state::State;
+use crate::validation;
+
+/// Label prepended to a hostname to get the name of its TXT challenge record.
+pub const CHALLENGE_LABEL: &str = "_rust-graphql-server-challenge";
+/// Maximum number of hostnames an organization can add, verified or not.
+pub const MAX_DOMAINS_PER_ORGANIZATION: i64 = 10;
+/// Max number of hostnames checked by a single check.
+pub const CHECK_BATCH_SIZE: i64 = 20;
+/// How long a hostname is claimed for while its challenge record is looked up. If the server stops
+/// before the lookup finishes, the hostname is checked again once this has passed.
+pub const CLAIM_SECONDS: i64 = 60;
+/// How long to wait before checking a hostname that isn't verified again after its first failed
+/// check. The wait doubles with each failed check.
+pub const RETRY_BASE_SECONDS: i64 = 60;
+/// Maximum time to wait before checking a hostname that isn't verified again.
+pub const MAX_RETRY_SECONDS: i64 = 60 * 60;
+/// How long to wait before checking that a verified hostname still has its challenge record.
+pub const RECHECK_SECONDS: i64 = 24 * 60 * 60;
+/// Number of checks in a row a verified hostname's challenge record must be missing from before it
+/// loses its verification, so a brief DNS outage doesn't take it offline.
+pub const MAX_FAILED_RECHECKS: i32 = 3;
+/// Number of characters in a verification token.
+pub const VERIFICATION_TOKEN_LENGTH: usize = 32;
+/// Maximum number of characters in a hostname.
+const MAX_HOSTNAME_LENGTH: usize = 253;
+/// Maximum number of characters in a label of a hostname.
+const MAX_LABEL_LENGTH: usize = 63;
+/// How long to wait between checks for hostnames to verify, and between reloads of the verified
+/// hostnames requests are routed by.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Normalize a hostname an organization wants to serve the API on, lowercasing it and removing a
+/// trailing dot. Hostnames must be fully qualified domain names made of letters, digits and
+/// hyphens, with at least two labels. This will return none if the hostname isn't valid, such as
+/// when it is an IP address, has a port or uses a wildcard.
+pub fn normalize_hostname(hostname: &str) -> Option<String> {
+    let hostname = hostname.trim();
+    let hostname = hostname
+        .strip_suffix('.')
+        .unwrap_or(hostname)
+        .to_ascii_lowercase();
+    if hostname.is_empty() || hostname.len() > MAX_HOSTNAME_LENGTH {
+        return None;
+    }
+
+    let labels = hostname.split('.').collect::<Vec<_>>();
+    let is_valid_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= MAX_LABEL_LENGTH
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|character| character.is_ascii_alphanumeric() || character == '-')
+    };
+    // Top-level domains are never all digits, which also rules out IPv4 addresses.
+    let is_numeric = labels
+        .last()
+        .is_some_and(|label| label.chars().all(|character| character.is_ascii_digit()));
+    if labels.len() < 2 || !labels.iter().all(is_valid_label) || is_numeric {
+        return None;
+    }
+
+    Some(hostname)
+}
+
+/// Get the hostname a request was sent to from its "Host" header, without its port. This will
+/// return none if the header doesn't hold a valid hostname, such as when it is an IP address.
+pub fn request_hostname(host: &str) -> Option<String> {
+    let hostname = match host.rsplit_once(':') {
+        Some((hostname, port)) if port.chars().all(|character| character.is_ascii_digit()) => {
+            hostname
+        }
+        _ => host,
+    };
+    normalize_hostname(hostname)
+}
+
+/// Get the hostname of an HTTPS origin, such as "https://example.com". This will return none for
+/// origins using another scheme or a port other than the default, as custom hostnames are only
+/// served over HTTPS.
+pub fn origin_hostname(origin: &str) -> Option<String> {
+    let url = Url::parse(origin).ok()?;
+    if url.scheme() != "https" || url.port().is_some() {
+        return None;
+    }
+    normalize_hostname(url.host_str()?)
+}
+
+/// Get the name of the TXT record an organization proves it owns a hostname with, such as
+/// "_rust-graphql-server-challenge.example.com".
+pub fn challenge_name(hostname: &str) -> String {
+    format!("{}.{}", CHALLENGE_LABEL, hostname)
+}
+
+/// The outcome of looking up a hostname's challenge record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckOutcome {
+    /// Specifies if the hostname is verified after the check.
+    pub is_verified: bool,
+    /// The number of checks in a row that didn't find the challenge record.
+    pub failed_checks: i32,
+    /// How long to wait before checking the hostname again, in seconds.
+    pub next_check_seconds: i64,
+}
+
+/// Decide what a check of a hostname's challenge record means for it, given whether it was
+/// verified before, whether the record was found and the number of checks in a row that didn't
+/// find it before this one. Verified hostnames are checked again daily, and only lose their
+/// verification once their record has been missing for several checks in a row. Hostnames that
+/// aren't verified are checked again with exponential backoff.
+pub fn check_outcome(was_verified: bool, is_found: bool, failed_checks: i32) -> CheckOutcome {
+    if is_found {
+        return CheckOutcome {
+            is_verified: true,
+            failed_checks: 0,
+            next_check_seconds: RECHECK_SECONDS,
+        };
+    }
+
+    let failed_checks = failed_checks.saturating_add(1);
+    if was_verified && failed_checks < MAX_FAILED_RECHECKS {
+        return CheckOutcome {
+            is_verified: true,
+            failed_checks,
+            next_check_seconds: RETRY_BASE_SECONDS,
+        };
+    }
+
+    let doublings = (failed_checks - 1).clamp(0, 16) as u32;
+    CheckOutcome {
+        is_verified: false,
+        failed_checks,
+        next_check_seconds: (RETRY_BASE_SECONDS << doublings).min(MAX_RETRY_SECONDS),
+    }
+}
+
+/// Look up a hostname's TXT challenge record, checking if any of its values is the verification
+/// token. This will return an error if the lookup itself failed, such as when DNS servers can't be
+/// reached, so callers can tell a missing record apart from a failed lookup.
+pub async fn has_challenge_record(hostname: &str, verification_token: &str) -> Result<bool> {
+    let resolver = resolver_from_system_conf().await?;
+    // The trailing dot stops the resolver appending search domains to the name.
+    let name = format!("{}.", challenge_name(hostname));
+    match resolver.txt_lookup(name.as_str()).await {
+        Ok(lookup) => Ok(lookup.iter().any(|record| {
+            let value = record
+                .txt_data()
+                .iter()
+                .flat_map(|part| part.iter().copied())
+                .collect::<Vec<_>>();
+            String::from_utf8_lossy(&value).trim() == verification_token
+        })),
+        Err(error) if validation::is_no_records(&error) => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// The verified custom hostnames of every organization, which requests are routed by. This is
+/// reloaded from the database by the domain worker, so requests don't have to query it. This is
+/// cheap to clone, and clones share the same hostnames.
+#[derive(Debug, Clone, Default)]
+pub struct DomainRegistry(Arc<RwLock<HashMap<String, Uuid>>>);
+
+impl DomainRegistry {
+    /// Replace every verified hostname, each with the ID of the organization it belongs to.
+    pub fn replace(&self, domains: impl IntoIterator<Item = (String, Uuid)>) {
+        *self.0.write().unwrap() = domains.into_iter().collect();
+    }
+
+    /// Stop routing requests sent to a hostname, such as when its organization removes it.
+    pub fn remove(&self, hostname: &str) {
+        self.0.write().unwrap().remove(hostname);
+    }
+
+    /// Find the organization a request was sent to from its "Host" header. This will return none
+    /// if the request wasn't sent to a verified custom hostname.
+    pub fn organization(&self, host: Option<&str>) -> Option<Uuid> {
+        let hostname = request_hostname(host?)?;
+        self.0.read().unwrap().get(&hostname).copied()
+    }
+
+    /// Check if an origin is one of an organization's verified hostnames, served over HTTPS.
+    pub fn is_organization_origin(&self, organization_id: Uuid, origin: &str) -> bool {
+        origin_hostname(origin)
+            .is_some_and(|hostname| self.0.read().unwrap().get(&hostname) == Some(&organization_id))
+    }
+}
+
+/// Periodically check the challenge records of custom hostnames, verifying hostnames whose record
+/// was found and revoking hostnames whose record is gone, then reload the verified hostnames
+/// requests are routed by. Each check is handled as its own request, so what it does can be traced
+/// back to its log lines. This runs until the server stops.
+pub async fn run_domain_worker(state: State) {
+    if !state.config.organization_domains_enabled {
+        log::info!("Organization domains are disabled, so no hostnames will be checked.");
+        return;
+    }
+
+    loop {
+        let executor = Executor::new(state.clone(), request_id(None), None, None);
+        loop {
+            match executor.check_organization_domains().await {
+                Ok(count) if count < CHECK_BATCH_SIZE as usize => break,
+                Ok(_) => {}
+                Err(error) => {
+                    log::error!("Failed to check organization domains: {}", error);
+                    break;
+                }
+            }
+        }
+        match executor.find_verified_domains().await {
+            Ok(domains) => state.domains.replace(domains),
+            Err(error) => log::error!("Failed to load verified organization domains: {}", error),
+        }
+
+        task::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostnames_are_normalized() {
+        assert_eq!(
+            normalize_hostname(" API.Example.com. ").as_deref(),
+            Some("api.example.com")
+        );
+        assert_eq!(
+            normalize_hostname("xn--bcher-kva.example").as_deref(),
+            Some("xn--bcher-kva.example")
+        );
+        for invalid in [
+            "",
+            "localhost",
+            "example.com:443",
+            "*.example.com",
+            "-api.example.com",
+            "api..example.com",
+            "192.168.0.1",
+            "https://example.com",
+            "exa_mple.com",
+        ]
+        .iter()
+        {
+            assert_eq!(normalize_hostname(invalid), None, "{}", invalid);
+        }
+        let long_label = format!("{}.com", "a".repeat(64));
+        assert_eq!(normalize_hostname(&long_label), None);
+    }
+
+    #[test]
+    fn request_hostnames_drop_the_port() {
+        assert_eq!(
+            request_hostname("Example.com:8443").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            request_hostname("example.com").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(request_hostname("[::1]:8080"), None);
+        assert_eq!(request_hostname("127.0.0.1:8080"), None);
+    }
+
+    #[test]
+    fn only_default_https_origins_have_hostnames() {
+        assert_eq!(
+            origin_hostname("https://app.example.com").as_deref(),
+            Some("app.example.com")
+        );
+        assert_eq!(origin_hostname("http://app.example.com"), None);
+        assert_eq!(origin_hostname("https://app.example.com:8443"), None);
+        assert_eq!(origin_hostname("null"), None);
+    }
+
+    #[test]
+    fn requests_are_routed_by_verified_hostname() {
+        let (organization, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let registry = DomainRegistry::default();
+        registry.replace(vec![("api.example.com".to_string(), organization)]);
+
+        assert_eq!(
+            registry.organization(Some("API.example.com:443")),
+            Some(organization)
+        );
+        assert_eq!(registry.organization(Some("example.com")), None);
+        assert_eq!(registry.organization(None), None);
+        assert!(registry.is_organization_origin(organization, "https://api.example.com"));
+        assert!(!registry.is_organization_origin(organization, "http://api.example.com"));
+        assert!(!registry.is_organization_origin(other, "https://api.example.com"));
+
+        registry.replace(Vec::new());
+        assert_eq!(registry.organization(Some("api.example.com")), None);
+    }
+
+    #[test]
+    fn unverified_hostnames_are_checked_with_backoff() {
+        let delays = (0..8)
+            .map(|failed_checks| check_outcome(false, false, failed_checks).next_check_seconds)
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert!(!check_outcome(false, false, 0).is_verified);
+        assert_eq!(
+            check_outcome(false, true, 5),
+            CheckOutcome {
+                is_verified: true,
+                failed_checks: 0,
+                next_check_seconds: RECHECK_SECONDS,
+            }
+        );
+    }
+
+    #[test]
+    fn verified_hostnames_survive_brief_outages() {
+        let first = check_outcome(true, false, 0);
+        assert!(first.is_verified);
+        assert_eq!(first.next_check_seconds, RETRY_BASE_SECONDS);
+        let second = check_outcome(true, false, first.failed_checks);
+        assert!(second.is_verified);
+        let third = check_outcome(true, false, second.failed_checks);
+        assert!(!third.is_verified);
+        assert_eq!(third.failed_checks, MAX_FAILED_RECHECKS);
+    }
+
+    #[test]
+    fn challenge_records_are_prefixed() {
+        assert_eq!(
+            challenge_name("example.com"),
+            "_rust-graphql-server-challenge.example.com"
+        );
+    }
+}
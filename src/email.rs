@@ -0,0 +1,8 @@
+#[cfg(feature = "email")]
+mod mailer;
+pub mod outbox;
+pub mod settings;
+pub mod templates;
+
+#[cfg(feature = "email")]
+pub use mailer::{EntityRefId, Mailer};
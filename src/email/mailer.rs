@@ -0,0 +1,645 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_lock::Semaphore;
+use async_std::channel::{self, Receiver, Sender};
+use async_std::{future, task};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::stream::{self, StreamExt};
+use lettre::address::Envelope;
+use lettre::message::dkim::{DkimConfig, DkimSigningAlgorithm, DkimSigningKey};
+use lettre::message::header::{Header, HeaderName, HeaderValue};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::PoolConfig;
+use lettre::{Address, AsyncSmtpTransport, AsyncStd1Executor, AsyncTransport, Message};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, PgPool};
+use tide::log;
+use uuid::Uuid;
+
+use crate::alerts::AlertCounters;
+use crate::config::Config;
+use crate::email::outbox::{OutboxEmail, OutboxStatus};
+use crate::email::settings::{SettingsCipher, SmtpSettings, IMPLICIT_TLS_PORT};
+use crate::metrics::InstrumentedConnection;
+
+/// Redis key of the sorted set holding queued emails, scored by when they should next be sent.
+const QUEUED_EMAILS_KEY: &str = "email/queue";
+/// Redis key of the list holding emails that couldn't be delivered, newest first.
+const DEAD_EMAILS_KEY: &str = "email/dead";
+/// Maximum number of emails kept in the dead letter list. Older emails are dropped.
+const MAX_DEAD_EMAILS: isize = 1000;
+/// How long the queue worker waits for new emails before checking for retries that are due.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long an email is deferred for when a send limit is reached.
+const DEFERRAL_SECONDS: i64 = 60;
+/// How long to wait before retrying an email after its first failed delivery. The wait doubles
+/// with each failed attempt.
+const RETRY_BASE_SECONDS: i64 = 30;
+/// Maximum time to wait before retrying an email.
+const MAX_RETRY_SECONDS: i64 = 60 * 60;
+/// Maximum number of queued emails to attempt to send at a time.
+const QUEUED_EMAILS_BATCH_SIZE: isize = 100;
+/// How long to wait for an SMTP server to respond before giving up on an email.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum number of pooled connections to each organization's own SMTP server.
+const MAX_ORGANIZATION_CONNECTIONS: u32 = 2;
+
+/// An SMTP transport sending emails through a pool of reused connections.
+type Transport = AsyncSmtpTransport<AsyncStd1Executor>;
+/// Transports of organizations' own SMTP servers by organization ID, with when their settings were
+/// last updated.
+type OrganizationTransports = HashMap<Uuid, (DateTime<Utc>, Transport)>;
+
+/// Limits on how many emails can be sent, protecting the sender's reputation and respecting the
+/// limits of the email provider. A limit of zero disables it.
+#[derive(Debug, Clone, Copy)]
+struct SendLimits {
+    per_recipient_per_hour: u32,
+    per_minute: u32,
+    per_day: u32,
+}
+
+/// An email waiting in the queue to be sent. The message is stored fully formatted and signed, so
+/// it can be sent as-is by any server.
+#[derive(Serialize, Deserialize)]
+struct QueuedEmail {
+    // Distinguishes otherwise identical emails in the queue.
+    id: Uuid,
+    from: Option<String>,
+    to: Vec<String>,
+    message: String,
+    // The number of failed attempts to deliver the email.
+    #[serde(default)]
+    attempts: u32,
+    // The organization the email is sent on behalf of, whose own SMTP server it is sent through
+    // if it has one.
+    #[serde(default)]
+    organization_id: Option<Uuid>,
+}
+
+impl QueuedEmail {
+    /// Describe the email as it is shown to admins, with its body redacted.
+    fn to_outbox(&self, status: OutboxStatus, send_at: Option<i64>) -> Result<OutboxEmail> {
+        Ok(OutboxEmail::new(
+            self.id,
+            status,
+            self.from.clone(),
+            self.to.clone(),
+            self.attempts,
+            send_at.map(|send_at| Utc.timestamp(send_at, 0)),
+            &base64::decode(&self.message)?,
+        ))
+    }
+
+    /// Get the envelope the email is delivered with.
+    fn envelope(&self) -> Result<Envelope> {
+        Ok(Envelope::new(
+            self.from.as_ref().map(|from| from.parse()).transpose()?,
+            self.to
+                .iter()
+                .map(|to| to.parse())
+                .collect::<Result<Vec<Address>, _>>()?,
+        )?)
+    }
+}
+
+/// Queues emails in Redis and sends them in the background through a pool of reused SMTP
+/// connections. This is cheap to clone, and clones share the same connections and queue.
+#[derive(Clone)]
+pub struct Mailer {
+    transport: Transport,
+    db: PgPool,
+    redis: InstrumentedConnection,
+    limits: SendLimits,
+    max_attempts: u32,
+    // Wakes the queue worker when an email is queued. Wakeups are coalesced, so this never holds
+    // more than one.
+    wake: (Sender<()>, Receiver<()>),
+    // Set when the server is shutting down, so the queue worker stops after sending what is due.
+    stopping: Arc<AtomicBool>,
+    // Limits how many emails are sent at once.
+    sends: Arc<Semaphore>,
+    // Signs outgoing emails if DKIM is configured.
+    dkim: Option<Arc<DkimConfig>>,
+    // Counts delivery attempts, so operators can be alerted when many of them fail.
+    alerts: AlertCounters,
+    // Decrypts the passwords of organizations' own SMTP servers, if a key is configured.
+    cipher: Option<SettingsCipher>,
+    // Transports of organizations' own SMTP servers, with when their settings were last updated,
+    // so connections are reused until the settings change.
+    organization_transports: Arc<Mutex<OrganizationTransports>>,
+}
+
+impl Mailer {
+    /// Create a mailer for the SMTP server defined by the server configuration. Connections are
+    /// only opened once emails are sent. Organizations' own SMTP servers are looked up in the
+    /// Postgres database, send limits are tracked in the Redis database, and delivery attempts are
+    /// counted by the alert counters.
+    pub fn new(
+        config: &Config,
+        db: PgPool,
+        redis: InstrumentedConnection,
+        alerts: AlertCounters,
+    ) -> Result<Self> {
+        let Config {
+            email_smtp,
+            email_smtp_port,
+            email_smtp_use_starttls,
+            email_smtp_max_connections,
+            email_max_concurrent_sends,
+            email_recipient_hourly_limit,
+            email_minute_limit,
+            email_daily_quota,
+            email_max_delivery_attempts,
+            email_verification_email_address,
+            email_verification_email_password,
+            email_settings_key,
+            ..
+        } = config;
+
+        let relay = if *email_smtp_use_starttls {
+            Transport::starttls_relay(email_smtp)?
+        } else {
+            Transport::relay(email_smtp)?
+        };
+
+        let transport = relay
+            .port(*email_smtp_port)
+            .credentials(Credentials::new(
+                email_verification_email_address.clone(),
+                email_verification_email_password.clone(),
+            ))
+            .timeout(Some(SMTP_TIMEOUT))
+            .pool_config(PoolConfig::new().max_size(*email_smtp_max_connections))
+            .build();
+
+        Ok(Mailer {
+            transport,
+            db,
+            redis,
+            limits: SendLimits {
+                per_recipient_per_hour: *email_recipient_hourly_limit,
+                per_minute: *email_minute_limit,
+                per_day: *email_daily_quota,
+            },
+            max_attempts: *email_max_delivery_attempts,
+            wake: channel::bounded(1),
+            stopping: Arc::new(AtomicBool::new(false)),
+            sends: Arc::new(Semaphore::new(*email_max_concurrent_sends)),
+            dkim: load_dkim_config(config)?.map(Arc::new),
+            alerts,
+            cipher: email_settings_key.clone().map(SettingsCipher::new),
+            organization_transports: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Queue an email to be sent in the background by the queue worker, so callers don't wait on
+    /// the SMTP server. Emails sent on behalf of an organization are sent through its own SMTP
+    /// server if it has one, as of when they are delivered. Other emails are signed first if DKIM
+    /// is configured, as the server's key can't sign for an organization's domain. This only
+    /// returns an error if the email couldn't be queued.
+    pub async fn send(&self, mut message: Message, organization_id: Option<Uuid>) -> Result<()> {
+        if let (Some(dkim), None) = (&self.dkim, organization_id) {
+            message.sign(dkim);
+        }
+
+        let envelope = message.envelope();
+        let email = QueuedEmail {
+            id: Uuid::new_v4(),
+            from: envelope.from().map(ToString::to_string),
+            to: envelope.to().iter().map(ToString::to_string).collect(),
+            message: base64::encode(message.formatted()),
+            attempts: 0,
+            organization_id,
+        };
+        self.enqueue(&email, Utc::now().timestamp()).await?;
+        // If a wakeup is already pending, the worker will pick this email up with it.
+        let _ = self.wake.0.try_send(());
+
+        Ok(())
+    }
+
+    /// Send queued emails as they are queued and once their retries are due. This runs until the
+    /// worker is stopped, after sending every email that is due by then.
+    pub async fn run_queue_worker(self) {
+        loop {
+            let _ = future::timeout(QUEUE_POLL_INTERVAL, self.wake.1.recv()).await;
+            let result = self.send_queued().await;
+            if let Err(error) = &result {
+                log::error!("Failed to send queued emails: {}", error);
+            }
+            if self.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+            if result.is_err() {
+                task::sleep(QUEUE_POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    /// Ask the queue worker to stop. It sends every email that is due first, rather than stopping
+    /// partway through sending one.
+    pub fn stop_queue_worker(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        let _ = self.wake.0.try_send(());
+    }
+
+    /// List a page of the emails in part of the outbox. Queued emails are listed in the order they
+    /// will be sent, and dead emails newest first.
+    pub async fn outbox(
+        &self,
+        status: OutboxStatus,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<OutboxEmail>> {
+        let (start, stop) = (offset as isize, (offset + limit - 1) as isize);
+        let mut redis = self.redis.clone();
+        match status {
+            OutboxStatus::Queued => redis
+                .zrange_withscores::<&str, Vec<(String, i64)>>(QUEUED_EMAILS_KEY, start, stop)
+                .await?
+                .into_iter()
+                .map(|(entry, send_at)| {
+                    let email: QueuedEmail = serde_json::from_str(&entry)?;
+                    email.to_outbox(status, Some(send_at))
+                })
+                .collect(),
+            OutboxStatus::Dead => redis
+                .lrange::<&str, Vec<String>>(DEAD_EMAILS_KEY, start, stop)
+                .await?
+                .into_iter()
+                .map(|entry| {
+                    let email: QueuedEmail = serde_json::from_str(&entry)?;
+                    email.to_outbox(status, None)
+                })
+                .collect(),
+        }
+    }
+
+    /// Move an email from the dead letter list back to the queue, to be sent right away with a
+    /// fresh set of attempts. Queued emails are already retried on their own. This will return the
+    /// email as queued, or none if no dead email has the ID, such as when it was already retried.
+    pub async fn retry(&self, id: Uuid) -> Result<Option<OutboxEmail>> {
+        let mut redis = self.redis.clone();
+        let entries = redis
+            .lrange::<&str, Vec<String>>(DEAD_EMAILS_KEY, 0, -1)
+            .await?;
+        let found = entries.into_iter().find_map(|entry| {
+            let email = serde_json::from_str::<QueuedEmail>(&entry).ok()?;
+            (email.id == id).then_some((entry, email))
+        });
+        let (entry, mut email) = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        // Only one of several admins retrying the same email removes it, so it is queued once.
+        let removed = redis
+            .lrem::<&str, &str, u32>(DEAD_EMAILS_KEY, 1, &entry)
+            .await?;
+        if removed == 0 {
+            return Ok(None);
+        }
+
+        email.attempts = 0;
+        let send_at = Utc::now().timestamp();
+        self.enqueue(&email, send_at).await?;
+        let _ = self.wake.0.try_send(());
+
+        Ok(Some(email.to_outbox(OutboxStatus::Queued, Some(send_at))?))
+    }
+
+    /// Delete every email in the dead letter list. This will return the number of emails deleted.
+    pub async fn purge_dead(&self) -> Result<u32> {
+        let (count,) = redis::pipe()
+            .atomic()
+            .llen(DEAD_EMAILS_KEY)
+            .del(DEAD_EMAILS_KEY)
+            .ignore()
+            .query_async::<_, (u32,)>(&mut self.redis.clone())
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Attempt to send every queued email that is due, a batch at a time.
+    async fn send_queued(&self) -> Result<()> {
+        loop {
+            let entries = self
+                .redis
+                .clone()
+                .zrangebyscore_limit::<&str, &str, i64, Vec<String>>(
+                    QUEUED_EMAILS_KEY,
+                    "-inf",
+                    Utc::now().timestamp(),
+                    0,
+                    QUEUED_EMAILS_BATCH_SIZE,
+                )
+                .await?;
+            let is_last_batch = (entries.len() as isize) < QUEUED_EMAILS_BATCH_SIZE;
+
+            stream::iter(entries)
+                .for_each_concurrent(None, |entry| async move {
+                    if let Err(error) = self.send_entry(&entry).await {
+                        log::error!("Failed to send queued email: {}", error);
+                    }
+                })
+                .await;
+
+            if is_last_batch {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Attempt to send a queued email. Emails that would exceed a send limit are deferred, and
+    /// emails that fail to be delivered are retried with exponential backoff until they run out of
+    /// attempts or are permanently rejected, at which point they are moved to the dead letter list.
+    async fn send_entry(&self, entry: &str) -> Result<()> {
+        // Claim the email first, so it isn't sent twice if several servers are running.
+        let claimed = self
+            .redis
+            .clone()
+            .zrem::<&str, &str, u32>(QUEUED_EMAILS_KEY, entry)
+            .await?;
+        if claimed == 0 {
+            return Ok(());
+        }
+
+        let mut email: QueuedEmail = serde_json::from_str(entry)?;
+        let envelope = email.envelope()?;
+        let raw = base64::decode(&email.message)?;
+
+        if !self.reserve(&envelope).await? {
+            log::warn!("Send limit reached. Deferring email {}.", email.id);
+            return self
+                .enqueue(&email, Utc::now().timestamp() + DEFERRAL_SECONDS)
+                .await;
+        }
+
+        let result = self.deliver(email.organization_id, &envelope, &raw).await;
+        self.alerts.record_email_delivery(result.is_ok());
+        let error = match result {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+
+        email.attempts += 1;
+        let is_permanent = error
+            .downcast_ref::<lettre::transport::smtp::Error>()
+            .is_some_and(|error| error.is_permanent());
+        if is_permanent || email.attempts >= self.max_attempts {
+            log::error!(
+                "Failed to deliver email {} after {} attempts, moving it to the dead letter list: {}",
+                email.id,
+                email.attempts,
+                error
+            );
+            let mut redis = self.redis.clone();
+            redis
+                .lpush::<&str, String, ()>(DEAD_EMAILS_KEY, serde_json::to_string(&email)?)
+                .await?;
+            redis
+                .ltrim::<&str, ()>(DEAD_EMAILS_KEY, 0, MAX_DEAD_EMAILS - 1)
+                .await?;
+            return Ok(());
+        }
+
+        let delay = (RETRY_BASE_SECONDS << (email.attempts - 1).min(16)).min(MAX_RETRY_SECONDS);
+        log::warn!(
+            "Failed to deliver email {}, retrying in {} seconds: {}",
+            email.id,
+            delay,
+            error
+        );
+        self.enqueue(&email, Utc::now().timestamp() + delay).await
+    }
+
+    /// Send a formatted email over SMTP, through the SMTP server of the organization it is sent on
+    /// behalf of if it has one, waiting for a free slot if the maximum number of emails are already
+    /// being sent.
+    async fn deliver(
+        &self,
+        organization_id: Option<Uuid>,
+        envelope: &Envelope,
+        raw: &[u8],
+    ) -> Result<()> {
+        let transport = match organization_id {
+            Some(organization_id) => self.organization_transport(organization_id).await?,
+            None => None,
+        };
+        let _slot = self.sends.acquire().await;
+        transport
+            .as_ref()
+            .unwrap_or(&self.transport)
+            .send_raw(envelope, raw)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the transport of an organization's own SMTP server, reusing its connections until its
+    /// settings change. This will return none if the organization doesn't have its own SMTP
+    /// server, or an error if its password can't be decrypted.
+    async fn organization_transport(&self, organization_id: Uuid) -> Result<Option<Transport>> {
+        let settings = query!(
+            "
+            SELECT updated_at, smtp_host, smtp_port, smtp_username, smtp_password_encrypted
+            FROM organization_email_settings
+            WHERE organization_id = $1
+            ",
+            organization_id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        let (updated_at, host, port, username, password) = match settings {
+            Some(settings) => match (
+                settings.smtp_host,
+                settings.smtp_port,
+                settings.smtp_username,
+                settings.smtp_password_encrypted,
+            ) {
+                (Some(host), Some(port), Some(username), Some(password)) => {
+                    (settings.updated_at, host, port, username, password)
+                }
+                _ => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+
+        let mut transports = self.organization_transports.lock().unwrap();
+        if let Some((cached_at, transport)) = transports.get(&organization_id) {
+            if *cached_at == updated_at {
+                return Ok(Some(transport.clone()));
+            }
+        }
+
+        let cipher = self.cipher.as_ref().ok_or_else(|| {
+            anyhow!(
+                "No email settings key is configured to decrypt the SMTP password of organization {}.",
+                organization_id
+            )
+        })?;
+        let transport = organization_transport(&SmtpSettings {
+            host,
+            port: u16::try_from(port)?,
+            username,
+            password: cipher.decrypt(organization_id, &password)?,
+        })?;
+        transports.insert(organization_id, (updated_at, transport.clone()));
+
+        Ok(Some(transport))
+    }
+
+    /// Add an email to the queue, to be sent once the specified Unix timestamp has passed.
+    async fn enqueue(&self, email: &QueuedEmail, send_at: i64) -> Result<()> {
+        self.redis
+            .clone()
+            .zadd::<&str, i64, String, ()>(
+                QUEUED_EMAILS_KEY,
+                serde_json::to_string(email)?,
+                send_at,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Count an email against every send limit it is subject to. This will return false, without
+    /// counting the email, if sending it would exceed any of the limits.
+    async fn reserve(&self, envelope: &Envelope) -> Result<bool> {
+        let now = Utc::now();
+        let mut counters = vec![
+            (
+                format!("email/sent/minute/{}", now.format("%Y%m%d%H%M")),
+                self.limits.per_minute,
+                60,
+            ),
+            (
+                format!("email/sent/day/{}", now.format("%Y%m%d")),
+                self.limits.per_day,
+                24 * 60 * 60,
+            ),
+        ];
+        for recipient in envelope.to() {
+            counters.push((
+                format!(
+                    "email/sent/recipient/{}/{}",
+                    recipient,
+                    now.format("%Y%m%d%H")
+                ),
+                self.limits.per_recipient_per_hour,
+                60 * 60,
+            ));
+        }
+
+        let mut redis = self.redis.clone();
+        let mut counted = Vec::new();
+        for (key, limit, window_seconds) in counters {
+            if limit == 0 {
+                continue;
+            }
+
+            let count = redis.incr::<&str, u32, u32>(&key, 1).await?;
+            if count == 1 {
+                redis.expire::<&str, ()>(&key, window_seconds).await?;
+            }
+            counted.push(key);
+
+            if count > limit {
+                // Uncount the email from every limit it was counted against.
+                for key in counted {
+                    redis.decr::<String, u32, ()>(key, 1).await?;
+                }
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Build a transport sending emails through an organization's own SMTP server. Port 465 uses TLS
+/// from the start of the connection, and other ports are upgraded to TLS with STARTTLS, so
+/// credentials are never sent in plain text.
+fn organization_transport(settings: &SmtpSettings) -> Result<Transport> {
+    let relay = if settings.port == IMPLICIT_TLS_PORT {
+        Transport::relay(&settings.host)?
+    } else {
+        Transport::starttls_relay(&settings.host)?
+    };
+
+    Ok(relay
+        .port(settings.port)
+        .credentials(Credentials::new(
+            settings.username.clone(),
+            settings.password.clone(),
+        ))
+        .timeout(Some(SMTP_TIMEOUT))
+        .pool_config(PoolConfig::new().max_size(MAX_ORGANIZATION_CONNECTIONS))
+        .build())
+}
+
+/// Load the DKIM signing configuration for outgoing emails. This will return none if DKIM is not
+/// configured, or an error if it is only partly configured or the private key is invalid.
+fn load_dkim_config(config: &Config) -> Result<Option<DkimConfig>> {
+    let Config {
+        email_dkim_selector,
+        email_dkim_domain,
+        email_dkim_private_key_path,
+        email_verification_email_address,
+        ..
+    } = config;
+
+    let selector = match email_dkim_selector {
+        Some(selector) => selector.clone(),
+        None => return Ok(None),
+    };
+    let private_key_path = email_dkim_private_key_path
+        .as_ref()
+        .ok_or_else(|| anyhow!("A DKIM private key path must be set to sign emails."))?;
+    let domain = match email_dkim_domain {
+        Some(domain) => domain.clone(),
+        None => email_verification_email_address
+            .rsplit('@')
+            .next()
+            .unwrap_or_default()
+            .into(),
+    };
+
+    let private_key = std::fs::read_to_string(private_key_path)?;
+    let private_key = DkimSigningKey::new(&private_key, DkimSigningAlgorithm::Rsa)
+        .map_err(|error| anyhow!("Invalid DKIM private key: {:?}", error))?;
+
+    Ok(Some(DkimConfig::default_config(
+        selector,
+        domain,
+        private_key,
+    )))
+}
+
+/// The "X-Entity-Ref-ID" email header. This holds the ID of the request that caused an email to be
+/// sent so delivery reports can be correlated with server logs. Some email clients also use it to
+/// avoid threading unrelated emails with the same subject together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityRefId(pub String);
+
+impl Header for EntityRefId {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("X-Entity-Ref-ID")
+    }
+
+    fn parse(value: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(EntityRefId(value.into()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
@@ -0,0 +1,312 @@
+use chrono::{DateTime, Utc};
+use juniper::{graphql_object, GraphQLEnum};
+use uuid::Uuid;
+
+/// Default number of emails returned when listing the outbox.
+pub const DEFAULT_PAGE_SIZE: i32 = 50;
+/// Maximum number of emails returned when listing the outbox.
+pub const MAX_PAGE_SIZE: i32 = 100;
+/// Headers of an email shown to admins. Other headers and every body part are redacted, as bodies
+/// hold verification codes and password reset links.
+const VISIBLE_HEADERS: &[&str] = &[
+    "Date",
+    "From",
+    "To",
+    "Subject",
+    "Message-ID",
+    "X-Entity-Ref-ID",
+    "Content-Type",
+];
+
+/// Which part of the outbox an email is in.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "Which part of the outbox an email is in.")]
+pub enum OutboxStatus {
+    #[graphql(
+        description = "The email is waiting to be sent, or to be retried after failing to be
+        delivered."
+    )]
+    Queued,
+    #[graphql(
+        description = "The email couldn't be delivered, and was moved to the dead letter list."
+    )]
+    Dead,
+}
+
+/// An email in the outbox, with its body redacted so it can be shown to admins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEmail {
+    /// The ID distinguishing the email in the outbox.
+    pub id: Uuid,
+    /// Which part of the outbox the email is in.
+    pub status: OutboxStatus,
+    /// The address the email is sent from, as given in its envelope.
+    pub from: Option<String>,
+    /// The addresses the email is sent to, as given in its envelope.
+    pub to: Vec<String>,
+    /// The number of failed attempts to deliver the email.
+    pub attempts: u32,
+    /// When the email will next be sent. This will be none for dead emails.
+    pub send_at: Option<DateTime<Utc>>,
+    /// The visible headers of the email, unfolded.
+    pub headers: Vec<(String, String)>,
+    /// The number of bytes in the redacted body of the email.
+    pub body_bytes: usize,
+}
+
+impl OutboxEmail {
+    /// Describe an email in the outbox from its formatted message, keeping only its visible
+    /// headers.
+    pub fn new(
+        id: Uuid,
+        status: OutboxStatus,
+        from: Option<String>,
+        to: Vec<String>,
+        attempts: u32,
+        send_at: Option<DateTime<Utc>>,
+        message: &[u8],
+    ) -> Self {
+        let (headers, body_bytes) = redact(message);
+        OutboxEmail {
+            id,
+            status,
+            from,
+            to,
+            attempts,
+            send_at,
+            headers,
+            body_bytes,
+        }
+    }
+
+    /// Get the value of a visible header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[graphql_object(description = "An email in the outbox, with its body redacted.")]
+impl OutboxEmail {
+    #[graphql(description = "The ID distinguishing the email in the outbox.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Which part of the outbox the email is in.")]
+    pub fn status(&self) -> OutboxStatus {
+        self.status
+    }
+
+    #[graphql(description = "The address the email is sent from.")]
+    pub fn from(&self) -> &Option<String> {
+        &self.from
+    }
+
+    #[graphql(description = "The addresses the email is sent to.")]
+    pub fn to(&self) -> &[String] {
+        &self.to
+    }
+
+    #[graphql(description = "The subject of the email, if it has one.")]
+    pub fn subject(&self) -> Option<String> {
+        self.header("Subject").map(decode_header)
+    }
+
+    #[graphql(description = "The number of failed attempts to deliver the email.")]
+    pub fn attempts(&self) -> i32 {
+        self.attempts.min(i32::MAX as u32) as i32
+    }
+
+    #[graphql(
+        description = "Date when the email will next be sent. This will be null for dead
+        emails."
+    )]
+    pub fn send_at(&self) -> &Option<DateTime<Utc>> {
+        &self.send_at
+    }
+
+    #[graphql(
+        description = "The email as it is sent, with only its date, addresses, subject, IDs and
+        content type headers. Other headers and the body are redacted, as bodies hold
+        verification codes and password reset links."
+    )]
+    pub fn payload(&self) -> String {
+        payload(&self.headers, self.body_bytes)
+    }
+}
+
+/// Format the redacted payload of an email from its visible headers and the size of its body.
+pub fn payload(headers: &[(String, String)], body_bytes: usize) -> String {
+    let mut payload = String::new();
+    for (name, value) in headers {
+        payload.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    payload.push_str(&format!("\r\n[{} bytes redacted]", body_bytes));
+    payload
+}
+
+/// Split a formatted email into its visible headers, unfolded, and the number of bytes in the rest
+/// of the message, which is redacted.
+fn redact(message: &[u8]) -> (Vec<(String, String)>, usize) {
+    let message = String::from_utf8_lossy(message);
+    let (head, body) = match message.find("\r\n\r\n") {
+        Some(end) => (&message[..end], &message[end + 4..]),
+        None => (&message[..], ""),
+    };
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut is_visible = false;
+    for line in head.split("\r\n") {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            // Folded lines continue the header before them.
+            if let (true, Some((_, value))) = (is_visible, headers.last_mut()) {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => continue,
+        };
+        is_visible = VISIBLE_HEADERS
+            .iter()
+            .any(|visible| visible.eq_ignore_ascii_case(name));
+        if is_visible {
+            headers.push((name.into(), value.into()));
+        }
+    }
+
+    (headers, body.len())
+}
+
+/// Decode the encoded words a header uses for text that isn't ASCII, such as
+/// "=?utf-8?b?SGVsbG8=?=". Words encoded with a charset other than UTF-8 are left as they are.
+pub fn decode_header(value: &str) -> String {
+    if !value.contains("=?") {
+        return value.into();
+    }
+
+    let mut decoded = String::new();
+    let mut was_encoded = false;
+    for word in value.split_whitespace() {
+        match decode_word(word) {
+            Some(text) => {
+                // Whitespace between encoded words isn't part of the text.
+                if !was_encoded && !decoded.is_empty() {
+                    decoded.push(' ');
+                }
+                decoded.push_str(&text);
+                was_encoded = true;
+            }
+            None => {
+                if !decoded.is_empty() {
+                    decoded.push(' ');
+                }
+                decoded.push_str(word);
+                was_encoded = false;
+            }
+        }
+    }
+
+    decoded
+}
+
+/// Decode a single encoded word. This will return none if the word isn't encoded, or isn't
+/// encoded as UTF-8.
+fn decode_word(word: &str) -> Option<String> {
+    let word = word.strip_prefix("=?")?.strip_suffix("?=")?;
+    let mut parts = word.splitn(3, '?');
+    let (charset, encoding, text) = (parts.next()?, parts.next()?, parts.next()?);
+    if !charset.eq_ignore_ascii_case("utf-8") && !charset.eq_ignore_ascii_case("us-ascii") {
+        return None;
+    }
+
+    let bytes = match encoding {
+        "B" | "b" => base64::decode(text).ok()?,
+        "Q" | "q" => {
+            let mut bytes = Vec::new();
+            let mut characters = text.bytes();
+            while let Some(character) = characters.next() {
+                match character {
+                    b'_' => bytes.push(b' '),
+                    b'=' => {
+                        let hex = [characters.next()?, characters.next()?];
+                        let hex = std::str::from_utf8(&hex).ok()?;
+                        bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                    }
+                    _ => bytes.push(character),
+                }
+            }
+            bytes
+        }
+        _ => return None,
+    };
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = "From: Server <server@example.com>\r\n\
+        To: Alice <alice@example.com>\r\n\
+        Subject: =?utf-8?b?UmVzZXQgeW91cg==?=\r\n \
+        =?utf-8?q?_password_=E2=9C=93?=\r\n\
+        DKIM-Signature: v=1; a=rsa-sha256;\r\n b=secret\r\n\
+        X-Entity-Ref-ID: request-1\r\n\
+        \r\n\
+        Reset your password at https://example.com/reset?token=secret";
+
+    fn email() -> OutboxEmail {
+        OutboxEmail::new(
+            Uuid::nil(),
+            OutboxStatus::Dead,
+            None,
+            Vec::new(),
+            3,
+            None,
+            MESSAGE.as_bytes(),
+        )
+    }
+
+    #[test]
+    fn bodies_and_hidden_headers_are_redacted() {
+        let email = email();
+        let names = email
+            .headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["From", "To", "Subject", "X-Entity-Ref-ID"]);
+
+        let payload = payload(&email.headers, email.body_bytes);
+        assert!(!payload.contains("secret"));
+        assert!(payload.ends_with("[61 bytes redacted]"));
+    }
+
+    #[test]
+    fn folded_headers_are_unfolded() {
+        let subject = email().header("subject").map(str::to_string);
+        let expected = "=?utf-8?b?UmVzZXQgeW91cg==?= =?utf-8?q?_password_=E2=9C=93?=";
+        assert_eq!(subject.as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn encoded_words_are_decoded() {
+        let subject = email().header("Subject").map(decode_header);
+        assert_eq!(subject.as_deref(), Some("Reset your password ✓"));
+        assert_eq!(decode_header("Plain subject"), "Plain subject");
+        assert_eq!(
+            decode_header("Hi =?utf-8?q?Z=C3=BCrich?= team"),
+            "Hi Zürich team"
+        );
+        let unknown = "=?iso-8859-1?q?caf=E9?=";
+        assert_eq!(decode_header(unknown), unknown);
+    }
+}
@@ -0,0 +1,483 @@
+use std::convert::TryFrom;
+use std::fmt::{Debug, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+use juniper::GraphQLInputObject;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use tide::http::Url;
+use uuid::Uuid;
+
+use crate::models::OrganizationEmailSettings;
+use crate::schema::InvalidInput;
+use crate::validation;
+
+/// Color emails are branded with when an organization hasn't chosen one.
+pub const DEFAULT_BRAND_COLOR: &str = "#18181b";
+/// Maximum number of characters in the name an organization's emails are sent from.
+pub const MAX_FROM_NAME_LENGTH: usize = 64;
+/// Maximum number of characters in the URL of an organization's logo.
+pub const MAX_LOGO_URL_LENGTH: usize = 2048;
+/// Maximum number of characters in an SMTP host or username.
+pub const MAX_SMTP_FIELD_LENGTH: usize = 255;
+/// Port of SMTP servers that expect TLS from the start of the connection, rather than upgrading to
+/// it with STARTTLS.
+pub const IMPLICIT_TLS_PORT: u16 = 465;
+
+/// A 256-bit key secrets in organizations' email settings are encrypted with, parsed from base64.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SettingsKey([u8; 32]);
+
+impl FromStr for SettingsKey {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        let bytes = base64::decode(value)?;
+        let key = <[u8; 32]>::try_from(bytes.as_slice())
+            .map_err(|_| anyhow!("Email settings keys must be 32 bytes long."))?;
+        Ok(SettingsKey(key))
+    }
+}
+
+impl Debug for SettingsKey {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "SettingsKey(..)")
+    }
+}
+
+/// Encrypts secrets in organizations' email settings, such as SMTP passwords, so they aren't
+/// stored in plain text. Secrets are encrypted with AES-256-GCM under a random nonce, and bound to
+/// their organization, so they can't be copied into another organization's settings.
+#[derive(Debug, Clone)]
+pub struct SettingsCipher {
+    key: SettingsKey,
+}
+
+impl SettingsCipher {
+    /// Create a cipher encrypting secrets with a key.
+    pub fn new(key: SettingsKey) -> Self {
+        SettingsCipher { key }
+    }
+
+    /// Get the AEAD key secrets are encrypted with.
+    fn key(&self) -> LessSafeKey {
+        // The key is always the right length, so this can't fail.
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &self.key.0).expect("Invalid AES key."))
+    }
+
+    /// Encrypt a secret of an organization. The nonce it is encrypted with is prepended to it.
+    pub fn encrypt(&self, organization_id: Uuid, secret: &str) -> Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("Failed to generate a nonce."))?;
+
+        let mut sealed = secret.as_bytes().to_vec();
+        self.key()
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(organization_id.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow!("Failed to encrypt a secret."))?;
+        sealed.splice(0..0, nonce);
+        Ok(sealed)
+    }
+
+    /// Decrypt a secret of an organization. This will return an error if the secret was encrypted
+    /// with another key or for another organization, or was tampered with.
+    pub fn decrypt(&self, organization_id: Uuid, sealed: &[u8]) -> Result<String> {
+        if sealed.len() < NONCE_LEN {
+            return Err(anyhow!("Encrypted secret is too short."));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| anyhow!("Invalid nonce for an encrypted secret."))?;
+        let mut ciphertext = ciphertext.to_vec();
+        let secret = self
+            .key()
+            .open_in_place(
+                nonce,
+                Aad::from(organization_id.as_bytes()),
+                &mut ciphertext,
+            )
+            .map_err(|_| {
+                anyhow!(
+                    "Failed to decrypt a secret of organization {}.",
+                    organization_id
+                )
+            })?;
+        Ok(String::from_utf8(secret.to_vec())?)
+    }
+}
+
+/// Branding shown in the HTML part of emails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailBranding {
+    /// The URL of a logo shown above the email, if any.
+    pub logo_url: Option<String>,
+    /// The color of the bar along the top of the email, as a hex code such as "#0055ff".
+    pub color: String,
+}
+
+impl Default for EmailBranding {
+    fn default() -> Self {
+        EmailBranding {
+            logo_url: None,
+            color: DEFAULT_BRAND_COLOR.into(),
+        }
+    }
+}
+
+/// The SMTP server an organization's emails are sent through, with its password decrypted.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SmtpSettings {
+    /// The host name of the SMTP server.
+    pub host: String,
+    /// The port of the SMTP server. Connections to port 465 use TLS from the start, and others
+    /// are upgraded to TLS with STARTTLS.
+    pub port: u16,
+    /// The username to log in to the SMTP server with.
+    pub username: String,
+    /// The password or provider API key to log in to the SMTP server with.
+    pub password: String,
+}
+
+/// New email settings for an organization. Settings that aren't set fall back to the server's.
+#[derive(GraphQLInputObject, Debug, Clone, Default)]
+#[graphql(
+    description = "New email settings for an organization. Settings that aren't set fall back to
+    the server's."
+)]
+pub struct EmailSettingsInput {
+    #[graphql(description = "The display name emails are sent from, up to 64 characters.")]
+    pub from_name: Option<String>,
+    #[graphql(
+        description = "The address emails are sent from. The SMTP server must be allowed to send
+        from it."
+    )]
+    pub from_address: Option<String>,
+    #[graphql(
+        description = "The host name of the SMTP server emails are sent through. The port,
+        username and password must be set along with it."
+    )]
+    pub smtp_host: Option<String>,
+    #[graphql(
+        description = "The port of the SMTP server. Port 465 uses TLS from the start, and other
+        ports are upgraded to TLS with STARTTLS."
+    )]
+    pub smtp_port: Option<i32>,
+    #[graphql(description = "The username to log in to the SMTP server with.")]
+    pub smtp_username: Option<String>,
+    #[graphql(
+        description = "The password to log in to the SMTP server with. Email providers that take
+        an API key over SMTP, such as SendGrid or Postmark, take it here. This is stored
+        encrypted and can't be read back. Leave it null to keep the current password when
+        changing other SMTP settings."
+    )]
+    pub smtp_password: Option<String>,
+    #[graphql(description = "The HTTPS URL of a logo shown above emails.")]
+    pub logo_url: Option<String>,
+    #[graphql(
+        description = "The color of the bar along the top of emails, as a hex code such as
+        \"#0055ff\"."
+    )]
+    pub brand_color: Option<String>,
+}
+
+/// Validate new email settings for an organization, given its current settings and whether a key
+/// is configured to encrypt SMTP passwords with. An SMTP server must be set along with its port,
+/// username and password, though the current password can be kept if the host and username don't
+/// change. This will return the first problem found with the settings, or none if they are valid.
+pub fn validate_email_settings(
+    settings: &EmailSettingsInput,
+    current: Option<&OrganizationEmailSettings>,
+    can_encrypt: bool,
+) -> Option<InvalidInput> {
+    if let Some(from_name) = &settings.from_name {
+        if from_name.trim().is_empty() || from_name.chars().count() > MAX_FROM_NAME_LENGTH {
+            return invalid(
+                "From name must be between 1 and 64 characters.",
+                "invalid-from-name",
+            );
+        }
+    }
+
+    if let Some(from_address) = &settings.from_address {
+        if validation::validate_email(from_address).is_some() {
+            return invalid(
+                "From address is not a valid email address.",
+                "invalid-from-address",
+            );
+        }
+    }
+
+    if let Some(logo_url) = &settings.logo_url {
+        let is_https = Url::parse(logo_url).is_ok_and(|url| url.scheme() == "https");
+        if !is_https || logo_url.chars().count() > MAX_LOGO_URL_LENGTH {
+            return invalid(
+                "Logo URL must be an HTTPS URL of up to 2048 characters.",
+                "invalid-logo-url",
+            );
+        }
+    }
+
+    if let Some(brand_color) = &settings.brand_color {
+        if !is_hex_color(brand_color) {
+            return invalid(
+                "Brand color must be a hex code such as \"#0055ff\".",
+                "invalid-brand-color",
+            );
+        }
+    }
+
+    let (host, username) = match (
+        &settings.smtp_host,
+        settings.smtp_port,
+        &settings.smtp_username,
+    ) {
+        (None, None, None) if settings.smtp_password.is_none() => return None,
+        (Some(host), Some(_), Some(username)) => (host, username),
+        _ => {
+            return invalid(
+                "SMTP host, port, username and password must be set together.",
+                "incomplete-smtp-settings",
+            )
+        }
+    };
+
+    let is_too_long =
+        |field: &str| field.is_empty() || field.chars().count() > MAX_SMTP_FIELD_LENGTH;
+    if is_too_long(host) || is_too_long(username) {
+        return invalid(
+            "SMTP host and username must be between 1 and 255 characters.",
+            "invalid-smtp-settings",
+        );
+    }
+
+    if !settings
+        .smtp_port
+        .is_some_and(|port| u16::try_from(port).is_ok_and(|port| port != 0))
+    {
+        return invalid(
+            "SMTP port must be between 1 and 65535.",
+            "invalid-smtp-port",
+        );
+    }
+
+    match &settings.smtp_password {
+        Some(_) if !can_encrypt => invalid(
+            "SMTP passwords can't be stored, as no encryption key is configured.",
+            "encryption-not-configured",
+        ),
+        Some(password) if password.is_empty() => {
+            invalid("SMTP password cannot be empty.", "invalid-smtp-settings")
+        }
+        Some(_) => None,
+        None => {
+            let keeps_password = current.is_some_and(|current| {
+                current.smtp_password_encrypted.is_some()
+                    && current.smtp_host.as_ref() == Some(host)
+                    && current.smtp_username.as_ref() == Some(username)
+            });
+            if keeps_password {
+                None
+            } else {
+                invalid(
+                    "SMTP password must be set again when the host or username changes.",
+                    "smtp-password-required",
+                )
+            }
+        }
+    }
+}
+
+/// Create a reason email settings were rejected.
+fn invalid(message: &'static str, code: &'static str) -> Option<InvalidInput> {
+    Some(InvalidInput {
+        message: message.into(),
+        code,
+    })
+}
+
+/// Specifies if a color is a six digit hex code, such as "#0055ff".
+pub fn is_hex_color(color: &str) -> bool {
+    color.len() == 7
+        && color.starts_with('#')
+        && color[1..]
+            .chars()
+            .all(|character| character.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher() -> SettingsCipher {
+        SettingsCipher::new(base64::encode([7; 32]).parse().unwrap())
+    }
+
+    #[test]
+    fn keys_must_be_32_bytes() {
+        assert!(base64::encode([0; 32]).parse::<SettingsKey>().is_ok());
+        assert!(base64::encode([0; 16]).parse::<SettingsKey>().is_err());
+        assert!("not base64!".parse::<SettingsKey>().is_err());
+    }
+
+    #[test]
+    fn secrets_are_decrypted_for_their_organization_only() {
+        let (organization, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let sealed = cipher().encrypt(organization, "hunter2").unwrap();
+        assert!(!sealed.windows(7).any(|window| window == b"hunter2"));
+        assert_eq!(cipher().decrypt(organization, &sealed).unwrap(), "hunter2");
+        assert!(cipher().decrypt(other, &sealed).is_err());
+
+        let other_key = SettingsCipher::new(base64::encode([8; 32]).parse().unwrap());
+        assert!(other_key.decrypt(organization, &sealed).is_err());
+    }
+
+    #[test]
+    fn tampered_secrets_are_rejected() {
+        let organization = Uuid::new_v4();
+        let mut sealed = cipher().encrypt(organization, "hunter2").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(cipher().decrypt(organization, &sealed).is_err());
+        assert!(cipher().decrypt(organization, &sealed[..4]).is_err());
+    }
+
+    #[test]
+    fn nonces_are_random() {
+        let organization = Uuid::new_v4();
+        let first = cipher().encrypt(organization, "hunter2").unwrap();
+        let second = cipher().encrypt(organization, "hunter2").unwrap();
+        assert_ne!(first, second);
+    }
+
+    fn smtp_settings() -> EmailSettingsInput {
+        EmailSettingsInput {
+            smtp_host: Some("smtp.example.com".into()),
+            smtp_port: Some(587),
+            smtp_username: Some("apikey".into()),
+            smtp_password: Some("hunter2".into()),
+            ..EmailSettingsInput::default()
+        }
+    }
+
+    fn code(invalid: Option<InvalidInput>) -> Option<&'static str> {
+        invalid.map(|invalid| invalid.code)
+    }
+
+    #[test]
+    fn branding_and_sender_are_validated() {
+        let valid = EmailSettingsInput {
+            from_name: Some("Acme".into()),
+            from_address: Some("hello@acme.com".into()),
+            logo_url: Some("https://acme.com/logo.png".into()),
+            brand_color: Some("#0055ff".into()),
+            ..EmailSettingsInput::default()
+        };
+        assert_eq!(code(validate_email_settings(&valid, None, false)), None);
+
+        let cases = [
+            ("from_name", "invalid-from-name"),
+            ("from_address", "invalid-from-address"),
+            ("logo_url", "invalid-logo-url"),
+            ("brand_color", "invalid-brand-color"),
+        ];
+        for (field, expected) in cases.iter() {
+            let mut settings = valid.clone();
+            match *field {
+                "from_name" => settings.from_name = Some(" ".into()),
+                "from_address" => settings.from_address = Some("acme".into()),
+                "logo_url" => settings.logo_url = Some("http://acme.com/logo.png".into()),
+                _ => settings.brand_color = Some("blue".into()),
+            }
+            let invalid = validate_email_settings(&settings, None, false);
+            assert_eq!(code(invalid), Some(*expected), "{}", field);
+        }
+    }
+
+    #[test]
+    fn smtp_settings_are_set_together() {
+        assert_eq!(
+            code(validate_email_settings(&smtp_settings(), None, true)),
+            None
+        );
+
+        let partial = EmailSettingsInput {
+            smtp_port: None,
+            ..smtp_settings()
+        };
+        let invalid = validate_email_settings(&partial, None, true);
+        assert_eq!(code(invalid), Some("incomplete-smtp-settings"));
+
+        let password_only = EmailSettingsInput {
+            smtp_password: Some("hunter2".into()),
+            ..EmailSettingsInput::default()
+        };
+        let invalid = validate_email_settings(&password_only, None, true);
+        assert_eq!(code(invalid), Some("incomplete-smtp-settings"));
+
+        for port in [0, -1, 65536].iter() {
+            let settings = EmailSettingsInput {
+                smtp_port: Some(*port),
+                ..smtp_settings()
+            };
+            let invalid = validate_email_settings(&settings, None, true);
+            assert_eq!(code(invalid), Some("invalid-smtp-port"));
+        }
+    }
+
+    #[test]
+    fn smtp_passwords_need_a_key() {
+        let invalid = validate_email_settings(&smtp_settings(), None, false);
+        assert_eq!(code(invalid), Some("encryption-not-configured"));
+    }
+
+    #[test]
+    fn passwords_are_only_kept_for_the_same_server() {
+        let current = OrganizationEmailSettings {
+            organization_id: Uuid::new_v4(),
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+            from_name: None,
+            from_address: None,
+            smtp_host: Some("smtp.example.com".into()),
+            smtp_port: Some(465),
+            smtp_username: Some("apikey".into()),
+            smtp_password_encrypted: Some(vec![1, 2, 3]),
+            logo_url: None,
+            brand_color: None,
+        };
+        let unchanged = EmailSettingsInput {
+            smtp_password: None,
+            ..smtp_settings()
+        };
+        assert_eq!(
+            code(validate_email_settings(&unchanged, Some(&current), false)),
+            None
+        );
+        let invalid = validate_email_settings(&unchanged, None, true);
+        assert_eq!(code(invalid), Some("smtp-password-required"));
+
+        let moved = EmailSettingsInput {
+            smtp_host: Some("smtp.attacker.com".into()),
+            ..unchanged
+        };
+        let invalid = validate_email_settings(&moved, Some(&current), true);
+        assert_eq!(code(invalid), Some("smtp-password-required"));
+    }
+
+    #[test]
+    fn hex_colors_have_six_digits() {
+        assert!(is_hex_color("#0055ff"));
+        assert!(is_hex_color("#ABCDEF"));
+        assert!(!is_hex_color("0055ff"));
+        assert!(!is_hex_color("#05f"));
+        assert!(!is_hex_color("#0055gg"));
+        assert!(!is_hex_color("red; x"));
+    }
+}
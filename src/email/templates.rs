@@ -0,0 +1,209 @@
+use std::ops::Deref;
+
+use askama::Template;
+
+use crate::email::settings::EmailBranding;
+
+/// An email rendered from templates, with a plain text part for clients that can't show HTML and
+/// an HTML part. Templates live in the "templates/email" directory and are compiled into the
+/// server, so a broken template is a compile error rather than a failed email.
+pub trait EmailTemplate {
+    /// The subject of the email.
+    fn subject(&self) -> String;
+
+    /// Render the plain text part of the email.
+    fn text(&self) -> askama::Result<String>;
+
+    /// Render the HTML part of the email, with the branding of whoever it is sent on behalf of.
+    fn html(&self, branding: &EmailBranding) -> askama::Result<String>;
+}
+
+/// Implement [`EmailTemplate`] for an email, rendering its plain text and HTML parts with the
+/// specified templates. Templates can refer to any field of the email directly, and the HTML
+/// layout expects every email to have "username" and "sender_name" fields. HTML templates can also
+/// refer to the "branding" the email is rendered with.
+macro_rules! email_template {
+    ($email:ident, $subject:expr, $text:ident => $text_path:tt, $html:ident => $html_path:tt) => {
+        #[derive(Template)]
+        #[template(path = $text_path)]
+        struct $text<'a> {
+            email: &'a $email<'a>,
+        }
+
+        impl<'a> Deref for $text<'a> {
+            type Target = $email<'a>;
+
+            fn deref(&self) -> &Self::Target {
+                self.email
+            }
+        }
+
+        #[derive(Template)]
+        #[template(path = $html_path)]
+        struct $html<'a> {
+            email: &'a $email<'a>,
+            branding: &'a EmailBranding,
+        }
+
+        impl<'a> Deref for $html<'a> {
+            type Target = $email<'a>;
+
+            fn deref(&self) -> &Self::Target {
+                self.email
+            }
+        }
+
+        impl EmailTemplate for $email<'_> {
+            fn subject(&self) -> String {
+                let subject: fn(&Self) -> String = $subject;
+                subject(self)
+            }
+
+            fn text(&self) -> askama::Result<String> {
+                $text { email: self }.render()
+            }
+
+            fn html(&self, branding: &EmailBranding) -> askama::Result<String> {
+                $html {
+                    email: self,
+                    branding,
+                }
+                .render()
+            }
+        }
+    };
+}
+
+/// Describe a number of seconds in words, such as "10 minutes" or "1 day", rounding down to the
+/// largest whole unit.
+pub fn describe_duration(seconds: u32) -> String {
+    let (amount, unit) = match seconds {
+        0..=59 => (seconds, "second"),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+
+    if amount == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", amount, unit)
+    }
+}
+
+/// Email sending a new user the code that verifies their email address.
+pub struct VerificationEmail<'a> {
+    pub sender_name: &'a str,
+    pub username: &'a str,
+    pub code: &'a str,
+    /// How long until the code expires, in words.
+    pub expires_in: String,
+}
+
+email_template!(
+    VerificationEmail,
+    |_| "Verify your account".into(),
+    VerificationText => "email/verification.txt",
+    VerificationHtml => "email/verification.html"
+);
+
+/// Email sending a user a token that lets them choose a new password.
+pub struct PasswordResetEmail<'a> {
+    pub sender_name: &'a str,
+    pub username: &'a str,
+    pub token: &'a str,
+    /// How long until the token expires, in words.
+    pub expires_in: String,
+}
+
+email_template!(
+    PasswordResetEmail,
+    |_| "Reset your password".into(),
+    PasswordResetText => "email/password_reset.txt",
+    PasswordResetHtml => "email/password_reset.html"
+);
+
+/// Email sending a user a password reset token after an admin required them to reset their
+/// password, such as when their account may have been compromised.
+pub struct ForcedPasswordResetEmail<'a> {
+    pub sender_name: &'a str,
+    pub username: &'a str,
+    pub token: &'a str,
+    /// How long until the token expires, in words.
+    pub expires_in: String,
+}
+
+email_template!(
+    ForcedPasswordResetEmail,
+    |_| "Your password needs to be reset".into(),
+    ForcedPasswordResetText => "email/password_reset_forced.txt",
+    ForcedPasswordResetHtml => "email/password_reset_forced.html"
+);
+
+/// Email welcoming a user once their email address is verified.
+pub struct WelcomeEmail<'a> {
+    pub sender_name: &'a str,
+    pub username: &'a str,
+}
+
+email_template!(
+    WelcomeEmail,
+    |email| format!("Welcome to {}", email.sender_name),
+    WelcomeText => "email/welcome.txt",
+    WelcomeHtml => "email/welcome.html"
+);
+
+/// Email reminding a user that their trial ends soon.
+pub struct TrialEndingEmail<'a> {
+    pub sender_name: &'a str,
+    pub username: &'a str,
+    /// The name of the plan being trialed.
+    pub plan: &'a str,
+    /// When the trial ends, formatted for display.
+    pub ends_at: String,
+}
+
+email_template!(
+    TrialEndingEmail,
+    |_| "Your trial is ending soon".into(),
+    TrialEndingText => "email/trial_ending.txt",
+    TrialEndingHtml => "email/trial_ending.html"
+);
+
+/// Email inviting a user to join an organization.
+pub struct OrganizationInviteEmail<'a> {
+    pub sender_name: &'a str,
+    pub username: &'a str,
+    /// The name of the organization.
+    pub organization: &'a str,
+    /// The username of the member that sent the invitation.
+    pub inviter: &'a str,
+    /// The role the user will have once they join, such as "admin".
+    pub role: &'a str,
+}
+
+email_template!(
+    OrganizationInviteEmail,
+    |email| format!("You've been invited to join {}", email.organization),
+    OrganizationInviteText => "email/organization_invite.txt",
+    OrganizationInviteHtml => "email/organization_invite.html"
+);
+
+/// Email alerting operators that a threshold was crossed, such as a spike in failed logins.
+pub struct AlertEmail<'a> {
+    pub sender_name: &'a str,
+    pub username: &'a str,
+    /// The name of the alert, such as "failed-logins".
+    pub alert: &'a str,
+    /// A description of what happened.
+    pub message: &'a str,
+    /// How long until another alert of the same kind can be sent, in words.
+    pub cooldown: String,
+}
+
+email_template!(
+    AlertEmail,
+    |email| format!("[{}] Alert: {}", email.sender_name, email.alert),
+    AlertText => "email/alert.txt",
+    AlertHtml => "email/alert.html"
+);
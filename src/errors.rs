@@ -0,0 +1,185 @@
+use juniper::{graphql_value, FieldError, IntoFieldError};
+use tide::log;
+
+use crate::budgets::{
+    RowBudgetExceeded, ROW_BUDGET_EXCEEDED_ERROR_CODE, ROW_BUDGET_EXCEEDED_ERROR_MESSAGE,
+};
+use crate::deadlines::{
+    DeadlineExceeded, DEADLINE_EXCEEDED_ERROR_CODE, DEADLINE_EXCEEDED_ERROR_MESSAGE,
+};
+use crate::middleware::UNKNOWN_ERROR_MESSAGE;
+use crate::schema::InvalidInput;
+
+/// Postgres error code for a violated unique constraint.
+const UNIQUE_VIOLATION_CODE: &str = "23505";
+
+/// An error reported to API clients. Every error a resolver can produce is classified as one of
+/// these, so clients always receive a stable error code and the details of server errors are never
+/// exposed. This deliberately doesn't implement `Display`, so it can't be converted into a GraphQL
+/// error without its code by the `?` operator.
+#[derive(Debug)]
+pub enum ApiError {
+    /// A requested record doesn't exist.
+    NotFound(String),
+    /// Input to a query or mutation was rejected, with a stable code describing why.
+    Validation { message: String, code: &'static str },
+    /// The request isn't allowed to do what it attempted.
+    Unauthorized(String),
+    /// The request conflicts with the current state of a record, such as a duplicate of something
+    /// that must be unique.
+    Conflict(String),
+    /// Something went wrong on the server. The details are logged but never sent to clients.
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    /// The stable error code reported to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not-found",
+            ApiError::Validation { code, .. } => code,
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Internal(_) => "unknown-error",
+        }
+    }
+
+    /// The message reported to clients.
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::Validation { message, .. }
+            | ApiError::Unauthorized(message)
+            | ApiError::Conflict(message) => message,
+            ApiError::Internal(_) => UNKNOWN_ERROR_MESSAGE,
+        }
+    }
+
+    /// Log the error at a level matching its severity. Only server errors are logged as errors,
+    /// as the others are caused by clients.
+    pub fn log(&self) {
+        match self {
+            ApiError::Internal(error) => log::error!("{}", error),
+            ApiError::Unauthorized(message) => log::warn!("Unauthorized request: {}", message),
+            ApiError::Conflict(message) => log::info!("Conflicting request: {}", message),
+            ApiError::NotFound(message) | ApiError::Validation { message, .. } => {
+                log::debug!("Rejected request: {}", message)
+            }
+        }
+    }
+}
+
+impl From<InvalidInput> for ApiError {
+    fn from(InvalidInput { message, code }: InvalidInput) -> Self {
+        ApiError::Validation {
+            message: message.into(),
+            code,
+        }
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        match &error {
+            sqlx::Error::RowNotFound => {
+                ApiError::NotFound("The requested record does not exist.".into())
+            }
+            sqlx::Error::Database(database_error)
+                if database_error.code().as_deref() == Some(UNIQUE_VIOLATION_CODE) =>
+            {
+                ApiError::Conflict("A record with these details already exists.".into())
+            }
+            _ => ApiError::Internal(error.into()),
+        }
+    }
+}
+
+impl From<RowBudgetExceeded> for ApiError {
+    fn from(_: RowBudgetExceeded) -> Self {
+        ApiError::Validation {
+            message: ROW_BUDGET_EXCEEDED_ERROR_MESSAGE.into(),
+            code: ROW_BUDGET_EXCEEDED_ERROR_CODE,
+        }
+    }
+}
+
+impl From<DeadlineExceeded> for ApiError {
+    fn from(_: DeadlineExceeded) -> Self {
+        ApiError::Validation {
+            message: DEADLINE_EXCEEDED_ERROR_MESSAGE.into(),
+            code: DEADLINE_EXCEEDED_ERROR_CODE,
+        }
+    }
+}
+
+impl From<redis::RedisError> for ApiError {
+    fn from(error: redis::RedisError) -> Self {
+        // Redis commands only time out when the request's deadline passes.
+        if error.is_timeout() {
+            DeadlineExceeded.into()
+        } else {
+            ApiError::Internal(error.into())
+        }
+    }
+}
+
+#[cfg(feature = "email")]
+impl From<lettre::transport::smtp::Error> for ApiError {
+    fn from(error: lettre::transport::smtp::Error) -> Self {
+        // Permanent failures are caused by addresses the SMTP server won't deliver to.
+        if error.is_permanent() {
+            ApiError::Validation {
+                message: "The email could not be delivered to the provided address.".into(),
+                code: "undeliverable-email",
+            }
+        } else {
+            ApiError::Internal(error.into())
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<sqlx::Error>() {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<redis::RedisError>() {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<RowBudgetExceeded>() {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<DeadlineExceeded>() {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        #[cfg(feature = "email")]
+        let error = match error.downcast::<lettre::transport::smtp::Error>() {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        ApiError::Internal(error)
+    }
+}
+
+impl IntoFieldError for ApiError {
+    fn into_field_error(self) -> FieldError {
+        self.log();
+        FieldError::new(self.message(), graphql_value!({ "code": (self.code()) }))
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+impl From<ApiError> for async_graphql::Error {
+    fn from(error: ApiError) -> Self {
+        use async_graphql::ErrorExtensions;
+
+        error.log();
+        let code = error.code();
+        async_graphql::Error::new(error.message())
+            .extend_with(|_, extensions| extensions.set("code", code))
+    }
+}
@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use async_std::task;
+use serde::Deserialize;
+use sqlx::postgres::PgListener;
+use tide::log;
+use uuid::Uuid;
+
+use crate::state::State;
+
+/// Postgres notification channel row change triggers notify on.
+const ROW_CHANGES_CHANNEL: &str = "row_changes";
+/// How long to wait before listening for row changes again after the connection fails.
+const LISTENER_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// Max number of events buffered for a subscriber before further events to it are dropped.
+const SUBSCRIBER_BUFFER_EVENTS: usize = 256;
+
+/// The kind of change made to a row.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RowOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// A change to a row of a table, as sent by the "notify_row_change" trigger.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RowChange {
+    /// The table the row belongs to.
+    pub table: String,
+    /// The kind of change made to the row.
+    pub operation: RowOperation,
+    /// The key identifying the row. For tables belonging to a user, this is the user's ID.
+    pub key: Uuid,
+}
+
+/// Something that happened in the domain of the server, regardless of whether it was done through
+/// the API or by writing to the database directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomainEvent {
+    UserCreated { user_id: Uuid },
+    UserUpdated { user_id: Uuid },
+    UserDeleted { user_id: Uuid },
+    RolesChanged { user_id: Uuid },
+    SubscriptionChanged { user_id: Uuid },
+    TrialChanged { user_id: Uuid },
+}
+
+impl DomainEvent {
+    /// Convert a row change into the domain event it represents, if any.
+    pub fn from_row_change(change: &RowChange) -> Option<Self> {
+        let user_id = change.key;
+        match (change.table.as_str(), change.operation) {
+            ("users", RowOperation::Insert) => Some(DomainEvent::UserCreated { user_id }),
+            ("users", RowOperation::Update) => Some(DomainEvent::UserUpdated { user_id }),
+            ("users", RowOperation::Delete) => Some(DomainEvent::UserDeleted { user_id }),
+            ("user_roles", _) => Some(DomainEvent::RolesChanged { user_id }),
+            ("subscriptions", _) => Some(DomainEvent::SubscriptionChanged { user_id }),
+            ("trials", _) => Some(DomainEvent::TrialChanged { user_id }),
+            _ => None,
+        }
+    }
+}
+
+/// Publishes domain events to every subscriber in the server. Subscribers that fall behind have
+/// events dropped rather than slowing down the publisher.
+#[derive(Clone, Default)]
+pub struct EventPublisher {
+    subscribers: Arc<Mutex<Vec<Sender<DomainEvent>>>>,
+}
+
+impl EventPublisher {
+    /// Create a new event publisher without any subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to every domain event published from now on. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> Receiver<DomainEvent> {
+        let (sender, receiver) = channel::bounded(SUBSCRIBER_BUFFER_EVENTS);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Disconnect every subscriber, ending their streams of events. Subscribing again is allowed.
+    pub fn close(&self) {
+        self.subscribers.lock().unwrap().clear();
+    }
+
+    /// Publish a domain event to every subscriber.
+    pub fn publish(&self, event: DomainEvent) {
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            match subscriber.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!(
+                        "Event subscriber is falling behind. Dropped event: {:?}",
+                        event
+                    );
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+}
+
+/// Listen for row changes, invalidating cached values read from changed rows and publishing
+/// changes on the tables selected in the server configuration as domain events, so writes made
+/// outside of this server propagate like those made through it. Caches are cleared whenever the
+/// connection is lost, as changes made while reconnecting are missed. This runs until the server
+/// stops.
+pub async fn run_change_listener(state: State) {
+    loop {
+        if let Err(error) = listen_for_changes(&state).await {
+            log::error!("Failed to listen for row changes: {}", error);
+        }
+        state.caches.clear();
+        task::sleep(LISTENER_RETRY_INTERVAL).await;
+    }
+}
+
+/// Handle row changes until the connection fails.
+async fn listen_for_changes(state: &State) -> Result<()> {
+    let mut listener = PgListener::connect_with(&state.db).await?;
+    listener.listen(ROW_CHANGES_CHANNEL).await?;
+    // Values may have been cached while the listener wasn't connected.
+    state.caches.clear();
+    if !state.config.change_event_tables.is_empty() {
+        log::info!(
+            "Publishing row changes on: {}",
+            state.config.change_event_tables.join(", ")
+        );
+    }
+
+    loop {
+        // The listener reconnects on its own, so this only notices a lost connection through
+        // there being no notification.
+        let notification = match listener.try_recv().await? {
+            Some(notification) => notification,
+            None => {
+                log::warn!("Lost connection while listening for row changes. Reconnecting...");
+                state.caches.clear();
+                continue;
+            }
+        };
+        let change = match serde_json::from_str::<RowChange>(notification.payload()) {
+            Ok(change) => change,
+            Err(error) => {
+                log::warn!("Ignored malformed row change: {}", error);
+                continue;
+            }
+        };
+
+        state.caches.invalidate(&change);
+        if !state.config.change_event_tables.contains(&change.table) {
+            continue;
+        }
+
+        if let Some(event) = DomainEvent::from_row_change(&change) {
+            log::debug!("Publishing domain event: {:?}", event);
+            state.events.publish(event);
+        }
+    }
+}
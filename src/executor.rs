@@ -1,112 +1,788 @@
-use anyhow::Result;
-use chrono::Utc;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, SmtpTransport, Transport};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use anyhow::{anyhow, Error, Result};
+use async_std::task;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use futures::channel::oneshot;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt, TryStreamExt};
+#[cfg(feature = "email")]
+use lettre::message::{Mailbox, MultiPart};
+#[cfg(feature = "email")]
+use lettre::Message;
+use rand::distributions::Alphanumeric;
 use rand::Rng;
-use redis::aio::ConnectionManager;
-use redis::AsyncCommands;
-use sqlx::{query, query_as, PgPool};
-use std::time::Duration;
+use redis::{AsyncCommands, FromRedisValue, Pipeline};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::{query, query_as, query_scalar, PgConnection, PgPool, Postgres, Transaction};
 use tide::log;
 use uuid::Uuid;
 
-use crate::auth::{SessionToken, SessionTokenData};
+use crate::alerts::{self, Alert, AlertCounts};
+use crate::announcements::{Audience, Severity};
+use crate::audit::{AuditChange, AuditEventKind, AuditedAction, AuditedActions};
+use crate::auth::{
+    ActiveSession, AuthenticatedUser, LoginMethod, LoginResult, ResendVerificationResult,
+    SessionToken, SessionTokenData,
+};
+use crate::avatars::{self, AvatarFormat};
+use crate::billing::{self, CheckoutSession, Plan, StripeSubscription};
+use crate::budgets::RowCounter;
+use crate::cache::{self, BillingRecords, Cache, CachedQuery};
+use crate::canary::{self, Canary};
+use crate::concurrency::{LimitedResolver, ResolverPermit};
 use crate::config::Config;
-use crate::models::User;
+use crate::db;
+use crate::deadlines::{Deadline, DeadlineCache, DeadlineConnection};
+use crate::deprecations::{self, DeprecatedFieldClient, DeprecatedFieldUsage, Deprecation};
+use crate::domains;
+use crate::email::outbox::{OutboxEmail, OutboxStatus};
+use crate::email::settings::{EmailSettingsInput, SettingsCipher};
+use crate::email::templates::{
+    describe_duration, AlertEmail, EmailTemplate, ForcedPasswordResetEmail,
+    OrganizationInviteEmail, PasswordResetEmail, TrialEndingEmail, VerificationEmail, WelcomeEmail,
+};
+#[cfg(feature = "email")]
+use crate::email::{EntityRefId, Mailer};
+use crate::exports::UserExportFilter;
+use crate::feedback::{self, FeedbackCategory, FeedbackStatus};
+use crate::models::{
+    Announcement, AuditEvent, AvatarJob, Feedback, Membership, Organization, OrganizationDomain,
+    OrganizationEmailSettings, Passkey, PendingAction, PendingSignup, PromoCode,
+    PromoCodeRedemption, SecurityEvent, Subscription, Trial, User, WebhookEvent,
+};
+#[cfg(feature = "oauth")]
+use crate::oauth::{self, OAuthProfile, OAuthProvider};
+use crate::organizations::{MembershipResult, OrganizationRole};
+use crate::pagination::{Cursor, PageRequest, SortValue, UserConnection, UserOrder};
+use crate::pending_actions::{self, PendingActionKind};
+use crate::promo_codes::{self, PromoCodeKind, RedeemResult};
+use crate::query_stats::{self, QueryStatistics, QueryStatisticsOrder};
+use crate::roles::Role;
+use crate::scanning::{self, ScanVerdict};
+use crate::security_events::SecurityEventKind;
+use crate::signup_screening::{self, SignupEvidence, SignupResult, SignupScreeningAction};
 use crate::state::State;
+use crate::storage::FileStore;
+use crate::uploads::ImageFormat;
+use crate::usage::{self, QuotaStatus, Usage, UsageLimits};
+use crate::user_search::{self, SortDirection, UserFilter, UserSearchHit, UserSort, UserSortField};
+use crate::webauthn::{self, Ceremony};
+use crate::webhooks::WebhookProvider;
 
 /// The business logic handler for a request.
 pub struct Executor {
     state: State,
+    request_id: String,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    rows: Arc<RowCounter>,
+    audited_actions: AuditedActions,
+    deadline: Deadline,
+    cache: DeadlineCache,
+    store: DeadlineCache,
 }
 
 impl Executor {
-    /// Create a new executor with access to the global server state.
-    pub fn new(state: State) -> Self {
-        Self { state }
+    /// Create a new executor with access to the global server state, handling the request with the
+    /// specified ID sent from the specified IP address and user agent, if known.
+    pub fn new(
+        state: State,
+        request_id: String,
+        client_ip: Option<String>,
+        user_agent: Option<String>,
+    ) -> Self {
+        let rows = Arc::new(RowCounter::new(state.config.request_budget));
+        let cache = DeadlineCache::new(state.cache.clone(), Deadline::none());
+        let store = DeadlineCache::new(state.store.clone(), Deadline::none());
+        Self {
+            state,
+            request_id,
+            client_ip,
+            user_agent,
+            rows,
+            audited_actions: AuditedActions::default(),
+            deadline: Deadline::none(),
+            cache,
+            store,
+        }
+    }
+
+    /// Abandon database queries, Redis commands and other work done by the executor once a
+    /// deadline passes. Executors have no deadline by default.
+    pub fn with_deadline(mut self, deadline: Deadline) -> Self {
+        self.deadline = deadline;
+        self.cache = DeadlineCache::new(self.state.cache.clone(), deadline);
+        self.store = DeadlineCache::new(self.state.store.clone(), deadline);
+        self
+    }
+
+    /// Get the deadline the request being handled has to be handled by.
+    pub fn deadline(&self) -> Deadline {
+        self.deadline
+    }
+
+    /// Get the ID of the request being handled. This is attached to any side effects of the
+    /// request, such as emails, so they can be traced back to the request's log lines.
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Create an executor for running a canary alongside the request. It handles the same request
+    /// by the same deadline, but counts rows against a budget of its own and records audited
+    /// actions nowhere, so the canary can't change the request's response.
+    fn shadow(&self) -> Self {
+        Executor::new(
+            self.state.clone(),
+            self.request_id.clone(),
+            self.client_ip.clone(),
+            self.user_agent.clone(),
+        )
+        .with_deadline(self.deadline)
+    }
+
+    /// Get the counter of rows fetched from the database while handling the request. Clones share
+    /// the same count, so it can be read after the request's context has been handed to the schema.
+    pub fn row_counter(&self) -> Arc<RowCounter> {
+        self.rows.clone()
+    }
+
+    /// Get the sensitive admin actions taken while handling the request. Clones share the same
+    /// actions, so they can be read after the request's context has been handed to the schema.
+    pub fn audited_actions(&self) -> AuditedActions {
+        self.audited_actions.clone()
+    }
+
+    /// Count rows fetched while handling the request against its budget. This will return an
+    /// error instead of the rows if the request has now fetched more rows than its budget allows
+    /// and such requests are rejected.
+    fn count_rows<T>(&self, rows: Vec<T>) -> Result<Vec<T>> {
+        self.rows.add(rows.len())?;
+        Ok(rows)
+    }
+
+    /// Run a database query, recording how long it took under a name that identifies the query.
+    /// The time is logged if the query was slow or every query is logged, and recorded in the
+    /// query's latency metrics. The query is abandoned if the request's deadline passes first.
+    async fn timed<T, F>(&self, name: &'static str, query: F) -> Result<T>
+    where
+        F: Future<Output = Result<T, sqlx::Error>>,
+    {
+        let start = Instant::now();
+        let result = self.deadline.run(query).await;
+        let duration = start.elapsed();
+        db::log_query(self.config(), name, duration);
+        self.state.metrics.observe_db_query(name, duration);
+        Ok(result??)
+    }
+
+    /// Time a database query whose rows are streamed, like [`Executor::timed`]. The query is
+    /// timed from now until the stream is dropped, as rows are only read while it is polled.
+    fn timed_stream<'a, T: Send + 'a>(
+        &'a self,
+        name: &'static str,
+        rows: BoxStream<'a, Result<T>>,
+    ) -> BoxStream<'a, Result<T>> {
+        TimedStream {
+            rows,
+            name,
+            start: Instant::now(),
+            state: &self.state,
+        }
+        .boxed()
+    }
+
+    /// Get the IP address of the client that sent the request being handled, if known.
+    pub fn client_ip(&self) -> Option<&str> {
+        self.client_ip.as_deref()
+    }
+
+    /// Get the user agent of the client that sent the request being handled, if known.
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
     }
 
     /// Access the server configuration settings.
-    fn config(&self) -> &Config {
+    pub fn config(&self) -> &Config {
         &self.state.config
     }
 
     /// Access the Postgres database connection pool.
-    fn db(&self) -> &PgPool {
+    pub fn db(&self) -> &PgPool {
         &self.state.db
     }
 
-    /// Access the Redis database connection manager.
-    fn redis(&self) -> ConnectionManager {
-        self.state.redis.clone()
+    /// Run statements that must take effect together, such as a change and the audit event
+    /// recording it, in a database transaction. The transaction is committed once `work` succeeds,
+    /// and rolled back if it fails, so none of its statements take effect. Side effects outside
+    /// Postgres, such as Redis writes and emails, should only happen once this returns, so they
+    /// aren't done for changes that were rolled back. The transaction is rolled back if the
+    /// request's deadline passes before `work` finishes.
+    pub async fn transaction<'a, T, F>(&'a self, work: F) -> Result<T>
+    where
+        F: for<'c> FnOnce(&'c mut Transaction<'a, Postgres>) -> BoxFuture<'c, Result<T>>,
+    {
+        let mut transaction = self.deadline.run(self.db().begin()).await??;
+        // Dropping the transaction without committing it rolls it back.
+        let result = self.deadline.run(work(&mut transaction)).await??;
+        transaction.commit().await?;
+        Ok(result)
+    }
+
+    /// Access the Redis database. Commands sent through the returned connection are counted in
+    /// the server metrics, and abandoned if the request's deadline passes first.
+    pub fn redis(&self) -> DeadlineConnection {
+        let connection = self.state.metrics.instrument(self.state.redis.clone());
+        DeadlineConnection::new(connection, self.deadline)
+    }
+
+    /// Access the cache results of expensive reads are kept in. Reads and writes are abandoned if
+    /// the request's deadline passes first.
+    pub fn cache(&self) -> &dyn Cache {
+        &self.cache
+    }
+
+    /// Access the store short-lived values that must be shared by every server instance, such as
+    /// rate limit counters, login lockouts and verification codes, are kept in. Reads and writes
+    /// are abandoned if the request's deadline passes first.
+    pub fn store(&self) -> &dyn Cache {
+        &self.store
+    }
+
+    /// Access the pooled SMTP mailer.
+    #[cfg(feature = "email")]
+    pub fn mailer(&self) -> &Mailer {
+        &self.state.mailer
+    }
+
+    /// Access the store private files are kept in.
+    pub fn files(&self) -> &dyn FileStore {
+        self.state.files.as_ref()
+    }
+
+    /// Wait for a slot to call an expensive resolver, queuing behind other calls to it on this
+    /// instance. This will return none if no slot was freed before the queue timeout. The slot is
+    /// held until the returned permit is dropped.
+    pub async fn acquire_permit(&self, resolver: LimitedResolver) -> Option<ResolverPermit> {
+        self.state.limiter.acquire(resolver).await
+    }
+
+    /// Run a read, and for the configured percentage of calls, a new implementation of it in the
+    /// shadow of the old one. The canary runs in the background on an executor of its own, so its
+    /// result, errors and latency never change the response. Once both finish, the canary's result
+    /// is compared with a summary of the read's, and mismatches are logged. Canaries must only
+    /// read, as they run in addition to the implementation they replace.
+    pub async fn canary<T, K, F>(
+        &self,
+        canary: Canary,
+        read: impl Future<Output = Result<T>>,
+        summarize: impl FnOnce(&T) -> K,
+        shadow: impl FnOnce(Executor) -> F,
+    ) -> Result<T>
+    where
+        K: PartialEq + Debug + Send + 'static,
+        F: Future<Output = Result<K>> + Send + 'static,
+    {
+        if !self.config().canary_percentages.is_sampled(canary) {
+            return read.await;
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        let shadow = shadow(self.shadow());
+        let metrics = self.state.metrics.clone();
+        let request_id = self.request_id.clone();
+        task::spawn(async move {
+            let actual = shadow.await;
+            // There is nothing to compare with if the read failed.
+            if let Ok(expected) = receiver.await {
+                let outcome = canary::compare(canary, &request_id, &expected, actual);
+                metrics.observe_canary(canary.name(), outcome.name());
+            }
+        });
+
+        let result = read.await;
+        if let Ok(result) = &result {
+            let _ = sender.send(summarize(result));
+        }
+        result
+    }
+
+    /// Read the result of an expensive query through the result cache. Results are keyed by the
+    /// query, the variables it is read with and the user it is read for, which should only be
+    /// specified if the result depends on them. On a miss, the result is loaded and cached. The
+    /// result is loaded directly if the cache is disabled, and cache errors are logged rather than
+    /// failing the read.
+    pub async fn cached<T>(
+        &self,
+        query: CachedQuery,
+        viewer: Option<Uuid>,
+        variables: Value,
+        load: impl Future<Output = Result<T>>,
+    ) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let ttl_seconds = self.config().result_cache_ttls.ttl_seconds(query);
+        if !self.config().result_cache_enabled || ttl_seconds == 0 {
+            return load.await;
+        }
+
+        let key = match self.cache().get(&cache::generation_key(query)).await {
+            Ok(generation) => {
+                let generation = generation.and_then(|generation| generation.parse().ok());
+                cache::result_key(query, generation.unwrap_or(0), viewer, &variables)
+            }
+            Err(error) => {
+                log::warn!("Failed to read the {} cache: {}", query.name(), error);
+                return load.await;
+            }
+        };
+        match self.cache().get(&key).await {
+            Ok(Some(cached)) => match serde_json::from_str(&cached) {
+                Ok(result) => {
+                    self.state
+                        .metrics
+                        .observe_result_cache_lookup(query.name(), true);
+                    return Ok(result);
+                }
+                Err(error) => log::warn!("Failed to parse a cached {}: {}", query.name(), error),
+            },
+            Ok(None) => {}
+            Err(error) => log::warn!("Failed to read the {} cache: {}", query.name(), error),
+        }
+        self.state
+            .metrics
+            .observe_result_cache_lookup(query.name(), false);
+
+        let result = load.await?;
+        if let Err(error) = self
+            .cache()
+            .set(&key, &serde_json::to_string(&result)?, ttl_seconds)
+            .await
+        {
+            log::warn!("Failed to cache a {}: {}", query.name(), error);
+        }
+
+        Ok(result)
+    }
+
+    /// Invalidate every cached result of queries, once they may have changed. Mutations changing
+    /// what a cached query reads must call this. Failures are logged, and leave results cached
+    /// until they expire.
+    pub async fn invalidate_cached(&self, queries: &[CachedQuery]) {
+        if !self.config().result_cache_enabled {
+            return;
+        }
+
+        for query in queries {
+            let generation = rand::random::<u64>().to_string();
+            if let Err(error) = self
+                .cache()
+                .set(
+                    &cache::generation_key(*query),
+                    &generation,
+                    cache::GENERATION_TTL_SECONDS,
+                )
+                .await
+            {
+                log::error!("Failed to invalidate the {} cache: {}", query.name(), error);
+            }
+        }
     }
 
     /// Attempt to create a new user with the provided username, email and password. Once the user
     /// is created, an email verification code will be sent to the user's email address. That same
-    /// verification code is stored temporarily in the Redis database until the code expires. To
+    /// verification code is stored temporarily in the cache until the code expires. To
     /// verify a user's email address, we just make sure the verification code the user sends in
-    /// later matches the code we have stored in Redis.
+    /// later matches the code we have stored in the cache.
     pub async fn create_user(&self, username: &str, email: &str, password: &str) -> Result<User> {
         let Config {
             password_hash_cost, ..
         } = self.config();
 
-        let id = Uuid::new_v4();
         let password_hash = bcrypt::hash(password, *password_hash_cost)?;
+        self.create_user_with_password_hash(username, email, &password_hash)
+            .await
+    }
 
-        // Create the user.
-        let user = query_as!(
-            User,
-            "
-            INSERT INTO users (id, username, email, password_hash)
-            VALUES ($1, $2, $3, $4)
-            RETURNING *
-            ",
-            id,
+    /// Create a new user whose password was already hashed. Once created, an email verification
+    /// code is sent to the user's email address.
+    async fn create_user_with_password_hash(
+        &self,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> Result<User> {
+        let user = self
+            .transaction(|transaction| {
+                Box::pin(self.insert_user(transaction, username, email, None, password_hash))
+            })
+            .await?;
+
+        self.invalidate_cached(&[CachedQuery::UserCount]).await;
+        self.start_email_verification(user.id, username, email)
+            .await?;
+
+        Ok(user)
+    }
+
+    /// Store a new user, start their trial and record their creation in the audit log, as part of
+    /// a transaction so the user is never created without the others.
+    async fn insert_user(
+        &self,
+        connection: &mut PgConnection,
+        username: &str,
+        email: &str,
+        email_verified_at: Option<DateTime<Utc>>,
+        password_hash: &str,
+    ) -> Result<User> {
+        let id = Uuid::new_v4();
+        let user = self
+            .timed(
+                "insert_user",
+                query_as!(
+                    User,
+                    "
+                    INSERT INTO users (id, username, email, email_verified_at, password_hash)
+                    VALUES ($1, $2, $3, $4, $5)
+                    RETURNING *
+                    ",
+                    id,
+                    username,
+                    email,
+                    email_verified_at,
+                    password_hash,
+                )
+                .fetch_one(&mut *connection),
+            )
+            .await?;
+
+        self.start_trial(connection, id).await?;
+        self.record_audit_event_in(
+            connection,
+            AuditEventKind::UserCreated,
+            Some(id),
+            Some(id),
+            &[],
+        )
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Issue a token for a signup form being loaded, to be sent back when signing up so signup
+    /// screening can tell how long the form took to fill in.
+    pub fn signup_form_token(&self) -> String {
+        signup_screening::issue_form_token(&self.config().session_token_secret, Utc::now())
+    }
+
+    /// Attempt to sign up a new user, screening the signup for signals suggesting it was made by
+    /// a bot if signup screening is enabled. Suspicious signups are either queued for review or
+    /// must pass a CAPTCHA, depending on the screening action. Every other signup creates the
+    /// user like `create_user`. In a dry run, the user that would be created is returned without
+    /// being saved, and screening is skipped, as CAPTCHA tokens can only be verified once.
+    pub async fn sign_up(
+        &self,
+        username: &str,
+        email: &str,
+        password: &str,
+        evidence: &SignupEvidence,
+        dry_run: bool,
+    ) -> Result<SignupResult> {
+        if dry_run {
+            let now = Utc::now();
+            return Ok(SignupResult::Created(Box::new(User {
+                id: Uuid::new_v4(),
+                created_at: now,
+                updated_at: now,
+                username: username.into(),
+                email: email.into(),
+                email_verified_at: None,
+                password_hash: String::new(),
+                avatar_path: None,
+                avatar_sizes: None,
+                deactivated_at: None,
+                deleted_at: None,
+                password_reset_required_at: None,
+                legal_hold_at: None,
+            })));
+        }
+
+        let Config {
+            signup_screening_enabled,
+            signup_screening_action,
+            password_hash_cost,
+            ..
+        } = self.config();
+        if !*signup_screening_enabled {
+            return Ok(SignupResult::Created(Box::new(
+                self.create_user(username, email, password).await?,
+            )));
+        }
+
+        let signals =
+            signup_screening::screen(self.config(), evidence, self.user_agent(), Utc::now());
+        let signal_names: Vec<&str> = signals.iter().map(|signal| signal.as_str()).collect();
+        if signals.is_empty() {
+            self.state.metrics.observe_signup_screening("allowed", &[]);
+            return Ok(SignupResult::Created(Box::new(
+                self.create_user(username, email, password).await?,
+            )));
+        }
+
+        log::warn!(
+            "Suspicious signup for username {}: {} (request {})",
             username,
-            email,
-            password_hash,
+            signal_names.join(", "),
+            self.request_id()
+        );
+        match signup_screening_action {
+            SignupScreeningAction::Captcha => {
+                let is_passed = match &evidence.captcha_token {
+                    Some(token) => self.verify_captcha(token).await,
+                    None => false,
+                };
+                if !is_passed {
+                    self.state
+                        .metrics
+                        .observe_signup_screening("captcha-required", &signal_names);
+                    return Ok(SignupResult::CaptchaRequired);
+                }
+
+                self.state
+                    .metrics
+                    .observe_signup_screening("captcha-passed", &signal_names);
+                Ok(SignupResult::Created(Box::new(
+                    self.create_user(username, email, password).await?,
+                )))
+            }
+            SignupScreeningAction::Review => {
+                let password_hash = bcrypt::hash(password, *password_hash_cost)?;
+                self.timed(
+                    "sign_up",
+                    query!(
+                        "
+                        INSERT INTO pending_signups
+                            (id, username, email, password_hash, signals, client_ip, user_agent)
+                        VALUES ($1, $2, $3, $4, $5, $6, $7)
+                        ",
+                        Uuid::new_v4(),
+                        username,
+                        email,
+                        password_hash,
+                        &signal_names
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>(),
+                        self.client_ip(),
+                        self.user_agent(),
+                    )
+                    .execute(self.db()),
+                )
+                .await?;
+
+                self.state
+                    .metrics
+                    .observe_signup_screening("queued-for-review", &signal_names);
+                Ok(SignupResult::PendingReview)
+            }
+        }
+    }
+
+    /// Verify a CAPTCHA token sent with a signup. Failing to reach the CAPTCHA provider is logged
+    /// and treated as a failed CAPTCHA, so signups aren't created unchecked while it is down.
+    async fn verify_captcha(&self, token: &str) -> bool {
+        match signup_screening::verify_captcha(self.config(), token, self.client_ip()).await {
+            Ok(is_passed) => is_passed,
+            Err(error) => {
+                log::error!(
+                    "Failed to verify CAPTCHA: {} (request {})",
+                    error,
+                    self.request_id()
+                );
+                false
+            }
+        }
+    }
+
+    /// Find a page of pending signups, oldest first.
+    pub async fn find_pending_signups(
+        &self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<PendingSignup>> {
+        self.count_rows(
+            self.timed(
+                "find_pending_signups",
+                query_as!(
+                    PendingSignup,
+                    "SELECT * FROM pending_signups ORDER BY created_at, id LIMIT $1 OFFSET $2",
+                    limit,
+                    offset,
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Find a pending signup by its ID.
+    pub async fn find_pending_signup(&self, id: Uuid) -> Result<Option<PendingSignup>> {
+        self.timed(
+            "find_pending_signup",
+            query_as!(
+                PendingSignup,
+                "SELECT * FROM pending_signups WHERE id = $1",
+                id,
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Approve a pending signup, creating its user with the password they signed up with. This
+    /// will return none if the pending signup does not exist.
+    pub async fn approve_pending_signup(&self, id: Uuid) -> Result<Option<User>> {
+        let user = self
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    // Remove the pending signup along with creating its user, so it can't be
+                    // approved twice.
+                    let signup = self
+                        .timed(
+                            "approve_pending_signup",
+                            query_as!(
+                                PendingSignup,
+                                "DELETE FROM pending_signups WHERE id = $1 RETURNING *",
+                                id,
+                            )
+                            .fetch_optional(&mut *transaction),
+                        )
+                        .await?;
+                    match signup {
+                        Some(signup) => Ok(Some(
+                            self.insert_user(
+                                transaction,
+                                &signup.username,
+                                &signup.email,
+                                None,
+                                &signup.password_hash,
+                            )
+                            .await?,
+                        )),
+                        None => Ok(None),
+                    }
+                })
+            })
+            .await?;
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        self.invalidate_cached(&[CachedQuery::UserCount]).await;
+        self.start_email_verification(user.id, &user.username, &user.email)
+            .await?;
+        Ok(Some(user))
+    }
+
+    /// Reject a pending signup, discarding it without creating its user. This will return true if
+    /// the pending signup existed.
+    pub async fn reject_pending_signup(&self, id: Uuid) -> Result<bool> {
+        let result = self
+            .timed(
+                "reject_pending_signup",
+                query!("DELETE FROM pending_signups WHERE id = $1", id).execute(self.db()),
+            )
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Start a trial of the "pro" plan for a new user, if trials are enabled. The trial length is
+    /// specified by the TRIAL_DAYS environment variable.
+    async fn start_trial(&self, connection: &mut PgConnection, user_id: Uuid) -> Result<()> {
+        let Config { trial_days, .. } = self.config();
+        if *trial_days == 0 {
+            return Ok(());
+        }
+
+        let ends_at = Utc::now() + Duration::days((*trial_days).into());
+        self.timed(
+            "start_trial",
+            query!(
+                "INSERT INTO trials (user_id, plan, ends_at) VALUES ($1, $2, $3)",
+                user_id,
+                Plan::Pro.as_str(),
+                ends_at,
+            )
+            .execute(connection),
         )
-        .fetch_one(self.db())
         .await?;
 
+        Ok(())
+    }
+
+    /// Create a new email verification code for a user, store it in the Redis database and send it
+    /// to the user's email address. Failing to send the email is logged rather than returned, so
+    /// the user can still request another code later.
+    async fn start_email_verification(
+        &self,
+        user_id: Uuid,
+        username: &str,
+        email: &str,
+    ) -> Result<()> {
         // Create a new verification code.
         let verification_code = self.generate_verification_code();
 
         // Put the verification code in the Redis database.
         log::info!("Registering email verification code: {}", verification_code);
-        self.register_email_verification_code(id, email, &verification_code)
+        self.register_email_verification_code(user_id, email, &verification_code)
             .await?;
 
         // Send the same verification code to the user's email address.
-        log::info!("Sending email verification code: {}", verification_code);
+        log::info!(
+            "Sending email verification code: {} (request {})",
+            verification_code,
+            self.request_id()
+        );
         if self
             .send_email_verification_code(username, email, &verification_code)
             .await
             .is_err()
         {
             log::error!(
-                "Failed to send email verification code: {}",
-                verification_code
+                "Failed to send email verification code: {} (request {})",
+                verification_code,
+                self.request_id()
             );
         }
 
-        Ok(user)
+        Ok(())
     }
 
     /// Create a new user-friendly verification code. As of now, these are just a 6 character long
     /// strings of upper-case letters.
     fn generate_verification_code(&self) -> String {
         let mut rng = rand::thread_rng();
-        (0..6).map(|_| rng.gen_range('A'..'Z')).collect()
+        (0..6).map(|_| rng.gen_range('A'..='Z')).collect()
     }
 
-    /// Create the key a verification code can be stored under in the Redis database.
+    /// Create the key a verification code can be stored under in the cache.
     fn create_email_verification_key(&self, user_id: Uuid, email: &str) -> String {
         format!("verify/{}/{}", user_id, email)
     }
 
-    /// Put a new email verification code into the Regis database. The time it takes for the
+    /// Put a new email verification code into the cache. The time it takes for the
     /// verification code to expire is specified by the EMAIL_VERIFICATION_CODE_EXPIRATION_SECONDS
     /// environment variable.
     async fn register_email_verification_code(
@@ -121,19 +797,18 @@ impl Executor {
         } = self.config();
         let verification_key = self.create_email_verification_key(user_id, email);
 
-        self.redis()
-            .set_ex::<String, String, ()>(
-                verification_key,
-                verification_code.into(),
-                *email_verification_code_expiration_seconds as usize,
+        self.store()
+            .set(
+                &verification_key,
+                verification_code,
+                *email_verification_code_expiration_seconds,
             )
             .await?;
 
         Ok(())
     }
 
-    /// Send an email verification code to a user via email. Email settings are defined by the
-    /// server configuration.
+    /// Send an email verification code to a user via email.
     async fn send_email_verification_code(
         &self,
         username: &str,
@@ -141,39 +816,188 @@ impl Executor {
         verification_code: &str,
     ) -> Result<()> {
         let Config {
-            email_smtp,
-            email_smtp_port,
-            email_smtp_use_starttls,
-            email_verification_email_address,
-            email_verification_email_password,
+            email_sender_name,
+            email_verification_code_expiration_seconds,
             ..
         } = self.config();
 
-        let message = Message::builder()
-            .from(format!("rust-graphql-server <{}>", email_verification_email_address).parse()?)
-            .to(format!("{} <{}>", username, email).parse()?)
-            .subject("Verify your account")
-            .body(format!("Your verification code is: {}", verification_code))?;
+        let template = VerificationEmail {
+            sender_name: email_sender_name,
+            username,
+            code: verification_code,
+            expires_in: describe_duration(*email_verification_code_expiration_seconds),
+        };
+        self.send_email(username, email, &template).await
+    }
 
-        let relay = if *email_smtp_use_starttls {
-            SmtpTransport::starttls_relay(email_smtp)?
-        } else {
-            SmtpTransport::relay(email_smtp)?
+    /// Send a welcome email to a user whose email address was just verified. Failing to send the
+    /// email is logged rather than returned, as it isn't needed to use the account.
+    async fn send_welcome_email(&self, username: &str, email: &str) {
+        let template = WelcomeEmail {
+            sender_name: &self.config().email_sender_name,
+            username,
         };
+        if let Err(error) = self.send_email(username, email, &template).await {
+            log::error!(
+                "Failed to send welcome email: {} (request {})",
+                error,
+                self.request_id()
+            );
+        }
+    }
 
-        let mailer = relay
-            .port(*email_smtp_port)
-            .credentials(Credentials::new(
-                email_verification_email_address.clone(),
-                email_verification_email_password.clone(),
-            ))
-            .timeout(Some(Duration::from_secs(10)))
-            .build();
+    /// Send an email rendered from a template to a user, with both a plain text and an HTML part.
+    /// Email settings are defined by the server configuration.
+    async fn send_email(
+        &self,
+        username: &str,
+        email: &str,
+        template: &impl EmailTemplate,
+    ) -> Result<()> {
+        self.send_organization_email(None, username, email, template)
+            .await
+    }
+
+    /// Send an email rendered from a template to a user like `send_email`, on behalf of an
+    /// organization. Email settings the organization set override the server's.
+    #[cfg(feature = "email")]
+    async fn send_organization_email(
+        &self,
+        settings: Option<&OrganizationEmailSettings>,
+        username: &str,
+        email: &str,
+        template: &impl EmailTemplate,
+    ) -> Result<()> {
+        let Config {
+            email_sender_name,
+            email_verification_email_address,
+            ..
+        } = self.config();
 
-        mailer.send(&message)?;
+        let from_name = settings
+            .and_then(|settings| settings.from_name.as_ref())
+            .unwrap_or(email_sender_name);
+        let from_address = settings
+            .and_then(|settings| settings.from_address.as_ref())
+            .unwrap_or(email_verification_email_address);
+        let branding = settings
+            .map(OrganizationEmailSettings::branding)
+            .unwrap_or_default();
+        let message = Message::builder()
+            .from(Mailbox::new(Some(from_name.clone()), from_address.parse()?))
+            .to(Mailbox::new(Some(username.into()), email.parse()?))
+            .subject(template.subject())
+            .header(EntityRefId(self.request_id().into()))
+            .multipart(MultiPart::alternative_plain_html(
+                template.text()?,
+                template.html(&branding)?,
+            ))?;
+
+        let organization_id = settings.map(|settings| settings.organization_id);
+        self.deadline
+            .run(self.mailer().send(message, organization_id))
+            .await?
+    }
+
+    /// Log an email that would have been sent to a user. Emails can't be sent, as the server was
+    /// built without the "email" feature.
+    #[cfg(not(feature = "email"))]
+    async fn send_organization_email(
+        &self,
+        _settings: Option<&OrganizationEmailSettings>,
+        username: &str,
+        email: &str,
+        template: &impl EmailTemplate,
+    ) -> Result<()> {
+        log::info!(
+            "Email delivery is disabled. Not sending \"{}\" to {} <{}> (request {}).",
+            template.subject(),
+            username,
+            email,
+            self.request_id()
+        );
         Ok(())
     }
 
+    /// List a page of the emails in part of the outbox, with their bodies redacted.
+    #[cfg(feature = "email")]
+    pub async fn find_outbox_emails(
+        &self,
+        status: OutboxStatus,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<OutboxEmail>> {
+        self.deadline
+            .run(self.mailer().outbox(status, limit, offset))
+            .await?
+    }
+
+    /// List a page of the emails in part of the outbox. The outbox is always empty, as the server
+    /// was built without the "email" feature.
+    #[cfg(not(feature = "email"))]
+    pub async fn find_outbox_emails(
+        &self,
+        _status: OutboxStatus,
+        _limit: i64,
+        _offset: i64,
+    ) -> Result<Vec<OutboxEmail>> {
+        Ok(Vec::new())
+    }
+
+    /// Queue an email from the dead letter list to be sent again, on behalf of the admin making
+    /// the request. This will return the email as queued, or none if no dead email has the ID.
+    #[cfg(feature = "email")]
+    pub async fn retry_email(
+        &self,
+        viewer: AuthenticatedUser,
+        id: Uuid,
+    ) -> Result<Option<OutboxEmail>> {
+        let email = self.deadline.run(self.mailer().retry(id)).await??;
+        if email.is_some() {
+            log::info!(
+                "Retrying email {} for user {} (request {})",
+                id,
+                viewer.user_id,
+                self.request_id()
+            );
+        }
+
+        Ok(email)
+    }
+
+    /// Queue an email from the dead letter list to be sent again. There are never any, as the
+    /// server was built without the "email" feature.
+    #[cfg(not(feature = "email"))]
+    pub async fn retry_email(
+        &self,
+        _viewer: AuthenticatedUser,
+        _id: Uuid,
+    ) -> Result<Option<OutboxEmail>> {
+        Ok(None)
+    }
+
+    /// Delete every email in the dead letter list, on behalf of the admin making the request. This
+    /// will return the number of emails deleted.
+    #[cfg(feature = "email")]
+    pub async fn purge_dead_emails(&self, viewer: AuthenticatedUser) -> Result<u32> {
+        let count = self.deadline.run(self.mailer().purge_dead()).await??;
+        log::info!(
+            "Purged {} dead emails for user {} (request {})",
+            count,
+            viewer.user_id,
+            self.request_id()
+        );
+
+        Ok(count)
+    }
+
+    /// Delete every email in the dead letter list. There are never any, as the server was built
+    /// without the "email" feature.
+    #[cfg(not(feature = "email"))]
+    pub async fn purge_dead_emails(&self, _viewer: AuthenticatedUser) -> Result<u32> {
+        Ok(0)
+    }
+
     /// Attempt to verify a user's email address using the provided verification code. This function
     /// will return true if the verification is successful and false otherwise. The verification
     /// will fail if the user does not exist or the verification code is invalid.
@@ -191,200 +1015,5415 @@ impl Executor {
         let verification_key = self.create_email_verification_key(user.id, &user.email);
 
         // Try to retrieve the stored verification code.
-        let stored_verification_code = self
-            .redis()
-            .get::<String, Option<String>>(verification_key.clone())
-            .await?;
+        let stored_verification_code = self.store().get(&verification_key).await?;
 
         // Verify the stored code matches the one passed in.
         if stored_verification_code == Some(verification_code.into()) {
-            // Delete the verification code from Redis. We don't need it any more.
-            self.redis().del::<String, ()>(verification_key).await?;
+            // Delete the verification code from the cache. We don't need it any more.
+            self.store().del(&verification_key).await?;
 
             // Mark the user as having a verified email.
             let email_verified_at = Some(Utc::now());
-            query!(
-                "UPDATE users SET email_verified_at = $1 WHERE id = $2",
-                email_verified_at,
-                user_id,
-            )
-            .execute(self.db())
+            let email = &user.email;
+            self.transaction(|transaction| {
+                Box::pin(async move {
+                    self.timed(
+                        "verify_user_email_address",
+                        query!(
+                            "UPDATE users SET email_verified_at = $1 WHERE id = $2",
+                            email_verified_at,
+                            user_id,
+                        )
+                        .execute(&mut *transaction),
+                    )
+                    .await?;
+
+                    self.record_audit_event_in(
+                        transaction,
+                        AuditEventKind::EmailVerified,
+                        Some(user_id),
+                        Some(user_id),
+                        &[("email", email)],
+                    )
+                    .await
+                })
+            })
             .await?;
 
+            // Welcome the user now that their account is set up.
+            self.send_welcome_email(&user.username, &user.email).await;
+
             // Return true. We verified the email successfully.
             Ok(true)
         } else {
+            self.record_audit_event(
+                AuditEventKind::EmailVerificationFailed,
+                Some(user_id),
+                Some(user_id),
+                &[("email", &user.email)],
+            )
+            .await?;
+
             // Return false. The email verification failed. We didn't have a matching verification
             // code stored.
             Ok(false)
         }
     }
 
-    // Attempt to log in using the provided credentials. If successful return a session token to be
-    // sent along with future requests. Otherwise return nothing.
-    pub async fn login(&self, username: &str, password: &str) -> Result<Option<SessionToken>> {
-        if let Some(User {
-            id, password_hash, ..
-        }) = &self.find_user_by_username(username).await?
-        {
-            if bcrypt::verify(password, password_hash)? {
-                Ok(Some(self.create_session(*id).await?))
-            } else {
-                Ok(None)
-            }
-        } else {
-            Ok(None)
+    /// Create a new email verification code for a user and email it to them, replacing any code
+    /// they were sent before. Resends are throttled per user by a cooldown and an hourly limit.
+    /// This will return none if the user does not exist.
+    pub async fn resend_verification_email(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<ResendVerificationResult>> {
+        let user = match self.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        if user.email_verified_at.is_some() {
+            return Ok(Some(ResendVerificationResult::AlreadyVerified));
         }
-    }
 
-    /// Attempt to refresh a session token. The current session token will be used to create a new
-    /// session token with an extended lifespan. The current session token will be invalidated and
-    /// the new, refreshed token will be returned. No token will be returned if the provided session
-    /// token is invalid.
-    pub async fn refresh(&self, unverified_session_token: &str) -> Result<Option<SessionToken>> {
+        if let Some(retry_at) = self.reserve_verification_email_resend(user.id).await? {
+            log::warn!(
+                "Verification email resend throttled for user: {} (request {})",
+                user.id,
+                self.request_id()
+            );
+            return Ok(Some(ResendVerificationResult::Throttled(retry_at)));
+        }
+
+        self.start_email_verification(user.id, &user.username, &user.email)
+            .await?;
+        Ok(Some(ResendVerificationResult::Sent))
+    }
+
+    /// Count a verification email resend for a user against the resend cooldown and hourly limit.
+    /// This will return when another resend can be made if the user is throttled, in which case
+    /// the resend isn't counted.
+    async fn reserve_verification_email_resend(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<DateTime<Utc>>> {
         let Config {
-            session_token_secret,
-            session_token_expiration_seconds,
+            verification_email_resend_cooldown_seconds,
+            verification_email_hourly_resend_limit,
             ..
         } = self.config();
+        let throttled_until =
+            |ttl: Option<u32>| Utc::now() + Duration::seconds(ttl.unwrap_or(0).into());
 
-        if let Some(SessionTokenData {
-            session_id,
-            user_id,
-            ..
-        }) = SessionToken::decode(unverified_session_token, session_token_secret)
-        {
-            if let Some(current_session_token) = self.find_session(session_id).await? {
-                if current_session_token.to_string() != unverified_session_token {
-                    return Ok(None);
-                }
-
-                let refreshed_session_token = SessionToken::encode(
-                    SessionTokenData {
-                        session_id,
-                        session_token_id: Uuid::new_v4(),
-                        user_id,
-                    },
-                    session_token_secret,
-                );
-
-                self.redis()
-                    .set_ex::<String, String, ()>(
-                        session_id.to_string(),
-                        refreshed_session_token.to_string(),
-                        *session_token_expiration_seconds as usize,
-                    )
-                    .await?;
+        // Only the first resend during the cooldown claims it.
+        if *verification_email_resend_cooldown_seconds > 0 {
+            let cooldown_key = format!("verify/{}/resend-cooldown", user_id);
+            let claims = self
+                .store()
+                .incr(&cooldown_key, *verification_email_resend_cooldown_seconds)
+                .await?;
+            if claims > 1 {
+                let ttl = self.store().ttl(&cooldown_key).await?;
+                return Ok(Some(throttled_until(ttl)));
+            }
+        }
 
-                Ok(Some(refreshed_session_token))
-            } else {
-                Ok(None)
+        // Resends are counted for an hour from the first one, so the key's TTL is when the limit
+        // resets.
+        if *verification_email_hourly_resend_limit > 0 {
+            let resends_key = format!("verify/{}/resends", user_id);
+            let count = self.store().incr(&resends_key, 60 * 60).await?;
+            if count > (*verification_email_hourly_resend_limit).into() {
+                let ttl = self.store().ttl(&resends_key).await?;
+                return Ok(Some(throttled_until(ttl)));
             }
-        } else {
-            Ok(None)
         }
+
+        Ok(None)
     }
 
-    /// Use the provided session token to terminate a session. This function will return true if the
-    /// session is terminated successfully and false otherwise. The logout will fail and return
-    /// false if the session token is invalid.
-    pub async fn logout(&self, unverified_session_token: &str) -> Result<bool> {
+    /// Request a password reset for every user with the specified email address. Each user is
+    /// emailed a one-time reset token, which is stored in the Redis database until it expires.
+    /// Nothing happens if no user has the email address, so callers can't use this to find out
+    /// which email addresses are registered.
+    pub async fn request_password_reset(&self, email: &str) -> Result<()> {
         let Config {
-            session_token_secret,
+            password_reset_token_expiration_seconds,
             ..
         } = self.config();
 
-        if let Some(SessionTokenData { session_id, .. }) =
-            SessionToken::decode(unverified_session_token, session_token_secret)
+        for user in self.find_users_by_email(email).await? {
+            let reset_token = self.create_password_reset_token(user.id).await?;
+            log::info!(
+                "Sending password reset token to user: {} (request {})",
+                user.id,
+                self.request_id()
+            );
+            let template = PasswordResetEmail {
+                sender_name: &self.config().email_sender_name,
+                username: &user.username,
+                token: &reset_token,
+                expires_in: describe_duration(*password_reset_token_expiration_seconds),
+            };
+            if self
+                .send_email(&user.username, &user.email, &template)
+                .await
+                .is_err()
+            {
+                log::error!(
+                    "Failed to send password reset token to user: {} (request {})",
+                    user.id,
+                    self.request_id()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create a one-time password reset token for a user, which is stored in the Redis database
+    /// until it expires.
+    async fn create_password_reset_token(&self, user_id: Uuid) -> Result<String> {
+        let reset_token = self.generate_token();
+        self.redis()
+            .set_ex::<String, String, ()>(
+                self.create_password_reset_key(&reset_token),
+                user_id.to_string(),
+                self.config().password_reset_token_expiration_seconds as usize,
+            )
+            .await?;
+
+        Ok(reset_token)
+    }
+
+    /// Require a user to reset their password on behalf of the admin making the request, as is
+    /// done when the user's account may have been compromised. The user can't log in with their
+    /// password until they reset it, every active session of the user is terminated, and the user
+    /// is emailed a password reset token. This will return the user, or none if the user does not
+    /// exist.
+    pub async fn force_password_reset(
+        &self,
+        viewer: AuthenticatedUser,
+        user_id: Uuid,
+    ) -> Result<Option<User>> {
+        let user = self
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let user = self
+                        .timed(
+                            "force_password_reset",
+                            query_as!(
+                                User,
+                                "UPDATE users
+                                SET password_reset_required_at =
+                                        COALESCE(password_reset_required_at, NOW()),
+                                    updated_at = NOW()
+                                WHERE id = $1 AND deleted_at IS NULL
+                                RETURNING *",
+                                user_id
+                            )
+                            .fetch_optional(&mut *transaction),
+                        )
+                        .await?;
+
+                    if user.is_some() {
+                        self.record_audit_event_in(
+                            transaction,
+                            AuditEventKind::PasswordResetForced,
+                            Some(user_id),
+                            Some(viewer.user_id),
+                            &[],
+                        )
+                        .await?;
+                    }
+                    Ok(user)
+                })
+            })
+            .await?;
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        self.delete_user_sessions(user_id, None).await?;
+
+        let expiration_seconds = self.config().password_reset_token_expiration_seconds;
+        let reset_token = self.create_password_reset_token(user_id).await?;
+        log::info!(
+            "Forced password reset for user: {} (request {})",
+            user_id,
+            self.request_id()
+        );
+        let template = ForcedPasswordResetEmail {
+            sender_name: &self.config().email_sender_name,
+            username: &user.username,
+            token: &reset_token,
+            expires_in: describe_duration(expiration_seconds),
+        };
+        if let Err(error) = self
+            .send_email(&user.username, &user.email, &template)
+            .await
         {
-            self.delete_session(session_id).await
-        } else {
-            Ok(false)
+            log::error!(
+                "Failed to send forced password reset token to user: {}: {} (request {})",
+                user_id,
+                error,
+                self.request_id()
+            );
+        }
+
+        Ok(Some(user))
+    }
+
+    /// Find the user a password reset token was issued to. This will return none if the token is
+    /// invalid, has expired or was already used.
+    pub async fn find_user_by_password_reset_token(
+        &self,
+        reset_token: &str,
+    ) -> Result<Option<User>> {
+        let reset_key = self.create_password_reset_key(reset_token);
+        match self
+            .redis()
+            .get::<String, Option<String>>(reset_key)
+            .await?
+            .and_then(|user_id| Uuid::parse_str(&user_id).ok())
+        {
+            Some(user_id) => self.find_user(user_id).await,
+            None => Ok(None),
         }
     }
 
-    /// Find a session by ID and return its associated session token. This will return none if the
-    /// session does not exist.
-    async fn find_session(&self, session_id: Uuid) -> Result<Option<SessionToken>> {
+    /// Attempt to reset a user's password using a password reset token. The token can only be used
+    /// once, and every active session of the user is terminated when the password changes. This
+    /// also lifts any password reset required by an admin. This will return true if the token was
+    /// valid and the password was reset successfully.
+    pub async fn reset_password(&self, reset_token: &str, new_password: &str) -> Result<bool> {
         let Config {
-            session_token_secret,
-            ..
+            password_hash_cost, ..
         } = self.config();
 
-        Ok(self
+        let reset_key = self.create_password_reset_key(reset_token);
+        let user_id = match self
             .redis()
-            .get::<String, Option<String>>(session_id.to_string())
+            .get::<String, Option<String>>(reset_key.clone())
             .await?
-            .map(|session_token| SessionToken::verify(&session_token, session_token_secret))
-            .flatten())
+            .and_then(|user_id| Uuid::parse_str(&user_id).ok())
+        {
+            Some(user_id) => user_id,
+            None => return Ok(false),
+        };
+
+        // Delete the token so it can't be used again. If it was already deleted, another request
+        // used it first.
+        if self.redis().del::<String, u32>(reset_key).await? == 0 {
+            return Ok(false);
+        }
+
+        let password_hash = bcrypt::hash(new_password, *password_hash_cost)?;
+        let is_reset = self
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let result = self
+                        .timed(
+                            "reset_password",
+                            query!(
+                                "UPDATE users
+                                SET password_hash = $1, password_reset_required_at = NULL,
+                                    updated_at = NOW()
+                                WHERE id = $2",
+                                password_hash,
+                                user_id,
+                            )
+                            .execute(&mut *transaction),
+                        )
+                        .await?;
+
+                    if result.rows_affected() == 0 {
+                        return Ok(false);
+                    }
+
+                    self.record_audit_event_in(
+                        transaction,
+                        AuditEventKind::PasswordReset,
+                        Some(user_id),
+                        Some(user_id),
+                        &[],
+                    )
+                    .await?;
+                    Ok(true)
+                })
+            })
+            .await?;
+
+        if is_reset {
+            self.delete_user_sessions(user_id, None).await?;
+        }
+        Ok(is_reset)
     }
 
-    /// Create a session for the specified user. The returned token includes the session ID, the
-    /// user's ID and a unique session token ID.
-    async fn create_session(&self, user_id: Uuid) -> Result<SessionToken> {
-        let Config {
-            session_token_secret,
-            ..
-        } = self.config();
+    /// Update a user's username and email address. Details that are none are left unchanged. If the
+    /// email address changes, it is marked as unverified and a new verification code is sent to
+    /// it. In a dry run, nothing is saved or sent. This will return the updated user, or none if
+    /// the user does not exist.
+    pub async fn update_profile(
+        &self,
+        user_id: Uuid,
+        username: Option<&str>,
+        email: Option<&str>,
+        dry_run: bool,
+    ) -> Result<Option<User>> {
+        let current = match self.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
 
-        let session_id = Uuid::new_v4();
-        let session_token_id = Uuid::new_v4();
-        let session_token_data = SessionTokenData {
-            session_id,
-            session_token_id,
+        let username = username.unwrap_or(&current.username);
+        let email = email.unwrap_or(&current.email);
+        let is_email_changed = email != current.email;
+        let email_verified_at = if is_email_changed {
+            None
+        } else {
+            current.email_verified_at
+        };
+        if dry_run {
+            return Ok(Some(User {
+                username: username.into(),
+                email: email.into(),
+                email_verified_at,
+                updated_at: Utc::now(),
+                ..current
+            }));
+        }
+
+        let current_email = &current.email;
+        let user = self
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let user = self
+                        .timed(
+                            "update_profile",
+                            query_as!(
+                                User,
+                                "
+                                UPDATE users
+                                SET username = $1, email = $2, email_verified_at = $3,
+                                    updated_at = NOW()
+                                WHERE id = $4
+                                RETURNING *
+                                ",
+                                username,
+                                email,
+                                email_verified_at,
+                                user_id,
+                            )
+                            .fetch_optional(&mut *transaction),
+                        )
+                        .await?;
+
+                    if let Some(user) = &user {
+                        if is_email_changed {
+                            self.record_audit_event_in(
+                                transaction,
+                                AuditEventKind::EmailChanged,
+                                Some(user.id),
+                                Some(user.id),
+                                &[("from", current_email), ("to", &user.email)],
+                            )
+                            .await?;
+                        }
+                    }
+                    Ok(user)
+                })
+            })
+            .await?;
+
+        if let Some(user) = &user {
+            if is_email_changed {
+                self.start_email_verification(user.id, &user.username, &user.email)
+                    .await?;
+            }
+        }
+
+        Ok(user)
+    }
+
+    /// Scan an uploaded file for viruses with the configured scanner. This will return true if the
+    /// file is clean or scanning isn't configured. Files that can't be scanned are treated as
+    /// infected.
+    pub async fn scan_file(&self, contents: &[u8]) -> Result<bool> {
+        let scanner = match scanning::scanner(self.config()) {
+            Some(scanner) => scanner,
+            None => return Ok(true),
+        };
+
+        match scanner.scan(contents).await {
+            Ok(ScanVerdict::Clean) => Ok(true),
+            Ok(ScanVerdict::Infected(signature)) => {
+                log::warn!(
+                    "Rejected an uploaded file infected with {} according to {}.",
+                    signature,
+                    scanner.name()
+                );
+                Ok(false)
+            }
+            Err(error) => {
+                log::error!("Failed to scan an uploaded file: {}", error);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Store an image as a user's avatar, replacing their current avatar. Each avatar is stored at
+    /// a new path, so URLs to the previous avatar stop working. The avatar is processed into
+    /// renditions in the background, and can't be downloaded until it is. This will return the
+    /// updated user, or none if the user does not exist.
+    pub async fn update_avatar(
+        &self,
+        user_id: Uuid,
+        format: ImageFormat,
+        contents: &[u8],
+    ) -> Result<Option<User>> {
+        let current = match self.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let path = format!(
+            "avatars/{}/{}.{}",
             user_id,
+            Uuid::new_v4(),
+            format.extension()
+        );
+        self.files()
+            .put(&path, contents, format.content_type())
+            .await?;
+        let user = self
+            .transaction(|transaction| {
+                let path = path.clone();
+                Box::pin(async move {
+                    let user = self
+                        .timed(
+                            "update_avatar",
+                            query_as!(
+                                User,
+                                "
+                                UPDATE users
+                                SET avatar_path = $1, avatar_sizes = NULL, updated_at = NOW()
+                                WHERE id = $2
+                                RETURNING *
+                                ",
+                                path,
+                                user_id,
+                            )
+                            .fetch_optional(&mut *transaction),
+                        )
+                        .await?;
+                    if user.is_some() {
+                        self.timed(
+                            "insert_avatar_job",
+                            query!(
+                                "INSERT INTO avatar_jobs (avatar_path, user_id) VALUES ($1, $2)",
+                                path,
+                                user_id,
+                            )
+                            .execute(&mut *transaction),
+                        )
+                        .await?;
+                    }
+                    Ok(user)
+                })
+            })
+            .await?;
+
+        // The old avatar is no longer needed once the user points at the new one, and the new one
+        // isn't needed if the user was deleted in the meantime.
+        let unused_path = match &user {
+            Some(_) => current.avatar_path,
+            None => Some(path),
+        };
+        if let Some(unused_path) = unused_path {
+            self.delete_avatar_files(&unused_path).await;
+        }
+
+        Ok(user)
+    }
+
+    /// Process uploaded avatars into renditions, oldest first. Avatars are claimed while they are
+    /// processed, so they are only processed once if several servers are running, and are
+    /// processed again later if processing fails. Avatars that can't be decoded, or fail to be
+    /// processed too many times, are left without renditions. This will return the number of
+    /// avatars claimed.
+    pub async fn process_avatars(&self) -> Result<usize> {
+        let jobs = self
+            .timed(
+                "claim_avatar_jobs",
+                query_as!(
+                    AvatarJob,
+                    "UPDATE avatar_jobs SET attempts = attempts + 1, run_at = $2
+                    WHERE avatar_path IN (
+                        SELECT avatar_path FROM avatar_jobs WHERE run_at <= NOW()
+                        ORDER BY run_at LIMIT $1 FOR UPDATE SKIP LOCKED
+                    )
+                    RETURNING *",
+                    avatars::PROCESS_BATCH_SIZE,
+                    Utc::now() + Duration::seconds(avatars::CLAIM_SECONDS),
+                )
+                .fetch_all(self.db()),
+            )
+            .await?;
+
+        for job in &jobs {
+            if let Err(error) = self.process_avatar(job).await {
+                log::error!(
+                    "Failed to process avatar {}: {} (request {})",
+                    job.avatar_path,
+                    error,
+                    self.request_id()
+                );
+            }
+        }
+
+        Ok(jobs.len())
+    }
+
+    /// Process an uploaded avatar into renditions, and record their sizes if the avatar still
+    /// belongs to its user. The avatar is unclaimed once it is done with, and left to be processed
+    /// again otherwise.
+    async fn process_avatar(&self, job: &AvatarJob) -> Result<()> {
+        let sizes = match self.render_avatar(&job.avatar_path).await {
+            Ok(sizes) => sizes,
+            Err(error) if job.attempts < avatars::MAX_ATTEMPTS => return Err(error),
+            Err(error) => {
+                log::error!(
+                    "Gave up on processing avatar {} after {} attempts: {} (request {})",
+                    job.avatar_path,
+                    job.attempts,
+                    error,
+                    self.request_id()
+                );
+                Some(Vec::new())
+            }
+        };
+
+        if let Some(sizes) = sizes {
+            let updated = self
+                .timed(
+                    "update_avatar_sizes",
+                    query!(
+                        "UPDATE users SET avatar_sizes = $1 WHERE id = $2 AND avatar_path = $3",
+                        &sizes,
+                        job.user_id,
+                        job.avatar_path,
+                    )
+                    .execute(self.db()),
+                )
+                .await?
+                .rows_affected();
+            // The renditions aren't needed if the avatar was replaced while it was processed.
+            if updated == 0 {
+                self.delete_avatar_files(&job.avatar_path).await;
+            }
+        }
+
+        self.timed(
+            "delete_avatar_job",
+            query!(
+                "DELETE FROM avatar_jobs WHERE avatar_path = $1",
+                job.avatar_path
+            )
+            .execute(self.db()),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Render an uploaded avatar into renditions on a blocking thread, and store them next to the
+    /// avatar. This will return the sizes of the stored renditions, which are empty if the avatar
+    /// can't be decoded, or none if the avatar no longer exists.
+    async fn render_avatar(&self, avatar_path: &str) -> Result<Option<Vec<i32>>> {
+        let contents = match self.files().get(avatar_path).await? {
+            Some(contents) => contents,
+            None => return Ok(None),
+        };
+        let renditions = match task::spawn_blocking(move || avatars::render(&contents)).await {
+            Ok(renditions) => renditions,
+            Err(error) => {
+                log::warn!(
+                    "Failed to decode avatar {}, so it has no renditions: {} (request {})",
+                    avatar_path,
+                    error,
+                    self.request_id()
+                );
+                return Ok(Some(Vec::new()));
+            }
         };
 
+        let mut sizes = Vec::new();
+        for rendition in &renditions {
+            let path = avatars::rendition_path(avatar_path, rendition.size, rendition.format);
+            self.files()
+                .put(&path, &rendition.contents, rendition.format.content_type())
+                .await?;
+            if !sizes.contains(&rendition.size) {
+                sizes.push(rendition.size);
+            }
+        }
+        Ok(Some(sizes))
+    }
+
+    /// Delete an avatar that is no longer used from file storage, along with any renditions of it.
+    /// Failures are only logged, as nothing points at the files anymore.
+    async fn delete_avatar_files(&self, avatar_path: &str) {
+        let mut paths = vec![avatar_path.to_string()];
+        for size in avatars::RENDITION_SIZES {
+            for format in &AvatarFormat::ALL {
+                paths.push(avatars::rendition_path(avatar_path, *size, *format));
+            }
+        }
+        for path in paths {
+            if let Err(error) = self.files().delete(&path).await {
+                log::error!("Failed to delete unused avatar {}: {}", path, error);
+            }
+        }
+    }
+
+    /// Change a user's password, checking their current password first. Every other active session
+    /// of the user is terminated, but the session making the change is kept. In a dry run, only
+    /// the current password is checked. This will return true if the current password was correct
+    /// and the password was changed successfully.
+    pub async fn change_password(
+        &self,
+        viewer: AuthenticatedUser,
+        current_password: &str,
+        new_password: &str,
+        dry_run: bool,
+    ) -> Result<bool> {
         let Config {
-            session_token_expiration_seconds,
-            ..
+            password_hash_cost, ..
         } = self.config();
 
-        let session_token = SessionToken::encode(session_token_data, session_token_secret);
+        let user = match self.find_user(viewer.user_id).await? {
+            Some(user) => user,
+            None => return Ok(false),
+        };
+        if !bcrypt::verify(current_password, &user.password_hash)? {
+            return Ok(false);
+        }
+        if dry_run {
+            return Ok(true);
+        }
 
-        self.redis()
-            .set_ex::<String, String, ()>(
-                session_id.to_string(),
-                session_token.to_string(),
-                *session_token_expiration_seconds as usize,
+        let password_hash = bcrypt::hash(new_password, *password_hash_cost)?;
+        let user_id = user.id;
+        self.transaction(|transaction| {
+            Box::pin(async move {
+                self.timed(
+                    "change_password",
+                    query!(
+                        "UPDATE users SET password_hash = $1, updated_at = NOW() WHERE id = $2",
+                        password_hash,
+                        user_id,
+                    )
+                    .execute(&mut *transaction),
+                )
+                .await?;
+
+                self.record_audit_event_in(
+                    transaction,
+                    AuditEventKind::PasswordChanged,
+                    Some(user_id),
+                    Some(viewer.user_id),
+                    &[],
+                )
+                .await
+            })
+        })
+        .await?;
+
+        self.delete_user_sessions(user_id, Some(viewer.session_id))
+            .await?;
+        Ok(true)
+    }
+
+    /// Soft delete a user, and terminate every active session of the user. The user's row is kept,
+    /// but they are left out of every lookup and can no longer log in. This will return true if
+    /// the user was found and hadn't already been deleted.
+    pub async fn soft_delete_user(&self, user_id: Uuid) -> Result<bool> {
+        let result = self
+            .timed(
+                "soft_delete_user",
+                query!(
+                    "UPDATE users SET deleted_at = NOW(), updated_at = NOW()
+                    WHERE id = $1 AND deleted_at IS NULL",
+                    user_id
+                )
+                .execute(self.db()),
             )
             .await?;
 
-        Ok(session_token)
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        self.invalidate_cached(&[CachedQuery::UserCount]).await;
+        self.delete_user_sessions(user_id, None).await?;
+        log::info!(
+            "Soft deleted user: {} (request {})",
+            user_id,
+            self.request_id()
+        );
+        Ok(true)
     }
 
-    /// Terminate a session by ID. This will return true if the session was found and deleted. False
-    /// will be returned otherwise.
-    async fn delete_session(&self, session_id: Uuid) -> Result<bool> {
-        let count = self
-            .redis()
-            .del::<String, u32>(session_id.to_string())
+    /// Schedule a destructive action on behalf of a user, to be done once the undo grace period is
+    /// over. Scheduling an action that is already pending returns the pending action, without
+    /// extending its grace period. This will return none if the grace period is zero, in which
+    /// case the action is done right away.
+    pub async fn schedule_action(
+        &self,
+        kind: PendingActionKind,
+        user_id: Uuid,
+        target_id: Uuid,
+    ) -> Result<Option<PendingAction>> {
+        let grace_period_seconds = self.config().undo_grace_period_seconds;
+        if grace_period_seconds == 0 {
+            self.execute_action(kind, target_id).await?;
+            return Ok(None);
+        }
+
+        let action = self
+            .timed(
+                "schedule_action",
+                query_as!(
+            PendingAction,
+            "INSERT INTO pending_actions (id, kind, user_id, target_id, undo_token, execute_at)
+            VALUES ($1, $2, $3, $4, $5, NOW() + make_interval(secs => $6))
+            ON CONFLICT (kind, target_id) DO UPDATE SET kind = EXCLUDED.kind
+            RETURNING *",
+            Uuid::new_v4(),
+            kind.as_str(),
+            user_id,
+            target_id,
+            self.generate_token(),
+            f64::from(grace_period_seconds),
+        )
+                .fetch_one(self.db()),
+            )
             .await?;
 
-        Ok(count != 0)
+        log::info!(
+            "Scheduled {} of {} for {} (request {})",
+            kind,
+            target_id,
+            action.execute_at.to_rfc3339(),
+            self.request_id()
+        );
+        Ok(Some(action))
     }
 
-    /// Find a user by ID. This will return none if the user is not found.
-    pub async fn find_user(&self, id: Uuid) -> Result<Option<User>> {
-        Ok(query_as!(User, "SELECT * FROM users WHERE id = $1", id)
-            .fetch_optional(self.db())
-            .await?)
+    /// Undo a pending action whose grace period isn't over yet. This will return true if the
+    /// action was undone, or false if it was already done or the undo token is invalid.
+    pub async fn undo_action(&self, undo_token: &str) -> Result<bool> {
+        let action = self
+            .timed(
+                "undo_action",
+                query_as!(
+            PendingAction,
+            "DELETE FROM pending_actions WHERE undo_token = $1 AND execute_at > NOW() RETURNING *",
+            undo_token
+        )
+                .fetch_optional(self.db()),
+            )
+            .await?;
+
+        match action {
+            Some(action) => {
+                log::info!(
+                    "Undid {} of {} (request {})",
+                    action.kind,
+                    action.target_id,
+                    self.request_id()
+                );
+                Ok(true)
+            }
+            None => Ok(false),
+        }
     }
 
-    /// Find a user by their username. This will return none if no user has the specified username.
-    pub async fn find_user_by_username(&self, username: &str) -> Result<Option<User>> {
-        Ok(
-            query_as!(User, "SELECT * FROM users WHERE username = $1", username)
-                .fetch_optional(self.db())
-                .await?,
-        )
+    /// Do the pending actions whose grace period is over, oldest first. Actions are removed as
+    /// they are claimed, so they are only done once if several servers are running. Actions that
+    /// fail are logged and dropped. This will return the number of actions claimed.
+    pub async fn execute_due_actions(&self) -> Result<usize> {
+        let actions = self
+            .timed(
+                "execute_due_actions",
+                query_as!(
+                    PendingAction,
+                    "DELETE FROM pending_actions
+                    WHERE id IN (
+                        SELECT id FROM pending_actions WHERE execute_at <= NOW()
+                        ORDER BY execute_at LIMIT $1 FOR UPDATE SKIP LOCKED
+                    )
+                    RETURNING *",
+                    pending_actions::EXECUTE_BATCH_SIZE,
+                )
+                .fetch_all(self.db()),
+            )
+            .await?;
+
+        for action in &actions {
+            let result = match action.kind.parse() {
+                Ok(kind) => self.execute_action(kind, action.target_id).await,
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                log::error!(
+                    "Failed to execute pending action {}: {} (request {})",
+                    action.id,
+                    error,
+                    self.request_id()
+                );
+            }
+        }
+
+        Ok(actions.len())
+    }
+
+    /// Do a destructive action right away.
+    async fn execute_action(&self, kind: PendingActionKind, target_id: Uuid) -> Result<()> {
+        match kind {
+            PendingActionKind::DeleteAccount => {
+                self.soft_delete_user(target_id).await?;
+            }
+        }
+
+        log::info!(
+            "Executed {} of {} (request {})",
+            kind,
+            target_id,
+            self.request_id()
+        );
+        Ok(())
+    }
+
+    /// Deactivate a user on behalf of the admin making the request, and terminate every active
+    /// session of the user. Deactivated users can't log in until they are reactivated, but are
+    /// still found by lookups. The change is recorded in the audit log. In a dry run, the user is
+    /// returned as they would be without being saved, and their sessions are kept. This will
+    /// return the deactivated user, or none if the user does not exist.
+    pub async fn deactivate_user(
+        &self,
+        viewer: AuthenticatedUser,
+        user_id: Uuid,
+        dry_run: bool,
+    ) -> Result<Option<User>> {
+        if dry_run {
+            let now = Utc::now();
+            let user = match self.find_user(user_id).await? {
+                Some(user) => user,
+                None => return Ok(None),
+            };
+            if user.deactivated_at.is_none() {
+                let changes = vec![deactivated_at_change(None, Some(now))];
+                self.record_audited_action(AuditEventKind::UserDeactivated, user_id, true, changes);
+            }
+            return Ok(Some(User {
+                deactivated_at: user.deactivated_at.or(Some(now)),
+                updated_at: now,
+                ..user
+            }));
+        }
+
+        let deactivated = self
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let user = self
+                        .timed(
+                            "deactivate_user",
+                            query_as!(
+                                User,
+                                "
+                                UPDATE users SET deactivated_at = NOW(), updated_at = NOW()
+                                WHERE id = $1 AND deleted_at IS NULL AND deactivated_at IS NULL
+                                RETURNING *
+                                ",
+                                user_id
+                            )
+                            .fetch_optional(&mut *transaction),
+                        )
+                        .await?;
+                    let user = match user {
+                        Some(user) => user,
+                        None => return Ok(None),
+                    };
+
+                    let changes = vec![deactivated_at_change(None, user.deactivated_at)];
+                    self.record_audit_changes_in(
+                        transaction,
+                        AuditEventKind::UserDeactivated,
+                        Some(user_id),
+                        Some(viewer.user_id),
+                        &[],
+                        &changes,
+                    )
+                    .await?;
+                    Ok(Some((user, changes)))
+                })
+            })
+            .await?;
+        let user = match deactivated {
+            Some((user, changes)) => {
+                self.record_audited_action(
+                    AuditEventKind::UserDeactivated,
+                    user_id,
+                    false,
+                    changes,
+                );
+                Some(user)
+            }
+            // The user is either already deactivated or doesn't exist.
+            None => self.find_user(user_id).await?,
+        };
+
+        if user.is_some() {
+            self.delete_user_sessions(user_id, None).await?;
+            log::info!(
+                "Deactivated user: {} (request {})",
+                user_id,
+                self.request_id()
+            );
+        }
+        Ok(user)
+    }
+
+    /// Reactivate a deactivated user on behalf of the admin making the request, so they can log in
+    /// again. The change is recorded in the audit log. This will return the reactivated user, or
+    /// none if the user does not exist.
+    pub async fn reactivate_user(
+        &self,
+        viewer: AuthenticatedUser,
+        user_id: Uuid,
+    ) -> Result<Option<User>> {
+        let reactivated = self
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let deactivated_at = self
+                        .timed(
+                            "lock_deactivated_user",
+                            query!(
+                                "
+                                SELECT deactivated_at FROM users
+                                WHERE id = $1 AND deleted_at IS NULL AND deactivated_at IS NOT NULL
+                                FOR UPDATE
+                                ",
+                                user_id
+                            )
+                            .fetch_optional(&mut *transaction),
+                        )
+                        .await?;
+                    let deactivated_at = match deactivated_at {
+                        Some(row) => row.deactivated_at,
+                        None => return Ok(None),
+                    };
+
+                    let user = self
+                        .timed(
+                            "reactivate_user",
+                            query_as!(
+                                User,
+                                "UPDATE users SET deactivated_at = NULL, updated_at = NOW()
+                                WHERE id = $1
+                                RETURNING *",
+                                user_id
+                            )
+                            .fetch_one(&mut *transaction),
+                        )
+                        .await?;
+
+                    let changes = vec![deactivated_at_change(deactivated_at, None)];
+                    self.record_audit_changes_in(
+                        transaction,
+                        AuditEventKind::UserReactivated,
+                        Some(user_id),
+                        Some(viewer.user_id),
+                        &[],
+                        &changes,
+                    )
+                    .await?;
+                    Ok(Some((user, changes)))
+                })
+            })
+            .await?;
+        let user = match reactivated {
+            Some((user, changes)) => {
+                self.record_audited_action(
+                    AuditEventKind::UserReactivated,
+                    user_id,
+                    false,
+                    changes,
+                );
+                user
+            }
+            // The user is either not deactivated or doesn't exist.
+            None => return self.find_user(user_id).await,
+        };
+
+        log::info!(
+            "Reactivated user: {} (request {})",
+            user_id,
+            self.request_id()
+        );
+        Ok(Some(user))
+    }
+
+    /// Place a user under a legal hold on behalf of the admin making the request, exempting the
+    /// user and their events from retention purges and permanent deletion until the hold is
+    /// released. Soft deleted users can be held too. The reason, such as a case number, is
+    /// recorded in the audit log. This will return the user, or none if the user does not exist.
+    pub async fn apply_legal_hold(
+        &self,
+        viewer: AuthenticatedUser,
+        user_id: Uuid,
+        reason: Option<&str>,
+    ) -> Result<Option<User>> {
+        let metadata = reason.map(|reason| ("reason", reason));
+        let metadata = metadata.as_slice();
+        let user = self
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let user = self
+                        .timed(
+                            "apply_legal_hold",
+                            query_as!(
+                                User,
+                                "UPDATE users SET legal_hold_at = NOW(), updated_at = NOW()
+                                WHERE id = $1 AND legal_hold_at IS NULL
+                                RETURNING *",
+                                user_id
+                            )
+                            .fetch_optional(&mut *transaction),
+                        )
+                        .await?;
+
+                    if user.is_some() {
+                        self.record_audit_event_in(
+                            transaction,
+                            AuditEventKind::LegalHoldApplied,
+                            Some(user_id),
+                            Some(viewer.user_id),
+                            metadata,
+                        )
+                        .await?;
+                    }
+                    Ok(user)
+                })
+            })
+            .await?;
+        let user = match user {
+            Some(user) => user,
+            // The user is either already held or doesn't exist.
+            None => return self.find_user_including_deleted(user_id).await,
+        };
+
+        log::info!(
+            "Applied legal hold to user: {} (request {})",
+            user_id,
+            self.request_id()
+        );
+        Ok(Some(user))
+    }
+
+    /// Release a user from a legal hold on behalf of the admin making the request, so retention
+    /// purges and deletion apply to them again. The reason is recorded in the audit log. This will
+    /// return the user, or none if the user does not exist.
+    pub async fn release_legal_hold(
+        &self,
+        viewer: AuthenticatedUser,
+        user_id: Uuid,
+        reason: Option<&str>,
+    ) -> Result<Option<User>> {
+        let metadata = reason.map(|reason| ("reason", reason));
+        let metadata = metadata.as_slice();
+        let user = self
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let user = self
+                        .timed(
+                            "release_legal_hold",
+                            query_as!(
+                                User,
+                                "UPDATE users SET legal_hold_at = NULL, updated_at = NOW()
+                                WHERE id = $1 AND legal_hold_at IS NOT NULL
+                                RETURNING *",
+                                user_id
+                            )
+                            .fetch_optional(&mut *transaction),
+                        )
+                        .await?;
+
+                    if user.is_some() {
+                        self.record_audit_event_in(
+                            transaction,
+                            AuditEventKind::LegalHoldReleased,
+                            Some(user_id),
+                            Some(viewer.user_id),
+                            metadata,
+                        )
+                        .await?;
+                    }
+                    Ok(user)
+                })
+            })
+            .await?;
+        let user = match user {
+            Some(user) => user,
+            // The user is either not held or doesn't exist.
+            None => return self.find_user_including_deleted(user_id).await,
+        };
+
+        log::info!(
+            "Released legal hold on user: {} (request {})",
+            user_id,
+            self.request_id()
+        );
+        Ok(Some(user))
+    }
+
+    /// Find a page of users under a legal hold, including soft deleted users, longest held first.
+    pub async fn find_legal_holds(&self, limit: i64, offset: i64) -> Result<Vec<User>> {
+        self.count_rows(
+            self.timed(
+                "find_legal_holds",
+                query_as!(
+                    User,
+                    "SELECT * FROM users WHERE legal_hold_at IS NOT NULL
+                    ORDER BY legal_hold_at, id LIMIT $1 OFFSET $2",
+                    limit,
+                    offset,
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Permanently delete a user along with their roles, and terminate every active session of the
+    /// user. This also deletes users that were soft deleted, but never users under a legal hold.
+    /// This will return true if the user was found and deleted.
+    pub async fn delete_user(&self, user_id: Uuid) -> Result<bool> {
+        let result = self
+            .timed(
+                "delete_user",
+                query!(
+                    "DELETE FROM users WHERE id = $1 AND legal_hold_at IS NULL",
+                    user_id
+                )
+                .execute(self.db()),
+            )
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        // Feedback submitted by the user is kept, but no longer points at them.
+        self.invalidate_cached(&[CachedQuery::UserCount, CachedQuery::Feedback])
+            .await;
+        self.delete_user_sessions(user_id, None).await?;
+        Ok(true)
+    }
+
+    /// Create a new random token, such as a password reset token. These are long random strings, as
+    /// unlike verification codes they aren't tied to a user the caller has to know about.
+    fn generate_token(&self) -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect()
+    }
+
+    /// Create the key a password reset token can be stored under in the Redis database.
+    fn create_password_reset_key(&self, reset_token: &str) -> String {
+        format!("password-reset/{}", reset_token)
+    }
+
+    // Attempt to log in using the provided credentials. If successful return a session token to be
+    // sent along with future requests. Failed attempts are counted per username and per client IP
+    // address. Once either reaches its threshold, login is locked for it until the lockout expires,
+    // and credentials aren't checked at all in the meantime.
+    pub async fn login(&self, username: &str, password: &str) -> Result<LoginResult> {
+        let lockouts = self.login_lockouts(username);
+        if let Some(locked_until) = LoginLockout::find_lock(self.store(), &lockouts).await? {
+            self.record_failed_login(None, username, "locked").await?;
+            return Ok(LoginResult::Locked(locked_until));
+        }
+
+        let user = self.find_user_by_username(username).await?;
+        if let Some(User {
+            id,
+            password_hash,
+            deactivated_at,
+            password_reset_required_at,
+            ..
+        }) = &user
+        {
+            if bcrypt::verify(password, password_hash)? {
+                self.store()
+                    .del(&self.create_login_failures_key("username", username))
+                    .await?;
+                // Only callers that know the password find out the account is deactivated.
+                if deactivated_at.is_some() {
+                    self.record_failed_login(Some(*id), username, "deactivated")
+                        .await?;
+                    return Ok(LoginResult::Deactivated);
+                }
+                if password_reset_required_at.is_some() {
+                    self.record_failed_login(Some(*id), username, "password-reset-required")
+                        .await?;
+                    return Ok(LoginResult::PasswordResetRequired);
+                }
+                let session_token = self.create_session(*id).await?;
+                self.record_audit_event(
+                    AuditEventKind::Login,
+                    Some(*id),
+                    Some(*id),
+                    &[("method", "password")],
+                )
+                .await?;
+                return Ok(LoginResult::LoggedIn(session_token));
+            }
+        }
+
+        let user_id = user.map(|user| user.id);
+        let lockout_seconds = self.config().login_lockout_seconds;
+        let locked = LoginLockout::record_failure(self.store(), &lockouts, lockout_seconds).await?;
+        Ok(match locked {
+            Some(locked_until) => {
+                log::warn!(
+                    "Locked login for username: {} (request {})",
+                    username,
+                    self.request_id()
+                );
+                self.record_failed_login(user_id, username, "locked")
+                    .await?;
+                LoginResult::Locked(locked_until)
+            }
+            None => {
+                self.record_failed_login(user_id, username, "invalid-credentials")
+                    .await?;
+                LoginResult::InvalidCredentials
+            }
+        })
+    }
+
+    /// Record a failed login attempt for a username in the audit log, along with why it failed.
+    /// The user is none if no user has the username.
+    async fn record_failed_login(
+        &self,
+        user_id: Option<Uuid>,
+        username: &str,
+        reason: &str,
+    ) -> Result<()> {
+        self.record_audit_event(
+            AuditEventKind::LoginFailed,
+            user_id,
+            None,
+            &[("username", username), ("reason", reason)],
+        )
+        .await
+    }
+
+    /// Unlock login for a user locked out by failed login attempts, and forget any failed attempts
+    /// made for their username. This will return none if the user does not exist, or whether they
+    /// were locked otherwise.
+    pub async fn unlock_user(&self, user_id: Uuid) -> Result<Option<bool>> {
+        let user = match self.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        self.store()
+            .del(&self.create_login_failures_key("username", &user.username))
+            .await?;
+        let unlocked = self
+            .store()
+            .del(&self.create_login_lock_key("username", &user.username))
+            .await?;
+
+        log::info!(
+            "Unlocked login for user: {} (request {})",
+            user_id,
+            self.request_id()
+        );
+        Ok(Some(unlocked))
+    }
+
+    /// Get the lockouts a login attempt for a username is subject to. A threshold of zero disables
+    /// a lockout.
+    fn login_lockouts(&self, username: &str) -> Vec<LoginLockout> {
+        let Config {
+            login_lockout_threshold,
+            login_ip_lockout_threshold,
+            ..
+        } = self.config();
+
+        let mut lockouts = vec![("username", username, *login_lockout_threshold)];
+        if let Some(client_ip) = self.client_ip() {
+            lockouts.push(("ip", client_ip, *login_ip_lockout_threshold));
+        }
+
+        lockouts
+            .into_iter()
+            .filter(|(_, _, threshold)| *threshold > 0)
+            .map(|(kind, subject, threshold)| LoginLockout {
+                failures_key: self.create_login_failures_key(kind, subject),
+                lock_key: self.create_login_lock_key(kind, subject),
+                threshold,
+            })
+            .collect()
+    }
+
+    /// Get the cache key counting failed login attempts for a username or IP address.
+    fn create_login_failures_key(&self, kind: &str, subject: &str) -> String {
+        format!("login-failures/{}/{}", kind, subject)
+    }
+
+    /// Get the cache key marking a username or IP address as locked out of logging in.
+    fn create_login_lock_key(&self, kind: &str, subject: &str) -> String {
+        format!("login-lock/{}/{}", kind, subject)
+    }
+
+    /// Attempt to refresh a session token. The current session token will be used to create a new
+    /// session token with an extended lifespan. The current session token will be invalidated and
+    /// the new, refreshed token will be returned. No token will be returned if the provided session
+    /// token is invalid. Presenting a token that was already rotated out means it has been replayed,
+    /// so the whole session is terminated.
+    pub async fn refresh(&self, unverified_session_token: &str) -> Result<Option<SessionToken>> {
+        let Config {
+            session_token_secret,
+            session_token_expiration_seconds,
+            ..
+        } = self.config();
+
+        if let Some(SessionTokenData {
+            session_id,
+            session_token_id,
+            user_id,
+        }) = SessionToken::decode(unverified_session_token, session_token_secret)
+        {
+            if let Some(current_session_token) = self.find_session(session_id).await? {
+                let history_key = self.create_session_token_history_key(session_id);
+                if current_session_token.to_string() != unverified_session_token {
+                    let is_reused = self
+                        .redis()
+                        .sismember::<&str, String, bool>(&history_key, session_token_id.to_string())
+                        .await?;
+                    if is_reused {
+                        self.revoke_reused_session(user_id, session_id).await?;
+                    }
+                    return Ok(None);
+                }
+
+                let refreshed_session_token = SessionToken::encode(
+                    SessionTokenData {
+                        session_id,
+                        session_token_id: Uuid::new_v4(),
+                        user_id,
+                    },
+                    session_token_secret,
+                );
+
+                let expiration_seconds = *session_token_expiration_seconds as usize;
+                let mut pipeline = redis::pipe();
+                pipeline
+                    .set_ex(
+                        session_id.to_string(),
+                        refreshed_session_token.to_string(),
+                        expiration_seconds,
+                    )
+                    .ignore()
+                    .expire(self.create_user_sessions_key(user_id), expiration_seconds)
+                    .ignore()
+                    .expire(
+                        self.create_session_metadata_key(&session_id.to_string()),
+                        expiration_seconds,
+                    )
+                    .ignore();
+                // Remember the rotated token so replaying it can be detected. The history expires
+                // along with the session.
+                pipeline
+                    .sadd(&history_key, session_token_id.to_string())
+                    .ignore()
+                    .expire(&history_key, expiration_seconds)
+                    .ignore();
+                self.write_session_keys::<()>(&pipeline).await?;
+
+                Ok(Some(refreshed_session_token))
+            } else {
+                Ok(None)
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Use the provided session token to terminate a session. This function will return true if the
+    /// session is terminated successfully and false otherwise. The logout will fail and return
+    /// false if the session token is invalid.
+    pub async fn logout(&self, unverified_session_token: &str) -> Result<bool> {
+        let Config {
+            session_token_secret,
+            ..
+        } = self.config();
+
+        if let Some(SessionTokenData {
+            session_id,
+            user_id,
+            ..
+        }) = SessionToken::decode(unverified_session_token, session_token_secret)
+        {
+            let is_deleted = self.delete_session(session_id).await?;
+            if is_deleted {
+                self.record_audit_event(AuditEventKind::Logout, Some(user_id), Some(user_id), &[])
+                    .await?;
+            }
+            Ok(is_deleted)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Start logging in with an OAuth provider. This returns a random state parameter to send to
+    /// the provider, which is stored in the Redis database until it expires so the provider's
+    /// response can be matched to this login attempt.
+    #[cfg(feature = "oauth")]
+    pub async fn start_oauth(&self, provider: OAuthProvider) -> Result<String> {
+        let state = self.generate_token();
+        self.redis()
+            .set_ex::<String, &str, ()>(
+                oauth::state_key(&state),
+                provider.as_str(),
+                oauth::STATE_EXPIRATION_SECONDS,
+            )
+            .await?;
+
+        Ok(state)
+    }
+
+    /// Check that a state parameter sent back by an OAuth provider was issued for a login attempt
+    /// with that provider. Each state parameter can only be used once.
+    #[cfg(feature = "oauth")]
+    pub async fn consume_oauth_state(&self, provider: OAuthProvider, state: &str) -> Result<bool> {
+        let key = oauth::state_key(state);
+        let stored = self.redis().get::<&str, Option<String>>(&key).await?;
+        // Only the request that deletes the state gets to use it.
+        let deleted = self.redis().del::<&str, u32>(&key).await?;
+
+        Ok(deleted == 1 && stored.as_deref() == Some(provider.as_str()))
+    }
+
+    /// Log in a user with their profile at an OAuth provider, returning a one-time ticket that can
+    /// be exchanged for a session token. The user linked to the provider account is logged in if
+    /// there is one. Otherwise, the account is linked to an existing user with the same verified
+    /// email address, or a new user is created for it. This will return none if a new user would
+    /// have to be created but the provider didn't share an email address.
+    #[cfg(feature = "oauth")]
+    pub async fn login_with_oauth_profile(
+        &self,
+        provider: OAuthProvider,
+        profile: &OAuthProfile,
+    ) -> Result<Option<String>> {
+        let linked = self
+            .timed(
+                "find_oauth_identity",
+                query!(
+                    "
+                    SELECT user_id FROM oauth_identities
+                    WHERE provider = $1 AND provider_user_id = $2
+                    ",
+                    provider.as_str(),
+                    profile.provider_user_id,
+                )
+                .fetch_optional(self.db()),
+            )
+            .await?;
+
+        let user_id = match linked {
+            Some(record) => record.user_id,
+            None => {
+                let email = match &profile.email {
+                    Some(email) => email,
+                    None => return Ok(None),
+                };
+
+                let existing = if profile.email_verified {
+                    self.find_users_by_email(email)
+                        .await?
+                        .into_iter()
+                        .find(|user| user.email_verified_at.is_some())
+                } else {
+                    None
+                };
+
+                let user_id = match existing {
+                    Some(user) => user.id,
+                    None => self.create_oauth_user(profile, email).await?.id,
+                };
+
+                self.timed(
+                    "link_oauth_identity",
+                    query!(
+                        "
+                        INSERT INTO oauth_identities (provider, provider_user_id, user_id, email)
+                        VALUES ($1, $2, $3, $4)
+                        ",
+                        provider.as_str(),
+                        profile.provider_user_id,
+                        user_id,
+                        email,
+                    )
+                    .execute(self.db()),
+                )
+                .await?;
+
+                log::info!(
+                    "Linked {} account to user: {} (request {})",
+                    provider,
+                    user_id,
+                    self.request_id()
+                );
+                user_id
+            }
+        };
+
+        let ticket = self.generate_token();
+        self.redis()
+            .set_ex::<String, String, ()>(
+                oauth::ticket_key(&ticket),
+                user_id.to_string(),
+                oauth::TICKET_EXPIRATION_SECONDS,
+            )
+            .await?;
+
+        Ok(Some(ticket))
+    }
+
+    /// Create a user for an account at an OAuth provider. The user gets a unique username based on
+    /// the one suggested by the provider and a random password, so they can only log in through
+    /// the provider until they reset it. The email address is only verified if the provider has
+    /// verified it.
+    #[cfg(feature = "oauth")]
+    async fn create_oauth_user(&self, profile: &OAuthProfile, email: &str) -> Result<User> {
+        let Config {
+            password_hash_cost, ..
+        } = self.config();
+
+        let base = oauth::normalize_username(&profile.username);
+        let mut username = base.clone();
+        while self
+            .find_user_by_username_including_deleted(&username)
+            .await?
+            .is_some()
+        {
+            username = format!("{}-{}", base, rand::thread_rng().gen_range(1000..10000));
+        }
+
+        let password_hash = bcrypt::hash(self.generate_token(), *password_hash_cost)?;
+        let email_verified_at = if profile.email_verified {
+            Some(Utc::now())
+        } else {
+            None
+        };
+        let user = self
+            .transaction(|transaction| {
+                Box::pin(self.insert_user(
+                    transaction,
+                    &username,
+                    email,
+                    email_verified_at,
+                    &password_hash,
+                ))
+            })
+            .await?;
+
+        self.invalidate_cached(&[CachedQuery::UserCount]).await;
+        if email_verified_at.is_none() {
+            self.start_email_verification(user.id, &username, email)
+                .await?;
+        } else {
+            self.send_welcome_email(&username, email).await;
+        }
+
+        Ok(user)
+    }
+
+    /// Exchange a one-time ticket issued by an OAuth login for a session token. Tickets that are
+    /// invalid, have expired or have already been used are treated as invalid credentials.
+    #[cfg(feature = "oauth")]
+    pub async fn redeem_oauth_ticket(&self, ticket: &str) -> Result<LoginResult> {
+        let key = oauth::ticket_key(ticket);
+        let user_id = self.redis().get::<&str, Option<String>>(&key).await?;
+        let deleted = self.redis().del::<&str, u32>(&key).await?;
+
+        let user_id = match user_id {
+            Some(user_id) if deleted == 1 => user_id.parse()?,
+            _ => return Ok(LoginResult::InvalidCredentials),
+        };
+
+        // The user may have been deleted or deactivated since the ticket was issued.
+        match self.find_user(user_id).await? {
+            Some(user) if user.deactivated_at.is_some() => {
+                self.record_failed_login(Some(user_id), &user.username, "deactivated")
+                    .await?;
+                Ok(LoginResult::Deactivated)
+            }
+            Some(_) => {
+                let session_token = self.create_session(user_id).await?;
+                self.record_audit_event(
+                    AuditEventKind::Login,
+                    Some(user_id),
+                    Some(user_id),
+                    &[("method", "oauth")],
+                )
+                .await?;
+                Ok(LoginResult::LoggedIn(session_token))
+            }
+            None => Ok(LoginResult::InvalidCredentials),
+        }
+    }
+
+    /// Reject a ticket for an OAuth login. Tickets are never issued, as the server was built
+    /// without the "oauth" feature.
+    #[cfg(not(feature = "oauth"))]
+    pub async fn redeem_oauth_ticket(&self, _ticket: &str) -> Result<LoginResult> {
+        Ok(LoginResult::InvalidCredentials)
+    }
+
+    /// Log in with any login method, using the credentials it takes.
+    pub async fn login_with(&self, method: &LoginMethod) -> Result<LoginResult> {
+        match method {
+            LoginMethod::Password { username, password } => self.login(username, password).await,
+            LoginMethod::Passkey { credential } => self.finish_passkey_login(credential).await,
+            LoginMethod::OAuth { ticket } => self.redeem_oauth_ticket(ticket).await,
+        }
+    }
+
+    /// Get the passkeys of a user, oldest first.
+    pub async fn find_passkeys_by_user(&self, user_id: Uuid) -> Result<Vec<Passkey>> {
+        self.timed(
+            "find_passkeys_by_user",
+            query_as!(
+                Passkey,
+                "SELECT * FROM webauthn_credentials WHERE user_id = $1 ORDER BY created_at",
+                user_id
+            )
+            .fetch_all(self.db()),
+        )
+        .await
+    }
+
+    /// Start creating a passkey for a user. This returns the options to pass to
+    /// "navigator.credentials.create()", including a random challenge that is stored in the cache
+    /// until it expires. This will return none if the user does not exist.
+    pub async fn start_passkey_registration(&self, user_id: Uuid) -> Result<Option<Value>> {
+        let user = match self.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        let credential_ids = self
+            .find_passkeys_by_user(user_id)
+            .await?
+            .into_iter()
+            .map(|passkey| passkey.credential_id)
+            .collect::<Vec<_>>();
+
+        let challenge = webauthn::generate_challenge();
+        self.store_webauthn_challenge(&challenge, Ceremony::Registration(user_id))
+            .await?;
+
+        Ok(Some(webauthn::registration_options(
+            self.config(),
+            &challenge,
+            user_id,
+            &user.username,
+            &credential_ids,
+        )))
+    }
+
+    /// Finish creating a passkey for a user with the credential returned by
+    /// "navigator.credentials.create()". This will return none if the credential is invalid, was
+    /// created for a challenge that wasn't issued to the user or has expired, or is already
+    /// registered.
+    pub async fn finish_passkey_registration(
+        &self,
+        user_id: Uuid,
+        credential: &str,
+        name: &str,
+    ) -> Result<Option<Passkey>> {
+        let registration = match webauthn::parse_registration(self.config(), credential) {
+            Ok(registration) => registration,
+            Err(error) => {
+                log::info!(
+                    "Rejected passkey for user {}: {} (request {})",
+                    user_id,
+                    error,
+                    self.request_id()
+                );
+                return Ok(None);
+            }
+        };
+        let ceremony = self
+            .consume_webauthn_challenge(&registration.challenge)
+            .await?;
+        if ceremony != Some(Ceremony::Registration(user_id)) {
+            return Ok(None);
+        }
+
+        let passkey = self
+            .timed(
+                "insert_passkey",
+                query_as!(
+                    Passkey,
+                    "
+                    INSERT INTO webauthn_credentials
+                        (id, user_id, credential_id, public_key, sign_count, name)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+                    ON CONFLICT (credential_id) DO NOTHING
+                    RETURNING *
+                    ",
+                    Uuid::new_v4(),
+                    user_id,
+                    registration.credential_id,
+                    registration.public_key,
+                    i64::from(registration.sign_count),
+                    name,
+                )
+                .fetch_optional(self.db()),
+            )
+            .await?;
+
+        if let Some(passkey) = &passkey {
+            self.record_audit_event(
+                AuditEventKind::PasskeyAdded,
+                Some(user_id),
+                Some(user_id),
+                &[("passkeyId", &passkey.id.to_string()), ("name", name)],
+            )
+            .await?;
+        }
+        Ok(passkey)
+    }
+
+    /// Remove one of a user's passkeys, so it can no longer be used to log in. This will return
+    /// false if the user has no passkey with the ID.
+    pub async fn delete_passkey(&self, user_id: Uuid, passkey_id: Uuid) -> Result<bool> {
+        let name = self
+            .timed(
+                "delete_passkey",
+                query_scalar!(
+                    "DELETE FROM webauthn_credentials WHERE id = $1 AND user_id = $2 RETURNING name",
+                    passkey_id,
+                    user_id
+                )
+                .fetch_optional(self.db()),
+            )
+            .await?;
+
+        match name {
+            Some(name) => {
+                self.record_audit_event(
+                    AuditEventKind::PasskeyRemoved,
+                    Some(user_id),
+                    Some(user_id),
+                    &[("passkeyId", &passkey_id.to_string()), ("name", &name)],
+                )
+                .await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Start logging in with a passkey. This returns the options to pass to
+    /// "navigator.credentials.get()", including a random challenge that is stored in the cache
+    /// until it expires. If a username is given, only that user's passkeys are offered.
+    /// Otherwise, the authenticator offers any passkey it has for the server.
+    pub async fn start_passkey_login(&self, username: Option<&str>) -> Result<Value> {
+        let credential_ids = match username {
+            Some(username) => match self.find_user_by_username(username).await? {
+                Some(user) => self
+                    .find_passkeys_by_user(user.id)
+                    .await?
+                    .into_iter()
+                    .map(|passkey| passkey.credential_id)
+                    .collect(),
+                // Answer the same as for a user without passkeys, so usernames can't be probed.
+                None => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+
+        let challenge = webauthn::generate_challenge();
+        self.store_webauthn_challenge(&challenge, Ceremony::Login)
+            .await?;
+
+        Ok(webauthn::login_options(
+            self.config(),
+            &challenge,
+            &credential_ids,
+        ))
+    }
+
+    /// Log in with the credential returned by "navigator.credentials.get()". Credentials that are
+    /// invalid, weren't signed for a login challenge that was issued and hasn't expired, or don't
+    /// belong to a registered passkey are treated as invalid credentials.
+    pub async fn finish_passkey_login(&self, credential: &str) -> Result<LoginResult> {
+        let assertion = match webauthn::parse_assertion(self.config(), credential) {
+            Ok(assertion) => assertion,
+            Err(error) => {
+                log::info!(
+                    "Rejected passkey login: {} (request {})",
+                    error,
+                    self.request_id()
+                );
+                return Ok(LoginResult::InvalidCredentials);
+            }
+        };
+        if self
+            .consume_webauthn_challenge(&assertion.challenge)
+            .await?
+            != Some(Ceremony::Login)
+        {
+            return Ok(LoginResult::InvalidCredentials);
+        }
+
+        let passkey = self
+            .timed(
+                "find_passkey_by_credential_id",
+                query_as!(
+                    Passkey,
+                    "SELECT * FROM webauthn_credentials WHERE credential_id = $1",
+                    assertion.credential_id
+                )
+                .fetch_optional(self.db()),
+            )
+            .await?;
+        let passkey = match passkey {
+            Some(passkey)
+                if assertion.user_handle.unwrap_or(passkey.user_id) == passkey.user_id =>
+            {
+                passkey
+            }
+            _ => return Ok(LoginResult::InvalidCredentials),
+        };
+        let user = match self.find_user(passkey.user_id).await? {
+            Some(user) => user,
+            None => return Ok(LoginResult::InvalidCredentials),
+        };
+
+        if let Err(error) = assertion.verify(&passkey.public_key) {
+            log::info!(
+                "Rejected passkey login for user {}: {} (request {})",
+                user.id,
+                error,
+                self.request_id()
+            );
+            self.record_failed_login(Some(user.id), &user.username, "invalid-passkey")
+                .await?;
+            return Ok(LoginResult::InvalidCredentials);
+        }
+
+        // A sign count that didn't increase means the authenticator may have been cloned. Both
+        // are zero for authenticators that don't count.
+        let sign_count = i64::from(assertion.sign_count);
+        if (sign_count != 0 || passkey.sign_count != 0) && sign_count <= passkey.sign_count {
+            log::warn!(
+                "Rejected passkey login for user {} as its sign count didn't increase. The \
+                authenticator may have been cloned. (request {})",
+                user.id,
+                self.request_id()
+            );
+            self.record_failed_login(Some(user.id), &user.username, "passkey-cloned")
+                .await?;
+            return Ok(LoginResult::InvalidCredentials);
+        }
+
+        if user.deactivated_at.is_some() {
+            self.record_failed_login(Some(user.id), &user.username, "deactivated")
+                .await?;
+            return Ok(LoginResult::Deactivated);
+        }
+
+        self.timed(
+            "update_passkey_usage",
+            query!(
+                "
+                UPDATE webauthn_credentials SET sign_count = $2, last_used_at = NOW()
+                WHERE id = $1
+                ",
+                passkey.id,
+                sign_count,
+            )
+            .execute(self.db()),
+        )
+        .await?;
+
+        let session_token = self.create_session(user.id).await?;
+        self.record_audit_event(
+            AuditEventKind::Login,
+            Some(user.id),
+            Some(user.id),
+            &[("method", "passkey")],
+        )
+        .await?;
+        Ok(LoginResult::LoggedIn(session_token))
+    }
+
+    /// Store a challenge issued for a WebAuthn ceremony in the cache until it expires.
+    async fn store_webauthn_challenge(&self, challenge: &str, ceremony: Ceremony) -> Result<()> {
+        self.store()
+            .set(
+                &webauthn::challenge_key(challenge),
+                &ceremony.to_string(),
+                self.config().webauthn_challenge_expiration_seconds,
+            )
+            .await
+    }
+
+    /// Get the ceremony a challenge was issued for. Each challenge can only be used once. This will
+    /// return none if the challenge wasn't issued or has expired.
+    async fn consume_webauthn_challenge(&self, challenge: &str) -> Result<Option<Ceremony>> {
+        let key = webauthn::challenge_key(challenge);
+        let stored = self.store().get(&key).await?;
+        // Only the request that deletes the challenge gets to use it.
+        let deleted = self.store().del(&key).await?;
+
+        match stored {
+            Some(ceremony) if deleted => Ok(Some(ceremony.parse()?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Terminate a session after one of its rotated tokens was replayed, as either the token or
+    /// the session's current token may have been stolen.
+    async fn revoke_reused_session(&self, user_id: Uuid, session_id: Uuid) -> Result<()> {
+        self.revoke_session(user_id, session_id).await?;
+        self.record_audit_event(
+            AuditEventKind::SessionTokenReused,
+            Some(user_id),
+            None,
+            &[("session_id", &session_id.to_string())],
+        )
+        .await?;
+        log::warn!(
+            "Session {} of user {} was terminated, as a rotated session token was reused.",
+            session_id,
+            user_id
+        );
+
+        Ok(())
+    }
+
+    /// Get the Redis key holding the IDs of the session tokens a session has rotated out.
+    fn create_session_token_history_key(&self, session_id: Uuid) -> String {
+        format!("session-token-history/{}", session_id)
+    }
+
+    /// Authenticate a user with a session token. This will return none if the session token is
+    /// invalid or its session has been terminated.
+    pub async fn authenticate(
+        &self,
+        unverified_session_token: &str,
+    ) -> Result<Option<AuthenticatedUser>> {
+        let Config {
+            session_token_secret,
+            ..
+        } = self.config();
+
+        if let Some(SessionTokenData {
+            session_id,
+            user_id,
+            ..
+        }) = SessionToken::decode(unverified_session_token, session_token_secret)
+        {
+            if let Some(current_session_token) = self.find_session(session_id).await? {
+                if current_session_token.to_string() == unverified_session_token {
+                    self.touch_session(session_id).await?;
+                    return Ok(Some(AuthenticatedUser {
+                        user_id,
+                        session_id,
+                    }));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Write session keys to the Redis database of this server's region, and queue the writes to
+    /// be replicated to other regions, so sessions can be used from any region. This will return
+    /// the results of the commands in the pipeline that aren't ignored.
+    async fn write_session_keys<T: FromRedisValue>(&self, pipeline: &Pipeline) -> Result<T> {
+        let result = pipeline.query_async(&mut self.redis()).await?;
+        self.state.regions.replicate(&self.state.metrics, pipeline);
+        Ok(result)
+    }
+
+    /// Delete session keys like `write_session_keys`. Deletes are never dropped, so this waits
+    /// for regions that have fallen behind to have room for them.
+    async fn delete_session_keys<T: FromRedisValue>(&self, pipeline: &Pipeline) -> Result<T> {
+        let result = pipeline.query_async(&mut self.redis()).await?;
+        self.state
+            .regions
+            .replicate_deletes(&self.state.metrics, pipeline)
+            .await;
+        Ok(result)
+    }
+
+    /// Get the Redis key marking a session as terminated. Tombstones keep a terminated session
+    /// from being found in other regions before its delete has been replicated to them.
+    fn create_session_tombstone_key(&self, session_id: &str) -> String {
+        format!("session-tombstone/{}", session_id)
+    }
+
+    /// Add commands to a pipeline leaving a tombstone for each of a set of terminated sessions.
+    /// Tombstones expire along with the longest possible session.
+    fn add_session_tombstones(&self, pipeline: &mut Pipeline, session_ids: &[String]) {
+        let expiration_seconds = self.config().session_token_expiration_seconds as usize;
+        for session_id in session_ids {
+            pipeline
+                .set_ex(
+                    self.create_session_tombstone_key(session_id),
+                    1,
+                    expiration_seconds,
+                )
+                .ignore();
+        }
+    }
+
+    /// Find a session by ID and return its associated session token. This will return none if the
+    /// session does not exist.
+    async fn find_session(&self, session_id: Uuid) -> Result<Option<SessionToken>> {
+        let Config {
+            session_token_secret,
+            ..
+        } = self.config();
+
+        let key = session_id.to_string();
+        let (mut session_token, is_terminated) = redis::pipe()
+            .get(&key)
+            .exists(self.create_session_tombstone_key(&key))
+            .query_async::<_, (Option<String>, bool)>(&mut self.redis())
+            .await?;
+        // Sessions created in another region may not have been replicated to this one yet.
+        // Sessions terminated in this one may not have had their deletes replicated elsewhere.
+        if session_token.is_none() && !is_terminated {
+            session_token = self.state.regions.get(&key, self.deadline).await;
+        }
+
+        Ok(session_token
+            .and_then(|session_token| SessionToken::verify(&session_token, session_token_secret)))
+    }
+
+    /// Create a session for the specified user. The returned token includes the session ID, the
+    /// user's ID and a unique session token ID.
+    async fn create_session(&self, user_id: Uuid) -> Result<SessionToken> {
+        let Config {
+            session_token_secret,
+            ..
+        } = self.config();
+
+        let session_id = Uuid::new_v4();
+        let session_token_id = Uuid::new_v4();
+        let session_token_data = SessionTokenData {
+            session_id,
+            session_token_id,
+            user_id,
+        };
+
+        let Config {
+            session_token_expiration_seconds,
+            ..
+        } = self.config();
+
+        let session_token = SessionToken::encode(session_token_data, session_token_secret);
+
+        let expiration_seconds = *session_token_expiration_seconds as usize;
+        let mut pipeline = redis::pipe();
+        pipeline
+            .set_ex(
+                session_id.to_string(),
+                session_token.to_string(),
+                expiration_seconds,
+            )
+            .ignore();
+
+        // Record details about the session so the user can recognize it when listing their
+        // sessions. These expire along with the session.
+        let now = Utc::now().to_rfc3339();
+        let mut metadata = vec![("created_at", now.clone()), ("last_seen_at", now)];
+        if let Some(client_ip) = self.client_ip() {
+            metadata.push(("ip", client_ip.into()));
+        }
+        if let Some(user_agent) = self.user_agent() {
+            metadata.push(("user_agent", user_agent.into()));
+        }
+        let metadata_key = self.create_session_metadata_key(&session_id.to_string());
+        pipeline
+            .hset_multiple(&metadata_key, &metadata)
+            .ignore()
+            .expire(&metadata_key, expiration_seconds)
+            .ignore();
+
+        // Keep track of the user's sessions so they can all be terminated at once. The set
+        // outlives every session it contains, so it only has to be cleaned up when it expires.
+        let user_sessions_key = self.create_user_sessions_key(user_id);
+        pipeline
+            .sadd(&user_sessions_key, session_id.to_string())
+            .ignore()
+            .expire(&user_sessions_key, expiration_seconds)
+            .ignore();
+        self.write_session_keys::<()>(&pipeline).await?;
+
+        Ok(session_token)
+    }
+
+    /// Terminate a session by ID. This will return true if the session was found and deleted. False
+    /// will be returned otherwise.
+    async fn delete_session(&self, session_id: Uuid) -> Result<bool> {
+        let mut pipeline = redis::pipe();
+        pipeline
+            .del(session_id.to_string())
+            .del(self.create_session_metadata_key(&session_id.to_string()))
+            .ignore()
+            .del(self.create_session_token_history_key(session_id))
+            .ignore();
+        self.add_session_tombstones(&mut pipeline, &[session_id.to_string()]);
+        let (count,) = self.delete_session_keys::<(u32,)>(&pipeline).await?;
+
+        Ok(count != 0)
+    }
+
+    /// Record that a session was just used. Sessions created before their details were recorded
+    /// get details that expire along with the longest possible session.
+    async fn touch_session(&self, session_id: Uuid) -> Result<()> {
+        let Config {
+            session_token_expiration_seconds,
+            ..
+        } = self.config();
+
+        let metadata_key = self.create_session_metadata_key(&session_id.to_string());
+        let added = self
+            .redis()
+            .hset::<&str, &str, String, u32>(&metadata_key, "last_seen_at", Utc::now().to_rfc3339())
+            .await?;
+        if added != 0 {
+            self.redis()
+                .expire::<&str, ()>(&metadata_key, *session_token_expiration_seconds as usize)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the Redis key holding details about a session, such as when it was last used.
+    fn create_session_metadata_key(&self, session_id: &str) -> String {
+        format!("session-metadata/{}", session_id)
+    }
+
+    /// Find every active session of a user, most recently used first. Sessions that have expired
+    /// are forgotten along the way.
+    pub async fn find_sessions(&self, viewer: AuthenticatedUser) -> Result<Vec<ActiveSession>> {
+        let user_sessions_key = self.create_user_sessions_key(viewer.user_id);
+        let session_ids = self
+            .redis()
+            .smembers::<&str, Vec<String>>(&user_sessions_key)
+            .await?;
+
+        let mut sessions = Vec::new();
+        let mut expired = Vec::new();
+        for session_id in session_ids {
+            if !self.redis().exists::<&str, bool>(&session_id).await? {
+                expired.push(session_id);
+                continue;
+            }
+
+            let metadata = self
+                .redis()
+                .hgetall::<String, HashMap<String, String>>(
+                    self.create_session_metadata_key(&session_id),
+                )
+                .await?;
+            let timestamp = |field: &str| {
+                metadata
+                    .get(field)
+                    .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+                    .map(|value| value.with_timezone(&Utc))
+            };
+
+            let id = session_id.parse()?;
+            sessions.push(ActiveSession {
+                id,
+                created_at: timestamp("created_at"),
+                last_seen_at: timestamp("last_seen_at"),
+                ip: metadata.get("ip").cloned(),
+                user_agent: metadata.get("user_agent").cloned(),
+                is_current: id == viewer.session_id,
+            });
+        }
+
+        if !expired.is_empty() {
+            self.redis()
+                .srem::<&str, Vec<String>, ()>(&user_sessions_key, expired)
+                .await?;
+        }
+
+        sessions.sort_by_key(|session| Reverse(session.last_seen_at));
+        Ok(sessions)
+    }
+
+    /// Terminate one of a user's sessions. This will return true if the session belonged to the
+    /// user and was active.
+    pub async fn revoke_session(&self, user_id: Uuid, session_id: Uuid) -> Result<bool> {
+        let (removed,) = self
+            .delete_session_keys::<(u32,)>(redis::pipe().srem(
+                self.create_user_sessions_key(user_id),
+                session_id.to_string(),
+            ))
+            .await?;
+        if removed == 0 {
+            return Ok(false);
+        }
+
+        self.delete_session(session_id).await
+    }
+
+    /// Terminate every session of a user other than the one making the request. This will return
+    /// the number of sessions terminated.
+    pub async fn revoke_other_sessions(&self, viewer: AuthenticatedUser) -> Result<u32> {
+        self.delete_user_sessions(viewer.user_id, Some(viewer.session_id))
+            .await
+    }
+
+    /// Create the key the set of a user's session IDs is stored under in the Redis database.
+    fn create_user_sessions_key(&self, user_id: Uuid) -> String {
+        format!("sessions/{}", user_id)
+    }
+
+    /// Terminate every active session of a user, except for the session with the specified ID if
+    /// one is provided. This will return the number of sessions terminated.
+    async fn delete_user_sessions(
+        &self,
+        user_id: Uuid,
+        kept_session_id: Option<Uuid>,
+    ) -> Result<u32> {
+        let user_sessions_key = self.create_user_sessions_key(user_id);
+        let kept_session_id = kept_session_id.map(|session_id| session_id.to_string());
+        let session_ids = self
+            .redis()
+            .smembers::<String, Vec<String>>(user_sessions_key.clone())
+            .await?
+            .into_iter()
+            .filter(|session_id| Some(session_id) != kept_session_id.as_ref())
+            .collect::<Vec<_>>();
+
+        let mut pipeline = redis::pipe();
+        if !session_ids.is_empty() {
+            let metadata_keys = session_ids
+                .iter()
+                .map(|session_id| self.create_session_metadata_key(session_id))
+                .collect::<Vec<_>>();
+            pipeline
+                .del(session_ids.clone())
+                .del(metadata_keys)
+                .ignore();
+            self.add_session_tombstones(&mut pipeline, &session_ids);
+        }
+
+        if kept_session_id.is_none() {
+            pipeline.del(user_sessions_key).ignore();
+        } else if !session_ids.is_empty() {
+            pipeline.srem(user_sessions_key, session_ids).ignore();
+        }
+
+        if pipeline.cmd_iter().next().is_none() {
+            return Ok(0);
+        }
+        let counts = self.delete_session_keys::<Vec<u32>>(&pipeline).await?;
+        Ok(counts.first().copied().unwrap_or(0))
+    }
+
+    /// Find every role granted to a user.
+    pub async fn find_user_roles(&self, user_id: Uuid) -> Result<Vec<Role>> {
+        let mut connection = self.db().acquire().await?;
+        self.find_user_roles_in(&mut connection, user_id).await
+    }
+
+    /// Find the roles of a user like `find_user_roles`, as part of a transaction.
+    async fn find_user_roles_in(
+        &self,
+        connection: &mut PgConnection,
+        user_id: Uuid,
+    ) -> Result<Vec<Role>> {
+        self.count_rows(
+            self.timed(
+                "find_user_roles_in",
+                query!(
+                    "SELECT role FROM user_roles WHERE user_id = $1 ORDER BY role",
+                    user_id
+                )
+                .fetch_all(connection),
+            )
+            .await?,
+        )?
+        .into_iter()
+        .map(|row| row.role.parse())
+        .collect()
+    }
+
+    /// Grant a role to a user on behalf of the user making the request. The user's roles before
+    /// and after are recorded in the audit log. This will return the user's roles afterwards, or
+    /// none if the user does not exist.
+    pub async fn grant_role(
+        &self,
+        viewer: AuthenticatedUser,
+        user_id: Uuid,
+        role: Role,
+    ) -> Result<Option<Vec<Role>>> {
+        if self.find_user(user_id).await?.is_none() {
+            return Ok(None);
+        }
+
+        let changes = self
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let result = self
+                        .timed(
+                            "grant_role",
+                            query!(
+                                "
+                                INSERT INTO user_roles (user_id, role) VALUES ($1, $2)
+                                ON CONFLICT DO NOTHING
+                                ",
+                                user_id,
+                                role.as_str(),
+                            )
+                            .execute(&mut *transaction),
+                        )
+                        .await?;
+
+                    if result.rows_affected() == 0 {
+                        return Ok(Vec::new());
+                    }
+
+                    let roles = self.find_user_roles_in(transaction, user_id).await?;
+                    let changes = vec![roles_granted_change(&roles, role)];
+                    self.record_audit_changes_in(
+                        transaction,
+                        AuditEventKind::RoleGranted,
+                        Some(user_id),
+                        Some(viewer.user_id),
+                        &[("role", role.as_str())],
+                        &changes,
+                    )
+                    .await?;
+                    Ok(changes)
+                })
+            })
+            .await?;
+        self.record_audited_action(AuditEventKind::RoleGranted, user_id, false, changes);
+
+        Ok(Some(self.find_user_roles(user_id).await?))
+    }
+
+    /// Revoke a role from a user on behalf of the user making the request. The user's roles before
+    /// and after are recorded in the audit log. This will return the user's roles afterwards, or
+    /// none if the user does not exist.
+    pub async fn revoke_role(
+        &self,
+        viewer: AuthenticatedUser,
+        user_id: Uuid,
+        role: Role,
+    ) -> Result<Option<Vec<Role>>> {
+        if self.find_user(user_id).await?.is_none() {
+            return Ok(None);
+        }
+
+        let changes = self
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let result = self
+                        .timed(
+                            "revoke_role",
+                            query!(
+                                "DELETE FROM user_roles WHERE user_id = $1 AND role = $2",
+                                user_id,
+                                role.as_str(),
+                            )
+                            .execute(&mut *transaction),
+                        )
+                        .await?;
+
+                    if result.rows_affected() == 0 {
+                        return Ok(Vec::new());
+                    }
+
+                    let roles = self.find_user_roles_in(transaction, user_id).await?;
+                    let changes = vec![roles_revoked_change(&roles, role)];
+                    self.record_audit_changes_in(
+                        transaction,
+                        AuditEventKind::RoleRevoked,
+                        Some(user_id),
+                        Some(viewer.user_id),
+                        &[("role", role.as_str())],
+                        &changes,
+                    )
+                    .await?;
+                    Ok(changes)
+                })
+            })
+            .await?;
+        self.record_audited_action(AuditEventKind::RoleRevoked, user_id, false, changes);
+
+        Ok(Some(self.find_user_roles(user_id).await?))
+    }
+
+    /// Create an organization on behalf of the user making the request, who becomes its owner.
+    pub async fn create_organization(
+        &self,
+        viewer: AuthenticatedUser,
+        name: &str,
+    ) -> Result<Organization> {
+        self.transaction(|transaction| {
+            Box::pin(async move {
+                let organization = self
+                    .timed(
+                        "insert_organization",
+                        query_as!(
+                            Organization,
+                            "INSERT INTO organizations (id, created_by, name) VALUES ($1, $2, $3)
+                            RETURNING *",
+                            Uuid::new_v4(),
+                            viewer.user_id,
+                            name,
+                        )
+                        .fetch_one(&mut *transaction),
+                    )
+                    .await?;
+
+                self.timed(
+                    "insert_organization_owner",
+                    query!(
+                        "INSERT INTO memberships (organization_id, user_id, role, accepted_at)
+                        VALUES ($1, $2, $3, NOW())",
+                        organization.id,
+                        viewer.user_id,
+                        OrganizationRole::Owner.as_str(),
+                    )
+                    .execute(&mut *transaction),
+                )
+                .await?;
+
+                Ok(organization)
+            })
+        })
+        .await
+    }
+
+    /// Find an organization by its ID. This will return none if the organization does not exist.
+    pub async fn find_organization(&self, id: Uuid) -> Result<Option<Organization>> {
+        self.timed(
+            "find_organization",
+            query_as!(
+                Organization,
+                "SELECT * FROM organizations WHERE id = $1",
+                id
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find a user's membership of an organization, including an invitation they haven't accepted.
+    /// This will return none if the user isn't a member of or invited to the organization.
+    pub async fn find_membership(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Membership>> {
+        let mut connection = self.db().acquire().await?;
+        self.find_membership_in(&mut connection, organization_id, user_id)
+            .await
+    }
+
+    /// Find a user's membership of an organization like `find_membership`, as part of a
+    /// transaction.
+    async fn find_membership_in(
+        &self,
+        connection: &mut PgConnection,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Membership>> {
+        self.timed(
+            "find_membership_in",
+            query_as!(
+                Membership,
+                "SELECT * FROM memberships WHERE organization_id = $1 AND user_id = $2",
+                organization_id,
+                user_id,
+            )
+            .fetch_optional(connection),
+        )
+        .await
+    }
+
+    /// Find the role of a member of an organization. This will return none if the user isn't a
+    /// member, including if they were invited but haven't accepted yet.
+    pub async fn find_member_role(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<OrganizationRole>> {
+        match self.find_membership(organization_id, user_id).await? {
+            Some(membership) if membership.is_accepted() => {
+                Ok(Some(membership.organization_role()?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Find the email settings an organization overrides the server's with. This will return none
+    /// if the organization hasn't set any.
+    pub async fn find_organization_email_settings(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Option<OrganizationEmailSettings>> {
+        self.timed(
+            "find_organization_email_settings",
+            query_as!(
+                OrganizationEmailSettings,
+                "SELECT * FROM organization_email_settings WHERE organization_id = $1",
+                organization_id
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Replace the email settings of an organization. The SMTP password is encrypted with the
+    /// server's email settings key before it is stored. If no new password is set, the current one
+    /// is kept as long as the SMTP host and username don't change, so it is never sent to a server
+    /// it wasn't set for. This will return an error if a password is set but no key is configured.
+    pub async fn update_organization_email_settings(
+        &self,
+        organization_id: Uuid,
+        settings: &EmailSettingsInput,
+    ) -> Result<OrganizationEmailSettings> {
+        let smtp_password_encrypted = match &settings.smtp_password {
+            Some(password) => {
+                let key = self.config().email_settings_key.clone().ok_or_else(|| {
+                    anyhow!("No email settings key is configured to encrypt SMTP passwords.")
+                })?;
+                Some(SettingsCipher::new(key).encrypt(organization_id, password)?)
+            }
+            None => None,
+        };
+
+        self.timed(
+            "update_organization_email_settings",
+            query_as!(
+                OrganizationEmailSettings,
+                "
+                INSERT INTO organization_email_settings (
+                    organization_id, from_name, from_address, smtp_host, smtp_port, smtp_username,
+                    smtp_password_encrypted, logo_url, brand_color
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                ON CONFLICT (organization_id) DO UPDATE SET
+                    updated_at = NOW(),
+                    from_name = EXCLUDED.from_name,
+                    from_address = EXCLUDED.from_address,
+                    smtp_host = EXCLUDED.smtp_host,
+                    smtp_port = EXCLUDED.smtp_port,
+                    smtp_username = EXCLUDED.smtp_username,
+                    smtp_password_encrypted = CASE
+                        WHEN EXCLUDED.smtp_host IS NULL THEN NULL
+                        WHEN EXCLUDED.smtp_password_encrypted IS NOT NULL
+                            THEN EXCLUDED.smtp_password_encrypted
+                        WHEN EXCLUDED.smtp_host = organization_email_settings.smtp_host
+                            AND EXCLUDED.smtp_username = organization_email_settings.smtp_username
+                            THEN organization_email_settings.smtp_password_encrypted
+                    END,
+                    logo_url = EXCLUDED.logo_url,
+                    brand_color = EXCLUDED.brand_color
+                RETURNING *
+                ",
+                organization_id,
+                settings.from_name,
+                settings.from_address,
+                settings.smtp_host,
+                settings.smtp_port,
+                settings.smtp_username,
+                smtp_password_encrypted,
+                settings.logo_url,
+                settings.brand_color,
+            )
+            .fetch_one(self.db()),
+        )
+        .await
+    }
+
+    /// Delete the email settings of an organization, so its emails fall back to the server's
+    /// settings. This will return true if the organization had settings to delete.
+    pub async fn delete_organization_email_settings(&self, organization_id: Uuid) -> Result<bool> {
+        let result = self
+            .timed(
+                "delete_organization_email_settings",
+                query!(
+                    "DELETE FROM organization_email_settings WHERE organization_id = $1",
+                    organization_id
+                )
+                .execute(self.db()),
+            )
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Find the custom hostnames of an organization, verified or not, oldest first.
+    pub async fn find_organization_domains(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Vec<OrganizationDomain>> {
+        self.count_rows(
+            self.timed(
+                "find_organization_domains",
+                query_as!(
+                    OrganizationDomain,
+                    "SELECT * FROM organization_domains WHERE organization_id = $1
+                    ORDER BY created_at, hostname",
+                    organization_id
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Add a custom hostname to an organization, with a new verification token for its challenge
+    /// record. Adding a hostname the organization already has checks its record again right away
+    /// instead. This will return none if the organization already has as many hostnames as it can.
+    pub async fn add_organization_domain(
+        &self,
+        organization_id: Uuid,
+        hostname: &str,
+    ) -> Result<Option<OrganizationDomain>> {
+        let existing = self
+            .timed(
+                "recheck_organization_domain",
+                query_as!(
+                    OrganizationDomain,
+                    "UPDATE organization_domains SET check_at = LEAST(check_at, NOW())
+                    WHERE organization_id = $1 AND hostname = $2
+                    RETURNING *",
+                    organization_id,
+                    hostname
+                )
+                .fetch_optional(self.db()),
+            )
+            .await?;
+        if existing.is_some() {
+            return Ok(existing);
+        }
+
+        let verification_token = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(domains::VERIFICATION_TOKEN_LENGTH)
+            .map(char::from)
+            .collect::<String>();
+        self.timed(
+            "add_organization_domain",
+            query_as!(
+                OrganizationDomain,
+                "INSERT INTO organization_domains (organization_id, hostname, verification_token)
+                SELECT $1, $2, $3
+                WHERE (SELECT COUNT(*) FROM organization_domains WHERE organization_id = $1) < $4
+                ON CONFLICT (organization_id, hostname) DO NOTHING
+                RETURNING *",
+                organization_id,
+                hostname,
+                verification_token,
+                domains::MAX_DOMAINS_PER_ORGANIZATION,
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Remove a custom hostname from an organization. Requests sent to it stop being routed to the
+    /// organization right away on this server, and once the domain worker reloads the verified
+    /// hostnames on others. This will return true if the organization had the hostname.
+    pub async fn remove_organization_domain(
+        &self,
+        organization_id: Uuid,
+        hostname: &str,
+    ) -> Result<bool> {
+        let result = self
+            .timed(
+                "remove_organization_domain",
+                query!(
+                    "DELETE FROM organization_domains WHERE organization_id = $1 AND hostname = $2",
+                    organization_id,
+                    hostname
+                )
+                .execute(self.db()),
+            )
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        if self.state.domains.organization(Some(hostname)) == Some(organization_id) {
+            self.state.domains.remove(hostname);
+        }
+        Ok(true)
+    }
+
+    /// Look up the challenge records of custom hostnames that are due to be checked, verifying
+    /// hostnames whose record was found and revoking hostnames whose record has been gone for too
+    /// long. Hostnames are claimed while they are checked, so they are only checked once if
+    /// several servers are running, and are checked again later if a lookup fails. A hostname
+    /// already verified by another organization is never verified. This will return the number of
+    /// hostnames claimed.
+    pub async fn check_organization_domains(&self) -> Result<usize> {
+        let claimed = self
+            .timed(
+                "claim_organization_domains",
+                query_as!(
+                    OrganizationDomain,
+                    "UPDATE organization_domains SET check_at = $2
+                    WHERE (organization_id, hostname) IN (
+                        SELECT organization_id, hostname FROM organization_domains
+                        WHERE check_at <= NOW()
+                        ORDER BY check_at LIMIT $1 FOR UPDATE SKIP LOCKED
+                    )
+                    RETURNING *",
+                    domains::CHECK_BATCH_SIZE,
+                    Utc::now() + Duration::seconds(domains::CLAIM_SECONDS),
+                )
+                .fetch_all(self.db()),
+            )
+            .await?;
+
+        for domain in &claimed {
+            if let Err(error) = self.check_organization_domain(domain).await {
+                log::error!(
+                    "Failed to check hostname {} of organization {}: {} (request {})",
+                    domain.hostname,
+                    domain.organization_id,
+                    error,
+                    self.request_id()
+                );
+            }
+        }
+
+        Ok(claimed.len())
+    }
+
+    /// Look up the challenge record of a claimed custom hostname, and record the outcome. The
+    /// hostname is left claimed if the lookup fails, so it is checked again once the claim expires.
+    async fn check_organization_domain(&self, domain: &OrganizationDomain) -> Result<()> {
+        let is_found =
+            domains::has_challenge_record(&domain.hostname, &domain.verification_token).await?;
+        let is_taken = self
+            .timed(
+                "find_domain_owner",
+                query_scalar!(
+                    r#"SELECT EXISTS (
+                        SELECT 1 FROM organization_domains
+                        WHERE hostname = $1 AND organization_id <> $2 AND verified_at IS NOT NULL
+                    ) AS "exists!""#,
+                    domain.hostname,
+                    domain.organization_id
+                )
+                .fetch_one(self.db()),
+            )
+            .await?;
+        let outcome = domains::check_outcome(
+            domain.verified_at.is_some(),
+            is_found && !is_taken,
+            domain.failed_checks,
+        );
+        if domain.verified_at.is_some() != outcome.is_verified {
+            log::info!(
+                "Hostname {} of organization {} is {} (request {})",
+                domain.hostname,
+                domain.organization_id,
+                if outcome.is_verified {
+                    "verified"
+                } else {
+                    "no longer verified"
+                },
+                self.request_id()
+            );
+        }
+
+        self.timed(
+            "record_organization_domain_check",
+            query!(
+                "UPDATE organization_domains SET
+                    verified_at = CASE WHEN $3 THEN COALESCE(verified_at, NOW()) END,
+                    checked_at = NOW(),
+                    check_at = $4,
+                    failed_checks = $5
+                WHERE organization_id = $1 AND hostname = $2",
+                domain.organization_id,
+                domain.hostname,
+                outcome.is_verified,
+                Utc::now() + Duration::seconds(outcome.next_check_seconds),
+                outcome.failed_checks,
+            )
+            .execute(self.db()),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Find every verified custom hostname, each with the ID of the organization it belongs to.
+    pub async fn find_verified_domains(&self) -> Result<Vec<(String, Uuid)>> {
+        let domains = self
+            .timed(
+                "find_verified_domains",
+                query!(
+                    "SELECT hostname, organization_id FROM organization_domains
+                    WHERE verified_at IS NOT NULL"
+                )
+                .fetch_all(self.db()),
+            )
+            .await?;
+
+        Ok(domains
+            .into_iter()
+            .map(|domain| (domain.hostname, domain.organization_id))
+            .collect())
+    }
+
+    /// Find the memberships of a user, including invitations they haven't accepted, oldest first.
+    pub async fn find_user_memberships(&self, user_id: Uuid) -> Result<Vec<Membership>> {
+        self.count_rows(
+            self.timed(
+                "find_user_memberships",
+                query_as!(
+                Membership,
+                "SELECT * FROM memberships WHERE user_id = $1 ORDER BY created_at, organization_id",
+                user_id,
+            )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Find a page of the members of an organization, including invited users, in the order they
+    /// were invited. Up to the specified number of members are returned, after skipping the
+    /// specified number.
+    pub async fn find_organization_members(
+        &self,
+        organization_id: Uuid,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Membership>> {
+        self.count_rows(
+            self.timed(
+                "find_organization_members",
+                query_as!(
+                    Membership,
+                    "
+                    SELECT * FROM memberships
+                    WHERE organization_id = $1
+                    ORDER BY created_at, user_id
+                    LIMIT $2 OFFSET $3
+                    ",
+                    organization_id,
+                    limit,
+                    offset,
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Invite a user to an organization on behalf of the member making the request, and email
+    /// them the invitation. Only owners and admins can invite users, and only owners can invite
+    /// other owners. The user only becomes a member once they accept. Failing to send the email is
+    /// logged rather than returned, as the user can still see the invitation when they log in.
+    pub async fn invite_member(
+        &self,
+        viewer: AuthenticatedUser,
+        organization_id: Uuid,
+        username: &str,
+        role: OrganizationRole,
+    ) -> Result<MembershipResult> {
+        let viewer_role = match self
+            .find_member_role(organization_id, viewer.user_id)
+            .await?
+        {
+            Some(viewer_role) => viewer_role,
+            None => return Ok(MembershipResult::OrganizationNotFound),
+        };
+        if !viewer_role.can_manage(role) {
+            return Ok(MembershipResult::Forbidden);
+        }
+        let user = match self.find_user_by_username(username).await? {
+            Some(user) => user,
+            None => return Ok(MembershipResult::UserNotFound),
+        };
+
+        let membership = self
+            .timed(
+                "invite_member",
+                query_as!(
+                    Membership,
+                    "
+                    INSERT INTO memberships (organization_id, user_id, role, invited_by)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT (organization_id, user_id) DO NOTHING
+                    RETURNING *
+                    ",
+                    organization_id,
+                    user.id,
+                    role.as_str(),
+                    viewer.user_id,
+                )
+                .fetch_optional(self.db()),
+            )
+            .await?;
+        let membership = match membership {
+            Some(membership) => membership,
+            None => return Ok(MembershipResult::AlreadyMember),
+        };
+
+        let organization = self.find_organization(organization_id).await?;
+        let inviter = self.find_user(viewer.user_id).await?;
+        if let (Some(organization), Some(inviter)) = (organization, inviter) {
+            let settings = self
+                .find_organization_email_settings(organization_id)
+                .await?;
+            let template = OrganizationInviteEmail {
+                sender_name: settings
+                    .as_ref()
+                    .and_then(|settings| settings.from_name.as_deref())
+                    .unwrap_or(&self.config().email_sender_name),
+                username: &user.username,
+                organization: &organization.name,
+                inviter: &inviter.username,
+                role: role.as_str(),
+            };
+            if let Err(error) = self
+                .send_organization_email(settings.as_ref(), &user.username, &user.email, &template)
+                .await
+            {
+                log::error!(
+                    "Failed to send organization invitation to user: {}: {} (request {})",
+                    user.id,
+                    error,
+                    self.request_id()
+                );
+            }
+        }
+
+        Ok(MembershipResult::Changed(membership))
+    }
+
+    /// Accept an invitation to an organization on behalf of the user making the request, making
+    /// them a member. This will return the membership, or none if the user wasn't invited to the
+    /// organization or already accepted.
+    pub async fn accept_invite(
+        &self,
+        viewer: AuthenticatedUser,
+        organization_id: Uuid,
+    ) -> Result<Option<Membership>> {
+        self.timed(
+            "accept_invite",
+            query_as!(
+                Membership,
+                "
+                    UPDATE memberships SET accepted_at = NOW(), updated_at = NOW()
+                    WHERE organization_id = $1 AND user_id = $2 AND accepted_at IS NULL
+                    RETURNING *
+                    ",
+                organization_id,
+                viewer.user_id,
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Remove a member or invited user from an organization on behalf of the member making the
+    /// request. Users can always remove themselves, such as to leave an organization or decline
+    /// an invitation. Otherwise, only owners and admins can remove members, and only owners can
+    /// remove other owners. The last owner of an organization can't be removed.
+    pub async fn remove_member(
+        &self,
+        viewer: AuthenticatedUser,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<MembershipResult> {
+        self.transaction(|transaction| {
+            Box::pin(async move {
+                if !self.lock_organization(transaction, organization_id).await? {
+                    return Ok(MembershipResult::OrganizationNotFound);
+                }
+                let member = match self
+                    .find_membership_in(transaction, organization_id, user_id)
+                    .await?
+                {
+                    Some(member) => member,
+                    None if user_id == viewer.user_id => {
+                        return Ok(MembershipResult::OrganizationNotFound)
+                    }
+                    None => return Ok(MembershipResult::MemberNotFound),
+                };
+                let member_role = member.organization_role()?;
+                if user_id != viewer.user_id {
+                    match self
+                        .find_membership_in(transaction, organization_id, viewer.user_id)
+                        .await?
+                    {
+                        Some(viewer_membership) if viewer_membership.is_accepted() => {
+                            if !viewer_membership
+                                .organization_role()?
+                                .can_manage(member_role)
+                            {
+                                return Ok(MembershipResult::Forbidden);
+                            }
+                        }
+                        _ => return Ok(MembershipResult::OrganizationNotFound),
+                    }
+                }
+                if member.is_accepted()
+                    && member_role == OrganizationRole::Owner
+                    && self.count_owners(transaction, organization_id).await? <= 1
+                {
+                    return Ok(MembershipResult::LastOwner);
+                }
+
+                self.timed(
+                    "remove_member",
+                    query!(
+                        "DELETE FROM memberships WHERE organization_id = $1 AND user_id = $2",
+                        organization_id,
+                        user_id,
+                    )
+                    .execute(&mut *transaction),
+                )
+                .await?;
+
+                Ok(MembershipResult::Changed(member))
+            })
+        })
+        .await
+    }
+
+    /// Change the role of a member of or user invited to an organization on behalf of the member
+    /// making the request. Only owners and admins can change roles, and only owners can make other
+    /// members owners or change the roles of owners. The last owner of an organization can't stop
+    /// being an owner.
+    pub async fn set_member_role(
+        &self,
+        viewer: AuthenticatedUser,
+        organization_id: Uuid,
+        user_id: Uuid,
+        role: OrganizationRole,
+    ) -> Result<MembershipResult> {
+        self.transaction(|transaction| {
+            Box::pin(async move {
+                if !self.lock_organization(transaction, organization_id).await? {
+                    return Ok(MembershipResult::OrganizationNotFound);
+                }
+                let viewer_role = match self
+                    .find_membership_in(transaction, organization_id, viewer.user_id)
+                    .await?
+                {
+                    Some(viewer_membership) if viewer_membership.is_accepted() => {
+                        viewer_membership.organization_role()?
+                    }
+                    _ => return Ok(MembershipResult::OrganizationNotFound),
+                };
+                let member = match self
+                    .find_membership_in(transaction, organization_id, user_id)
+                    .await?
+                {
+                    Some(member) => member,
+                    None => return Ok(MembershipResult::MemberNotFound),
+                };
+                let member_role = member.organization_role()?;
+                if !viewer_role.can_manage(member_role) || !viewer_role.can_manage(role) {
+                    return Ok(MembershipResult::Forbidden);
+                }
+                if member.is_accepted()
+                    && member_role == OrganizationRole::Owner
+                    && role != OrganizationRole::Owner
+                    && self.count_owners(transaction, organization_id).await? <= 1
+                {
+                    return Ok(MembershipResult::LastOwner);
+                }
+
+                let member = self
+                    .timed(
+                        "set_member_role",
+                        query_as!(
+                            Membership,
+                            "
+                            UPDATE memberships SET role = $1, updated_at = NOW()
+                            WHERE organization_id = $2 AND user_id = $3
+                            RETURNING *
+                            ",
+                            role.as_str(),
+                            organization_id,
+                            user_id,
+                        )
+                        .fetch_one(&mut *transaction),
+                    )
+                    .await?;
+
+                Ok(MembershipResult::Changed(member))
+            })
+        })
+        .await
+    }
+
+    /// Lock an organization until the end of a transaction, so concurrent changes to its members
+    /// can't together leave it without an owner. This will return false if the organization does
+    /// not exist.
+    async fn lock_organization(
+        &self,
+        connection: &mut PgConnection,
+        organization_id: Uuid,
+    ) -> Result<bool> {
+        Ok(self
+            .timed(
+                "lock_organization",
+                query!(
+                    "SELECT id FROM organizations WHERE id = $1 FOR UPDATE",
+                    organization_id
+                )
+                .fetch_optional(connection),
+            )
+            .await?
+            .is_some())
+    }
+
+    /// Count the owners of an organization that have accepted their membership.
+    async fn count_owners(
+        &self,
+        connection: &mut PgConnection,
+        organization_id: Uuid,
+    ) -> Result<i64> {
+        self.timed(
+            "count_owners",
+            query_scalar!(
+                r#"
+                    SELECT COUNT(*) AS "count!" FROM memberships
+                    WHERE organization_id = $1 AND role = $2 AND accepted_at IS NOT NULL
+                    "#,
+                organization_id,
+                OrganizationRole::Owner.as_str(),
+            )
+            .fetch_one(connection),
+        )
+        .await
+    }
+
+    /// Find a user's subscription. This will return none if the user has never subscribed to a
+    /// paid plan.
+    pub async fn find_subscription(&self, user_id: Uuid) -> Result<Option<Subscription>> {
+        self.timed(
+            "find_subscription",
+            query_as!(
+                Subscription,
+                "SELECT * FROM subscriptions WHERE user_id = $1",
+                user_id
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find a user's subscription and trial, using the cached records if there are any. Cached
+    /// records are invalidated when they change, but only once the change notification reaches
+    /// this server, so this shouldn't be used to read records right after changing them.
+    async fn find_cached_billing_records(&self, user_id: Uuid) -> Result<BillingRecords> {
+        if let Some(records) = self.state.caches.billing.get(user_id) {
+            return Ok(records);
+        }
+
+        let version = self.state.caches.billing.version();
+        let records = BillingRecords {
+            subscription: self.find_subscription(user_id).await?,
+            trial: self.find_trial(user_id).await?,
+        };
+        self.state
+            .caches
+            .billing
+            .insert(user_id, version, records.clone());
+        Ok(records)
+    }
+
+    /// Find a user's trial. This will return none if the user was never given a trial.
+    pub async fn find_trial(&self, user_id: Uuid) -> Result<Option<Trial>> {
+        self.timed(
+            "find_trial",
+            query_as!(Trial, "SELECT * FROM trials WHERE user_id = $1", user_id)
+                .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find the plan a user is entitled to. Users without an active subscription are on the plan
+    /// of their trial while it lasts and are restricted once it ends. Users that were never given
+    /// a trial are on the free plan.
+    pub async fn find_user_plan(&self, user_id: Uuid) -> Result<Plan> {
+        let subscription = self.find_subscription(user_id).await?;
+        user_plan(&subscription, &self.find_trial(user_id).await?)
+    }
+
+    /// Send a reminder email to every user whose trial ends within the specified number of days,
+    /// unless they have subscribed or were already reminded that close to the end of their trial.
+    /// This will return the number of users reminded.
+    pub async fn send_trial_reminders(&self, days: i32) -> Result<usize> {
+        // Mark the trials as reminded first, so users aren't reminded twice if several servers
+        // are running.
+        let trials = self
+            .timed(
+                "send_trial_reminders",
+                query_as!(
+                    Trial,
+                    "
+                    UPDATE trials SET reminder_days = $1
+                    WHERE ends_at > NOW()
+                        AND ends_at <= NOW() + make_interval(days => $1)
+                        AND (reminder_days IS NULL OR reminder_days > $1)
+                        AND NOT EXISTS (
+                            SELECT 1 FROM subscriptions
+                            WHERE subscriptions.user_id = trials.user_id
+                                AND subscriptions.status = ANY($2)
+                        )
+                    RETURNING *
+                    ",
+                    days,
+                    &billing::active_statuses() as &[String],
+                )
+                .fetch_all(self.db()),
+            )
+            .await?;
+
+        for trial in &trials {
+            let user = match self.find_user(trial.user_id).await? {
+                Some(user) => user,
+                None => continue,
+            };
+            let template = TrialEndingEmail {
+                sender_name: &self.config().email_sender_name,
+                username: &user.username,
+                plan: trial.plan.parse::<Plan>()?.name(),
+                ends_at: trial.ends_at.format("%B %-d, %Y at %H:%M UTC").to_string(),
+            };
+            if let Err(error) = self
+                .send_email(&user.username, &user.email, &template)
+                .await
+            {
+                log::error!(
+                    "Failed to send trial reminder to user {}: {} (request {})",
+                    user.id,
+                    error,
+                    self.request_id()
+                );
+            }
+        }
+
+        Ok(trials.len())
+    }
+
+    /// Check whether any alert thresholds were crossed since alerts were last checked, given what
+    /// happened on this server instance since then. Failed logins are counted across every server
+    /// instance. This will return an alert for each threshold that was crossed.
+    pub async fn check_alerts(&self, counts: &AlertCounts) -> Result<Vec<Alert>> {
+        let Config {
+            alert_check_interval_seconds,
+            alert_thresholds,
+            database_max_connection_count,
+            ..
+        } = self.config();
+
+        let failed_logins = self
+            .timed(
+                "check_alerts",
+                query_scalar!(
+                    r#"
+                    SELECT COUNT(*) AS "count!" FROM audit_events
+                    WHERE kind = $1 AND created_at > NOW() - make_interval(secs => $2)
+                    "#,
+                    AuditEventKind::LoginFailed.as_str(),
+                    f64::from(*alert_check_interval_seconds),
+                )
+                .fetch_one(self.db()),
+            )
+            .await?;
+        let connections_in_use = self.db().size().saturating_sub(self.db().num_idle() as u32);
+
+        Ok(alerts::evaluate(
+            alert_thresholds,
+            counts,
+            failed_logins,
+            connections_in_use,
+            *database_max_connection_count,
+        ))
+    }
+
+    /// Alert operators that a threshold was crossed. Alerts are always logged, and are posted to
+    /// the alert webhook and emailed to the alert email address if they are set. Once an alert is
+    /// sent, further alerts of the same kind are only logged until its cooldown ends, even if they
+    /// are raised by other server instances. This will return an error if the alert couldn't be
+    /// delivered.
+    pub async fn send_alert(&self, alert: &Alert) -> Result<()> {
+        let Config {
+            alert_cooldown_seconds,
+            alert_webhook_url,
+            alert_email_address,
+            email_sender_name,
+            ..
+        } = self.config();
+
+        log::warn!(
+            "Alert {}: {} (request {})",
+            alert.kind.name(),
+            alert.message,
+            self.request_id()
+        );
+        if alert_webhook_url.is_none() && alert_email_address.is_none() {
+            return Ok(());
+        }
+
+        if *alert_cooldown_seconds > 0 {
+            // Only the first instance to claim the cooldown sends the alert.
+            let claimed = redis::cmd("SET")
+                .arg(alerts::cooldown_key(alert.kind))
+                .arg(self.request_id())
+                .arg("NX")
+                .arg("EX")
+                .arg(*alert_cooldown_seconds)
+                .query_async::<_, Option<String>>(&mut self.redis())
+                .await?;
+            if claimed.is_none() {
+                return Ok(());
+            }
+        }
+
+        let mut result = Ok(());
+        if let Some(url) = alert_webhook_url {
+            result = alerts::post_to_webhook(url, email_sender_name, alert).await;
+        }
+        if let Some(email) = alert_email_address {
+            let template = AlertEmail {
+                sender_name: email_sender_name,
+                username: alerts::EMAIL_RECIPIENT_NAME,
+                alert: alert.kind.name(),
+                message: &alert.message,
+                cooldown: describe_duration(*alert_cooldown_seconds),
+            };
+            let sent = self
+                .send_email(alerts::EMAIL_RECIPIENT_NAME, email, &template)
+                .await;
+            result = result.and(sent);
+        }
+
+        result
+    }
+
+    /// Create a Stripe checkout session for a user to subscribe to a plan with. Users that have
+    /// subscribed before are charged as the same Stripe customer. This will return none if the
+    /// user does not exist or the plan can't be purchased.
+    pub async fn create_checkout_session(
+        &self,
+        user_id: Uuid,
+        plan: Plan,
+    ) -> Result<Option<CheckoutSession>> {
+        let price_id = match plan.stripe_price_id(self.config()) {
+            Some(price_id) if self.config().stripe_secret_key.is_some() => price_id,
+            _ => return Ok(None),
+        };
+        let user = match self.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let subscription = self.find_subscription(user_id).await?;
+        let customer_id = subscription
+            .as_ref()
+            .map(|subscription| subscription.stripe_customer_id.as_str());
+        let promo_code = self.find_pending_promo_code(user_id).await?;
+        let coupon_id = promo_code
+            .as_ref()
+            .map(|promo_code| promo_code.stripe_coupon_id.as_str());
+        let checkout_session = billing::create_checkout_session(
+            self.config(),
+            &user,
+            price_id,
+            customer_id,
+            coupon_id,
+        )
+        .await?;
+
+        log::info!(
+            "Created checkout session {} for user: {} (request {})",
+            checkout_session.id,
+            user_id,
+            self.request_id()
+        );
+        Ok(Some(checkout_session))
+    }
+
+    /// Store the current state of a subscription sent by Stripe. Subscriptions for unknown users
+    /// or prices are ignored.
+    pub async fn sync_subscription(&self, subscription: &StripeSubscription) -> Result<()> {
+        let user_id = match subscription.user_id() {
+            Some(user_id) if self.find_user_including_deleted(user_id).await?.is_some() => user_id,
+            _ => {
+                log::warn!(
+                    "Ignoring Stripe subscription for unknown user: {}",
+                    subscription.id
+                );
+                return Ok(());
+            }
+        };
+        let plan = match subscription
+            .price_id()
+            .and_then(|price_id| Plan::from_stripe_price_id(self.config(), price_id))
+        {
+            Some(plan) => plan,
+            None => {
+                log::warn!(
+                    "Ignoring Stripe subscription with unknown price: {}",
+                    subscription.id
+                );
+                return Ok(());
+            }
+        };
+
+        let current_period_end = Utc.timestamp(subscription.current_period_end, 0);
+        self.timed(
+            "sync_subscription",
+            query!(
+                "
+                INSERT INTO subscriptions (
+                    user_id,
+                    stripe_customer_id,
+                    stripe_subscription_id,
+                    plan,
+                    status,
+                    current_period_end,
+                    cancel_at_period_end
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (user_id) DO UPDATE SET
+                    stripe_customer_id = EXCLUDED.stripe_customer_id,
+                    stripe_subscription_id = EXCLUDED.stripe_subscription_id,
+                    plan = EXCLUDED.plan,
+                    status = EXCLUDED.status,
+                    current_period_end = EXCLUDED.current_period_end,
+                    cancel_at_period_end = EXCLUDED.cancel_at_period_end,
+                    updated_at = NOW()
+                ",
+                user_id,
+                subscription.customer,
+                subscription.id,
+                plan.as_str(),
+                subscription.status,
+                current_period_end,
+                subscription.cancel_at_period_end,
+            )
+            .execute(self.db()),
+        )
+        .await?;
+
+        if let Some(coupon_id) = subscription.coupon_id() {
+            self.mark_promo_codes_applied(user_id, &subscription.id, coupon_id)
+                .await?;
+        }
+
+        log::info!(
+            "Synced Stripe subscription {} for user: {} ({})",
+            subscription.id,
+            user_id,
+            subscription.status
+        );
+        Ok(())
+    }
+
+    /// Record a webhook event a provider sent and process it, unless it was already processed
+    /// successfully, as providers resend events they aren't sure were received. This will return
+    /// an error if processing failed, so the provider sends the event again later.
+    pub async fn receive_webhook_event(
+        &self,
+        provider: WebhookProvider,
+        event_id: &str,
+        kind: &str,
+        payload: &str,
+    ) -> Result<()> {
+        let event = self
+            .timed(
+                "receive_webhook_event",
+                query_as!(
+                    WebhookEvent,
+                    "INSERT INTO webhook_events (id, provider, event_id, kind, payload)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT (provider, event_id) DO UPDATE SET provider = EXCLUDED.provider
+                    RETURNING *",
+                    Uuid::new_v4(),
+                    provider.as_str(),
+                    event_id,
+                    kind,
+                    payload,
+                )
+                .fetch_one(self.db()),
+            )
+            .await?;
+
+        if event.processed_at.is_some() {
+            log::info!(
+                "Ignoring {} event {} that was already processed (request {})",
+                provider,
+                event_id,
+                self.request_id()
+            );
+            return Ok(());
+        }
+
+        match self.process_webhook_event(&event).await?.error {
+            Some(error) => Err(anyhow!(error)),
+            None => Ok(()),
+        }
+    }
+
+    /// Process a recorded webhook event again, whether or not it was processed successfully
+    /// before, on behalf of the admin making the request. This will return the event as recorded
+    /// after processing it, or none if the event does not exist.
+    pub async fn replay_webhook_event(
+        &self,
+        viewer: AuthenticatedUser,
+        id: Uuid,
+    ) -> Result<Option<WebhookEvent>> {
+        let event = self
+            .timed(
+                "replay_webhook_event",
+                query_as!(
+                    WebhookEvent,
+                    "SELECT * FROM webhook_events WHERE id = $1",
+                    id
+                )
+                .fetch_optional(self.db()),
+            )
+            .await?;
+        let event = match event {
+            Some(event) => event,
+            None => return Ok(None),
+        };
+
+        log::info!(
+            "Replaying {} event {} for user {} (request {})",
+            event.provider,
+            event.event_id,
+            viewer.user_id,
+            self.request_id()
+        );
+        Ok(Some(self.process_webhook_event(&event).await?))
+    }
+
+    /// Process a recorded webhook event, and record whether it succeeded. Events that succeeded
+    /// before keep the time they were processed if they fail when replayed. This will return the
+    /// event as recorded after processing it.
+    async fn process_webhook_event(&self, event: &WebhookEvent) -> Result<WebhookEvent> {
+        let error = match self.handle_webhook_event(event).await {
+            Ok(()) => None,
+            Err(error) => {
+                log::error!(
+                    "Failed to process {} event {}: {} (request {})",
+                    event.provider,
+                    event.event_id,
+                    error,
+                    self.request_id()
+                );
+                Some(error.to_string())
+            }
+        };
+
+        self.timed(
+            "process_webhook_event",
+            query_as!(
+                WebhookEvent,
+                "UPDATE webhook_events
+                    SET processed_at = CASE WHEN $2::TEXT IS NULL THEN NOW() ELSE processed_at END,
+                        error = $2
+                    WHERE id = $1
+                    RETURNING *",
+                event.id,
+                error,
+            )
+            .fetch_one(self.db()),
+        )
+        .await
+    }
+
+    /// Do what a webhook event asks for. Events the server doesn't need are ignored.
+    async fn handle_webhook_event(&self, event: &WebhookEvent) -> Result<()> {
+        match event.provider.parse()? {
+            WebhookProvider::Stripe => match event.kind.as_str() {
+                "customer.subscription.created"
+                | "customer.subscription.updated"
+                | "customer.subscription.deleted" => {
+                    let mut payload: Value = serde_json::from_str(&event.payload)?;
+                    let subscription: StripeSubscription =
+                        serde_json::from_value(payload["data"]["object"].take())?;
+                    self.sync_subscription(&subscription).await
+                }
+                kind => {
+                    log::info!("Ignoring Stripe event: {}", kind);
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    /// Find recorded webhook events, newest first. Only events that haven't been processed
+    /// successfully are found if specified.
+    pub async fn find_webhook_events(
+        &self,
+        limit: i64,
+        offset: i64,
+        unprocessed_only: bool,
+    ) -> Result<Vec<WebhookEvent>> {
+        self.count_rows(
+            self.timed(
+                "find_webhook_events",
+                query_as!(
+                    WebhookEvent,
+                    "SELECT * FROM webhook_events
+                    WHERE NOT $3 OR processed_at IS NULL
+                    ORDER BY received_at DESC, id
+                    LIMIT $1 OFFSET $2",
+                    limit,
+                    offset,
+                    unprocessed_only,
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Create a promo code on behalf of an admin, along with the Stripe coupon its discount is
+    /// applied with. The code is stored in upper case.
+    pub async fn create_promo_code(
+        &self,
+        created_by: Uuid,
+        code: &str,
+        kind: PromoCodeKind,
+        amount: i32,
+        max_redemptions: Option<i32>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<PromoCode> {
+        let code = promo_codes::normalize_code(code);
+        let stripe_coupon_id = billing::create_coupon(self.config(), &code, kind, amount).await?;
+        let currency = match kind {
+            PromoCodeKind::Percentage => None,
+            PromoCodeKind::Fixed => Some(self.config().billing_currency.clone()),
+        };
+
+        let promo_code = self
+            .timed(
+                "create_promo_code",
+                query_as!(
+                    PromoCode,
+                    "
+                    INSERT INTO promo_codes (
+                        id,
+                        created_by,
+                        code,
+                        kind,
+                        amount,
+                        currency,
+                        max_redemptions,
+                        expires_at,
+                        stripe_coupon_id
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                    RETURNING *
+                    ",
+                    Uuid::new_v4(),
+                    created_by,
+                    code,
+                    kind.as_str(),
+                    amount,
+                    currency,
+                    max_redemptions,
+                    expires_at,
+                    stripe_coupon_id,
+                )
+                .fetch_one(self.db()),
+            )
+            .await?;
+
+        log::info!(
+            "Created promo code {} by user: {} (request {})",
+            promo_code.code,
+            created_by,
+            self.request_id()
+        );
+        Ok(promo_code)
+    }
+
+    /// Find a promo code by its code, ignoring case. This will return none if no promo code has
+    /// the code.
+    pub async fn find_promo_code_by_code(&self, code: &str) -> Result<Option<PromoCode>> {
+        self.timed(
+            "find_promo_code_by_code",
+            query_as!(
+                PromoCode,
+                "SELECT * FROM promo_codes WHERE code = $1",
+                promo_codes::normalize_code(code)
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find every promo code, newest first.
+    pub async fn find_promo_codes(&self) -> Result<Vec<PromoCode>> {
+        self.count_rows(
+            self.timed(
+                "find_promo_codes",
+                query_as!(
+                    PromoCode,
+                    "SELECT * FROM promo_codes ORDER BY created_at DESC"
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Find every redemption of a promo code, oldest first.
+    pub async fn find_promo_code_redemptions(
+        &self,
+        promo_code_id: Uuid,
+    ) -> Result<Vec<PromoCodeRedemption>> {
+        self.count_rows(
+            self.timed(
+                "find_promo_code_redemptions",
+                query_as!(
+                    PromoCodeRedemption,
+                    "
+                    SELECT * FROM promo_code_redemptions
+                    WHERE promo_code_id = $1
+                    ORDER BY created_at
+                    ",
+                    promo_code_id
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Find the promo code a user has redeemed that hasn't been applied to a subscription yet. This
+    /// will return none if the user has no such promo code.
+    pub async fn find_pending_promo_code(&self, user_id: Uuid) -> Result<Option<PromoCode>> {
+        self.timed(
+            "find_pending_promo_code",
+            query_as!(
+                PromoCode,
+                "
+                    SELECT promo_codes.* FROM promo_codes
+                    INNER JOIN promo_code_redemptions
+                        ON promo_code_redemptions.promo_code_id = promo_codes.id
+                    WHERE promo_code_redemptions.user_id = $1
+                        AND promo_code_redemptions.applied_at IS NULL
+                    ",
+                user_id
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Attempt to redeem a promo code for a user. The redemption is recorded along with the
+    /// request it was made in. If the user has an active subscription, the discount is applied to
+    /// it right away. Otherwise, it is applied when the user subscribes.
+    pub async fn redeem_promo_code(&self, user_id: Uuid, code: &str) -> Result<RedeemResult> {
+        let promo_code = match self.find_promo_code_by_code(code).await? {
+            Some(promo_code) => promo_code,
+            None => return Ok(RedeemResult::NotFound),
+        };
+        if promo_code
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+        {
+            return Ok(RedeemResult::Expired);
+        }
+        let already_redeemed = self
+            .timed(
+                "check_promo_code_redeemed",
+                query!(
+                    r#"
+                    SELECT EXISTS (
+                        SELECT 1 FROM promo_code_redemptions
+                            WHERE promo_code_id = $1 AND user_id = $2
+                    ) AS "already_redeemed!"
+                    "#,
+                    promo_code.id,
+                    user_id
+                )
+                .fetch_one(self.db()),
+            )
+            .await?
+            .already_redeemed;
+        if already_redeemed {
+            return Ok(RedeemResult::AlreadyRedeemed);
+        }
+        if self.find_pending_promo_code(user_id).await?.is_some() {
+            return Ok(RedeemResult::Pending);
+        }
+
+        // Count the redemption and record it together, so neither happens without the other.
+        let mut transaction = self.db().begin().await?;
+        let claimed = self
+            .timed(
+                "claim_promo_code",
+                query!(
+                    "
+                    UPDATE promo_codes SET redemption_count = redemption_count + 1
+                    WHERE id = $1
+                        AND (max_redemptions IS NULL OR redemption_count < max_redemptions)
+                        AND (expires_at IS NULL OR expires_at > NOW())
+                    ",
+                    promo_code.id
+                )
+                .execute(&mut transaction),
+            )
+            .await?
+            .rows_affected();
+        if claimed == 0 {
+            return Ok(RedeemResult::Exhausted);
+        }
+
+        let redemption = self
+            .timed(
+                "insert_promo_code_redemption",
+                query_as!(
+                    PromoCodeRedemption,
+                    "
+                    INSERT INTO promo_code_redemptions
+                        (id, promo_code_id, user_id, request_id, client_ip)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT (promo_code_id, user_id) DO NOTHING
+                    RETURNING *
+                    ",
+                    Uuid::new_v4(),
+                    promo_code.id,
+                    user_id,
+                    self.request_id(),
+                    self.client_ip(),
+                )
+                .fetch_optional(&mut transaction),
+            )
+            .await?;
+        let mut redemption = match redemption {
+            Some(redemption) => redemption,
+            None => return Ok(RedeemResult::AlreadyRedeemed),
+        };
+        transaction.commit().await?;
+
+        log::info!(
+            "Redeemed promo code {} for user: {} (request {})",
+            promo_code.code,
+            user_id,
+            self.request_id()
+        );
+
+        match self.find_subscription(user_id).await? {
+            Some(subscription) if billing::is_active_status(&subscription.status) => {
+                let applied = billing::apply_coupon(
+                    self.config(),
+                    &subscription.stripe_subscription_id,
+                    &promo_code.stripe_coupon_id,
+                )
+                .await;
+                match applied {
+                    Ok(()) => {
+                        self.mark_promo_codes_applied(
+                            user_id,
+                            &subscription.stripe_subscription_id,
+                            &promo_code.stripe_coupon_id,
+                        )
+                        .await?;
+                        redemption.applied_at = Some(Utc::now());
+                        redemption.stripe_subscription_id =
+                            Some(subscription.stripe_subscription_id);
+                    }
+                    // The redemption is kept, so the discount can still be applied by hand.
+                    Err(error) => log::error!(
+                        "Failed to apply promo code redemption {}: {} (request {})",
+                        redemption.id,
+                        error,
+                        self.request_id()
+                    ),
+                }
+            }
+            _ => {}
+        }
+
+        Ok(RedeemResult::Redeemed(redemption))
+    }
+
+    /// Mark a user's pending promo code redemptions as applied to a Stripe subscription, if the
+    /// subscription was discounted with the promo code's coupon.
+    async fn mark_promo_codes_applied(
+        &self,
+        user_id: Uuid,
+        stripe_subscription_id: &str,
+        stripe_coupon_id: &str,
+    ) -> Result<()> {
+        self.timed(
+            "mark_promo_codes_applied",
+            query!(
+                "
+                UPDATE promo_code_redemptions
+                SET applied_at = NOW(), stripe_subscription_id = $2
+                FROM promo_codes
+                WHERE promo_code_redemptions.promo_code_id = promo_codes.id
+                    AND promo_code_redemptions.user_id = $1
+                    AND promo_code_redemptions.applied_at IS NULL
+                    AND promo_codes.stripe_coupon_id = $3
+                ",
+                user_id,
+                stripe_subscription_id,
+                stripe_coupon_id,
+            )
+            .execute(self.db()),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Create an announcement on behalf of an admin. Announcements without a start date start being
+    /// shown right away.
+    pub async fn create_announcement(
+        &self,
+        created_by: Uuid,
+        message: &str,
+        severity: Severity,
+        audience: Audience,
+        starts_at: Option<DateTime<Utc>>,
+        ends_at: Option<DateTime<Utc>>,
+    ) -> Result<Announcement> {
+        let announcement = self
+            .timed(
+                "create_announcement",
+                query_as!(
+                    Announcement,
+                    "
+                    INSERT INTO announcements (
+                        id,
+                        created_by,
+                        message,
+                        severity,
+                        audience,
+                        starts_at,
+                        ends_at
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    RETURNING *
+                    ",
+                    Uuid::new_v4(),
+                    created_by,
+                    message,
+                    severity.as_str(),
+                    audience.as_str(),
+                    starts_at.unwrap_or_else(Utc::now),
+                    ends_at,
+                )
+                .fetch_one(self.db()),
+            )
+            .await?;
+
+        self.invalidate_cached(&[CachedQuery::ActiveAnnouncements])
+            .await;
+        Ok(announcement)
+    }
+
+    /// Update an announcement. Details that are none are left unchanged. This will return the
+    /// updated announcement, or none if the announcement does not exist.
+    pub async fn update_announcement(
+        &self,
+        id: Uuid,
+        message: Option<&str>,
+        severity: Option<Severity>,
+        audience: Option<Audience>,
+        starts_at: Option<DateTime<Utc>>,
+        ends_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<Announcement>> {
+        let announcement = self
+            .timed(
+                "update_announcement",
+                query_as!(
+                    Announcement,
+                    "
+                    UPDATE announcements
+                    SET
+                        message = COALESCE($2, message),
+                        severity = COALESCE($3, severity),
+                        audience = COALESCE($4, audience),
+                        starts_at = COALESCE($5, starts_at),
+                        ends_at = COALESCE($6, ends_at),
+                        updated_at = NOW()
+                    WHERE id = $1
+                    RETURNING *
+                    ",
+                    id,
+                    message,
+                    severity.map(Severity::as_str),
+                    audience.map(Audience::as_str),
+                    starts_at,
+                    ends_at,
+                )
+                .fetch_optional(self.db()),
+            )
+            .await?;
+
+        self.invalidate_cached(&[CachedQuery::ActiveAnnouncements])
+            .await;
+        Ok(announcement)
+    }
+
+    /// Delete an announcement. This will return true if the announcement existed.
+    pub async fn delete_announcement(&self, id: Uuid) -> Result<bool> {
+        let deleted = self
+            .timed(
+                "delete_announcement",
+                query!("DELETE FROM announcements WHERE id = $1", id).execute(self.db()),
+            )
+            .await?
+            .rows_affected();
+
+        if deleted > 0 {
+            self.invalidate_cached(&[CachedQuery::ActiveAnnouncements])
+                .await;
+        }
+        Ok(deleted > 0)
+    }
+
+    /// Find an announcement by ID. This will return none if the announcement is not found.
+    pub async fn find_announcement(&self, id: Uuid) -> Result<Option<Announcement>> {
+        self.timed(
+            "find_announcement",
+            query_as!(
+                Announcement,
+                "SELECT * FROM announcements WHERE id = $1",
+                id
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find every announcement, including those that have ended or haven't started yet, newest
+    /// first.
+    pub async fn find_announcements(&self) -> Result<Vec<Announcement>> {
+        self.count_rows(
+            self.timed(
+                "find_announcements",
+                query_as!(
+                    Announcement,
+                    "SELECT * FROM announcements ORDER BY starts_at DESC"
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Find every announcement currently being shown to one of the specified audiences, newest
+    /// first. If a user is specified, announcements they have dismissed are left out.
+    pub async fn find_active_announcements(
+        &self,
+        user_id: Option<Uuid>,
+        audiences: &[Audience],
+    ) -> Result<Vec<Announcement>> {
+        let audiences = audiences
+            .iter()
+            .map(|audience| audience.as_str().into())
+            .collect::<Vec<String>>();
+        let variables = json!({ "audiences": audiences });
+
+        self.cached(
+            CachedQuery::ActiveAnnouncements,
+            user_id,
+            variables,
+            async {
+                self.count_rows(
+                    self.timed(
+                        "find_active_announcements",
+                        query_as!(
+                            Announcement,
+                            "
+                            SELECT * FROM announcements
+                            WHERE starts_at <= NOW()
+                                AND (ends_at IS NULL OR ends_at > NOW())
+                                AND audience = ANY($1)
+                                AND NOT EXISTS (
+                                    SELECT 1 FROM announcement_dismissals
+                                    WHERE announcement_id = announcements.id AND user_id = $2
+                                )
+                            ORDER BY starts_at DESC
+                            ",
+                            &audiences,
+                            user_id,
+                        )
+                        .fetch_all(self.db()),
+                    )
+                    .await?,
+                )
+            },
+        )
+        .await
+    }
+
+    /// Dismiss an announcement for a user, so it is no longer shown to them. This will return true
+    /// if the announcement exists.
+    pub async fn dismiss_announcement(&self, user_id: Uuid, id: Uuid) -> Result<bool> {
+        if self.find_announcement(id).await?.is_none() {
+            return Ok(false);
+        }
+
+        self.timed(
+            "dismiss_announcement",
+            query!(
+                "
+                INSERT INTO announcement_dismissals (announcement_id, user_id)
+                VALUES ($1, $2)
+                ON CONFLICT DO NOTHING
+                ",
+                id,
+                user_id
+            )
+            .execute(self.db()),
+        )
+        .await?;
+
+        self.invalidate_cached(&[CachedQuery::ActiveAnnouncements])
+            .await;
+        Ok(true)
+    }
+
+    /// Submit feedback on behalf of a user, or anonymously if no user is specified. Submissions are
+    /// limited per user, or per IP address for anonymous feedback. This will return none, without
+    /// storing the feedback, if the limit has been reached.
+    pub async fn submit_feedback(
+        &self,
+        user_id: Option<Uuid>,
+        category: FeedbackCategory,
+        message: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<Option<Feedback>> {
+        if !self.reserve_feedback_submission(user_id).await? {
+            return Ok(None);
+        }
+
+        let feedback = self
+            .timed(
+                "submit_feedback",
+                query_as!(
+                    Feedback,
+                    "
+                    INSERT INTO feedback
+                        (id, user_id, category, message, metadata, request_id, client_ip)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    RETURNING *
+                    ",
+                    Uuid::new_v4(),
+                    user_id,
+                    category.as_str(),
+                    message,
+                    serde_json::to_string(metadata)?,
+                    self.request_id(),
+                    self.client_ip(),
+                )
+                .fetch_one(self.db()),
+            )
+            .await?;
+
+        self.invalidate_cached(&[CachedQuery::Feedback]).await;
+        log::info!(
+            "Received {} feedback {} (request {})",
+            feedback.category,
+            feedback.id,
+            self.request_id()
+        );
+        Ok(Some(feedback))
+    }
+
+    /// Count a feedback submission against the hourly limit of the user or IP address making it.
+    /// This will return false if the limit has been reached.
+    async fn reserve_feedback_submission(&self, user_id: Option<Uuid>) -> Result<bool> {
+        let limit = self.config().feedback_hourly_limit;
+        if limit == 0 {
+            return Ok(true);
+        }
+
+        let hour = Utc::now().format("%Y%m%d%H").to_string();
+        let key = match (user_id, self.client_ip()) {
+            (Some(user_id), _) => feedback::submissions_key("user", &user_id.to_string(), &hour),
+            (None, Some(client_ip)) => feedback::submissions_key("ip", client_ip, &hour),
+            (None, None) => feedback::submissions_key("ip", "unknown", &hour),
+        };
+
+        let count = self.store().incr(&key, 60 * 60).await?;
+        Ok(count <= limit.into())
+    }
+
+    /// Record a security-relevant event in the audit log, along with the request it happened in.
+    /// The event is about one user, and may have been caused by another, such as an admin granting
+    /// them a role.
+    pub async fn record_audit_event(
+        &self,
+        kind: AuditEventKind,
+        user_id: Option<Uuid>,
+        actor_id: Option<Uuid>,
+        metadata: &[(&str, &str)],
+    ) -> Result<()> {
+        self.transaction(|transaction| {
+            Box::pin(self.record_audit_event_in(transaction, kind, user_id, actor_id, metadata))
+        })
+        .await
+    }
+
+    /// Record an event in the audit log like `record_audit_event`, as part of a transaction, so
+    /// the event is only kept if the change it records is.
+    pub async fn record_audit_event_in(
+        &self,
+        connection: &mut PgConnection,
+        kind: AuditEventKind,
+        user_id: Option<Uuid>,
+        actor_id: Option<Uuid>,
+        metadata: &[(&str, &str)],
+    ) -> Result<()> {
+        self.record_audit_changes_in(connection, kind, user_id, actor_id, metadata, &[])
+            .await
+    }
+
+    /// Record an event in the audit log like `record_audit_event_in`, along with the fields a
+    /// sensitive admin action changed, so reviewers can see exactly what the action did.
+    pub async fn record_audit_changes_in(
+        &self,
+        connection: &mut PgConnection,
+        kind: AuditEventKind,
+        user_id: Option<Uuid>,
+        actor_id: Option<Uuid>,
+        metadata: &[(&str, &str)],
+        changes: &[AuditChange],
+    ) -> Result<()> {
+        let metadata = metadata.iter().copied().collect::<BTreeMap<_, _>>();
+        self.timed(
+            "record_audit_event_in",
+            query!(
+                "
+                INSERT INTO audit_events (
+                    id, kind, user_id, actor_id, metadata, changes, request_id, client_ip,
+                    user_agent
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                ",
+                Uuid::new_v4(),
+                kind.as_str(),
+                user_id,
+                actor_id,
+                serde_json::to_string(&metadata)?,
+                serde_json::to_string(changes)?,
+                self.request_id(),
+                self.client_ip(),
+                self.user_agent(),
+            )
+            .execute(&mut *connection),
+        )
+        .await?;
+
+        // Events users should know about are also shown in their security activity.
+        if let (Some(user_id), Some(kind)) = (user_id, SecurityEventKind::from_audit_event(kind)) {
+            self.record_security_event(connection, kind, user_id)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Record a sensitive admin action taken while handling the request, so what it changed is
+    /// returned in the response's extensions. Actions that changed nothing aren't recorded.
+    fn record_audited_action(
+        &self,
+        kind: AuditEventKind,
+        user_id: Uuid,
+        dry_run: bool,
+        changes: Vec<AuditChange>,
+    ) {
+        if !changes.is_empty() {
+            self.audited_actions.push(AuditedAction {
+                kind,
+                user_id,
+                dry_run,
+                changes,
+            });
+        }
+    }
+
+    /// Record an event in the security activity of a user's account. Logins with a user agent the
+    /// user hasn't logged in with before are also recorded as logins from a new device, unless
+    /// they are the user's first login. Events about users that don't exist are ignored.
+    pub async fn record_security_event(
+        &self,
+        connection: &mut PgConnection,
+        kind: SecurityEventKind,
+        user_id: Uuid,
+    ) -> Result<()> {
+        if kind == SecurityEventKind::Login {
+            let logins = self
+                .timed(
+                    "record_security_event",
+                    query!(
+                        r#"
+                        SELECT
+                            EXISTS (
+                                SELECT 1 FROM security_events WHERE user_id = $1 AND kind = $2
+                            ) AS "has_logged_in!",
+                            EXISTS (
+                                SELECT 1 FROM security_events
+                                WHERE user_id = $1 AND kind = $2
+                                    AND user_agent IS NOT DISTINCT FROM $3
+                            ) AS "is_known_device!"
+                        "#,
+                        user_id,
+                        kind.as_str(),
+                        self.user_agent(),
+                    )
+                    .fetch_one(&mut *connection),
+                )
+                .await?;
+            if logins.has_logged_in && !logins.is_known_device {
+                self.insert_security_event(connection, SecurityEventKind::NewDevice, user_id)
+                    .await?;
+            }
+        }
+
+        self.insert_security_event(connection, kind, user_id).await
+    }
+
+    /// Store an event in the security activity of a user's account, if the user exists.
+    async fn insert_security_event(
+        &self,
+        connection: &mut PgConnection,
+        kind: SecurityEventKind,
+        user_id: Uuid,
+    ) -> Result<()> {
+        self.timed(
+            "insert_security_event",
+            query!(
+                "
+                INSERT INTO security_events (id, user_id, kind, client_ip, user_agent)
+                SELECT $1, $2, $3, $4, $5
+                WHERE EXISTS (SELECT 1 FROM users WHERE id = $2)
+                ",
+                Uuid::new_v4(),
+                user_id,
+                kind.as_str(),
+                self.client_ip(),
+                self.user_agent(),
+            )
+            .execute(connection),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Find the security activity of a user's account, newest first. Up to the specified number of
+    /// events are returned, after skipping the specified number.
+    pub async fn find_security_events(
+        &self,
+        user_id: Uuid,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SecurityEvent>> {
+        self.count_rows(
+            self.timed(
+                "find_security_events",
+                query_as!(
+                    SecurityEvent,
+                    "
+                    SELECT * FROM security_events
+                    WHERE user_id = $1
+                    ORDER BY created_at DESC
+                    LIMIT $2 OFFSET $3
+                    ",
+                    user_id,
+                    limit,
+                    offset,
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Find audit events about a user and of a kind, newest first. Filters that are none match any
+    /// event. Up to the specified number of events are returned, after skipping the specified
+    /// number.
+    pub async fn find_audit_events(
+        &self,
+        user_id: Option<Uuid>,
+        kind: Option<AuditEventKind>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AuditEvent>> {
+        self.count_rows(
+            self.timed(
+                "find_audit_events",
+                query_as!(
+                    AuditEvent,
+                    "
+                    SELECT * FROM audit_events
+                    WHERE ($1::UUID IS NULL OR user_id = $1)
+                        AND ($2::VARCHAR IS NULL OR kind = $2)
+                    ORDER BY created_at DESC
+                    LIMIT $3 OFFSET $4
+                    ",
+                    user_id,
+                    kind.map(AuditEventKind::as_str),
+                    limit,
+                    offset,
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Find feedback by ID. This will return none if the feedback is not found.
+    pub async fn find_feedback(&self, id: Uuid) -> Result<Option<Feedback>> {
+        self.timed(
+            "find_feedback",
+            query_as!(Feedback, "SELECT * FROM feedback WHERE id = $1", id)
+                .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find feedback with a status and category, newest first. Filters that are none match any
+    /// feedback. Up to the specified number of submissions are returned, after skipping the
+    /// specified number.
+    pub async fn find_feedback_submissions(
+        &self,
+        status: Option<FeedbackStatus>,
+        category: Option<FeedbackCategory>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Feedback>> {
+        let status = status.map(FeedbackStatus::as_str);
+        let category = category.map(FeedbackCategory::as_str);
+        let variables = json!({
+            "status": status,
+            "category": category,
+            "limit": limit,
+            "offset": offset,
+        });
+
+        self.cached(CachedQuery::Feedback, None, variables, async {
+            self.count_rows(
+                self.timed(
+                    "find_feedback_submissions",
+                    query_as!(
+                        Feedback,
+                        "
+                        SELECT * FROM feedback
+                        WHERE ($1::VARCHAR IS NULL OR status = $1)
+                            AND ($2::VARCHAR IS NULL OR category = $2)
+                        ORDER BY created_at DESC
+                        LIMIT $3 OFFSET $4
+                        ",
+                        status,
+                        category,
+                        limit,
+                        offset,
+                    )
+                    .fetch_all(self.db()),
+                )
+                .await?,
+            )
+        })
+        .await
+    }
+
+    /// Move feedback to another triage status. This will return the updated feedback, or none if
+    /// the feedback does not exist.
+    pub async fn update_feedback_status(
+        &self,
+        id: Uuid,
+        status: FeedbackStatus,
+    ) -> Result<Option<Feedback>> {
+        let feedback = self
+            .timed(
+                "update_feedback_status",
+                query_as!(
+                    Feedback,
+                    "
+                    UPDATE feedback SET status = $2, updated_at = NOW()
+                    WHERE id = $1
+                    RETURNING *
+                    ",
+                    id,
+                    status.as_str(),
+                )
+                .fetch_optional(self.db()),
+            )
+            .await?;
+
+        self.invalidate_cached(&[CachedQuery::Feedback]).await;
+        Ok(feedback)
+    }
+
+    /// Count an API call made by a user against the limits of their plan. This will return whether
+    /// the user is within their limits. API calls that would exceed the hard limit are not counted.
+    pub async fn record_api_call(&self, user_id: Uuid) -> Result<QuotaStatus> {
+        let records = self.find_cached_billing_records(user_id).await?;
+        let plan = user_plan(&records.subscription, &records.trial)?;
+        let limits = UsageLimits::for_plan(self.config(), plan);
+
+        // Start a new billing period if the user hasn't made any API calls in the current one.
+        let period_end = usage_period_end(&records.subscription);
+        let mut redis = self.redis();
+        redis::cmd("ZADD")
+            .arg(usage::USAGE_PERIODS_KEY)
+            .arg("NX")
+            .arg(period_end.timestamp())
+            .arg(user_id.to_string())
+            .query_async::<_, ()>(&mut redis)
+            .await?;
+
+        let key = usage::api_calls_key(user_id);
+        let api_calls = redis.incr::<&str, u32, u32>(&key, 1).await?;
+        let status = match limits {
+            Some(limits) => limits.status(api_calls),
+            None => QuotaStatus::Within,
+        };
+        if status == QuotaStatus::HardLimitExceeded {
+            redis.decr::<&str, u32, ()>(&key, 1).await?;
+            log::warn!(
+                "API call limit reached for user: {} (request {})",
+                user_id,
+                self.request_id()
+            );
+        }
+
+        Ok(status)
+    }
+
+    /// Find a user's metered usage in the current billing period.
+    pub async fn find_usage(&self, user_id: Uuid) -> Result<Usage> {
+        let subscription = self.find_subscription(user_id).await?;
+        let plan = user_plan(&subscription, &self.find_trial(user_id).await?)?;
+
+        let mut redis = self.redis();
+        let api_calls = redis
+            .get::<String, Option<u32>>(usage::api_calls_key(user_id))
+            .await?;
+        let period_end = redis
+            .zscore::<&str, String, Option<i64>>(usage::USAGE_PERIODS_KEY, user_id.to_string())
+            .await?;
+
+        Ok(Usage {
+            plan,
+            api_calls: api_calls.unwrap_or_default(),
+            limits: UsageLimits::for_plan(self.config(), plan),
+            period_end: match period_end {
+                Some(period_end) => Utc.timestamp(period_end, 0),
+                None => usage_period_end(&subscription),
+            },
+        })
+    }
+
+    /// Find a user by ID. This will return none if the user is not found or has been soft deleted.
+    pub async fn find_user(&self, id: Uuid) -> Result<Option<User>> {
+        self.timed(
+            "find_user",
+            query_as!(
+                User,
+                "SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL",
+                id
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find a user by ID, even if they have been soft deleted. This will return none if the user is
+    /// not found.
+    pub async fn find_user_including_deleted(&self, id: Uuid) -> Result<Option<User>> {
+        self.timed(
+            "find_user_including_deleted",
+            query_as!(User, "SELECT * FROM users WHERE id = $1", id).fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find what a user looked like at a moment in the past, from the versions of the user kept in
+    /// the "user_history" table. Password hashes aren't kept, so the user's is left empty. This
+    /// will return none if the user didn't exist at that moment, or had been permanently deleted.
+    pub async fn find_user_at_time(&self, id: Uuid, at: DateTime<Utc>) -> Result<Option<User>> {
+        // Versions are rebuilt from JSON, so their columns can't be checked at compile time.
+        self.timed(
+            "find_user_at_time",
+            query_as::<_, User>(
+                r#"SELECT users.* FROM user_history
+                    CROSS JOIN LATERAL jsonb_populate_record(
+                        NULL::users,
+                        user_history.data || '{"password_hash": ""}'
+                    ) AS users
+                    WHERE user_history.user_id = $1 AND user_history.valid_from <= $2
+                        AND (user_history.valid_to IS NULL OR user_history.valid_to > $2)
+                    ORDER BY user_history.id DESC
+                    LIMIT 1"#,
+            )
+            .bind(id)
+            .bind(at)
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find a user by their username. This will return none if no user has the specified username
+    /// or the user has been soft deleted.
+    pub async fn find_user_by_username(&self, username: &str) -> Result<Option<User>> {
+        self.timed(
+            "find_user_by_username",
+            query_as!(
+                User,
+                "SELECT * FROM users WHERE username = $1 AND deleted_at IS NULL",
+                username
+            )
+            .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find a user by their username, even if they have been soft deleted. Usernames of soft
+    /// deleted users stay taken, so this should be used to check if a username is available. This
+    /// will return none if no user has the specified username.
+    pub async fn find_user_by_username_including_deleted(
+        &self,
+        username: &str,
+    ) -> Result<Option<User>> {
+        self.timed(
+            "find_user_by_username_including_deleted",
+            query_as!(User, "SELECT * FROM users WHERE username = $1", username)
+                .fetch_optional(self.db()),
+        )
+        .await
+    }
+
+    /// Find every user with one of the specified IDs. Users that aren't found or have been soft
+    /// deleted are left out, and users are returned in no particular order.
+    pub async fn find_users_by_ids(&self, ids: &[Uuid]) -> Result<Vec<User>> {
+        self.count_rows(
+            self.timed(
+                "find_users_by_ids",
+                query_as!(
+                    User,
+                    "SELECT * FROM users WHERE id = ANY($1) AND deleted_at IS NULL",
+                    ids
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Find every user with one of the specified usernames. Users that aren't found or have been
+    /// soft deleted are left out, and users are returned in no particular order.
+    pub async fn find_users_by_usernames(&self, usernames: &[String]) -> Result<Vec<User>> {
+        self.count_rows(
+            self.timed(
+                "find_users_by_usernames",
+                query_as!(
+                    User,
+                    "SELECT * FROM users WHERE username = ANY($1) AND deleted_at IS NULL",
+                    usernames
+                )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Find every user with the specified email address, leaving out soft deleted users. Email
+    /// addresses aren't unique, so this may return more than one user.
+    pub async fn find_users_by_email(&self, email: &str) -> Result<Vec<User>> {
+        self.count_rows(
+            self.timed(
+                "find_users_by_email",
+                query_as!(
+                User,
+                "SELECT * FROM users WHERE email = $1 AND deleted_at IS NULL ORDER BY created_at",
+                email
+            )
+                .fetch_all(self.db()),
+            )
+            .await?,
+        )
+    }
+
+    /// Find the queries run against the database that are most expensive by an ordering, as
+    /// tracked by the "pg_stat_statements" extension. Query texts are redacted. This will return
+    /// none if the extension isn't installed and loaded.
+    pub async fn find_top_queries(
+        &self,
+        order: QueryStatisticsOrder,
+        limit: i64,
+    ) -> Result<Option<Vec<QueryStatistics>>> {
+        let variables = json!({ "order": order.column(), "limit": limit });
+        self.cached(
+            CachedQuery::TopQueries,
+            None,
+            variables,
+            self.load_top_queries(order, limit),
+        )
+        .await
+    }
+
+    /// Read the most expensive queries by an ordering from "pg_stat_statements", or none if the
+    /// extension can't be queried.
+    async fn load_top_queries(
+        &self,
+        order: QueryStatisticsOrder,
+        limit: i64,
+    ) -> Result<Option<Vec<QueryStatistics>>> {
+        let is_installed = self
+            .timed(
+                "check_pg_stat_statements",
+                query_scalar::<_, bool>(
+                    "
+                    SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'pg_stat_statements')
+                    ",
+                )
+                .fetch_one(self.db()),
+            )
+            .await?;
+        if !is_installed {
+            return Ok(None);
+        }
+
+        // The timing columns were renamed in Postgres 13.
+        let version = self
+            .timed(
+                "find_server_version",
+                query_scalar::<_, String>("SHOW server_version_num").fetch_one(self.db()),
+            )
+            .await?
+            .parse::<u32>()?;
+        let (total_time, mean_time) = if version >= 130000 {
+            ("total_exec_time", "mean_exec_time")
+        } else {
+            ("total_time", "mean_time")
+        };
+
+        let sql = format!(
+            "
+            SELECT query, calls, {} AS total_time, {} AS mean_time, rows
+            FROM pg_stat_statements
+            WHERE dbid = (SELECT oid FROM pg_database WHERE datname = current_database())
+            AND query <> '<insufficient privilege>'
+            ORDER BY {} DESC
+            LIMIT $1
+            ",
+            total_time,
+            mean_time,
+            order.column()
+        );
+        let rows = match self
+            .timed(
+                "load_top_queries",
+                query_as::<_, (String, i64, f64, f64, i64)>(&sql)
+                    .bind(limit)
+                    .fetch_all(self.db()),
+            )
+            .await
+        {
+            Ok(rows) => rows,
+            // The extension can be installed without its library being loaded, in which case it
+            // can't be queried.
+            Err(error)
+                if matches!(
+                    error.downcast_ref::<sqlx::Error>(),
+                    Some(sqlx::Error::Database(error))
+                        if error.code().as_deref()
+                            == Some(query_stats::OBJECT_NOT_IN_PREREQUISITE_STATE_CODE)
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(error) => return Err(error),
+        };
+
+        Ok(Some(
+            rows.into_iter()
+                .map(
+                    |(text, calls, total_time_ms, mean_time_ms, rows)| QueryStatistics {
+                        query: query_stats::redact_query(&text),
+                        calls,
+                        total_time_ms,
+                        mean_time_ms,
+                        rows,
+                    },
+                )
+                .collect(),
+        ))
+    }
+
+    /// Count a use of a deprecated field by the client making the request, identified by its user
+    /// agent. Only a limited number of clients are tracked per field, and uses by any others are
+    /// counted together.
+    pub async fn record_deprecated_use(&self, deprecation: &Deprecation) -> Result<()> {
+        self.state
+            .metrics
+            .observe_deprecated_field_use(deprecation.field);
+
+        let uses_key = deprecations::uses_key(deprecation.field);
+        let mut client = self.user_agent().unwrap_or(deprecations::UNKNOWN_CLIENT);
+        let mut redis = self.redis();
+        if !redis.hexists::<&str, &str, bool>(&uses_key, client).await?
+            && redis.hlen::<&str, usize>(&uses_key).await? >= deprecations::MAX_TRACKED_CLIENTS
+        {
+            client = deprecations::OTHER_CLIENT;
+        }
+
+        redis
+            .hincr::<&str, &str, i64, ()>(&uses_key, client, 1)
+            .await?;
+        redis
+            .hset::<String, &str, i64, ()>(
+                deprecations::last_used_key(deprecation.field),
+                client,
+                Utc::now().timestamp(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Find how every deprecated field is being used by clients.
+    pub async fn find_deprecated_field_usage(&self) -> Result<Vec<DeprecatedFieldUsage>> {
+        let mut redis = self.redis();
+        let mut usage = Vec::new();
+        for deprecation in deprecations::DEPRECATIONS {
+            let uses = redis
+                .hgetall::<String, HashMap<String, i64>>(deprecations::uses_key(deprecation.field))
+                .await?;
+            let last_used = redis
+                .hgetall::<String, HashMap<String, i64>>(deprecations::last_used_key(
+                    deprecation.field,
+                ))
+                .await?;
+
+            let mut clients = uses
+                .into_iter()
+                .map(|(client, uses)| DeprecatedFieldClient {
+                    last_used_at: last_used
+                        .get(&client)
+                        .map(|timestamp| Utc.timestamp(*timestamp, 0)),
+                    client,
+                    uses,
+                })
+                .collect::<Vec<_>>();
+            clients.sort_by_key(|client| Reverse(client.last_used_at));
+            usage.push(DeprecatedFieldUsage {
+                deprecation,
+                clients,
+            });
+        }
+
+        Ok(usage)
+    }
+
+    /// Stream every user matching an export filter, oldest first, leaving out soft deleted users.
+    /// Users are read from the database as the stream is polled rather than all at once, and the
+    /// query is timed until the stream is dropped.
+    pub fn stream_users<'a>(&'a self, filter: &UserExportFilter) -> BoxStream<'a, Result<User>> {
+        let users = query_as!(
+            User,
+            "SELECT * FROM users
+            WHERE deleted_at IS NULL
+            AND ($1::timestamptz IS NULL OR created_at >= $1)
+            AND ($2::timestamptz IS NULL OR created_at < $2)
+            AND ($3::boolean IS NULL OR (email_verified_at IS NOT NULL) = $3)
+            ORDER BY created_at, id",
+            filter.created_after,
+            filter.created_before,
+            filter.email_verified
+        )
+        .fetch(self.db())
+        .map_err(Error::from)
+        .boxed();
+        self.timed_stream("stream_users", users)
+    }
+
+    /// Search for users matching a filter, sorted by each of the specified fields in turn and then
+    /// by ID, so results are in a stable order. Up to the specified number of users are returned,
+    /// after skipping the specified number.
+    pub async fn search_users(
+        &self,
+        filter: &UserFilter,
+        sort: &[UserSort],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<User>> {
+        // Only the ordering changes between searches, and it is built from column names fixed by
+        // the sort fields. Every value searched for is bound as a parameter.
+        let mut order = sort
+            .iter()
+            .map(|sort| {
+                let direction = sort.direction.unwrap_or(SortDirection::Asc).keyword();
+                format!("{} {} NULLS LAST", sort.field.column(), direction)
+            })
+            .collect::<Vec<_>>();
+        order.push("id ASC".into());
+        let sql = format!(
+            r"SELECT * FROM users
+            WHERE ($1::TEXT IS NULL OR lower(username) LIKE $1 ESCAPE '\')
+                AND ($2::TEXT IS NULL OR lower(email) LIKE $2 ESCAPE '\')
+                AND ($3::BOOLEAN IS NULL OR (email_verified_at IS NOT NULL) = $3)
+                AND ($4::TIMESTAMPTZ IS NULL OR created_at >= $4)
+                AND ($5::TIMESTAMPTZ IS NULL OR created_at < $5)
+                AND ($6 OR deleted_at IS NULL)
+            ORDER BY {}
+            LIMIT $7 OFFSET $8",
+            order.join(", ")
+        );
+
+        let username_pattern = filter
+            .username_prefix
+            .as_ref()
+            .map(|prefix| format!("{}%", user_search::escape_like(&prefix.to_lowercase())));
+        let email_pattern = filter
+            .email_domain
+            .as_ref()
+            .map(|domain| format!("%@{}", user_search::escape_like(&domain.to_lowercase())));
+        let users = query_as::<_, User>(&sql)
+            .bind(username_pattern)
+            .bind(email_pattern)
+            .bind(filter.email_verified)
+            .bind(filter.created_after)
+            .bind(filter.created_before)
+            .bind(filter.include_deleted.unwrap_or(false))
+            .bind(limit)
+            .bind(offset);
+        self.count_rows(
+            self.timed("search_users", users.fetch_all(self.db()))
+                .await?,
+        )
+    }
+
+    /// Search for users with a word in their username or email address starting with every term,
+    /// most relevant first. Username matches are more relevant than email address matches, and
+    /// users that are as relevant are ordered by ID. Soft deleted users are left out.
+    pub async fn search_users_full_text(
+        &self,
+        terms: &[String],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<UserSearchHit>> {
+        let users = self
+            .timed(
+                "search_users_full_text",
+                query_as!(
+                    User,
+                    "SELECT users.* FROM users
+                    JOIN user_search_documents ON user_search_documents.user_id = users.id
+                    WHERE user_search_documents.document @@ to_tsquery('simple', $1)
+                        AND users.deleted_at IS NULL
+                    ORDER BY ts_rank(user_search_documents.document, to_tsquery('simple', $1)) DESC,
+                        users.id
+                    LIMIT $2 OFFSET $3",
+                    user_search::ts_query(terms),
+                    limit,
+                    offset,
+                )
+                .fetch_all(self.db()),
+            )
+            .await?;
+
+        Ok(self
+            .count_rows(users)?
+            .into_iter()
+            .map(|user| {
+                let snippet = user_search::snippet(&user, terms);
+                UserSearchHit { user, snippet }
+            })
+            .collect())
+    }
+
+    /// Find a page of users using keyset pagination. Rather than skipping over the users before
+    /// the page, this seeks directly to the page's cursor using an index on the sorted column.
+    /// Soft deleted users are left out unless they are included. First pages are also found with
+    /// the user search query by the user pages canary, if it is enabled.
+    pub async fn find_users(
+        &self,
+        request: &PageRequest,
+        include_deleted: bool,
+    ) -> Result<UserConnection> {
+        let page = self.find_users_page(request, include_deleted);
+        if request.cursor.is_some() || request.is_backward {
+            return page.await;
+        }
+
+        let filter = UserFilter {
+            include_deleted: Some(include_deleted),
+            ..UserFilter::default()
+        };
+        let sort = [UserSort {
+            field: match request.order {
+                UserOrder::CreatedAtAsc | UserOrder::CreatedAtDesc => UserSortField::CreatedAt,
+                UserOrder::UsernameAsc | UserOrder::UsernameDesc => UserSortField::Username,
+            },
+            direction: Some(if request.order.is_ascending() {
+                SortDirection::Asc
+            } else {
+                SortDirection::Desc
+            }),
+        }];
+        let size = request.size as i64;
+        self.canary(
+            Canary::UserPages,
+            page,
+            |page| {
+                page.edges
+                    .iter()
+                    .map(|edge| edge.node.id)
+                    .collect::<Vec<_>>()
+            },
+            move |executor| async move {
+                let users = executor.search_users(&filter, &sort, size, 0).await?;
+                Ok(users.into_iter().map(|user| user.id).collect())
+            },
+        )
+        .await
+    }
+
+    /// Find a page of users using keyset pagination.
+    async fn find_users_page(
+        &self,
+        request: &PageRequest,
+        include_deleted: bool,
+    ) -> Result<UserConnection> {
+        // Paginating backward flips the ordering. The page is flipped back once it is built.
+        let is_ascending = request.order.is_ascending() != request.is_backward;
+        let (comparison, direction) = if is_ascending {
+            (">", "ASC")
+        } else {
+            ("<", "DESC")
+        };
+
+        let column = request.order.column();
+        let mut conditions = Vec::new();
+        if request.cursor.is_some() {
+            conditions.push(format!("({}, id) {} ($1, $2)", column, comparison));
+        }
+        if !include_deleted {
+            conditions.push("deleted_at IS NULL".into());
+        }
+        let condition = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        // Fetch one extra user to find out if there are more users past the end of the page.
+        let sql = format!(
+            "SELECT * FROM users {} ORDER BY {} {}, id {} LIMIT {}",
+            condition,
+            column,
+            direction,
+            direction,
+            request.size + 1
+        );
+
+        let mut users = query_as::<_, User>(&sql);
+        if let Some(Cursor { value, id }) = &request.cursor {
+            users = match value {
+                SortValue::Timestamp(timestamp) => users.bind(*timestamp),
+                SortValue::Text(text) => users.bind(text.clone()),
+            }
+            .bind(*id);
+        }
+        let users = self.count_rows(self.timed("find_users", users.fetch_all(self.db())).await?)?;
+
+        let total_count = self
+            .cached(
+                CachedQuery::UserCount,
+                None,
+                json!({ "includeDeleted": include_deleted }),
+                async {
+                    Ok(self
+                        .timed(
+                            "count_users",
+                            query!(
+                                r#"
+                                SELECT COUNT(*) AS "count!" FROM users
+                                WHERE $1 OR deleted_at IS NULL
+                                "#,
+                                include_deleted
+                            )
+                            .fetch_one(self.db()),
+                        )
+                        .await?
+                        .count)
+                },
+            )
+            .await?;
+
+        Ok(UserConnection::new(request, users, total_count))
+    }
+}
+
+/// Describe the change of a user's roles made by granting them a role, from their roles afterwards.
+fn roles_granted_change(roles: &[Role], granted: Role) -> AuditChange {
+    let before = roles
+        .iter()
+        .copied()
+        .filter(|role| *role != granted)
+        .collect::<Vec<_>>();
+    roles_change(&before, roles)
+}
+
+/// Describe the change of a user's roles made by revoking a role from them, from their roles
+/// afterwards.
+fn roles_revoked_change(roles: &[Role], revoked: Role) -> AuditChange {
+    let mut before = roles.to_vec();
+    before.push(revoked);
+    before.sort_by_key(|role| role.as_str());
+    roles_change(&before, roles)
+}
+
+/// Describe a change of a user's roles, listing the roles before and after by name.
+fn roles_change(before: &[Role], after: &[Role]) -> AuditChange {
+    let names = |roles: &[Role]| {
+        roles
+            .iter()
+            .map(|role| role.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    AuditChange::new("roles", Some(names(before)), Some(names(after)))
+}
+
+/// Describe a change of when a user was deactivated, with none meaning the user was active.
+fn deactivated_at_change(
+    before: Option<DateTime<Utc>>,
+    after: Option<DateTime<Utc>>,
+) -> AuditChange {
+    let format = |time: Option<DateTime<Utc>>| time.map(|time| time.to_rfc3339());
+    AuditChange::new("deactivatedAt", format(before), format(after))
+}
+
+/// Get the plan a user is entitled to by their subscription and trial. Users without an active
+/// subscription are on the plan of their trial while it lasts and are restricted once it ends.
+/// Users that were never given a trial are on the free plan.
+fn user_plan(subscription: &Option<Subscription>, trial: &Option<Trial>) -> Result<Plan> {
+    match (subscription, trial) {
+        (Some(subscription), _) if billing::is_active_status(&subscription.status) => {
+            subscription.plan.parse()
+        }
+        (_, Some(trial)) if trial.is_active() => trial.plan.parse(),
+        (_, Some(_)) => Ok(Plan::Restricted),
+        (_, None) => Ok(Plan::Free),
+    }
+}
+
+/// Get when a billing period starting now ends. Usage follows the billing period of an active
+/// subscription, and otherwise resets after a fixed period.
+fn usage_period_end(subscription: &Option<Subscription>) -> DateTime<Utc> {
+    let now = Utc::now();
+    match subscription {
+        Some(subscription)
+            if billing::is_active_status(&subscription.status)
+                && subscription.current_period_end > now =>
+        {
+            subscription.current_period_end
+        }
+        _ => now + Duration::seconds(usage::DEFAULT_PERIOD_SECONDS),
+    }
+}
+
+/// Rows streamed from a database query, recording how long the query took once they are dropped.
+struct TimedStream<'a, T> {
+    rows: BoxStream<'a, Result<T>>,
+    name: &'static str,
+    start: Instant,
+    state: &'a State,
+}
+
+impl<T> Stream for TimedStream<'_, T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rows.poll_next_unpin(cx)
+    }
+}
+
+impl<T> Drop for TimedStream<'_, T> {
+    fn drop(&mut self) {
+        let duration = self.start.elapsed();
+        db::log_query(&self.state.config, self.name, duration);
+        self.state.metrics.observe_db_query(self.name, duration);
+    }
+}
+
+/// A lockout a login attempt is subject to, for either its username or its client IP address.
+struct LoginLockout {
+    failures_key: String,
+    lock_key: String,
+    threshold: u32,
+}
+
+impl LoginLockout {
+    /// Find when the latest of the provided login lockouts ends. This will return none if none of
+    /// them are locked.
+    async fn find_lock(
+        store: &dyn Cache,
+        lockouts: &[LoginLockout],
+    ) -> Result<Option<DateTime<Utc>>> {
+        let mut locked_until = None;
+        for lockout in lockouts {
+            if let Some(timestamp) = store.get(&lockout.lock_key).await? {
+                locked_until = locked_until.max(Some(Utc.timestamp(timestamp.parse()?, 0)));
+            }
+        }
+
+        Ok(locked_until)
+    }
+
+    /// Count a failed login attempt against the provided lockouts, locking any that reach their
+    /// threshold for a number of seconds. Failures are forgotten once a lockout is locked, so it
+    /// takes as many failures again to lock it once it expires. This will return when login is
+    /// locked until if any lockout was locked.
+    async fn record_failure(
+        store: &dyn Cache,
+        lockouts: &[LoginLockout],
+        lockout_seconds: u32,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let locked_until = Utc::now() + Duration::seconds(lockout_seconds.into());
+        let mut locked = false;
+        for lockout in lockouts {
+            let failures = store.incr(&lockout.failures_key, lockout_seconds).await?;
+            if failures >= lockout.threshold.into() {
+                store
+                    .set(
+                        &lockout.lock_key,
+                        &locked_until.timestamp().to_string(),
+                        lockout_seconds,
+                    )
+                    .await?;
+                store.del(&lockout.failures_key).await?;
+                locked = true;
+            }
+        }
+
+        Ok(if locked { Some(locked_until) } else { None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::MemoryCache;
+
+    use super::*;
+
+    fn lockout(subject: &str, threshold: u32) -> LoginLockout {
+        LoginLockout {
+            failures_key: format!("login-failures/{}", subject),
+            lock_key: format!("login-lock/{}", subject),
+            threshold,
+        }
+    }
+
+    #[test]
+    fn logins_are_locked_once_failures_reach_the_threshold() {
+        task::block_on(async {
+            let store = MemoryCache::new();
+            let lockouts = [lockout("username", 3)];
+            for _ in 0..2 {
+                let locked = LoginLockout::record_failure(&store, &lockouts, 60).await;
+                assert_eq!(locked.unwrap(), None);
+            }
+            assert_eq!(
+                LoginLockout::find_lock(&store, &lockouts).await.unwrap(),
+                None
+            );
+
+            let locked = LoginLockout::record_failure(&store, &lockouts, 60).await;
+            let locked_until = locked.unwrap().unwrap();
+            let found = LoginLockout::find_lock(&store, &lockouts).await.unwrap();
+            let found = found.map(|found| found.timestamp());
+            assert_eq!(found, Some(locked_until.timestamp()));
+            assert!(locked_until > Utc::now() + Duration::seconds(50));
+            // Failures are forgotten once locked, so counting starts over when the lock expires.
+            assert_eq!(store.get("login-failures/username").await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn lockouts_are_counted_separately() {
+        task::block_on(async {
+            let store = MemoryCache::new();
+            let username = [lockout("username", 3)];
+            let both = [lockout("username", 3), lockout("ip", 2)];
+            LoginLockout::record_failure(&store, &username, 60)
+                .await
+                .unwrap();
+            let locked = LoginLockout::record_failure(&store, &both, 60).await;
+            assert_eq!(locked.unwrap(), None);
+
+            // The IP address reaches its threshold first, which locks the attempt.
+            let locked = LoginLockout::record_failure(&store, &both, 60).await;
+            assert!(locked.unwrap().is_some());
+            let found = LoginLockout::find_lock(&store, &username).await.unwrap();
+            assert!(found.is_some());
+            let found = LoginLockout::find_lock(&store, &[lockout("ip", 2)]).await;
+            assert!(found.unwrap().is_some());
+            let found = LoginLockout::find_lock(&store, &[lockout("other", 2)]).await;
+            assert_eq!(found.unwrap(), None);
+        });
     }
 
-    /// Find users. As of now this just returns a list of all users. It should really be paginated
-    /// and have parameters.
-    pub async fn find_users(&self) -> Result<Vec<User>> {
-        Ok(query_as!(User, "SELECT * FROM users ORDER BY created_at")
-            .fetch_all(self.db())
-            .await?)
+    #[test]
+    fn the_latest_lock_is_found() {
+        task::block_on(async {
+            let store = MemoryCache::new();
+            let lockouts = [lockout("username", 1), lockout("ip", 1)];
+            store.set("login-lock/username", "100", 60).await.unwrap();
+            store.set("login-lock/ip", "200", 60).await.unwrap();
+            let found = LoginLockout::find_lock(&store, &lockouts).await.unwrap();
+            assert_eq!(found, Some(Utc.timestamp(200, 0)));
+        });
     }
 }
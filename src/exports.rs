@@ -0,0 +1,323 @@
+use std::io::Error as IoError;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use async_std::channel;
+use async_std::io::BufReader;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::{StreamExt, TryStreamExt};
+use serde::Deserialize;
+use tide::http::headers::{AUTHORIZATION, COOKIE, USER_AGENT};
+use tide::http::Method;
+use tide::{log, Body, Request, Response, StatusCode};
+
+use crate::concurrency::{LimitedResolver, RESOLVER_BUSY_ERROR_MESSAGE};
+use crate::context::Context;
+use crate::deadlines::{Deadline, REQUEST_DEADLINE_HEADER};
+use crate::executor::Executor;
+use crate::graphql::{client_ip, request_id, user_agent, REQUEST_ID_HEADER};
+use crate::models::User;
+use crate::roles::{Access, Role};
+use crate::routes::{RouteModule, Router};
+use crate::session_cookies::RequestCredentials;
+use crate::state::State;
+
+/// Columns exported when a request doesn't select any.
+const DEFAULT_COLUMNS: &[ExportColumn] = &[
+    ExportColumn::Id,
+    ExportColumn::CreatedAt,
+    ExportColumn::Username,
+];
+/// Number of rows buffered between the database and the client. Reading from the database pauses
+/// once this many rows are waiting to be sent, so slow clients don't cause exports to be buffered
+/// in memory.
+const EXPORT_BUFFER_ROWS: usize = 64;
+/// Characters that make spreadsheet applications treat a cell as a formula.
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+/// A column that can be included in an export of users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportColumn {
+    Id,
+    CreatedAt,
+    UpdatedAt,
+    Username,
+    Email,
+    EmailVerifiedAt,
+}
+
+impl ExportColumn {
+    /// The name of the column, used to select it and as its header.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExportColumn::Id => "id",
+            ExportColumn::CreatedAt => "created_at",
+            ExportColumn::UpdatedAt => "updated_at",
+            ExportColumn::Username => "username",
+            ExportColumn::Email => "email",
+            ExportColumn::EmailVerifiedAt => "email_verified_at",
+        }
+    }
+
+    /// Specifies if the column holds personally identifiable information. These columns are only
+    /// exported if the request explicitly opts into them.
+    pub fn is_pii(self) -> bool {
+        matches!(self, ExportColumn::Email)
+    }
+
+    /// Get the value of the column for a user.
+    fn value(self, user: &User) -> String {
+        let timestamp =
+            |timestamp: &DateTime<Utc>| timestamp.to_rfc3339_opts(SecondsFormat::Secs, true);
+        match self {
+            ExportColumn::Id => user.id.to_string(),
+            ExportColumn::CreatedAt => timestamp(&user.created_at),
+            ExportColumn::UpdatedAt => timestamp(&user.updated_at),
+            ExportColumn::Username => user.username.clone(),
+            ExportColumn::Email => user.email.clone(),
+            ExportColumn::EmailVerifiedAt => user
+                .email_verified_at
+                .as_ref()
+                .map(timestamp)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl FromStr for ExportColumn {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "id" => Ok(ExportColumn::Id),
+            "created_at" => Ok(ExportColumn::CreatedAt),
+            "updated_at" => Ok(ExportColumn::UpdatedAt),
+            "username" => Ok(ExportColumn::Username),
+            "email" => Ok(ExportColumn::Email),
+            "email_verified_at" => Ok(ExportColumn::EmailVerifiedAt),
+            _ => Err(anyhow!("Unknown export column: {}", value)),
+        }
+    }
+}
+
+/// Filter deciding which users are included in an export. Every condition that is set must match.
+#[derive(Debug, Clone, Default)]
+pub struct UserExportFilter {
+    /// Only include users created at or after this time.
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only include users created before this time.
+    pub created_before: Option<DateTime<Utc>>,
+    /// Only include users that have, or haven't, verified their email address.
+    pub email_verified: Option<bool>,
+}
+
+/// Encode a value as a CSV field. Fields are quoted if they contain separators or quotes, and
+/// values a spreadsheet would evaluate as a formula are prefixed with a quote so they are shown as
+/// text instead.
+pub fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(FORMULA_PREFIXES) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Encode a row of CSV fields, including the trailing line break.
+fn csv_row(fields: impl IntoIterator<Item = impl AsRef<str>>) -> String {
+    let mut row = fields
+        .into_iter()
+        .map(|field| csv_field(field.as_ref()))
+        .collect::<Vec<_>>()
+        .join(",");
+    row.push_str("\r\n");
+    row
+}
+
+/// Route module exposing exports of data for back-office reporting. Exports are only available to
+/// admins.
+pub struct ExportModule;
+
+impl RouteModule for ExportModule {
+    fn name(&self) -> &'static str {
+        "exports"
+    }
+
+    fn mount(&self, router: &mut dyn Router) {
+        router.route(
+            Method::Get,
+            "/admin/exports/users.csv",
+            Arc::new(export_users),
+        );
+    }
+}
+
+/// Query parameters of a user export.
+#[derive(Deserialize)]
+struct ExportQuery {
+    columns: Option<String>,
+    pii: Option<bool>,
+    created_after: Option<String>,
+    created_before: Option<String>,
+    email_verified: Option<bool>,
+}
+
+/// Create an error for an export request with invalid parameters.
+fn bad_request(message: impl Into<String>) -> tide::Error {
+    tide::Error::from_str(StatusCode::BadRequest, message.into())
+}
+
+/// Parse a timestamp parameter of an export request.
+fn parse_timestamp(name: &str, value: Option<&str>) -> tide::Result<Option<DateTime<Utc>>> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .map_err(|_| bad_request(format!("\"{}\" must be an RFC 3339 timestamp.", name)))
+        })
+        .transpose()
+}
+
+/// Export the users matching a filter as CSV. Rows are streamed to the client in a chunked
+/// response as they are read from the database, so exports of any size use a constant amount of
+/// memory. Columns holding personally identifiable information are only exported if the "pii"
+/// parameter is set, and every such export is logged.
+async fn export_users(request: Request<State>) -> tide::Result {
+    let query: ExportQuery = request
+        .query()
+        .map_err(|_| bad_request("Invalid export parameters."))?;
+    let columns = match &query.columns {
+        Some(columns) => columns
+            .split(',')
+            .map(|column| column.trim().parse::<ExportColumn>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| bad_request(error.to_string()))?,
+        None => DEFAULT_COLUMNS.to_vec(),
+    };
+    let includes_pii = columns.iter().any(|column| column.is_pii());
+    if includes_pii && query.pii != Some(true) {
+        return Err(bad_request(
+            "Exporting personally identifiable information requires the \"pii\" parameter.",
+        ));
+    }
+
+    let filter = UserExportFilter {
+        created_after: parse_timestamp("created_after", query.created_after.as_deref())?,
+        created_before: parse_timestamp("created_before", query.created_before.as_deref())?,
+        email_verified: query.email_verified,
+    };
+
+    let state = request.state().clone();
+    let request_id = request_id(
+        request
+            .header(REQUEST_ID_HEADER)
+            .map(|values| values.as_str()),
+    );
+    let client_ip = client_ip(&request);
+    let user_agent = user_agent(request.header(USER_AGENT).map(|values| values.as_str()));
+    // Exports only read data, so there is nothing for a forged request to change.
+    let session_token = RequestCredentials::new(
+        &state.config.session_cookie_policy,
+        request.header(AUTHORIZATION).map(|values| values.as_str()),
+        request.header(COOKIE).map(|values| values.as_str()),
+        None,
+    )
+    .session_token;
+    let deadline = Deadline::for_request(
+        request
+            .header(REQUEST_DEADLINE_HEADER)
+            .map(|values| values.as_str()),
+        state.config.request_timeout_seconds,
+    );
+    let context = Context::new(
+        state.clone(),
+        request_id.clone(),
+        client_ip.clone(),
+        user_agent.clone(),
+        session_token,
+        deadline,
+    )
+    .await;
+    match context.access(Role::Admin).await? {
+        Access::Granted => {}
+        Access::Unauthenticated => {
+            return Err(tide::Error::from_str(
+                StatusCode::Unauthorized,
+                "You must be logged in to do this.",
+            ))
+        }
+        Access::Forbidden => {
+            return Err(tide::Error::from_str(
+                StatusCode::Forbidden,
+                "You do not have permission to do this.",
+            ))
+        }
+    }
+
+    // The permit is held by the task reading rows, so the slot stays taken until the export has
+    // been sent or the client disconnects.
+    let permit = match state.limiter.acquire(LimitedResolver::Exports).await {
+        Some(permit) => permit,
+        None => {
+            return Err(tide::Error::from_str(
+                StatusCode::TooManyRequests,
+                RESOLVER_BUSY_ERROR_MESSAGE,
+            ))
+        }
+    };
+
+    if includes_pii {
+        if let Some(viewer) = context.viewer() {
+            log::warn!(
+                "User {} exported personally identifiable information: {:?}",
+                viewer.user_id,
+                filter
+            );
+        }
+    }
+
+    // Rows are read in a background task and sent to the response body through a bounded channel.
+    // If the client disconnects, the channel closes and the task stops reading.
+    let (sender, receiver) = channel::bounded::<Result<String, IoError>>(EXPORT_BUFFER_ROWS);
+    let header = csv_row(columns.iter().map(|column| column.as_str()));
+    async_std::task::spawn(async move {
+        let _permit = permit;
+        let executor = Executor::new(state, request_id, client_ip, user_agent);
+        let mut users = executor.stream_users(&filter);
+        if sender.send(Ok(header)).await.is_err() {
+            return;
+        }
+
+        while let Some(user) = users.next().await {
+            let row = match user {
+                Ok(user) => Ok(csv_row(columns.iter().map(|column| column.value(&user)))),
+                Err(error) => {
+                    // The response has already started, so the only way to signal the failure is
+                    // to abort it before it is complete.
+                    log::error!("Failed to export users: {}", error);
+                    Err(IoError::other("Failed to export users."))
+                }
+            };
+
+            let is_error = row.is_err();
+            if sender.send(row).await.is_err() || is_error {
+                return;
+            }
+        }
+    });
+
+    let body = Body::from_reader(BufReader::new(receiver.into_async_read()), None);
+    let response = Response::builder(StatusCode::Ok)
+        .header("Cache-Control", "private, no-store")
+        .header("Content-Type", "text/csv; charset=utf-8")
+        .header("Content-Disposition", "attachment; filename=\"users.csv\"")
+        .body(body);
+
+    Ok(response.build())
+}
@@ -0,0 +1,139 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use juniper::{GraphQLEnum, GraphQLInputObject, GraphQLObject};
+
+/// Maximum length of a feedback message.
+pub const MAX_MESSAGE_LENGTH: usize = 5000;
+/// Maximum number of metadata entries attached to feedback.
+pub const MAX_METADATA_ENTRIES: usize = 20;
+/// Maximum length of a metadata key.
+pub const MAX_METADATA_KEY_LENGTH: usize = 64;
+/// Maximum length of a metadata value.
+pub const MAX_METADATA_VALUE_LENGTH: usize = 1000;
+/// Default number of feedback submissions returned when listing feedback.
+pub const DEFAULT_PAGE_SIZE: i32 = 50;
+/// Maximum number of feedback submissions returned when listing feedback.
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+/// What feedback is about.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "What feedback is about.")]
+pub enum FeedbackCategory {
+    #[graphql(description = "Something isn't working as expected.")]
+    Bug,
+    #[graphql(description = "A suggestion for something new.")]
+    FeatureRequest,
+    #[graphql(description = "A question about how something works.")]
+    Question,
+    #[graphql(description = "Anything else.")]
+    Other,
+}
+
+impl FeedbackCategory {
+    /// The name the category is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FeedbackCategory::Bug => "bug",
+            FeedbackCategory::FeatureRequest => "feature-request",
+            FeedbackCategory::Question => "question",
+            FeedbackCategory::Other => "other",
+        }
+    }
+}
+
+impl Display for FeedbackCategory {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for FeedbackCategory {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "bug" => Ok(FeedbackCategory::Bug),
+            "feature-request" => Ok(FeedbackCategory::FeatureRequest),
+            "question" => Ok(FeedbackCategory::Question),
+            "other" => Ok(FeedbackCategory::Other),
+            _ => Err(anyhow!("Unknown feedback category: {}", value)),
+        }
+    }
+}
+
+/// Where feedback is in triage.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "Where feedback is in triage.")]
+pub enum FeedbackStatus {
+    #[graphql(description = "The feedback hasn't been looked at yet.")]
+    New,
+    #[graphql(description = "The feedback has been looked at and is being acted on.")]
+    Triaged,
+    #[graphql(description = "The feedback has been acted on.")]
+    Resolved,
+    #[graphql(description = "The feedback won't be acted on.")]
+    Dismissed,
+}
+
+impl FeedbackStatus {
+    /// The name the status is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FeedbackStatus::New => "new",
+            FeedbackStatus::Triaged => "triaged",
+            FeedbackStatus::Resolved => "resolved",
+            FeedbackStatus::Dismissed => "dismissed",
+        }
+    }
+}
+
+impl Display for FeedbackStatus {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for FeedbackStatus {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "new" => Ok(FeedbackStatus::New),
+            "triaged" => Ok(FeedbackStatus::Triaged),
+            "resolved" => Ok(FeedbackStatus::Resolved),
+            "dismissed" => Ok(FeedbackStatus::Dismissed),
+            _ => Err(anyhow!("Unknown feedback status: {}", value)),
+        }
+    }
+}
+
+/// A piece of context attached to feedback by the client, such as the app version or the page the
+/// user was on.
+#[derive(GraphQLInputObject, Debug, Clone)]
+#[graphql(
+    description = "A piece of context attached to feedback, such as the app version or the page the
+    user was on."
+)]
+pub struct FeedbackMetadataInput {
+    #[graphql(description = "The name of the entry.")]
+    pub key: String,
+    #[graphql(description = "The value of the entry.")]
+    pub value: String,
+}
+
+/// A piece of context attached to feedback.
+#[derive(GraphQLObject, Debug, Clone)]
+#[graphql(description = "A piece of context attached to feedback.")]
+pub struct FeedbackMetadataEntry {
+    #[graphql(description = "The name of the entry.")]
+    pub key: String,
+    #[graphql(description = "The value of the entry.")]
+    pub value: String,
+}
+
+/// Get the cache key counting the feedback submitted by a user or IP address in the current hour.
+pub fn submissions_key(kind: &str, subject: &str, hour: &str) -> String {
+    format!("feedback/submitted/{}/{}/{}", kind, subject, hour)
+}
@@ -0,0 +1,1581 @@
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Instant;
+
+use anyhow::{anyhow, Error};
+#[cfg(feature = "async-graphql")]
+use async_graphql::Value as ConstValue;
+use async_std::channel::{self, Sender};
+use async_std::io::BufReader;
+use async_std::task;
+use futures::stream;
+use futures::{future, Stream, StreamExt, TryStreamExt};
+#[cfg(not(feature = "async-graphql"))]
+use juniper::http::GraphQLResponse;
+#[cfg(not(feature = "async-graphql"))]
+use juniper::InputValue;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tide::http::headers::{AUTHORIZATION, CONTENT_TYPE, COOKIE, HOST, IF_NONE_MATCH, USER_AGENT};
+use tide::http::{mime, Method};
+use tide::{log, Body, Request, Response, StatusCode};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::context::Context;
+use crate::deadlines::{
+    Deadline, DEADLINE_EXCEEDED_ERROR_CODE, DEADLINE_EXCEEDED_ERROR_MESSAGE,
+    REQUEST_DEADLINE_HEADER,
+};
+use crate::errors::ApiError;
+use crate::logging;
+use crate::middleware::{
+    accept_encoding, error_body, CompressionPolicy, ContentEncoding, ACCEPT_ENCODING_HEADER,
+    CONTENT_ENCODING_HEADER,
+};
+use crate::persisted_queries;
+use crate::proxies::{self, FORWARDED_FOR_HEADER};
+use crate::query_limits::{check_query, check_read_only, QueryBudget, QueryLimits};
+use crate::routes::{RouteModule, Router};
+#[cfg(not(feature = "async-graphql"))]
+use crate::schema::Schema;
+use crate::session_cookies::{
+    RequestCredentials, CSRF_HEADER, INVALID_CSRF_TOKEN_ERROR_CODE,
+    INVALID_CSRF_TOKEN_ERROR_MESSAGE,
+};
+use crate::state::State;
+use crate::uploads::{is_multipart, parse_multipart, Uploads, UPLOAD_TOO_LARGE_ERROR_MESSAGE};
+use crate::usage::{
+    QuotaStatus, QUOTA_EXCEEDED_ERROR_CODE, QUOTA_EXCEEDED_ERROR_MESSAGE, QUOTA_WARNING_HEADER,
+};
+
+/// Header used to propagate a request ID from clients and proxies, and to return it to clients.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// Maximum length of a request ID accepted from a client.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+/// Maximum length of a user agent that is kept. Longer user agents are truncated.
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// Message returned in place of GraphQL responses larger than the configured limit.
+pub const RESPONSE_TOO_LARGE_ERROR_MESSAGE: &str =
+    "The response is too large. Select fewer fields or paginate lists to fetch fewer items at once.";
+/// Error code returned in place of GraphQL responses larger than the configured limit.
+pub const RESPONSE_TOO_LARGE_ERROR_CODE: &str = "response-too-large";
+
+/// Responses are buffered while they are serialized until they reach this many bytes. Larger
+/// responses are then streamed to the client in chunks as the rest of them is serialized, rather
+/// than being held in memory in full before being sent.
+const STREAMED_RESPONSE_MIN_BYTES: usize = 256 * 1024;
+/// Size of the chunks streamed responses are sent in.
+const STREAMED_CHUNK_BYTES: usize = 64 * 1024;
+/// Number of chunks buffered between serializing or compressing a streamed response and sending it.
+/// Serializing pauses once this many chunks are waiting to be sent, so slow clients don't cause
+/// responses to be buffered in memory.
+const STREAMED_BUFFER_CHUNKS: usize = 4;
+
+/// Header controlling how responses to GET requests are cached.
+const CACHE_CONTROL_HEADER: &str = "Cache-Control";
+/// Header identifying the version of a cacheable response, so clients can revalidate it.
+pub const ETAG_HEADER: &str = "ETag";
+/// Header listing the request headers a cacheable response depends on.
+const VARY_HEADER: &str = "Vary";
+
+/// Message returned when a batch holds more GraphQL requests than the configured limit.
+pub const BATCH_TOO_LARGE_ERROR_MESSAGE: &str =
+    "The batch holds too many requests. Send fewer requests at once.";
+/// Error code returned when a batch holds more GraphQL requests than the configured limit.
+pub const BATCH_TOO_LARGE_ERROR_CODE: &str = "batch-too-large";
+/// Message returned when a batch holds no GraphQL requests.
+pub const EMPTY_BATCH_ERROR_MESSAGE: &str = "The batch holds no requests.";
+/// Error code returned when a batch holds no GraphQL requests.
+pub const EMPTY_BATCH_ERROR_CODE: &str = "empty-batch";
+
+/// Get the ID of a request from the value of its request ID header. A new ID is generated if the
+/// header is missing or its value is unsuitable for use in logs and email headers.
+pub fn request_id(header: Option<&str>) -> String {
+    match header {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.chars().all(|character| character.is_ascii_graphic()) =>
+        {
+            id.into()
+        }
+        _ => Uuid::new_v4().to_string(),
+    }
+}
+
+/// Get the session token from the value of a request's authorization header. This will return
+/// none if the header is missing or isn't a bearer token. The token itself is not verified.
+pub fn bearer_token(header: Option<&str>) -> Option<String> {
+    header?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().into())
+        .filter(|token: &String| !token.is_empty())
+}
+
+/// Get the IP address of the client that sent a request. Addresses in the "X-Forwarded-For" header
+/// are only used if the request came through a trusted proxy, as anyone else can set them to
+/// whatever they like. This will return none if the address the request came from is unknown or
+/// isn't an IP address.
+pub fn client_ip(request: &Request<State>) -> Option<String> {
+    let peer = proxies::parse_address(request.peer_addr()?)?;
+    let forwarded_for = request.header(FORWARDED_FOR_HEADER).map(|values| {
+        values
+            .iter()
+            .map(|value| value.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+    let trusted_proxies = &request.state().config.trusted_proxies;
+    Some(proxies::forwarded_client(peer, forwarded_for.as_deref(), trusted_proxies).to_string())
+}
+
+/// Get the user agent of a client from the value of its user agent header. This will return none if
+/// the header is missing or empty.
+pub fn user_agent(header: Option<&str>) -> Option<String> {
+    let user_agent = header?.trim();
+    if user_agent.is_empty() {
+        return None;
+    }
+
+    Some(user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect())
+}
+
+/// Route module exposing the GraphQL API.
+pub struct GraphQLModule;
+
+impl RouteModule for GraphQLModule {
+    fn name(&self) -> &'static str {
+        "graphql"
+    }
+
+    fn mount(&self, router: &mut dyn Router) {
+        router.route(Method::Get, "/graphql", Arc::new(graphql_get));
+        router.route(Method::Post, "/graphql", Arc::new(graphql));
+        router.route(Method::Get, "/graphql/schema", Arc::new(graphql_schema));
+    }
+}
+
+/// The operations a GraphQL request is allowed to run, decided by the HTTP method it was sent
+/// with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllowedOperations {
+    /// Any operation can run, as in POST requests.
+    Any,
+    /// Only queries can run, as GET requests shouldn't change anything and may be cached.
+    QueriesOnly,
+    /// Only queries can run, as the request was authenticated with a session cookie but didn't
+    /// send a matching CSRF token, so it may have been forged by another site.
+    QueriesWithoutCsrfToken,
+}
+
+/// A GraphQL request sent in the query string of a GET request. Variables and extensions are sent
+/// as JSON.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetRequest {
+    pub query: Option<String>,
+    pub operation_name: Option<String>,
+    pub variables: Option<String>,
+    pub extensions: Option<String>,
+}
+
+impl GetRequest {
+    /// Build the raw GraphQL request body the request would be sent as in a POST request. This
+    /// will return an error if the variables or extensions are not valid JSON.
+    pub fn body(&self) -> serde_json::Result<Vec<u8>> {
+        let json = |value: &Option<String>| match value.as_deref() {
+            Some(value) if !value.is_empty() => serde_json::from_str(value),
+            _ => Ok(Value::Null),
+        };
+        let body = serde_json::json!({
+            "query": self.query,
+            "operationName": self.operation_name,
+            "variables": json(&self.variables)?,
+            "extensions": json(&self.extensions)?,
+        });
+
+        serde_json::to_vec(&body)
+    }
+}
+
+/// The schema requests are executed against, in the GraphQL schema language. This is served so
+/// tools and gateways can fetch the schema without sending introspection queries.
+#[derive(Debug, Clone)]
+pub struct SchemaDocument {
+    /// The schema in the GraphQL schema language.
+    pub sdl: String,
+    /// A strong entity tag identifying the schema, which changes whenever the schema does.
+    pub etag: String,
+}
+
+impl SchemaDocument {
+    /// Describe a schema from its definition in the GraphQL schema language.
+    pub fn new(sdl: String) -> Self {
+        let etag = format!("\"{:x}\"", Sha256::digest(sdl.as_bytes()));
+        SchemaDocument { sdl, etag }
+    }
+
+    /// Get the headers letting clients and shared caches cache the schema for the configured
+    /// number of seconds, then revalidate it with its ETag. The schema only changes when a new
+    /// version of the server is deployed, so revalidating is usually answered with a
+    /// "304 Not Modified" response.
+    pub fn cache_headers(&self, config: &Config) -> Vec<(&'static str, String)> {
+        let cache_control = match config.schema_cache_max_age_seconds {
+            0 => "public, no-cache".to_string(),
+            max_age_seconds => format!("public, max-age={}", max_age_seconds),
+        };
+        vec![
+            (CACHE_CONTROL_HEADER, cache_control),
+            (ETAG_HEADER, self.etag.clone()),
+        ]
+    }
+}
+
+/// Whether a GraphQL request failed, kept once its response is serialized to decide the HTTP status
+/// of the response and if it can be cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Outcome {
+    /// Specifies if any errors occurred while validating or executing the request.
+    has_errors: bool,
+    /// Specifies if any data was resolved for the request.
+    has_data: bool,
+}
+
+impl Outcome {
+    /// The outcome of a request answered with an error instead of being executed.
+    const REJECTED: Outcome = Outcome {
+        has_errors: true,
+        has_data: false,
+    };
+}
+
+/// The chunks of a response streamed to the client as it is serialized.
+pub type Chunks = Pin<Box<dyn Stream<Item = io::Result<Vec<u8>>> + Send + Sync>>;
+
+/// The serialized JSON body of a GraphQL response.
+pub enum ResponseBody {
+    /// A response serialized in full before being sent, along with its ETag, hashed from its bytes
+    /// as they were serialized.
+    Buffered { bytes: Vec<u8>, etag: String },
+    /// A response large enough to be sent in chunks as it is serialized. Its size is only known
+    /// once it has been sent, so it has no ETag. The stream fails if serializing the response does,
+    /// such as when it grows larger than the limit, so the response is cut off rather than sent
+    /// incomplete.
+    Streamed(Chunks),
+}
+
+impl ResponseBody {
+    /// Build a response body from bytes serialized in full.
+    fn buffered(bytes: Vec<u8>) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(&bytes);
+        ResponseBody::Buffered {
+            bytes,
+            etag: etag(hasher),
+        }
+    }
+
+    /// Join the bodies of the responses to a batch of requests into an array. The array is only
+    /// streamed if any of the responses in it are, in which case the responses are sent one after
+    /// another as they are serialized.
+    fn array(bodies: Vec<ResponseBody>) -> Self {
+        let is_buffered = |body: &ResponseBody| matches!(body, ResponseBody::Buffered { .. });
+        if bodies.iter().all(is_buffered) {
+            let mut hasher = Sha256::new();
+            let mut array = Vec::new();
+            let mut append = |bytes: &[u8]| {
+                hasher.update(bytes);
+                array.extend_from_slice(bytes);
+            };
+            append(b"[");
+            for (index, body) in bodies.into_iter().enumerate() {
+                if index > 0 {
+                    append(b",");
+                }
+                if let ResponseBody::Buffered { bytes, .. } = body {
+                    append(&bytes);
+                }
+            }
+            append(b"]");
+
+            return ResponseBody::Buffered {
+                bytes: array,
+                etag: etag(hasher),
+            };
+        }
+
+        let elements = bodies.into_iter().enumerate().map(|(index, body)| {
+            let separator = if index == 0 { b"[" } else { b"," };
+            stream::once(future::ready(Ok(separator.to_vec()))).chain(body.into_stream())
+        });
+        let end = stream::once(future::ready(Ok(b"]".to_vec())));
+        ResponseBody::Streamed(Box::pin(stream::iter(elements).flatten().chain(end)))
+    }
+
+    /// Get the chunks of the body, which is a single chunk for buffered bodies.
+    fn into_stream(self) -> Chunks {
+        match self {
+            ResponseBody::Buffered { bytes, .. } => {
+                Box::pin(stream::once(future::ready(Ok(bytes))))
+            }
+            ResponseBody::Streamed(chunks) => chunks,
+        }
+    }
+}
+
+/// The outcome of executing a GraphQL request.
+pub struct Execution {
+    /// The JSON response to send back to the client, serialized once the request was executed.
+    /// Batches hold an array of the response to each request in them.
+    pub body: ResponseBody,
+    /// The outcome of each request executed, in the order they were sent.
+    outcomes: Vec<Outcome>,
+    /// How the usage of the user making the request compares to the limits of their plan.
+    pub quota: QuotaStatus,
+    /// Headers warning the client that the request used deprecated fields, if it did.
+    pub deprecation_headers: Vec<(&'static str, String)>,
+    /// Headers setting or deleting the session cookie, if the request started or ended a session.
+    pub cookie_headers: Vec<(&'static str, String)>,
+}
+
+impl Execution {
+    /// Build the outcome of executing a request from its response, serializing it from a blocking
+    /// task. Responses are buffered until they are large enough to be streamed, and their bytes are
+    /// counted and hashed as they are written, so the size and ETag of buffered responses are known
+    /// without serializing them again. Responses larger than a number of bytes are replaced with a
+    /// "response-too-large" error if they are still buffered, or cut off if they are already being
+    /// streamed, and serializing stops as soon as the limit is reached, so oversized responses
+    /// aren't serialized in full. A limit of zero means there is no limit.
+    async fn new<T>(response: T, outcome: Outcome, max_bytes: usize) -> Self
+    where
+        T: Serialize + Send + 'static,
+    {
+        let (sender, receiver) = channel::bounded(1);
+        task::spawn_blocking(move || {
+            let mut writer = ResponseWriter::new(max_bytes, sender);
+            let result = serde_json::to_writer(&mut writer, &response);
+            writer.finish(result.map_err(io::Error::from));
+        });
+
+        match receiver.recv().await {
+            Ok(Some(body)) => Execution {
+                body,
+                outcomes: vec![outcome],
+                quota: QuotaStatus::Within,
+                deprecation_headers: Vec::new(),
+                cookie_headers: Vec::new(),
+            },
+            _ => Execution::rejected(response_too_large()),
+        }
+    }
+
+    /// Build the outcome of a request that was rejected before being executed.
+    fn rejected(response: Value) -> Self {
+        // Error bodies are small and made of plain JSON values, so they can always be serialized.
+        let bytes = serde_json::to_vec(&response).unwrap_or_default();
+        Execution {
+            body: ResponseBody::buffered(bytes),
+            outcomes: vec![Outcome::REJECTED],
+            quota: QuotaStatus::Within,
+            deprecation_headers: Vec::new(),
+            cookie_headers: Vec::new(),
+        }
+    }
+
+    /// Combine the outcomes of a batch of requests into one, responding with an array holding the
+    /// response to each request in the order they were sent. The quota status is the most severe
+    /// of any request in the batch, and the session cookie is set by the last request changing it.
+    fn batch(executions: Vec<Execution>) -> Self {
+        let quota = executions
+            .iter()
+            .map(|execution| execution.quota)
+            .max()
+            .unwrap_or(QuotaStatus::Within);
+        let mut deprecation_headers = Vec::new();
+        let mut cookie_headers = Vec::new();
+        let mut outcomes = Vec::with_capacity(executions.len());
+        let mut bodies = Vec::with_capacity(executions.len());
+        for execution in executions {
+            for header in execution.deprecation_headers {
+                if !deprecation_headers.contains(&header) {
+                    deprecation_headers.push(header);
+                }
+            }
+            if !execution.cookie_headers.is_empty() {
+                cookie_headers = execution.cookie_headers;
+            }
+            bodies.push(execution.body);
+            outcomes.extend(execution.outcomes);
+        }
+
+        Execution {
+            body: ResponseBody::array(bodies),
+            outcomes,
+            quota,
+            deprecation_headers,
+            cookie_headers,
+        }
+    }
+
+    /// Read the rest of a streamed response, so it can be sent with an ETag. Responses that fail
+    /// to be read in full, such as ones cut off for growing larger than the limit, are replaced
+    /// with a "response-too-large" error.
+    pub async fn buffer(mut self) -> Self {
+        let chunks = match &mut self.body {
+            ResponseBody::Streamed(chunks) => chunks,
+            ResponseBody::Buffered { .. } => return self,
+        };
+
+        let mut hasher = Sha256::new();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = chunks.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    hasher.update(&chunk);
+                    bytes.extend_from_slice(&chunk);
+                }
+                Err(_) => {
+                    let rejected = Execution::rejected(response_too_large());
+                    self.body = rejected.body;
+                    self.outcomes = rejected.outcomes;
+                    return self;
+                }
+            }
+        }
+
+        self.body = ResponseBody::Buffered {
+            bytes,
+            etag: etag(hasher),
+        };
+        self
+    }
+
+    /// Get the ETag of the response, if it has been serialized in full.
+    pub fn etag(&self) -> Option<&str> {
+        match &self.body {
+            ResponseBody::Buffered { etag, .. } => Some(etag),
+            ResponseBody::Streamed(_) => None,
+        }
+    }
+
+    /// Call a function with the size of the response in bytes once it is known. Streamed responses
+    /// are measured as they are sent, and the function is called once they have been sent in full
+    /// or stop being sent.
+    fn on_size(&mut self, observe: impl FnOnce(usize) + Send + Sync + 'static) {
+        let chunks = match &mut self.body {
+            ResponseBody::Buffered { bytes, .. } => return observe(bytes.len()),
+            ResponseBody::Streamed(chunks) => mem::replace(chunks, Box::pin(stream::empty())),
+        };
+
+        self.body = ResponseBody::Streamed(Box::pin(SizedStream {
+            chunks,
+            bytes: 0,
+            observe: Some(Box::new(observe)),
+        }));
+    }
+
+    /// Specifies if any errors occurred while validating or executing the request.
+    pub fn has_errors(&self) -> bool {
+        self.outcomes.iter().any(|outcome| outcome.has_errors)
+    }
+
+    /// Specifies if any data was resolved for the request.
+    pub fn has_data(&self) -> bool {
+        self.outcomes.iter().any(|outcome| outcome.has_data)
+    }
+
+    /// Get the value of the quota warning header to send with the response, if any.
+    pub fn quota_warning(&self) -> Option<&'static str> {
+        match self.quota {
+            QuotaStatus::SoftLimitExceeded => Some("API call soft limit exceeded"),
+            _ => None,
+        }
+    }
+
+    /// Specifies if the response should be sent with a successful HTTP status under a policy. A
+    /// batch is only successful if the response to every request in it is.
+    pub fn is_success(&self, policy: ErrorStatusPolicy) -> bool {
+        self.outcomes.iter().all(|outcome| match policy {
+            ErrorStatusPolicy::Spec => true,
+            ErrorStatusPolicy::Partial => !outcome.has_errors || outcome.has_data,
+            ErrorStatusPolicy::Strict => !outcome.has_errors,
+        })
+    }
+}
+
+/// Get the headers controlling how the response to a GET request is cached. Successful responses
+/// can be cached for a number of seconds, by shared caches such as CDNs if the request was
+/// anonymous, or only by the client if it was sent with a session token, as the response may hold
+/// the user's data. Responses vary by the session cookie too when sessions are delivered as
+/// cookies. Responses with errors aren't cached.
+pub fn cache_headers(
+    execution: &Execution,
+    is_authenticated: bool,
+    config: &Config,
+) -> Vec<(&'static str, String)> {
+    if execution.has_errors() || execution.quota == QuotaStatus::HardLimitExceeded {
+        return vec![(CACHE_CONTROL_HEADER, "no-store".into())];
+    }
+
+    let visibility = if is_authenticated {
+        "private"
+    } else {
+        "public"
+    };
+    let cache_control = match config.get_cache_max_age_seconds {
+        0 => format!("{}, no-cache", visibility),
+        max_age_seconds => format!("{}, max-age={}", visibility, max_age_seconds),
+    };
+    let vary = if config.session_cookie_policy.enabled {
+        "Authorization, Cookie"
+    } else {
+        "Authorization"
+    };
+    let mut headers = vec![
+        (CACHE_CONTROL_HEADER, cache_control),
+        (VARY_HEADER, vary.into()),
+    ];
+    if let Some(etag) = execution.etag() {
+        headers.push((ETAG_HEADER, etag.to_string()));
+    }
+
+    headers
+}
+
+/// Specifies if the client's cached copy of a response is still current, from the value of its
+/// If-None-Match header and the caching headers of the response.
+pub fn is_not_modified(if_none_match: Option<&str>, headers: &[(&'static str, String)]) -> bool {
+    let etag = match headers.iter().find(|(name, _)| *name == ETAG_HEADER) {
+        Some((_, etag)) => etag,
+        None => return false,
+    };
+
+    if_none_match.is_some_and(|if_none_match| {
+        if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag.as_str())
+    })
+}
+
+/// Policy deciding the HTTP status of GraphQL responses that contain errors. Requests that cannot
+/// be parsed are always rejected with a bad request status.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorStatusPolicy {
+    /// Always respond with a successful status and report errors in the response body, as the
+    /// GraphQL spec recommends.
+    #[default]
+    Spec,
+    /// Respond with a successful status if any data was resolved, or a bad request status if the
+    /// request failed entirely.
+    Partial,
+    /// Respond with a bad request status if any error occurred.
+    Strict,
+}
+
+impl FromStr for ErrorStatusPolicy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "spec" => Ok(ErrorStatusPolicy::Spec),
+            "partial" => Ok(ErrorStatusPolicy::Partial),
+            "strict" => Ok(ErrorStatusPolicy::Strict),
+            _ => Err(anyhow!("Unknown error status policy: {}", value)),
+        }
+    }
+}
+
+/// Count a request against the usage limits of the user making it. Requests that aren't made by
+/// a logged in user aren't metered. If usage can't be counted, the request is allowed.
+async fn check_quota(context: &Context) -> QuotaStatus {
+    let viewer = match context.viewer() {
+        Some(viewer) => viewer,
+        None => return QuotaStatus::Within,
+    };
+
+    context
+        .executor()
+        .record_api_call(viewer.user_id)
+        .await
+        .unwrap_or_else(|error| {
+            log::error!("Failed to record API call: {}", error);
+            QuotaStatus::Within
+        })
+}
+
+/// Build the error response sent in place of a response larger than the configured limit.
+fn response_too_large() -> Value {
+    error_body(
+        RESPONSE_TOO_LARGE_ERROR_MESSAGE,
+        RESPONSE_TOO_LARGE_ERROR_CODE,
+    )
+}
+
+/// Format the ETag of a response from the hash of its bytes.
+fn etag(hasher: Sha256) -> String {
+    format!("\"{:x}\"", hasher.finalize())
+}
+
+/// Writer serializing a response, counting its bytes and hashing them as they are written. Bytes
+/// are buffered until there are enough of them to stream the response, and are then sent through
+/// a bounded channel in chunks as they are written. The body is handed over once it is known if
+/// the response is streamed, which is when the response is finished for buffered responses.
+/// Writing fails once more than a limit of bytes are written, or once the client stops reading a
+/// streamed response.
+struct ResponseWriter {
+    buffer: Vec<u8>,
+    hasher: Sha256,
+    written: usize,
+    max_bytes: usize,
+    /// Where the body is handed over, taken once it has been.
+    body: Option<Sender<Option<ResponseBody>>>,
+    /// The channel a streamed response is written to, once it is being streamed.
+    stream: Option<BufWriter<ChannelWriter>>,
+}
+
+impl ResponseWriter {
+    /// Create a writer that hands the body over through a channel, and fails once more than a
+    /// number of bytes are written. A limit of zero means there is no limit.
+    fn new(max_bytes: usize, body: Sender<Option<ResponseBody>>) -> Self {
+        ResponseWriter {
+            buffer: Vec::new(),
+            hasher: Sha256::new(),
+            written: 0,
+            max_bytes: if max_bytes == 0 {
+                usize::MAX
+            } else {
+                max_bytes
+            },
+            body: Some(body),
+            stream: None,
+        }
+    }
+
+    /// Hand over the body of the response, or none if it is too large to be sent.
+    fn hand_over(&mut self, body: Option<ResponseBody>) {
+        if let Some(sender) = self.body.take() {
+            // The request may have been abandoned, in which case nobody is waiting for the body.
+            let _ = task::block_on(sender.send(body));
+        }
+    }
+
+    /// Start streaming the response, handing over a stream of its chunks and sending what has
+    /// been buffered so far as the first of them.
+    fn start_streaming(&mut self) -> io::Result<()> {
+        let (sender, receiver) = channel::bounded(STREAMED_BUFFER_CHUNKS);
+        self.hand_over(Some(ResponseBody::Streamed(Box::pin(receiver))));
+        let mut stream = BufWriter::with_capacity(STREAMED_CHUNK_BYTES, ChannelWriter(sender));
+        stream.write_all(&mem::take(&mut self.buffer))?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Finish the response once serializing it has succeeded or failed. Buffered responses are
+    /// handed over in full, or as too large if serializing them failed. Streamed responses that
+    /// failed are aborted by sending the error in place of their remaining chunks.
+    fn finish(mut self, result: io::Result<()>) {
+        let result = match &mut self.stream {
+            Some(stream) => result.and_then(|()| stream.flush()),
+            None => result,
+        };
+        let error = match (result, self.stream.take()) {
+            (Ok(()), None) => {
+                let bytes = mem::take(&mut self.buffer);
+                let hasher = mem::take(&mut self.hasher);
+                self.hand_over(Some(ResponseBody::Buffered {
+                    bytes,
+                    etag: etag(hasher),
+                }));
+                return;
+            }
+            (Ok(()), Some(_)) => return,
+            (Err(error), None) => {
+                self.hand_over(None);
+                error
+            }
+            (Err(error), Some(stream)) => {
+                // Whatever is left in the buffer is dropped rather than sent after the error.
+                let (ChannelWriter(sender), _) = stream.into_parts();
+                if error.kind() == io::ErrorKind::BrokenPipe {
+                    return;
+                }
+                let _ = task::block_on(sender.send(Err(io::Error::new(error.kind(), "Aborted"))));
+                error
+            }
+        };
+
+        if self.written > self.max_bytes {
+            log::warn!(
+                "Rejected GraphQL response larger than {} bytes.",
+                self.max_bytes
+            );
+        } else {
+            log::error!("Failed to serialize a GraphQL response: {}", error);
+        }
+    }
+}
+
+impl Write for ResponseWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.written = self.written.saturating_add(bytes.len());
+        if self.written > self.max_bytes {
+            return Err(io::Error::other("Size limit exceeded"));
+        }
+
+        match &mut self.stream {
+            Some(stream) => stream.write_all(bytes)?,
+            None => {
+                self.hasher.update(bytes);
+                self.buffer.extend_from_slice(bytes);
+                if self.buffer.len() >= STREAMED_RESPONSE_MIN_BYTES {
+                    self.start_streaming()?;
+                }
+            }
+        }
+
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writer sending everything written to it through a channel, as it is written. Writing fails once
+/// the channel is closed, such as when the client disconnects.
+struct ChannelWriter(Sender<io::Result<Vec<u8>>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        task::block_on(self.0.send(Ok(buffer.to_vec())))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The chunks of a streamed response, counting their bytes as they are sent. A function is called
+/// with the number of bytes sent once the stream is dropped, which is once the response has been
+/// sent or stopped being sent.
+struct SizedStream {
+    chunks: Chunks,
+    bytes: usize,
+    observe: Option<Box<dyn FnOnce(usize) + Send + Sync>>,
+}
+
+impl Stream for SizedStream {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let chunk = self.chunks.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &chunk {
+            self.bytes += chunk.len();
+        }
+        chunk
+    }
+}
+
+impl Drop for SizedStream {
+    fn drop(&mut self) {
+        if let Some(observe) = self.observe.take() {
+            observe(self.bytes);
+        }
+    }
+}
+
+/// Compress the chunks of a streamed response with an encoding from a blocking task, sending the
+/// compressed chunks through a bounded channel as they are produced. This lets large responses be
+/// compressed without holding them in memory. If the channel closes because the client
+/// disconnected, compressing stops, and if the response fails, the error is passed on to abort the
+/// compressed response before it is complete.
+pub fn compress_stream(mut chunks: Chunks, encoding: ContentEncoding) -> Chunks {
+    let (sender, receiver) = channel::bounded(STREAMED_BUFFER_CHUNKS);
+    let errors = sender.clone();
+    task::spawn_blocking(move || {
+        let writer = BufWriter::with_capacity(STREAMED_CHUNK_BYTES, ChannelWriter(sender));
+        let result = encoding
+            .compress_into(writer, |writer| {
+                while let Some(chunk) = task::block_on(chunks.next()) {
+                    writer.write_all(&chunk?)?;
+                }
+                Ok(())
+            })
+            .and_then(|mut writer| writer.flush());
+        if let Err(error) = result {
+            if error.kind() != io::ErrorKind::BrokenPipe {
+                let _ = task::block_on(errors.send(Err(error)));
+            }
+        }
+    });
+
+    Box::pin(receiver)
+}
+
+/// A GraphQL response along with what the sensitive admin actions taken while executing the
+/// request changed, in the "changes" extension of the response, so clients can see exactly what
+/// changed without reading the audit log. The extensions are left out if no actions were taken.
+#[derive(Serialize)]
+struct AuditedResponse<T> {
+    #[serde(flatten)]
+    response: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extensions: Option<Value>,
+}
+
+/// The response to a GraphQL request executed with juniper, owning everything in it so it can be
+/// serialized from a blocking task. Requests that fail to be validated have errors borrowing from
+/// their query, and those responses are small, so they are converted to JSON values instead.
+#[cfg(not(feature = "async-graphql"))]
+#[derive(Serialize)]
+#[serde(untagged)]
+enum JuniperResponse {
+    Executed(GraphQLResponse<'static>),
+    Failed(Value),
+}
+
+/// A GraphQL request executed with juniper. Juniper's own request type only gives back a response
+/// to serialize, so the request is executed from its parts instead, letting the outcome be read
+/// from its result without serializing it.
+#[cfg(not(feature = "async-graphql"))]
+#[derive(Deserialize)]
+struct GraphQLRequest {
+    query: String,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<InputValue>,
+}
+
+#[cfg(not(feature = "async-graphql"))]
+impl GraphQLRequest {
+    /// Get the name of the operation to run, if the request names one.
+    fn operation_name(&self) -> Option<&str> {
+        self.operation_name.as_deref()
+    }
+
+    /// Execute the request with a schema, returning the response along with its outcome.
+    async fn execute(&self, schema: &Schema, context: &Context) -> (JuniperResponse, Outcome) {
+        let variables = self
+            .variables
+            .as_ref()
+            .and_then(InputValue::to_object_value)
+            .map(|variables| {
+                variables
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let result = juniper::execute(
+            &self.query,
+            self.operation_name(),
+            schema,
+            &variables,
+            context,
+        )
+        .await;
+        match result {
+            Ok((data, errors)) => {
+                let outcome = Outcome {
+                    has_errors: !errors.is_empty(),
+                    has_data: !data.is_null(),
+                };
+                let response = GraphQLResponse::from_result(Ok((data, errors)));
+                (JuniperResponse::Executed(response), outcome)
+            }
+            Err(error) => {
+                let response: GraphQLResponse = GraphQLResponse::from_result(Err(error));
+                let response = serde_json::to_value(response).unwrap_or(Value::Null);
+                (JuniperResponse::Failed(response), Outcome::REJECTED)
+            }
+        }
+    }
+}
+
+/// Parse a raw GraphQL request body, resolving its query if it uses a persisted query, and check
+/// the query against limits, taking what it uses from the request's budget. This will return the
+/// error response to send if the persisted query can't be used or the query exceeds a limit, or an
+/// error if the body is not a valid GraphQL request.
+async fn parse_request<T: DeserializeOwned>(
+    state: &State,
+    body: &[u8],
+    limits: &QueryLimits,
+    budget: &mut QueryBudget,
+    allowed: AllowedOperations,
+) -> serde_json::Result<Result<T, Value>> {
+    let mut request: Value = serde_json::from_slice(body)?;
+    let mut redis = state.metrics.instrument(state.redis.clone());
+    let resolved = persisted_queries::resolve_query(
+        &mut redis,
+        &mut request,
+        state.config.persisted_queries_only,
+    )
+    .await
+    .and_then(|()| match request.get("query").and_then(Value::as_str) {
+        Some(query) => check_query(query, limits, budget).and_then(|()| match allowed {
+            AllowedOperations::Any => Ok(()),
+            AllowedOperations::QueriesOnly => {
+                check_read_only(query, request.get("operationName").and_then(Value::as_str))
+            }
+            AllowedOperations::QueriesWithoutCsrfToken => {
+                check_read_only(query, request.get("operationName").and_then(Value::as_str))
+                    .map_err(|_| ApiError::Validation {
+                        message: INVALID_CSRF_TOKEN_ERROR_MESSAGE.into(),
+                        code: INVALID_CSRF_TOKEN_ERROR_CODE,
+                    })
+            }
+        }),
+        None => Ok(()),
+    });
+    match resolved {
+        Ok(()) => serde_json::from_value(request).map(Ok),
+        Err(error) => {
+            error.log();
+            Ok(Err(error_body(error.message(), error.code())))
+        }
+    }
+}
+
+/// Execute a raw GraphQL request body using the configured GraphQL backend, on behalf of the
+/// request with the specified ID, client IP address, user agent and session token, along with the
+/// files uploaded with it. The query is checked against the limits of the caller, sharing a budget
+/// with any other queries sent in the same batch. Requests running operations that aren't allowed,
+/// or made by users that have reached the usage limit of their plan, are rejected without being
+/// executed. Execution is abandoned once the request's deadline passes. This will return an error
+/// if the body is not a valid GraphQL request.
+#[cfg(not(feature = "async-graphql"))]
+#[allow(clippy::too_many_arguments)]
+pub async fn execute(
+    state: &State,
+    request_id: String,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    session_token: Option<String>,
+    host_organization: Option<Uuid>,
+    deadline: Deadline,
+    body: &[u8],
+    uploads: Uploads,
+    budget: &mut QueryBudget,
+    allowed: AllowedOperations,
+) -> serde_json::Result<Execution> {
+    let start = Instant::now();
+    // Initialize a context struct for the request. This context may include configuration,
+    // connections to databases, authentication info, etc..
+    let context = Context::new(
+        state.clone(),
+        request_id,
+        client_ip,
+        user_agent,
+        session_token,
+        deadline,
+    )
+    .await
+    .with_uploads(uploads)
+    .with_host_organization(host_organization);
+    // Attempt to parse the GraphQL query from the request, looking it up if it was persisted, and
+    // check it against the limits for the user making it.
+    let limits = match context.viewer() {
+        Some(_) => &state.config.query_limits,
+        None => &state.config.anonymous_query_limits,
+    };
+    let query: GraphQLRequest = match parse_request(state, body, limits, budget, allowed).await? {
+        Ok(query) => query,
+        Err(response) => return Ok(Execution::rejected(response)),
+    };
+    logging::record_operation(
+        query.operation_name(),
+        context.viewer().map(|viewer| viewer.user_id),
+    );
+    let quota = check_quota(&context).await;
+    // Execute the query using our GraphQL schema, serializing its response straight from the result.
+    let mut execution = if quota == QuotaStatus::HardLimitExceeded {
+        Execution::rejected(error_body(
+            QUOTA_EXCEEDED_ERROR_MESSAGE,
+            QUOTA_EXCEEDED_ERROR_CODE,
+        ))
+    } else {
+        let result = deadline.run(query.execute(&state.schema, &context)).await;
+        let extensions = context.executor().audited_actions().extensions();
+        match result {
+            Ok((response, outcome)) => {
+                let response = AuditedResponse {
+                    response,
+                    extensions,
+                };
+                Execution::new(response, outcome, state.config.max_response_bytes).await
+            }
+            Err(_) => {
+                let response = AuditedResponse {
+                    response: deadline_exceeded(),
+                    extensions,
+                };
+                Execution::new(response, Outcome::REJECTED, 0).await
+            }
+        }
+    };
+    execution.quota = quota;
+    execution.deprecation_headers = context.deprecated_uses().headers();
+    execution.cookie_headers = context
+        .session_cookies()
+        .headers(&state.config, host_organization.is_some());
+    state.metrics.observe_graphql_request(
+        query.operation_name(),
+        execution.has_errors(),
+        start.elapsed(),
+    );
+    let metrics = state.metrics.clone();
+    let operation_name = query.operation_name().map(str::to_string);
+    let rows = context.executor().row_counter().rows();
+    execution
+        .on_size(move |size| metrics.observe_graphql_usage(operation_name.as_deref(), rows, size));
+
+    Ok(execution)
+}
+
+/// Execute a raw GraphQL request body using the configured GraphQL backend, on behalf of the
+/// request with the specified ID, client IP address, user agent and session token, along with the
+/// files uploaded with it. The query is checked against the limits of the caller, sharing a budget
+/// with any other queries sent in the same batch. Requests running operations that aren't allowed,
+/// or made by users that have reached the usage limit of their plan, are rejected without being
+/// executed. Execution is abandoned once the request's deadline passes. This will return an error
+/// if the body is not a valid GraphQL request.
+#[cfg(feature = "async-graphql")]
+#[allow(clippy::too_many_arguments)]
+pub async fn execute(
+    state: &State,
+    request_id: String,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    session_token: Option<String>,
+    host_organization: Option<Uuid>,
+    deadline: Deadline,
+    body: &[u8],
+    uploads: Uploads,
+    budget: &mut QueryBudget,
+    allowed: AllowedOperations,
+) -> serde_json::Result<Execution> {
+    let start = Instant::now();
+    // Initialize a context struct for the request and make it available to resolvers.
+    let context = Context::new(
+        state.clone(),
+        request_id,
+        client_ip,
+        user_agent,
+        session_token,
+        deadline,
+    )
+    .await
+    .with_uploads(uploads)
+    .with_host_organization(host_organization);
+    // Attempt to parse the GraphQL query from the request, looking it up if it was persisted, and
+    // check it against the limits for the user making it.
+    let limits = match context.viewer() {
+        Some(_) => &state.config.query_limits,
+        None => &state.config.anonymous_query_limits,
+    };
+    let query: async_graphql::Request =
+        match parse_request(state, body, limits, budget, allowed).await? {
+            Ok(query) => query,
+            Err(response) => return Ok(Execution::rejected(response)),
+        };
+    let operation_name = query.operation_name.clone();
+    logging::record_operation(
+        operation_name.as_deref(),
+        context.viewer().map(|viewer| viewer.user_id),
+    );
+    let quota = check_quota(&context).await;
+    // The context is handed to the schema, so keep handles to the deprecated fields, session cookie
+    // changes, rows fetched and admin actions it records.
+    let deprecated_uses = context.deprecated_uses().clone();
+    let session_cookies = context.session_cookies().clone();
+    let rows = context.executor().row_counter();
+    let audited_actions = context.executor().audited_actions();
+    // Execute the query using our async-graphql schema, serializing its response straight from the
+    // result.
+    let mut execution = if quota == QuotaStatus::HardLimitExceeded {
+        Execution::rejected(error_body(
+            QUOTA_EXCEEDED_ERROR_MESSAGE,
+            QUOTA_EXCEEDED_ERROR_CODE,
+        ))
+    } else {
+        let result = deadline
+            .run(state.async_schema.execute(query.data(context)))
+            .await;
+        let extensions = audited_actions.extensions();
+        match result {
+            Ok(mut response) => {
+                if let Some(Value::Object(changes)) = extensions {
+                    for (name, value) in changes {
+                        response
+                            .extensions
+                            .insert(name, ConstValue::from_json(value)?);
+                    }
+                }
+                let outcome = Outcome {
+                    has_errors: response.is_err(),
+                    has_data: response.data != ConstValue::Null,
+                };
+                Execution::new(response, outcome, state.config.max_response_bytes).await
+            }
+            Err(_) => {
+                let response = AuditedResponse {
+                    response: deadline_exceeded(),
+                    extensions,
+                };
+                Execution::new(response, Outcome::REJECTED, 0).await
+            }
+        }
+    };
+    execution.quota = quota;
+    execution.deprecation_headers = deprecated_uses.headers();
+    execution.cookie_headers = session_cookies.headers(&state.config, host_organization.is_some());
+    state.metrics.observe_graphql_request(
+        operation_name.as_deref(),
+        execution.has_errors(),
+        start.elapsed(),
+    );
+    let metrics = state.metrics.clone();
+    let rows = rows.rows();
+    execution
+        .on_size(move |size| metrics.observe_graphql_usage(operation_name.as_deref(), rows, size));
+
+    Ok(execution)
+}
+
+/// Execute a raw GraphQL request body holding either a single request or a batch of them, as an
+/// array of requests. Requests in a batch are executed one after another, each with its own
+/// context, and the response is an array holding the response to each request. Every request in a
+/// batch is checked against one budget for the query limits, so a batch can't do more than a
+/// single request could. Batches that are empty or hold more requests than the configured limit
+/// are rejected without being executed. Every request in a batch is only allowed to run the
+/// specified operations. This will return an error if the body or any request in the batch is not
+/// a valid GraphQL request.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_body(
+    state: &State,
+    request_id: String,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    session_token: Option<String>,
+    host_organization: Option<Uuid>,
+    deadline: Deadline,
+    body: &[u8],
+    uploads: Uploads,
+    allowed: AllowedOperations,
+) -> serde_json::Result<Execution> {
+    if !is_batch(body) {
+        return execute(
+            state,
+            request_id,
+            client_ip,
+            user_agent,
+            session_token,
+            host_organization,
+            deadline,
+            body,
+            uploads,
+            &mut QueryBudget::default(),
+            allowed,
+        )
+        .await;
+    }
+
+    let requests: Vec<Value> = serde_json::from_slice(body)?;
+    if requests.is_empty() {
+        return Ok(Execution::rejected(error_body(
+            EMPTY_BATCH_ERROR_MESSAGE,
+            EMPTY_BATCH_ERROR_CODE,
+        )));
+    }
+    if requests.len() > state.config.max_batch_size {
+        log::warn!(
+            "Rejected a batch of {} GraphQL requests, over the limit of {}.",
+            requests.len(),
+            state.config.max_batch_size
+        );
+        return Ok(Execution::rejected(error_body(
+            BATCH_TOO_LARGE_ERROR_MESSAGE,
+            BATCH_TOO_LARGE_ERROR_CODE,
+        )));
+    }
+
+    let mut executions = Vec::with_capacity(requests.len());
+    let mut budget = QueryBudget::default();
+    for request in requests {
+        let execution = execute(
+            state,
+            request_id.clone(),
+            client_ip.clone(),
+            user_agent.clone(),
+            session_token.clone(),
+            host_organization,
+            deadline,
+            &serde_json::to_vec(&request)?,
+            uploads.clone(),
+            &mut budget,
+            allowed,
+        )
+        .await?;
+        executions.push(execution);
+    }
+
+    Ok(Execution::batch(executions))
+}
+
+/// Build the response sent for a GraphQL request whose deadline passed before it was executed.
+fn deadline_exceeded() -> Value {
+    log::warn!("Abandoned a GraphQL request whose deadline passed.");
+    error_body(
+        DEADLINE_EXCEEDED_ERROR_MESSAGE,
+        DEADLINE_EXCEEDED_ERROR_CODE,
+    )
+}
+
+/// Specifies if a raw GraphQL request body holds a batch of requests rather than a single one.
+fn is_batch(body: &[u8]) -> bool {
+    body.iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|byte| *byte == b'[')
+}
+
+/// Find the organization a request was sent to from its "Host" header, if it was sent to one of
+/// an organization's verified custom hostnames.
+fn host_organization(request: &Request<State>) -> Option<Uuid> {
+    request
+        .state()
+        .domains
+        .organization(request.header(HOST).map(|values| values.as_str()))
+}
+
+/// Handle a GraphQL request.
+async fn graphql(mut request: Request<State>) -> tide::Result {
+    let request_id = request_id(
+        request
+            .header(REQUEST_ID_HEADER)
+            .map(|values| values.as_str()),
+    );
+    let client_ip = client_ip(&request);
+    let user_agent = user_agent(request.header(USER_AGENT).map(|values| values.as_str()));
+    let deadline = Deadline::for_request(
+        request
+            .header(REQUEST_DEADLINE_HEADER)
+            .map(|values| values.as_str()),
+        request.state().config.request_timeout_seconds,
+    );
+    let credentials = RequestCredentials::new(
+        &request.state().config.session_cookie_policy,
+        request.header(AUTHORIZATION).map(|values| values.as_str()),
+        request.header(COOKIE).map(|values| values.as_str()),
+        request.header(CSRF_HEADER).map(|values| values.as_str()),
+    );
+    let host_organization = host_organization(&request);
+    let content_type = request
+        .header(CONTENT_TYPE)
+        .map(|values| values.as_str().to_string());
+    let state = request.state().clone();
+    // Multipart requests upload files along with the GraphQL request, so they are size limited.
+    let (body, uploads) = match content_type {
+        Some(content_type) if is_multipart(&content_type) => {
+            let too_large = || {
+                tide::Error::from_str(StatusCode::PayloadTooLarge, UPLOAD_TOO_LARGE_ERROR_MESSAGE)
+            };
+            if request.len().unwrap_or_default() > state.config.upload_max_bytes {
+                return Err(too_large());
+            }
+            let body = request.body_bytes().await?;
+            if body.len() > state.config.upload_max_bytes {
+                return Err(too_large());
+            }
+
+            parse_multipart(&content_type, body)
+                .await
+                .map_err(|error| {
+                    let message = format!("Invalid multipart request: {}", error);
+                    tide::Error::from_str(StatusCode::BadRequest, message)
+                })?
+        }
+        _ => (request.body_bytes().await?, Uploads::new()),
+    };
+    let execution = execute_body(
+        &state,
+        request_id.clone(),
+        client_ip,
+        user_agent,
+        credentials.session_token.clone(),
+        host_organization,
+        deadline,
+        &body,
+        uploads,
+        credentials.allowed_operations(),
+    )
+    .await
+    .map_err(|error| {
+        let message = format!("Invalid GraphQL request: {}", error);
+        tide::Error::from_str(StatusCode::BadRequest, message)
+    })?;
+
+    let accept_encoding = accept_encoding(&request);
+    respond(&state, request_id, execution, accept_encoding.as_deref())
+}
+
+/// Handle a GraphQL query sent in the query string of a GET request. Only queries can be sent this
+/// way, and their responses are sent with headers letting clients and CDNs cache them.
+async fn graphql_get(request: Request<State>) -> tide::Result {
+    let request_id = request_id(
+        request
+            .header(REQUEST_ID_HEADER)
+            .map(|values| values.as_str()),
+    );
+    let client_ip = client_ip(&request);
+    let user_agent = user_agent(request.header(USER_AGENT).map(|values| values.as_str()));
+    let deadline = Deadline::for_request(
+        request
+            .header(REQUEST_DEADLINE_HEADER)
+            .map(|values| values.as_str()),
+        request.state().config.request_timeout_seconds,
+    );
+    // Only queries can be sent this way, so there is nothing for a forged request to change.
+    let session_token = RequestCredentials::new(
+        &request.state().config.session_cookie_policy,
+        request.header(AUTHORIZATION).map(|values| values.as_str()),
+        request.header(COOKIE).map(|values| values.as_str()),
+        None,
+    )
+    .session_token;
+    let host_organization = host_organization(&request);
+    let is_authenticated = session_token.is_some();
+    let state = request.state().clone();
+    let invalid = |error: serde_json::Error| {
+        let message = format!("Invalid GraphQL request: {}", error);
+        tide::Error::from_str(StatusCode::BadRequest, message)
+    };
+    let body = request.query::<GetRequest>()?.body().map_err(invalid)?;
+    let execution = execute(
+        &state,
+        request_id.clone(),
+        client_ip,
+        user_agent,
+        session_token,
+        host_organization,
+        deadline,
+        &body,
+        Uploads::new(),
+        &mut QueryBudget::default(),
+        AllowedOperations::QueriesOnly,
+    )
+    .await
+    .map_err(invalid)?
+    .buffer()
+    .await;
+
+    // The response is buffered, so it has an ETag and the compression middleware compresses it,
+    // making the ETag weak. Headers are appended, as the compression middleware may already have
+    // the response vary on "Accept-Encoding".
+    let headers = cache_headers(&execution, is_authenticated, &state.config);
+    let if_none_match = request.header(IF_NONE_MATCH).map(|values| values.as_str());
+    let mut response = if is_not_modified(if_none_match, &headers) {
+        Response::builder(StatusCode::NotModified)
+            .header(REQUEST_ID_HEADER, request_id)
+            .build()
+    } else {
+        let accept_encoding = accept_encoding(&request);
+        respond(&state, request_id, execution, accept_encoding.as_deref())?
+    };
+    for (name, value) in headers {
+        response.append_header(name, value);
+    }
+
+    Ok(response)
+}
+
+/// Serve the schema in the GraphQL schema language, with headers letting clients and CDNs cache it.
+/// Requests with an "If-None-Match" header matching the schema's ETag get a "304 Not Modified"
+/// response without a body.
+async fn graphql_schema(request: Request<State>) -> tide::Result {
+    let state = request.state();
+    let document = &state.schema_document;
+    let headers = document.cache_headers(&state.config);
+    let if_none_match = request.header(IF_NONE_MATCH).map(|values| values.as_str());
+    let mut response = if is_not_modified(if_none_match, &headers) {
+        Response::builder(StatusCode::NotModified)
+    } else {
+        Response::builder(StatusCode::Ok)
+            .content_type(mime::PLAIN)
+            .body(document.sdl.as_str())
+    };
+    for (name, value) in &headers {
+        response = response.header(*name, value.as_str());
+    }
+
+    Ok(response.build())
+}
+
+/// Build the response to a GraphQL request from the outcome of executing it. Streamed responses are
+/// compressed as they are sent with an encoding from the request's "Accept-Encoding" header if
+/// compression is enabled, as the compression middleware only compresses bodies of a known length.
+fn respond(
+    state: &State,
+    request_id: String,
+    execution: Execution,
+    accept_encoding: Option<&str>,
+) -> tide::Result<Response> {
+    // If we get an error while executing the query, the configured policy decides if a bad request
+    // status is returned.
+    let status = if execution.quota == QuotaStatus::HardLimitExceeded {
+        StatusCode::TooManyRequests
+    } else if execution.is_success(state.config.error_status_policy) {
+        StatusCode::Ok
+    } else {
+        StatusCode::BadRequest
+    };
+
+    let mut response = Response::builder(status)
+        .header(REQUEST_ID_HEADER, request_id)
+        .content_type(mime::JSON);
+    if let Some(warning) = execution.quota_warning() {
+        response = response.header(QUOTA_WARNING_HEADER, warning);
+    }
+    for (name, value) in &execution.deprecation_headers {
+        response = response.header(*name, value.as_str());
+    }
+    match execution.body {
+        ResponseBody::Buffered { bytes, .. } => response = response.body(Body::from_bytes(bytes)),
+        ResponseBody::Streamed(chunks) => {
+            let policy = CompressionPolicy::new(&state.config);
+            let encoding = policy
+                .as_ref()
+                .and_then(|policy| policy.negotiate(accept_encoding));
+            if policy.is_some() {
+                response = response.header(VARY_HEADER, ACCEPT_ENCODING_HEADER);
+            }
+            if let Some(encoding) = encoding {
+                response = response.header(CONTENT_ENCODING_HEADER, encoding.name());
+            }
+            let chunks = match encoding {
+                Some(encoding) => compress_stream(chunks, encoding),
+                None => chunks,
+            };
+            response = response.body(Body::from_reader(
+                BufReader::new(chunks.into_async_read()),
+                None,
+            ));
+        }
+    }
+
+    // Cookies are set with a header each, so they are appended rather than set on the builder.
+    let mut response = response.build();
+    for (name, value) in &execution.cookie_headers {
+        response.append_header(*name, value.as_str());
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A response with a list of strings adding up to about a number of bytes, so it is written in
+    /// many small parts like responses with many fields are.
+    fn response_of(bytes: usize) -> Value {
+        let items = vec!["x".repeat(16); bytes / 16];
+        json!({ "data": { "items": items } })
+    }
+
+    /// Execute nothing, serializing a response as if it was the result of a successful request.
+    fn serialize(response: Value, max_bytes: usize) -> Execution {
+        let outcome = Outcome {
+            has_errors: false,
+            has_data: true,
+        };
+        task::block_on(Execution::new(response, outcome, max_bytes))
+    }
+
+    /// Read every chunk of a response body, or the error that cut it off.
+    fn read(body: ResponseBody) -> io::Result<Vec<u8>> {
+        task::block_on(body.into_stream().try_concat())
+    }
+
+    #[test]
+    fn small_responses_are_buffered_with_an_etag() {
+        let response = response_of(16);
+        let execution = serialize(response.clone(), 0);
+        assert!(execution.etag().is_some());
+        let body = read(execution.body).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), response);
+    }
+
+    #[test]
+    fn large_responses_are_streamed_in_full() {
+        let response = response_of(STREAMED_RESPONSE_MIN_BYTES * 3);
+        let execution = serialize(response.clone(), 0);
+        assert!(matches!(execution.body, ResponseBody::Streamed(_)));
+        assert!(execution.etag().is_none());
+        let body = read(execution.body).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), response);
+    }
+
+    #[test]
+    fn buffered_responses_over_the_limit_are_rejected() {
+        let execution = serialize(response_of(1024), 512);
+        assert_eq!(execution.outcomes, vec![Outcome::REJECTED]);
+        let body = read(execution.body).unwrap();
+        assert!(String::from_utf8(body)
+            .unwrap()
+            .contains(RESPONSE_TOO_LARGE_ERROR_CODE));
+    }
+
+    #[test]
+    fn streamed_responses_over_the_limit_are_cut_off() {
+        let max_bytes = STREAMED_RESPONSE_MIN_BYTES * 2;
+        let execution = serialize(response_of(max_bytes), max_bytes);
+        assert!(read(execution.body).is_err());
+    }
+
+    #[test]
+    fn buffering_a_streamed_response_gives_it_an_etag() {
+        let response = response_of(STREAMED_RESPONSE_MIN_BYTES);
+        let execution = task::block_on(serialize(response.clone(), 0).buffer());
+        let buffered = serialize(response_of(16), 0);
+        assert!(execution.etag().is_some());
+        assert_ne!(execution.etag(), buffered.etag());
+        let body = read(execution.body).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), response);
+    }
+
+    #[test]
+    fn batches_join_buffered_and_streamed_responses_into_an_array() {
+        let small = response_of(16);
+        let large = response_of(STREAMED_RESPONSE_MIN_BYTES);
+        let buffered = ResponseBody::array(vec![
+            serialize(small.clone(), 0).body,
+            serialize(small.clone(), 0).body,
+        ]);
+        assert!(matches!(buffered, ResponseBody::Buffered { .. }));
+        let body = read(buffered).unwrap();
+        let array = serde_json::from_slice::<Value>(&body).unwrap();
+        assert_eq!(array, json!([small, small]));
+
+        let streamed = ResponseBody::array(vec![
+            serialize(small.clone(), 0).body,
+            serialize(large.clone(), 0).body,
+        ]);
+        assert!(matches!(streamed, ResponseBody::Streamed(_)));
+        let body = read(streamed).unwrap();
+        let array = serde_json::from_slice::<Value>(&body).unwrap();
+        assert_eq!(array, json!([small, large]));
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tide::log;
+
+/// Costs below this hash passwords quickly enough for stolen hashes to be cracked cheaply.
+pub const MIN_SAFE_COST: u32 = 10;
+/// Costs above this take long enough per hash that a burst of login attempts can tie up every
+/// CPU, letting anyone slow the server down without valid credentials.
+pub const MAX_SAFE_COST: u32 = 14;
+/// The lowest cost the "bcrypt" crate accepts.
+pub const MIN_BCRYPT_COST: u32 = 4;
+/// The highest cost the "bcrypt" crate accepts.
+pub const MAX_BCRYPT_COST: u32 = 31;
+/// The number of hashes timed at each cost. The median is used, so one slow hash caused by other
+/// work on the host doesn't skew the result.
+const SAMPLES: usize = 3;
+/// Password hashed while benchmarking. Its contents don't affect how long hashing takes.
+const BENCHMARK_PASSWORD: &str = "calibrate-hash-cost";
+
+/// How long hashing a password took at a cost.
+#[derive(Debug, Clone, Copy)]
+pub struct CostTiming {
+    pub cost: u32,
+    pub duration: Duration,
+}
+
+/// The result of calibrating the password hashing cost on the current host.
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    /// Every cost that was timed, from lowest to highest.
+    pub timings: Vec<CostTiming>,
+    /// The highest cost that hashes a password within the target time. This is the lowest cost
+    /// bcrypt accepts if none do.
+    pub recommended_cost: u32,
+}
+
+/// Log a warning if the configured password hashing cost is outside the safe range.
+pub fn check_cost(cost: u32) {
+    if cost < MIN_SAFE_COST {
+        log::warn!(
+            "PASSWORD_HASH_COST is {}, below the safe minimum of {}. Stolen password hashes could \
+            be cracked cheaply. Run \"calibrate-hash-cost\" to find a cost suited to this host.",
+            cost,
+            MIN_SAFE_COST
+        );
+    } else if cost > MAX_SAFE_COST {
+        log::warn!(
+            "PASSWORD_HASH_COST is {}, above the safe maximum of {}. Bursts of login attempts \
+            could use up every CPU. Run \"calibrate-hash-cost\" to find a cost suited to this host.",
+            cost,
+            MAX_SAFE_COST
+        );
+    }
+}
+
+/// Time how long hashing a password takes at a cost, taking the median of a few samples.
+pub fn benchmark(cost: u32) -> Result<Duration> {
+    let mut durations = Vec::with_capacity(SAMPLES);
+    for _ in 0..SAMPLES {
+        let start = Instant::now();
+        bcrypt::hash(BENCHMARK_PASSWORD, cost)?;
+        durations.push(start.elapsed());
+    }
+
+    durations.sort();
+    Ok(durations[SAMPLES / 2])
+}
+
+/// Find the highest cost that hashes a password within a target time on the current host. Each
+/// step up in cost doubles the time taken, so costs are timed from the lowest up until one takes
+/// longer than the target.
+pub fn calibrate(target: Duration) -> Result<Calibration> {
+    let mut timings = Vec::new();
+    let mut recommended_cost = MIN_BCRYPT_COST;
+    for cost in MIN_BCRYPT_COST..=MAX_BCRYPT_COST {
+        let duration = benchmark(cost)?;
+        timings.push(CostTiming { cost, duration });
+        if duration > target {
+            break;
+        }
+        recommended_cost = cost;
+    }
+
+    Ok(Calibration {
+        timings,
+        recommended_cost,
+    })
+}
+
+/// Set the password hashing cost in an environment file, replacing the existing setting if there
+/// is one. The file is created if it doesn't exist.
+pub fn write_cost(path: &Path, cost: u32) -> Result<()> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error.into()),
+    };
+
+    let setting = format!("PASSWORD_HASH_COST={}", cost);
+    let mut replaced = false;
+    let mut lines = contents
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("PASSWORD_HASH_COST=") {
+                replaced = true;
+                setting.clone()
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+    if !replaced {
+        lines.push(setting);
+    }
+
+    std::fs::write(path, lines.join("\n") + "\n")?;
+    Ok(())
+}
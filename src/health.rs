@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use tide::convert::json;
+use tide::http::{mime, Method};
+use tide::{Body, Request, Response, StatusCode};
+
+use crate::routes::{RouteModule, Router};
+use crate::state::State;
+
+/// Route module exposing a health check for load balancers and container orchestrators.
+pub struct HealthModule;
+
+impl RouteModule for HealthModule {
+    fn name(&self) -> &'static str {
+        "health"
+    }
+
+    fn mount(&self, router: &mut dyn Router) {
+        router.route(Method::Get, "/health", Arc::new(health));
+    }
+}
+
+/// Report that the server is up and able to handle requests.
+async fn health(_request: Request<State>) -> tide::Result {
+    let response = Response::builder(StatusCode::Ok)
+        .content_type(mime::JSON)
+        .body(Body::from_json(&json!({ "status": "ok" }))?);
+
+    Ok(response.build())
+}
@@ -0,0 +1,200 @@
+//! A GraphQL API server for creating, querying and authenticating users. The server can be run
+//! on its own via the "rust-graphql-server" binary or embedded in another project using
+//! [`build_app`].
+
+pub mod admin;
+pub mod alerts;
+pub mod analyze;
+pub mod announcements;
+#[cfg(feature = "async-graphql")]
+pub mod async_schema;
+pub mod audit;
+pub mod auth;
+pub mod avatars;
+#[cfg(feature = "axum")]
+pub mod axum_server;
+pub mod billing;
+pub mod budgets;
+pub mod cache;
+pub mod canary;
+pub mod concurrency;
+pub mod config;
+pub mod config_schema;
+pub mod context;
+pub mod db;
+pub mod deadlines;
+pub mod deprecations;
+pub mod domains;
+pub mod email;
+pub mod errors;
+pub mod events;
+pub mod executor;
+pub mod exports;
+pub mod feedback;
+pub mod graphql;
+pub mod hash_cost;
+pub mod health;
+pub mod lifecycle;
+pub mod logging;
+pub mod metrics;
+pub mod middleware;
+pub mod models;
+#[cfg(feature = "oauth")]
+pub mod oauth;
+pub mod operation_validation;
+pub mod organizations;
+pub mod pagination;
+pub mod password_policy;
+pub mod pending_actions;
+pub mod persisted_queries;
+pub mod plugin;
+pub mod policy;
+pub mod promo_codes;
+pub mod proxies;
+pub mod query_limits;
+pub mod query_stats;
+pub mod regions;
+pub mod retention;
+pub mod roles;
+pub mod routes;
+pub mod scanning;
+pub mod schema;
+pub mod security_events;
+pub mod seed;
+pub mod session_cookies;
+pub mod signup_screening;
+pub mod state;
+pub mod storage;
+pub mod trials;
+pub mod uploads;
+pub mod usage;
+pub mod user_search;
+pub mod validation;
+pub mod warmup;
+pub mod webauthn;
+pub mod webhooks;
+
+use anyhow::Result;
+use tide::{log, Server};
+
+use alerts::AlertCounters;
+use config::Config;
+use db::{connect_to_db, connect_to_redis, run_migrations};
+#[cfg(feature = "email")]
+use email::Mailer;
+use lifecycle::Lifecycle;
+use metrics::Metrics;
+#[cfg(feature = "metrics")]
+use metrics::MetricsMiddleware;
+use middleware::{
+    CompressionMiddleware, CompressionPolicy, CorsMiddleware, CorsPolicy, ErrorMiddleware,
+    RequestLogMiddleware,
+};
+use plugin::Plugin;
+use regions::Regions;
+use schema::build_schema;
+use state::State;
+
+/// Connect to the databases, run any pending migrations unless disabled, build the global server
+/// state, warm it up for requests and start its subsystems. Subsystems should be shut down with
+/// the state's lifecycle once the server stops. The GraphQL schema and migrations are composed
+/// with those contributed by the provided plugins. With the "async-graphql" feature, this will
+/// return an error if any plugin contributes GraphQL fields, as that schema can't include them.
+pub async fn build_state(config: Config, plugins: &[Box<dyn Plugin>]) -> Result<State> {
+    #[cfg(feature = "async-graphql")]
+    async_schema::check_plugins(plugins)?;
+    hash_cost::check_cost(config.password_hash_cost);
+    log::info!("Connecting to Postgres database...");
+    let db = connect_to_db(&config).await?;
+    log::info!("Connecting to Redis database...");
+    let redis = connect_to_redis(&config).await?;
+    let metrics = Metrics::new()?;
+    let regions = Regions::connect(&config.region, &metrics).await?;
+    if !config.region.remotes.is_empty() {
+        log::info!(
+            "Replicating sessions from region {} to {} other regions.",
+            config.region.region,
+            config.region.remotes.len()
+        );
+    }
+    let alert_counters = AlertCounters::default();
+    #[cfg(feature = "email")]
+    let mailer = Mailer::new(
+        &config,
+        db.clone(),
+        metrics.instrument(redis.clone()),
+        alert_counters.clone(),
+    )?;
+    let files = storage::file_store(&config)?;
+    let cache = cache::cache(&config, metrics.instrument(redis.clone()));
+    log::info!("Keeping cached results in the {} cache.", cache.name());
+    let mut subsystems = lifecycle::builtin_subsystems();
+    subsystems.extend(plugin::subsystems(plugins));
+    let lifecycle = Lifecycle::new(subsystems)?;
+
+    if config.run_migrations_on_startup {
+        log::info!("Running any pending database migrations...");
+        run_migrations(&db, &plugin::migrations(plugins)).await?;
+    } else {
+        log::info!("Skipping database migrations, as running them on startup is disabled.");
+    }
+
+    let schema = build_schema(plugins);
+    let state = State::new(
+        config,
+        db,
+        redis,
+        #[cfg(feature = "email")]
+        mailer,
+        metrics,
+        files,
+        schema,
+    )
+    .with_alert_counters(alert_counters)
+    .with_regions(regions)
+    .with_cache(cache)
+    .with_lifecycle(lifecycle);
+    log::info!("Warming up before accepting requests...");
+    warmup::warm_up(&state).await?;
+    log::info!("Starting subsystems...");
+    state.lifecycle.start(&state).await?;
+
+    Ok(state)
+}
+
+/// Build the global server state and a tide server with every enabled route module mounted,
+/// including any route modules contributed by the provided plugins. The returned server can be
+/// listened on directly or nested under a path prefix of another tide server.
+pub async fn build_app(config: Config, plugins: Vec<Box<dyn Plugin>>) -> Result<Server<State>> {
+    let mut server = Server::with_state(build_state(config.clone(), &plugins).await?);
+    let mut modules = routes::modules();
+    for plugin in &plugins {
+        log::info!("Loaded plugin: {}", plugin.name());
+        modules.extend(plugin.route_modules());
+    }
+
+    let routes = routes::describe(&modules, &config);
+    // Requests are logged outside the other middleware, so lines they log include the request ID.
+    server.with(RequestLogMiddleware);
+    // Responses are compressed outside the middleware adding headers to them, so their "Vary"
+    // headers are kept.
+    if let Some(policy) = CompressionPolicy::new(&config) {
+        server.with(CompressionMiddleware::new(policy));
+    }
+    // CORS runs before the remaining middleware, so error responses they produce get CORS headers
+    // too.
+    if let Some(policy) = CorsPolicy::new(&config, &server.state().domains) {
+        server.with(CorsMiddleware::new(policy));
+    }
+    #[cfg(feature = "metrics")]
+    server.with(MetricsMiddleware::new(
+        routes.iter().map(|route| route.path),
+    ));
+    server.with(ErrorMiddleware::new(config.request_timeout_seconds));
+    routes::mount(&mut server, &modules, &config);
+    for route in routes {
+        log::info!("Mounted route: {}", route);
+    }
+
+    Ok(server)
+}
@@ -0,0 +1,359 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use async_std::future;
+use async_std::task::{self, JoinHandle};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use tide::log;
+
+use crate::state::State;
+use crate::{alerts, avatars, domains, events, pending_actions, regions, retention, trials, usage};
+
+/// A part of the server that needs to do work when the server starts or shuts down, such as a
+/// background worker that has to be stopped before the connections it uses are closed.
+#[async_trait]
+pub trait Subsystem: Send + Sync {
+    /// A short, unique name for the subsystem, used in logs and by subsystems depending on it.
+    fn name(&self) -> &'static str;
+
+    /// Names of the subsystems that must start before this one, and shut down after it.
+    fn depends_on(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Start the subsystem. The server doesn't start if this returns an error.
+    async fn on_start(&self, _state: &State) -> Result<()> {
+        Ok(())
+    }
+
+    /// Shut the subsystem down, finishing or saving any work in progress.
+    async fn on_shutdown(&self, _state: &State) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs the startup and shutdown hooks of every subsystem. Subsystems start after the subsystems
+/// they depend on and shut down before them, and are otherwise run in the order they were
+/// registered. This is cheap to clone, and clones share the same subsystems.
+#[derive(Clone, Default)]
+pub struct Lifecycle {
+    subsystems: Arc<Vec<Arc<dyn Subsystem>>>,
+}
+
+impl Lifecycle {
+    /// Create a lifecycle running the hooks of a set of subsystems. This will return an error if
+    /// two subsystems have the same name, or if a subsystem depends on one that doesn't exist or
+    /// on itself, directly or through other subsystems.
+    pub fn new(subsystems: Vec<Arc<dyn Subsystem>>) -> Result<Self> {
+        let mut names = HashSet::new();
+        for subsystem in &subsystems {
+            if !names.insert(subsystem.name()) {
+                return Err(anyhow!("Duplicate subsystem: {}", subsystem.name()));
+            }
+        }
+        for subsystem in &subsystems {
+            if let Some(dependency) = subsystem
+                .depends_on()
+                .into_iter()
+                .find(|dependency| !names.contains(dependency))
+            {
+                return Err(anyhow!(
+                    "Subsystem {} depends on unknown subsystem {}",
+                    subsystem.name(),
+                    dependency
+                ));
+            }
+        }
+
+        // Repeatedly take the first remaining subsystem whose dependencies have all been taken.
+        let mut remaining = subsystems;
+        let mut ordered = Vec::with_capacity(remaining.len());
+        let mut started = HashSet::new();
+        while !remaining.is_empty() {
+            let index = remaining
+                .iter()
+                .position(|subsystem| {
+                    subsystem
+                        .depends_on()
+                        .iter()
+                        .all(|dependency| started.contains(dependency))
+                })
+                .ok_or_else(|| {
+                    let names = remaining
+                        .iter()
+                        .map(|subsystem| subsystem.name())
+                        .collect::<Vec<_>>();
+                    anyhow!("Subsystems depend on each other: {}", names.join(", "))
+                })?;
+            let subsystem = remaining.remove(index);
+            started.insert(subsystem.name());
+            ordered.push(subsystem);
+        }
+
+        Ok(Lifecycle {
+            subsystems: Arc::new(ordered),
+        })
+    }
+
+    /// Run the startup hook of every subsystem in dependency order. If one fails or times out,
+    /// the subsystems already started are shut down and an error is returned.
+    pub async fn start(&self, state: &State) -> Result<()> {
+        for (index, subsystem) in self.subsystems.iter().enumerate() {
+            let result = run_hook(
+                state,
+                subsystem.name(),
+                "startup",
+                subsystem.on_start(state),
+            )
+            .await;
+            if let Err(error) = result {
+                shutdown_all(state, &self.subsystems[..index]).await;
+                return Err(error.context(format!("Failed to start {}", subsystem.name())));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the shutdown hook of every subsystem in reverse dependency order. Hooks that fail or
+    /// time out are logged, and the remaining subsystems are still shut down.
+    pub async fn shutdown(&self, state: &State) {
+        log::info!("Shutting down...");
+        shutdown_all(state, &self.subsystems).await;
+        log::info!("Shut down.");
+    }
+}
+
+/// Run the shutdown hooks of subsystems in reverse order, logging any that fail.
+async fn shutdown_all(state: &State, subsystems: &[Arc<dyn Subsystem>]) {
+    for subsystem in subsystems.iter().rev() {
+        let _ = run_hook(
+            state,
+            subsystem.name(),
+            "shutdown",
+            subsystem.on_shutdown(state),
+        )
+        .await;
+    }
+}
+
+/// Run a subsystem's startup or shutdown hook within the configured timeout, logging how it went.
+async fn run_hook(
+    state: &State,
+    name: &str,
+    action: &str,
+    hook: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    let start = Instant::now();
+    let timeout = Duration::from_secs(state.config.lifecycle_hook_timeout_seconds.into());
+    let result = match future::timeout(timeout, hook).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("Timed out after {}s", timeout.as_secs())),
+    };
+    match &result {
+        Ok(()) => log::info!(
+            "Ran the {} hook of {} in {}ms.",
+            action,
+            name,
+            start.elapsed().as_millis()
+        ),
+        Err(error) => log::error!("The {} hook of {} failed: {}", action, name, error),
+    }
+
+    result
+}
+
+/// Future run by a worker for as long as the server is up.
+type WorkerTask = Box<dyn Fn(&State) -> BoxFuture<'static, ()> + Send + Sync>;
+/// Asks a worker to stop once it has finished the work it is doing.
+type WorkerStop = Box<dyn Fn(&State) + Send + Sync>;
+
+/// A subsystem running a task in the background while the server is up. The task is cancelled
+/// when the server shuts down, unless the worker can be asked to stop, in which case it is waited
+/// on to finish instead.
+pub struct Worker {
+    name: &'static str,
+    depends_on: Vec<&'static str>,
+    run: WorkerTask,
+    stop: Option<WorkerStop>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Worker {
+    /// Create a worker running a task spawned from the server state.
+    pub fn new<F>(name: &'static str, run: impl Fn(&State) -> F + Send + Sync + 'static) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        Worker {
+            name,
+            depends_on: Vec::new(),
+            run: Box::new(move |state| run(state).boxed()),
+            stop: None,
+            handle: Mutex::new(None),
+        }
+    }
+
+    /// Start the worker after other subsystems, and shut it down before them.
+    pub fn with_dependencies(mut self, depends_on: &[&'static str]) -> Self {
+        self.depends_on = depends_on.to_vec();
+        self
+    }
+
+    /// Ask the worker to stop instead of cancelling it when the server shuts down. The worker's
+    /// task must return once asked.
+    pub fn with_stop(mut self, stop: impl Fn(&State) + Send + Sync + 'static) -> Self {
+        self.stop = Some(Box::new(stop));
+        self
+    }
+}
+
+#[async_trait]
+impl Subsystem for Worker {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn depends_on(&self) -> Vec<&'static str> {
+        self.depends_on.clone()
+    }
+
+    async fn on_start(&self, state: &State) -> Result<()> {
+        let handle = task::spawn((self.run)(state));
+        *self.handle.lock().unwrap() = Some(handle);
+        Ok(())
+    }
+
+    async fn on_shutdown(&self, state: &State) -> Result<()> {
+        let handle = match self.handle.lock().unwrap().take() {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+        match &self.stop {
+            Some(stop) => {
+                stop(state);
+                handle.await;
+            }
+            None => {
+                handle.cancel().await;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The Postgres connection pool, which is closed once every subsystem using it has shut down.
+struct Database;
+
+#[async_trait]
+impl Subsystem for Database {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn on_shutdown(&self, state: &State) -> Result<()> {
+        state.db.close().await;
+        Ok(())
+    }
+}
+
+/// The domain event publisher, whose subscribers are disconnected once nothing else can publish.
+struct Events;
+
+#[async_trait]
+impl Subsystem for Events {
+    fn name(&self) -> &'static str {
+        "events"
+    }
+
+    async fn on_shutdown(&self, state: &State) -> Result<()> {
+        state.events.close();
+        Ok(())
+    }
+}
+
+/// The subsystems built into the server.
+pub fn builtin_subsystems() -> Vec<Arc<dyn Subsystem>> {
+    let mut subsystems: Vec<Arc<dyn Subsystem>> = vec![Arc::new(Database), Arc::new(Events)];
+    #[cfg(feature = "email")]
+    subsystems.push(Arc::new(
+        Worker::new("email-queue", |state| {
+            state.mailer.clone().run_queue_worker()
+        })
+        .with_stop(|state| state.mailer.stop_queue_worker()),
+    ));
+    subsystems.push(Arc::new(
+        Worker::new("redis-regions", |state| {
+            regions::run_region_worker(state.clone())
+        })
+        .with_stop(|state| state.regions.stop_worker()),
+    ));
+    subsystems.push(Arc::new(Worker::new("usage-reset", |state| {
+        usage::run_reset_worker(state.metrics.instrument(state.redis.clone()))
+    })));
+    // Trial reminders are sent by email, so they stop before the email queue is flushed.
+    let reminder_dependencies: &[&str] = if cfg!(feature = "email") {
+        &["database", "email-queue"]
+    } else {
+        &["database"]
+    };
+    subsystems.push(Arc::new(
+        Worker::new("trial-reminders", |state| {
+            trials::run_reminder_worker(state.clone())
+        })
+        .with_dependencies(reminder_dependencies),
+    ));
+    // Alerts may be sent by email too.
+    subsystems.push(Arc::new(
+        Worker::new("alerts", |state| alerts::run_alert_worker(state.clone()))
+            .with_dependencies(reminder_dependencies),
+    ));
+    subsystems.push(Arc::new(
+        Worker::new("retention", |state| {
+            retention::run_retention_worker(state.clone())
+        })
+        .with_dependencies(&["database"]),
+    ));
+    subsystems.push(Arc::new(
+        Worker::new("pending-actions", |state| {
+            pending_actions::run_pending_action_worker(state.clone())
+        })
+        .with_dependencies(&["database"]),
+    ));
+    subsystems.push(Arc::new(
+        Worker::new("avatars", |state| avatars::run_avatar_worker(state.clone()))
+            .with_dependencies(&["database"]),
+    ));
+    subsystems.push(Arc::new(
+        Worker::new("domains", |state| domains::run_domain_worker(state.clone()))
+            .with_dependencies(&["database"]),
+    ));
+    subsystems.push(Arc::new(
+        Worker::new("change-listener", |state| {
+            events::run_change_listener(state.clone())
+        })
+        .with_dependencies(&["database", "events"]),
+    ));
+
+    subsystems
+}
+
+/// Wait until the process is asked to stop with SIGINT or SIGTERM. Once this is called, those
+/// signals no longer kill the process, so it can shut down gracefully.
+pub async fn shutdown_signal() -> Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    let signal = task::spawn_blocking(move || signals.forever().next()).await;
+    if let Some(signal) = signal {
+        log::info!("Received signal {}.", signal);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, Key, Value as KeyValue, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+/// Target of the log lines tide's built-in request logger writes. Tide runs that logger ahead of
+/// every other middleware, before the request ID is known, so its lines are dropped in favor of
+/// those written by the server's own request logging middleware.
+const TIDE_REQUEST_LOG_TARGET: &str = "tide::log::middleware";
+
+// ANSI terminal codes used to color text log lines.
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+
+/// Decides how log lines are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Colored text meant to be read in a terminal.
+    #[default]
+    Text,
+    /// A JSON object per line, meant to be collected by log aggregators.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow!("Unknown log format: {}", value)),
+        }
+    }
+}
+
+/// Fields describing the request being handled, included in every line logged while handling it.
+#[derive(Debug, Clone)]
+struct RequestFields {
+    request_id: String,
+    operation_name: Option<String>,
+    user_id: Option<Uuid>,
+}
+
+async_std::task_local! {
+    // Every connection tide accepts is handled in its own task, one request at a time, so fields
+    // set here apply to the request the task is currently handling.
+    static REQUEST_FIELDS: RefCell<Option<RequestFields>> = RefCell::new(None);
+}
+
+#[cfg(feature = "axum")]
+tokio::task_local! {
+    // Requests handled by the axum front end run on tokio, so their fields are scoped to the
+    // future handling them instead.
+    static AXUM_REQUEST_FIELDS: RefCell<Option<RequestFields>>;
+}
+
+/// Run a function on the fields of the request being handled by the current task. This will
+/// return none if no request is being handled.
+fn with_request_fields<R>(mut f: impl FnMut(&mut Option<RequestFields>) -> R) -> Option<R> {
+    #[cfg(feature = "axum")]
+    if let Ok(result) = AXUM_REQUEST_FIELDS.try_with(|fields| f(&mut fields.borrow_mut())) {
+        return Some(result);
+    }
+
+    REQUEST_FIELDS
+        .try_with(|fields| f(&mut fields.borrow_mut()))
+        .ok()
+}
+
+/// Start handling a request with the specified ID in the current task. Lines logged by the task
+/// include the request ID until the request ends.
+pub fn start_request(request_id: String) {
+    with_request_fields(|fields| {
+        *fields = Some(RequestFields {
+            request_id: request_id.clone(),
+            operation_name: None,
+            user_id: None,
+        })
+    });
+}
+
+/// Stop handling the request the current task was handling.
+pub fn end_request() {
+    with_request_fields(|fields| *fields = None);
+}
+
+/// Run a future handling a request with the specified ID on tokio. Lines logged by the future
+/// include the request ID.
+#[cfg(feature = "axum")]
+pub async fn scope_request<F: std::future::Future>(request_id: String, future: F) -> F::Output {
+    let fields = RequestFields {
+        request_id,
+        operation_name: None,
+        user_id: None,
+    };
+    AXUM_REQUEST_FIELDS
+        .scope(RefCell::new(Some(fields)), future)
+        .await
+}
+
+/// Record the GraphQL operation the request being handled executes and the user making it, so
+/// they are included in the rest of the lines logged while handling it.
+pub fn record_operation(operation_name: Option<&str>, user_id: Option<Uuid>) {
+    with_request_fields(|fields| {
+        if let Some(fields) = fields {
+            fields.operation_name = operation_name.map(String::from);
+            fields.user_id = user_id;
+        }
+    });
+}
+
+/// Log that a database query has been run, along with how long it took. Slow queries are logged
+/// as warnings. Lines logged while handling a request include its GraphQL operation name, so slow
+/// queries can be traced back to the operation that ran them.
+pub fn log_query(name: &str, duration: Duration, is_slow: bool) {
+    let duration_ms = duration.as_secs_f64() * 1000.0;
+    if is_slow {
+        log::warn!(query = name, duration_ms = duration_ms; "Slow database query");
+    } else {
+        log::info!(query = name, duration_ms = duration_ms; "Ran database query");
+    }
+}
+
+/// Log that a request has been handled, along with its response status and how long it took.
+pub fn log_request(method: &str, path: &str, status: u16, duration: Duration) {
+    let level = if status >= 500 {
+        Level::Error
+    } else {
+        Level::Info
+    };
+    let duration_ms = duration.as_secs_f64() * 1000.0;
+    log::log!(
+        level,
+        method = method,
+        path = path,
+        status = status,
+        duration_ms = duration_ms;
+        "Handled request"
+    );
+}
+
+/// Logger writing lines to stdout in the configured format.
+struct Logger {
+    /// Set once the configuration has been loaded and asks for JSON lines. Lines logged before
+    /// then, while loading it, are written as text.
+    json: AtomicBool,
+}
+
+static LOGGER: Logger = Logger {
+    json: AtomicBool::new(false),
+};
+
+/// Start writing log lines of info level and above to stdout as text.
+pub fn start() {
+    log::set_logger(&LOGGER).expect("Could not start logging");
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// Change the format log lines are written in from now on.
+pub fn set_format(format: LogFormat) {
+    LOGGER
+        .json
+        .store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level() && metadata.target() != TIDE_REQUEST_LOG_TARGET
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let fields = with_request_fields(|fields| fields.clone()).flatten();
+        let line = if self.json.load(Ordering::Relaxed) {
+            json_line(record, fields)
+        } else {
+            text_line(record, fields)
+        };
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        let _ = writeln!(handle, "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Format a log line as colored text, prefixed with the ID of the request being handled and its
+/// GraphQL operation name, if known. Key-value pairs are written on their own lines under it.
+fn text_line(record: &Record<'_>, fields: Option<RequestFields>) -> String {
+    let color = match record.level() {
+        Level::Error => RED,
+        Level::Warn => YELLOW,
+        _ => GREEN,
+    };
+    let mut line = format!("{}{}{}{}", color, BOLD, record.target(), RESET);
+    if let Some(fields) = fields {
+        match fields.operation_name {
+            Some(operation_name) => {
+                line.push_str(&format!(" [{} {}]", fields.request_id, operation_name))
+            }
+            None => line.push_str(&format!(" [{}]", fields.request_id)),
+        }
+    }
+    line.push_str(&format!(" {}", record.args()));
+
+    let mut pairs = Map::new();
+    let _ = record.key_values().visit(&mut PairCollector(&mut pairs));
+    for (key, value) in pairs {
+        let value = match value {
+            Value::String(value) => value,
+            value => value.to_string(),
+        };
+        line.push_str(&format!("\n    {}{}{} {}", BOLD, key, RESET, value));
+    }
+
+    line
+}
+
+/// Format a log line as a JSON object, including the fields of the request being handled.
+fn json_line(record: &Record<'_>, fields: Option<RequestFields>) -> String {
+    let mut line = Map::new();
+    line.insert(
+        "time".into(),
+        Utc::now()
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+            .into(),
+    );
+    line.insert("level".into(), record.level().as_str().into());
+    line.insert("target".into(), record.target().into());
+    line.insert("message".into(), record.args().to_string().into());
+    if let Some(fields) = fields {
+        line.insert("request_id".into(), fields.request_id.into());
+        if let Some(operation_name) = fields.operation_name {
+            line.insert("operation_name".into(), operation_name.into());
+        }
+        if let Some(user_id) = fields.user_id {
+            line.insert("user_id".into(), user_id.to_string().into());
+        }
+    }
+    let _ = record.key_values().visit(&mut PairCollector(&mut line));
+
+    Value::Object(line).to_string()
+}
+
+/// Collects the key-value pairs of a log line into a JSON object, keeping numbers and booleans as
+/// such.
+struct PairCollector<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for PairCollector<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: KeyValue<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(value) = value.to_bool() {
+            value.into()
+        } else if let Some(value) = value.to_u64() {
+            value.into()
+        } else if let Some(value) = value.to_i64() {
+            value.into()
+        } else if let Some(value) = value.to_f64() {
+            value.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
@@ -1,103 +1,519 @@
-mod auth;
-mod config;
-mod context;
-mod db;
-mod executor;
-mod models;
-mod schema;
-mod state;
-
-use anyhow::Result;
-use clap::{App, ArgMatches, SubCommand};
-use juniper::http::GraphQLRequest;
-use tide::http::mime;
-use tide::{log, Body, Request, Response, Server, StatusCode};
-
-use config::Config;
-use context::Context;
-use db::{connect_to_db, connect_to_redis, run_migrations};
-use schema::SCHEMA;
-use state::State;
-
-/// Handle a GraphQL request.
-async fn graphql(mut request: Request<State>) -> tide::Result {
-    // Attempt to parse the GraphQL query from the request.
-    let query: GraphQLRequest = request.body_json().await?;
-    // Initialize a context struct for the request. This context may include configuration,
-    // connections to databases, authentication info, etc..
-    let context = Context::new(request).await;
-    // Execute the query using our GraphQL schema.
-    let response = query.execute(&SCHEMA, &context).await;
-    // If we get an error while executing the query, return a bad request status.
-    let status = if response.is_ok() {
-        StatusCode::Ok
-    } else {
-        StatusCode::BadRequest
-    };
-
-    // Build and return the response.
-    let response = Response::builder(status)
-        .content_type(mime::JSON)
-        .body(Body::from_json(&response)?);
+use std::path::Path;
+use std::time::Duration;
 
-    Ok(response.build())
-}
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+#[cfg(not(feature = "axum"))]
+use futures::future::{self, Either};
+use rust_graphql_server::admin::create_admin;
+use rust_graphql_server::analyze::find_missing_indexes;
+#[cfg(feature = "axum")]
+use rust_graphql_server::axum_server;
+#[cfg(not(feature = "axum"))]
+use rust_graphql_server::build_app;
+use rust_graphql_server::config::Config;
+use rust_graphql_server::config_schema;
+use rust_graphql_server::db::{
+    self, connect_to_db, connect_to_redis, run_migrations, MigrationState,
+};
+use rust_graphql_server::hash_cost::{self, MAX_SAFE_COST, MIN_SAFE_COST};
+#[cfg(not(feature = "axum"))]
+use rust_graphql_server::lifecycle;
+use rust_graphql_server::logging;
+use rust_graphql_server::operation_validation::validate_operations;
+use rust_graphql_server::persisted_queries::{register_manifest, PersistedQueryManifest};
+use rust_graphql_server::retention::purge_expired;
+use rust_graphql_server::schema::{build_schema, schema_language};
+use rust_graphql_server::seed::{self, Fixtures, Seed};
+use rust_graphql_server::{plugin, routes};
+use tide::log;
 
 /// Parse command line arguments for the server.
 fn parse_args() -> ArgMatches<'static> {
     App::new("rust-graphql-server")
         .version("0.1.0")
-        .subcommand(SubCommand::with_name("generate"))
+        .subcommand(
+            SubCommand::with_name("generate").subcommand(SubCommand::with_name("config-schema")),
+        )
         .subcommand(SubCommand::with_name("dev"))
+        .subcommand(SubCommand::with_name("check-config"))
+        .subcommand(SubCommand::with_name("routes"))
+        .subcommand(SubCommand::with_name("analyze"))
+        .subcommand(
+            SubCommand::with_name("purge-expired-data")
+                .arg(Arg::with_name("dry-run").long("dry-run")),
+        )
+        .subcommand(
+            SubCommand::with_name("schema")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("validate-operations")
+                        .arg(Arg::with_name("directory").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("persist-queries").arg(Arg::with_name("manifest").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("calibrate-hash-cost")
+                .arg(
+                    Arg::with_name("target-ms")
+                        .long("target-ms")
+                        .takes_value(true)
+                        .default_value("250"),
+                )
+                .arg(Arg::with_name("write").long("write")),
+        )
+        .subcommand(
+            SubCommand::with_name("create-admin")
+                .arg(
+                    Arg::with_name("username")
+                        .long("username")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("email")
+                        .long("email")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("status"))
+                .subcommand(
+                    SubCommand::with_name("up")
+                        .arg(Arg::with_name("target").long("target").takes_value(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("down")
+                        .arg(Arg::with_name("target").long("target").takes_value(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("new").arg(Arg::with_name("name").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("seed")
+                .arg(Arg::with_name("file").long("file").takes_value(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("fixtures")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("load").arg(Arg::with_name("file").required(true)),
+                ),
+        )
         .get_matches()
 }
 
-/// Write generated files. At the moment this only includes the GraphQL schema.
+/// Write generated files. This includes the GraphQL schema and the configuration schema.
 fn generate() {
     log::info!("Writing generated files...");
 
     // Write the derived GraphQL schema.
     {
         log::info!("Writing schema.gql...");
-        std::fs::write("./schema.gql", SCHEMA.as_schema_language())
+        std::fs::write("./schema.gql", schema_language(&build_schema(&[])))
             .expect("Failed to write schema.gql.");
     }
 
+    generate_config_schema();
     log::info!("Done");
 }
 
+/// Write a JSON schema and a markdown table describing every environment variable the server is
+/// configured with. Variables are described as they are read from the ".env" file, which provides
+/// their defaults.
+fn generate_config_schema() {
+    let variables =
+        Config::describe_variables(Path::new(".env")).expect("Failed to read the .env file.");
+
+    log::info!("Writing config.schema.json...");
+    let schema = serde_json::to_string_pretty(&config_schema::json_schema(&variables))
+        .expect("Failed to serialize the configuration schema.");
+    std::fs::write("./config.schema.json", schema + "\n")
+        .expect("Failed to write config.schema.json.");
+
+    log::info!("Writing CONFIGURATION.md...");
+    std::fs::write("./CONFIGURATION.md", config_schema::markdown(&variables))
+        .expect("Failed to write CONFIGURATION.md.");
+}
+
+/// Check every configuration variable, printing every problem found. The process exits with an
+/// error status if there are any, so deployments with a broken configuration can be caught before
+/// the server is started.
+fn check_config() {
+    match Config::try_load() {
+        Ok(_) => println!("The configuration is valid."),
+        Err(errors) => {
+            println!("{}", errors);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Print every mounted HTTP route along with its method and middleware.
+fn print_routes(config: &Config) {
+    for route in routes::describe(&routes::modules(), config) {
+        println!("{}", route);
+    }
+}
+
+/// Inspect the database for missing indexes the server's queries need, and print statements
+/// creating them.
+async fn analyze(config: &Config) -> Result<()> {
+    let db = connect_to_db(config).await?;
+    let missing = find_missing_indexes(&db).await?;
+    if missing.is_empty() {
+        println!("-- No missing indexes found.");
+    }
+
+    for suggestion in missing {
+        println!(
+            "-- {} ({}): {}.",
+            suggestion.table,
+            suggestion.columns.join(", "),
+            suggestion.reason
+        );
+        println!("{}", suggestion.create_statement());
+    }
+
+    Ok(())
+}
+
+/// Purge data older than its retention period once, printing what was purged for each kind of
+/// data. In a dry run, or if dry runs are configured, expired data is only counted.
+async fn purge_expired_data(config: &Config, dry_run: bool) -> Result<()> {
+    let dry_run = dry_run || config.retention_dry_run;
+    let db = connect_to_db(config).await?;
+    let mut redis = connect_to_redis(config).await?;
+    let reports = purge_expired(
+        &db,
+        &mut redis,
+        &config.retention_policy,
+        dry_run,
+        Utc::now(),
+    )
+    .await?;
+    if reports.is_empty() {
+        println!("No data has a retention period.");
+    }
+
+    let action = if dry_run { "Would purge" } else { "Purged" };
+    for report in reports {
+        println!(
+            "{} {} {} from before {}.",
+            action,
+            report.count,
+            report.target.name(),
+            report.cutoff.to_rfc3339()
+        );
+    }
+
+    Ok(())
+}
+
+/// Register the operations in a persisted query manifest file, so they can be executed even when
+/// only registered persisted queries are allowed.
+async fn persist_queries(config: &Config, path: &str) -> Result<()> {
+    let manifest: PersistedQueryManifest = serde_json::from_slice(&std::fs::read(path)?)?;
+    let mut redis = connect_to_redis(config).await?;
+    let count = register_manifest(&mut redis, &manifest).await?;
+    println!("Registered {} persisted queries.", count);
+
+    Ok(())
+}
+
+/// Validate the client operation files in a directory against the current schema, printing every
+/// problem found. This will return an error if any were, so client and server changes that break
+/// each other fail CI.
+fn validate_operation_files(directory: &str) -> Result<()> {
+    let report = validate_operations(&build_schema(&[]), Path::new(directory))?;
+    for error in &report.errors {
+        println!("{}", error);
+    }
+    println!(
+        "Validated {} operations in {} files.",
+        report.operations, report.files
+    );
+
+    if !report.errors.is_empty() {
+        return Err(anyhow!(
+            "Found {} problems in client operations.",
+            report.errors.len()
+        ));
+    }
+
+    Ok(())
+}
+
+/// File the calibrated password hashing cost is written to. Variables in it take precedence over
+/// those in ".env".
+const ENV_OVERRIDE_FILE: &str = ".env.override";
+
+/// Time password hashing at increasing costs on this host and recommend the highest cost that
+/// hashes a password within a target number of milliseconds. The recommendation is written to the
+/// ".env.override" file if asked to.
+fn calibrate_hash_cost(config: &Config, target_ms: u64, write: bool) -> Result<()> {
+    println!(
+        "Timing bcrypt hashes against a target of {}ms...",
+        target_ms
+    );
+    let calibration = hash_cost::calibrate(Duration::from_millis(target_ms))?;
+    for timing in &calibration.timings {
+        println!(
+            "Cost {:>2}: {:>8.1}ms",
+            timing.cost,
+            timing.duration.as_secs_f64() * 1000.0
+        );
+    }
+
+    let cost = calibration.recommended_cost;
+    println!(
+        "Recommended cost: {} (currently {}).",
+        cost, config.password_hash_cost
+    );
+    if cost < MIN_SAFE_COST {
+        println!(
+            "Warning: this is below the safe minimum of {}. Consider a higher target or faster host.",
+            MIN_SAFE_COST
+        );
+    } else if cost > MAX_SAFE_COST {
+        println!(
+            "Warning: this is above the safe maximum of {}. Consider a lower target.",
+            MAX_SAFE_COST
+        );
+    }
+
+    if write {
+        hash_cost::write_cost(Path::new(ENV_OVERRIDE_FILE), cost)?;
+        println!(
+            "Wrote PASSWORD_HASH_COST={} to {}.",
+            cost, ENV_OVERRIDE_FILE
+        );
+    }
+
+    Ok(())
+}
+
+/// Create a user with the admin role, prompting for their password. Pending migrations are run
+/// first, so this can be used on a fresh database.
+async fn create_admin_user(config: &Config, username: &str, email: &str) -> Result<()> {
+    let password = rpassword::prompt_password_stderr("Password: ")?;
+    if rpassword::prompt_password_stderr("Confirm password: ")? != password {
+        return Err(anyhow!("Passwords do not match."));
+    }
+
+    let db = connect_to_db(config).await?;
+    run_migrations(&db, &plugin::migrations(&[])).await?;
+    let user = create_admin(&db, config, username, email, &password).await?;
+    println!("Created admin {} with ID {}.", user.username, user.id);
+
+    Ok(())
+}
+
+/// Directory new migrations are created in.
+const MIGRATIONS_DIRECTORY: &str = "./migrations";
+
+/// Manage database migrations. "status" lists every migration and whether it has been applied,
+/// "up" and "down" run or revert migrations up to an optional target version, and "new" creates
+/// an empty pair of migration files.
+async fn migrate(config: &Config, args: &ArgMatches<'_>) -> Result<()> {
+    if let Some(args) = args.subcommand_matches("new") {
+        let name = args.value_of("name").unwrap_or_default();
+        let (up, down) = db::create_migration(Path::new(MIGRATIONS_DIRECTORY), name)?;
+        println!("Created {}", up.display());
+        println!("Created {}", down.display());
+        return Ok(());
+    }
+
+    let db = connect_to_db(config).await?;
+    let migrations = plugin::migrations(&[]);
+    if args.subcommand_matches("status").is_some() {
+        for status in db::migration_status(&db, &migrations).await? {
+            let state = match status.state {
+                MigrationState::Applied => "applied",
+                MigrationState::Pending => "pending",
+                MigrationState::Modified => "modified",
+            };
+            let reversible = if status.is_reversible {
+                ""
+            } else {
+                " (irreversible)"
+            };
+            println!(
+                "{} {:<8} {}{}",
+                status.version, state, status.description, reversible
+            );
+        }
+    } else if let Some(args) = args.subcommand_matches("up") {
+        let target = args.value_of("target").map(str::parse).transpose()?;
+        let applied = db::migrate_up(&db, &migrations, target).await?;
+        for version in &applied {
+            println!("Applied {}", version);
+        }
+        println!("Applied {} migrations.", applied.len());
+    } else if let Some(args) = args.subcommand_matches("down") {
+        let target = args.value_of("target").map(str::parse).transpose()?;
+        let reverted = db::migrate_down(&db, &migrations, target).await?;
+        for version in &reverted {
+            println!("Reverted {}", version);
+        }
+        println!("Reverted {} migrations.", reverted.len());
+    }
+
+    Ok(())
+}
+
+/// Populate the database with development data. The data declared in a fixtures file is seeded if
+/// one is given, otherwise the built-in seeds are run. Pending migrations are run first, and
+/// seeding is idempotent, so this can be run again after resetting or changing the data.
+async fn seed(config: &Config, file: Option<&str>) -> Result<()> {
+    let seeds: Vec<Box<dyn Seed>> = match file {
+        Some(path) => vec![Box::new(Fixtures::load(Path::new(path))?)],
+        None => seed::default_seeds()
+            .into_iter()
+            .chain(plugin::seeds(&[]))
+            .collect(),
+    };
+
+    let db = connect_to_db(config).await?;
+    run_migrations(&db, &plugin::migrations(&[])).await?;
+    for seed in seeds {
+        seed.run(&db, config).await?;
+        println!("Ran seed {}.", seed.name());
+    }
+
+    Ok(())
+}
+
+/// Load the data declared in a YAML or TOML fixtures file into the database, printing how many
+/// records it declared. Pending migrations are run first, and nothing is changed if any fixture is
+/// invalid.
+async fn load_fixtures(config: &Config, file: &str) -> Result<()> {
+    let fixtures = Fixtures::load(Path::new(file))?;
+
+    let db = connect_to_db(config).await?;
+    run_migrations(&db, &plugin::migrations(&[])).await?;
+    let loaded = fixtures.apply(&db, config).await?;
+    println!("Loaded {} users from {}.", loaded.users.len(), file);
+
+    Ok(())
+}
+
 /// Run the server with the provided configuration settings.
 async fn run(config: Config) -> Result<()> {
     log::debug!("Running with config: {:#?}", config);
+    if config.is_docker {
+        log::info!("Running in a Docker container.");
+    }
 
-    log::info!("Connecting to Postgres database...");
-    let db = connect_to_db(&config).await?;
-    log::info!("Connecting to Redis database...");
-    let redis = connect_to_redis(&config).await?;
-
-    log::info!("Running any pending database migrations...");
-    run_migrations(&db).await?;
+    // Stop accepting requests when asked to stop, then shut the server's subsystems down.
+    #[cfg(not(feature = "axum"))]
+    {
+        let server = build_app(config.clone(), Vec::new()).await?;
+        let state = server.state().clone();
+        let listening = server.listen(format!("0.0.0.0:{}", &config.port));
+        match future::select(Box::pin(listening), Box::pin(lifecycle::shutdown_signal())).await {
+            Either::Left((result, _)) => result?,
+            Either::Right((result, _)) => result?,
+        }
+        state.lifecycle.shutdown(&state).await;
+    }
 
-    let mut server = Server::with_state(State::new(config.clone(), db, redis));
-    server.at("/graphql").post(graphql);
-    server.listen(format!("0.0.0.0:{}", &config.port)).await?;
+    #[cfg(feature = "axum")]
+    {
+        // The axum front end needs a tokio runtime. Drive it from a blocking thread so it doesn't
+        // tie up the async-std executor.
+        let runtime = tokio::runtime::Runtime::new()?;
+        async_std::task::spawn_blocking(move || {
+            runtime.block_on(axum_server::serve(config, Vec::new()))
+        })
+        .await?;
+    }
 
     Ok(())
 }
 
 #[async_std::main]
 async fn main() -> Result<()> {
-    // Setup server logging.
-    log::start();
-
-    // Parse configuration from environment variables and .env files.
-    let config = Config::load().await;
+    // Setup server logging. Lines are written as text until the configuration is loaded.
+    logging::start();
 
     // Parse command line arguments.
     let args = parse_args();
-    if args.subcommand_matches("generate").is_some() {
-        // If the second argument is "generate", write generated files and exit.
-        generate();
+    if args.subcommand_matches("check-config").is_some() {
+        // If the second argument is "check-config", check the configuration and exit without
+        // loading it.
+        check_config();
+        return Ok(());
+    }
+
+    // Parse configuration from environment variables and .env files. Every problem with it is
+    // reported at once before exiting.
+    let config = Config::load().await;
+    logging::set_format(config.log_format);
+
+    if let Some(args) = args.subcommand_matches("generate") {
+        // If the second argument is "generate", write generated files and exit. Only the
+        // configuration schema is written if the third argument is "config-schema".
+        if args.subcommand_matches("config-schema").is_some() {
+            generate_config_schema();
+        } else {
+            generate();
+        }
+    } else if args.subcommand_matches("routes").is_some() {
+        // If the second argument is "routes", print the mounted HTTP routes and exit.
+        print_routes(&config);
+    } else if args.subcommand_matches("analyze").is_some() {
+        // If the second argument is "analyze", print suggested indexes for the database and exit.
+        analyze(&config).await?;
+    } else if let Some(args) = args.subcommand_matches("purge-expired-data") {
+        // If the second argument is "purge-expired-data", purge data older than its retention
+        // period, or only count it if "--dry-run" is passed, and exit.
+        purge_expired_data(&config, args.is_present("dry-run")).await?;
+    } else if let Some(args) = args.subcommand_matches("schema") {
+        // If the second argument is "schema" and the third is "validate-operations", validate the
+        // client operations in the directory passed as the fourth argument and exit.
+        if let Some(args) = args.subcommand_matches("validate-operations") {
+            validate_operation_files(args.value_of("directory").unwrap_or_default())?;
+        }
+    } else if let Some(args) = args.subcommand_matches("persist-queries") {
+        // If the second argument is "persist-queries", register the operations in the manifest file
+        // passed as the third argument and exit.
+        persist_queries(&config, args.value_of("manifest").unwrap_or_default()).await?;
+    } else if let Some(args) = args.subcommand_matches("calibrate-hash-cost") {
+        // If the second argument is "calibrate-hash-cost", recommend a password hashing cost for
+        // this host and exit.
+        let target_ms = args.value_of("target-ms").unwrap_or_default().parse()?;
+        calibrate_hash_cost(&config, target_ms, args.is_present("write"))?;
+    } else if let Some(args) = args.subcommand_matches("create-admin") {
+        // If the second argument is "create-admin", create an admin user with the username and
+        // email address passed as options and exit.
+        create_admin_user(
+            &config,
+            args.value_of("username").unwrap_or_default(),
+            args.value_of("email").unwrap_or_default(),
+        )
+        .await?;
+    } else if let Some(args) = args.subcommand_matches("migrate") {
+        // If the second argument is "migrate", show, run, revert or create migrations as asked by
+        // the third argument and exit.
+        migrate(&config, args).await?;
+    } else if let Some(args) = args.subcommand_matches("seed") {
+        // If the second argument is "seed", populate the database with development data from the
+        // fixtures file passed as an option, or the built-in seeds, and exit.
+        seed(&config, args.value_of("file")).await?;
+    } else if let Some(args) = args.subcommand_matches("fixtures") {
+        // If the second argument is "fixtures" and the third is "load", load the fixtures file
+        // passed as the fourth argument and exit.
+        if let Some(args) = args.subcommand_matches("load") {
+            load_fixtures(&config, args.value_of("file").unwrap_or_default()).await?;
+        }
     } else if args.subcommand_matches("dev").is_some() {
         // If the second argument is "dev", write generated files and start the server.
         generate();
@@ -0,0 +1,11 @@
+#[cfg(not(feature = "metrics"))]
+mod disabled;
+#[cfg(feature = "metrics")]
+mod registry;
+
+#[cfg(not(feature = "metrics"))]
+pub use disabled::{InstrumentedConnection, Metrics};
+#[cfg(feature = "metrics")]
+pub use registry::{
+    InstrumentedConnection, Metrics, MetricsMiddleware, MetricsModule, UNMATCHED_ROUTE,
+};
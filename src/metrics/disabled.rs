@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use redis::aio::ConnectionManager;
+
+/// Stands in for the server's Prometheus metrics when the server is built without the "metrics"
+/// feature. Nothing is recorded.
+#[derive(Clone)]
+pub struct Metrics;
+
+impl Metrics {
+    /// Create metrics that record nothing.
+    pub fn new() -> Result<Self> {
+        Ok(Metrics)
+    }
+
+    /// Ignore an executed GraphQL request.
+    pub fn observe_graphql_request(
+        &self,
+        _operation_name: Option<&str>,
+        _has_errors: bool,
+        _duration: Duration,
+    ) {
+    }
+
+    /// Ignore the rows fetched and response size of a GraphQL request.
+    pub fn observe_graphql_usage(
+        &self,
+        _operation_name: Option<&str>,
+        _rows_fetched: u64,
+        _response_bytes: usize,
+    ) {
+    }
+
+    /// Ignore a GraphQL request that used a deprecated field.
+    pub fn observe_deprecated_field_use(&self, _field: &str) {}
+
+    /// Ignore a lookup in the result cache.
+    pub fn observe_result_cache_lookup(&self, _query: &str, _is_hit: bool) {}
+
+    /// Ignore a canary run.
+    pub fn observe_canary(&self, _canary: &str, _outcome: &str) {}
+
+    /// Ignore a screened signup.
+    pub fn observe_signup_screening(&self, _outcome: &str, _signals: &[&str]) {}
+
+    /// Ignore purged data.
+    pub fn observe_retention_purge(&self, _target: &str, _count: u64) {}
+
+    /// Ignore a database query.
+    pub fn observe_db_query(&self, _query: &str, _duration: Duration) {}
+
+    /// Ignore a replicated session write.
+    pub fn observe_redis_replication(&self, _region: &str, _result: &str) {}
+
+    /// Ignore a region's round trip time.
+    pub fn observe_redis_region_latency(&self, _region: &str, _latency: Duration) {}
+
+    /// Return a Redis connection as-is, as commands aren't counted.
+    pub fn instrument(&self, connection: ConnectionManager) -> InstrumentedConnection {
+        connection
+    }
+}
+
+/// A Redis connection. Commands sent through it aren't counted, as metrics are disabled.
+pub type InstrumentedConnection = ConnectionManager;
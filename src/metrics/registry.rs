@@ -0,0 +1,481 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use prometheus::{
+    exponential_buckets, Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder, TEXT_FORMAT,
+};
+use redis::aio::{ConnectionLike, ConnectionManager};
+use redis::{Arg, Cmd, Pipeline, RedisFuture, Value};
+use sqlx::PgPool;
+use tide::http::Method;
+use tide::utils::async_trait;
+use tide::{Body, Middleware, Next, Request, Response, StatusCode};
+
+use crate::config::Config;
+use crate::routes::{RouteModule, Router};
+use crate::state::State;
+
+/// Maximum number of distinct GraphQL operation names tracked. Operation names are chosen by
+/// clients, so this stops clients from creating an unbounded number of time series.
+const MAX_OPERATION_NAMES: usize = 256;
+/// Maximum length of a GraphQL operation name that is tracked.
+const MAX_OPERATION_NAME_LENGTH: usize = 64;
+/// Route label used for requests to paths no route is mounted at.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Prometheus metrics describing the server. This is cheap to clone, and clones share the same
+/// metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    graphql_requests: IntCounterVec,
+    graphql_request_duration: HistogramVec,
+    graphql_rows_fetched: HistogramVec,
+    graphql_response_bytes: HistogramVec,
+    graphql_deprecated_field_uses: IntCounterVec,
+    result_cache_lookups: IntCounterVec,
+    canary_runs: IntCounterVec,
+    signup_screenings: IntCounterVec,
+    signup_signals: IntCounterVec,
+    retention_purged: IntCounterVec,
+    redis_commands: IntCounterVec,
+    redis_replicated_writes: IntCounterVec,
+    redis_region_latency: GaugeVec,
+    db_query_duration: HistogramVec,
+    db_pool_connections: IntGauge,
+    db_pool_idle_connections: IntGauge,
+    // Operation names that have been given their own time series.
+    operation_names: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Metrics {
+    /// Create and register every metric the server exposes.
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests handled."),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests.",
+            ),
+            &["method", "route"],
+        )?;
+        let graphql_requests = IntCounterVec::new(
+            Opts::new(
+                "graphql_requests_total",
+                "Number of GraphQL requests executed, by whether any errors occurred.",
+            ),
+            &["operation", "result"],
+        )?;
+        let graphql_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "graphql_request_duration_seconds",
+                "Time taken to execute GraphQL requests.",
+            ),
+            &["operation"],
+        )?;
+        let graphql_rows_fetched = HistogramVec::new(
+            HistogramOpts::new(
+                "graphql_rows_fetched",
+                "Number of database rows fetched while executing GraphQL requests.",
+            )
+            .buckets(exponential_buckets(1.0, 4.0, 10)?),
+            &["operation"],
+        )?;
+        let graphql_response_bytes = HistogramVec::new(
+            HistogramOpts::new(
+                "graphql_response_bytes",
+                "Size of serialized GraphQL responses in bytes.",
+            )
+            .buckets(exponential_buckets(256.0, 4.0, 10)?),
+            &["operation"],
+        )?;
+        let graphql_deprecated_field_uses = IntCounterVec::new(
+            Opts::new(
+                "graphql_deprecated_field_uses_total",
+                "Number of GraphQL requests that used a deprecated field.",
+            ),
+            &["field"],
+        )?;
+        let result_cache_lookups = IntCounterVec::new(
+            Opts::new(
+                "result_cache_lookups_total",
+                "Number of expensive reads looked up in the result cache, by whether they were hits.",
+            ),
+            &["query", "result"],
+        )?;
+        let canary_runs = IntCounterVec::new(
+            Opts::new(
+                "canary_runs_total",
+                "Number of new implementations run in the shadow of old ones, by whether they agreed.",
+            ),
+            &["canary", "outcome"],
+        )?;
+        let signup_screenings = IntCounterVec::new(
+            Opts::new(
+                "signup_screenings_total",
+                "Number of signups screened for bots, by what happened to them.",
+            ),
+            &["outcome"],
+        )?;
+        let signup_signals = IntCounterVec::new(
+            Opts::new(
+                "signup_signals_total",
+                "Number of signals suggesting a signup was made by a bot, by signal.",
+            ),
+            &["signal"],
+        )?;
+        let retention_purged = IntCounterVec::new(
+            Opts::new(
+                "retention_purged_total",
+                "Number of rows or sessions purged for being older than their retention period, \
+                by kind of data.",
+            ),
+            &["target"],
+        )?;
+        let redis_commands = IntCounterVec::new(
+            Opts::new("redis_commands_total", "Number of Redis commands sent."),
+            &["command"],
+        )?;
+        let redis_replicated_writes = IntCounterVec::new(
+            Opts::new(
+                "redis_replicated_writes_total",
+                "Number of session writes replicated to the Redis database of another region, by \
+                whether they were written, retried, failed or dropped.",
+            ),
+            &["region", "result"],
+        )?;
+        let redis_region_latency = GaugeVec::new(
+            Opts::new(
+                "redis_region_latency_seconds",
+                "Last measured round trip time to the Redis database of another region.",
+            ),
+            &["region"],
+        )?;
+        let db_query_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "db_query_duration_seconds",
+                "Time taken to run database queries, by query.",
+            ),
+            &["query"],
+        )?;
+        let db_pool_connections = IntGauge::new(
+            "db_pool_connections",
+            "Number of open connections in the Postgres connection pool.",
+        )?;
+        let db_pool_idle_connections = IntGauge::new(
+            "db_pool_idle_connections",
+            "Number of idle connections in the Postgres connection pool.",
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(graphql_requests.clone()))?;
+        registry.register(Box::new(graphql_request_duration.clone()))?;
+        registry.register(Box::new(graphql_rows_fetched.clone()))?;
+        registry.register(Box::new(graphql_response_bytes.clone()))?;
+        registry.register(Box::new(graphql_deprecated_field_uses.clone()))?;
+        registry.register(Box::new(result_cache_lookups.clone()))?;
+        registry.register(Box::new(canary_runs.clone()))?;
+        registry.register(Box::new(signup_screenings.clone()))?;
+        registry.register(Box::new(signup_signals.clone()))?;
+        registry.register(Box::new(retention_purged.clone()))?;
+        registry.register(Box::new(redis_commands.clone()))?;
+        registry.register(Box::new(redis_replicated_writes.clone()))?;
+        registry.register(Box::new(redis_region_latency.clone()))?;
+        registry.register(Box::new(db_query_duration.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(db_pool_idle_connections.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            graphql_requests,
+            graphql_request_duration,
+            graphql_rows_fetched,
+            graphql_response_bytes,
+            graphql_deprecated_field_uses,
+            result_cache_lookups,
+            canary_runs,
+            signup_screenings,
+            signup_signals,
+            retention_purged,
+            redis_commands,
+            redis_replicated_writes,
+            redis_region_latency,
+            db_query_duration,
+            db_pool_connections,
+            db_pool_idle_connections,
+            operation_names: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    /// Record a handled HTTP request. The route should be the path of the route that handled the
+    /// request rather than the requested path, so requests to unknown paths share a time series.
+    pub fn observe_http_request(&self, method: &str, route: &str, status: u16, duration: Duration) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_request_duration
+            .with_label_values(&[method, route])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record an executed GraphQL request with the operation name the client sent, if any.
+    pub fn observe_graphql_request(
+        &self,
+        operation_name: Option<&str>,
+        has_errors: bool,
+        duration: Duration,
+    ) {
+        let operation = self.operation_label(operation_name);
+        let result = if has_errors { "error" } else { "success" };
+        self.graphql_requests
+            .with_label_values(&[&operation, result])
+            .inc();
+        self.graphql_request_duration
+            .with_label_values(&[&operation])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record the number of rows a GraphQL request fetched from the database and the size of its
+    /// response, with the operation name the client sent, if any.
+    pub fn observe_graphql_usage(
+        &self,
+        operation_name: Option<&str>,
+        rows_fetched: u64,
+        response_bytes: usize,
+    ) {
+        let operation = self.operation_label(operation_name);
+        self.graphql_rows_fetched
+            .with_label_values(&[&operation])
+            .observe(rows_fetched as f64);
+        self.graphql_response_bytes
+            .with_label_values(&[&operation])
+            .observe(response_bytes as f64);
+    }
+
+    /// Record a GraphQL request that used a deprecated field. Deprecated fields are listed by the
+    /// server, so they can be used as labels without limiting them.
+    pub fn observe_deprecated_field_use(&self, field: &str) {
+        self.graphql_deprecated_field_uses
+            .with_label_values(&[field])
+            .inc();
+    }
+
+    /// Record a lookup of an expensive read in the result cache, by whether the result was cached.
+    pub fn observe_result_cache_lookup(&self, query: &str, is_hit: bool) {
+        let result = if is_hit { "hit" } else { "miss" };
+        self.result_cache_lookups
+            .with_label_values(&[query, result])
+            .inc();
+    }
+
+    /// Record a run of a canary, by whether it found the same result as the implementation it
+    /// replaces.
+    pub fn observe_canary(&self, canary: &str, outcome: &str) {
+        self.canary_runs.with_label_values(&[canary, outcome]).inc();
+    }
+
+    /// Record a screened signup by what happened to it, along with the signals it was screened
+    /// with.
+    pub fn observe_signup_screening(&self, outcome: &str, signals: &[&str]) {
+        self.signup_screenings.with_label_values(&[outcome]).inc();
+        for signal in signals {
+            self.signup_signals.with_label_values(&[signal]).inc();
+        }
+    }
+
+    /// Record data purged for being older than its retention period, by kind of data.
+    pub fn observe_retention_purge(&self, target: &str, count: u64) {
+        self.retention_purged
+            .with_label_values(&[target])
+            .inc_by(count);
+    }
+
+    /// Record how long a database query took. Queries are named by the executor, so names can be
+    /// used as labels without limiting them.
+    pub fn observe_db_query(&self, query: &str, duration: Duration) {
+        self.db_query_duration
+            .with_label_values(&[query])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record a session write replicated to the Redis database of another region, by whether it
+    /// was written, retried, failed or dropped. Regions are configured, so they can be used as labels
+    /// without limiting them.
+    pub fn observe_redis_replication(&self, region: &str, result: &str) {
+        self.redis_replicated_writes
+            .with_label_values(&[region, result])
+            .inc();
+    }
+
+    /// Record the round trip time to the Redis database of another region.
+    pub fn observe_redis_region_latency(&self, region: &str, latency: Duration) {
+        self.redis_region_latency
+            .with_label_values(&[region])
+            .set(latency.as_secs_f64());
+    }
+
+    /// Get the label a GraphQL operation is recorded under. Once too many distinct operation names
+    /// have been seen, any new names are recorded as "other".
+    fn operation_label(&self, operation_name: Option<&str>) -> String {
+        let name = match operation_name {
+            Some(name) if is_valid_operation_name(name) => name,
+            Some(_) => return "invalid".into(),
+            None => return "anonymous".into(),
+        };
+
+        let mut names = self
+            .operation_names
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if names.contains(name) || names.len() < MAX_OPERATION_NAMES {
+            names.insert(name.into());
+            name.into()
+        } else {
+            "other".into()
+        }
+    }
+
+    /// Wrap a Redis connection so every command sent through it is counted.
+    pub fn instrument(&self, connection: ConnectionManager) -> InstrumentedConnection {
+        InstrumentedConnection {
+            connection,
+            commands: self.redis_commands.clone(),
+        }
+    }
+
+    /// Render every metric in the Prometheus text format. Database pool utilization is sampled
+    /// when this is called.
+    pub fn render(&self, db: &PgPool) -> Result<String> {
+        self.db_pool_connections.set(db.size().into());
+        self.db_pool_idle_connections.set(db.num_idle() as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+/// Specifies if a GraphQL operation name is valid and short enough to be used as a label.
+fn is_valid_operation_name(name: &str) -> bool {
+    let mut characters = name.chars();
+    name.len() <= MAX_OPERATION_NAME_LENGTH
+        && characters
+            .next()
+            .is_some_and(|first| first == '_' || first.is_ascii_alphabetic())
+        && characters.all(|character| character == '_' || character.is_ascii_alphanumeric())
+}
+
+/// A Redis connection that counts every command sent through it. This can be used anywhere a
+/// Redis connection manager can.
+#[derive(Clone)]
+pub struct InstrumentedConnection {
+    connection: ConnectionManager,
+    commands: IntCounterVec,
+}
+
+impl InstrumentedConnection {
+    /// Count a command that is about to be sent.
+    fn count(&self, command: &Cmd) {
+        let name = match command.args_iter().next() {
+            Some(Arg::Simple(name)) => String::from_utf8_lossy(name).to_uppercase(),
+            _ => "UNKNOWN".into(),
+        };
+        self.commands.with_label_values(&[&name]).inc();
+    }
+}
+
+impl ConnectionLike for InstrumentedConnection {
+    fn req_packed_command<'a>(&'a mut self, command: &'a Cmd) -> RedisFuture<'a, Value> {
+        self.count(command);
+        self.connection.req_packed_command(command)
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        pipeline: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        for command in pipeline.cmd_iter() {
+            self.count(command);
+        }
+        self.connection.req_packed_commands(pipeline, offset, count)
+    }
+
+    fn get_db(&self) -> i64 {
+        self.connection.get_db()
+    }
+}
+
+/// Middleware recording the count, status and duration of every HTTP request.
+pub struct MetricsMiddleware {
+    routes: HashSet<&'static str>,
+}
+
+impl MetricsMiddleware {
+    /// Create metrics middleware for a server with routes mounted at the specified paths.
+    pub fn new(routes: impl IntoIterator<Item = &'static str>) -> Self {
+        MetricsMiddleware {
+            routes: routes.into_iter().collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware<State> for MetricsMiddleware {
+    async fn handle(&self, request: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let metrics = request.state().metrics.clone();
+        let method = request.method().to_string();
+        let route = self
+            .routes
+            .get(request.url().path())
+            .copied()
+            .unwrap_or(UNMATCHED_ROUTE);
+
+        let start = Instant::now();
+        let response = next.run(request).await;
+        metrics.observe_http_request(&method, route, response.status().into(), start.elapsed());
+
+        Ok(response)
+    }
+}
+
+/// Route module exposing server metrics in the Prometheus text format. This is only enabled if
+/// metrics are enabled in the configuration.
+pub struct MetricsModule;
+
+impl RouteModule for MetricsModule {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        config.metrics_enabled
+    }
+
+    fn mount(&self, router: &mut dyn Router) {
+        router.route(Method::Get, "/metrics", Arc::new(metrics));
+    }
+}
+
+/// Report server metrics to a Prometheus scraper.
+async fn metrics(request: Request<State>) -> tide::Result {
+    let state = request.state();
+    let response = Response::builder(StatusCode::Ok)
+        .content_type(TEXT_FORMAT)
+        .body(Body::from_string(state.metrics.render(&state.db)?));
+
+    Ok(response.build())
+}
@@ -0,0 +1,656 @@
+use std::io::{self, Write};
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+
+use async_std::future::timeout;
+use brotli::enc::BrotliEncoderParams;
+use brotli::CompressorWriter;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::FutureExt;
+use serde_json::{json, Value};
+use tide::http::headers::HOST;
+use tide::http::{mime, Method};
+use tide::utils::async_trait;
+use tide::{log, Body, Middleware, Next, Request, Response, StatusCode};
+
+use crate::config::Config;
+use crate::deprecations::{DEPRECATION_HEADER, SUNSET_HEADER};
+use crate::domains::DomainRegistry;
+use crate::graphql::{request_id, ETAG_HEADER, REQUEST_ID_HEADER};
+use crate::logging;
+use crate::state::State;
+use crate::usage::QUOTA_WARNING_HEADER;
+
+/// Message returned in place of the details of server errors, which may be sensitive.
+pub const UNKNOWN_ERROR_MESSAGE: &str = "An unknown error occurred.";
+/// Message returned when a request takes too long to handle.
+pub const TIMEOUT_ERROR_MESSAGE: &str = "The request timed out.";
+/// Message returned for requests to unknown routes.
+pub const NOT_FOUND_ERROR_MESSAGE: &str = "The requested route does not exist.";
+/// Message returned for requests using a method a route does not support.
+pub const METHOD_NOT_ALLOWED_ERROR_MESSAGE: &str =
+    "The requested route does not support this method.";
+/// Message returned for CORS preflight requests from origins that aren't allowed.
+pub const ORIGIN_NOT_ALLOWED_ERROR_MESSAGE: &str =
+    "The origin is not allowed to make cross-origin requests.";
+
+/// Header browsers send the origin of cross-origin requests in.
+pub const ORIGIN_HEADER: &str = "Origin";
+/// Header browsers send the method of the request a CORS preflight request is checking in.
+pub const PREFLIGHT_METHOD_HEADER: &str = "Access-Control-Request-Method";
+/// Header clients list the content encodings they accept in.
+pub const ACCEPT_ENCODING_HEADER: &str = "Accept-Encoding";
+/// Header naming the encoding a response body is compressed with.
+pub const CONTENT_ENCODING_HEADER: &str = "Content-Encoding";
+/// Header listing the request headers a response depends on, for caches.
+pub const VARY_HEADER: &str = "Vary";
+/// Brotli quality responses are compressed with, from 0 to 11. Higher qualities compress
+/// slightly better but are much slower, which isn't worth it for responses compressed on the fly.
+const BROTLI_QUALITY: i32 = 5;
+/// Size of the buffer Brotli compresses streamed bodies through.
+const BROTLI_BUFFER_BYTES: usize = 4096;
+/// How long browsers can cache the result of a CORS preflight request, in seconds.
+const CORS_MAX_AGE_SECONDS: u32 = 600;
+/// Response headers browsers let cross-origin clients read, besides the standard ones.
+const CORS_EXPOSED_HEADERS: &[&str] = &[
+    REQUEST_ID_HEADER,
+    QUOTA_WARNING_HEADER,
+    DEPRECATION_HEADER,
+    SUNSET_HEADER,
+    ETAG_HEADER,
+];
+
+/// Build a GraphQL-shaped error body containing a single error with a stable error code.
+pub fn error_body(message: &str, code: &str) -> Value {
+    json!({
+        "errors": [{
+            "message": message,
+            "extensions": { "code": code },
+        }],
+    })
+}
+
+/// Get the stable error code reported to clients for an HTTP error status.
+pub fn error_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BadRequest => "bad-request",
+        StatusCode::Unauthorized => "unauthorized",
+        StatusCode::Forbidden => "forbidden",
+        StatusCode::NotFound => "not-found",
+        StatusCode::MethodNotAllowed => "method-not-allowed",
+        StatusCode::RequestTimeout | StatusCode::GatewayTimeout => "timeout",
+        StatusCode::PayloadTooLarge => "payload-too-large",
+        StatusCode::UnsupportedMediaType => "unsupported-media-type",
+        StatusCode::UnprocessableEntity => "unprocessable-entity",
+        StatusCode::TooManyRequests => "too-many-requests",
+        _ => "unknown-error",
+    }
+}
+
+/// Build a response with a GraphQL-shaped error body.
+fn error_response(status: StatusCode, message: &str) -> Response {
+    Response::builder(status)
+        .content_type(mime::JSON)
+        .body(Body::from(error_body(message, error_code(status))))
+        .build()
+}
+
+/// Middleware converting every error the server produces into a GraphQL-shaped
+/// `{ "errors": [...] }` JSON response. This covers errors returned by endpoints, requests to
+/// unknown routes, endpoints that panic and requests that take too long to handle.
+pub struct ErrorMiddleware {
+    timeout: Duration,
+}
+
+impl ErrorMiddleware {
+    /// Create error handling middleware that aborts requests taking longer than a specified
+    /// number of seconds.
+    pub fn new(timeout_seconds: u32) -> Self {
+        ErrorMiddleware {
+            timeout: Duration::from_secs(timeout_seconds.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware<State> for ErrorMiddleware {
+    async fn handle(&self, request: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let handled = AssertUnwindSafe(next.run(request)).catch_unwind();
+        let response = match timeout(self.timeout, handled).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => {
+                log::error!("Panicked while handling a request.");
+                return Ok(error_response(
+                    StatusCode::InternalServerError,
+                    UNKNOWN_ERROR_MESSAGE,
+                ));
+            }
+            Err(_) => {
+                log::warn!("Timed out while handling a request.");
+                return Ok(error_response(
+                    StatusCode::GatewayTimeout,
+                    TIMEOUT_ERROR_MESSAGE,
+                ));
+            }
+        };
+
+        let status = response.status();
+        // Only empty responses are replaced, as tide produces these for unknown routes and methods.
+        let is_empty = response.is_empty() == Some(true);
+        match response.error() {
+            // Hide the details of server errors from clients.
+            Some(error) if status.is_server_error() => {
+                log::error!("{}", error);
+                Ok(error_response(status, UNKNOWN_ERROR_MESSAGE))
+            }
+            Some(error) => Ok(error_response(status, &error.to_string())),
+            None if is_empty && status == StatusCode::NotFound => {
+                Ok(error_response(status, NOT_FOUND_ERROR_MESSAGE))
+            }
+            None if is_empty && status == StatusCode::MethodNotAllowed => {
+                Ok(error_response(status, METHOD_NOT_ALLOWED_ERROR_MESSAGE))
+            }
+            None => Ok(response),
+        }
+    }
+}
+
+/// Middleware assigning every request an ID and logging it once it has been handled. The ID is
+/// taken from the request's "X-Request-Id" header if it has a suitable one, and generated
+/// otherwise. The header is replaced with the ID, so endpoints use the same one, and the ID is
+/// included in every line logged while handling the request and returned in the response's
+/// "X-Request-Id" header. Responses are also counted, so operators can be alerted about spikes in
+/// server errors.
+pub struct RequestLogMiddleware;
+
+#[async_trait]
+impl Middleware<State> for RequestLogMiddleware {
+    async fn handle(&self, mut request: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let request_id = request_id(
+            request
+                .header(REQUEST_ID_HEADER)
+                .map(|values| values.as_str()),
+        );
+        request.insert_header(REQUEST_ID_HEADER, request_id.as_str());
+        let method = request.method().to_string();
+        let path = request.url().path().to_string();
+        let alert_counters = request.state().alert_counters.clone();
+
+        let start = Instant::now();
+        logging::start_request(request_id.clone());
+        let mut response = next.run(request).await;
+        let status = response.status().into();
+        logging::log_request(&method, &path, status, start.elapsed());
+        alert_counters.record_response(status);
+        logging::end_request();
+
+        response.insert_header(REQUEST_ID_HEADER, request_id);
+        Ok(response)
+    }
+}
+
+/// Cross-origin resource sharing policy, deciding which websites browsers let call the API.
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    allowed_origins: Vec<String>,
+    allowed_methods: String,
+    allowed_headers: String,
+    allow_credentials: bool,
+    domains: Option<DomainRegistry>,
+}
+
+impl CorsPolicy {
+    /// Create the CORS policy described by the server configuration, or none if no origins are
+    /// allowed to make cross-origin requests. When organization domains are enabled, requests sent
+    /// to an organization's verified hostnames are only allowed from its verified hostnames.
+    pub fn new(config: &Config, domains: &DomainRegistry) -> Option<Self> {
+        if config.cors_allowed_origins.is_empty() && !config.organization_domains_enabled {
+            return None;
+        }
+
+        Some(CorsPolicy {
+            allowed_origins: config.cors_allowed_origins.clone(),
+            allowed_methods: config.cors_allowed_methods.join(", "),
+            allowed_headers: config.cors_allowed_headers.join(", "),
+            allow_credentials: config.cors_allow_credentials,
+            domains: config.organization_domains_enabled.then(|| domains.clone()),
+        })
+    }
+
+    /// Get the headers to add to the response to a request from an origin, sent to the host in
+    /// its "Host" header, or none if the origin isn't allowed. Preflight requests also get headers
+    /// describing which methods and headers the actual request can use. Responses to allowed
+    /// origins should also have "Origin" appended to their "Vary" header, keeping any other
+    /// request headers it already lists.
+    pub fn headers(
+        &self,
+        host: Option<&str>,
+        origin: &str,
+        is_preflight: bool,
+    ) -> Option<Vec<(&'static str, String)>> {
+        let organization_id = self
+            .domains
+            .as_ref()
+            .and_then(|domains| Some((domains, domains.organization(host)?)));
+        let allowed_origin = match organization_id {
+            // Other organizations' pages and the configured origins can't call an organization's
+            // hostnames, so they can't make requests carrying its users' host-only cookies.
+            Some((domains, organization_id)) => domains
+                .is_organization_origin(organization_id, origin)
+                .then(|| origin.to_string())?,
+            None => {
+                let is_listed = self.allowed_origins.iter().any(|allowed| allowed == origin);
+                let is_wildcard = self.allowed_origins.iter().any(|allowed| allowed == "*");
+                // The configuration doesn't allow the wildcard with credentials, so it is always
+                // sent as a literal "*" rather than echoing the origin back.
+                match (is_listed, is_wildcard) {
+                    (true, _) => origin.to_string(),
+                    (false, true) => "*".to_string(),
+                    (false, false) => return None,
+                }
+            }
+        };
+
+        let mut headers = vec![("Access-Control-Allow-Origin", allowed_origin)];
+        if self.allow_credentials {
+            headers.push(("Access-Control-Allow-Credentials", "true".to_string()));
+        }
+        if is_preflight {
+            headers.push(("Access-Control-Allow-Methods", self.allowed_methods.clone()));
+            headers.push(("Access-Control-Allow-Headers", self.allowed_headers.clone()));
+            headers.push(("Access-Control-Max-Age", CORS_MAX_AGE_SECONDS.to_string()));
+        } else {
+            headers.push((
+                "Access-Control-Expose-Headers",
+                CORS_EXPOSED_HEADERS.join(", "),
+            ));
+        }
+
+        Some(headers)
+    }
+}
+
+/// Middleware letting browsers call the API from the origins allowed by a CORS policy. Preflight
+/// requests are answered directly, for every route, and other requests from allowed origins have
+/// CORS headers added to their responses, including error responses. Requests from other origins
+/// are handled without CORS headers, so browsers don't let the calling page read the response.
+pub struct CorsMiddleware {
+    policy: CorsPolicy,
+}
+
+impl CorsMiddleware {
+    /// Create CORS middleware enforcing a policy.
+    pub fn new(policy: CorsPolicy) -> Self {
+        CorsMiddleware { policy }
+    }
+}
+
+#[async_trait]
+impl Middleware<State> for CorsMiddleware {
+    async fn handle(&self, request: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let origin = match request.header(ORIGIN_HEADER) {
+            Some(origin) => origin.last().as_str().to_string(),
+            None => return Ok(next.run(request).await),
+        };
+        let is_preflight = request.method() == Method::Options
+            && request.header(PREFLIGHT_METHOD_HEADER).is_some();
+
+        let host = request.header(HOST).map(|values| values.as_str());
+        let headers = self.policy.headers(host, &origin, is_preflight);
+        let mut response = match &headers {
+            Some(_) if is_preflight => Response::new(StatusCode::NoContent),
+            None if is_preflight => {
+                return Ok(error_response(
+                    StatusCode::Forbidden,
+                    ORIGIN_NOT_ALLOWED_ERROR_MESSAGE,
+                ))
+            }
+            _ => next.run(request).await,
+        };
+        if let Some(headers) = headers {
+            for (name, value) in headers {
+                response.insert_header(name, value);
+            }
+            response.append_header(VARY_HEADER, ORIGIN_HEADER);
+        }
+
+        Ok(response)
+    }
+}
+
+/// A content encoding response bodies can be compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Brotli,
+    Gzip,
+}
+
+impl ContentEncoding {
+    /// Get the name of the encoding used in the "Accept-Encoding" and "Content-Encoding" headers.
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Gzip => "gzip",
+        }
+    }
+
+    /// Compress a response body with the encoding.
+    pub fn compress(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        match self {
+            ContentEncoding::Brotli => {
+                let params = BrotliEncoderParams {
+                    quality: BROTLI_QUALITY,
+                    ..BrotliEncoderParams::default()
+                };
+                brotli::BrotliCompress(&mut &body[..], &mut compressed, &params)?;
+            }
+            ContentEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(compressed, Compression::default());
+                encoder.write_all(body)?;
+                compressed = encoder.finish()?;
+            }
+        }
+
+        Ok(compressed)
+    }
+
+    /// Compress everything a function writes with the encoding, writing the compressed bytes to a
+    /// writer as they are produced, so large bodies don't have to be held in memory to be
+    /// compressed. This will return the writer once the compressed stream is finished.
+    pub fn compress_into<W, F>(self, writer: W, write: F) -> io::Result<W>
+    where
+        W: Write,
+        F: FnOnce(&mut dyn Write) -> io::Result<()>,
+    {
+        match self {
+            ContentEncoding::Brotli => {
+                let params = BrotliEncoderParams {
+                    quality: BROTLI_QUALITY,
+                    ..BrotliEncoderParams::default()
+                };
+                let mut encoder =
+                    CompressorWriter::with_params(writer, BROTLI_BUFFER_BYTES, &params);
+                write(&mut encoder)?;
+                Ok(encoder.into_inner())
+            }
+            ContentEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(writer, Compression::default());
+                write(&mut encoder)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Response compression policy, deciding which responses are compressed and how.
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    min_bytes: usize,
+}
+
+impl CompressionPolicy {
+    /// Create the compression policy described by the server configuration, or none if responses
+    /// aren't compressed.
+    pub fn new(config: &Config) -> Option<Self> {
+        if !config.response_compression_enabled {
+            return None;
+        }
+
+        Some(CompressionPolicy {
+            min_bytes: config.response_compression_min_bytes,
+        })
+    }
+
+    /// Specifies if a response can be compressed, from its content type, whether it is already
+    /// encoded and the length of its body. Only text and JSON bodies of a known length of at
+    /// least the minimum size are compressed. Bodies streamed without a known length are sent as
+    /// they are.
+    pub fn applies(
+        &self,
+        content_type: Option<&str>,
+        is_encoded: bool,
+        len: Option<usize>,
+    ) -> bool {
+        let is_compressible = content_type.is_some_and(|content_type| {
+            content_type.starts_with("text/") || content_type.ends_with("json")
+        });
+        let is_large_enough = len.is_some_and(|len| len > 0 && len >= self.min_bytes);
+
+        is_compressible && !is_encoded && is_large_enough
+    }
+
+    /// Choose the encoding to compress a response with from the "Accept-Encoding" header of the
+    /// request, or none if the client doesn't accept a supported encoding. The encoding the
+    /// client prefers most is chosen, and Brotli is preferred over gzip when the client has no
+    /// preference.
+    pub fn negotiate(&self, accept_encoding: Option<&str>) -> Option<ContentEncoding> {
+        let (mut brotli, mut gzip, mut any) = (None, None, None);
+        for entry in accept_encoding?.split(',') {
+            let mut parts = entry.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = parts
+                .find_map(|parameter| parameter.strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.parse::<f32>().ok())
+                .unwrap_or(0.0);
+            match name.as_str() {
+                "br" => brotli = Some(quality),
+                "gzip" | "x-gzip" => gzip = Some(quality),
+                "*" => any = Some(quality),
+                _ => {}
+            }
+        }
+
+        // A wildcard covers the encodings the client didn't list.
+        let brotli = brotli.or(any).unwrap_or(0.0);
+        let gzip = gzip.or(any).unwrap_or(0.0);
+        if brotli > 0.0 && brotli >= gzip {
+            Some(ContentEncoding::Brotli)
+        } else if gzip > 0.0 {
+            Some(ContentEncoding::Gzip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Make an entity tag weak, since a compressed body isn't byte-for-byte identical to the body the
+/// tag was computed from. Weak tags still match "If-None-Match" headers.
+pub fn weak_etag(etag: &str) -> String {
+    if etag.starts_with("W/") {
+        etag.to_string()
+    } else {
+        format!("W/{}", etag)
+    }
+}
+
+/// Get the encodings a client accepts from the "Accept-Encoding" headers of its request, joined
+/// into one list.
+pub fn accept_encoding(request: &Request<State>) -> Option<String> {
+    request.header(ACCEPT_ENCODING_HEADER).map(|values| {
+        values
+            .iter()
+            .map(|value| value.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    })
+}
+
+/// Middleware compressing response bodies with Brotli or gzip, following a compression policy
+/// and the encodings the client accepts. Responses that could be compressed get a
+/// "Vary: Accept-Encoding" header, so caches don't serve compressed bodies to clients that don't
+/// accept them, and bodies that don't shrink are sent uncompressed.
+pub struct CompressionMiddleware {
+    policy: CompressionPolicy,
+}
+
+impl CompressionMiddleware {
+    /// Create compression middleware following a policy.
+    pub fn new(policy: CompressionPolicy) -> Self {
+        CompressionMiddleware { policy }
+    }
+}
+
+#[async_trait]
+impl Middleware<State> for CompressionMiddleware {
+    async fn handle(&self, request: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let accept_encoding = accept_encoding(&request);
+
+        let mut response = next.run(request).await;
+        let content_type = response.content_type();
+        let is_encoded = response.header(CONTENT_ENCODING_HEADER).is_some();
+        let essence = content_type.as_ref().map(|mime| mime.essence());
+        if !self.policy.applies(essence, is_encoded, response.len()) {
+            return Ok(response);
+        }
+        response.append_header(VARY_HEADER, ACCEPT_ENCODING_HEADER);
+        let encoding = match self.policy.negotiate(accept_encoding.as_deref()) {
+            Some(encoding) => encoding,
+            None => return Ok(response),
+        };
+
+        let body = response.take_body().into_bytes().await?;
+        let compressed = match encoding.compress(&body) {
+            Ok(compressed) if compressed.len() < body.len() => compressed,
+            _ => {
+                response.set_body(body);
+                return Ok(response);
+            }
+        };
+        response.set_body(compressed);
+        response.insert_header(CONTENT_ENCODING_HEADER, encoding.name());
+        if let Some(etag) = response.header(ETAG_HEADER) {
+            let etag = weak_etag(etag.last().as_str());
+            response.insert_header(ETAG_HEADER, etag);
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn policy(allowed_origins: &[&str], allow_credentials: bool) -> CorsPolicy {
+        CorsPolicy {
+            allowed_origins: allowed_origins
+                .iter()
+                .map(|origin| origin.to_string())
+                .collect(),
+            allowed_methods: "GET, POST".into(),
+            allowed_headers: "Authorization, Content-Type".into(),
+            allow_credentials,
+            domains: None,
+        }
+    }
+
+    fn header<'a>(headers: &'a [(&'static str, String)], name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(header, _)| *header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn listed_origins_are_echoed_back() {
+        let policy = policy(&["https://app.example.com"], true);
+        let headers = policy
+            .headers(None, "https://app.example.com", false)
+            .unwrap();
+        let origin = header(&headers, "Access-Control-Allow-Origin");
+        assert_eq!(origin, Some("https://app.example.com"));
+        let credentials = header(&headers, "Access-Control-Allow-Credentials");
+        assert_eq!(credentials, Some("true"));
+        let exposed = header(&headers, "Access-Control-Expose-Headers");
+        assert_eq!(exposed, Some(CORS_EXPOSED_HEADERS.join(", ").as_str()));
+        assert_eq!(header(&headers, "Access-Control-Allow-Methods"), None);
+    }
+
+    #[test]
+    fn other_origins_get_no_headers() {
+        let policy = policy(&["https://app.example.com"], false);
+        assert!(policy
+            .headers(None, "https://evil.example.com", false)
+            .is_none());
+        assert!(policy
+            .headers(None, "https://app.example.com.evil.com", true)
+            .is_none());
+    }
+
+    #[test]
+    fn organization_hostnames_only_allow_their_organization() {
+        let domains = DomainRegistry::default();
+        let organization_id = Uuid::new_v4();
+        domains.replace(vec![
+            ("api.example.com".to_string(), organization_id),
+            ("app.example.com".to_string(), organization_id),
+            ("other.example.org".to_string(), Uuid::new_v4()),
+        ]);
+        let policy = CorsPolicy {
+            domains: Some(domains),
+            ..policy(&["*"], false)
+        };
+
+        let host = Some("api.example.com:443");
+        let headers = policy
+            .headers(host, "https://app.example.com", false)
+            .unwrap();
+        let origin = header(&headers, "Access-Control-Allow-Origin");
+        assert_eq!(origin, Some("https://app.example.com"));
+        assert!(policy
+            .headers(host, "http://app.example.com", false)
+            .is_none());
+        assert!(policy
+            .headers(host, "https://other.example.org", true)
+            .is_none());
+        assert!(policy
+            .headers(host, "https://anywhere.com", false)
+            .is_none());
+
+        // Requests sent to the server's own hostname fall back to the configured origins.
+        let headers = policy.headers(Some("localhost:8080"), "https://anywhere.com", false);
+        assert_eq!(
+            header(&headers.unwrap(), "Access-Control-Allow-Origin"),
+            Some("*")
+        );
+    }
+
+    #[test]
+    fn the_wildcard_allows_any_origin_literally() {
+        let policy = policy(&["*"], false);
+        let headers = policy
+            .headers(None, "https://anywhere.example.com", false)
+            .unwrap();
+        assert_eq!(header(&headers, "Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(header(&headers, "Access-Control-Allow-Credentials"), None);
+    }
+
+    #[test]
+    fn preflight_requests_get_the_allowed_methods_and_headers() {
+        let policy = policy(&["https://app.example.com"], false);
+        let headers = policy
+            .headers(None, "https://app.example.com", true)
+            .unwrap();
+        let methods = header(&headers, "Access-Control-Allow-Methods");
+        assert_eq!(methods, Some("GET, POST"));
+        let allowed = header(&headers, "Access-Control-Allow-Headers");
+        assert_eq!(allowed, Some("Authorization, Content-Type"));
+        let max_age = CORS_MAX_AGE_SECONDS.to_string();
+        let max_age = Some(max_age.as_str());
+        assert_eq!(header(&headers, "Access-Control-Max-Age"), max_age);
+        assert_eq!(header(&headers, "Access-Control-Expose-Headers"), None);
+    }
+
+    #[test]
+    fn vary_is_left_to_callers_to_append() {
+        let policy = policy(&["https://app.example.com"], false);
+        let headers = policy
+            .headers(None, "https://app.example.com", false)
+            .unwrap();
+        assert_eq!(header(&headers, VARY_HEADER), None);
+    }
+}
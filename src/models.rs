@@ -1,8 +1,27 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
-use juniper::graphql_object;
+use juniper::{graphql_object, FieldResult, IntoFieldError};
+use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
+use crate::announcements::{Audience, Severity};
+use crate::audit::{AuditChange, AuditEventKind, AuditMetadataEntry};
+use crate::avatars::{self, AvatarFormat};
+use crate::billing::{is_active_status, Plan};
+use crate::context::Context;
+use crate::domains;
+use crate::email::settings::{EmailBranding, DEFAULT_BRAND_COLOR};
+use crate::feedback::{FeedbackCategory, FeedbackMetadataEntry, FeedbackStatus};
+use crate::organizations::OrganizationRole;
+use crate::pending_actions::PendingActionKind;
+use crate::policy;
+use crate::promo_codes::PromoCodeKind;
+use crate::security_events::SecurityEventKind;
+use crate::webhooks::WebhookProvider;
+
 /// Represents a user in the "users" table.
 #[derive(Debug, Clone, FromRow)]
 pub struct User {
@@ -23,10 +42,30 @@ pub struct User {
     /// verify a password is correct without actually storing the plain-text password in the
     /// database.
     pub password_hash: String,
+    /// The path of the user's avatar in file storage. This will be none if the user hasn't
+    /// uploaded an avatar.
+    pub avatar_path: Option<String>,
+    /// The sizes of the square renditions stored for the user's avatar, in pixels. This will be
+    /// none until the avatar has been processed, and empty if it couldn't be.
+    pub avatar_sizes: Option<Vec<i32>>,
+    /// Timestamp specifying when the user was deactivated. Deactivated users can't log in. This
+    /// will be none if the user is active.
+    pub deactivated_at: Option<DateTime<Utc>>,
+    /// Timestamp specifying when the user was soft deleted. Soft deleted users are left out of
+    /// lookups unless they are asked for. This will be none if the user hasn't been deleted.
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Timestamp specifying when an admin required the user to reset their password. The user
+    /// can't log in with their password until they reset it. This will be none if no reset is
+    /// required.
+    pub password_reset_required_at: Option<DateTime<Utc>>,
+    /// Timestamp specifying when an admin placed the user under a legal hold. The user and their
+    /// events are exempt from retention purges and permanent deletion while held. This will be
+    /// none if the user isn't held.
+    pub legal_hold_at: Option<DateTime<Utc>>,
 }
 
 /// Defines user fields exposed over GraphQL.
-#[graphql_object(description = "Information about a user.")]
+#[graphql_object(context = Context, description = "Information about a user.")]
 impl User {
     #[graphql(description = "The unique ID of the user.")]
     pub fn id(&self) -> &Uuid {
@@ -48,16 +87,1247 @@ impl User {
         &self.username
     }
 
-    #[graphql(description = "The user's email address.")]
+    #[graphql(
+        description = "The user's email address. This will be null for users other than the user
+        themselves and admins."
+    )]
+    pub async fn email(&self, context: &Context) -> FieldResult<Option<&str>> {
+        let access = context.authorize_field(policy::USER_EMAIL, self.id).await?;
+        access
+            .apply(Some(self.email.as_str()))
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    #[graphql(
+        description = "Date when the user's email address was last verified. This will be null
+        if the email has not been verified yet. Only the user themselves and admins can see this."
+    )]
+    pub async fn email_verified_at(&self, context: &Context) -> FieldResult<Option<DateTime<Utc>>> {
+        let access = context
+            .authorize_field(policy::USER_EMAIL_VERIFIED_AT, self.id)
+            .await?;
+        access
+            .apply(self.email_verified_at)
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    #[graphql(
+        description = "A URL the user's avatar can be downloaded from until it expires, as a square
+        image. This will be null if the user hasn't uploaded an avatar, or if it hasn't been
+        processed yet.",
+        arguments(
+            size(
+                description = "The size in pixels the avatar will be shown at. The smallest
+                rendition at least this large is picked, or the largest if none is. By default,
+                the largest rendition is picked."
+            ),
+            format(description = "The format of the rendition. Defaults to WebP.")
+        )
+    )]
+    pub fn avatar_url(
+        &self,
+        context: &Context,
+        size: Option<i32>,
+        format: Option<AvatarFormat>,
+    ) -> Option<String> {
+        let path = self.avatar_path.as_ref()?;
+        let size = avatars::closest_size(self.avatar_sizes.as_deref()?, size)?;
+        let format = format.unwrap_or(AvatarFormat::Webp);
+        context
+            .executor()
+            .files()
+            .url(&avatars::rendition_path(path, size, format))
+    }
+
+    #[graphql(
+        description = "Date when the user was deactivated. Deactivated users can't log in. This will
+        be null if the user is active."
+    )]
+    pub fn deactivated_at(&self) -> &Option<DateTime<Utc>> {
+        &self.deactivated_at
+    }
+
+    #[graphql(
+        description = "Date when the user was deleted. Deleted users are only returned to admins
+        that ask for them. This will be null if the user hasn't been deleted."
+    )]
+    pub fn deleted_at(&self) -> &Option<DateTime<Utc>> {
+        &self.deleted_at
+    }
+
+    #[graphql(
+        description = "Date when an admin required the user to reset their password. The user can't
+        log in with their password until they reset it. This will be null if no reset is required.
+        Only the user themselves and admins can see this."
+    )]
+    pub async fn password_reset_required_at(
+        &self,
+        context: &Context,
+    ) -> FieldResult<Option<DateTime<Utc>>> {
+        let access = context
+            .authorize_field(policy::USER_PASSWORD_RESET_REQUIRED_AT, self.id)
+            .await?;
+        access
+            .apply(self.password_reset_required_at)
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    #[graphql(
+        description = "Date when an admin placed the user under a legal hold, exempting them from
+        retention purges and deletion. This will be null if the user isn't held, and is always null
+        for users that aren't admins."
+    )]
+    pub async fn legal_hold_at(&self, context: &Context) -> FieldResult<Option<DateTime<Utc>>> {
+        let access = context
+            .authorize_field(policy::USER_LEGAL_HOLD_AT, self.id)
+            .await?;
+        access
+            .apply(self.legal_hold_at)
+            .map_err(IntoFieldError::into_field_error)
+    }
+}
+
+/// Represents a user's subscription to a paid plan in the "subscriptions" table. This is kept in
+/// sync with the subscription in Stripe through webhook events.
+#[derive(Debug, Clone, FromRow)]
+pub struct Subscription {
+    /// The ID of the user the subscription belongs to.
+    pub user_id: Uuid,
+    /// Auto-generated timestamp specifying when this subscription was created.
+    pub created_at: DateTime<Utc>,
+    /// Auto-generated timestamp specifying when this subscription was last updated.
+    pub updated_at: DateTime<Utc>,
+    /// The ID of the Stripe customer paying for the subscription.
+    pub stripe_customer_id: String,
+    /// The ID of the subscription in Stripe.
+    pub stripe_subscription_id: String,
+    /// The plan subscribed to.
+    pub plan: String,
+    /// The status of the subscription in Stripe, e.g. "active" or "canceled".
+    pub status: String,
+    /// Timestamp specifying when the subscription's current billing period ends.
+    pub current_period_end: DateTime<Utc>,
+    /// Specifies if the subscription will be canceled at the end of the current billing period.
+    pub cancel_at_period_end: bool,
+}
+
+/// Defines subscription fields exposed over GraphQL.
+#[graphql_object(description = "A user's subscription to a paid plan.")]
+impl Subscription {
+    #[graphql(description = "The plan subscribed to.")]
+    pub fn plan(&self) -> FieldResult<Plan> {
+        Ok(self.plan.parse()?)
+    }
+
+    #[graphql(
+        description = "The status of the subscription, e.g. \"active\", \"past_due\" or
+        \"canceled\"."
+    )]
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    #[graphql(
+        description = "Specifies if the subscription currently entitles the user to its plan."
+    )]
+    pub fn is_active(&self) -> bool {
+        is_active_status(&self.status)
+    }
+
+    #[graphql(description = "Date when the current billing period ends.")]
+    pub fn current_period_end(&self) -> &DateTime<Utc> {
+        &self.current_period_end
+    }
+
+    #[graphql(
+        description = "Specifies if the subscription will be canceled at the end of the current
+        billing period."
+    )]
+    pub fn cancel_at_period_end(&self) -> bool {
+        self.cancel_at_period_end
+    }
+}
+
+/// Represents a user's free trial of a paid plan in the "trials" table. Users whose trial has
+/// ended without subscribing are restricted to read-only access.
+#[derive(Debug, Clone, FromRow)]
+pub struct Trial {
+    /// The ID of the user the trial belongs to.
+    pub user_id: Uuid,
+    /// Auto-generated timestamp specifying when this trial started.
+    pub created_at: DateTime<Utc>,
+    /// The plan being trialed.
+    pub plan: String,
+    /// Timestamp specifying when the trial ends.
+    pub ends_at: DateTime<Utc>,
+    /// The number of days before the trial ends that the latest reminder email was sent for. This
+    /// will be none if no reminder has been sent yet.
+    pub reminder_days: Option<i32>,
+}
+
+impl Trial {
+    /// Specifies if the trial hasn't ended yet.
+    pub fn is_active(&self) -> bool {
+        self.ends_at > Utc::now()
+    }
+}
+
+/// Defines trial fields exposed over GraphQL.
+#[graphql_object(description = "A user's free trial of a paid plan.")]
+impl Trial {
+    #[graphql(description = "The plan being trialed.")]
+    pub fn plan(&self) -> FieldResult<Plan> {
+        Ok(self.plan.parse()?)
+    }
+
+    #[graphql(description = "Date when the trial started.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(description = "Date when the trial ends.")]
+    pub fn ends_at(&self) -> &DateTime<Utc> {
+        &self.ends_at
+    }
+
+    #[graphql(
+        description = "Specifies if the trial hasn't ended yet. Once it ends, users without a
+        subscription are restricted to read-only access."
+    )]
+    pub fn is_active(&self) -> bool {
+        Trial::is_active(self)
+    }
+}
+
+/// Represents a promo code giving a discount on subscriptions in the "promo_codes" table. Each
+/// promo code has a matching coupon in Stripe that the discount is applied with.
+#[derive(Debug, Clone, FromRow)]
+pub struct PromoCode {
+    /// The unique ID of the promo code.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when this promo code was created.
+    pub created_at: DateTime<Utc>,
+    /// The ID of the admin that created the promo code. This will be none if they were deleted.
+    pub created_by: Option<Uuid>,
+    /// The code users redeem, in upper case.
+    pub code: String,
+    /// The kind of discount the promo code gives, "percentage" or "fixed".
+    pub kind: String,
+    /// The percentage taken off for percentage discounts, or the amount taken off in the smallest
+    /// currency unit for fixed discounts.
+    pub amount: i32,
+    /// The currency of fixed discounts. This will be none for percentage discounts.
+    pub currency: Option<String>,
+    /// The max number of times the promo code can be redeemed. This will be none if there is no
+    /// limit.
+    pub max_redemptions: Option<i32>,
+    /// The number of times the promo code has been redeemed.
+    pub redemption_count: i32,
+    /// Timestamp specifying when the promo code expires. This will be none if it never expires.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The ID of the coupon in Stripe the discount is applied with.
+    pub stripe_coupon_id: String,
+}
+
+/// Defines promo code fields exposed over GraphQL.
+#[graphql_object(description = "A promo code giving a discount on subscriptions.")]
+impl PromoCode {
+    #[graphql(description = "The unique ID of the promo code.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Date when the promo code was created.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(
+        description = "The ID of the admin that created the promo code. This will be null if they
+        were deleted."
+    )]
+    pub fn created_by(&self) -> &Option<Uuid> {
+        &self.created_by
+    }
+
+    #[graphql(description = "The code users redeem.")]
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    #[graphql(description = "The kind of discount the promo code gives.")]
+    pub fn kind(&self) -> FieldResult<PromoCodeKind> {
+        Ok(self.kind.parse()?)
+    }
+
+    #[graphql(
+        description = "The percentage taken off for percentage discounts, or the amount taken off
+        in the smallest currency unit, e.g. cents, for fixed discounts."
+    )]
+    pub fn amount(&self) -> i32 {
+        self.amount
+    }
+
+    #[graphql(
+        description = "The currency of fixed discounts. This will be null for percentage
+        discounts."
+    )]
+    pub fn currency(&self) -> &Option<String> {
+        &self.currency
+    }
+
+    #[graphql(
+        description = "The max number of times the promo code can be redeemed. This will be null
+        if there is no limit."
+    )]
+    pub fn max_redemptions(&self) -> Option<i32> {
+        self.max_redemptions
+    }
+
+    #[graphql(description = "The number of times the promo code has been redeemed.")]
+    pub fn redemption_count(&self) -> i32 {
+        self.redemption_count
+    }
+
+    #[graphql(
+        description = "Date when the promo code expires. This will be null if it never expires."
+    )]
+    pub fn expires_at(&self) -> &Option<DateTime<Utc>> {
+        &self.expires_at
+    }
+}
+
+/// Represents a user redeeming a promo code in the "promo_code_redemptions" table. Redemptions are
+/// kept as an audit trail, including after the user is deleted.
+#[derive(Debug, Clone, FromRow)]
+pub struct PromoCodeRedemption {
+    /// The unique ID of the redemption.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when the promo code was redeemed.
+    pub created_at: DateTime<Utc>,
+    /// The ID of the promo code redeemed.
+    pub promo_code_id: Uuid,
+    /// The ID of the user that redeemed the promo code. This will be none if they were deleted.
+    pub user_id: Option<Uuid>,
+    /// The ID of the request the promo code was redeemed in.
+    pub request_id: String,
+    /// The IP address the promo code was redeemed from, if known.
+    pub client_ip: Option<String>,
+    /// Timestamp specifying when the discount was applied to a subscription. This will be none
+    /// until the user subscribes.
+    pub applied_at: Option<DateTime<Utc>>,
+    /// The ID of the Stripe subscription the discount was applied to, if any.
+    pub stripe_subscription_id: Option<String>,
+}
+
+/// Defines promo code redemption fields exposed over GraphQL.
+#[graphql_object(description = "A user redeeming a promo code.")]
+impl PromoCodeRedemption {
+    #[graphql(description = "The unique ID of the redemption.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Date when the promo code was redeemed.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(description = "The ID of the promo code redeemed.")]
+    pub fn promo_code_id(&self) -> &Uuid {
+        &self.promo_code_id
+    }
+
+    #[graphql(
+        description = "The ID of the user that redeemed the promo code. This will be null if they
+        were deleted."
+    )]
+    pub fn user_id(&self) -> &Option<Uuid> {
+        &self.user_id
+    }
+
+    #[graphql(description = "The ID of the request the promo code was redeemed in.")]
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    #[graphql(description = "The IP address the promo code was redeemed from, if known.")]
+    pub fn client_ip(&self) -> &Option<String> {
+        &self.client_ip
+    }
+
+    #[graphql(
+        description = "Date when the discount was applied to a subscription. This will be null
+        until the user subscribes."
+    )]
+    pub fn applied_at(&self) -> &Option<DateTime<Utc>> {
+        &self.applied_at
+    }
+}
+
+/// Represents an announcement shown to users in the "announcements" table, such as a maintenance
+/// notice or release notes.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Announcement {
+    /// The unique ID of the announcement.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when this announcement was created.
+    pub created_at: DateTime<Utc>,
+    /// Timestamp specifying when this announcement was last updated.
+    pub updated_at: DateTime<Utc>,
+    /// The ID of the admin that created the announcement. This will be none if they were deleted.
+    pub created_by: Option<Uuid>,
+    /// The message to show.
+    pub message: String,
+    /// How important the announcement is, "info", "warning" or "critical".
+    pub severity: String,
+    /// Who the announcement is shown to, "everyone", "guests", "users" or "admins".
+    pub audience: String,
+    /// Timestamp specifying when the announcement starts being shown.
+    pub starts_at: DateTime<Utc>,
+    /// Timestamp specifying when the announcement stops being shown. This will be none if it is
+    /// shown until it is deleted.
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+/// Defines announcement fields exposed over GraphQL.
+#[graphql_object(description = "An announcement shown to users, such as a maintenance notice.")]
+impl Announcement {
+    #[graphql(description = "The unique ID of the announcement.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Date when the announcement was created.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(description = "Date when the announcement was last updated.")]
+    pub fn updated_at(&self) -> &DateTime<Utc> {
+        &self.updated_at
+    }
+
+    #[graphql(description = "The message to show.")]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    #[graphql(description = "How important the announcement is.")]
+    pub fn severity(&self) -> FieldResult<Severity> {
+        Ok(self.severity.parse()?)
+    }
+
+    #[graphql(description = "Who the announcement is shown to.")]
+    pub fn audience(&self) -> FieldResult<Audience> {
+        Ok(self.audience.parse()?)
+    }
+
+    #[graphql(description = "Date when the announcement starts being shown.")]
+    pub fn starts_at(&self) -> &DateTime<Utc> {
+        &self.starts_at
+    }
+
+    #[graphql(
+        description = "Date when the announcement stops being shown. This will be null if it is
+        shown until it is deleted."
+    )]
+    pub fn ends_at(&self) -> &Option<DateTime<Utc>> {
+        &self.ends_at
+    }
+}
+
+/// Represents feedback submitted by a user in the "feedback" table.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Feedback {
+    /// The unique ID of the feedback.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when this feedback was submitted.
+    pub created_at: DateTime<Utc>,
+    /// Timestamp specifying when this feedback was last updated.
+    pub updated_at: DateTime<Utc>,
+    /// The ID of the user that submitted the feedback. This will be none if the feedback was
+    /// submitted without logging in or the user was deleted.
+    pub user_id: Option<Uuid>,
+    /// What the feedback is about, e.g. "bug" or "feature-request".
+    pub category: String,
+    /// The feedback itself.
+    pub message: String,
+    /// Context attached to the feedback by the client, as a JSON object of strings.
+    pub metadata: String,
+    /// Where the feedback is in triage, e.g. "new" or "resolved".
+    pub status: String,
+    /// The ID of the request the feedback was submitted in.
+    pub request_id: String,
+    /// The IP address the feedback was submitted from, if known.
+    pub client_ip: Option<String>,
+}
+
+impl Feedback {
+    /// Parse the context attached to the feedback, ordered by key.
+    pub fn metadata_entries(&self) -> serde_json::Result<Vec<FeedbackMetadataEntry>> {
+        let metadata: BTreeMap<String, String> = serde_json::from_str(&self.metadata)?;
+        Ok(metadata
+            .into_iter()
+            .map(|(key, value)| FeedbackMetadataEntry { key, value })
+            .collect())
+    }
+}
+
+/// Defines feedback fields exposed over GraphQL.
+#[graphql_object(description = "Feedback submitted by a user.")]
+impl Feedback {
+    #[graphql(description = "The unique ID of the feedback.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Date when the feedback was submitted.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(description = "Date when the feedback was last updated.")]
+    pub fn updated_at(&self) -> &DateTime<Utc> {
+        &self.updated_at
+    }
+
+    #[graphql(
+        description = "The ID of the user that submitted the feedback. This will be null if the
+        feedback was submitted without logging in or the user was deleted."
+    )]
+    pub fn user_id(&self) -> &Option<Uuid> {
+        &self.user_id
+    }
+
+    #[graphql(description = "What the feedback is about.")]
+    pub fn category(&self) -> FieldResult<FeedbackCategory> {
+        Ok(self.category.parse()?)
+    }
+
+    #[graphql(description = "The feedback itself.")]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    #[graphql(description = "Context attached to the feedback by the client.")]
+    pub fn metadata(&self) -> FieldResult<Vec<FeedbackMetadataEntry>> {
+        Ok(self.metadata_entries()?)
+    }
+
+    #[graphql(description = "Where the feedback is in triage.")]
+    pub fn status(&self) -> FieldResult<FeedbackStatus> {
+        Ok(self.status.parse()?)
+    }
+
+    #[graphql(description = "The ID of the request the feedback was submitted in.")]
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    #[graphql(description = "The IP address the feedback was submitted from, if known.")]
+    pub fn client_ip(&self) -> &Option<String> {
+        &self.client_ip
+    }
+}
+
+/// Represents a security-relevant event in the "audit_events" table.
+#[derive(Debug, Clone, FromRow)]
+pub struct AuditEvent {
+    /// The unique ID of the event.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when the event happened.
+    pub created_at: DateTime<Utc>,
+    /// What happened, e.g. "login" or "role-granted".
+    pub kind: String,
+    /// The ID of the user the event is about. This will be none for failed logins with a username
+    /// no user has. The ID is kept after the user is deleted.
+    pub user_id: Option<Uuid>,
+    /// The ID of the user that caused the event, if they were logged in or logging in. This differs
+    /// from the user the event is about when an admin acts on another user.
+    pub actor_id: Option<Uuid>,
+    /// Details of the event, as a JSON object of strings.
+    pub metadata: String,
+    /// The fields a sensitive admin action changed with their values before and after, as a JSON
+    /// array. This is empty for other events.
+    pub changes: String,
+    /// The ID of the request the event happened in.
+    pub request_id: String,
+    /// The IP address the request was sent from, if known.
+    pub client_ip: Option<String>,
+    /// The user agent the request was sent with, if known.
+    pub user_agent: Option<String>,
+}
+
+impl AuditEvent {
+    /// Parse the details of the event, ordered by key.
+    pub fn metadata_entries(&self) -> serde_json::Result<Vec<AuditMetadataEntry>> {
+        let metadata: BTreeMap<String, String> = serde_json::from_str(&self.metadata)?;
+        Ok(metadata
+            .into_iter()
+            .map(|(key, value)| AuditMetadataEntry { key, value })
+            .collect())
+    }
+
+    /// Parse the fields changed by the event, in the order they were recorded.
+    pub fn audit_changes(&self) -> serde_json::Result<Vec<AuditChange>> {
+        serde_json::from_str(&self.changes)
+    }
+}
+
+/// Defines audit event fields exposed over GraphQL.
+#[graphql_object(description = "A security-relevant event recorded in the audit log.")]
+impl AuditEvent {
+    #[graphql(description = "The unique ID of the event.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Date when the event happened.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(description = "What happened.")]
+    pub fn kind(&self) -> FieldResult<AuditEventKind> {
+        Ok(self.kind.parse()?)
+    }
+
+    #[graphql(
+        description = "The ID of the user the event is about. This will be null for failed logins
+        with a username no user has."
+    )]
+    pub fn user_id(&self) -> &Option<Uuid> {
+        &self.user_id
+    }
+
+    #[graphql(
+        description = "The ID of the user that caused the event, if known. This differs from the
+        user the event is about when an admin acts on another user."
+    )]
+    pub fn actor_id(&self) -> &Option<Uuid> {
+        &self.actor_id
+    }
+
+    #[graphql(description = "Details of the event, such as the role that was granted.")]
+    pub fn metadata(&self) -> FieldResult<Vec<AuditMetadataEntry>> {
+        Ok(self.metadata_entries()?)
+    }
+
+    #[graphql(
+        description = "The fields a sensitive admin action changed, such as the roles of the user
+        before and after a role was granted. This is empty for other events."
+    )]
+    pub fn changes(&self) -> FieldResult<Vec<AuditChange>> {
+        Ok(self.audit_changes()?)
+    }
+
+    #[graphql(description = "The ID of the request the event happened in.")]
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    #[graphql(description = "The IP address the request was sent from, if known.")]
+    pub fn client_ip(&self) -> &Option<String> {
+        &self.client_ip
+    }
+
+    #[graphql(description = "The user agent the request was sent with, if known.")]
+    pub fn user_agent(&self) -> &Option<String> {
+        &self.user_agent
+    }
+}
+
+/// Represents an event in the security activity of a user's account in the "security_events"
+/// table, such as a login or a password change.
+#[derive(Debug, Clone, FromRow)]
+pub struct SecurityEvent {
+    /// The unique ID of the event.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when the event happened.
+    pub created_at: DateTime<Utc>,
+    /// The ID of the user whose account the event happened to.
+    pub user_id: Uuid,
+    /// What happened, e.g. "login" or "password-changed".
+    pub kind: String,
+    /// The IP address the request causing the event was sent from, if known.
+    pub client_ip: Option<String>,
+    /// The user agent the request causing the event was sent with, if known.
+    pub user_agent: Option<String>,
+}
+
+/// Defines security event fields exposed over GraphQL.
+#[graphql_object(description = "An event in the security activity of a user's account.")]
+impl SecurityEvent {
+    #[graphql(description = "The unique ID of the event.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Date when the event happened.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(description = "What happened.")]
+    pub fn kind(&self) -> FieldResult<SecurityEventKind> {
+        Ok(self.kind.parse()?)
+    }
+
+    #[graphql(description = "The IP address the event came from, if known.")]
+    pub fn client_ip(&self) -> &Option<String> {
+        &self.client_ip
+    }
+
+    #[graphql(
+        description = "The user agent of the device the event came from, such as a browser, if
+        known."
+    )]
+    pub fn user_agent(&self) -> &Option<String> {
+        &self.user_agent
+    }
+}
+
+/// Represents a signup in the "pending_signups" table that looked like it was made by a bot,
+/// waiting for an admin to approve or reject it.
+#[derive(Debug, Clone, FromRow)]
+pub struct PendingSignup {
+    /// The unique ID of the pending signup.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when the signup was made.
+    pub created_at: DateTime<Utc>,
+    /// The username the user signed up with.
+    pub username: String,
+    /// The email address the user signed up with.
+    pub email: String,
+    /// Hash of the password the user signed up with, so the user can be created once approved.
+    pub password_hash: String,
+    /// The signals suggesting the signup was made by a bot, e.g. "honeypot".
+    pub signals: Vec<String>,
+    /// The IP address the signup was sent from, if known.
+    pub client_ip: Option<String>,
+    /// The user agent the signup was sent with, if known.
+    pub user_agent: Option<String>,
+}
+
+/// Defines pending signup fields exposed over GraphQL. The password hash is never exposed.
+#[graphql_object(description = "A signup waiting for an admin to approve or reject it.")]
+impl PendingSignup {
+    #[graphql(description = "The unique ID of the pending signup.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Date when the signup was made.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(description = "The username the user signed up with.")]
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    #[graphql(description = "The email address the user signed up with.")]
     pub fn email(&self) -> &str {
         &self.email
     }
 
     #[graphql(
-        description = "Date when the user's email address was last verified. This will be null
-        if the email has not been verified yet."
+        description = "The signals suggesting the signup was made by a bot, such as \"honeypot\"
+        or \"automated-user-agent\"."
+    )]
+    pub fn signals(&self) -> &Vec<String> {
+        &self.signals
+    }
+
+    #[graphql(description = "The IP address the signup was sent from, if known.")]
+    pub fn client_ip(&self) -> &Option<String> {
+        &self.client_ip
+    }
+
+    #[graphql(description = "The user agent the signup was sent with, if known.")]
+    pub fn user_agent(&self) -> &Option<String> {
+        &self.user_agent
+    }
+}
+
+/// Represents an uploaded avatar in the "avatar_jobs" table, waiting to be processed into
+/// renditions.
+#[derive(Debug, Clone, FromRow)]
+pub struct AvatarJob {
+    /// The path of the uploaded avatar in file storage.
+    pub avatar_path: String,
+    /// Auto-generated timestamp specifying when the avatar was uploaded.
+    pub created_at: DateTime<Utc>,
+    /// The ID of the user the avatar belongs to.
+    pub user_id: Uuid,
+    /// The number of times processing the avatar was started.
+    pub attempts: i32,
+    /// Timestamp specifying when the avatar can next be processed.
+    pub run_at: DateTime<Utc>,
+}
+
+/// Represents a destructive action in the "pending_actions" table that is only done once its grace
+/// period is over, so it can be undone until then.
+#[derive(Debug, Clone, FromRow)]
+pub struct PendingAction {
+    /// The unique ID of the pending action.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when the action was scheduled.
+    pub created_at: DateTime<Utc>,
+    /// What the action does, e.g. "delete-account".
+    pub kind: String,
+    /// The ID of the user that scheduled the action.
+    pub user_id: Uuid,
+    /// The ID of what the action is done to, such as the user whose account is deleted.
+    pub target_id: Uuid,
+    /// The token the action can be undone with until it is done.
+    pub undo_token: String,
+    /// Timestamp specifying when the grace period is over and the action is done.
+    pub execute_at: DateTime<Utc>,
+}
+
+/// Defines pending action fields exposed over GraphQL. These are only returned to the user that
+/// scheduled the action.
+#[graphql_object(description = "A destructive action that can be undone until it is done.")]
+impl PendingAction {
+    #[graphql(description = "What the action does.")]
+    pub fn kind(&self) -> FieldResult<PendingActionKind> {
+        Ok(self.kind.parse()?)
+    }
+
+    #[graphql(description = "Date when the grace period is over and the action is done.")]
+    pub fn execute_at(&self) -> &DateTime<Utc> {
+        &self.execute_at
+    }
+
+    #[graphql(
+        description = "The token to pass to \"undoAction\" to undo the action until it is done."
+    )]
+    pub fn undo_token(&self) -> &str {
+        &self.undo_token
+    }
+}
+
+/// Represents an event a provider such as Stripe sent to the server's webhook in the
+/// "webhook_events" table. Events are recorded before they are processed, so each is only
+/// processed once and failed events can be replayed.
+#[derive(Debug, Clone, FromRow)]
+pub struct WebhookEvent {
+    /// The unique ID of the webhook event.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when the event was first received.
+    pub received_at: DateTime<Utc>,
+    /// The provider that sent the event, e.g. "stripe".
+    pub provider: String,
+    /// The ID the provider gave the event.
+    pub event_id: String,
+    /// What happened according to the provider, e.g. "customer.subscription.updated".
+    pub kind: String,
+    /// The body of the request the event was received in.
+    pub payload: String,
+    /// Timestamp specifying when the event was last processed successfully. This will be none if
+    /// it hasn't been.
+    pub processed_at: Option<DateTime<Utc>>,
+    /// Why the event was last processed unsuccessfully. This will be none if it succeeded or
+    /// hasn't been processed yet.
+    pub error: Option<String>,
+}
+
+/// Defines webhook event fields exposed over GraphQL.
+#[graphql_object(description = "An event a provider such as Stripe sent to the server's webhook.")]
+impl WebhookEvent {
+    #[graphql(description = "The unique ID of the webhook event.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Date when the event was first received.")]
+    pub fn received_at(&self) -> &DateTime<Utc> {
+        &self.received_at
+    }
+
+    #[graphql(description = "The provider that sent the event.")]
+    pub fn provider(&self) -> FieldResult<WebhookProvider> {
+        Ok(self.provider.parse()?)
+    }
+
+    #[graphql(description = "The ID the provider gave the event.")]
+    pub fn event_id(&self) -> &str {
+        &self.event_id
+    }
+
+    #[graphql(description = "What happened according to the provider, such as
+        \"customer.subscription.updated\".")]
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    #[graphql(description = "The body of the request the event was received in.")]
+    pub fn payload(&self) -> &str {
+        &self.payload
+    }
+
+    #[graphql(
+        description = "Date when the event was last processed successfully. This will be null if
+        it hasn't been."
+    )]
+    pub fn processed_at(&self) -> &Option<DateTime<Utc>> {
+        &self.processed_at
+    }
+
+    #[graphql(
+        description = "Why the event was last processed unsuccessfully. This will be null if it
+        succeeded or hasn't been processed yet."
+    )]
+    pub fn error(&self) -> &Option<String> {
+        &self.error
+    }
+}
+
+/// Represents an organization in the "organizations" table, which users can be members of.
+#[derive(Debug, Clone, FromRow)]
+pub struct Organization {
+    /// The unique ID of the organization.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when this organization was created.
+    pub created_at: DateTime<Utc>,
+    /// Timestamp specifying when this organization was last updated.
+    pub updated_at: DateTime<Utc>,
+    /// The ID of the user that created the organization. This will be none if the user was
+    /// deleted.
+    pub created_by: Option<Uuid>,
+    /// The organization's name.
+    pub name: String,
+}
+
+/// Defines organization fields exposed over GraphQL.
+#[graphql_object(description = "An organization users can be members of, such as a team.")]
+impl Organization {
+    #[graphql(description = "The unique ID of the organization.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Date when the organization was created.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(description = "Date when the organization was last updated.")]
+    pub fn updated_at(&self) -> &DateTime<Utc> {
+        &self.updated_at
+    }
+
+    #[graphql(description = "The organization's name.")]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Represents a user's membership of an organization in the "memberships" table. Invited users
+/// have a membership that hasn't been accepted yet.
+#[derive(Debug, Clone, FromRow)]
+pub struct Membership {
+    /// The ID of the organization.
+    pub organization_id: Uuid,
+    /// The ID of the member.
+    pub user_id: Uuid,
+    /// Auto-generated timestamp specifying when the user was invited, or became a member if they
+    /// created the organization.
+    pub created_at: DateTime<Utc>,
+    /// Timestamp specifying when this membership was last updated.
+    pub updated_at: DateTime<Utc>,
+    /// What the member can do in the organization, e.g. "owner" or "member".
+    pub role: String,
+    /// The ID of the user that invited the member. This will be none if the member created the
+    /// organization or the user was deleted.
+    pub invited_by: Option<Uuid>,
+    /// Timestamp specifying when the user accepted their invitation. This will be none if they
+    /// haven't yet.
+    pub accepted_at: Option<DateTime<Utc>>,
+}
+
+impl Membership {
+    /// Parse what the member can do in the organization.
+    pub fn organization_role(&self) -> Result<OrganizationRole> {
+        self.role.parse()
+    }
+
+    /// Check if the user is a member of the organization, rather than only invited to it.
+    pub fn is_accepted(&self) -> bool {
+        self.accepted_at.is_some()
+    }
+}
+
+/// Defines membership fields exposed over GraphQL.
+#[graphql_object(
+    context = Context,
+    description = "A user's membership of an organization, or invitation to it."
+)]
+impl Membership {
+    #[graphql(description = "The organization.")]
+    pub async fn organization(&self, context: &Context) -> FieldResult<Organization> {
+        Ok(context
+            .executor()
+            .find_organization(self.organization_id)
+            .await?
+            .ok_or_else(|| anyhow!("Organization not found: {}", self.organization_id))?)
+    }
+
+    #[graphql(description = "The ID of the member.")]
+    pub fn user_id(&self) -> &Uuid {
+        &self.user_id
+    }
+
+    #[graphql(description = "The member. This will be null if the user has been deleted.")]
+    pub async fn user(&self, context: &Context) -> FieldResult<Option<User>> {
+        Ok(context.load_user(self.user_id).await?)
+    }
+
+    #[graphql(description = "What the member can do in the organization.")]
+    pub fn role(&self) -> FieldResult<OrganizationRole> {
+        Ok(self.organization_role()?)
+    }
+
+    #[graphql(
+        description = "Date when the user was invited, or became a member if they created the
+        organization."
+    )]
+    pub fn invited_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(
+        description = "Date when the user accepted their invitation. This will be null if they
+        haven't yet, in which case they aren't a member."
+    )]
+    pub fn accepted_at(&self) -> &Option<DateTime<Utc>> {
+        &self.accepted_at
+    }
+}
+
+/// Represents the email settings an organization overrides the server's with, in the
+/// "organization_email_settings" table. Settings that aren't set fall back to the server's.
+#[derive(Debug, Clone, FromRow)]
+pub struct OrganizationEmailSettings {
+    /// The ID of the organization.
+    pub organization_id: Uuid,
+    /// Auto-generated timestamp specifying when the settings were created.
+    pub created_at: DateTime<Utc>,
+    /// Timestamp specifying when the settings were last updated.
+    pub updated_at: DateTime<Utc>,
+    /// The display name emails are sent from.
+    pub from_name: Option<String>,
+    /// The address emails are sent from.
+    pub from_address: Option<String>,
+    /// The host name of the SMTP server emails are sent through. The port, username and password
+    /// are set if and only if this is.
+    pub smtp_host: Option<String>,
+    /// The port of the SMTP server.
+    pub smtp_port: Option<i32>,
+    /// The username to log in to the SMTP server with.
+    pub smtp_username: Option<String>,
+    /// The password to log in to the SMTP server with, encrypted with the server's email settings
+    /// key.
+    pub smtp_password_encrypted: Option<Vec<u8>>,
+    /// The URL of a logo shown above emails.
+    pub logo_url: Option<String>,
+    /// The color of the bar along the top of emails, as a hex code.
+    pub brand_color: Option<String>,
+}
+
+impl OrganizationEmailSettings {
+    /// Get the branding the organization's emails are rendered with.
+    pub fn branding(&self) -> EmailBranding {
+        EmailBranding {
+            logo_url: self.logo_url.clone(),
+            color: self
+                .brand_color
+                .clone()
+                .unwrap_or_else(|| DEFAULT_BRAND_COLOR.into()),
+        }
+    }
+}
+
+/// Defines organization email settings fields exposed over GraphQL.
+#[graphql_object(
+    description = "Email settings an organization overrides the server's with, for emails sent on
+    its behalf. Settings that are null fall back to the server's."
+)]
+impl OrganizationEmailSettings {
+    #[graphql(description = "The ID of the organization.")]
+    pub fn organization_id(&self) -> &Uuid {
+        &self.organization_id
+    }
+
+    #[graphql(description = "Date when the settings were last updated.")]
+    pub fn updated_at(&self) -> &DateTime<Utc> {
+        &self.updated_at
+    }
+
+    #[graphql(description = "The display name emails are sent from.")]
+    pub fn from_name(&self) -> &Option<String> {
+        &self.from_name
+    }
+
+    #[graphql(description = "The address emails are sent from.")]
+    pub fn from_address(&self) -> &Option<String> {
+        &self.from_address
+    }
+
+    #[graphql(description = "The host name of the SMTP server emails are sent through.")]
+    pub fn smtp_host(&self) -> &Option<String> {
+        &self.smtp_host
+    }
+
+    #[graphql(description = "The port of the SMTP server.")]
+    pub fn smtp_port(&self) -> &Option<i32> {
+        &self.smtp_port
+    }
+
+    #[graphql(description = "The username to log in to the SMTP server with.")]
+    pub fn smtp_username(&self) -> &Option<String> {
+        &self.smtp_username
+    }
+
+    #[graphql(
+        description = "Specifies if an SMTP password is set. The password itself can't be read
+        back."
+    )]
+    pub fn has_smtp_password(&self) -> bool {
+        self.smtp_password_encrypted.is_some()
+    }
+
+    #[graphql(description = "The URL of a logo shown above emails.")]
+    pub fn logo_url(&self) -> &Option<String> {
+        &self.logo_url
+    }
+
+    #[graphql(description = "The color of the bar along the top of emails, as a hex code.")]
+    pub fn brand_color(&self) -> &Option<String> {
+        &self.brand_color
+    }
+}
+
+/// Represents a custom hostname an organization serves the API on, in the "organization_domains"
+/// table. Requests are only routed to the organization once it has verified the hostname.
+#[derive(Debug, Clone, FromRow)]
+pub struct OrganizationDomain {
+    /// The ID of the organization.
+    pub organization_id: Uuid,
+    /// The hostname, such as "api.example.com".
+    pub hostname: String,
+    /// Auto-generated timestamp specifying when the hostname was added.
+    pub created_at: DateTime<Utc>,
+    /// The value the organization must publish in the hostname's TXT challenge record.
+    pub verification_token: String,
+    /// Timestamp specifying when the hostname was verified. This will be none if it hasn't been,
+    /// or lost its verification when its challenge record was removed.
+    pub verified_at: Option<DateTime<Utc>>,
+    /// Timestamp specifying when the challenge record was last looked up.
+    pub checked_at: Option<DateTime<Utc>>,
+    /// Timestamp specifying when the challenge record will next be looked up.
+    pub check_at: DateTime<Utc>,
+    /// The number of lookups in a row that didn't find the challenge record.
+    pub failed_checks: i32,
+}
+
+/// Defines organization domain fields exposed over GraphQL.
+#[graphql_object(
+    description = "A custom hostname an organization serves the API on. Requests are only routed
+    to the organization once it has proven it owns the hostname with a DNS TXT record."
+)]
+impl OrganizationDomain {
+    #[graphql(description = "The hostname, such as \"api.example.com\".")]
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    #[graphql(description = "Date when the hostname was added.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(
+        description = "The name of the TXT record to publish to verify the hostname, such as
+        \"_rust-graphql-server-challenge.api.example.com\"."
+    )]
+    pub fn challenge_name(&self) -> String {
+        domains::challenge_name(&self.hostname)
+    }
+
+    #[graphql(description = "The value to publish in the TXT record to verify the hostname.")]
+    pub fn challenge_value(&self) -> &str {
+        &self.verification_token
+    }
+
+    #[graphql(
+        description = "Date when the hostname was verified. This will be null if it hasn't been
+        yet, or lost its verification after its TXT record was removed."
+    )]
+    pub fn verified_at(&self) -> &Option<DateTime<Utc>> {
+        &self.verified_at
+    }
+
+    #[graphql(description = "Date when the TXT record was last looked up.")]
+    pub fn checked_at(&self) -> &Option<DateTime<Utc>> {
+        &self.checked_at
+    }
+
+    #[graphql(description = "Date when the TXT record will next be looked up.")]
+    pub fn check_at(&self) -> &DateTime<Utc> {
+        &self.check_at
+    }
+}
+
+/// Represents a passkey in the "webauthn_credentials" table, which a user can log in with instead
+/// of their password.
+#[derive(Debug, Clone, FromRow)]
+pub struct Passkey {
+    /// The unique ID of the passkey.
+    pub id: Uuid,
+    /// Auto-generated timestamp specifying when the passkey was created.
+    pub created_at: DateTime<Utc>,
+    /// The ID of the user the passkey logs in as.
+    pub user_id: Uuid,
+    /// The base64url-encoded ID the authenticator assigned to the credential.
+    pub credential_id: String,
+    /// The COSE-encoded public key logins are verified with.
+    pub public_key: Vec<u8>,
+    /// The number of times the passkey has been used, according to the authenticator. This is
+    /// zero for authenticators that don't count.
+    pub sign_count: i64,
+    /// A name the user gave the passkey, e.g. "Work laptop".
+    pub name: String,
+    /// Timestamp specifying when the passkey was last used to log in, if ever.
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Defines passkey fields exposed over GraphQL. The public key is never exposed.
+#[graphql_object(description = "A passkey a user can log in with instead of their password.")]
+impl Passkey {
+    #[graphql(description = "The unique ID of the passkey.")]
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    #[graphql(description = "Date when the passkey was created.")]
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    #[graphql(description = "The name the user gave the passkey.")]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[graphql(
+        description = "Date when the passkey was last used to log in. This will be null if it
+        hasn't been used yet."
     )]
-    pub fn email_verified_at(&self) -> &Option<DateTime<Utc>> {
-        &self.email_verified_at
+    pub fn last_used_at(&self) -> &Option<DateTime<Utc>> {
+        &self.last_used_at
     }
 }
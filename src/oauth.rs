@@ -0,0 +1,396 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use tide::http::headers::USER_AGENT;
+use tide::http::{Method, Url};
+use tide::{log, Redirect, Request, StatusCode};
+
+use crate::config::Config;
+use crate::deadlines::{Deadline, REQUEST_DEADLINE_HEADER};
+use crate::executor::Executor;
+use crate::graphql::{client_ip, request_id, user_agent, REQUEST_ID_HEADER};
+use crate::routes::{RouteModule, Router};
+use crate::state::State;
+
+/// The number of seconds a client has to finish logging in with a provider after starting.
+pub const STATE_EXPIRATION_SECONDS: usize = 10 * 60;
+/// The number of seconds a client has to exchange a login ticket for a session token.
+pub const TICKET_EXPIRATION_SECONDS: usize = 60;
+/// User agent sent with requests to providers. GitHub rejects requests without one.
+const PROVIDER_USER_AGENT: &str = "rust-graphql-server";
+
+/// An external identity provider users can log in with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OAuthProvider {
+    Google,
+    GitHub,
+}
+
+impl OAuthProvider {
+    /// The name the provider is stored under in the database and appears as in routes.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OAuthProvider::Google => "google",
+            OAuthProvider::GitHub => "github",
+        }
+    }
+
+    /// Get the client ID and secret the server is registered with at the provider. This will
+    /// return none if logging in with the provider isn't configured.
+    pub fn credentials(self, config: &Config) -> Option<(&str, &str)> {
+        let (client_id, client_secret) = match self {
+            OAuthProvider::Google => (
+                &config.google_oauth_client_id,
+                &config.google_oauth_client_secret,
+            ),
+            OAuthProvider::GitHub => (
+                &config.github_oauth_client_id,
+                &config.github_oauth_client_secret,
+            ),
+        };
+
+        Some((client_id.as_deref()?, client_secret.as_deref()?))
+    }
+
+    /// Get the URL the provider sends users back to after they log in.
+    pub fn callback_url(self, config: &Config) -> String {
+        format!(
+            "{}/auth/{}/callback",
+            config.server_url.trim_end_matches('/'),
+            self
+        )
+    }
+
+    /// Get the URL users are sent to in order to log in with the provider. This will return none
+    /// if logging in with the provider isn't configured.
+    pub fn authorize_url(self, config: &Config, state: &str) -> Option<Url> {
+        let (client_id, _) = self.credentials(config)?;
+        let (url, scope) = match self {
+            OAuthProvider::Google => (
+                "https://accounts.google.com/o/oauth2/v2/auth",
+                "openid email profile",
+            ),
+            OAuthProvider::GitHub => (
+                "https://github.com/login/oauth/authorize",
+                "read:user user:email",
+            ),
+        };
+
+        Url::parse_with_params(
+            url,
+            &[
+                ("client_id", client_id),
+                ("redirect_uri", &self.callback_url(config)),
+                ("response_type", "code"),
+                ("scope", scope),
+                ("state", state),
+            ],
+        )
+        .ok()
+    }
+
+    /// Get the URL authorization codes are exchanged for access tokens at.
+    fn token_url(self) -> &'static str {
+        match self {
+            OAuthProvider::Google => "https://oauth2.googleapis.com/token",
+            OAuthProvider::GitHub => "https://github.com/login/oauth/access_token",
+        }
+    }
+}
+
+impl Display for OAuthProvider {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for OAuthProvider {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "google" => Ok(OAuthProvider::Google),
+            "github" => Ok(OAuthProvider::GitHub),
+            _ => Err(anyhow!("Unknown OAuth provider: {}", value)),
+        }
+    }
+}
+
+/// The details of a user's account at a provider.
+#[derive(Debug, Clone)]
+pub struct OAuthProfile {
+    /// The ID of the account at the provider.
+    pub provider_user_id: String,
+    /// A username suggested by the provider, used as the basis for the username of new users.
+    pub username: String,
+    /// The email address of the account, if the provider shared one.
+    pub email: Option<String>,
+    /// Specifies if the provider has verified the email address belongs to the account.
+    pub email_verified: bool,
+}
+
+/// Exchange an authorization code sent back by a provider for the profile of the user that logged
+/// in.
+pub async fn fetch_profile(
+    config: &Config,
+    provider: OAuthProvider,
+    code: &str,
+) -> Result<OAuthProfile> {
+    let (client_id, client_secret) = provider
+        .credentials(config)
+        .ok_or_else(|| anyhow!("Logging in with {} isn't configured.", provider))?;
+
+    let form = [
+        ("client_id", client_id),
+        ("client_secret", client_secret),
+        ("code", code),
+        ("grant_type", "authorization_code"),
+        ("redirect_uri", &provider.callback_url(config)),
+    ];
+    let mut response = surf::post(provider.token_url())
+        .header("Accept", "application/json")
+        .body(surf::Body::from_form(&form).map_err(|error| error.into_inner())?)
+        .await
+        .map_err(|error| error.into_inner())?;
+    let body: Value = response
+        .body_json()
+        .await
+        .map_err(|error| error.into_inner())?;
+    let access_token = body["access_token"]
+        .as_str()
+        .ok_or_else(|| anyhow!("{} token exchange failed: {}", provider, body["error"]))?;
+
+    match provider {
+        OAuthProvider::Google => fetch_google_profile(access_token).await,
+        OAuthProvider::GitHub => fetch_github_profile(access_token).await,
+    }
+}
+
+/// A Google user, as returned by the OpenID Connect user info endpoint.
+#[derive(Deserialize)]
+struct GoogleUser {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    given_name: Option<String>,
+}
+
+/// Fetch the profile of the Google user an access token belongs to.
+async fn fetch_google_profile(access_token: &str) -> Result<OAuthProfile> {
+    let user: GoogleUser = serde_json::from_value(
+        get_json(
+            "https://openidconnect.googleapis.com/v1/userinfo",
+            access_token,
+        )
+        .await?,
+    )?;
+
+    let username = match (&user.given_name, &user.email) {
+        (Some(given_name), _) => given_name.clone(),
+        (None, Some(email)) => email.split('@').next().unwrap_or_default().into(),
+        (None, None) => String::new(),
+    };
+    Ok(OAuthProfile {
+        provider_user_id: user.sub,
+        username,
+        email: user.email,
+        email_verified: user.email_verified,
+    })
+}
+
+/// A GitHub user, as returned by the REST API.
+#[derive(Deserialize)]
+struct GitHubUser {
+    id: i64,
+    login: String,
+}
+
+/// An email address of a GitHub user, as returned by the REST API.
+#[derive(Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+/// Fetch the profile of the GitHub user an access token belongs to. GitHub users can hide their
+/// email address from their public profile, so it is looked up separately.
+async fn fetch_github_profile(access_token: &str) -> Result<OAuthProfile> {
+    let user: GitHubUser =
+        serde_json::from_value(get_json("https://api.github.com/user", access_token).await?)?;
+    let emails: Vec<GitHubEmail> = serde_json::from_value(
+        get_json("https://api.github.com/user/emails", access_token).await?,
+    )?;
+    let email = emails.into_iter().find(|email| email.primary);
+
+    Ok(OAuthProfile {
+        provider_user_id: user.id.to_string(),
+        username: user.login,
+        email_verified: email.as_ref().is_some_and(|email| email.verified),
+        email: email.map(|email| email.email),
+    })
+}
+
+/// Send an authenticated GET request to a provider's API and return the JSON response.
+async fn get_json(url: &str, access_token: &str) -> Result<Value> {
+    let mut response = surf::get(url)
+        .header("Authorization", format!("Bearer {}", access_token))
+        .header("Accept", "application/json")
+        .header("User-Agent", PROVIDER_USER_AGENT)
+        .await
+        .map_err(|error| error.into_inner())?;
+
+    let body: Value = response
+        .body_json()
+        .await
+        .map_err(|error| error.into_inner())?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Request to {} failed: {}", url, body));
+    }
+
+    Ok(body)
+}
+
+/// Turn a username suggested by a provider into a valid username. Characters other than letters,
+/// digits, dashes and underscores are dropped.
+pub fn normalize_username(username: &str) -> String {
+    let username: String = username
+        .chars()
+        .filter(|character| character.is_ascii_alphanumeric() || "-_".contains(*character))
+        .take(32)
+        .collect();
+
+    if username.is_empty() {
+        "user".into()
+    } else {
+        username
+    }
+}
+
+/// Get the Redis key holding the provider a login attempt with a state parameter was started for.
+pub fn state_key(state: &str) -> String {
+    format!("oauth/state/{}", state)
+}
+
+/// Get the Redis key holding the ID of the user a login ticket was issued to.
+pub fn ticket_key(ticket: &str) -> String {
+    format!("oauth/ticket/{}", ticket)
+}
+
+/// Route module letting users log in with external identity providers. This is only enabled if at
+/// least one provider is configured.
+pub struct OAuthModule;
+
+impl RouteModule for OAuthModule {
+    fn name(&self) -> &'static str {
+        "oauth"
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        [OAuthProvider::Google, OAuthProvider::GitHub]
+            .iter()
+            .any(|provider| provider.credentials(config).is_some())
+    }
+
+    fn mount(&self, router: &mut dyn Router) {
+        router.route(Method::Get, "/auth/:provider/start", Arc::new(start));
+        router.route(Method::Get, "/auth/:provider/callback", Arc::new(callback));
+    }
+}
+
+/// Get the configured provider a request is for. This will return a not found error if the
+/// provider is unknown or isn't configured.
+fn provider(request: &Request<State>) -> tide::Result<OAuthProvider> {
+    request
+        .param("provider")?
+        .parse::<OAuthProvider>()
+        .ok()
+        .filter(|provider| provider.credentials(&request.state().config).is_some())
+        .ok_or_else(|| tide::Error::from_str(StatusCode::NotFound, "Unknown OAuth provider."))
+}
+
+/// Create an executor for a request made directly to a route.
+fn executor(request: &Request<State>) -> Executor {
+    let request_id = request_id(
+        request
+            .header(REQUEST_ID_HEADER)
+            .map(|values| values.last().as_str()),
+    );
+    let deadline = Deadline::for_request(
+        request
+            .header(REQUEST_DEADLINE_HEADER)
+            .map(|values| values.last().as_str()),
+        request.state().config.request_timeout_seconds,
+    );
+    Executor::new(
+        request.state().clone(),
+        request_id,
+        client_ip(request),
+        user_agent(request.header(USER_AGENT).map(|values| values.as_str())),
+    )
+    .with_deadline(deadline)
+}
+
+/// Send the client back to the configured OAuth success URL with a query parameter.
+fn finish(config: &Config, parameter: (&str, &str)) -> tide::Result {
+    let url = Url::parse_with_params(&config.oauth_success_url, &[parameter])?;
+    Ok(Redirect::new(url).into())
+}
+
+/// Start logging in with a provider by sending the client to the provider's login page.
+async fn start(request: Request<State>) -> tide::Result {
+    let provider = provider(&request)?;
+    let state = executor(&request).start_oauth(provider).await?;
+    let url = provider
+        .authorize_url(&request.state().config, &state)
+        .ok_or_else(|| anyhow!("Failed to build the {} authorization URL.", provider))?;
+
+    Ok(Redirect::new(url).into())
+}
+
+/// Query parameters a provider sends the client back with.
+#[derive(Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Finish logging in with a provider. The client is sent back to the configured OAuth success URL
+/// with a one-time "ticket" parameter, which can be exchanged for a session token with the
+/// "loginWithOAuth" mutation, or an "error" parameter holding an error code if logging in failed.
+async fn callback(request: Request<State>) -> tide::Result {
+    let provider = provider(&request)?;
+    let config = request.state().config.clone();
+    let query: CallbackQuery = request.query()?;
+    if query.error.is_some() {
+        return finish(&config, ("error", "access-denied"));
+    }
+
+    let executor = executor(&request);
+    let code = match (query.code, query.state) {
+        (Some(code), Some(state)) if executor.consume_oauth_state(provider, &state).await? => code,
+        _ => return finish(&config, ("error", "invalid-state")),
+    };
+
+    let profile = match fetch_profile(&config, provider, &code).await {
+        Ok(profile) => profile,
+        Err(error) => {
+            log::error!("Failed to log in with {}: {}", provider, error);
+            return finish(&config, ("error", "oauth-failed"));
+        }
+    };
+
+    match executor
+        .login_with_oauth_profile(provider, &profile)
+        .await?
+    {
+        Some(ticket) => finish(&config, ("ticket", &ticket)),
+        None => finish(&config, ("error", "email-required")),
+    }
+}
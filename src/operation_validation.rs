@@ -0,0 +1,795 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use graphql_parser::query::{
+    parse_query, Definition, Directive, FragmentDefinition, OperationDefinition, Selection,
+    SelectionSet, Type, TypeCondition, Value, VariableDefinition,
+};
+use graphql_parser::schema::{self, TypeDefinition};
+use graphql_parser::Pos;
+
+use crate::schema::Schema;
+
+/// Extensions of the client operation files that are validated.
+const OPERATION_FILE_EXTENSIONS: &[&str] = &["graphql", "gql"];
+/// Scalars every schema has, which aren't listed in the schema language.
+const BUILT_IN_SCALARS: &[&str] = &["Boolean", "Float", "ID", "Int", "String"];
+
+/// A problem found in a client operation file.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OperationError {
+    /// The file the problem was found in.
+    pub path: PathBuf,
+    /// The line and column the problem was found at, or none if the file couldn't be parsed.
+    pub position: Option<(usize, usize)>,
+    /// What the problem is.
+    pub message: String,
+}
+
+impl Display for OperationError {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        match self.position {
+            Some((line, column)) => write!(
+                formatter,
+                "{}:{}:{}: {}",
+                self.path.display(),
+                line,
+                column,
+                self.message
+            ),
+            None => write!(formatter, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// The outcome of validating a directory of client operation files against the schema.
+#[derive(Debug, Clone, Default)]
+pub struct OperationReport {
+    /// The number of operation files found.
+    pub files: usize,
+    /// The number of operations found in the files.
+    pub operations: usize,
+    /// Every problem found, ordered by file and position.
+    pub errors: Vec<OperationError>,
+}
+
+/// Validate every ".graphql" and ".gql" file in a directory and its subdirectories against a
+/// schema. Operations are checked for unknown types, fields and arguments, values of the wrong
+/// type, and variables that are undefined, unused or of the wrong type. Fragments can be defined
+/// in any of the files and spread in the others, as clients that bundle their operations allow.
+pub fn validate_operations(schema: &Schema, directory: &Path) -> Result<OperationReport> {
+    let mut paths = Vec::new();
+    find_operation_files(directory, &mut paths)?;
+    paths.sort();
+    let files = paths
+        .into_iter()
+        .map(|path| Ok((std::fs::read_to_string(&path)?, path)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let document = schema::Document::<&str>::from(&schema.schema);
+    let types = SchemaTypes::new(&document);
+    let mut validator = Validator {
+        types: &types,
+        fragments: HashMap::new(),
+        errors: BTreeSet::new(),
+    };
+
+    let mut documents = Vec::new();
+    for (contents, path) in &files {
+        match parse_query::<&str>(contents) {
+            Ok(document) => documents.push((path.as_path(), document)),
+            Err(error) => {
+                validator.errors.insert(OperationError {
+                    path: path.clone(),
+                    position: None,
+                    message: error.to_string().trim().to_string(),
+                });
+            }
+        }
+    }
+
+    for (path, document) in &documents {
+        for definition in &document.definitions {
+            if let Definition::Fragment(fragment) = definition {
+                if validator.fragments.contains_key(fragment.name) {
+                    validator.error(
+                        path,
+                        fragment.position,
+                        format!(
+                            "There can be only one fragment named \"{}\".",
+                            fragment.name
+                        ),
+                    );
+                } else {
+                    validator.fragments.insert(fragment.name, (path, fragment));
+                }
+            }
+        }
+    }
+
+    let mut operations = 0;
+    let mut spread_fragments = HashSet::new();
+    for (path, document) in &documents {
+        for definition in &document.definitions {
+            if let Definition::Operation(operation) = definition {
+                operations += 1;
+                spread_fragments.extend(validator.check_operation(path, operation));
+            }
+        }
+    }
+
+    // Fragments no operation spreads are still checked, without variables to check against.
+    let mut unused_fragments: Vec<_> = validator
+        .fragments
+        .iter()
+        .filter(|(name, _)| !spread_fragments.contains(*name))
+        .map(|(_, fragment)| *fragment)
+        .collect();
+    unused_fragments.sort_by_key(|(_, fragment)| fragment.name);
+    for (path, fragment) in unused_fragments {
+        let mut scope = Scope::default();
+        validator.check_fragment(path, fragment, &mut scope);
+    }
+
+    Ok(OperationReport {
+        files: files.len(),
+        operations,
+        errors: validator.errors.into_iter().collect(),
+    })
+}
+
+/// Find every operation file in a directory and its subdirectories.
+fn find_operation_files(directory: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_operation_files(&path, paths)?;
+        } else if path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| OPERATION_FILE_EXTENSIONS.contains(&extension))
+        {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// The types of a schema, looked up by name.
+struct SchemaTypes<'s> {
+    types: HashMap<&'s str, &'s TypeDefinition<'s, &'s str>>,
+    query: &'s str,
+    mutation: Option<&'s str>,
+    subscription: Option<&'s str>,
+}
+
+impl<'s> SchemaTypes<'s> {
+    fn new(document: &'s schema::Document<'s, &'s str>) -> Self {
+        let mut types = HashMap::new();
+        let mut schema_definition = None;
+        for definition in &document.definitions {
+            match definition {
+                schema::Definition::TypeDefinition(definition) => {
+                    types.insert(type_definition_name(definition), definition);
+                }
+                schema::Definition::SchemaDefinition(definition) => {
+                    schema_definition = Some(definition)
+                }
+                _ => {}
+            }
+        }
+
+        SchemaTypes {
+            query: schema_definition
+                .and_then(|definition| definition.query)
+                .unwrap_or("Query"),
+            mutation: match schema_definition {
+                Some(definition) => definition.mutation,
+                None => types.get("Mutation").map(|_| "Mutation"),
+            },
+            subscription: schema_definition.and_then(|definition| definition.subscription),
+            types,
+        }
+    }
+
+    /// Check if a type with a name exists.
+    fn exists(&self, name: &str) -> bool {
+        BUILT_IN_SCALARS.contains(&name) || self.types.contains_key(name)
+    }
+
+    /// Check if a type is a scalar or enum, which can't have fields selected.
+    fn is_leaf(&self, name: &str) -> bool {
+        BUILT_IN_SCALARS.contains(&name)
+            || matches!(
+                self.types.get(name),
+                Some(TypeDefinition::Scalar(_)) | Some(TypeDefinition::Enum(_))
+            )
+    }
+
+    /// Check if a type can be used for variables and arguments.
+    fn is_input(&self, name: &str) -> bool {
+        self.is_leaf(name) || matches!(self.types.get(name), Some(TypeDefinition::InputObject(_)))
+    }
+
+    /// Check if a type can have fields selected or fragments spread on it.
+    fn is_composite(&self, name: &str) -> bool {
+        matches!(
+            self.types.get(name),
+            Some(TypeDefinition::Object(_))
+                | Some(TypeDefinition::Interface(_))
+                | Some(TypeDefinition::Union(_))
+        )
+    }
+
+    /// Find a field of an object or interface type.
+    fn field(&self, type_name: &str, field_name: &str) -> Option<&'s schema::Field<'s, &'s str>> {
+        let fields = match self.types.get(type_name)? {
+            TypeDefinition::Object(object) => &object.fields,
+            TypeDefinition::Interface(interface) => &interface.fields,
+            _ => return None,
+        };
+        fields.iter().find(|field| field.name == field_name)
+    }
+}
+
+/// Get the name of a type defined in a schema.
+fn type_definition_name<'s>(definition: &TypeDefinition<'s, &'s str>) -> &'s str {
+    match definition {
+        TypeDefinition::Scalar(scalar) => scalar.name,
+        TypeDefinition::Object(object) => object.name,
+        TypeDefinition::Interface(interface) => interface.name,
+        TypeDefinition::Union(union) => union.name,
+        TypeDefinition::Enum(enumeration) => enumeration.name,
+        TypeDefinition::InputObject(input) => input.name,
+    }
+}
+
+/// Get the name of a type without its list and non-null wrappers.
+fn named_type<'a>(value_type: &Type<'a, &'a str>) -> &'a str {
+    match value_type {
+        Type::NamedType(name) => name,
+        Type::ListType(inner) | Type::NonNullType(inner) => named_type(inner),
+    }
+}
+
+/// Check if a variable of a type can be used where a value of another type is expected. A
+/// nullable variable can be used for a non-null value if either has a default.
+fn is_variable_allowed<'a, 'b>(
+    variable: &Type<'a, &'a str>,
+    location: &Type<'b, &'b str>,
+    has_default: bool,
+) -> bool {
+    match (variable, location) {
+        (Type::NonNullType(variable), Type::NonNullType(location)) => {
+            is_variable_allowed(variable, location, false)
+        }
+        (variable, Type::NonNullType(location)) => {
+            has_default && is_variable_allowed(variable, location, false)
+        }
+        (Type::NonNullType(variable), location) => is_variable_allowed(variable, location, false),
+        (Type::ListType(variable), Type::ListType(location)) => {
+            is_variable_allowed(variable, location, false)
+        }
+        (Type::NamedType(variable), Type::NamedType(location)) => variable == location,
+        _ => false,
+    }
+}
+
+/// The variables of the operation being checked, and the fragments spread in it so far.
+#[derive(Default)]
+struct Scope<'d> {
+    /// The variables the operation defines, or none when checking a fragment no operation spreads.
+    variables: Option<HashMap<&'d str, &'d VariableDefinition<'d, &'d str>>>,
+    used_variables: HashSet<&'d str>,
+    spread_fragments: HashSet<&'d str>,
+}
+
+/// Checks client operations against the types of a schema, collecting every problem found.
+struct Validator<'s, 'd> {
+    types: &'s SchemaTypes<'s>,
+    fragments: HashMap<&'d str, (&'d Path, &'d FragmentDefinition<'d, &'d str>)>,
+    errors: BTreeSet<OperationError>,
+}
+
+impl<'s, 'd> Validator<'s, 'd> {
+    fn error(&mut self, path: &Path, position: Pos, message: String) {
+        self.errors.insert(OperationError {
+            path: path.to_path_buf(),
+            position: Some((position.line, position.column)),
+            message,
+        });
+    }
+
+    /// Check an operation. This will return the names of the fragments spread in it.
+    fn check_operation(
+        &mut self,
+        path: &'d Path,
+        operation: &'d OperationDefinition<'d, &'d str>,
+    ) -> HashSet<&'d str> {
+        let (position, name, variable_definitions, directives, selection_set, root) =
+            match operation {
+                OperationDefinition::SelectionSet(selection_set) => (
+                    selection_set.span.0,
+                    None,
+                    &[][..],
+                    &[][..],
+                    selection_set,
+                    Some(self.types.query),
+                ),
+                OperationDefinition::Query(query) => (
+                    query.position,
+                    query.name,
+                    &query.variable_definitions[..],
+                    &query.directives[..],
+                    &query.selection_set,
+                    Some(self.types.query),
+                ),
+                OperationDefinition::Mutation(mutation) => (
+                    mutation.position,
+                    mutation.name,
+                    &mutation.variable_definitions[..],
+                    &mutation.directives[..],
+                    &mutation.selection_set,
+                    self.types.mutation,
+                ),
+                OperationDefinition::Subscription(subscription) => (
+                    subscription.position,
+                    subscription.name,
+                    &subscription.variable_definitions[..],
+                    &subscription.directives[..],
+                    &subscription.selection_set,
+                    self.types.subscription,
+                ),
+            };
+
+        let mut variables = HashMap::new();
+        for definition in variable_definitions {
+            let type_name = named_type(&definition.var_type);
+            if !self.types.exists(type_name) {
+                self.error(
+                    path,
+                    definition.position,
+                    format!("Unknown type \"{}\".", type_name),
+                );
+            } else if !self.types.is_input(type_name) {
+                self.error(
+                    path,
+                    definition.position,
+                    format!(
+                        "Variable \"${}\" can't be of non-input type \"{}\".",
+                        definition.name, definition.var_type
+                    ),
+                );
+            } else if let Some(default_value) = &definition.default_value {
+                let mut scope = Scope::default();
+                self.check_value(
+                    path,
+                    definition.position,
+                    default_value,
+                    &definition.var_type,
+                    false,
+                    &mut scope,
+                );
+            }
+
+            if variables.insert(definition.name, definition).is_some() {
+                self.error(
+                    path,
+                    definition.position,
+                    format!(
+                        "There can be only one variable named \"${}\".",
+                        definition.name
+                    ),
+                );
+            }
+        }
+
+        let mut scope = Scope {
+            variables: Some(variables),
+            ..Scope::default()
+        };
+        self.check_directives(path, directives, &mut scope);
+        match root {
+            Some(root) => self.check_selection_set(path, selection_set, root, &mut scope),
+            None => self.error(
+                path,
+                position,
+                "The schema doesn't support this kind of operation.".into(),
+            ),
+        }
+
+        for definition in variable_definitions {
+            if !scope.used_variables.contains(definition.name) {
+                self.error(
+                    path,
+                    definition.position,
+                    format!(
+                        "Variable \"${}\" is never used in operation \"{}\".",
+                        definition.name,
+                        name.unwrap_or("anonymous")
+                    ),
+                );
+            }
+        }
+
+        scope.spread_fragments
+    }
+
+    /// Check a fragment against the type it is spread on.
+    fn check_fragment(
+        &mut self,
+        path: &'d Path,
+        fragment: &'d FragmentDefinition<'d, &'d str>,
+        scope: &mut Scope<'d>,
+    ) {
+        let TypeCondition::On(type_name) = fragment.type_condition;
+        self.check_directives(path, &fragment.directives, scope);
+        if !self.types.is_composite(type_name) {
+            self.error(
+                path,
+                fragment.position,
+                format!(
+                    "Fragment \"{}\" can't be spread on unknown or leaf type \"{}\".",
+                    fragment.name, type_name
+                ),
+            );
+            return;
+        }
+
+        self.check_selection_set(path, &fragment.selection_set, type_name, scope);
+    }
+
+    fn check_selection_set(
+        &mut self,
+        path: &'d Path,
+        selection_set: &'d SelectionSet<'d, &'d str>,
+        type_name: &str,
+        scope: &mut Scope<'d>,
+    ) {
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    self.check_directives(path, &field.directives, scope);
+                    if field.name == "__typename" {
+                        continue;
+                    }
+                    // Introspection fields are resolved by GraphQL itself, so they aren't checked.
+                    if type_name == self.types.query
+                        && (field.name == "__schema" || field.name == "__type")
+                    {
+                        continue;
+                    }
+
+                    let definition = match self.types.field(type_name, field.name) {
+                        Some(definition) => definition,
+                        None => {
+                            self.error(
+                                path,
+                                field.position,
+                                format!(
+                                    "Cannot query field \"{}\" on type \"{}\".",
+                                    field.name, type_name
+                                ),
+                            );
+                            continue;
+                        }
+                    };
+
+                    let owner = format!("Field \"{}.{}\"", type_name, field.name);
+                    self.check_arguments(
+                        path,
+                        field.position,
+                        &owner,
+                        &field.arguments,
+                        &definition.arguments,
+                        scope,
+                    );
+
+                    let field_type = named_type(&definition.field_type);
+                    if self.types.is_leaf(field_type) {
+                        if !field.selection_set.items.is_empty() {
+                            self.error(
+                                path,
+                                field.position,
+                                format!(
+                                    "{} of type \"{}\" can't have a selection of subfields.",
+                                    owner, definition.field_type
+                                ),
+                            );
+                        }
+                    } else if field.selection_set.items.is_empty() {
+                        self.error(
+                            path,
+                            field.position,
+                            format!(
+                                "{} of type \"{}\" must have a selection of subfields.",
+                                owner, definition.field_type
+                            ),
+                        );
+                    } else {
+                        self.check_selection_set(path, &field.selection_set, field_type, scope);
+                    }
+                }
+                Selection::FragmentSpread(spread) => {
+                    self.check_directives(path, &spread.directives, scope);
+                    match self.fragments.get(spread.fragment_name).copied() {
+                        Some((fragment_path, fragment)) => {
+                            // Each fragment is only checked once per operation, which also stops
+                            // fragments that spread themselves from being checked forever.
+                            if scope.spread_fragments.insert(fragment.name) {
+                                self.check_fragment(fragment_path, fragment, scope);
+                            }
+                        }
+                        None => self.error(
+                            path,
+                            spread.position,
+                            format!("Unknown fragment \"{}\".", spread.fragment_name),
+                        ),
+                    }
+                }
+                Selection::InlineFragment(fragment) => {
+                    self.check_directives(path, &fragment.directives, scope);
+                    let fragment_type = match &fragment.type_condition {
+                        Some(TypeCondition::On(fragment_type)) => *fragment_type,
+                        None => type_name,
+                    };
+                    if self.types.is_composite(fragment_type) {
+                        self.check_selection_set(
+                            path,
+                            &fragment.selection_set,
+                            fragment_type,
+                            scope,
+                        );
+                    } else {
+                        self.error(
+                            path,
+                            fragment.position,
+                            format!(
+                                "Fragment can't be spread on unknown or leaf type \"{}\".",
+                                fragment_type
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check the "@include" and "@skip" directives, the only ones that can be used in operations.
+    fn check_directives(
+        &mut self,
+        path: &'d Path,
+        directives: &'d [Directive<'d, &'d str>],
+        scope: &mut Scope<'d>,
+    ) {
+        let condition = [schema::InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "if",
+            value_type: Type::NonNullType(Box::new(Type::NamedType("Boolean"))),
+            default_value: None,
+            directives: Vec::new(),
+        }];
+
+        for directive in directives {
+            if directive.name != "include" && directive.name != "skip" {
+                self.error(
+                    path,
+                    directive.position,
+                    format!("Unknown directive \"@{}\".", directive.name),
+                );
+                continue;
+            }
+
+            self.check_arguments(
+                path,
+                directive.position,
+                &format!("Directive \"@{}\"", directive.name),
+                &directive.arguments,
+                &condition,
+                scope,
+            );
+        }
+    }
+
+    /// Check the arguments passed to a field or directive against the arguments it accepts.
+    fn check_arguments<'a>(
+        &mut self,
+        path: &'d Path,
+        position: Pos,
+        owner: &str,
+        arguments: &'d [(&'d str, Value<'d, &'d str>)],
+        definitions: &[schema::InputValue<'a, &'a str>],
+        scope: &mut Scope<'d>,
+    ) {
+        for (name, _) in arguments {
+            if !definitions
+                .iter()
+                .any(|definition| definition.name == *name)
+            {
+                self.error(
+                    path,
+                    position,
+                    format!("{} has no argument \"{}\".", owner, name),
+                );
+            }
+        }
+
+        for definition in definitions {
+            match arguments.iter().find(|(name, _)| *name == definition.name) {
+                Some((_, value)) => self.check_value(
+                    path,
+                    position,
+                    value,
+                    &definition.value_type,
+                    definition.default_value.is_some(),
+                    scope,
+                ),
+                None => {
+                    if matches!(definition.value_type, Type::NonNullType(_))
+                        && definition.default_value.is_none()
+                    {
+                        self.error(
+                            path,
+                            position,
+                            format!(
+                                "{} argument \"{}\" of type \"{}\" is required but not provided.",
+                                owner, definition.name, definition.value_type
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check a value passed where a value of a type is expected, and mark the variables in it as
+    /// used.
+    fn check_value<'a>(
+        &mut self,
+        path: &'d Path,
+        position: Pos,
+        value: &'d Value<'d, &'d str>,
+        expected: &Type<'a, &'a str>,
+        has_default: bool,
+        scope: &mut Scope<'d>,
+    ) {
+        if let Value::Variable(name) = value {
+            scope.used_variables.insert(name);
+            let definition = match &scope.variables {
+                Some(variables) => variables.get(name).copied(),
+                None => return,
+            };
+            match definition {
+                Some(definition) => {
+                    let has_default = has_default
+                        || !matches!(definition.default_value, None | Some(Value::Null));
+                    if !is_variable_allowed(&definition.var_type, expected, has_default) {
+                        self.error(
+                            path,
+                            position,
+                            format!(
+                                "Variable \"${}\" of type \"{}\" can't be used where \"{}\" is expected.",
+                                name, definition.var_type, expected
+                            ),
+                        );
+                    }
+                }
+                None => self.error(
+                    path,
+                    position,
+                    format!("Variable \"${}\" is not defined.", name),
+                ),
+            }
+            return;
+        }
+
+        let mismatch = |value: &Value<'d, &'d str>| {
+            format!(
+                "Expected a value of type \"{}\", found {}.",
+                expected, value
+            )
+        };
+        match (expected, value) {
+            (Type::NonNullType(_), Value::Null) => self.error(path, position, mismatch(value)),
+            (Type::NonNullType(inner), value) => {
+                self.check_value(path, position, value, inner, false, scope)
+            }
+            (_, Value::Null) => {}
+            (Type::ListType(inner), Value::List(items)) => {
+                for item in items {
+                    self.check_value(path, position, item, inner, false, scope);
+                }
+            }
+            // A single value is accepted where a list is expected, as if it were a list of one.
+            (Type::ListType(inner), value) => {
+                self.check_value(path, position, value, inner, false, scope)
+            }
+            (Type::NamedType(name), value) => {
+                let types = self.types;
+                let valid = match (*name, value) {
+                    ("Int", Value::Int(_))
+                    | ("Float", Value::Int(_))
+                    | ("Float", Value::Float(_))
+                    | ("String", Value::String(_))
+                    | ("ID", Value::String(_))
+                    | ("ID", Value::Int(_))
+                    | ("Boolean", Value::Boolean(_)) => true,
+                    (name, _) if BUILT_IN_SCALARS.contains(&name) => false,
+                    (name, value) => match types.types.get(name) {
+                        // Custom scalars parse their own values, so any value is let through.
+                        Some(TypeDefinition::Scalar(_)) => true,
+                        Some(TypeDefinition::Enum(enumeration)) => match value {
+                            Value::Enum(value) => enumeration
+                                .values
+                                .iter()
+                                .any(|enum_value| enum_value.name == *value),
+                            _ => false,
+                        },
+                        Some(TypeDefinition::InputObject(input)) => match value {
+                            Value::Object(fields) => {
+                                self.check_input_object(path, position, input, fields, scope);
+                                true
+                            }
+                            _ => false,
+                        },
+                        _ => false,
+                    },
+                };
+                if !valid {
+                    self.error(path, position, mismatch(value));
+                }
+            }
+        }
+    }
+
+    /// Check the fields of an input object value.
+    fn check_input_object<'a>(
+        &mut self,
+        path: &'d Path,
+        position: Pos,
+        input: &schema::InputObjectType<'a, &'a str>,
+        fields: &'d BTreeMap<&'d str, Value<'d, &'d str>>,
+        scope: &mut Scope<'d>,
+    ) {
+        for name in fields.keys() {
+            if !input.fields.iter().any(|field| field.name == *name) {
+                self.error(
+                    path,
+                    position,
+                    format!("Input type \"{}\" has no field \"{}\".", input.name, name),
+                );
+            }
+        }
+
+        for field in &input.fields {
+            match fields.get(field.name) {
+                Some(value) => self.check_value(
+                    path,
+                    position,
+                    value,
+                    &field.value_type,
+                    field.default_value.is_some(),
+                    scope,
+                ),
+                None => {
+                    if matches!(field.value_type, Type::NonNullType(_))
+                        && field.default_value.is_none()
+                    {
+                        self.error(
+                            path,
+                            position,
+                            format!(
+                                "Input type \"{}\" field \"{}\" of type \"{}\" is required but not provided.",
+                                input.name, field.name, field.value_type
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use juniper::GraphQLEnum;
+
+use crate::models::Membership;
+
+/// Maximum number of characters in an organization's name.
+pub const MAX_NAME_LENGTH: usize = 64;
+/// Default number of members returned when listing an organization's members.
+pub const DEFAULT_PAGE_SIZE: i32 = 50;
+/// Maximum number of members returned when listing an organization's members.
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+/// What a member of an organization can do in it.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "What a member of an organization can do in it.")]
+pub enum OrganizationRole {
+    #[graphql(description = "Can manage every member, including other owners.")]
+    Owner,
+    #[graphql(
+        description = "Can invite, remove and change the roles of members that aren't owners."
+    )]
+    Admin,
+    #[graphql(description = "Can see the organization and its members.")]
+    Member,
+}
+
+impl OrganizationRole {
+    /// The name the role is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrganizationRole::Owner => "owner",
+            OrganizationRole::Admin => "admin",
+            OrganizationRole::Member => "member",
+        }
+    }
+
+    /// Check if a member with this role can manage members with a role, meaning invite them,
+    /// remove them, or give them or take away the role. Only owners can manage other owners.
+    pub fn can_manage(self, role: OrganizationRole) -> bool {
+        match self {
+            OrganizationRole::Owner => true,
+            OrganizationRole::Admin => role != OrganizationRole::Owner,
+            OrganizationRole::Member => false,
+        }
+    }
+}
+
+impl Display for OrganizationRole {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for OrganizationRole {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "owner" => Ok(OrganizationRole::Owner),
+            "admin" => Ok(OrganizationRole::Admin),
+            "member" => Ok(OrganizationRole::Member),
+            _ => Err(anyhow!("Unknown organization role: {}", value)),
+        }
+    }
+}
+
+/// The outcome of an attempt to change the members of an organization.
+#[derive(Debug, Clone)]
+pub enum MembershipResult {
+    /// The membership was created, changed or removed, and is returned as it is afterwards, or
+    /// as it was before being removed.
+    Changed(Membership),
+    /// The organization doesn't exist, or the user making the change isn't a member of it.
+    OrganizationNotFound,
+    /// The user making the change isn't allowed to make it.
+    Forbidden,
+    /// The user being invited doesn't exist.
+    UserNotFound,
+    /// The user being changed isn't a member of or invited to the organization.
+    MemberNotFound,
+    /// The user being invited is already a member of or invited to the organization.
+    AlreadyMember,
+    /// The change would leave the organization without an owner.
+    LastOwner,
+}
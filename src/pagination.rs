@@ -0,0 +1,293 @@
+use chrono::{DateTime, Utc};
+use juniper::{graphql_object, GraphQLEnum};
+use uuid::Uuid;
+
+use crate::context::Context;
+use crate::models::User;
+use crate::schema::InvalidInput;
+
+/// Number of items returned when a page size isn't specified.
+const DEFAULT_PAGE_SIZE: usize = 20;
+/// Maximum number of items that can be returned in a single page.
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Orderings that can be used when paginating through users.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[graphql(description = "Orderings that can be used when paginating through users.")]
+pub enum UserOrder {
+    #[graphql(description = "Oldest users first.")]
+    CreatedAtAsc,
+    #[graphql(description = "Newest users first.")]
+    CreatedAtDesc,
+    #[graphql(description = "Users ordered alphabetically by username.")]
+    UsernameAsc,
+    #[graphql(description = "Users ordered reverse alphabetically by username.")]
+    UsernameDesc,
+}
+
+impl UserOrder {
+    /// The column users are sorted by.
+    pub fn column(self) -> &'static str {
+        match self {
+            UserOrder::CreatedAtAsc | UserOrder::CreatedAtDesc => "created_at",
+            UserOrder::UsernameAsc | UserOrder::UsernameDesc => "username",
+        }
+    }
+
+    /// Specifies if users are sorted in ascending order.
+    pub fn is_ascending(self) -> bool {
+        matches!(self, UserOrder::CreatedAtAsc | UserOrder::UsernameAsc)
+    }
+
+    /// Get the value a user is sorted by, as it is stored in a cursor.
+    fn sort_value(self, user: &User) -> String {
+        match self {
+            UserOrder::CreatedAtAsc | UserOrder::CreatedAtDesc => user.created_at.to_rfc3339(),
+            UserOrder::UsernameAsc | UserOrder::UsernameDesc => user.username.clone(),
+        }
+    }
+}
+
+/// The value a cursor points at, typed to match the column being sorted by.
+pub enum SortValue {
+    /// A value of a timestamp column.
+    Timestamp(DateTime<Utc>),
+    /// A value of a text column.
+    Text(String),
+}
+
+/// A decoded cursor, pointing at a position in an ordered list of users. Cursors are opaque to
+/// clients and are encoded as base64 strings.
+pub struct Cursor {
+    /// The value of the sorted column at the cursor's position.
+    pub value: SortValue,
+    /// The ID of the user at the cursor's position. This breaks ties between equal sort values.
+    pub id: Uuid,
+}
+
+impl Cursor {
+    /// Encode a cursor pointing at a user in a specified ordering.
+    pub fn encode(order: UserOrder, user: &User) -> String {
+        base64::encode(format!(
+            "{}|{}|{}",
+            order.column(),
+            user.id,
+            order.sort_value(user)
+        ))
+    }
+
+    /// Attempt to decode a cursor for a specified ordering. This will return none if the cursor is
+    /// malformed or was created for a different ordering.
+    pub fn decode(order: UserOrder, cursor: &str) -> Option<Self> {
+        let decoded = String::from_utf8(base64::decode(cursor).ok()?).ok()?;
+        let mut parts = decoded.splitn(3, '|');
+        if parts.next()? != order.column() {
+            return None;
+        }
+
+        let id = Uuid::parse_str(parts.next()?).ok()?;
+        let value = match order {
+            UserOrder::CreatedAtAsc | UserOrder::CreatedAtDesc => {
+                SortValue::Timestamp(parts.next()?.parse().ok()?)
+            }
+            UserOrder::UsernameAsc | UserOrder::UsernameDesc => {
+                SortValue::Text(parts.next()?.into())
+            }
+        };
+
+        Some(Cursor { value, id })
+    }
+}
+
+/// A validated request for a page of users, built from Relay-style pagination arguments.
+pub struct PageRequest {
+    /// The ordering of the users being paginated through.
+    pub order: UserOrder,
+    /// The maximum number of users to return.
+    pub size: usize,
+    /// Specifies if users are returned before the cursor rather than after it.
+    pub is_backward: bool,
+    /// The cursor the page starts after, or ends before if paginating backward.
+    pub cursor: Option<Cursor>,
+}
+
+impl PageRequest {
+    /// Validate Relay-style pagination arguments. Either "first" and "after" can be used to
+    /// paginate forward or "last" and "before" can be used to paginate backward, but not both.
+    pub fn new(
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+        order: Option<UserOrder>,
+    ) -> Result<Self, InvalidInput> {
+        let order = order.unwrap_or(UserOrder::CreatedAtAsc);
+        let is_backward = last.is_some() || before.is_some();
+        if is_backward && (first.is_some() || after.is_some()) {
+            return Err(InvalidInput {
+                message: "Cannot paginate forward and backward at the same time.".into(),
+                code: "invalid-pagination",
+            });
+        }
+
+        let size = match if is_backward { last } else { first } {
+            Some(size) if size < 0 => {
+                return Err(InvalidInput {
+                    message: "Page size cannot be negative.".into(),
+                    code: "invalid-pagination",
+                })
+            }
+            Some(size) => (size as usize).min(MAX_PAGE_SIZE),
+            None => DEFAULT_PAGE_SIZE,
+        };
+
+        let cursor = match if is_backward { before } else { after } {
+            Some(cursor) => Some(Cursor::decode(order, &cursor).ok_or(InvalidInput {
+                message: "Invalid cursor.".into(),
+                code: "invalid-cursor",
+            })?),
+            None => None,
+        };
+
+        Ok(PageRequest {
+            order,
+            size,
+            is_backward,
+            cursor,
+        })
+    }
+}
+
+/// Information about a page of results.
+#[derive(Debug, Clone)]
+pub struct PageInfo {
+    /// Specifies if there are more results after this page.
+    pub has_next_page: bool,
+    /// Specifies if there are more results before this page.
+    pub has_previous_page: bool,
+    /// The cursor of the first result in the page.
+    pub start_cursor: Option<String>,
+    /// The cursor of the last result in the page.
+    pub end_cursor: Option<String>,
+}
+
+#[graphql_object(description = "Information about a page of results.")]
+impl PageInfo {
+    #[graphql(description = "Specifies if there are more results after this page.")]
+    pub fn has_next_page(&self) -> bool {
+        self.has_next_page
+    }
+
+    #[graphql(description = "Specifies if there are more results before this page.")]
+    pub fn has_previous_page(&self) -> bool {
+        self.has_previous_page
+    }
+
+    #[graphql(description = "The cursor of the first result in the page.")]
+    pub fn start_cursor(&self) -> &Option<String> {
+        &self.start_cursor
+    }
+
+    #[graphql(description = "The cursor of the last result in the page.")]
+    pub fn end_cursor(&self) -> &Option<String> {
+        &self.end_cursor
+    }
+}
+
+/// A user in a page of users, along with its cursor.
+#[derive(Debug, Clone)]
+pub struct UserEdge {
+    /// A cursor pointing at the user.
+    pub cursor: String,
+    /// The user.
+    pub node: User,
+}
+
+#[graphql_object(
+    context = Context,
+    description = "A user in a page of users, along with its cursor."
+)]
+impl UserEdge {
+    #[graphql(description = "A cursor pointing at the user.")]
+    pub fn cursor(&self) -> &str {
+        &self.cursor
+    }
+
+    #[graphql(description = "The user.")]
+    pub fn node(&self) -> &User {
+        &self.node
+    }
+}
+
+/// A page of users.
+#[derive(Debug, Clone)]
+pub struct UserConnection {
+    /// The users in the page.
+    pub edges: Vec<UserEdge>,
+    /// Information about the page.
+    pub page_info: PageInfo,
+    /// The total number of users across every page.
+    pub total_count: i64,
+}
+
+impl UserConnection {
+    /// Build a page of users. Users must be in the page's order and, if the page request has more
+    /// users than fit in the page, include one extra user past the end of the page.
+    pub fn new(request: &PageRequest, mut users: Vec<User>, total_count: i64) -> Self {
+        let has_more = users.len() > request.size;
+        users.truncate(request.size);
+        if request.is_backward {
+            users.reverse();
+        }
+
+        let edges = users
+            .into_iter()
+            .map(|user| UserEdge {
+                cursor: Cursor::encode(request.order, &user),
+                node: user,
+            })
+            .collect::<Vec<_>>();
+
+        // A cursor always points at an existing user, so there are more users on the side of the
+        // page the cursor is on.
+        let has_cursor = request.cursor.is_some();
+        let page_info = PageInfo {
+            has_next_page: if request.is_backward {
+                has_cursor
+            } else {
+                has_more
+            },
+            has_previous_page: if request.is_backward {
+                has_more
+            } else {
+                has_cursor
+            },
+            start_cursor: edges.first().map(|edge| edge.cursor.clone()),
+            end_cursor: edges.last().map(|edge| edge.cursor.clone()),
+        };
+
+        UserConnection {
+            edges,
+            page_info,
+            total_count,
+        }
+    }
+}
+
+#[graphql_object(context = Context, description = "A page of users.")]
+impl UserConnection {
+    #[graphql(description = "The users in the page.")]
+    pub fn edges(&self) -> &Vec<UserEdge> {
+        &self.edges
+    }
+
+    #[graphql(description = "Information about the page.")]
+    pub fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
+
+    #[graphql(description = "The total number of users across every page.")]
+    pub fn total_count(&self) -> i32 {
+        self.total_count as i32
+    }
+}
@@ -0,0 +1,169 @@
+use zxcvbn::feedback::{Feedback, Suggestion, Warning};
+use zxcvbn::zxcvbn;
+
+/// Max number of characters in a password.
+pub const MAX_PASSWORD_LENGTH: usize = 255;
+/// The most commonly used passwords, one per line in lowercase, from most to least common.
+const BANNED_PASSWORDS: &str = include_str!("../data/banned-passwords.txt");
+/// Details of a user shorter than this aren't checked for in their password, as short strings
+/// appear in passwords by chance.
+const MIN_USER_DETAIL_LENGTH: usize = 3;
+
+/// Rules passwords must follow when users start using them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// The min number of characters in a password.
+    pub min_length: usize,
+    /// The min zxcvbn score of a password, from 0 for passwords guessed within a thousand tries to
+    /// 4 for passwords that take over ten billion. Zero disables the check.
+    pub min_score: u8,
+    /// Specifies if the 10,000 most commonly used passwords are rejected.
+    pub reject_common: bool,
+    /// Specifies if passwords containing the user's username or email address are rejected.
+    pub reject_user_details: bool,
+}
+
+/// A problem with a password, along with feedback clients can show to help users choose a better
+/// one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPassword {
+    /// A human-readable description of the problem.
+    pub message: String,
+    /// A stable error code for clients to match on.
+    pub code: &'static str,
+    /// A warning explaining why the password is easy to guess, if there is one.
+    pub warning: Option<String>,
+    /// Suggestions for choosing a password that is harder to guess.
+    pub suggestions: Vec<String>,
+}
+
+impl InvalidPassword {
+    fn new(message: impl Into<String>, code: &'static str) -> Self {
+        InvalidPassword {
+            message: message.into(),
+            code,
+            warning: None,
+            suggestions: Vec::new(),
+        }
+    }
+
+    fn with_feedback(mut self, warning: Option<String>, suggestions: Vec<String>) -> Self {
+        self.warning = warning;
+        self.suggestions = suggestions;
+        self
+    }
+}
+
+/// Check that a password is within the policy's length limits. This will return the problem found
+/// with the password, or none if its length is valid.
+pub fn check_password_length(policy: &PasswordPolicy, password: &str) -> Option<InvalidPassword> {
+    let length = password.chars().count();
+    if length < policy.min_length {
+        return Some(InvalidPassword::new(
+            format!(
+                "Password must be at least {} characters.",
+                policy.min_length
+            ),
+            "password-too-short",
+        ));
+    }
+
+    if length > MAX_PASSWORD_LENGTH {
+        return Some(InvalidPassword::new(
+            "Password cannot exceed 255 characters.",
+            "password-too-long",
+        ));
+    }
+
+    None
+}
+
+/// Check a password a user is about to start using against a policy. The user's details, such as
+/// their username and email address, are checked for in the password and make it easier to guess
+/// when scored. This will return the first problem found with the password, or none if it is
+/// valid.
+pub fn check_password(
+    policy: &PasswordPolicy,
+    password: &str,
+    user_details: &[&str],
+) -> Option<InvalidPassword> {
+    if let Some(invalid) = check_password_length(policy, password) {
+        return Some(invalid);
+    }
+
+    let lowercase = password.to_lowercase();
+    if policy.reject_common && BANNED_PASSWORDS.lines().any(|banned| banned == lowercase) {
+        return Some(
+            InvalidPassword::new("Password is too commonly used.", "password-too-common")
+                .with_feedback(
+                    Some(Warning::ThisIsACommonPassword.to_string()),
+                    vec![Suggestion::AddAnotherWordOrTwo.to_string()],
+                ),
+        );
+    }
+
+    if policy.reject_user_details && contains_user_detail(&lowercase, user_details) {
+        return Some(
+            InvalidPassword::new(
+                "Password cannot contain your username or email address.",
+                "password-contains-user-details",
+            )
+            .with_feedback(
+                None,
+                vec!["Avoid using your username or email address in your password.".into()],
+            ),
+        );
+    }
+
+    if policy.min_score > 0 {
+        // Scoring only fails for empty passwords, which are as easy to guess as it gets.
+        let (score, feedback) = match zxcvbn(password, user_details) {
+            Ok(entropy) => (entropy.score(), entropy.feedback().clone()),
+            Err(_) => (0, None),
+        };
+        if score < policy.min_score {
+            let (warning, suggestions) = describe_feedback(feedback.as_ref());
+            return Some(
+                InvalidPassword::new("Password is too easy to guess.", "password-too-weak")
+                    .with_feedback(warning, suggestions),
+            );
+        }
+    }
+
+    None
+}
+
+/// Check if a lowercase password contains any of a user's details, or the part of their email
+/// address before the "@".
+fn contains_user_detail(password: &str, user_details: &[&str]) -> bool {
+    user_details
+        .iter()
+        .flat_map(|detail| {
+            let local_part = detail.split_once('@').map(|(local_part, _)| local_part);
+            std::iter::once(*detail).chain(local_part)
+        })
+        .map(str::to_lowercase)
+        .filter(|detail| detail.chars().count() >= MIN_USER_DETAIL_LENGTH)
+        .any(|detail| password.contains(&detail))
+}
+
+/// Convert zxcvbn's feedback into a warning and suggestions for users. Weak passwords always get
+/// at least one suggestion, even when zxcvbn has none.
+fn describe_feedback(feedback: Option<&Feedback>) -> (Option<String>, Vec<String>) {
+    let warning = feedback
+        .and_then(Feedback::warning)
+        .map(|warning| warning.to_string());
+    let mut suggestions = feedback
+        .map(|feedback| {
+            feedback
+                .suggestions()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if suggestions.is_empty() {
+        suggestions.push(Suggestion::AddAnotherWordOrTwo.to_string());
+    }
+    (warning, suggestions)
+}
@@ -0,0 +1,73 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use async_std::task;
+use juniper::GraphQLEnum;
+use tide::log;
+
+use crate::executor::Executor;
+use crate::graphql::request_id;
+use crate::state::State;
+
+/// Max number of due actions executed by a single check.
+pub const EXECUTE_BATCH_SIZE: i64 = 100;
+/// How long to wait between checks for actions whose grace period is over.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A destructive action that is only done once its grace period is over, so it can be undone
+/// until then.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "A destructive action that can be undone until its grace period is over.")]
+pub enum PendingActionKind {
+    #[graphql(description = "The user deleted their account.")]
+    DeleteAccount,
+}
+
+impl PendingActionKind {
+    /// The name the kind is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PendingActionKind::DeleteAccount => "delete-account",
+        }
+    }
+}
+
+impl Display for PendingActionKind {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for PendingActionKind {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "delete-account" => Ok(PendingActionKind::DeleteAccount),
+            _ => Err(anyhow!("Unknown pending action kind: {}", value)),
+        }
+    }
+}
+
+/// Periodically do the pending actions whose grace period is over. Each check is handled as its
+/// own request, so what it does can be traced back to its log lines. This runs until the server
+/// stops.
+pub async fn run_pending_action_worker(state: State) {
+    loop {
+        let executor = Executor::new(state.clone(), request_id(None), None, None);
+        loop {
+            match executor.execute_due_actions().await {
+                Ok(count) if count < EXECUTE_BATCH_SIZE as usize => break,
+                Ok(_) => {}
+                Err(error) => {
+                    log::error!("Failed to execute pending actions: {}", error);
+                    break;
+                }
+            }
+        }
+
+        task::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::anyhow;
+use redis::aio::ConnectionLike;
+use redis::{AsyncCommands, AsyncIter};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::errors::ApiError;
+
+/// Message returned for persisted queries that aren't known. Apollo clients look for this exact
+/// message to know they should retry with the full query text.
+pub const PERSISTED_QUERY_NOT_FOUND_ERROR_MESSAGE: &str = "PersistedQueryNotFound";
+/// Error code returned for persisted queries that aren't known.
+pub const PERSISTED_QUERY_NOT_FOUND_ERROR_CODE: &str = "persisted-query-not-found";
+/// Error code returned for persisted queries sent with an unsupported version or a hash that
+/// doesn't match the query text.
+pub const INVALID_PERSISTED_QUERY_ERROR_CODE: &str = "invalid-persisted-query";
+/// Message returned for queries that aren't registered when only registered queries are allowed.
+pub const PERSISTED_QUERY_NOT_ALLOWED_ERROR_MESSAGE: &str =
+    "Only registered persisted queries can be executed.";
+/// Error code returned for queries that aren't registered when only registered queries are
+/// allowed.
+pub const PERSISTED_QUERY_NOT_ALLOWED_ERROR_CODE: &str = "persisted-query-not-allowed";
+
+/// The only version of the persisted query protocol that is supported.
+const PERSISTED_QUERY_VERSION: u32 = 1;
+/// How long queries registered by clients are kept after being registered. Clients register them
+/// again once they expire, so this only bounds how much space unused queries take up.
+const CACHED_QUERY_EXPIRATION_SECONDS: usize = 30 * 24 * 60 * 60;
+
+/// The persisted query extension of a GraphQL request, as sent by Apollo clients.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedQueryExtension {
+    version: u32,
+    sha256_hash: String,
+}
+
+/// A persisted query manifest, as generated by Apollo's client tooling.
+#[derive(Debug, Deserialize)]
+pub struct PersistedQueryManifest {
+    pub operations: Vec<PersistedOperation>,
+}
+
+/// An operation in a persisted query manifest.
+#[derive(Debug, Deserialize)]
+pub struct PersistedOperation {
+    /// The SHA-256 hash of the operation's query text.
+    pub id: String,
+    /// The query text of the operation.
+    pub body: String,
+}
+
+/// Get the Redis key of a query registered by a client through the persisted query protocol.
+fn cached_key(hash: &str) -> String {
+    format!("persisted-queries/cached/{}", hash)
+}
+
+/// Get the Redis key of a query registered ahead of time by the server's operators.
+fn allowed_key(hash: &str) -> String {
+    format!("persisted-queries/allowed/{}", hash)
+}
+
+/// Get the hash persisted queries are identified by, the hex-encoded SHA-256 hash of their text.
+pub fn query_hash(query: &str) -> String {
+    format!("{:x}", Sha256::digest(query.as_bytes()))
+}
+
+/// Build an error rejecting a persisted query sent with an unsupported version or a hash that
+/// doesn't match its text.
+fn invalid_persisted_query(message: &str) -> ApiError {
+    ApiError::Validation {
+        message: message.into(),
+        code: INVALID_PERSISTED_QUERY_ERROR_CODE,
+    }
+}
+
+/// Build an error rejecting a query that isn't registered when only registered queries are
+/// allowed.
+fn not_allowed() -> ApiError {
+    ApiError::Validation {
+        message: PERSISTED_QUERY_NOT_ALLOWED_ERROR_MESSAGE.into(),
+        code: PERSISTED_QUERY_NOT_ALLOWED_ERROR_CODE,
+    }
+}
+
+/// Resolve the query of a raw GraphQL request. Requests sent with only the hash of a persisted
+/// query have its text filled in, and requests sent with both register the text under the hash.
+/// If only registered queries are allowed, requests with any other query are rejected and clients
+/// can't register queries of their own.
+pub async fn resolve_query<C: ConnectionLike + Send>(
+    redis: &mut C,
+    request: &mut Value,
+    registered_only: bool,
+) -> Result<(), ApiError> {
+    let extension = match request.pointer("/extensions/persistedQuery") {
+        Some(extension) => Some(
+            serde_json::from_value::<PersistedQueryExtension>(extension.clone()).map_err(|_| {
+                invalid_persisted_query("The persisted query extension is invalid.")
+            })?,
+        ),
+        None => None,
+    };
+    if let Some(extension) = &extension {
+        if extension.version != PERSISTED_QUERY_VERSION {
+            return Err(invalid_persisted_query(
+                "The persisted query version is not supported.",
+            ));
+        }
+    }
+
+    let query = request
+        .get("query")
+        .and_then(Value::as_str)
+        .map(String::from);
+    match (query, extension) {
+        // Only the hash was sent, so look up the text it was registered with.
+        (None, Some(extension)) => {
+            let hash = extension.sha256_hash.to_lowercase();
+            let mut query = redis.get::<_, Option<String>>(allowed_key(&hash)).await?;
+            if query.is_none() && !registered_only {
+                query = redis.get(cached_key(&hash)).await?;
+            }
+
+            match query {
+                Some(query) => {
+                    request["query"] = Value::String(query);
+                    Ok(())
+                }
+                None if registered_only => Err(not_allowed()),
+                None => Err(ApiError::Validation {
+                    message: PERSISTED_QUERY_NOT_FOUND_ERROR_MESSAGE.into(),
+                    code: PERSISTED_QUERY_NOT_FOUND_ERROR_CODE,
+                }),
+            }
+        }
+        // The text was sent along with its hash, so register it for later requests.
+        (Some(query), Some(extension)) => {
+            let hash = query_hash(&query);
+            if extension.sha256_hash.to_lowercase() != hash {
+                return Err(invalid_persisted_query(
+                    "The persisted query hash does not match the query.",
+                ));
+            }
+
+            if registered_only {
+                if !redis.exists::<_, bool>(allowed_key(&hash)).await? {
+                    return Err(not_allowed());
+                }
+            } else {
+                redis
+                    .set_ex::<_, _, ()>(cached_key(&hash), query, CACHED_QUERY_EXPIRATION_SECONDS)
+                    .await?;
+            }
+
+            Ok(())
+        }
+        // A plain query can only be executed if it is registered when only those are allowed.
+        (Some(query), None) if registered_only => {
+            if redis
+                .exists::<_, bool>(allowed_key(&query_hash(&query)))
+                .await?
+            {
+                Ok(())
+            } else {
+                Err(not_allowed())
+            }
+        }
+        // Requests without a query are left for the GraphQL backend to reject.
+        _ => Ok(()),
+    }
+}
+
+/// Register the operations in a persisted query manifest, so they can be executed even when only
+/// registered queries are allowed. This will return the number of operations registered, or an
+/// error if any operation's ID isn't the hash of its query.
+pub async fn register_manifest<C: ConnectionLike + Send>(
+    redis: &mut C,
+    manifest: &PersistedQueryManifest,
+) -> anyhow::Result<usize> {
+    for operation in &manifest.operations {
+        if operation.id.to_lowercase() != query_hash(&operation.body) {
+            return Err(anyhow!(
+                "Persisted operation ID is not the SHA-256 hash of its body: {}",
+                operation.id
+            ));
+        }
+    }
+
+    for operation in &manifest.operations {
+        redis
+            .set::<_, _, ()>(allowed_key(&query_hash(&operation.body)), &operation.body)
+            .await?;
+    }
+
+    Ok(manifest.operations.len())
+}
+
+/// List the text of every query registered ahead of time by the server's operators.
+pub async fn registered_queries<C: ConnectionLike + Send>(
+    redis: &mut C,
+) -> anyhow::Result<Vec<String>> {
+    let mut keys = Vec::new();
+    let mut iter: AsyncIter<String> = redis.scan_match(allowed_key("*")).await?;
+    while let Some(key) = iter.next_item().await {
+        keys.push(key);
+    }
+
+    let mut queries = Vec::with_capacity(keys.len());
+    for key in keys {
+        if let Some(query) = redis.get::<_, Option<String>>(key).await? {
+            queries.push(query);
+        }
+    }
+
+    Ok(queries)
+}
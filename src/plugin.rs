@@ -0,0 +1,217 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use juniper::meta::{Field, MetaType, ObjectMeta};
+use juniper::{
+    Arguments, BoxFuture, DefaultScalarValue, ExecutionResult, Executor, GraphQLType, GraphQLValue,
+    GraphQLValueAsync, Registry,
+};
+use sqlx::migrate::Migration;
+
+use crate::context::Context;
+use crate::lifecycle::Subsystem;
+use crate::routes::RouteModule;
+use crate::seed::Seed;
+
+/// An extension to the server. Plugins can contribute additional GraphQL query and mutation
+/// fields, database migrations and HTTP routes, all of which are composed with the server's own
+/// at startup. They can also contribute seed data for local development.
+pub trait Plugin: Send + Sync {
+    /// A short, unique name for the plugin.
+    fn name(&self) -> &'static str;
+
+    /// Additional fields to add to the root query type.
+    fn query(&self) -> Option<Arc<dyn FieldSource>> {
+        None
+    }
+
+    /// Additional fields to add to the root mutation type.
+    fn mutation(&self) -> Option<Arc<dyn FieldSource>> {
+        None
+    }
+
+    /// Additional database migrations to run. Versions must not collide with the server's own
+    /// migrations or those of other plugins.
+    fn migrations(&self) -> Vec<Migration> {
+        Vec::new()
+    }
+
+    /// Additional HTTP routes to mount.
+    fn route_modules(&self) -> Vec<Box<dyn RouteModule>> {
+        Vec::new()
+    }
+
+    /// Additional seeds to run with the "seed" command when no fixtures file is given.
+    fn seeds(&self) -> Vec<Box<dyn Seed>> {
+        Vec::new()
+    }
+
+    /// Additional subsystems to start and shut down with the server. These can depend on the
+    /// server's own subsystems.
+    fn subsystems(&self) -> Vec<Arc<dyn Subsystem>> {
+        Vec::new()
+    }
+}
+
+/// A set of GraphQL fields that can be merged into a root type.
+pub trait FieldSource: Send + Sync {
+    /// Register the fields this source provides.
+    fn fields<'r>(&self, registry: &mut Registry<'r>) -> Vec<Field<'r, DefaultScalarValue>>;
+
+    /// Resolve one of the fields this source provides.
+    fn resolve_field<'a>(
+        &'a self,
+        field_name: &'a str,
+        arguments: &'a Arguments,
+        executor: &'a Executor<Context>,
+    ) -> BoxFuture<'a, ExecutionResult>;
+}
+
+/// Exposes every field of a GraphQL object type as a field source. This allows any type declared
+/// with the "graphql_object" macro to be merged into a root type.
+pub struct ObjectFields<T>(pub T);
+
+impl<T> FieldSource for ObjectFields<T>
+where
+    T: GraphQLType<Context = Context, TypeInfo = ()> + GraphQLValueAsync + Send + Sync,
+{
+    fn fields<'r>(&self, registry: &mut Registry<'r>) -> Vec<Field<'r, DefaultScalarValue>> {
+        match T::meta(&(), registry) {
+            MetaType::Object(ObjectMeta { fields, .. }) => fields,
+            _ => panic!("Field sources must be GraphQL object types."),
+        }
+    }
+
+    fn resolve_field<'a>(
+        &'a self,
+        field_name: &'a str,
+        arguments: &'a Arguments,
+        executor: &'a Executor<Context>,
+    ) -> BoxFuture<'a, ExecutionResult> {
+        self.0
+            .resolve_field_async(&(), field_name, arguments, executor)
+    }
+}
+
+/// Type info for a merged root type. This holds every source the root's fields come from.
+pub struct RootInfo {
+    name: &'static str,
+    description: &'static str,
+    sources: Vec<Arc<dyn FieldSource>>,
+    // Maps each field name to the index of the source providing it.
+    owners: HashMap<String, usize>,
+}
+
+impl RootInfo {
+    /// Create type info for a root type merging the fields of each source. This will panic if
+    /// two sources provide a field with the same name.
+    pub fn new(
+        name: &'static str,
+        description: &'static str,
+        sources: Vec<Arc<dyn FieldSource>>,
+    ) -> Self {
+        let mut registry = Registry::new(Default::default());
+        let mut owners = HashMap::new();
+        for (index, source) in sources.iter().enumerate() {
+            for field in source.fields(&mut registry) {
+                if owners.insert(field.name.clone(), index).is_some() {
+                    panic!(
+                        "Field '{}' is declared more than once on {}.",
+                        field.name, name
+                    );
+                }
+            }
+        }
+
+        RootInfo {
+            name,
+            description,
+            sources,
+            owners,
+        }
+    }
+}
+
+/// A root GraphQL type whose fields are merged from several field sources.
+pub struct MergedRoot;
+
+impl GraphQLType for MergedRoot {
+    fn name(info: &RootInfo) -> Option<&str> {
+        Some(info.name)
+    }
+
+    fn meta<'r>(info: &RootInfo, registry: &mut Registry<'r>) -> MetaType<'r>
+    where
+        DefaultScalarValue: 'r,
+    {
+        let fields = info
+            .sources
+            .iter()
+            .flat_map(|source| source.fields(registry))
+            .collect::<Vec<_>>();
+
+        registry
+            .build_object_type::<Self>(info, &fields)
+            .description(info.description)
+            .into_meta()
+    }
+}
+
+impl GraphQLValue for MergedRoot {
+    type Context = Context;
+    type TypeInfo = RootInfo;
+
+    fn type_name<'i>(&self, info: &'i RootInfo) -> Option<&'i str> {
+        Some(info.name)
+    }
+
+    fn concrete_type_name(&self, _context: &Context, info: &RootInfo) -> String {
+        info.name.into()
+    }
+}
+
+impl GraphQLValueAsync for MergedRoot {
+    fn resolve_field_async<'a>(
+        &'a self,
+        info: &'a RootInfo,
+        field_name: &'a str,
+        arguments: &'a Arguments,
+        executor: &'a Executor<Context>,
+    ) -> BoxFuture<'a, ExecutionResult> {
+        // Validation guarantees only declared fields are resolved, so every field has an owner.
+        let source = &info.sources[info.owners[field_name]];
+        source.resolve_field(field_name, arguments, executor)
+    }
+}
+
+/// Collect the migrations contributed by every plugin.
+pub fn migrations(plugins: &[Box<dyn Plugin>]) -> Vec<Migration> {
+    plugins
+        .iter()
+        .flat_map(|plugin| plugin.migrations())
+        .collect()
+}
+
+/// Collect the subsystems contributed by every plugin.
+pub fn subsystems(plugins: &[Box<dyn Plugin>]) -> Vec<Arc<dyn Subsystem>> {
+    plugins
+        .iter()
+        .flat_map(|plugin| plugin.subsystems())
+        .collect()
+}
+
+/// Collect the seeds contributed by every plugin.
+pub fn seeds(plugins: &[Box<dyn Plugin>]) -> Vec<Box<dyn Seed>> {
+    plugins.iter().flat_map(|plugin| plugin.seeds()).collect()
+}
+
+/// Create a migration for a plugin from a version number and SQL.
+pub fn migration(version: i64, description: &'static str, sql: &'static str) -> Migration {
+    Migration::new(
+        version,
+        Cow::Borrowed(description),
+        sqlx::migrate::MigrationType::Simple,
+        Cow::Borrowed(sql),
+    )
+}
@@ -0,0 +1,119 @@
+use std::future::Future;
+
+use anyhow::Result;
+use juniper::{graphql_value, FieldError, IntoFieldError, ScalarValue};
+
+/// Message of the error returned for fields the user making the request isn't allowed to see.
+pub const UNAUTHORIZED_FIELD_ERROR_MESSAGE: &str = "You are not allowed to see this field.";
+/// Error code returned for fields the user making the request isn't allowed to see.
+pub const UNAUTHORIZED_FIELD_ERROR_CODE: &str = "unauthorized";
+
+/// Only the user themselves or admins can see the user's email address.
+pub const USER_EMAIL: FieldPolicy = FieldPolicy {
+    visibility: Visibility::OwnerOrAdmins,
+    denial: Denial::Null,
+};
+/// Only the user themselves or admins can see if the user's email address is verified. Null
+/// already means the address isn't verified, so other users get an error instead.
+pub const USER_EMAIL_VERIFIED_AT: FieldPolicy = FieldPolicy {
+    visibility: Visibility::OwnerOrAdmins,
+    denial: Denial::Error,
+};
+/// Only the user themselves or admins can see if the user must reset their password. Null already
+/// means no reset is required, so other users get an error instead.
+pub const USER_PASSWORD_RESET_REQUIRED_AT: FieldPolicy = FieldPolicy {
+    visibility: Visibility::OwnerOrAdmins,
+    denial: Denial::Error,
+};
+/// Only admins can see if a user is under a legal hold, including the user themselves.
+pub const USER_LEGAL_HOLD_AT: FieldPolicy = FieldPolicy {
+    visibility: Visibility::Admins,
+    denial: Denial::Null,
+};
+
+/// Who can see a field of a record belonging to a user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    /// The user the record belongs to and admins.
+    OwnerOrAdmins,
+    /// Only admins.
+    Admins,
+}
+
+/// What a field resolves to for users that aren't allowed to see it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denial {
+    /// The field is null, as if it had no value.
+    Null,
+    /// The field fails with the "unauthorized" error code. This is used for fields where null
+    /// already means something.
+    Error,
+}
+
+/// The authorization policy of a field, declaring who can see it and what everyone else gets.
+/// Resolvers check the policies of their fields with `Context::authorize_field`, so every
+/// decision is made here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldPolicy {
+    /// Who can see the field.
+    pub visibility: Visibility,
+    /// What the field resolves to for everyone else.
+    pub denial: Denial,
+}
+
+impl FieldPolicy {
+    /// Decide if the user making the request can see the field, given whether they own the record
+    /// and a check of whether they are an admin. Admins are only checked when owning the record
+    /// isn't enough, as the check looks up the user's roles.
+    pub async fn decide<F>(self, is_owner: bool, is_admin: F) -> Result<FieldAccess>
+    where
+        F: Future<Output = Result<bool>>,
+    {
+        let visible = match self.visibility {
+            Visibility::OwnerOrAdmins => is_owner || is_admin.await?,
+            Visibility::Admins => is_admin.await?,
+        };
+
+        Ok(match (visible, self.denial) {
+            (true, _) => FieldAccess::Visible,
+            (false, Denial::Null) => FieldAccess::Hidden,
+            (false, Denial::Error) => FieldAccess::Unauthorized,
+        })
+    }
+}
+
+/// The outcome of checking if the user making a request can see a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldAccess {
+    /// The user can see the field.
+    Visible,
+    /// The user can't see the field, which resolves to null.
+    Hidden,
+    /// The user can't see the field, which fails with the "unauthorized" error code.
+    Unauthorized,
+}
+
+impl FieldAccess {
+    /// Get the value the field resolves to: its actual value if the user can see it, none if it
+    /// is hidden, or an error if the user isn't allowed to see it.
+    pub fn apply<T>(self, value: Option<T>) -> Result<Option<T>, UnauthorizedField> {
+        match self {
+            FieldAccess::Visible => Ok(value),
+            FieldAccess::Hidden => Ok(None),
+            FieldAccess::Unauthorized => Err(UnauthorizedField),
+        }
+    }
+}
+
+/// The error returned for a field the user making the request isn't allowed to see.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnauthorizedField;
+
+impl<S: ScalarValue> IntoFieldError<S> for UnauthorizedField {
+    fn into_field_error(self) -> FieldError<S> {
+        FieldError::new(
+            UNAUTHORIZED_FIELD_ERROR_MESSAGE,
+            graphql_value!({ "code": UNAUTHORIZED_FIELD_ERROR_CODE }),
+        )
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use juniper::GraphQLEnum;
+
+use crate::models::PromoCodeRedemption;
+
+/// Minimum length of a promo code.
+pub const MIN_CODE_LENGTH: usize = 3;
+/// Maximum length of a promo code.
+pub const MAX_CODE_LENGTH: usize = 32;
+
+/// The kind of discount a promo code gives.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "The kind of discount a promo code gives.")]
+pub enum PromoCodeKind {
+    #[graphql(description = "Takes a percentage off the price.")]
+    Percentage,
+    #[graphql(description = "Takes a fixed amount off the price.")]
+    Fixed,
+}
+
+impl PromoCodeKind {
+    /// The name the kind is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PromoCodeKind::Percentage => "percentage",
+            PromoCodeKind::Fixed => "fixed",
+        }
+    }
+}
+
+impl Display for PromoCodeKind {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for PromoCodeKind {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "percentage" => Ok(PromoCodeKind::Percentage),
+            "fixed" => Ok(PromoCodeKind::Fixed),
+            _ => Err(anyhow!("Unknown promo code kind: {}", value)),
+        }
+    }
+}
+
+/// Normalize a promo code entered by a user or admin. Promo codes are case-insensitive and stored
+/// in upper case.
+pub fn normalize_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+/// Specifies if a normalized promo code only contains letters, digits, dashes and underscores and
+/// has a valid length.
+pub fn is_valid_code(code: &str) -> bool {
+    (MIN_CODE_LENGTH..=MAX_CODE_LENGTH).contains(&code.len())
+        && code
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || "-_".contains(character))
+}
+
+/// The outcome of an attempt to redeem a promo code.
+#[derive(Debug, Clone)]
+pub enum RedeemResult {
+    /// The promo code was redeemed. The discount has been applied if the user has an active
+    /// subscription, and will be applied when they subscribe otherwise.
+    Redeemed(PromoCodeRedemption),
+    /// No promo code exists with the code.
+    NotFound,
+    /// The promo code has expired.
+    Expired,
+    /// The promo code has been redeemed the max number of times.
+    Exhausted,
+    /// The user has already redeemed the promo code.
+    AlreadyRedeemed,
+    /// The user has redeemed another promo code that hasn't been applied to a subscription yet.
+    Pending,
+}
@@ -0,0 +1,192 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+
+/// Header proxies send the IP addresses a request was forwarded for in, with each proxy appending
+/// the address it received the request from.
+pub const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// A range of IP addresses requests can be forwarded from, written as a single address such as
+/// "10.0.0.1" or a CIDR block such as "10.0.0.0/8".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrustedProxy {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl TrustedProxy {
+    /// Check if an IP address is in the range. IPv4 addresses mapped to IPv6 are compared as IPv4
+    /// addresses.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, canonical(address)) {
+            (IpAddr::V4(range), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_length as u32);
+                let mask = mask.unwrap_or(0);
+                u32::from(range) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_length as u32);
+                let mask = mask.unwrap_or(0);
+                u128::from(range) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        let (address, prefix_length) = match value.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (value, None),
+        };
+        let address = canonical(address.trim().parse::<IpAddr>()?);
+        let max_prefix_length = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_length = match prefix_length {
+            Some(prefix_length) => prefix_length.trim().parse::<u8>()?,
+            None => max_prefix_length,
+        };
+        if prefix_length > max_prefix_length {
+            return Err(anyhow!(
+                "Expected a prefix length of at most {}",
+                max_prefix_length
+            ));
+        }
+
+        Ok(TrustedProxy {
+            address,
+            prefix_length,
+        })
+    }
+}
+
+/// Get the IP address of the client that sent a request, from the address of the peer that
+/// connected to the server and the addresses in the request's "X-Forwarded-For" header. Forwarded
+/// addresses are only used while the address they were received from is a trusted proxy, as
+/// anyone else can send whatever addresses they like. Proxies append to the header, so it is read
+/// from the end, and the client is the last address that isn't a trusted proxy.
+pub fn forwarded_client(
+    peer: IpAddr,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[TrustedProxy],
+) -> IpAddr {
+    let is_trusted = |address| trusted_proxies.iter().any(|proxy| proxy.contains(address));
+    let mut client = peer;
+    let forwarded = forwarded_for.unwrap_or_default().rsplit(',');
+    for address in forwarded {
+        if !is_trusted(client) {
+            break;
+        }
+        match parse_address(address) {
+            Some(address) => client = address,
+            None => break,
+        }
+    }
+
+    client
+}
+
+/// Parse an IP address, which may include a port and have its IPv6 address in brackets.
+pub fn parse_address(address: &str) -> Option<IpAddr> {
+    let address = address.trim();
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return Some(address.ip());
+    }
+
+    address
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .ok()
+}
+
+/// Convert an IPv4 address mapped to IPv6 back to IPv4, leaving other addresses as they are.
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => address,
+        },
+        IpAddr::V4(_) => address,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(ranges: &[&str]) -> Vec<TrustedProxy> {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    fn client(peer: &str, forwarded_for: Option<&str>, trusted: &[&str]) -> String {
+        let peer = peer.parse().unwrap();
+        forwarded_client(peer, forwarded_for, &proxies(trusted)).to_string()
+    }
+
+    #[test]
+    fn ranges_contain_addresses_within_their_prefix() {
+        let range = "10.1.0.0/16".parse::<TrustedProxy>().unwrap();
+        assert!(range.contains("10.1.200.3".parse().unwrap()));
+        assert!(!range.contains("10.2.0.1".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.0.9".parse().unwrap()));
+
+        let address = "fd00::1".parse::<TrustedProxy>().unwrap();
+        assert!(address.contains("fd00::1".parse().unwrap()));
+        assert!(!address.contains("fd00::2".parse().unwrap()));
+
+        let everything = "0.0.0.0/0".parse::<TrustedProxy>().unwrap();
+        assert!(everything.contains("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn ranges_with_invalid_prefixes_are_rejected() {
+        assert!("10.0.0.0/33".parse::<TrustedProxy>().is_err());
+        assert!("10.0.0.0/x".parse::<TrustedProxy>().is_err());
+        assert!("proxy.internal".parse::<TrustedProxy>().is_err());
+    }
+
+    #[test]
+    fn forwarded_addresses_are_ignored_from_untrusted_peers() {
+        let ip = client("203.0.113.7", Some("198.51.100.1"), &["10.0.0.0/8"]);
+        assert_eq!(ip, "203.0.113.7");
+        let ip = client("203.0.113.7", Some("198.51.100.1"), &[]);
+        assert_eq!(ip, "203.0.113.7");
+    }
+
+    #[test]
+    fn forwarded_addresses_are_read_from_the_end_while_they_are_trusted() {
+        let trusted = ["10.0.0.0/8"];
+        let ip = client("10.0.0.2", Some("198.51.100.1"), &trusted);
+        assert_eq!(ip, "198.51.100.1");
+        // Addresses before the one the trusted proxy saw could have been sent by the client.
+        let ip = client("10.0.0.2", Some("1.2.3.4, 198.51.100.1"), &trusted);
+        assert_eq!(ip, "198.51.100.1");
+        let ip = client("10.0.0.2", Some("198.51.100.1, 10.0.0.3"), &trusted);
+        assert_eq!(ip, "198.51.100.1");
+        let ip = client("10.0.0.2", Some("10.0.0.4, 10.0.0.3"), &trusted);
+        assert_eq!(ip, "10.0.0.4");
+    }
+
+    #[test]
+    fn unparseable_forwarded_addresses_stop_the_search() {
+        let ip = client("10.0.0.2", Some("198.51.100.1, unknown"), &["10.0.0.0/8"]);
+        assert_eq!(ip, "10.0.0.2");
+        let ip = client("10.0.0.2", None, &["10.0.0.0/8"]);
+        assert_eq!(ip, "10.0.0.2");
+    }
+
+    #[test]
+    fn addresses_with_ports_are_parsed() {
+        assert_eq!(parse_address("[::1]:8080"), Some("::1".parse().unwrap()));
+        assert_eq!(
+            parse_address(" 10.0.0.1:443"),
+            Some("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(parse_address("[fd00::1]"), Some("fd00::1".parse().unwrap()));
+        assert_eq!(parse_address("unknown"), None);
+    }
+}
@@ -0,0 +1,480 @@
+use std::collections::{HashMap, HashSet};
+
+use graphql_parser::query::{
+    parse_query, Definition, Document, OperationDefinition, Selection, SelectionSet,
+};
+
+use crate::errors::ApiError;
+
+/// Error code returned for queries with more aliases than allowed.
+pub const TOO_MANY_ALIASES_ERROR_CODE: &str = "too-many-aliases";
+/// Error code returned for operations selecting more root fields than allowed.
+pub const TOO_MANY_ROOT_FIELDS_ERROR_CODE: &str = "too-many-root-fields";
+/// Error code returned for queries with more directives than allowed.
+pub const TOO_MANY_DIRECTIVES_ERROR_CODE: &str = "too-many-directives";
+/// Message returned for queries with fragments that spread themselves, directly or through other
+/// fragments.
+pub const FRAGMENT_CYCLE_ERROR_MESSAGE: &str =
+    "The query contains fragments that spread themselves.";
+/// Error code returned for queries with fragments that spread themselves.
+pub const FRAGMENT_CYCLE_ERROR_CODE: &str = "fragment-cycle";
+/// Message returned for queries nested deeper than the GraphQL parsers can handle.
+pub const QUERY_TOO_DEEP_ERROR_MESSAGE: &str = "The query is nested too deeply.";
+/// Error code returned for queries nested deeper than the GraphQL parsers can handle.
+pub const QUERY_TOO_DEEP_ERROR_CODE: &str = "query-too-deep";
+/// Message returned for mutations and subscriptions sent in requests that can only read data.
+pub const OPERATION_NOT_ALLOWED_ERROR_MESSAGE: &str =
+    "Only queries can be sent in GET requests. Send mutations and subscriptions in POST requests.";
+/// Error code returned for mutations and subscriptions sent in requests that can only read data.
+pub const OPERATION_NOT_ALLOWED_ERROR_CODE: &str = "operation-not-allowed";
+
+/// Max depth selection sets, arguments and list values can be nested to in a query. The parsers
+/// are recursive, so deeper queries could overflow the stack.
+const MAX_NESTING_DEPTH: usize = 40;
+
+/// Limits on the shape of GraphQL documents a caller can send, checked before they are executed.
+/// These stop a single request from doing the work of many, such as a mutation logging in with a
+/// thousand aliased password guesses. Zero means there is no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryLimits {
+    /// The max number of aliased fields in a document.
+    pub max_aliases: u32,
+    /// The max number of fields an operation can select at its root.
+    pub max_root_fields: u32,
+    /// The max number of directives in a document.
+    pub max_directives: u32,
+}
+
+/// What the documents sent in a request have used of the query limits so far. Every document in a
+/// batch is checked against the same budget, so a batch can't get around the limits by spreading
+/// aliases, root fields or directives over many documents.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryBudget {
+    used: Counts,
+}
+
+/// The number of aliases, root fields and directives in part of a document.
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    aliases: u32,
+    /// The number of fields at the top level of the selection set, including those selected
+    /// through fragments.
+    fields: u32,
+    directives: u32,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.aliases = self.aliases.saturating_add(other.aliases);
+        self.fields = self.fields.saturating_add(other.fields);
+        self.directives = self.directives.saturating_add(other.directives);
+    }
+}
+
+/// Counts the parts of a document, expanding fragments where they are spread. Each fragment is
+/// only counted once, so documents spreading fragments into each other can't make this slow.
+struct Counter<'d, 'a> {
+    fragments: HashMap<&'d str, &'d SelectionSet<'a, &'a str>>,
+    counted: HashMap<&'d str, Counts>,
+    counting: HashSet<&'d str>,
+    /// Set if a fragment spreads itself, directly or through other fragments.
+    has_cycle: bool,
+}
+
+impl<'d, 'a> Counter<'d, 'a> {
+    fn count_fragment(&mut self, name: &'d str) -> Counts {
+        if let Some(counts) = self.counted.get(name) {
+            return *counts;
+        }
+        let selection_set = match self.fragments.get(name) {
+            Some(selection_set) => *selection_set,
+            None => return Counts::default(),
+        };
+        if !self.counting.insert(name) {
+            self.has_cycle = true;
+            return Counts::default();
+        }
+
+        let counts = self.count_selection_set(selection_set);
+        self.counting.remove(name);
+        self.counted.insert(name, counts);
+        counts
+    }
+
+    fn count_selection_set(&mut self, selection_set: &'d SelectionSet<'a, &'a str>) -> Counts {
+        let mut counts = Counts::default();
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    let nested = self.count_selection_set(&field.selection_set);
+                    counts.add(Counts {
+                        aliases: nested.aliases.saturating_add(field.alias.is_some().into()),
+                        fields: 1,
+                        directives: nested
+                            .directives
+                            .saturating_add(field.directives.len() as u32),
+                    });
+                }
+                Selection::FragmentSpread(spread) => {
+                    counts.add(self.count_fragment(spread.fragment_name));
+                    counts.directives = counts
+                        .directives
+                        .saturating_add(spread.directives.len() as u32);
+                }
+                Selection::InlineFragment(fragment) => {
+                    counts.add(self.count_selection_set(&fragment.selection_set));
+                    counts.directives = counts
+                        .directives
+                        .saturating_add(fragment.directives.len() as u32);
+                }
+            }
+        }
+
+        counts
+    }
+}
+
+/// Count the aliases and directives in every operation of a document, along with the most root
+/// fields any of its operations selects. This will return none if a fragment spreads itself.
+fn count_document<'a>(document: &Document<'a, &'a str>) -> Option<Counts> {
+    let mut counter = Counter {
+        fragments: HashMap::new(),
+        counted: HashMap::new(),
+        counting: HashSet::new(),
+        has_cycle: false,
+    };
+    for definition in &document.definitions {
+        if let Definition::Fragment(fragment) = definition {
+            counter
+                .fragments
+                .insert(fragment.name, &fragment.selection_set);
+        }
+    }
+
+    let mut totals = Counts::default();
+    for definition in &document.definitions {
+        let (selection_set, directives) = match definition {
+            Definition::Operation(OperationDefinition::SelectionSet(selection_set)) => {
+                (selection_set, 0)
+            }
+            Definition::Operation(OperationDefinition::Query(query)) => {
+                (&query.selection_set, query.directives.len())
+            }
+            Definition::Operation(OperationDefinition::Mutation(mutation)) => {
+                (&mutation.selection_set, mutation.directives.len())
+            }
+            Definition::Operation(OperationDefinition::Subscription(subscription)) => {
+                (&subscription.selection_set, subscription.directives.len())
+            }
+            Definition::Fragment(fragment) => {
+                totals.directives = totals
+                    .directives
+                    .saturating_add(fragment.directives.len() as u32);
+                continue;
+            }
+        };
+
+        let counts = counter.count_selection_set(selection_set);
+        totals.aliases = totals.aliases.saturating_add(counts.aliases);
+        totals.directives = totals
+            .directives
+            .saturating_add(counts.directives)
+            .saturating_add(directives as u32);
+        totals.fields = totals.fields.max(counts.fields);
+    }
+
+    // Fragments that aren't spread by any operation are rejected when the document is validated,
+    // but are still checked for cycles.
+    let names = counter.fragments.keys().copied().collect::<Vec<_>>();
+    for name in names {
+        counter.count_fragment(name);
+    }
+    if counter.has_cycle {
+        return None;
+    }
+
+    Some(totals)
+}
+
+/// Find how deep the selection sets, arguments and list values of a query are nested, without
+/// parsing it. Brackets in strings and comments are ignored.
+fn nesting_depth(query: &str) -> usize {
+    let mut depth = 0usize;
+    let mut max_depth = 0;
+    let mut characters = query.chars();
+    while let Some(character) = characters.next() {
+        match character {
+            '{' | '(' | '[' => {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+            }
+            '}' | ')' | ']' => depth = depth.saturating_sub(1),
+            '#' => {
+                characters.by_ref().find(|character| *character == '\n');
+            }
+            '"' => {
+                // Skip the string, including escaped quotes. Block strings are skipped as a series
+                // of regular strings, which is equivalent for finding where they end.
+                while let Some(character) = characters.next() {
+                    match character {
+                        '\\' => {
+                            characters.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    max_depth
+}
+
+/// Check a query against limits before it is executed, along with the other queries sent in the
+/// same request, which have already used part of the budget. The query's aliases, root fields and
+/// directives are only taken from the budget if it is allowed. Queries nested too deeply or with
+/// fragments that spread themselves are always rejected, as they would overflow the stack while
+/// being parsed or validated. Queries that can't be parsed are allowed, so the backend can report
+/// why they are invalid.
+pub fn check_query(
+    query: &str,
+    limits: &QueryLimits,
+    budget: &mut QueryBudget,
+) -> Result<(), ApiError> {
+    if nesting_depth(query) > MAX_NESTING_DEPTH {
+        return Err(ApiError::Validation {
+            message: QUERY_TOO_DEEP_ERROR_MESSAGE.into(),
+            code: QUERY_TOO_DEEP_ERROR_CODE,
+        });
+    }
+
+    let document = match parse_query::<&str>(query) {
+        Ok(document) => document,
+        Err(_) => return Ok(()),
+    };
+    let mut counts = count_document(&document).ok_or_else(|| ApiError::Validation {
+        message: FRAGMENT_CYCLE_ERROR_MESSAGE.into(),
+        code: FRAGMENT_CYCLE_ERROR_CODE,
+    })?;
+    counts.add(budget.used);
+
+    let exceeded = |count: u32, max: u32| max > 0 && count > max;
+    if exceeded(counts.aliases, limits.max_aliases) {
+        return Err(ApiError::Validation {
+            message: format!(
+                "The query uses too many aliases. At most {} are allowed, counting every query \
+                in a batch.",
+                limits.max_aliases
+            ),
+            code: TOO_MANY_ALIASES_ERROR_CODE,
+        });
+    }
+    if exceeded(counts.fields, limits.max_root_fields) {
+        return Err(ApiError::Validation {
+            message: format!(
+                "The query selects too many root fields. At most {} are allowed, counting every \
+                query in a batch.",
+                limits.max_root_fields
+            ),
+            code: TOO_MANY_ROOT_FIELDS_ERROR_CODE,
+        });
+    }
+    if exceeded(counts.directives, limits.max_directives) {
+        return Err(ApiError::Validation {
+            message: format!(
+                "The query uses too many directives. At most {} are allowed, counting every query \
+                in a batch.",
+                limits.max_directives
+            ),
+            code: TOO_MANY_DIRECTIVES_ERROR_CODE,
+        });
+    }
+
+    budget.used = counts;
+    Ok(())
+}
+
+/// Check that the operation a request runs only reads data, being a query rather than a mutation
+/// or subscription. The operation is picked by name, or is the only one in the document if no name
+/// is given. Documents that can't be parsed or don't hold the operation are allowed, so the
+/// backend can report why they are invalid. Queries should be checked against limits first, so
+/// ones nested too deeply aren't parsed here.
+pub fn check_read_only(query: &str, operation_name: Option<&str>) -> Result<(), ApiError> {
+    let document = match parse_query::<&str>(query) {
+        Ok(document) => document,
+        Err(_) => return Ok(()),
+    };
+    let mut operations = document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            Definition::Operation(operation) => Some(operation),
+            Definition::Fragment(_) => None,
+        });
+    let operation = match operation_name {
+        Some(operation_name) => operations.find(|operation| {
+            let name = match operation {
+                OperationDefinition::Query(query) => query.name,
+                OperationDefinition::Mutation(mutation) => mutation.name,
+                OperationDefinition::Subscription(subscription) => subscription.name,
+                OperationDefinition::SelectionSet(_) => None,
+            };
+            name == Some(operation_name)
+        }),
+        None => match (operations.next(), operations.next()) {
+            (Some(operation), None) => Some(operation),
+            _ => None,
+        },
+    };
+
+    match operation {
+        Some(OperationDefinition::Mutation(_)) | Some(OperationDefinition::Subscription(_)) => {
+            Err(ApiError::Validation {
+                message: OPERATION_NOT_ALLOWED_ERROR_MESSAGE.into(),
+                code: OPERATION_NOT_ALLOWED_ERROR_CODE,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: QueryLimits = QueryLimits {
+        max_aliases: 3,
+        max_root_fields: 2,
+        max_directives: 2,
+    };
+
+    /// Check a query, returning the code of the error it was rejected with.
+    fn check(query: &str, budget: &mut QueryBudget) -> Result<(), &'static str> {
+        check_query(query, &LIMITS, budget).map_err(|error| error.code())
+    }
+
+    #[test]
+    fn queries_within_the_limits_are_allowed() {
+        let query = "{ a: viewer { id } b: viewer { id } }";
+        assert_eq!(check(query, &mut QueryBudget::default()), Ok(()));
+    }
+
+    #[test]
+    fn aliases_in_fragments_are_counted_where_they_are_spread() {
+        let query = "
+            query { viewer { ...names } other: viewer { ...names } }
+            fragment names on User { first: name second: name }
+        ";
+        let result = check(query, &mut QueryBudget::default());
+        assert_eq!(result, Err(TOO_MANY_ALIASES_ERROR_CODE));
+    }
+
+    #[test]
+    fn root_fields_are_counted_per_operation() {
+        let query = "query A { a b } query B { c d }";
+        assert_eq!(check(query, &mut QueryBudget::default()), Ok(()));
+        let query = "{ a b c }";
+        let result = check(query, &mut QueryBudget::default());
+        assert_eq!(result, Err(TOO_MANY_ROOT_FIELDS_ERROR_CODE));
+    }
+
+    #[test]
+    fn queries_in_a_batch_share_a_budget() {
+        let mut budget = QueryBudget::default();
+        assert_eq!(check("{ viewer { a: id b: id } }", &mut budget), Ok(()));
+        assert_eq!(check("{ viewer { c: id } }", &mut budget), Ok(()));
+        let result = check("{ viewer { d: id } }", &mut budget);
+        assert_eq!(result, Err(TOO_MANY_ALIASES_ERROR_CODE));
+
+        // Root fields add up across the batch too, rather than taking the most of any query.
+        let mut budget = QueryBudget::default();
+        assert_eq!(check("{ a b }", &mut budget), Ok(()));
+        let result = check("{ c }", &mut budget);
+        assert_eq!(result, Err(TOO_MANY_ROOT_FIELDS_ERROR_CODE));
+    }
+
+    #[test]
+    fn rejected_queries_use_none_of_the_budget() {
+        let mut budget = QueryBudget::default();
+        let result = check(
+            "{ a @skip(if: true) b @skip(if: true) c @skip(if: true) }",
+            &mut budget,
+        );
+        assert_eq!(result, Err(TOO_MANY_ROOT_FIELDS_ERROR_CODE));
+        assert_eq!(
+            check("{ a @skip(if: true) b @skip(if: true) }", &mut budget),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn fragment_cycles_are_rejected() {
+        let query = "
+            { viewer { ...a } }
+            fragment a on User { ...b }
+            fragment b on User { ...a }
+        ";
+        let result = check(query, &mut QueryBudget::default());
+        assert_eq!(result, Err(FRAGMENT_CYCLE_ERROR_CODE));
+    }
+
+    #[test]
+    fn deeply_nested_queries_are_rejected_before_being_parsed() {
+        let query = format!(
+            "{}{}",
+            "{ a ".repeat(MAX_NESTING_DEPTH + 1),
+            "}".repeat(MAX_NESTING_DEPTH + 1)
+        );
+        let result = check(&query, &mut QueryBudget::default());
+        assert_eq!(result, Err(QUERY_TOO_DEEP_ERROR_CODE));
+        // Brackets in strings and comments don't count.
+        let query = format!(
+            "{{ a(text: \"{}\") }} # {}",
+            "{".repeat(100),
+            "[".repeat(100)
+        );
+        assert_eq!(check(&query, &mut QueryBudget::default()), Ok(()));
+    }
+
+    #[test]
+    fn unparseable_queries_are_left_to_the_backend() {
+        assert_eq!(check("{ a: }", &mut QueryBudget::default()), Ok(()));
+    }
+
+    #[test]
+    fn zero_means_no_limit() {
+        let limits = QueryLimits {
+            max_aliases: 0,
+            max_root_fields: 0,
+            max_directives: 0,
+        };
+        let query = "{ a: b c: d e: f g h }";
+        assert!(check_query(query, &limits, &mut QueryBudget::default()).is_ok());
+    }
+
+    #[test]
+    fn queries_are_read_only() {
+        assert!(check_read_only("{ viewer { id } }", None).is_ok());
+        assert!(check_read_only("query Viewer { viewer { id } }", None).is_ok());
+    }
+
+    #[test]
+    fn mutations_and_subscriptions_are_not_read_only() {
+        let result = check_read_only("mutation { logout }", None);
+        assert_eq!(result.unwrap_err().code(), OPERATION_NOT_ALLOWED_ERROR_CODE);
+        let result = check_read_only("subscription { events }", None);
+        assert_eq!(result.unwrap_err().code(), OPERATION_NOT_ALLOWED_ERROR_CODE);
+    }
+
+    #[test]
+    fn the_named_operation_is_checked() {
+        let document = "query Viewer { viewer { id } } mutation Logout { logout }";
+        assert!(check_read_only(document, Some("Viewer")).is_ok());
+        assert!(check_read_only(document, Some("Logout")).is_err());
+        // Without a name the backend reports that the operation is ambiguous.
+        assert!(check_read_only(document, None).is_ok());
+        assert!(check_read_only(document, Some("Missing")).is_ok());
+    }
+}
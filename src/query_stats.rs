@@ -0,0 +1,131 @@
+use juniper::{graphql_object, GraphQLEnum};
+use serde::{Deserialize, Serialize};
+
+/// Default number of queries returned when listing the top queries.
+pub const DEFAULT_QUERY_COUNT: i32 = 20;
+/// Maximum number of queries returned when listing the top queries.
+pub const MAX_QUERY_COUNT: i32 = 100;
+/// Postgres error code returned when "pg_stat_statements" is installed but its library isn't
+/// loaded.
+pub const OBJECT_NOT_IN_PREREQUISITE_STATE_CODE: &str = "55000";
+/// Maximum length of a query's text. Longer queries are truncated.
+const MAX_QUERY_LENGTH: usize = 2000;
+
+/// Orderings that can be used when listing the top queries.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[graphql(description = "Orderings that can be used when listing the top queries.")]
+pub enum QueryStatisticsOrder {
+    #[graphql(description = "Queries that spent the most time executing in total first.")]
+    TotalTime,
+    #[graphql(description = "Queries that took the longest to execute on average first.")]
+    MeanTime,
+    #[graphql(description = "The most frequently executed queries first.")]
+    Calls,
+}
+
+impl QueryStatisticsOrder {
+    /// The column queries are sorted by.
+    pub fn column(self) -> &'static str {
+        match self {
+            QueryStatisticsOrder::TotalTime => "total_time",
+            QueryStatisticsOrder::MeanTime => "mean_time",
+            QueryStatisticsOrder::Calls => "calls",
+        }
+    }
+}
+
+/// Execution statistics of a normalized SQL query, as tracked by the "pg_stat_statements"
+/// extension. Statistics are cumulative since they were last reset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryStatistics {
+    /// The normalized and redacted text of the query.
+    pub query: String,
+    /// The number of times the query was executed.
+    pub calls: i64,
+    /// The total time spent executing the query, in milliseconds.
+    pub total_time_ms: f64,
+    /// The mean time spent executing the query, in milliseconds.
+    pub mean_time_ms: f64,
+    /// The total number of rows the query retrieved or affected.
+    pub rows: i64,
+}
+
+#[graphql_object(description = "Execution statistics of a normalized SQL query.")]
+impl QueryStatistics {
+    #[graphql(
+        description = "The text of the query. Constants are replaced with placeholders, so
+        executions of the same query with different values are grouped together."
+    )]
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    #[graphql(description = "The number of times the query was executed.")]
+    pub fn calls(&self) -> i32 {
+        self.calls.min(i32::MAX.into()) as i32
+    }
+
+    #[graphql(description = "The total time spent executing the query, in milliseconds.")]
+    pub fn total_time_ms(&self) -> f64 {
+        self.total_time_ms
+    }
+
+    #[graphql(description = "The mean time spent executing the query, in milliseconds.")]
+    pub fn mean_time_ms(&self) -> f64 {
+        self.mean_time_ms
+    }
+
+    #[graphql(description = "The total number of rows the query retrieved or affected.")]
+    pub fn rows(&self) -> i32 {
+        self.rows.min(i32::MAX.into()) as i32
+    }
+}
+
+/// Normalize and redact the text of a query so it can be shown to operators. Whitespace is
+/// collapsed, and any literals "pg_stat_statements" didn't already replace with placeholders,
+/// such as those in utility statements like "ALTER USER ... PASSWORD", are replaced with "?".
+pub fn redact_query(query: &str) -> String {
+    let mut redacted = String::with_capacity(query.len());
+    let mut characters = query.chars().peekable();
+    let mut previous = ' ';
+    while let Some(character) = characters.next() {
+        match character {
+            // String literals. Quoted identifiers use double quotes, so they are left alone.
+            '\'' => {
+                while let Some(character) = characters.next() {
+                    if character == '\'' {
+                        // A doubled quote is an escaped quote inside the literal.
+                        if characters.peek() != Some(&'\'') {
+                            break;
+                        }
+                        characters.next();
+                    }
+                }
+                redacted.push('?');
+            }
+            // Numeric literals, unless they are part of an identifier or a "$1" placeholder.
+            '0'..='9' if !(previous.is_alphanumeric() || previous == '_' || previous == '$') => {
+                while characters
+                    .peek()
+                    .is_some_and(|character| character.is_ascii_digit() || *character == '.')
+                {
+                    characters.next();
+                }
+                redacted.push('?');
+            }
+            character if character.is_whitespace() => {
+                if !redacted.ends_with(' ') && !redacted.is_empty() {
+                    redacted.push(' ');
+                }
+            }
+            character => redacted.push(character),
+        }
+        previous = character;
+    }
+
+    let redacted = redacted.trim_end();
+    match redacted.char_indices().nth(MAX_QUERY_LENGTH) {
+        Some((index, _)) => format!("{}...", &redacted[..index]),
+        None => redacted.to_string(),
+    }
+}
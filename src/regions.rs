@@ -0,0 +1,301 @@
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Error, Result};
+use async_std::channel::{self, Receiver, Sender};
+use async_std::future;
+use futures::future::join_all;
+use redis::{AsyncCommands, Pipeline, RedisResult};
+use tide::log;
+
+use crate::db::connect_to_redis_url;
+use crate::deadlines::Deadline;
+use crate::metrics::{InstrumentedConnection, Metrics};
+use crate::state::State;
+
+/// The max number of session writes waiting to be replicated to each other region. Writes are
+/// dropped once a region falls this far behind, rather than holding up requests. Deletes are never
+/// dropped, and wait for room in the queue instead.
+const REPLICATION_QUEUE_CAPACITY: usize = 1024;
+/// How long replicating a delete waits before trying again after it fails. The wait doubles after
+/// each failure, up to `MAX_DELETE_RETRY_DELAY`.
+const DELETE_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// The longest replicating a delete waits before trying again after it fails.
+const MAX_DELETE_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// How long a session lookup waits on the Redis database of another region before trying the next.
+const REMOTE_READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The Redis database of another region, which session writes are replicated to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRegion {
+    /// The label of the region, such as "eu-west".
+    pub region: String,
+    /// A connection string for the region's Redis database.
+    pub url: String,
+}
+
+impl FromStr for RemoteRegion {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value.split_once('=') {
+            Some((region, url)) if !region.trim().is_empty() && !url.trim().is_empty() => {
+                Ok(RemoteRegion {
+                    region: region.trim().into(),
+                    url: url.trim().into(),
+                })
+            }
+            _ => Err(anyhow!("Expected a region and a Redis URL joined by \"=\"")),
+        }
+    }
+}
+
+/// The region this server runs in, and the Redis databases of other regions sessions are
+/// replicated to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionConfig {
+    /// The label of the region the Redis database at `REDIS_URL` is in, such as "us-east".
+    pub region: String,
+    /// The Redis database of each other region.
+    pub remotes: Vec<RemoteRegion>,
+    /// The number of seconds between measurements of the round trip time to each other region.
+    /// Zero measures it only once, when the server starts.
+    pub latency_probe_interval_seconds: u64,
+}
+
+/// Session writes waiting to be replicated to another region.
+struct Write {
+    pipeline: Pipeline,
+    /// Specifies if the writes delete sessions. Deletes are retried until they are replicated, as
+    /// the deleted sessions could otherwise still be found in the region.
+    is_delete: bool,
+}
+
+/// A connection to the Redis database of another region, along with the writes waiting to be
+/// replicated to it.
+struct Remote {
+    region: String,
+    redis: InstrumentedConnection,
+    /// The last measured round trip time, or none if the region hasn't answered yet.
+    latency: Mutex<Option<Duration>>,
+    writes: (Sender<Write>, Receiver<Write>),
+}
+
+impl Remote {
+    /// Send writes to the region in the order they were queued, until the queue is closed and
+    /// every queued write has been sent. Deletes that fail are retried until they are replicated,
+    /// or the worker is stopped.
+    async fn drain(&self, metrics: &Metrics, stop: &Receiver<()>) {
+        let mut redis = self.redis.clone();
+        while let Ok(write) = self.writes.1.recv().await {
+            let mut delay = DELETE_RETRY_DELAY;
+            let result = loop {
+                match write.pipeline.query_async::<_, ()>(&mut redis).await {
+                    Ok(()) => break "written",
+                    Err(error) => {
+                        log::warn!(
+                            "Failed to replicate session write to region {}: {}",
+                            self.region,
+                            error
+                        );
+                        if !write.is_delete || stop.is_closed() {
+                            break "failed";
+                        }
+                    }
+                }
+                metrics.observe_redis_replication(&self.region, "retried");
+                // The stop channel is only ever closed, which ends the wait early.
+                let _ = future::timeout(delay, stop.recv()).await;
+                delay = (delay * 2).min(MAX_DELETE_RETRY_DELAY);
+            };
+            metrics.observe_redis_replication(&self.region, result);
+        }
+    }
+
+    /// Measure the round trip time to the region. Regions that don't answer are tried last.
+    async fn probe_latency(&self, metrics: &Metrics) {
+        let start = Instant::now();
+        let result = redis::cmd("PING")
+            .query_async::<_, String>(&mut self.redis.clone())
+            .await;
+        let latency = match result {
+            Ok(_) => {
+                let latency = start.elapsed();
+                metrics.observe_redis_region_latency(&self.region, latency);
+                Some(latency)
+            }
+            Err(error) => {
+                log::warn!(
+                    "Failed to reach Redis database in region {}: {}",
+                    self.region,
+                    error
+                );
+                None
+            }
+        };
+        *self.latency.lock().unwrap() = latency;
+    }
+}
+
+/// The Redis databases of other regions. Session writes are made to the Redis database of this
+/// server's region and replicated to the others in the background, so authenticating a request
+/// never waits on another region. This is cheap to clone, and clones share the same connections.
+#[derive(Clone)]
+pub struct Regions {
+    remotes: Arc<Vec<Remote>>,
+    stop: (Sender<()>, Receiver<()>),
+}
+
+impl Default for Regions {
+    fn default() -> Self {
+        Regions {
+            remotes: Arc::new(Vec::new()),
+            stop: channel::bounded(1),
+        }
+    }
+}
+
+impl Regions {
+    /// Connect to the Redis database of every other region in the configuration.
+    pub async fn connect(config: &RegionConfig, metrics: &Metrics) -> RedisResult<Self> {
+        let mut remotes = Vec::with_capacity(config.remotes.len());
+        for remote in &config.remotes {
+            log::info!(
+                "Connecting to Redis database in region {}...",
+                remote.region
+            );
+            let redis = connect_to_redis_url(&remote.url).await?;
+            remotes.push(Remote {
+                region: remote.region.clone(),
+                redis: metrics.instrument(redis),
+                latency: Mutex::new(None),
+                writes: channel::bounded(REPLICATION_QUEUE_CAPACITY),
+            });
+        }
+
+        Ok(Regions {
+            remotes: Arc::new(remotes),
+            ..Regions::default()
+        })
+    }
+
+    /// Queue session writes made in this region to be replicated to every other region. Writes
+    /// reach each region in the order they were queued. Writes are dropped for regions that have
+    /// fallen too far behind.
+    pub fn replicate(&self, metrics: &Metrics, pipeline: &Pipeline) {
+        for remote in self.remotes.iter() {
+            let write = Write {
+                pipeline: pipeline.clone(),
+                is_delete: false,
+            };
+            if remote.writes.0.try_send(write).is_err() {
+                log::warn!(
+                    "Dropped session write replicated to region {}, as it has fallen behind.",
+                    remote.region
+                );
+                metrics.observe_redis_replication(&remote.region, "dropped");
+            }
+        }
+    }
+
+    /// Queue session deletes made in this region to be replicated to every other region, in order
+    /// with other writes. Deletes are never dropped. This waits for room in the queue of regions
+    /// that have fallen behind, and deletes that fail are retried until they are replicated.
+    pub async fn replicate_deletes(&self, metrics: &Metrics, pipeline: &Pipeline) {
+        for remote in self.remotes.iter() {
+            let write = Write {
+                pipeline: pipeline.clone(),
+                is_delete: true,
+            };
+            // Sending only fails once the worker has been stopped.
+            if remote.writes.0.send(write).await.is_err() {
+                log::warn!(
+                    "Dropped session delete replicated to region {}, as the server is stopping.",
+                    remote.region
+                );
+                metrics.observe_redis_replication(&remote.region, "dropped");
+            }
+        }
+    }
+
+    /// Get the value of a key from the Redis databases of other regions, nearest first, for keys
+    /// written in another region that haven't been replicated to this one yet. Regions that fail
+    /// or take too long to answer are skipped, and no more regions are tried once the deadline of
+    /// the request reading the key passes. This will return none if no region has the key.
+    pub async fn get(&self, key: &str, deadline: Deadline) -> Option<String> {
+        let mut remotes = self.remotes.iter().collect::<Vec<_>>();
+        remotes.sort_by_key(|remote| remote.latency.lock().unwrap().unwrap_or(Duration::MAX));
+
+        for remote in remotes {
+            if deadline.is_exceeded() {
+                break;
+            }
+            let mut redis = remote.redis.clone();
+            let lookup = redis.get::<&str, Option<String>>(key);
+            match future::timeout(deadline.limit(REMOTE_READ_TIMEOUT), lookup).await {
+                Ok(Ok(Some(value))) => return Some(value),
+                Ok(Ok(None)) => {}
+                Ok(Err(error)) => log::warn!(
+                    "Failed to read from Redis database in region {}: {}",
+                    remote.region,
+                    error
+                ),
+                Err(_) => log::warn!(
+                    "Timed out reading from Redis database in region {}.",
+                    remote.region
+                ),
+            }
+        }
+
+        None
+    }
+
+    /// Ask the region worker to stop. It replicates every queued write first.
+    pub fn stop_worker(&self) {
+        self.stop.0.close();
+        for remote in self.remotes.iter() {
+            remote.writes.0.close();
+        }
+    }
+}
+
+/// Replicate session writes to other regions as they are queued, and periodically measure the
+/// round trip time to each region so the nearest is tried first. This runs until the worker is
+/// stopped, after replicating every write queued by then.
+pub async fn run_region_worker(state: State) {
+    let regions = &state.regions;
+    let metrics = &state.metrics;
+    let interval = Duration::from_secs(state.config.region.latency_probe_interval_seconds);
+
+    let drains = join_all(
+        regions
+            .remotes
+            .iter()
+            .map(|remote| remote.drain(metrics, &regions.stop.1)),
+    );
+    let probes = async {
+        loop {
+            join_all(
+                regions
+                    .remotes
+                    .iter()
+                    .map(|remote| remote.probe_latency(metrics)),
+            )
+            .await;
+            if interval.is_zero() {
+                let _ = regions.stop.1.recv().await;
+                return;
+            }
+            // The stop channel is only ever closed, which ends the wait early.
+            if future::timeout(interval, regions.stop.1.recv())
+                .await
+                .is_ok()
+            {
+                return;
+            }
+        }
+    };
+
+    futures::join!(drains, probes);
+}
@@ -0,0 +1,391 @@
+use std::collections::HashSet;
+use std::time::Duration as StdDuration;
+
+use anyhow::Result;
+use async_std::task;
+use chrono::{DateTime, Duration, Utc};
+use redis::aio::ConnectionLike;
+use redis::{AsyncCommands, AsyncIter};
+use sqlx::{query, PgPool};
+use tide::log;
+
+use crate::state::State;
+
+/// Max number of rows deleted by a single statement, so purging a large backlog of expired rows
+/// doesn't lock a table for long.
+const PURGE_BATCH_SIZE: i64 = 1000;
+/// Pattern matching the Redis keys holding details about sessions.
+const SESSION_METADATA_KEY_PATTERN: &str = "session-metadata/*";
+/// Prefix of the Redis keys holding details about sessions, followed by the session's ID.
+const SESSION_METADATA_KEY_PREFIX: &str = "session-metadata/";
+/// Fields of a session's details that identify where it was started from.
+const IDENTIFYING_SESSION_FIELDS: &[&str] = &["ip", "user_agent"];
+/// Default number of held users returned when listing legal holds.
+pub const LEGAL_HOLD_DEFAULT_PAGE_SIZE: i32 = 50;
+/// Maximum number of held users returned when listing legal holds.
+pub const LEGAL_HOLD_MAX_PAGE_SIZE: i32 = 100;
+
+/// Data that is purged once it is older than its retention period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionTarget {
+    /// Audit events, which are deleted. Events about or made by users under a legal hold are
+    /// kept.
+    AuditEvents,
+    /// Security events shown to users, which are deleted. Events of users under a legal hold are
+    /// kept.
+    SecurityEvents,
+    /// Users that never verified their email address, which are deleted along with everything
+    /// belonging to them. Users with a subscription or under a legal hold are kept.
+    UnverifiedUsers,
+    /// The IP addresses and user agents recorded for sessions, which are removed from sessions
+    /// that are still active. Sessions of users under a legal hold are kept.
+    SessionMetadata,
+}
+
+impl RetentionTarget {
+    /// Every kind of data with a retention period.
+    pub const ALL: [RetentionTarget; 4] = [
+        RetentionTarget::AuditEvents,
+        RetentionTarget::SecurityEvents,
+        RetentionTarget::UnverifiedUsers,
+        RetentionTarget::SessionMetadata,
+    ];
+
+    /// The name the data is reported and recorded in metrics under.
+    pub fn name(self) -> &'static str {
+        match self {
+            RetentionTarget::AuditEvents => "audit_events",
+            RetentionTarget::SecurityEvents => "security_events",
+            RetentionTarget::UnverifiedUsers => "unverified_users",
+            RetentionTarget::SessionMetadata => "session_metadata",
+        }
+    }
+}
+
+/// The number of days each kind of data is kept for. Zero means the data is kept forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// The number of days audit events are kept for.
+    pub audit_event_days: u32,
+    /// The number of days security events are kept for.
+    pub security_event_days: u32,
+    /// The number of days users have to verify their email address before they are deleted.
+    pub unverified_user_days: u32,
+    /// The number of days after a session starts that its IP address and user agent are kept for.
+    pub session_metadata_days: u32,
+}
+
+impl RetentionPolicy {
+    /// Get the number of days a kind of data is kept for.
+    pub fn days(&self, target: RetentionTarget) -> u32 {
+        match target {
+            RetentionTarget::AuditEvents => self.audit_event_days,
+            RetentionTarget::SecurityEvents => self.security_event_days,
+            RetentionTarget::UnverifiedUsers => self.unverified_user_days,
+            RetentionTarget::SessionMetadata => self.session_metadata_days,
+        }
+    }
+}
+
+/// What was purged, or would have been purged in a dry run, for a kind of data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PurgeReport {
+    /// The kind of data.
+    pub target: RetentionTarget,
+    /// Data from before this time is expired.
+    pub cutoff: DateTime<Utc>,
+    /// The number of expired rows or sessions.
+    pub count: u64,
+}
+
+/// Purge every kind of data older than its retention period. In a dry run, expired data is only
+/// counted. This will return a report for each kind of data with a retention period.
+pub async fn purge_expired<C: ConnectionLike + Send>(
+    db: &PgPool,
+    redis: &mut C,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+    now: DateTime<Utc>,
+) -> Result<Vec<PurgeReport>> {
+    let mut reports = Vec::new();
+    for target in RetentionTarget::ALL.iter().copied() {
+        let days = policy.days(target);
+        if days == 0 {
+            continue;
+        }
+
+        let cutoff = now - Duration::days(days.into());
+        let count = match target {
+            RetentionTarget::AuditEvents => purge_audit_events(db, cutoff, dry_run).await?,
+            RetentionTarget::SecurityEvents => purge_security_events(db, cutoff, dry_run).await?,
+            RetentionTarget::UnverifiedUsers => purge_unverified_users(db, cutoff, dry_run).await?,
+            RetentionTarget::SessionMetadata => {
+                purge_session_metadata(db, redis, cutoff, dry_run).await?
+            }
+        };
+        reports.push(PurgeReport {
+            target,
+            cutoff,
+            count,
+        });
+    }
+
+    Ok(reports)
+}
+
+/// Delete audit events recorded before a cutoff, except for events about or made by users under a
+/// legal hold. This will return the number of events deleted, or that would be deleted in a dry
+/// run.
+async fn purge_audit_events(db: &PgPool, cutoff: DateTime<Utc>, dry_run: bool) -> Result<u64> {
+    if dry_run {
+        let count = query!(
+            r#"SELECT COUNT(*) AS "count!" FROM audit_events
+            WHERE created_at < $1
+                AND NOT EXISTS (
+                    SELECT 1 FROM users
+                    WHERE users.legal_hold_at IS NOT NULL
+                        AND users.id IN (audit_events.user_id, audit_events.actor_id)
+                )"#,
+            cutoff
+        )
+        .fetch_one(db)
+        .await?
+        .count;
+        return Ok(count as u64);
+    }
+
+    let mut total = 0;
+    loop {
+        let deleted = query!(
+            "DELETE FROM audit_events
+            WHERE id IN (
+                SELECT id FROM audit_events
+                WHERE created_at < $1
+                    AND NOT EXISTS (
+                        SELECT 1 FROM users
+                        WHERE users.legal_hold_at IS NOT NULL
+                            AND users.id IN (audit_events.user_id, audit_events.actor_id)
+                    )
+                LIMIT $2
+            )",
+            cutoff,
+            PURGE_BATCH_SIZE,
+        )
+        .execute(db)
+        .await?
+        .rows_affected();
+        total += deleted;
+        if deleted < PURGE_BATCH_SIZE as u64 {
+            return Ok(total);
+        }
+    }
+}
+
+/// Delete security events recorded before a cutoff, except for events of users under a legal
+/// hold. This will return the number of events deleted, or that would be deleted in a dry run.
+async fn purge_security_events(db: &PgPool, cutoff: DateTime<Utc>, dry_run: bool) -> Result<u64> {
+    if dry_run {
+        let count = query!(
+            r#"SELECT COUNT(*) AS "count!" FROM security_events
+            WHERE created_at < $1
+                AND NOT EXISTS (
+                    SELECT 1 FROM users
+                    WHERE users.id = security_events.user_id AND users.legal_hold_at IS NOT NULL
+                )"#,
+            cutoff
+        )
+        .fetch_one(db)
+        .await?
+        .count;
+        return Ok(count as u64);
+    }
+
+    let mut total = 0;
+    loop {
+        let deleted = query!(
+            "DELETE FROM security_events
+            WHERE id IN (
+                SELECT id FROM security_events
+                WHERE created_at < $1
+                    AND NOT EXISTS (
+                        SELECT 1 FROM users
+                        WHERE users.id = security_events.user_id
+                            AND users.legal_hold_at IS NOT NULL
+                    )
+                LIMIT $2
+            )",
+            cutoff,
+            PURGE_BATCH_SIZE,
+        )
+        .execute(db)
+        .await?
+        .rows_affected();
+        total += deleted;
+        if deleted < PURGE_BATCH_SIZE as u64 {
+            return Ok(total);
+        }
+    }
+}
+
+/// Delete users created before a cutoff that never verified their email address. Users with a
+/// subscription are kept, as they are paying for their account, as are users under a legal hold. This will return the number of
+/// users deleted, or that would be deleted in a dry run.
+async fn purge_unverified_users(db: &PgPool, cutoff: DateTime<Utc>, dry_run: bool) -> Result<u64> {
+    if dry_run {
+        let count = query!(
+            r#"SELECT COUNT(*) AS "count!" FROM users
+            WHERE email_verified_at IS NULL AND created_at < $1 AND legal_hold_at IS NULL
+                AND NOT EXISTS (
+                    SELECT 1 FROM subscriptions WHERE subscriptions.user_id = users.id
+                )"#,
+            cutoff
+        )
+        .fetch_one(db)
+        .await?
+        .count;
+        return Ok(count as u64);
+    }
+
+    let mut total = 0;
+    loop {
+        let deleted = query!(
+            "DELETE FROM users
+            WHERE id IN (
+                SELECT id FROM users
+                WHERE email_verified_at IS NULL AND created_at < $1 AND legal_hold_at IS NULL
+                    AND NOT EXISTS (
+                        SELECT 1 FROM subscriptions WHERE subscriptions.user_id = users.id
+                    )
+                LIMIT $2
+            )",
+            cutoff,
+            PURGE_BATCH_SIZE,
+        )
+        .execute(db)
+        .await?
+        .rows_affected();
+        total += deleted;
+        if deleted < PURGE_BATCH_SIZE as u64 {
+            return Ok(total);
+        }
+    }
+}
+
+/// Remove the IP address and user agent recorded for sessions started before a cutoff. Sessions
+/// recorded without a start time are judged by when they were last used, and sessions of users
+/// under a legal hold are kept. This will return the number of sessions whose details were
+/// removed, or would be removed in a dry run.
+async fn purge_session_metadata<C: ConnectionLike + Send>(
+    db: &PgPool,
+    redis: &mut C,
+    cutoff: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<u64> {
+    let mut keys = Vec::new();
+    let mut iter: AsyncIter<String> = redis.scan_match(SESSION_METADATA_KEY_PATTERN).await?;
+    while let Some(key) = iter.next_item().await {
+        keys.push(key);
+    }
+
+    let held_user_ids = query!("SELECT id FROM users WHERE legal_hold_at IS NOT NULL")
+        .fetch_all(db)
+        .await?;
+    let mut held_session_ids = HashSet::new();
+    for row in held_user_ids {
+        let session_ids: Vec<String> = redis.smembers(format!("sessions/{}", row.id)).await?;
+        held_session_ids.extend(session_ids);
+    }
+
+    let mut count = 0;
+    for key in keys {
+        let is_held = key
+            .strip_prefix(SESSION_METADATA_KEY_PREFIX)
+            .is_some_and(|session_id| held_session_ids.contains(session_id));
+        if is_held {
+            continue;
+        }
+
+        let (created_at, last_seen_at, ip, user_agent) = redis
+            .hget::<_, _, (
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+            )>(&key, &["created_at", "last_seen_at", "ip", "user_agent"])
+            .await?;
+        if ip.is_none() && user_agent.is_none() {
+            continue;
+        }
+        let is_expired = created_at
+            .or(last_seen_at)
+            .and_then(|value| DateTime::parse_from_rfc3339(&value).ok())
+            .is_some_and(|started_at| started_at < cutoff);
+        if !is_expired {
+            continue;
+        }
+
+        if !dry_run {
+            redis
+                .hdel::<_, _, ()>(&key, IDENTIFYING_SESSION_FIELDS)
+                .await?;
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Log what was purged, or would have been purged in a dry run, for a kind of data.
+fn log_report(report: &PurgeReport, dry_run: bool) {
+    if report.count == 0 {
+        return;
+    }
+
+    let action = if dry_run { "Would purge" } else { "Purged" };
+    log::info!(
+        "{} {} {} from before {}.",
+        action,
+        report.count,
+        report.target.name(),
+        report.cutoff.to_rfc3339()
+    );
+}
+
+/// Periodically purge data older than its retention period, starting as soon as the server
+/// starts. Purged data is counted in metrics, and dry runs are only logged. An interval of zero
+/// disables the worker.
+pub async fn run_retention_worker(state: State) {
+    let interval = StdDuration::from_secs(state.config.retention_interval_seconds.into());
+    if interval.as_secs() == 0 {
+        log::info!("Data retention is disabled, as the retention interval is zero.");
+        return;
+    }
+
+    let dry_run = state.config.retention_dry_run;
+    loop {
+        let mut redis = state.metrics.instrument(state.redis.clone());
+        match purge_expired(
+            &state.db,
+            &mut redis,
+            &state.config.retention_policy,
+            dry_run,
+            Utc::now(),
+        )
+        .await
+        {
+            Ok(reports) => {
+                for report in &reports {
+                    log_report(report, dry_run);
+                    if !dry_run {
+                        state
+                            .metrics
+                            .observe_retention_purge(report.target.name(), report.count);
+                    }
+                }
+            }
+            Err(error) => log::error!("Failed to purge expired data: {}", error),
+        }
+
+        task::sleep(interval).await;
+    }
+}
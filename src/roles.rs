@@ -0,0 +1,50 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use juniper::GraphQLEnum;
+
+/// A role granting a user access to restricted parts of the API.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "A role granting a user access to restricted parts of the API.")]
+pub enum Role {
+    #[graphql(description = "Can manage users and their roles.")]
+    Admin,
+}
+
+impl Role {
+    /// The name the role is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "admin" => Ok(Role::Admin),
+            _ => Err(anyhow!("Unknown role: {}", value)),
+        }
+    }
+}
+
+/// The outcome of checking if the user making a request has a role or is entitled to a plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// The user has the role or is entitled to the plan.
+    Granted,
+    /// The request was not made by a logged in user.
+    Unauthenticated,
+    /// The user does not have the role or is not entitled to the plan.
+    Forbidden,
+}
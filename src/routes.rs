@@ -0,0 +1,153 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::sync::Arc;
+
+use tide::http::Method;
+use tide::{Endpoint, Server};
+
+#[cfg(feature = "webhooks")]
+use crate::billing::BillingModule;
+use crate::config::Config;
+use crate::exports::ExportModule;
+use crate::graphql::GraphQLModule;
+use crate::health::HealthModule;
+#[cfg(feature = "metrics")]
+use crate::metrics::MetricsModule;
+#[cfg(feature = "oauth")]
+use crate::oauth::OAuthModule;
+use crate::state::State;
+use crate::storage::StorageModule;
+
+/// Names of the middleware applied to every route with the provided configuration, in the order
+/// they run.
+pub fn global_middleware(config: &Config) -> Vec<&'static str> {
+    let mut middleware = vec!["cookies", "logger"];
+    if config.response_compression_enabled {
+        middleware.push("compression");
+    }
+    if !config.cors_allowed_origins.is_empty() || config.organization_domains_enabled {
+        middleware.push("cors");
+    }
+    if cfg!(feature = "metrics") {
+        middleware.push("metrics");
+    }
+    middleware.push("errors");
+
+    middleware
+}
+
+/// Something HTTP routes can be mounted on. Routes are always declared through this trait so the
+/// same declarations can be used to both configure the server and describe it.
+pub trait Router {
+    /// Mount an endpoint at a path for the specified HTTP method. Endpoints receive the global
+    /// server state through the request they are called with.
+    fn route(&mut self, method: Method, path: &'static str, endpoint: Arc<dyn Endpoint<State>>);
+}
+
+impl Router for Server<State> {
+    fn route(&mut self, method: Method, path: &'static str, endpoint: Arc<dyn Endpoint<State>>) {
+        self.at(path).method(method, move |request| {
+            let endpoint = endpoint.clone();
+            async move { endpoint.call(request).await }
+        });
+    }
+}
+
+/// A subsystem of the server that contributes its own HTTP routes. Every group of routes the
+/// server exposes should be registered through one of these.
+pub trait RouteModule: Send + Sync {
+    /// A short, unique name for the module.
+    fn name(&self) -> &'static str;
+
+    /// Specifies if the module should be mounted with the provided configuration. Modules are
+    /// enabled by default.
+    fn enabled(&self, _config: &Config) -> bool {
+        true
+    }
+
+    /// Mount every route the module exposes.
+    fn mount(&self, router: &mut dyn Router);
+}
+
+/// Description of a single mounted HTTP route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteInfo {
+    /// The name of the module that mounted the route.
+    pub module: &'static str,
+    /// The HTTP method the route responds to.
+    pub method: Method,
+    /// The path the route is mounted at.
+    pub path: &'static str,
+    /// The names of the middleware applied to the route, in the order they run.
+    pub middleware: Vec<&'static str>,
+}
+
+impl Display for RouteInfo {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(
+            formatter,
+            "{:<7} {:<24} {:<12} [{}]",
+            self.method.to_string(),
+            self.path,
+            self.module,
+            self.middleware.join(", ")
+        )
+    }
+}
+
+/// A router that only records the routes mounted on it.
+struct RouteCollector {
+    module: &'static str,
+    middleware: Vec<&'static str>,
+    routes: Vec<RouteInfo>,
+}
+
+impl Router for RouteCollector {
+    fn route(&mut self, method: Method, path: &'static str, _endpoint: Arc<dyn Endpoint<State>>) {
+        self.routes.push(RouteInfo {
+            module: self.module,
+            method,
+            path,
+            middleware: self.middleware.clone(),
+        });
+    }
+}
+
+/// Every route module the server knows about, in the order they are mounted. Modules belonging
+/// to subsystems the server was built without are left out.
+pub fn modules() -> Vec<Box<dyn RouteModule>> {
+    vec![
+        Box::new(GraphQLModule),
+        Box::new(HealthModule),
+        #[cfg(feature = "metrics")]
+        Box::new(MetricsModule),
+        #[cfg(feature = "webhooks")]
+        Box::new(BillingModule),
+        Box::new(StorageModule),
+        #[cfg(feature = "oauth")]
+        Box::new(OAuthModule),
+        Box::new(ExportModule),
+    ]
+}
+
+/// Mount every enabled route module on a router.
+pub fn mount(router: &mut dyn Router, modules: &[Box<dyn RouteModule>], config: &Config) {
+    for module in modules.iter().filter(|module| module.enabled(config)) {
+        module.mount(router);
+    }
+}
+
+/// List every HTTP route the enabled route modules expose without needing a running server.
+pub fn describe(modules: &[Box<dyn RouteModule>], config: &Config) -> Vec<RouteInfo> {
+    let mut routes = Vec::new();
+    for module in modules.iter().filter(|module| module.enabled(config)) {
+        let mut collector = RouteCollector {
+            module: module.name(),
+            middleware: global_middleware(config),
+            routes: Vec::new(),
+        };
+        module.mount(&mut collector);
+        routes.append(&mut collector.routes);
+    }
+
+    routes
+}
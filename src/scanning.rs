@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_std::io::prelude::*;
+use async_std::net::TcpStream;
+use async_trait::async_trait;
+
+use crate::config::Config;
+
+/// Error code returned for files rejected by a virus scan.
+pub const FILE_REJECTED_ERROR_CODE: &str = "file-rejected";
+/// Error message returned for files rejected by a virus scan.
+pub const FILE_REJECTED_ERROR_MESSAGE: &str = "The file was rejected by a virus scan.";
+/// Size of the chunks files are streamed to clamd in. This must be smaller than clamd's
+/// "StreamMaxLength" setting.
+const CLAMD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The outcome of scanning a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    /// No threats were found in the file.
+    Clean,
+    /// The file contains a threat, described by the name of the signature it matched.
+    Infected(String),
+}
+
+/// Something that can scan files for viruses and other malware. Implement this to scan files with
+/// an external API instead of clamd.
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    /// A short name for the scanner, used when logging verdicts.
+    fn name(&self) -> &'static str;
+
+    /// Scan the contents of a file. This will return an error if the file couldn't be scanned,
+    /// which callers should treat as a rejection rather than a clean verdict.
+    async fn scan(&self, contents: &[u8]) -> Result<ScanVerdict>;
+}
+
+/// Scans files with ClamAV by streaming them to a clamd daemon over TCP.
+pub struct ClamdScanner {
+    address: String,
+}
+
+impl ClamdScanner {
+    /// Create a scanner for the clamd daemon listening at an address such as "localhost:3310".
+    pub fn new(address: impl Into<String>) -> Self {
+        ClamdScanner {
+            address: address.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Scanner for ClamdScanner {
+    fn name(&self) -> &'static str {
+        "clamd"
+    }
+
+    async fn scan(&self, contents: &[u8]) -> Result<ScanVerdict> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+
+        // Stream the file with the "INSTREAM" command. Each chunk is prefixed with its length,
+        // and a zero-length chunk marks the end of the file.
+        stream.write_all(b"zINSTREAM\0").await?;
+        for chunk in contents.chunks(CLAMD_CHUNK_SIZE) {
+            stream
+                .write_all(&(chunk.len() as u32).to_be_bytes())
+                .await?;
+            stream.write_all(chunk).await?;
+        }
+        stream.write_all(&0u32.to_be_bytes()).await?;
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await?;
+        parse_clamd_reply(&String::from_utf8_lossy(&reply))
+    }
+}
+
+/// Parse clamd's reply to a scan, such as "stream: OK" or "stream: Eicar-Signature FOUND".
+fn parse_clamd_reply(reply: &str) -> Result<ScanVerdict> {
+    let result = reply.trim_end_matches(['\0', '\n']);
+    let result = result.strip_prefix("stream: ").unwrap_or(result);
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected(signature.into()))
+    } else {
+        Err(anyhow!("clamd failed to scan a file: {}", result))
+    }
+}
+
+/// Get the scanner defined by the server configuration. This will return none if scanning isn't
+/// configured.
+pub fn scanner(config: &Config) -> Option<Arc<dyn Scanner>> {
+    let address = config.clamd_address.as_ref()?;
+    Some(Arc::new(ClamdScanner::new(address.clone())))
+}
+
+/// Specifies if a file is large enough that it should be scanned by a background job after it is
+/// stored, rather than before the request storing it completes.
+pub fn scan_in_background(config: &Config, size: usize) -> bool {
+    config.scan_async_threshold_bytes != 0 && size > config.scan_async_threshold_bytes
+}
@@ -1,186 +1,2869 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use graphql_parser::schema::Document;
 use juniper::{
-    graphql_object, graphql_value, EmptySubscription, FieldError, FieldResult, RootNode,
+    graphql_object, graphql_value, EmptySubscription, FieldError, FieldResult, IntoFieldError,
+    Object, RootNode, Value,
 };
-use lazy_static::lazy_static;
-use tide::log;
 use uuid::Uuid;
 
-use crate::context::Context;
-use crate::models::User;
+use crate::announcements::{self, Audience, Severity};
+use crate::audit::{self, AuditEventKind};
+use crate::auth::{
+    ActiveSession, AuthenticatedUser, LoginCredentials, LoginResult, ResendVerificationResult,
+};
+use crate::billing::{CheckoutSession, Plan, PlanDetails};
+use crate::concurrency::{
+    LimitedResolver, ResolverPermit, RESOLVER_BUSY_ERROR_CODE, RESOLVER_BUSY_ERROR_MESSAGE,
+};
+use crate::config::Config;
+use crate::context::Context;
+use crate::deprecations::DeprecatedFieldUsage;
+use crate::domains;
+use crate::email::outbox::{self, OutboxEmail, OutboxStatus};
+use crate::email::settings::{validate_email_settings, EmailSettingsInput};
+use crate::errors::ApiError;
+use crate::executor::Executor;
+use crate::feedback::{self, FeedbackCategory, FeedbackMetadataInput, FeedbackStatus};
+use crate::models::{
+    Announcement, AuditEvent, Feedback, Membership, Organization, OrganizationDomain,
+    OrganizationEmailSettings, Passkey, PendingAction, PendingSignup, PromoCode,
+    PromoCodeRedemption, SecurityEvent, Subscription, Trial, User, WebhookEvent,
+};
+use crate::organizations::{self, MembershipResult, OrganizationRole};
+use crate::pagination::{PageRequest, UserConnection, UserOrder};
+use crate::password_policy::{self, InvalidPassword};
+use crate::pending_actions::PendingActionKind;
+use crate::plugin::{FieldSource, MergedRoot, ObjectFields, Plugin, RootInfo};
+use crate::promo_codes::{self, PromoCodeKind, RedeemResult};
+use crate::query_stats::{self, QueryStatistics, QueryStatisticsOrder};
+use crate::retention;
+use crate::roles::{Access, Role};
+use crate::scanning::{FILE_REJECTED_ERROR_CODE, FILE_REJECTED_ERROR_MESSAGE};
+use crate::security_events;
+use crate::signup_screening::{
+    self, SignupEvidence, SignupResult, CAPTCHA_REQUIRED_ERROR_MESSAGE,
+    SIGNUP_PENDING_REVIEW_ERROR_MESSAGE,
+};
+use crate::uploads::{
+    ImageFormat, Upload, UploadedFile, FILE_NOT_UPLOADED_ERROR_CODE,
+    FILE_NOT_UPLOADED_ERROR_MESSAGE,
+};
+use crate::usage::Usage;
+use crate::user_search::{self, UserFilter, UserSearchHit, UserSort};
+use crate::validation::{self, ONE_OF_INPUT_OBJECTS};
+use crate::webhooks;
+
+/// Queries for the GraphQL schema.
+pub struct Query;
+
+/// Convert a generic "anyhow" result into a GraphQL field result.
+fn convert_result<T>(result: Result<T>) -> FieldResult<T> {
+    result.map_err(|error| ApiError::from(error).into_field_error())
+}
+
+/// Message of the error returned when query statistics are requested but "pg_stat_statements"
+/// isn't available.
+pub const QUERY_STATISTICS_UNAVAILABLE_ERROR_MESSAGE: &str =
+    "Query statistics are unavailable. Install and load the pg_stat_statements extension.";
+
+/// Message of the error returned when a user with read-only access attempts to make changes.
+pub const READ_ONLY_ERROR_MESSAGE: &str =
+    "Your trial has ended. Subscribe to a plan to make changes to your account.";
+
+/// Create an error for a request that must be made by a logged in user.
+fn unauthenticated() -> FieldError {
+    FieldError::new(
+        "You must be logged in to do this.",
+        graphql_value!({ "code": "unauthenticated" }),
+    )
+}
+
+/// Get the user making a request. This will return an error suitable for clients if the request
+/// wasn't made by a logged in user.
+pub fn require_viewer(context: &Context) -> FieldResult<AuthenticatedUser> {
+    context.viewer().copied().ok_or_else(unauthenticated)
+}
+
+/// Ensure the user making a request has a role. This will return an error suitable for clients if
+/// the request wasn't made by a logged in user or the user doesn't have the role.
+pub async fn require_role(context: &Context, role: Role) -> FieldResult<()> {
+    match convert_result(context.access(role).await)? {
+        Access::Granted => Ok(()),
+        Access::Unauthenticated => Err(unauthenticated()),
+        Access::Forbidden => Err(FieldError::new(
+            "You do not have permission to do this.",
+            graphql_value!({ "code": "forbidden" }),
+        )),
+    }
+}
+
+/// Ensure the user making a request can make changes. This will return an error suitable for
+/// clients if the request wasn't made by a logged in user or the user only has read-only access
+/// because their trial has ended. Plugins should use this in mutations that change data.
+pub async fn require_write_access(context: &Context) -> FieldResult<()> {
+    match convert_result(context.write_access().await)? {
+        Access::Granted => Ok(()),
+        Access::Unauthenticated => Err(unauthenticated()),
+        Access::Forbidden => Err(FieldError::new(
+            READ_ONLY_ERROR_MESSAGE,
+            graphql_value!({ "code": "read-only" }),
+        )),
+    }
+}
+
+/// Ensure the user making a request is entitled to a plan. This will return an error suitable for
+/// clients if the request wasn't made by a logged in user or the user's plan doesn't include the
+/// plan. Plugins can use this to restrict their fields to paying users.
+pub async fn require_plan(context: &Context, plan: Plan) -> FieldResult<()> {
+    match convert_result(context.plan_access(plan).await)? {
+        Access::Granted => Ok(()),
+        Access::Unauthenticated => Err(unauthenticated()),
+        Access::Forbidden => Err(FieldError::new(
+            format!("This requires the {} plan.", plan.name()),
+            graphql_value!({ "code": "plan-required" }),
+        )),
+    }
+}
+
+/// Wait for a slot to call an expensive resolver. This will return an error suitable for clients if
+/// too many calls to the resolver are already running and none finish before the queue timeout.
+async fn acquire_permit(
+    context: &Context,
+    resolver: LimitedResolver,
+) -> FieldResult<ResolverPermit> {
+    context
+        .executor()
+        .acquire_permit(resolver)
+        .await
+        .ok_or_else(|| {
+            FieldError::new(
+                RESOLVER_BUSY_ERROR_MESSAGE,
+                graphql_value!({ "code": RESOLVER_BUSY_ERROR_CODE }),
+            )
+        })
+}
+
+/// Create an error for a user that could not be found.
+fn user_not_found() -> FieldError {
+    FieldError::new(
+        "User not found.",
+        graphql_value!({ "code": "user-not-found" }),
+    )
+}
+
+/// Create an error for a pending signup that doesn't exist.
+fn pending_signup_not_found() -> FieldError {
+    FieldError::new(
+        "Pending signup not found.",
+        graphql_value!({ "code": "pending-signup-not-found" }),
+    )
+}
+
+/// Create an error for a file argument whose file wasn't sent with the request.
+fn file_not_uploaded() -> FieldError {
+    FieldError::new(
+        FILE_NOT_UPLOADED_ERROR_MESSAGE,
+        graphql_value!({ "code": FILE_NOT_UPLOADED_ERROR_CODE }),
+    )
+}
+
+/// Create an error for an uploaded file rejected by a virus scan.
+fn file_rejected() -> FieldError {
+    FieldError::new(
+        FILE_REJECTED_ERROR_MESSAGE,
+        graphql_value!({ "code": FILE_REJECTED_ERROR_CODE }),
+    )
+}
+
+/// Message of the error returned when login is locked after too many failed attempts.
+pub const ACCOUNT_LOCKED_ERROR_MESSAGE: &str =
+    "Too many failed login attempts. Login is temporarily locked.";
+
+/// Create an error for a login attempt rejected because login is locked until the specified time.
+fn account_locked(locked_until: DateTime<Utc>) -> FieldError {
+    FieldError::new(
+        ACCOUNT_LOCKED_ERROR_MESSAGE,
+        graphql_value!({ "code": "account-locked", "lockedUntil": (locked_until.to_rfc3339()) }),
+    )
+}
+
+/// Message of the error returned when a deactivated user attempts to log in.
+pub const ACCOUNT_DEACTIVATED_ERROR_MESSAGE: &str = "This account has been deactivated.";
+
+/// Create an error for a login attempt rejected because the user has been deactivated.
+fn account_deactivated() -> FieldError {
+    FieldError::new(
+        ACCOUNT_DEACTIVATED_ERROR_MESSAGE,
+        graphql_value!({ "code": "account-deactivated" }),
+    )
+}
+
+/// Message of the error returned when a user required to reset their password attempts to log in
+/// with it.
+pub const PASSWORD_RESET_REQUIRED_ERROR_MESSAGE: &str =
+    "This account's password must be reset before logging in.";
+
+/// Create an error for a login attempt rejected because the user is required to reset their
+/// password.
+fn password_reset_required() -> FieldError {
+    FieldError::new(
+        PASSWORD_RESET_REQUIRED_ERROR_MESSAGE,
+        graphql_value!({ "code": "password-reset-required" }),
+    )
+}
+
+/// Create an error for an announcement that could not be found.
+fn announcement_not_found() -> FieldError {
+    FieldError::new(
+        "Announcement not found.",
+        graphql_value!({ "code": "announcement-not-found" }),
+    )
+}
+
+/// Create an error for feedback that could not be found.
+fn feedback_not_found() -> FieldError {
+    FieldError::new(
+        "Feedback not found.",
+        graphql_value!({ "code": "feedback-not-found" }),
+    )
+}
+
+/// Create an error for an organization that could not be found, or that the user making the
+/// request isn't a member of.
+fn organization_not_found() -> FieldError {
+    FieldError::new(
+        "Organization not found.",
+        graphql_value!({ "code": "organization-not-found" }),
+    )
+}
+
+/// Ensure the user making a request is an owner or admin of an organization. This will return an
+/// error suitable for clients if they aren't, hiding the organization from users that aren't
+/// members of it.
+async fn require_organization_admin(
+    context: &Context,
+    organization_id: Uuid,
+) -> FieldResult<AuthenticatedUser> {
+    let viewer = require_viewer(context)?;
+    let role = convert_result(
+        context
+            .executor()
+            .find_member_role(organization_id, viewer.user_id)
+            .await,
+    )?
+    .ok_or_else(organization_not_found)?;
+    if role == OrganizationRole::Member {
+        return Err(FieldError::new(
+            "You do not have permission to do this.",
+            graphql_value!({ "code": "forbidden" }),
+        ));
+    }
+
+    Ok(viewer)
+}
+
+/// Create an error for a custom hostname that isn't a valid fully qualified domain name.
+fn invalid_hostname() -> FieldError {
+    FieldError::new(
+        "Hostnames must be fully qualified domain names, such as \"api.example.com\".",
+        graphql_value!({ "code": "invalid-hostname" }),
+    )
+}
+
+/// Create an error for a custom hostname added while organization domains are disabled.
+fn custom_domains_disabled() -> FieldError {
+    FieldError::new(
+        "Custom hostnames are not enabled on this server.",
+        graphql_value!({ "code": "custom-domains-disabled" }),
+    )
+}
+
+/// Create an error for a custom hostname added to an organization that already has as many as it
+/// can.
+fn too_many_domains() -> FieldError {
+    FieldError::new(
+        format!(
+            "Organizations can't have more than {} custom hostnames.",
+            domains::MAX_DOMAINS_PER_ORGANIZATION
+        ),
+        graphql_value!({ "code": "too-many-domains" }),
+    )
+}
+
+/// Create an error for an invitation to an organization that could not be found.
+fn invite_not_found() -> FieldError {
+    FieldError::new(
+        "Invitation not found.",
+        graphql_value!({ "code": "invite-not-found" }),
+    )
+}
+
+/// Create an error for a webhook event that could not be found.
+fn webhook_event_not_found() -> FieldError {
+    FieldError::new(
+        "Webhook event not found.",
+        graphql_value!({ "code": "webhook-event-not-found" }),
+    )
+}
+
+/// Create an error for an email that could not be found in the dead letter list.
+fn dead_email_not_found() -> FieldError {
+    FieldError::new(
+        "Email not found in the dead letter list.",
+        graphql_value!({ "code": "email-not-found" }),
+    )
+}
+
+/// Message of the error returned when feedback is submitted too often.
+pub const FEEDBACK_RATE_LIMITED_ERROR_MESSAGE: &str =
+    "Too much feedback has been submitted recently. Try again later.";
+
+/// Create an error for feedback rejected because too much has been submitted recently.
+fn feedback_rate_limited() -> FieldError {
+    FieldError::new(
+        FEEDBACK_RATE_LIMITED_ERROR_MESSAGE,
+        graphql_value!({ "code": "rate-limited" }),
+    )
+}
+
+/// Message of the error returned when a verification email was resent too recently or too often.
+pub const VERIFICATION_EMAIL_THROTTLED_ERROR_MESSAGE: &str =
+    "The verification email was resent too recently. Try again later.";
+
+/// Get the number of whole seconds until a time, rounded up so clients waiting that long aren't
+/// early.
+pub fn seconds_until(time: DateTime<Utc>) -> i64 {
+    let milliseconds = (time - Utc::now()).num_milliseconds().max(0);
+    (milliseconds + 999) / 1000
+}
+
+/// Create an error for a verification email resend rejected until the specified time.
+fn verification_email_throttled(retry_at: DateTime<Utc>) -> FieldError {
+    let retry_after_seconds = seconds_until(retry_at) as i32;
+    FieldError::new(
+        VERIFICATION_EMAIL_THROTTLED_ERROR_MESSAGE,
+        graphql_value!({
+            "code": "rate-limited",
+            "retryAt": (retry_at.to_rfc3339()),
+            "retryAfterSeconds": retry_after_seconds,
+        }),
+    )
+}
+
+/// Create an error for a billing operation attempted while billing isn't configured.
+fn billing_unavailable() -> FieldError {
+    FieldError::new(
+        "Billing is not available.",
+        graphql_value!({ "code": "billing-unavailable" }),
+    )
+}
+
+/// Create an error for query statistics requested while "pg_stat_statements" isn't available.
+fn query_statistics_unavailable() -> FieldError {
+    FieldError::new(
+        QUERY_STATISTICS_UNAVAILABLE_ERROR_MESSAGE,
+        graphql_value!({ "code": "query-statistics-unavailable" }),
+    )
+}
+
+/// A reason input to a query or mutation was rejected.
+pub struct InvalidInput {
+    /// A human-readable description of the problem.
+    pub message: Cow<'static, str>,
+    /// A stable error code for clients to match on.
+    pub code: &'static str,
+}
+
+impl InvalidInput {
+    fn new(message: &'static str, code: &'static str) -> Option<Self> {
+        Some(InvalidInput {
+            message: message.into(),
+            code,
+        })
+    }
+}
+
+impl IntoFieldError for InvalidInput {
+    fn into_field_error(self) -> FieldError {
+        FieldError::new(self.message, graphql_value!({ "code": (self.code) }))
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+impl From<InvalidInput> for async_graphql::Error {
+    fn from(InvalidInput { message, code }: InvalidInput) -> Self {
+        use async_graphql::ErrorExtensions;
+
+        async_graphql::Error::new(message).extend_with(|_, extensions| extensions.set("code", code))
+    }
+}
+
+/// Validate the username and email address of a user that is about to be created. Their password
+/// is validated separately with `validate_password`. This will return the first problem found with
+/// the details, or none if they are valid.
+pub async fn validate_new_user(
+    executor: &Executor,
+    username: &str,
+    email: &str,
+) -> Result<Option<InvalidInput>> {
+    if let Some(invalid) = validation::validate_username(username) {
+        return Ok(Some(invalid));
+    }
+
+    if executor
+        .find_user_by_username_including_deleted(username)
+        .await?
+        .is_some()
+    {
+        return Ok(InvalidInput::new(
+            "Username is already in use.",
+            "username-taken",
+        ));
+    }
+
+    Ok(validate_new_email(executor, email).await)
+}
+
+/// Validate changes a user is about to make to their own profile. Details that are none are left
+/// unchanged and aren't validated. This will return the first problem found with the changes, or
+/// none if they are valid.
+pub async fn validate_profile_update(
+    executor: &Executor,
+    user_id: Uuid,
+    username: Option<&str>,
+    email: Option<&str>,
+) -> Result<Option<InvalidInput>> {
+    if let Some(username) = username {
+        if let Some(invalid) = validation::validate_username(username) {
+            return Ok(Some(invalid));
+        }
+
+        if let Some(user) = executor
+            .find_user_by_username_including_deleted(username)
+            .await?
+        {
+            if user.id != user_id {
+                return Ok(InvalidInput::new(
+                    "Username is already in use.",
+                    "username-taken",
+                ));
+            }
+        }
+    }
+
+    if let Some(email) = email {
+        if let Some(invalid) = validate_new_email(executor, email).await {
+            return Ok(Some(invalid));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Validate an email address a user is about to start using. Its domain is only looked up if MX
+/// lookups are enabled, and the address is let through if the lookup fails. This will return the
+/// first problem found with the address, or none if it is valid.
+async fn validate_new_email(executor: &Executor, email: &str) -> Option<InvalidInput> {
+    if let Some(invalid) = validation::validate_email(email) {
+        return Some(invalid);
+    }
+
+    if !executor.config().email_mx_lookup_enabled {
+        return None;
+    }
+
+    match validation::validate_email_domain(email).await {
+        Ok(invalid) => invalid,
+        Err(error) => {
+            log::warn!(
+                "Failed to look up the domain of an email address: {}",
+                error
+            );
+            None
+        }
+    }
+}
+
+/// Validate a password a user is about to start using against the configured password policy.
+/// The user's details, such as their username and email address, can't appear in the password.
+/// This will return the first problem found with the password, or none if it is valid.
+pub fn validate_password(
+    config: &Config,
+    password: &str,
+    user_details: &[&str],
+) -> Option<InvalidPassword> {
+    password_policy::check_password(&config.password_policy, password, user_details)
+}
+
+/// Create an error for a password rejected by the password policy, with feedback to help the user
+/// choose a better one.
+fn invalid_password(
+    InvalidPassword {
+        message,
+        code,
+        warning,
+        suggestions,
+    }: InvalidPassword,
+) -> FieldError {
+    let mut extensions = Object::with_capacity(3);
+    extensions.add_field("code", Value::scalar(code));
+    extensions.add_field("warning", warning.map_or(Value::null(), Value::scalar));
+    extensions.add_field(
+        "suggestions",
+        Value::list(suggestions.into_iter().map(Value::scalar).collect()),
+    );
+    FieldError::new(message, Value::Object(extensions))
+}
+
+/// Validate the details of a promo code that is about to be created. This will return the first
+/// problem found with the details, or none if they are valid.
+pub async fn validate_new_promo_code(
+    executor: &Executor,
+    code: &str,
+    kind: PromoCodeKind,
+    amount: i32,
+    max_redemptions: Option<i32>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Option<InvalidInput>> {
+    if !promo_codes::is_valid_code(&promo_codes::normalize_code(code)) {
+        return Ok(InvalidInput::new(
+            "Promo codes must be 3 to 32 letters, digits, dashes or underscores.",
+            "invalid-promo-code",
+        ));
+    }
+
+    if executor.find_promo_code_by_code(code).await?.is_some() {
+        return Ok(InvalidInput::new(
+            "Promo code is already in use.",
+            "promo-code-taken",
+        ));
+    }
+
+    let valid_amount = match kind {
+        PromoCodeKind::Percentage => (1..=100).contains(&amount),
+        PromoCodeKind::Fixed => amount > 0,
+    };
+    if !valid_amount {
+        return Ok(InvalidInput::new(
+            "Percentage discounts must be between 1 and 100, and fixed discounts must be positive.",
+            "invalid-discount",
+        ));
+    }
+
+    if max_redemptions.is_some_and(|max_redemptions| max_redemptions <= 0) {
+        return Ok(InvalidInput::new(
+            "Max redemptions must be positive.",
+            "invalid-max-redemptions",
+        ));
+    }
+
+    if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return Ok(InvalidInput::new(
+            "Expiry date must be in the future.",
+            "invalid-expiry",
+        ));
+    }
+
+    Ok(None)
+}
+
+/// Validate the details of an announcement that is about to be created or updated. This will
+/// return the first problem found with the details, or none if they are valid.
+pub fn validate_announcement(
+    message: &str,
+    starts_at: DateTime<Utc>,
+    ends_at: Option<DateTime<Utc>>,
+) -> Option<InvalidInput> {
+    if message.trim().is_empty() {
+        return InvalidInput::new("Message cannot be empty.", "message-empty");
+    }
+
+    if message.len() > announcements::MAX_MESSAGE_LENGTH {
+        return InvalidInput::new("Message cannot exceed 1000 characters.", "message-too-long");
+    }
+
+    if ends_at.is_some_and(|ends_at| ends_at <= starts_at) {
+        return InvalidInput::new("End date must be after the start date.", "invalid-end-date");
+    }
+
+    None
+}
+
+/// Validate an image a user is about to use as their avatar. This will return the format of the
+/// image, or the problem found with it.
+pub fn validate_avatar(config: &Config, file: &UploadedFile) -> Result<ImageFormat, InvalidInput> {
+    if file.contents.len() > config.avatar_max_bytes {
+        return Err(InvalidInput {
+            message: "Avatar is too large.".into(),
+            code: "avatar-too-large",
+        });
+    }
+
+    ImageFormat::detect(&file.contents).ok_or(InvalidInput {
+        message: "Avatar must be a PNG, JPEG, GIF or WebP image.".into(),
+        code: "invalid-avatar",
+    })
+}
+
+/// Validate feedback that is about to be submitted. This will return the first problem found with
+/// the feedback, or none if it is valid.
+pub fn validate_feedback(
+    message: &str,
+    metadata: &[FeedbackMetadataInput],
+) -> Option<InvalidInput> {
+    if message.trim().is_empty() {
+        return InvalidInput::new("Message cannot be empty.", "message-empty");
+    }
+
+    if message.len() > feedback::MAX_MESSAGE_LENGTH {
+        return InvalidInput::new("Message cannot exceed 5000 characters.", "message-too-long");
+    }
+
+    if metadata.len() > feedback::MAX_METADATA_ENTRIES {
+        return InvalidInput::new(
+            "Feedback cannot have more than 20 metadata entries.",
+            "too-much-metadata",
+        );
+    }
+
+    let is_valid_entry = |entry: &FeedbackMetadataInput| {
+        !entry.key.is_empty()
+            && entry.key.len() <= feedback::MAX_METADATA_KEY_LENGTH
+            && entry.value.len() <= feedback::MAX_METADATA_VALUE_LENGTH
+    };
+    if !metadata.iter().all(is_valid_entry) {
+        return InvalidInput::new(
+            "Metadata keys must be 1 to 64 characters, and values cannot exceed 1000 characters.",
+            "invalid-metadata",
+        );
+    }
+
+    None
+}
+
+/// Validate the page of a list to return, from the number of items to return and skip, falling
+/// back to the list's default page size. This will return the number of items to return and skip,
+/// or the problem found with the page.
+pub fn validate_page(
+    first: Option<i32>,
+    offset: Option<i32>,
+    default_size: i32,
+    max_size: i32,
+) -> Result<(i64, i64), InvalidInput> {
+    let first = first.unwrap_or(default_size);
+    if !(1..=max_size).contains(&first) {
+        return Err(InvalidInput {
+            message: format!("Page size must be between 1 and {}.", max_size).into(),
+            code: "invalid-page-size",
+        });
+    }
+
+    let offset = offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(InvalidInput {
+            message: "Offset cannot be negative.".into(),
+            code: "invalid-offset",
+        });
+    }
+
+    Ok((first.into(), offset.into()))
+}
+
+/// Validate a search for users and the page of results to return. This will return the number of
+/// users to return and skip, or the problem found with the search.
+pub fn validate_user_search(
+    filter: &UserFilter,
+    sort: &[UserSort],
+    first: Option<i32>,
+    offset: Option<i32>,
+) -> Result<(i64, i64), InvalidInput> {
+    let filters = [&filter.username_prefix, &filter.email_domain];
+    if filters.iter().any(|value| value.as_deref() == Some("")) {
+        return Err(InvalidInput {
+            message: "Search filters cannot be empty.".into(),
+            code: "invalid-filter",
+        });
+    }
+    if filters.iter().any(|value| {
+        value
+            .as_ref()
+            .is_some_and(|value| value.len() > user_search::MAX_FILTER_LENGTH)
+    }) {
+        return Err(InvalidInput {
+            message: "Search filters cannot be longer than 255 characters.".into(),
+            code: "invalid-filter",
+        });
+    }
+    if let (Some(created_after), Some(created_before)) =
+        (filter.created_after, filter.created_before)
+    {
+        if created_after >= created_before {
+            return Err(InvalidInput {
+                message: "The creation date range must end after it starts.".into(),
+                code: "invalid-date-range",
+            });
+        }
+    }
+
+    if sort.len() > user_search::MAX_SORT_FIELDS {
+        return Err(InvalidInput {
+            message: "Users cannot be sorted by more than 4 fields.".into(),
+            code: "invalid-sort",
+        });
+    }
+    for (index, entry) in sort.iter().enumerate() {
+        if sort[..index]
+            .iter()
+            .any(|previous| previous.field == entry.field)
+        {
+            return Err(InvalidInput {
+                message: "Users cannot be sorted by the same field twice.".into(),
+                code: "invalid-sort",
+            });
+        }
+    }
+
+    let (first, offset) = validate_page(
+        first,
+        offset,
+        user_search::DEFAULT_PAGE_SIZE,
+        user_search::MAX_PAGE_SIZE,
+    )?;
+
+    Ok((first, offset))
+}
+
+/// Validate a full-text search for users and the page of results to return. This will return
+/// the words searched for and the number of users to return and skip, or the problem found with
+/// the search.
+pub fn validate_full_text_search(
+    query: &str,
+    first: Option<i32>,
+    offset: Option<i32>,
+) -> Result<(Vec<String>, i64, i64), InvalidInput> {
+    if query.len() > user_search::MAX_QUERY_LENGTH {
+        return Err(InvalidInput {
+            message: "Search queries cannot be longer than 255 characters.".into(),
+            code: "invalid-query",
+        });
+    }
+    let terms = user_search::query_terms(query);
+    if terms.is_empty() {
+        return Err(InvalidInput {
+            message: "Search queries must contain a letter or digit.".into(),
+            code: "invalid-query",
+        });
+    }
+    if terms.len() > user_search::MAX_QUERY_TERMS {
+        return Err(InvalidInput {
+            message: "Search queries cannot contain more than 8 words.".into(),
+            code: "invalid-query",
+        });
+    }
+
+    let (first, offset) = validate_page(
+        first,
+        offset,
+        user_search::DEFAULT_PAGE_SIZE,
+        user_search::MAX_PAGE_SIZE,
+    )?;
+
+    Ok((terms, first, offset))
+}
+
+/// Validate the name of an organization. This will return the problem found with the name, or
+/// none if it is valid.
+pub fn validate_organization_name(name: &str) -> Option<InvalidInput> {
+    if name.trim().is_empty() {
+        return InvalidInput::new(
+            "Organization name cannot be empty.",
+            "organization-name-empty",
+        );
+    }
+
+    if name.chars().count() > organizations::MAX_NAME_LENGTH {
+        return InvalidInput::new(
+            "Organization name cannot be longer than 64 characters.",
+            "organization-name-too-long",
+        );
+    }
+
+    None
+}
+
+/// Convert the outcome of an attempt to change the members of an organization into the changed
+/// membership, or an error suitable for clients if nothing was changed.
+pub fn convert_membership_result(result: MembershipResult) -> Result<Membership, InvalidInput> {
+    let (message, code) = match result {
+        MembershipResult::Changed(membership) => return Ok(membership),
+        MembershipResult::OrganizationNotFound => {
+            ("Organization not found.", "organization-not-found")
+        }
+        MembershipResult::Forbidden => ("You do not have permission to do this.", "forbidden"),
+        MembershipResult::UserNotFound => ("User not found.", "user-not-found"),
+        MembershipResult::MemberNotFound => ("Member not found.", "member-not-found"),
+        MembershipResult::AlreadyMember => (
+            "User is already a member of or invited to this organization.",
+            "already-member",
+        ),
+        MembershipResult::LastOwner => {
+            ("Organizations must have at least one owner.", "last-owner")
+        }
+    };
+
+    Err(InvalidInput {
+        message: message.into(),
+        code,
+    })
+}
+
+/// Validate the number of queries requested when listing the top queries, returning the number
+/// to list. This defaults to 20 queries.
+pub fn validate_query_count(first: Option<i32>) -> Result<i64, InvalidInput> {
+    let first = first.unwrap_or(query_stats::DEFAULT_QUERY_COUNT);
+    if !(1..=query_stats::MAX_QUERY_COUNT).contains(&first) {
+        return Err(InvalidInput {
+            message: "Query count must be between 1 and 100.".into(),
+            code: "invalid-page-size",
+        });
+    }
+
+    Ok(first.into())
+}
+
+/// Convert the outcome of an attempt to redeem a promo code into the redemption, or an error
+/// suitable for clients if the promo code wasn't redeemed.
+pub fn convert_redeem_result(result: RedeemResult) -> Result<PromoCodeRedemption, InvalidInput> {
+    let (message, code) = match result {
+        RedeemResult::Redeemed(redemption) => return Ok(redemption),
+        RedeemResult::NotFound => ("Promo code not found.", "promo-code-not-found"),
+        RedeemResult::Expired => ("Promo code has expired.", "promo-code-expired"),
+        RedeemResult::Exhausted => (
+            "Promo code has been redeemed too many times.",
+            "promo-code-exhausted",
+        ),
+        RedeemResult::AlreadyRedeemed => (
+            "You have already redeemed this promo code.",
+            "promo-code-already-redeemed",
+        ),
+        RedeemResult::Pending => (
+            "You have already redeemed a promo code that will be applied when you subscribe.",
+            "promo-code-pending",
+        ),
+    };
+
+    Err(InvalidInput {
+        message: message.into(),
+        code,
+    })
+}
+
+#[graphql_object(context = Context, description="All available GraphQL queries.")]
+impl Query {
+    #[graphql(
+        description = "Find a user by their ID. Deleted users are left out unless
+        \"includeDeleted\" is set.",
+        arguments(
+            id(description = "The user's ID."),
+            include_deleted(description = "Find the user even if they have been deleted. Only
+            admins can find deleted users."),
+        )
+    )]
+    async fn user(
+        &self,
+        context: &Context,
+        id: Uuid,
+        include_deleted: Option<bool>,
+    ) -> FieldResult<Option<User>> {
+        if include_deleted == Some(true) {
+            require_role(context, Role::Admin).await?;
+            return convert_result(context.executor().find_user_including_deleted(id).await);
+        }
+
+        convert_result(context.load_user(id).await)
+    }
+
+    #[graphql(
+        description = "Find a user by their username. Deleted users are left out unless
+        \"includeDeleted\" is set.",
+        arguments(
+            username(description = "The user's username."),
+            include_deleted(description = "Find the user even if they have been deleted. Only
+            admins can find deleted users."),
+        )
+    )]
+    async fn user_by_username(
+        &self,
+        context: &Context,
+        username: String,
+        include_deleted: Option<bool>,
+    ) -> FieldResult<Option<User>> {
+        if include_deleted == Some(true) {
+            require_role(context, Role::Admin).await?;
+            return convert_result(
+                context
+                    .executor()
+                    .find_user_by_username_including_deleted(&username)
+                    .await,
+            );
+        }
+
+        convert_result(context.load_user_by_username(&username).await)
+    }
+
+    #[graphql(
+        description = "Find what a user looked like at a moment in the past, including deleted
+        users. This will return null if the user didn't exist at that moment. Only admins can look
+        at the history of users.",
+        arguments(
+            id(description = "The user's ID."),
+            timestamp(description = "The moment to look at the user as of."),
+        )
+    )]
+    async fn user_at_time(
+        &self,
+        context: &Context,
+        id: Uuid,
+        timestamp: DateTime<Utc>,
+    ) -> FieldResult<Option<User>> {
+        require_role(context, Role::Admin).await?;
+        convert_result(context.executor().find_user_at_time(id, timestamp).await)
+    }
+
+    #[graphql(
+        description = "Get the user making the request. This will return null if the request
+        wasn't sent with a valid session token."
+    )]
+    async fn me(&self, context: &Context) -> FieldResult<Option<User>> {
+        match context.viewer() {
+            Some(viewer) => convert_result(context.load_user(viewer.user_id).await),
+            None => Ok(None),
+        }
+    }
+
+    #[graphql(
+        description = "List the active sessions of the user making the request, most recently used
+        first."
+    )]
+    async fn my_sessions(&self, context: &Context) -> FieldResult<Vec<ActiveSession>> {
+        let viewer = require_viewer(context)?;
+        convert_result(context.executor().find_sessions(viewer).await)
+    }
+
+    #[graphql(description = "List the passkeys of the user making the request, oldest first.")]
+    async fn my_passkeys(&self, context: &Context) -> FieldResult<Vec<Passkey>> {
+        let viewer = require_viewer(context)?;
+        convert_result(
+            context
+                .executor()
+                .find_passkeys_by_user(viewer.user_id)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Get a token for a signup form being loaded, to be sent back with
+        \"createUser\". Signups without one, or sent too soon after it was issued, look like
+        they were made by a bot."
+    )]
+    fn signup_form_token(&self, context: &Context) -> String {
+        context.executor().signup_form_token()
+    }
+
+    #[graphql(
+        description = "List signups that looked like they were made by a bot and are waiting to be
+        approved or rejected, oldest first. Only admins can list pending signups.",
+        arguments(
+            first(description = "The number of signups to return. Defaults to 50, up to 100."),
+            offset(description = "The number of signups to skip. Defaults to 0."),
+        )
+    )]
+    async fn pending_signups(
+        &self,
+        context: &Context,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<PendingSignup>> {
+        require_role(context, Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            signup_screening::DEFAULT_PAGE_SIZE,
+            signup_screening::MAX_PAGE_SIZE,
+        )
+        .map_err(InvalidInput::into_field_error)?;
+
+        convert_result(context.executor().find_pending_signups(limit, offset).await)
+    }
+
+    #[graphql(
+        description = "List users under a legal hold, including deleted users, longest held first.
+        Held users are exempt from retention purges and deletion. Only admins can list legal
+        holds.",
+        arguments(
+            first(description = "The number of users to return. Defaults to 50, up to 100."),
+            offset(description = "The number of users to skip. Defaults to 0."),
+        )
+    )]
+    async fn legal_holds(
+        &self,
+        context: &Context,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<User>> {
+        require_role(context, Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            retention::LEGAL_HOLD_DEFAULT_PAGE_SIZE,
+            retention::LEGAL_HOLD_MAX_PAGE_SIZE,
+        )
+        .map_err(InvalidInput::into_field_error)?;
+
+        convert_result(context.executor().find_legal_holds(limit, offset).await)
+    }
+
+    #[graphql(
+        description = "List the events providers such as Stripe sent to the server's webhooks,
+        newest first. Only admins can list webhook events.",
+        arguments(
+            first(description = "The number of events to return. Defaults to 50, up to 100."),
+            offset(description = "The number of events to skip. Defaults to 0."),
+            unprocessed_only(description = "Only list events that haven't been processed
+            successfully, such as events that failed and need to be replayed."),
+        )
+    )]
+    async fn webhook_events(
+        &self,
+        context: &Context,
+        first: Option<i32>,
+        offset: Option<i32>,
+        unprocessed_only: Option<bool>,
+    ) -> FieldResult<Vec<WebhookEvent>> {
+        require_role(context, Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            webhooks::DEFAULT_PAGE_SIZE,
+            webhooks::MAX_PAGE_SIZE,
+        )
+        .map_err(InvalidInput::into_field_error)?;
+
+        convert_result(
+            context
+                .executor()
+                .find_webhook_events(limit, offset, unprocessed_only.unwrap_or(false))
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "List the emails in part of the outbox, with their bodies redacted. Queued
+        emails are listed in the order they will be sent, and dead emails newest first. Only
+        admins can list emails.",
+        arguments(
+            status(description = "Whether to list queued or dead emails."),
+            first(description = "The number of emails to return. Defaults to 50, up to 100."),
+            offset(description = "The number of emails to skip. Defaults to 0."),
+        )
+    )]
+    async fn outbox_emails(
+        &self,
+        context: &Context,
+        status: OutboxStatus,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<OutboxEmail>> {
+        require_role(context, Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            outbox::DEFAULT_PAGE_SIZE,
+            outbox::MAX_PAGE_SIZE,
+        )
+        .map_err(InvalidInput::into_field_error)?;
+
+        convert_result(
+            context
+                .executor()
+                .find_outbox_emails(status, limit, offset)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "List the security activity of the account of the user making the request,
+        such as logins, failed logins and password changes, newest first.",
+        arguments(
+            first(description = "The number of events to return. Defaults to 50, up to 100."),
+            offset(description = "The number of events to skip. Defaults to 0."),
+        )
+    )]
+    async fn my_security_activity(
+        &self,
+        context: &Context,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<SecurityEvent>> {
+        let viewer = require_viewer(context)?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            security_events::DEFAULT_PAGE_SIZE,
+            security_events::MAX_PAGE_SIZE,
+        )
+        .map_err(InvalidInput::into_field_error)?;
+
+        convert_result(
+            context
+                .executor()
+                .find_security_events(viewer.user_id, limit, offset)
+                .await,
+        )
+    }
+
+    #[graphql(description = "List every plan that can be subscribed to.")]
+    fn plans(&self, context: &Context) -> Vec<PlanDetails> {
+        PlanDetails::all(context.executor().config())
+    }
+
+    #[graphql(
+        description = "Get the subscription of the user making the request. This will return null
+        if the user has never subscribed to a paid plan."
+    )]
+    async fn my_subscription(&self, context: &Context) -> FieldResult<Option<Subscription>> {
+        let viewer = require_viewer(context)?;
+        convert_result(context.executor().find_subscription(viewer.user_id).await)
+    }
+
+    #[graphql(
+        description = "Get the trial of the user making the request. This will return null if the
+        user was never given a trial."
+    )]
+    async fn my_trial(&self, context: &Context) -> FieldResult<Option<Trial>> {
+        let viewer = require_viewer(context)?;
+        convert_result(context.executor().find_trial(viewer.user_id).await)
+    }
+
+    #[graphql(
+        description = "Get the metered usage of the user making the request in the current billing
+        period, along with the limits of their plan."
+    )]
+    async fn my_usage(&self, context: &Context) -> FieldResult<Usage> {
+        let viewer = require_viewer(context)?;
+        convert_result(context.executor().find_usage(viewer.user_id).await)
+    }
+
+    #[graphql(
+        description = "List the organizations the user making the request is a member of or
+        invited to, oldest first. Invitations that haven't been accepted have a null
+        \"acceptedAt\"."
+    )]
+    async fn my_organizations(&self, context: &Context) -> FieldResult<Vec<Membership>> {
+        let viewer = require_viewer(context)?;
+        convert_result(
+            context
+                .executor()
+                .find_user_memberships(viewer.user_id)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Find an organization by its ID. This will return null if the organization
+        does not exist or the user making the request isn't a member of it.",
+        arguments(id(description = "The organization's ID."))
+    )]
+    async fn organization(&self, context: &Context, id: Uuid) -> FieldResult<Option<Organization>> {
+        let viewer = require_viewer(context)?;
+        let executor = context.executor();
+        if convert_result(executor.find_member_role(id, viewer.user_id).await)?.is_none() {
+            return Ok(None);
+        }
+
+        convert_result(executor.find_organization(id).await)
+    }
+
+    #[graphql(
+        description = "List the members of an organization, including invited users, in the order
+        they were invited. Only members of the organization can list its members.",
+        arguments(
+            organization_id(description = "The organization's ID."),
+            first(description = "The number of members to return. Defaults to 50, up to 100."),
+            offset(description = "The number of members to skip. Defaults to 0."),
+        )
+    )]
+    async fn organization_members(
+        &self,
+        context: &Context,
+        organization_id: Uuid,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<Membership>> {
+        let viewer = require_viewer(context)?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            organizations::DEFAULT_PAGE_SIZE,
+            organizations::MAX_PAGE_SIZE,
+        )
+        .map_err(InvalidInput::into_field_error)?;
+
+        let executor = context.executor();
+        convert_result(
+            executor
+                .find_member_role(organization_id, viewer.user_id)
+                .await,
+        )?
+        .ok_or_else(organization_not_found)?;
+        convert_result(
+            executor
+                .find_organization_members(organization_id, limit, offset)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Find the email settings an organization overrides the server's with, for
+        emails sent on its behalf such as invitations. This will return null if it hasn't set any.
+        Only owners and admins of the organization can see its email settings.",
+        arguments(organization_id(description = "The organization's ID."))
+    )]
+    async fn organization_email_settings(
+        &self,
+        context: &Context,
+        organization_id: Uuid,
+    ) -> FieldResult<Option<OrganizationEmailSettings>> {
+        require_organization_admin(context, organization_id).await?;
+        convert_result(
+            context
+                .executor()
+                .find_organization_email_settings(organization_id)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "List the custom hostnames of an organization, verified or not, oldest
+        first. Only owners and admins of the organization can list its hostnames.",
+        arguments(organization_id(description = "The organization's ID."))
+    )]
+    async fn organization_domains(
+        &self,
+        context: &Context,
+        organization_id: Uuid,
+    ) -> FieldResult<Vec<OrganizationDomain>> {
+        require_organization_admin(context, organization_id).await?;
+        convert_result(
+            context
+                .executor()
+                .find_organization_domains(organization_id)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Find the organization the request was sent to through one of its verified
+        custom hostnames. This will return null if the request was sent to the server's own
+        hostname."
+    )]
+    async fn current_organization(&self, context: &Context) -> FieldResult<Option<Organization>> {
+        match context.host_organization() {
+            Some(organization_id) => {
+                convert_result(context.executor().find_organization(organization_id).await)
+            }
+            None => Ok(None),
+        }
+    }
+
+    #[graphql(
+        description = "Paginate through users. Use \"first\" and \"after\" to paginate forward or
+        \"last\" and \"before\" to paginate backward. Pages default to the first 20 users and
+        cannot be larger than 100 users. Only admins can list users.",
+        arguments(
+            first(description = "The number of users to return after the \"after\" cursor."),
+            after(description = "The cursor to return users after."),
+            last(description = "The number of users to return before the \"before\" cursor."),
+            before(description = "The cursor to return users before."),
+            order_by(description = "The order to return users in. Defaults to oldest first."),
+            include_deleted(description = "Include users that have been deleted. Defaults to
+            false."),
+        )
+    )]
+    async fn users(
+        &self,
+        context: &Context,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+        order_by: Option<UserOrder>,
+        include_deleted: Option<bool>,
+    ) -> FieldResult<UserConnection> {
+        require_role(context, Role::Admin).await?;
+        let request = PageRequest::new(first, after, last, before, order_by)
+            .map_err(InvalidInput::into_field_error)?;
+
+        let _permit = acquire_permit(context, LimitedResolver::Users).await?;
+        convert_result(
+            context
+                .executor()
+                .find_users(&request, include_deleted.unwrap_or(false))
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Search for users matching a filter, sorted by any number of fields in turn.
+        Users that sort the same are ordered by ID, so pages are stable. Only admins can search
+        users.",
+        arguments(
+            filter(description = "Conditions users must meet. Defaults to every user."),
+            sort(
+                description = "The fields to sort users by, most significant first. Defaults to
+            sorting by ID."
+            ),
+            first(description = "The number of users to return. Defaults to 20, up to 100."),
+            offset(description = "The number of users to skip. Defaults to 0."),
+        )
+    )]
+    async fn search_users(
+        &self,
+        context: &Context,
+        filter: Option<UserFilter>,
+        sort: Option<Vec<UserSort>>,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<User>> {
+        require_role(context, Role::Admin).await?;
+        let filter = filter.unwrap_or_default();
+        let sort = sort.unwrap_or_default();
+        let (limit, offset) = validate_user_search(&filter, &sort, first, offset)
+            .map_err(InvalidInput::into_field_error)?;
+
+        let _permit = acquire_permit(context, LimitedResolver::Users).await?;
+        convert_result(
+            context
+                .executor()
+                .search_users(&filter, &sort, limit, offset)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Search for users by the words in their username and email address, most
+        relevant first. Each word in the query matches words starting with it, and every word must
+        match. Username matches rank above email address matches. Deleted users are left out. Only
+        admins can search users.",
+        arguments(
+            query(description = "The words to search for, such as \"jane example\"."),
+            first(description = "The number of users to return. Defaults to 20, up to 100."),
+            offset(description = "The number of users to skip. Defaults to 0."),
+        )
+    )]
+    async fn search_users_full_text(
+        &self,
+        context: &Context,
+        query: String,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<UserSearchHit>> {
+        require_role(context, Role::Admin).await?;
+        let (terms, limit, offset) = validate_full_text_search(&query, first, offset)
+            .map_err(InvalidInput::into_field_error)?;
+
+        let _permit = acquire_permit(context, LimitedResolver::Users).await?;
+        convert_result(
+            context
+                .executor()
+                .search_users_full_text(&terms, limit, offset)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "List the announcements currently being shown to the user making the
+        request, newest first. Announcements the user has dismissed are left out."
+    )]
+    async fn active_announcements(&self, context: &Context) -> FieldResult<Vec<Announcement>> {
+        let is_admin = convert_result(context.access(Role::Admin).await)? == Access::Granted;
+        let user_id = context.viewer().map(|viewer| viewer.user_id);
+        let audiences = Audience::of(user_id.is_some(), is_admin);
+        convert_result(
+            context
+                .executor()
+                .find_active_announcements(user_id, &audiences)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "List every announcement, including those that have ended or haven't
+        started yet, newest first. Only admins can list every announcement."
+    )]
+    async fn announcements(&self, context: &Context) -> FieldResult<Vec<Announcement>> {
+        require_role(context, Role::Admin).await?;
+        convert_result(context.executor().find_announcements().await)
+    }
+
+    #[graphql(
+        description = "List feedback, newest first. Only admins can list feedback.",
+        arguments(
+            status(description = "Only list feedback with this status."),
+            category(description = "Only list feedback in this category."),
+            first(description = "The number of submissions to return. Defaults to 50, up to 100."),
+            offset(description = "The number of submissions to skip. Defaults to 0."),
+        )
+    )]
+    async fn feedback(
+        &self,
+        context: &Context,
+        status: Option<FeedbackStatus>,
+        category: Option<FeedbackCategory>,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<Feedback>> {
+        require_role(context, Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            feedback::DEFAULT_PAGE_SIZE,
+            feedback::MAX_PAGE_SIZE,
+        )
+        .map_err(InvalidInput::into_field_error)?;
+
+        let _permit = acquire_permit(context, LimitedResolver::Feedback).await?;
+        convert_result(
+            context
+                .executor()
+                .find_feedback_submissions(status, category, limit, offset)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "List security-relevant events, such as logins and role changes, newest
+        first. Only admins can list audit events.",
+        arguments(
+            user_id(description = "Only list events about this user."),
+            kind(description = "Only list events of this kind."),
+            first(description = "The number of events to return. Defaults to 50, up to 100."),
+            offset(description = "The number of events to skip. Defaults to 0."),
+        )
+    )]
+    async fn audit_events(
+        &self,
+        context: &Context,
+        user_id: Option<Uuid>,
+        kind: Option<AuditEventKind>,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<AuditEvent>> {
+        require_role(context, Role::Admin).await?;
+        let (limit, offset) = validate_page(
+            first,
+            offset,
+            audit::DEFAULT_PAGE_SIZE,
+            audit::MAX_PAGE_SIZE,
+        )
+        .map_err(InvalidInput::into_field_error)?;
+
+        convert_result(
+            context
+                .executor()
+                .find_audit_events(user_id, kind, limit, offset)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "List the SQL queries run against the database that are most expensive, as
+        tracked by the \"pg_stat_statements\" Postgres extension. Only admins can list queries.",
+        arguments(
+            first(description = "The number of queries to return. Defaults to 20, up to 100."),
+            order_by(description = "What makes a query expensive. Defaults to total time."),
+        )
+    )]
+    async fn top_queries(
+        &self,
+        context: &Context,
+        first: Option<i32>,
+        order_by: Option<QueryStatisticsOrder>,
+    ) -> FieldResult<Vec<QueryStatistics>> {
+        require_role(context, Role::Admin).await?;
+        let limit = validate_query_count(first).map_err(InvalidInput::into_field_error)?;
+
+        let order = order_by.unwrap_or(QueryStatisticsOrder::TotalTime);
+        let _permit = acquire_permit(context, LimitedResolver::TopQueries).await?;
+        convert_result(context.executor().find_top_queries(order, limit).await)?
+            .ok_or_else(query_statistics_unavailable)
+    }
+
+    #[graphql(
+        description = "List every deprecated field along with the clients still using it, so the
+        field can be removed once they stop. Only admins can list deprecated fields."
+    )]
+    async fn deprecated_field_usage(
+        &self,
+        context: &Context,
+    ) -> FieldResult<Vec<DeprecatedFieldUsage>> {
+        require_role(context, Role::Admin).await?;
+        convert_result(context.executor().find_deprecated_field_usage().await)
+    }
+
+    #[graphql(
+        description = "List every promo code, newest first. Only admins can list promo codes."
+    )]
+    async fn promo_codes(&self, context: &Context) -> FieldResult<Vec<PromoCode>> {
+        require_role(context, Role::Admin).await?;
+        convert_result(context.executor().find_promo_codes().await)
+    }
+
+    #[graphql(
+        description = "List every redemption of a promo code, oldest first. Only admins can list
+        redemptions.",
+        arguments(promo_code_id(description = "The ID of the promo code."))
+    )]
+    async fn promo_code_redemptions(
+        &self,
+        context: &Context,
+        promo_code_id: Uuid,
+    ) -> FieldResult<Vec<PromoCodeRedemption>> {
+        require_role(context, Role::Admin).await?;
+        convert_result(
+            context
+                .executor()
+                .find_promo_code_redemptions(promo_code_id)
+                .await,
+        )
+    }
+}
+
+/// Mutations for the GraphQL schema.
+pub struct Mutation;
+
+#[graphql_object(context = Context, description="All available GraphQL mutations.")]
+impl Mutation {
+    #[graphql(
+        description = "Log in using a specified username and password.",
+        arguments(
+            username(description = "The username of the user to log in as."),
+            password(description = "The user's password"),
+        )
+    )]
+    async fn login(
+        &self,
+        context: &Context,
+        username: String,
+        password: String,
+    ) -> FieldResult<AuthResult> {
+        match convert_result(context.executor().login(&username, &password).await)? {
+            LoginResult::LoggedIn(session_token) => Ok(AuthResult {
+                session_token: context.deliver_session_token(&session_token),
+            }),
+            LoginResult::InvalidCredentials => Err(FieldError::new(
+                "Invalid username or password.",
+                graphql_value!({ "code": "invalid-login" }),
+            )),
+            LoginResult::Locked(locked_until) => Err(account_locked(locked_until)),
+            LoginResult::Deactivated => Err(account_deactivated()),
+            LoginResult::PasswordResetRequired => Err(password_reset_required()),
+        }
+    }
+
+    #[graphql(
+        description = "Log in with any login method, using the credentials it takes. Exactly one
+        kind of credentials must be set.",
+        arguments(credentials(description = "The credentials to log in with."))
+    )]
+    async fn login_with(
+        &self,
+        context: &Context,
+        credentials: LoginCredentials,
+    ) -> FieldResult<AuthResult> {
+        let method = credentials
+            .into_method()
+            .map_err(InvalidInput::into_field_error)?;
+
+        match convert_result(context.executor().login_with(&method).await)? {
+            LoginResult::LoggedIn(session_token) => Ok(AuthResult {
+                session_token: context.deliver_session_token(&session_token),
+            }),
+            LoginResult::InvalidCredentials => {
+                let (message, code) = method.invalid_credentials_error();
+                Err(FieldError::new(message, graphql_value!({ "code": code })))
+            }
+            LoginResult::Locked(locked_until) => Err(account_locked(locked_until)),
+            LoginResult::Deactivated => Err(account_deactivated()),
+            LoginResult::PasswordResetRequired => Err(password_reset_required()),
+        }
+    }
+
+    #[graphql(
+        name = "loginWithOAuth",
+        description = "Log in with a one-time ticket issued after logging in with an OAuth provider
+        such as Google or GitHub. Tickets are sent to the client as a query parameter once the
+        provider sends the user back, and expire shortly after.",
+        arguments(ticket(description = "The ticket issued after logging in with the provider."))
+    )]
+    async fn login_with_oauth(&self, context: &Context, ticket: String) -> FieldResult<AuthResult> {
+        match convert_result(context.executor().redeem_oauth_ticket(&ticket).await)? {
+            LoginResult::LoggedIn(session_token) => Ok(AuthResult {
+                session_token: context.deliver_session_token(&session_token),
+            }),
+            LoginResult::InvalidCredentials => Err(FieldError::new(
+                "Invalid or expired login ticket.",
+                graphql_value!({ "code": "invalid-oauth-ticket" }),
+            )),
+            LoginResult::Locked(locked_until) => Err(account_locked(locked_until)),
+            LoginResult::Deactivated => Err(account_deactivated()),
+            LoginResult::PasswordResetRequired => Err(password_reset_required()),
+        }
+    }
+
+    #[graphql(
+        description = "Start logging in with a passkey. This returns the JSON-encoded options to
+        pass to \"navigator.credentials.get()\", which expire shortly after.",
+        arguments(username(
+            description = "The username of the user to log in as. If omitted, the authenticator
+            offers any passkey it has for the server."
+        ))
+    )]
+    async fn start_passkey_login(
+        &self,
+        context: &Context,
+        username: Option<String>,
+    ) -> FieldResult<String> {
+        let executor = context.executor();
+        let options = convert_result(executor.start_passkey_login(username.as_deref()).await)?;
+        Ok(options.to_string())
+    }
+
+    #[graphql(
+        description = "Log in with a passkey, using the credential returned by
+        \"navigator.credentials.get()\" for the options from \"startPasskeyLogin\".",
+        arguments(
+            credential(description = "The JSON-encoded credential, as serialized by
+            \"PublicKeyCredential.toJSON()\".")
+        )
+    )]
+    async fn finish_passkey_login(
+        &self,
+        context: &Context,
+        credential: String,
+    ) -> FieldResult<AuthResult> {
+        match convert_result(context.executor().finish_passkey_login(&credential).await)? {
+            LoginResult::LoggedIn(session_token) => Ok(AuthResult {
+                session_token: context.deliver_session_token(&session_token),
+            }),
+            LoginResult::InvalidCredentials => Err(FieldError::new(
+                "Invalid or expired passkey login.",
+                graphql_value!({ "code": "invalid-passkey" }),
+            )),
+            LoginResult::Locked(locked_until) => Err(account_locked(locked_until)),
+            LoginResult::Deactivated => Err(account_deactivated()),
+            LoginResult::PasswordResetRequired => Err(password_reset_required()),
+        }
+    }
+
+    #[graphql(
+        description = "Attempt to refresh an active session using a session token. If successful,
+        the lifespan of the session will be extended, the current session token will be invalidated,
+        and a new session token will be returned for future authentication.",
+        arguments(session_token(
+            description = "The session token to refresh. Defaults to the token the request was sent
+            with, such as the session cookie."
+        ))
+    )]
+    async fn refresh(
+        &self,
+        context: &Context,
+        session_token: Option<String>,
+    ) -> FieldResult<AuthResult> {
+        if let Some(session_token) = session_token.as_deref().or_else(|| context.session_token()) {
+            if let Some(session_token) =
+                convert_result(context.executor().refresh(session_token).await)?
+            {
+                return Ok(AuthResult {
+                    session_token: context.deliver_session_token(&session_token),
+                });
+            }
+        }
+
+        Err(FieldError::new(
+            "Invalid session token.",
+            graphql_value!({ "code": "invalid-session-token" }),
+        ))
+    }
+
+    #[graphql(
+        description = "Terminate the session associated with a specified session token. The token
+        will be invalidated so it cannot be used for future authentication. This will return true
+        if the specified session token was valid and the log out operation was successful.",
+        arguments(session_token(
+            description = "The session token to invalidate. Defaults to the token the request was
+            sent with, such as the session cookie."
+        ))
+    )]
+    async fn logout(&self, context: &Context, session_token: Option<String>) -> FieldResult<bool> {
+        context.session_cookies().clear();
+        match session_token.as_deref().or_else(|| context.session_token()) {
+            Some(session_token) => convert_result(context.executor().logout(session_token).await),
+            None => Ok(false),
+        }
+    }
+
+    #[graphql(
+        description = "Terminate one of the sessions of the user making the request, such as a
+        session on a lost device. This will return true if the session was active and has been
+        terminated.",
+        arguments(session_id(description = "The ID of the session to terminate."))
+    )]
+    async fn revoke_session(&self, context: &Context, session_id: Uuid) -> FieldResult<bool> {
+        let viewer = require_viewer(context)?;
+        convert_result(
+            context
+                .executor()
+                .revoke_session(viewer.user_id, session_id)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Terminate every session of the user making the request except for the one
+        making the request. This will return the number of sessions terminated."
+    )]
+    async fn revoke_all_other_sessions(&self, context: &Context) -> FieldResult<i32> {
+        let viewer = require_viewer(context)?;
+        let count = convert_result(context.executor().revoke_other_sessions(viewer).await)?;
+        Ok(count as i32)
+    }
+
+    #[graphql(
+        description = "Start creating a passkey for the user making the request. This returns the
+        JSON-encoded options to pass to \"navigator.credentials.create()\", which expire shortly
+        after."
+    )]
+    async fn start_passkey_registration(&self, context: &Context) -> FieldResult<String> {
+        let viewer = require_viewer(context)?;
+        let executor = context.executor();
+        match convert_result(executor.start_passkey_registration(viewer.user_id).await)? {
+            Some(options) => Ok(options.to_string()),
+            None => Err(user_not_found()),
+        }
+    }
+
+    #[graphql(
+        description = "Finish creating a passkey for the user making the request, using the
+        credential returned by \"navigator.credentials.create()\" for the options from
+        \"startPasskeyRegistration\".",
+        arguments(
+            credential(description = "The JSON-encoded credential, as serialized by
+                \"PublicKeyCredential.toJSON()\"."),
+            name(description = "A name for the passkey, such as the device it was created on."),
+        )
+    )]
+    async fn finish_passkey_registration(
+        &self,
+        context: &Context,
+        credential: String,
+        name: String,
+    ) -> FieldResult<Passkey> {
+        let viewer = require_viewer(context)?;
+        let executor = context.executor();
+        match convert_result(
+            executor
+                .finish_passkey_registration(viewer.user_id, &credential, &name)
+                .await,
+        )? {
+            Some(passkey) => Ok(passkey),
+            None => Err(FieldError::new(
+                "Invalid or expired passkey registration.",
+                graphql_value!({ "code": "invalid-passkey" }),
+            )),
+        }
+    }
+
+    #[graphql(
+        description = "Remove one of the passkeys of the user making the request, so it can no
+        longer be used to log in. This will return true if the passkey was removed.",
+        arguments(passkey_id(description = "The ID of the passkey to remove."))
+    )]
+    async fn delete_passkey(&self, context: &Context, passkey_id: Uuid) -> FieldResult<bool> {
+        let viewer = require_viewer(context)?;
+        convert_result(
+            context
+                .executor()
+                .delete_passkey(viewer.user_id, passkey_id)
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Attempt to create a new user with the provided username, email and password.
+        Once the user is created, an email verification code will be sent to the user's email
+        address.",
+        arguments(username(description = "The user's username.")),
+        arguments(email(description = "The user's email.")),
+        arguments(email(description = "The password the user will use to log in.")),
+        arguments(honeypot(
+            description = "The value of a form field hidden from people, which bots fill in."
+        )),
+        arguments(form_token(
+            description = "The token from \"signupFormToken\", fetched when the signup form was
+            loaded."
+        )),
+        arguments(captcha_token(
+            description = "The token issued by the CAPTCHA provider, if a CAPTCHA was required."
+        )),
+        arguments(dry_run(
+            description = "Only validate the signup and return the user that would be created,
+            without saving it. Dry runs skip bot screening."
+        ))
+    )]
+    async fn create_user(
+        &self,
+        context: &Context,
+        username: String,
+        email: String,
+        password: String,
+        honeypot: Option<String>,
+        form_token: Option<String>,
+        captcha_token: Option<String>,
+        dry_run: Option<bool>,
+    ) -> FieldResult<User> {
+        if let Some(invalid) =
+            convert_result(validate_new_user(context.executor(), &username, &email).await)?
+        {
+            return Err(invalid.into_field_error());
+        }
+        if let Some(invalid) =
+            validate_password(context.executor().config(), &password, &[&username, &email])
+        {
+            return Err(invalid_password(invalid));
+        }
+
+        let evidence = SignupEvidence {
+            honeypot,
+            form_token,
+            captcha_token,
+        };
+        let result = convert_result(
+            context
+                .executor()
+                .sign_up(
+                    &username,
+                    &email,
+                    &password,
+                    &evidence,
+                    dry_run.unwrap_or(false),
+                )
+                .await,
+        )?;
+        match result {
+            SignupResult::Created(user) => Ok(*user),
+            SignupResult::CaptchaRequired => Err(FieldError::new(
+                CAPTCHA_REQUIRED_ERROR_MESSAGE,
+                graphql_value!({ "code": "captcha-required" }),
+            )),
+            SignupResult::PendingReview => Err(FieldError::new(
+                SIGNUP_PENDING_REVIEW_ERROR_MESSAGE,
+                graphql_value!({ "code": "signup-pending-review" }),
+            )),
+        }
+    }
+
+    #[graphql(
+        description = "Approve a pending signup, creating its user. Only admins can approve
+        signups.",
+        arguments(id(description = "The ID of the pending signup."))
+    )]
+    async fn approve_signup(&self, context: &Context, id: Uuid) -> FieldResult<User> {
+        require_role(context, Role::Admin).await?;
+        let executor = context.executor();
+        let signup = convert_result(executor.find_pending_signup(id).await)?
+            .ok_or_else(pending_signup_not_found)?;
+        // The username may have been taken since the signup was made.
+        if convert_result(
+            executor
+                .find_user_by_username_including_deleted(&signup.username)
+                .await,
+        )?
+        .is_some()
+        {
+            return Err(FieldError::new(
+                "Username is already in use.",
+                graphql_value!({ "code": "username-taken" }),
+            ));
+        }
+
+        convert_result(executor.approve_pending_signup(id).await)?
+            .ok_or_else(pending_signup_not_found)
+    }
+
+    #[graphql(
+        description = "Reject a pending signup without creating its user. Only admins can reject
+        signups. This will return true if the pending signup existed.",
+        arguments(id(description = "The ID of the pending signup."))
+    )]
+    async fn reject_signup(&self, context: &Context, id: Uuid) -> FieldResult<bool> {
+        require_role(context, Role::Admin).await?;
+        convert_result(context.executor().reject_pending_signup(id).await)
+    }
+
+    #[graphql(
+        description = "Request a password reset for the users with the specified email address.
+        Each user will be emailed a one-time password reset token. This always returns true, so it
+        can't be used to find out which email addresses are registered.",
+        arguments(email(description = "The email address of the user."))
+    )]
+    async fn request_password_reset(&self, context: &Context, email: String) -> FieldResult<bool> {
+        convert_result(context.executor().request_password_reset(&email).await)?;
+        Ok(true)
+    }
+
+    #[graphql(
+        description = "Reset a user's password using a password reset token. Every active session
+        of the user will be terminated. This will return true if the reset token was valid and the
+        password was reset successfully.",
+        arguments(
+            reset_token(description = "The password reset token that was emailed to the user."),
+            new_password(description = "The new password the user will use to log in."),
+        )
+    )]
+    async fn reset_password(
+        &self,
+        context: &Context,
+        reset_token: String,
+        new_password: String,
+    ) -> FieldResult<bool> {
+        let executor = context.executor();
+        let user = match convert_result(
+            executor
+                .find_user_by_password_reset_token(&reset_token)
+                .await,
+        )? {
+            Some(user) => user,
+            None => return Ok(false),
+        };
+        if let Some(invalid) = validate_password(
+            executor.config(),
+            &new_password,
+            &[&user.username, &user.email],
+        ) {
+            return Err(invalid_password(invalid));
+        }
+
+        convert_result(executor.reset_password(&reset_token, &new_password).await)
+    }
+
+    #[graphql(
+        description = "Update the profile of the user making the request. Details that aren't
+        provided are left unchanged. If the email address changes, it will need to be verified
+        again and a new verification code will be sent to it.",
+        arguments(
+            username(description = "The user's new username."),
+            email(description = "The user's new email address."),
+            dry_run(
+                description = "Only validate the changes and return the user as they would
+            be, without saving them or sending a verification code."
+            ),
+        )
+    )]
+    async fn update_profile(
+        &self,
+        context: &Context,
+        username: Option<String>,
+        email: Option<String>,
+        dry_run: Option<bool>,
+    ) -> FieldResult<User> {
+        let viewer = require_viewer(context)?;
+        require_write_access(context).await?;
+        let executor = context.executor();
+        if let Some(invalid) = convert_result(
+            validate_profile_update(
+                executor,
+                viewer.user_id,
+                username.as_deref(),
+                email.as_deref(),
+            )
+            .await,
+        )? {
+            return Err(invalid.into_field_error());
+        }
+
+        convert_result(
+            executor
+                .update_profile(
+                    viewer.user_id,
+                    username.as_deref(),
+                    email.as_deref(),
+                    dry_run.unwrap_or(false),
+                )
+                .await,
+        )?
+        .ok_or_else(user_not_found)
+    }
+
+    #[graphql(
+        description = "Upload a new avatar for the user making the request, replacing their
+        current one. The avatar must be a PNG, JPEG, GIF or WebP image, uploaded in a multipart
+        request.",
+        arguments(file(description = "The image to use as the avatar."))
+    )]
+    async fn upload_avatar(&self, context: &Context, file: Upload) -> FieldResult<User> {
+        let viewer = require_viewer(context)?;
+        require_write_access(context).await?;
+        let executor = context.executor();
+        let file = context.upload(&file).ok_or_else(file_not_uploaded)?;
+        let format =
+            validate_avatar(executor.config(), file).map_err(InvalidInput::into_field_error)?;
+        if !convert_result(executor.scan_file(&file.contents).await)? {
+            return Err(file_rejected());
+        }
+
+        convert_result(
+            executor
+                .update_avatar(viewer.user_id, format, &file.contents)
+                .await,
+        )?
+        .ok_or_else(user_not_found)
+    }
+
+    #[graphql(
+        description = "Change the password of the user making the request. Every other active
+        session of the user will be terminated, but the session making the change is kept. This
+        will return true if the password was changed successfully.",
+        arguments(
+            current_password(description = "The user's current password."),
+            new_password(description = "The new password the user will use to log in."),
+            dry_run(
+                description = "Only check the current password and the new password against
+            the password policy, without changing the password or terminating sessions."
+            ),
+        )
+    )]
+    async fn change_password(
+        &self,
+        context: &Context,
+        current_password: String,
+        new_password: String,
+        dry_run: Option<bool>,
+    ) -> FieldResult<bool> {
+        let viewer = require_viewer(context)?;
+        let executor = context.executor();
+        let user =
+            convert_result(executor.find_user(viewer.user_id).await)?.ok_or_else(user_not_found)?;
+        if let Some(invalid) = validate_password(
+            executor.config(),
+            &new_password,
+            &[&user.username, &user.email],
+        ) {
+            return Err(invalid_password(invalid));
+        }
+
+        if convert_result(
+            executor
+                .change_password(
+                    viewer,
+                    &current_password,
+                    &new_password,
+                    dry_run.unwrap_or(false),
+                )
+                .await,
+        )? {
+            return Ok(true);
+        }
+
+        Err(FieldError::new(
+            "Current password is incorrect.",
+            graphql_value!({ "code": "invalid-password" }),
+        ))
+    }
+
+    #[graphql(
+        description = "Delete the account of the user making the request once the undo grace period
+        is over. Every active session of the user will then be terminated, and the user can no
+        longer log in or be found. Until then, the deletion can be undone with \"undoAction\".
+        This will return the pending deletion, or null if there is no grace period and the account
+        was deleted right away."
+    )]
+    async fn delete_account(&self, context: &Context) -> FieldResult<Option<PendingAction>> {
+        let viewer = require_viewer(context)?;
+        convert_result(
+            context
+                .executor()
+                .schedule_action(
+                    PendingActionKind::DeleteAccount,
+                    viewer.user_id,
+                    viewer.user_id,
+                )
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Undo a destructive action, such as deleting an account, before its grace
+        period is over. Logging in isn't required, so deleted accounts can be restored after their
+        sessions end. This will return true if the action was undone, or false if it was already
+        done or the undo token is invalid.",
+        arguments(undo_token(
+            description = "The undo token returned when the action was scheduled."
+        ))
+    )]
+    async fn undo_action(&self, context: &Context, undo_token: String) -> FieldResult<bool> {
+        convert_result(context.executor().undo_action(&undo_token).await)
+    }
+
+    #[graphql(
+        description = "Create a checkout session for the user making the request to subscribe to a
+        plan with. The user should be sent to the returned checkout page, and will be returned to
+        the site once they are done.",
+        arguments(plan(description = "The plan to subscribe to."))
+    )]
+    async fn create_checkout_session(
+        &self,
+        context: &Context,
+        plan: Plan,
+    ) -> FieldResult<CheckoutSession> {
+        let viewer = require_viewer(context)?;
+        convert_result(
+            context
+                .executor()
+                .create_checkout_session(viewer.user_id, plan)
+                .await,
+        )?
+        .ok_or_else(|| {
+            FieldError::new(
+                "This plan cannot be purchased.",
+                graphql_value!({ "code": "plan-unavailable" }),
+            )
+        })
+    }
+
+    #[graphql(
+        description = "Create a promo code giving a discount on the first invoice of a
+        subscription. Only admins can create promo codes.",
+        arguments(
+            code(description = "The code users redeem. Codes are case-insensitive."),
+            kind(description = "The kind of discount the promo code gives."),
+            amount(
+                description = "The percentage to take off for percentage discounts, or the amount
+                to take off in the smallest currency unit, e.g. cents, for fixed discounts."
+            ),
+            max_redemptions(
+                description = "The max number of times the promo code can be redeemed. Defaults to
+                no limit."
+            ),
+            expires_at(description = "Date when the promo code expires. Defaults to never."),
+        )
+    )]
+    async fn create_promo_code(
+        &self,
+        context: &Context,
+        code: String,
+        kind: PromoCodeKind,
+        amount: i32,
+        max_redemptions: Option<i32>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> FieldResult<PromoCode> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        let executor = context.executor();
+        if executor.config().stripe_secret_key.is_none() {
+            return Err(billing_unavailable());
+        }
+
+        if let Some(invalid) = convert_result(
+            validate_new_promo_code(executor, &code, kind, amount, max_redemptions, expires_at)
+                .await,
+        )? {
+            return Err(invalid.into_field_error());
+        }
+
+        convert_result(
+            executor
+                .create_promo_code(
+                    viewer.user_id,
+                    &code,
+                    kind,
+                    amount,
+                    max_redemptions,
+                    expires_at,
+                )
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Redeem a promo code for the user making the request. If the user has an
+        active subscription, the discount is applied to its next invoice. Otherwise, it is applied
+        when they subscribe.",
+        arguments(code(description = "The promo code to redeem."))
+    )]
+    async fn redeem_promo_code(
+        &self,
+        context: &Context,
+        code: String,
+    ) -> FieldResult<PromoCodeRedemption> {
+        let viewer = require_viewer(context)?;
+        let result = convert_result(
+            context
+                .executor()
+                .redeem_promo_code(viewer.user_id, &code)
+                .await,
+        )?;
+
+        convert_redeem_result(result).map_err(InvalidInput::into_field_error)
+    }
+
+    #[graphql(
+        description = "Submit feedback, such as a bug report or feature request. Feedback can be
+        submitted without logging in. Submissions are limited per user, or per IP address if the
+        user isn't logged in.",
+        arguments(
+            category(description = "What the feedback is about."),
+            message(description = "The feedback itself."),
+            metadata(
+                description = "Context to attach to the feedback, such as the app version or the
+                page the user was on."
+            ),
+        )
+    )]
+    async fn submit_feedback(
+        &self,
+        context: &Context,
+        category: FeedbackCategory,
+        message: String,
+        metadata: Option<Vec<FeedbackMetadataInput>>,
+    ) -> FieldResult<Feedback> {
+        let metadata = metadata.unwrap_or_default();
+        if let Some(invalid) = validate_feedback(&message, &metadata) {
+            return Err(invalid.into_field_error());
+        }
+
+        let user_id = context.viewer().map(|viewer| viewer.user_id);
+        let metadata = metadata
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect();
+        convert_result(
+            context
+                .executor()
+                .submit_feedback(user_id, category, &message, &metadata)
+                .await,
+        )?
+        .ok_or_else(feedback_rate_limited)
+    }
+
+    #[graphql(
+        description = "Move feedback to another triage status. Only admins can triage feedback.",
+        arguments(
+            id(description = "The ID of the feedback."),
+            status(description = "The new status."),
+        )
+    )]
+    async fn update_feedback_status(
+        &self,
+        context: &Context,
+        id: Uuid,
+        status: FeedbackStatus,
+    ) -> FieldResult<Feedback> {
+        require_role(context, Role::Admin).await?;
+        convert_result(context.executor().update_feedback_status(id, status).await)?
+            .ok_or_else(feedback_not_found)
+    }
+
+    #[graphql(
+        description = "Create an announcement. Only admins can create announcements.",
+        arguments(
+            message(description = "The message to show."),
+            severity(description = "How important the announcement is."),
+            audience(description = "Who the announcement is shown to."),
+            starts_at(
+                description = "Date when the announcement starts being shown. Defaults to now."
+            ),
+            ends_at(
+                description = "Date when the announcement stops being shown. Defaults to never."
+            ),
+        )
+    )]
+    async fn create_announcement(
+        &self,
+        context: &Context,
+        message: String,
+        severity: Severity,
+        audience: Audience,
+        starts_at: Option<DateTime<Utc>>,
+        ends_at: Option<DateTime<Utc>>,
+    ) -> FieldResult<Announcement> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        if let Some(invalid) =
+            validate_announcement(&message, starts_at.unwrap_or_else(Utc::now), ends_at)
+        {
+            return Err(invalid.into_field_error());
+        }
+
+        convert_result(
+            context
+                .executor()
+                .create_announcement(
+                    viewer.user_id,
+                    &message,
+                    severity,
+                    audience,
+                    starts_at,
+                    ends_at,
+                )
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Update an announcement. Only admins can update announcements. Details that
+        are null are left unchanged. To stop showing an announcement early, set its end date to
+        now.",
+        arguments(
+            id(description = "The ID of the announcement."),
+            message(description = "The new message to show."),
+            severity(description = "The new severity."),
+            audience(description = "The new audience."),
+            starts_at(description = "The new date when the announcement starts being shown."),
+            ends_at(description = "The new date when the announcement stops being shown."),
+        )
+    )]
+    async fn update_announcement(
+        &self,
+        context: &Context,
+        id: Uuid,
+        message: Option<String>,
+        severity: Option<Severity>,
+        audience: Option<Audience>,
+        starts_at: Option<DateTime<Utc>>,
+        ends_at: Option<DateTime<Utc>>,
+    ) -> FieldResult<Announcement> {
+        require_role(context, Role::Admin).await?;
+        let executor = context.executor();
+        let current = convert_result(executor.find_announcement(id).await)?
+            .ok_or_else(announcement_not_found)?;
 
-/// Queries for the GraphQL schema.
-pub struct Query;
+        if let Some(invalid) = validate_announcement(
+            message.as_deref().unwrap_or(&current.message),
+            starts_at.unwrap_or(current.starts_at),
+            ends_at.or(current.ends_at),
+        ) {
+            return Err(invalid.into_field_error());
+        }
 
-/// Minimum password length for a user's password.
-const MIN_PASSWORD_LENGTH: usize = 6;
-/// Maximum password length for a user's password.
-const MAX_PASSWORD_LENGTH: usize = 255;
+        convert_result(
+            executor
+                .update_announcement(
+                    id,
+                    message.as_deref(),
+                    severity,
+                    audience,
+                    starts_at,
+                    ends_at,
+                )
+                .await,
+        )?
+        .ok_or_else(announcement_not_found)
+    }
 
-/// Convert a generic "anyhow" result into a GraphQL field result.
-fn convert_result<T>(result: Result<T>) -> FieldResult<T> {
-    match result {
-        Ok(value) => Ok(value),
-        Err(error) => {
-            log::error!("{}", error);
-            Err(FieldError::new(
-                "An unknown error occurred.",
-                graphql_value!({ "code": "unknown-error" }),
-            ))
+    #[graphql(
+        description = "Delete an announcement, along with every dismissal of it. Only admins can
+        delete announcements. This will return true if the announcement was deleted successfully.",
+        arguments(id(description = "The ID of the announcement."))
+    )]
+    async fn delete_announcement(&self, context: &Context, id: Uuid) -> FieldResult<bool> {
+        require_role(context, Role::Admin).await?;
+        if convert_result(context.executor().delete_announcement(id).await)? {
+            Ok(true)
+        } else {
+            Err(announcement_not_found())
         }
     }
-}
 
-#[graphql_object(context = Context, description="All available GraphQL queries.")]
-impl Query {
     #[graphql(
-        description = "Find a user by their ID.",
-        arguments(id(description = "The user's ID."))
+        description = "Dismiss an announcement for the user making the request, so it is no
+        longer returned by \"activeAnnouncements\". This will return true if the announcement was
+        dismissed successfully.",
+        arguments(id(description = "The ID of the announcement."))
     )]
-    async fn user(&self, context: &Context, id: Uuid) -> FieldResult<Option<User>> {
-        convert_result(context.executor().find_user(id).await)
+    async fn dismiss_announcement(&self, context: &Context, id: Uuid) -> FieldResult<bool> {
+        let viewer = require_viewer(context)?;
+        if convert_result(
+            context
+                .executor()
+                .dismiss_announcement(viewer.user_id, id)
+                .await,
+        )? {
+            Ok(true)
+        } else {
+            Err(announcement_not_found())
+        }
     }
 
     #[graphql(
-        description = "Find a user by their username.",
-        arguments(username(description = "The user's username."))
+        description = "Create an organization. The user making the request becomes its owner.",
+        arguments(name(description = "The organization's name, up to 64 characters."))
     )]
-    async fn user_by_username(
+    async fn create_organization(
+        &self,
+        context: &Context,
+        name: String,
+    ) -> FieldResult<Organization> {
+        require_write_access(context).await?;
+        let viewer = require_viewer(context)?;
+        if let Some(invalid) = validate_organization_name(&name) {
+            return Err(invalid.into_field_error());
+        }
+
+        convert_result(
+            context
+                .executor()
+                .create_organization(viewer, name.trim())
+                .await,
+        )
+    }
+
+    #[graphql(
+        description = "Invite a user to an organization, and email them the invitation. They only
+        become a member once they accept it. Only owners and admins can invite users, and only
+        owners can invite other owners.",
+        arguments(
+            organization_id(description = "The organization's ID."),
+            username(description = "The username of the user to invite."),
+            role(
+                description = "The role the user will have once they accept. Defaults to
+            MEMBER."
+            ),
+        )
+    )]
+    async fn invite_member(
         &self,
         context: &Context,
+        organization_id: Uuid,
         username: String,
-    ) -> FieldResult<Option<User>> {
-        convert_result(context.executor().find_user_by_username(&username).await)
+        role: Option<OrganizationRole>,
+    ) -> FieldResult<Membership> {
+        let viewer = require_viewer(context)?;
+        let result = convert_result(
+            context
+                .executor()
+                .invite_member(
+                    viewer,
+                    organization_id,
+                    &username,
+                    role.unwrap_or(OrganizationRole::Member),
+                )
+                .await,
+        )?;
+
+        convert_membership_result(result).map_err(InvalidInput::into_field_error)
     }
 
     #[graphql(
-        description = "Find users. As of now this just returns a list of all users. It should really
-        be paginated and have parameters."
+        description = "Accept an invitation to an organization, becoming a member of it.",
+        arguments(organization_id(description = "The ID of the organization."))
     )]
-    async fn users(&self, context: &Context) -> FieldResult<Vec<User>> {
-        convert_result(context.executor().find_users().await)
+    async fn accept_invite(
+        &self,
+        context: &Context,
+        organization_id: Uuid,
+    ) -> FieldResult<Membership> {
+        let viewer = require_viewer(context)?;
+        convert_result(
+            context
+                .executor()
+                .accept_invite(viewer, organization_id)
+                .await,
+        )?
+        .ok_or_else(invite_not_found)
     }
-}
 
-/// Mutations for the GraphQL schema.
-pub struct Mutation;
+    #[graphql(
+        description = "Remove a member or invited user from an organization. Users can remove
+        themselves to leave an organization or decline an invitation. Otherwise, only owners and
+        admins can remove members, and only owners can remove other owners. The last owner can't
+        be removed. This will return true once the user has been removed.",
+        arguments(
+            organization_id(description = "The organization's ID."),
+            user_id(description = "The ID of the user to remove."),
+        )
+    )]
+    async fn remove_member(
+        &self,
+        context: &Context,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> FieldResult<bool> {
+        let viewer = require_viewer(context)?;
+        let result = convert_result(
+            context
+                .executor()
+                .remove_member(viewer, organization_id, user_id)
+                .await,
+        )?;
+
+        convert_membership_result(result)
+            .map(|_| true)
+            .map_err(InvalidInput::into_field_error)
+    }
 
-#[graphql_object(context = Context, description="All available GraphQL mutations.")]
-impl Mutation {
     #[graphql(
-        description = "Log in using a specified username and password.",
+        description = "Change the role of a member of or user invited to an organization. Only
+        owners and admins can change roles, and only owners can make members owners or change the
+        roles of other owners. The last owner can't stop being an owner.",
         arguments(
-            username(description = "The username of the user to log in as."),
-            password(description = "The user's password"),
+            organization_id(description = "The organization's ID."),
+            user_id(description = "The ID of the member."),
+            role(description = "The member's new role."),
         )
     )]
-    async fn login(
+    async fn set_member_role(
         &self,
         context: &Context,
-        username: String,
-        password: String,
-    ) -> FieldResult<AuthResult> {
-        if let Some(session_token) =
-            convert_result(context.executor().login(&username, &password).await)?
-        {
-            return Ok(AuthResult {
-                session_token: session_token.to_string(),
-            });
-        }
+        organization_id: Uuid,
+        user_id: Uuid,
+        role: OrganizationRole,
+    ) -> FieldResult<Membership> {
+        let viewer = require_viewer(context)?;
+        let result = convert_result(
+            context
+                .executor()
+                .set_member_role(viewer, organization_id, user_id, role)
+                .await,
+        )?;
 
-        Err(FieldError::new(
-            "Invalid username or password.",
-            graphql_value!({ "code": "invalid-login" }),
-        ))
+        convert_membership_result(result).map_err(InvalidInput::into_field_error)
     }
 
     #[graphql(
-        description = "Attempt to refresh an active session using a session token. If successful,
-        the lifespan of the session will be extended, the current session token will be invalidated,
-        and a new session token will be returned for future authentication.",
-        arguments(session_token(description = "The session token to refresh."))
+        description = "Replace the email settings of an organization, which override the server's
+        for emails sent on its behalf such as invitations. Settings that are null fall back to the
+        server's. Only owners and admins of the organization can change its email settings.",
+        arguments(
+            organization_id(description = "The organization's ID."),
+            settings(description = "The organization's new email settings."),
+        )
     )]
-    async fn refresh(&self, context: &Context, session_token: String) -> FieldResult<AuthResult> {
-        if let Some(session_token) =
-            convert_result(context.executor().refresh(&session_token).await)?
-        {
-            return Ok(AuthResult {
-                session_token: session_token.to_string(),
-            });
+    async fn update_organization_email_settings(
+        &self,
+        context: &Context,
+        organization_id: Uuid,
+        settings: EmailSettingsInput,
+    ) -> FieldResult<OrganizationEmailSettings> {
+        require_write_access(context).await?;
+        require_organization_admin(context, organization_id).await?;
+        let executor = context.executor();
+        let current = convert_result(
+            executor
+                .find_organization_email_settings(organization_id)
+                .await,
+        )?;
+        let can_encrypt = executor.config().email_settings_key.is_some();
+        if let Some(invalid) = validate_email_settings(&settings, current.as_ref(), can_encrypt) {
+            return Err(invalid.into_field_error());
         }
 
-        Err(FieldError::new(
-            "Invalid session token.",
-            graphql_value!({ "code": "invalid-session-token" }),
-        ))
+        convert_result(
+            executor
+                .update_organization_email_settings(organization_id, &settings)
+                .await,
+        )
     }
 
     #[graphql(
-        description = "Terminate the session associated with a specified session token. The token
-        will be invalidated so it cannot be used for future authentication. This will return true
-        if the specified session token was valid and the log out operation was successful.",
-        arguments(session_token(description = "The session token to invalidate."))
+        description = "Delete the email settings of an organization, so emails sent on its behalf
+        use the server's settings again. Only owners and admins of the organization can delete
+        its email settings. This will return true if it had settings to delete.",
+        arguments(organization_id(description = "The organization's ID."))
     )]
-    async fn logout(&self, context: &Context, session_token: String) -> FieldResult<bool> {
-        convert_result(context.executor().logout(&session_token).await)
+    async fn delete_organization_email_settings(
+        &self,
+        context: &Context,
+        organization_id: Uuid,
+    ) -> FieldResult<bool> {
+        require_write_access(context).await?;
+        require_organization_admin(context, organization_id).await?;
+        convert_result(
+            context
+                .executor()
+                .delete_organization_email_settings(organization_id)
+                .await,
+        )
     }
 
     #[graphql(
-        description = "Attempt to create a new user with the provided username, email and password.
-        Once the user is created, an email verification code will be sent to the user's email
-        address.",
-        arguments(username(description = "The user's username.")),
-        arguments(email(description = "The user's email.")),
-        arguments(email(description = "The password the user will use to log in."))
+        description = "Add a custom hostname to an organization, such as \"api.example.com\".
+        Requests sent to it are routed to the organization once it publishes the returned
+        challenge value in a TXT record named by the returned challenge name. Adding a hostname
+        the organization already has checks its TXT record again right away. Only owners and
+        admins of the organization can add hostnames.",
+        arguments(
+            organization_id(description = "The organization's ID."),
+            hostname(description = "The hostname to serve the API on for the organization."),
+        )
     )]
-    async fn create_user(
+    async fn add_organization_domain(
         &self,
         context: &Context,
-        username: String,
-        email: String,
-        password: String,
-    ) -> FieldResult<User> {
-        if username.is_empty() {
-            return Err(FieldError::new(
-                "Username cannot be empty.",
-                graphql_value!({ "code": "username-empty" }),
-            ));
+        organization_id: Uuid,
+        hostname: String,
+    ) -> FieldResult<OrganizationDomain> {
+        require_write_access(context).await?;
+        require_organization_admin(context, organization_id).await?;
+        let executor = context.executor();
+        if !executor.config().organization_domains_enabled {
+            return Err(custom_domains_disabled());
         }
+        let hostname = domains::normalize_hostname(&hostname).ok_or_else(invalid_hostname)?;
 
-        if convert_result(context.executor().find_user_by_username(&username).await)?.is_some() {
-            return Err(FieldError::new(
-                "Username is already in use.",
-                graphql_value!({ "code": "username-taken" }),
-            ));
-        }
+        convert_result(
+            executor
+                .add_organization_domain(organization_id, &hostname)
+                .await,
+        )?
+        .ok_or_else(too_many_domains)
+    }
 
-        if email.is_empty() {
-            return Err(FieldError::new(
-                "Email cannot be empty.",
-                graphql_value!({ "code": "email-empty" }),
-            ));
-        }
+    #[graphql(
+        description = "Remove a custom hostname from an organization, so requests sent to it are
+        no longer routed to the organization. Only owners and admins of the organization can
+        remove hostnames. This will return true if the organization had the hostname.",
+        arguments(
+            organization_id(description = "The organization's ID."),
+            hostname(description = "The hostname to remove."),
+        )
+    )]
+    async fn remove_organization_domain(
+        &self,
+        context: &Context,
+        organization_id: Uuid,
+        hostname: String,
+    ) -> FieldResult<bool> {
+        require_write_access(context).await?;
+        require_organization_admin(context, organization_id).await?;
+        let hostname = match domains::normalize_hostname(&hostname) {
+            Some(hostname) => hostname,
+            None => return Ok(false),
+        };
 
-        if password.len() < MIN_PASSWORD_LENGTH {
-            return Err(FieldError::new(
-                "Password must be at least 6 characters.",
-                graphql_value!({ "code": "password-too-short" }),
-            ));
-        }
+        convert_result(
+            context
+                .executor()
+                .remove_organization_domain(organization_id, &hostname)
+                .await,
+        )
+    }
 
-        if password.len() > MAX_PASSWORD_LENGTH {
-            return Err(FieldError::new(
-                "Password cannot exceed 255 characters.",
-                graphql_value!({ "code": "password-too-long" }),
-            ));
-        }
+    #[graphql(
+        description = "Grant a role to a user. Only admins can grant roles. The user's roles before
+        and after are recorded in the audit log and returned in the \"changes\" response
+        extension. This will return the user's roles afterwards.",
+        arguments(
+            user_id(description = "The ID of the user to grant the role to."),
+            role(description = "The role to grant."),
+        )
+    )]
+    async fn grant_role(
+        &self,
+        context: &Context,
+        user_id: Uuid,
+        role: Role,
+    ) -> FieldResult<Vec<Role>> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        convert_result(context.executor().grant_role(viewer, user_id, role).await)?
+            .ok_or_else(user_not_found)
+    }
+
+    #[graphql(
+        description = "Revoke a role from a user. Only admins can revoke roles. The user's roles
+        before and after are recorded in the audit log and returned in the \"changes\" response
+        extension. This will return the user's roles afterwards.",
+        arguments(
+            user_id(description = "The ID of the user to revoke the role from."),
+            role(description = "The role to revoke."),
+        )
+    )]
+    async fn revoke_role(
+        &self,
+        context: &Context,
+        user_id: Uuid,
+        role: Role,
+    ) -> FieldResult<Vec<Role>> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        convert_result(context.executor().revoke_role(viewer, user_id, role).await)?
+            .ok_or_else(user_not_found)
+    }
+
+    #[graphql(
+        description = "Unlock login for a user locked out by too many failed login attempts. Only
+        admins can unlock users. This will return true if the user was locked.",
+        arguments(user_id(description = "The ID of the user to unlock."))
+    )]
+    async fn unlock_user(&self, context: &Context, user_id: Uuid) -> FieldResult<bool> {
+        require_role(context, Role::Admin).await?;
+        convert_result(context.executor().unlock_user(user_id).await)?.ok_or_else(user_not_found)
+    }
+
+    #[graphql(
+        description = "Deactivate a user, ending every one of their sessions. Deactivated users
+        can't log in until they are reactivated. Only admins can deactivate users. The change is
+        recorded in the audit log and returned in the \"changes\" response extension.",
+        arguments(
+            user_id(description = "The ID of the user to deactivate."),
+            dry_run(description = "Only return the user as they would be once deactivated,
+            and the change in the \"changes\" response extension, without saving it or ending
+            their sessions."),
+        )
+    )]
+    async fn deactivate_user(
+        &self,
+        context: &Context,
+        user_id: Uuid,
+        dry_run: Option<bool>,
+    ) -> FieldResult<User> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        convert_result(
+            context
+                .executor()
+                .deactivate_user(viewer, user_id, dry_run.unwrap_or(false))
+                .await,
+        )?
+        .ok_or_else(user_not_found)
+    }
+
+    #[graphql(
+        description = "Require a user to reset their password, as is done when their account may
+        have been compromised. The user can't log in with their password until they reset it,
+        every one of their sessions is ended, and they are emailed a password reset token. Only
+        admins can force password resets.",
+        arguments(user_id(description = "The ID of the user whose password must be reset."))
+    )]
+    async fn force_password_reset(&self, context: &Context, user_id: Uuid) -> FieldResult<User> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        convert_result(
+            context
+                .executor()
+                .force_password_reset(viewer, user_id)
+                .await,
+        )?
+        .ok_or_else(user_not_found)
+    }
+
+    #[graphql(
+        description = "Reactivate a deactivated user, so they can log in again. Only admins can
+        reactivate users. The change is recorded in the audit log and returned in the \"changes\"
+        response extension.",
+        arguments(user_id(description = "The ID of the user to reactivate."))
+    )]
+    async fn reactivate_user(&self, context: &Context, user_id: Uuid) -> FieldResult<User> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        convert_result(context.executor().reactivate_user(viewer, user_id).await)?
+            .ok_or_else(user_not_found)
+    }
 
+    #[graphql(
+        description = "Place a user under a legal hold, exempting them and their events from
+        retention purges and deletion until the hold is released. Deleted users can be held too.
+        Holding a user that is already held changes nothing. Only admins can apply legal holds.",
+        arguments(
+            user_id(description = "The ID of the user to hold."),
+            reason(
+                description = "Why the user is held, such as a case number. This is recorded in
+            the audit log."
+            ),
+        )
+    )]
+    async fn apply_legal_hold(
+        &self,
+        context: &Context,
+        user_id: Uuid,
+        reason: Option<String>,
+    ) -> FieldResult<User> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
         convert_result(
             context
                 .executor()
-                .create_user(&username, &email, &password)
+                .apply_legal_hold(viewer, user_id, reason.as_deref())
                 .await,
+        )?
+        .ok_or_else(user_not_found)
+    }
+
+    #[graphql(
+        description = "Release a user from a legal hold, so retention purges and deletion apply to
+        them again. Only admins can release legal holds.",
+        arguments(
+            user_id(description = "The ID of the user to release."),
+            reason(description = "Why the hold was released. This is recorded in the audit log."),
         )
+    )]
+    async fn release_legal_hold(
+        &self,
+        context: &Context,
+        user_id: Uuid,
+        reason: Option<String>,
+    ) -> FieldResult<User> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        convert_result(
+            context
+                .executor()
+                .release_legal_hold(viewer, user_id, reason.as_deref())
+                .await,
+        )?
+        .ok_or_else(user_not_found)
+    }
+
+    #[graphql(
+        description = "Process a webhook event again, such as after fixing what made it fail.
+        Events are processed again even if they succeeded before, and the returned event records
+        whether the replay succeeded. Only admins can replay webhook events.",
+        arguments(id(description = "The ID of the webhook event to replay."))
+    )]
+    async fn replay_webhook_event(&self, context: &Context, id: Uuid) -> FieldResult<WebhookEvent> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        convert_result(context.executor().replay_webhook_event(viewer, id).await)?
+            .ok_or_else(webhook_event_not_found)
+    }
+
+    #[graphql(
+        description = "Move an email from the dead letter list back to the queue, to be sent right
+        away with a fresh set of attempts. Queued emails are already retried on their own. Only
+        admins can retry emails.",
+        arguments(id(description = "The ID of the dead email to retry."))
+    )]
+    async fn retry_email(&self, context: &Context, id: Uuid) -> FieldResult<OutboxEmail> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        convert_result(context.executor().retry_email(viewer, id).await)?
+            .ok_or_else(dead_email_not_found)
+    }
+
+    #[graphql(
+        description = "Delete every email in the dead letter list, returning how many were
+        deleted. Only admins can purge dead emails."
+    )]
+    async fn purge_dead_emails(&self, context: &Context) -> FieldResult<i32> {
+        require_role(context, Role::Admin).await?;
+        let viewer = require_viewer(context)?;
+        let count = convert_result(context.executor().purge_dead_emails(viewer).await)?;
+        Ok(count.min(i32::MAX as u32) as i32)
     }
 
     #[graphql(
@@ -204,29 +2887,83 @@ impl Mutation {
                 .await,
         )
     }
+
+    #[graphql(
+        description = "Email a user a new verification code for their current email address,
+        replacing any code they were sent before. Resends are throttled per user, and throttled
+        requests fail with \"retryAt\" and \"retryAfterSeconds\" error extensions. This will
+        return false if the email address is already verified.",
+        arguments(user_id(
+            description = "The ID of the user to resend the verification email to."
+        ))
+    )]
+    async fn resend_verification_email(
+        &self,
+        context: &Context,
+        user_id: Uuid,
+    ) -> FieldResult<bool> {
+        let result = convert_result(context.executor().resend_verification_email(user_id).await)?;
+        match result.ok_or_else(user_not_found)? {
+            ResendVerificationResult::Sent => Ok(true),
+            ResendVerificationResult::AlreadyVerified => Ok(false),
+            ResendVerificationResult::Throttled(retry_at) => {
+                Err(verification_email_throttled(retry_at))
+            }
+        }
+    }
 }
 
-/// Type of the executable GraphQL schema.
-pub type Schema = RootNode<'static, Query, Mutation, EmptySubscription<Context>>;
+/// Type of the executable GraphQL schema. The query and mutation roots merge the server's own
+/// fields with any fields contributed by plugins.
+pub type Schema = RootNode<'static, MergedRoot, MergedRoot, EmptySubscription<Context>>;
+
+/// Build the executable GraphQL schema, composing the server's own queries and mutations with
+/// those contributed by the provided plugins.
+pub fn build_schema(plugins: &[Box<dyn Plugin>]) -> Schema {
+    let mut queries: Vec<Arc<dyn FieldSource>> = vec![Arc::new(ObjectFields(Query))];
+    let mut mutations: Vec<Arc<dyn FieldSource>> = vec![Arc::new(ObjectFields(Mutation))];
+    for plugin in plugins {
+        queries.extend(plugin.query());
+        mutations.extend(plugin.mutation());
+    }
+
+    Schema::new_with_info(
+        MergedRoot,
+        MergedRoot,
+        EmptySubscription::new(),
+        RootInfo::new("Query", "All available GraphQL queries.", queries),
+        RootInfo::new("Mutation", "All available GraphQL mutations.", mutations),
+        (),
+    )
+}
 
-lazy_static! {
-    /// Static immutable reference to the executable GraphQL schema.
-    pub static ref SCHEMA: Schema =
-        Schema::new(Query, Mutation, EmptySubscription::<Context>::new());
+/// Render a schema in the GraphQL schema language.
+pub fn schema_language(schema: &Schema) -> String {
+    let mut sdl = Document::<&str>::from(&schema.schema).to_string();
+    // Juniper has no support for one-of input objects, so they are marked as such here and
+    // validated by their resolvers.
+    for name in ONE_OF_INPUT_OBJECTS {
+        sdl = sdl.replace(
+            &format!("input {} {{", name),
+            &format!("input {} @oneOf {{", name),
+        );
+    }
+    sdl + "\ndirective @oneOf on INPUT_OBJECT\n"
 }
 
 #[derive(Debug, Clone)]
 pub struct AuthResult {
-    session_token: String,
+    session_token: Option<String>,
 }
 
 #[graphql_object(description = "The result of a successful authentication action.")]
 impl AuthResult {
     #[graphql(
         description = "The session token to be used for future requests. This should be sent as a
-        bearer token in the 'authorization' header."
+        bearer token in the 'authorization' header. This will be null when session tokens are
+        delivered as HTTP-only cookies, which browsers send along with future requests."
     )]
-    pub fn session_token(&self) -> &str {
-        &self.session_token
+    pub fn session_token(&self) -> Option<&str> {
+        self.session_token.as_deref()
     }
 }
@@ -0,0 +1,111 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use juniper::GraphQLEnum;
+
+use crate::audit::AuditEventKind;
+
+/// Default number of security events returned when listing a user's security activity.
+pub const DEFAULT_PAGE_SIZE: i32 = 50;
+/// Maximum number of security events returned when listing a user's security activity.
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+/// An event in the security activity of a user's account, which the user can review.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "An event in the security activity of a user's account.")]
+pub enum SecurityEventKind {
+    #[graphql(description = "The user logged in.")]
+    Login,
+    #[graphql(description = "The user logged in from a device they hadn't logged in from before.")]
+    NewDevice,
+    #[graphql(description = "Someone failed to log in as the user.")]
+    LoginFailed,
+    #[graphql(description = "The user changed their password.")]
+    PasswordChanged,
+    #[graphql(description = "The user reset their password with a reset token.")]
+    PasswordReset,
+    #[graphql(description = "An admin required the user to reset their password.")]
+    PasswordResetForced,
+    #[graphql(description = "The user changed their email address.")]
+    EmailChanged,
+    #[graphql(
+        description = "A session token that was already refreshed was used again, so its session
+        was terminated."
+    )]
+    SessionTokenReused,
+    #[graphql(description = "The user added a passkey.")]
+    PasskeyAdded,
+    #[graphql(description = "The user removed a passkey.")]
+    PasskeyRemoved,
+}
+
+impl SecurityEventKind {
+    /// The name the kind is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SecurityEventKind::Login => "login",
+            SecurityEventKind::NewDevice => "new-device",
+            SecurityEventKind::LoginFailed => "login-failed",
+            SecurityEventKind::PasswordChanged => "password-changed",
+            SecurityEventKind::PasswordReset => "password-reset",
+            SecurityEventKind::PasswordResetForced => "password-reset-forced",
+            SecurityEventKind::EmailChanged => "email-changed",
+            SecurityEventKind::SessionTokenReused => "session-token-reused",
+            SecurityEventKind::PasskeyAdded => "passkey-added",
+            SecurityEventKind::PasskeyRemoved => "passkey-removed",
+        }
+    }
+
+    /// Get the security event recorded along with an audit event about a user, or none if the
+    /// audit event isn't shown to the user it is about.
+    pub fn from_audit_event(kind: AuditEventKind) -> Option<Self> {
+        match kind {
+            AuditEventKind::Login => Some(SecurityEventKind::Login),
+            AuditEventKind::LoginFailed => Some(SecurityEventKind::LoginFailed),
+            AuditEventKind::PasswordChanged => Some(SecurityEventKind::PasswordChanged),
+            AuditEventKind::PasswordReset => Some(SecurityEventKind::PasswordReset),
+            AuditEventKind::PasswordResetForced => Some(SecurityEventKind::PasswordResetForced),
+            AuditEventKind::EmailChanged => Some(SecurityEventKind::EmailChanged),
+            AuditEventKind::SessionTokenReused => Some(SecurityEventKind::SessionTokenReused),
+            AuditEventKind::PasskeyAdded => Some(SecurityEventKind::PasskeyAdded),
+            AuditEventKind::PasskeyRemoved => Some(SecurityEventKind::PasskeyRemoved),
+            AuditEventKind::UserCreated
+            | AuditEventKind::Logout
+            | AuditEventKind::EmailVerified
+            | AuditEventKind::EmailVerificationFailed
+            | AuditEventKind::RoleGranted
+            | AuditEventKind::RoleRevoked
+            | AuditEventKind::LegalHoldApplied
+            | AuditEventKind::LegalHoldReleased
+            | AuditEventKind::UserDeactivated
+            | AuditEventKind::UserReactivated => None,
+        }
+    }
+}
+
+impl Display for SecurityEventKind {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for SecurityEventKind {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "login" => Ok(SecurityEventKind::Login),
+            "new-device" => Ok(SecurityEventKind::NewDevice),
+            "login-failed" => Ok(SecurityEventKind::LoginFailed),
+            "password-changed" => Ok(SecurityEventKind::PasswordChanged),
+            "password-reset" => Ok(SecurityEventKind::PasswordReset),
+            "password-reset-forced" => Ok(SecurityEventKind::PasswordResetForced),
+            "email-changed" => Ok(SecurityEventKind::EmailChanged),
+            "session-token-reused" => Ok(SecurityEventKind::SessionTokenReused),
+            "passkey-added" => Ok(SecurityEventKind::PasskeyAdded),
+            "passkey-removed" => Ok(SecurityEventKind::PasskeyRemoved),
+            _ => Err(anyhow!("Unknown security event kind: {}", value)),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use sqlx::{query, query_as, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::models::User;
+use crate::password_policy::{check_password_length, InvalidPassword};
+use crate::roles::Role;
+
+/// Password given to the built-in development users.
+const DEVELOPMENT_PASSWORD: &str = "password";
+
+/// Something that populates the database with development or test data. Seeds must be safe to run
+/// repeatedly, updating the data they created on earlier runs rather than duplicating it.
+#[async_trait]
+pub trait Seed: Send + Sync {
+    /// A short, unique name for the seed, printed when it is run.
+    fn name(&self) -> &'static str;
+
+    /// Insert or update the seed's data.
+    async fn run(&self, db: &PgPool, config: &Config) -> Result<()>;
+}
+
+/// A user to create, or update if a user with the username already exists.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserFixture {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+    /// Roles to grant the user. Roles the user already has are kept.
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Specifies if the user's email address is marked as verified.
+    #[serde(default = "default_verified")]
+    pub verified: bool,
+}
+
+/// Fixtures users are verified unless they say otherwise, so they can use every part of the API.
+fn default_verified() -> bool {
+    true
+}
+
+/// A format fixtures files can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixturesFormat {
+    Yaml,
+    Toml,
+}
+
+impl FixturesFormat {
+    /// Get the format of a fixtures file from its extension. Files with a ".toml" extension are
+    /// TOML, and every other file is YAML.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => FixturesFormat::Toml,
+            _ => FixturesFormat::Yaml,
+        }
+    }
+}
+
+/// Data to seed the database with, declared in a YAML or TOML file such as:
+///
+/// ```yaml
+/// users:
+///   - username: ava
+///     email: ava@example.com
+///     password: password
+///     roles: [admin]
+/// ```
+///
+/// Unknown fields are rejected, so mistakes in the file aren't silently ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixtures {
+    #[serde(default)]
+    pub users: Vec<UserFixture>,
+}
+
+impl Fixtures {
+    /// Load fixtures from a file, in the format given by its extension.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(&contents, FixturesFormat::from_path(path))
+            .map_err(|error| anyhow!("Invalid fixtures file {}: {}", path.display(), error))
+    }
+
+    /// Parse fixtures written in a format, such as ones declared inline by a test.
+    pub fn parse(contents: &str, format: FixturesFormat) -> Result<Self> {
+        Ok(match format {
+            FixturesFormat::Yaml => serde_yaml::from_str(contents)?,
+            FixturesFormat::Toml => toml::from_str(contents)?,
+        })
+    }
+
+    /// Insert or update the data declared by the fixtures, in a single transaction so nothing is
+    /// changed if any fixture is invalid. This will return the records the fixtures match, so
+    /// tests can refer to them by the names they were declared with.
+    pub async fn apply(&self, db: &PgPool, config: &Config) -> Result<LoadedFixtures> {
+        let mut loaded = LoadedFixtures::default();
+        let mut transaction = db.begin().await?;
+        for fixture in &self.users {
+            let user = upsert_user(&mut transaction, config, fixture).await?;
+            loaded.users.insert(user.username.clone(), user);
+        }
+        transaction.commit().await?;
+
+        Ok(loaded)
+    }
+}
+
+#[async_trait]
+impl Seed for Fixtures {
+    fn name(&self) -> &'static str {
+        "fixtures"
+    }
+
+    async fn run(&self, db: &PgPool, config: &Config) -> Result<()> {
+        self.apply(db, config).await?;
+        Ok(())
+    }
+}
+
+/// The records matched by fixtures once they have been applied.
+#[derive(Debug, Clone, Default)]
+pub struct LoadedFixtures {
+    /// The users declared by the fixtures, by username.
+    pub users: HashMap<String, User>,
+}
+
+impl LoadedFixtures {
+    /// Get a user declared by the fixtures. This will return an error if no user with the
+    /// username was declared.
+    pub fn user(&self, username: &str) -> Result<&User> {
+        self.users
+            .get(username)
+            .ok_or_else(|| anyhow!("No user fixture named {}.", username))
+    }
+}
+
+/// Seeds a small set of users for local development: an admin, a verified user and an unverified
+/// user, all with the password "password".
+pub struct DevelopmentUsers;
+
+#[async_trait]
+impl Seed for DevelopmentUsers {
+    fn name(&self) -> &'static str {
+        "development-users"
+    }
+
+    async fn run(&self, db: &PgPool, config: &Config) -> Result<()> {
+        let user = |username: &str, roles: &[Role], verified| UserFixture {
+            username: username.into(),
+            email: format!("{}@example.com", username),
+            password: DEVELOPMENT_PASSWORD.into(),
+            roles: roles.iter().map(|role| role.as_str().into()).collect(),
+            verified,
+        };
+
+        Fixtures {
+            users: vec![
+                user("admin", &[Role::Admin], true),
+                user("ava", &[], true),
+                user("ben", &[], false),
+            ],
+        }
+        .run(db, config)
+        .await
+    }
+}
+
+/// The seeds run when no fixtures file is given.
+pub fn default_seeds() -> Vec<Box<dyn Seed>> {
+    vec![Box::new(DevelopmentUsers)]
+}
+
+/// Create a user from a fixture, or update the user with the fixture's username to match it.
+/// Existing users are restored if they were deactivated or deleted. This will return an error if
+/// the fixture is invalid.
+async fn upsert_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    config: &Config,
+    fixture: &UserFixture,
+) -> Result<User> {
+    // Fixtures are development data, so their passwords only need to be a valid length.
+    if let Some(InvalidPassword { message, .. }) =
+        check_password_length(&config.password_policy, &fixture.password)
+    {
+        return Err(anyhow!("{} ({})", message, fixture.username));
+    }
+    let roles = fixture
+        .roles
+        .iter()
+        .map(|role| role.parse::<Role>())
+        .collect::<Result<Vec<_>>>()?;
+
+    let password_hash = bcrypt::hash(&fixture.password, config.password_hash_cost)?;
+    let user = query_as!(
+        User,
+        "
+        INSERT INTO users (id, username, email, email_verified_at, password_hash)
+        VALUES ($1, $2, $3, CASE WHEN $4 THEN NOW() END, $5)
+        ON CONFLICT (username) DO UPDATE SET
+            email = EXCLUDED.email,
+            email_verified_at = CASE
+                WHEN $4 THEN COALESCE(users.email_verified_at, NOW())
+            END,
+            password_hash = EXCLUDED.password_hash,
+            deactivated_at = NULL,
+            deleted_at = NULL
+        RETURNING *
+        ",
+        Uuid::new_v4(),
+        fixture.username,
+        fixture.email,
+        fixture.verified,
+        password_hash,
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    for role in roles {
+        query!(
+            "INSERT INTO user_roles (user_id, role) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            user.id,
+            role.as_str(),
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    Ok(user)
+}
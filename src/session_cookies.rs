@@ -0,0 +1,360 @@
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Error};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use crate::config::Config;
+use crate::graphql::{bearer_token, AllowedOperations};
+
+/// Header responses set cookies with.
+pub const SET_COOKIE_HEADER: &str = "Set-Cookie";
+/// Header clients send the value of the CSRF cookie back in, so the server can tell the request
+/// came from a page that could read the cookie.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+/// Name of the cookie holding the CSRF token. Unlike the session cookie, scripts can read it.
+pub const CSRF_COOKIE_NAME: &str = "csrf_token";
+/// Message returned when a mutation authenticated with a session cookie didn't send a matching
+/// CSRF token.
+pub const INVALID_CSRF_TOKEN_ERROR_MESSAGE: &str =
+    "Send the value of the \"csrf_token\" cookie in the \"X-CSRF-Token\" header to run mutations.";
+/// Error code returned when a mutation authenticated with a session cookie didn't send a matching
+/// CSRF token.
+pub const INVALID_CSRF_TOKEN_ERROR_CODE: &str = "invalid-csrf-token";
+/// Number of characters in a CSRF token.
+const CSRF_TOKEN_LENGTH: usize = 32;
+
+/// Decides when browsers send a cookie along with requests started by other sites.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SameSite {
+    /// The cookie is only sent with requests started by the site that set it.
+    Strict,
+    /// The cookie is also sent when navigating to the site from another site.
+    #[default]
+    Lax,
+    /// The cookie is sent with every request. Cookies must be secure for browsers to accept this.
+    None,
+}
+
+impl SameSite {
+    /// The value of the cookie attribute.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+impl FromStr for SameSite {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(anyhow!("Unknown SameSite policy: {}", value)),
+        }
+    }
+}
+
+/// How session tokens are delivered to clients as cookies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionCookiePolicy {
+    /// Specifies if session tokens are delivered as HTTP-only cookies instead of in response
+    /// bodies, and read from the cookie when requests don't send a bearer token.
+    pub enabled: bool,
+    /// The name of the cookie holding the session token.
+    pub name: String,
+    /// The domain the cookies are sent to, or none to only send them to the server's own host.
+    pub domain: Option<String>,
+    /// Specifies if the cookies are only sent over HTTPS.
+    pub secure: bool,
+    /// Decides when browsers send the cookies along with requests started by other sites.
+    pub same_site: SameSite,
+}
+
+impl SessionCookiePolicy {
+    /// Build the value of the header setting a cookie. Cookies with a max age of zero are deleted.
+    /// Host-only cookies are only sent to the host that set them, ignoring the configured domain.
+    fn set_cookie(
+        &self,
+        name: &str,
+        value: &str,
+        max_age_seconds: u32,
+        http_only: bool,
+        host_only: bool,
+    ) -> String {
+        let mut cookie = format!("{}={}; Path=/; Max-Age={}", name, value, max_age_seconds);
+        if let Some(domain) = self.domain.as_ref().filter(|_| !host_only) {
+            cookie.push_str(&format!("; Domain={}", domain));
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        cookie.push_str(&format!("; SameSite={}", self.same_site.as_str()));
+        cookie
+    }
+}
+
+/// The session token and CSRF protection of a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestCredentials {
+    /// The unverified session token the request was sent with, if any.
+    pub session_token: Option<String>,
+    /// Specifies if the request can run mutations. Requests authenticated with a session cookie
+    /// must send the CSRF cookie's value in a header, as browsers send cookies along with requests
+    /// forged by other sites but don't let those sites read them.
+    pub csrf_verified: bool,
+}
+
+impl RequestCredentials {
+    /// Get the credentials of a request from the values of its authorization, cookie and CSRF
+    /// headers. Bearer tokens are preferred over session cookies, which are only read when cookie
+    /// sessions are enabled.
+    pub fn new(
+        policy: &SessionCookiePolicy,
+        authorization: Option<&str>,
+        cookie: Option<&str>,
+        csrf_token: Option<&str>,
+    ) -> Self {
+        if let Some(session_token) = bearer_token(authorization) {
+            return RequestCredentials {
+                session_token: Some(session_token),
+                csrf_verified: true,
+            };
+        }
+
+        let session_token = match cookie {
+            Some(cookie) if policy.enabled => find_cookie(cookie, &policy.name),
+            _ => None,
+        };
+        let csrf_verified = session_token.is_none()
+            || match (
+                cookie.and_then(|cookie| find_cookie(cookie, CSRF_COOKIE_NAME)),
+                csrf_token,
+            ) {
+                (Some(expected), Some(actual)) => constant_time_eq(&expected, actual.trim()),
+                _ => false,
+            };
+
+        RequestCredentials {
+            session_token,
+            csrf_verified,
+        }
+    }
+
+    /// Get the operations the request is allowed to run.
+    pub fn allowed_operations(&self) -> AllowedOperations {
+        if self.csrf_verified {
+            AllowedOperations::Any
+        } else {
+            AllowedOperations::QueriesWithoutCsrfToken
+        }
+    }
+}
+
+/// Find the value of a cookie by name in the value of a request's cookie header. This will return
+/// none if the cookie wasn't sent or is empty.
+fn find_cookie(header: &str, name: &str) -> Option<String> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"').to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Compare two tokens in time that only depends on their length, so guesses can't be refined by
+/// timing how long they take to be rejected.
+fn constant_time_eq(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |difference, (first, second)| {
+                difference | (first ^ second)
+            })
+            == 0
+}
+
+/// A change to the session cookie made while resolving a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SessionCookieChange {
+    /// A session was started or refreshed, and its token should be stored.
+    Set(String),
+    /// The session ended, so the cookies should be deleted.
+    Clear,
+}
+
+/// Changes to the session cookie made while resolving a request, applied to the response once it
+/// is sent. Clones share the same changes, so they can be read after the request's context has
+/// been handed to the schema.
+#[derive(Debug, Clone, Default)]
+pub struct SessionCookies(Arc<Mutex<Option<SessionCookieChange>>>);
+
+impl SessionCookies {
+    /// Store a session token in the session cookie. A new CSRF token is issued along with it.
+    pub fn set(&self, session_token: &str) {
+        *self.0.lock().unwrap() = Some(SessionCookieChange::Set(session_token.into()));
+    }
+
+    /// Delete the session and CSRF cookies.
+    pub fn clear(&self) {
+        *self.0.lock().unwrap() = Some(SessionCookieChange::Clear);
+    }
+
+    /// Get the headers setting the cookies changed while resolving the request. Cookies are kept for
+    /// as long as session tokens last. Host-only cookies are set for requests sent to an
+    /// organization's custom hostname, which the configured cookie domain doesn't cover. There are
+    /// none if the cookies weren't changed or cookie sessions are disabled.
+    pub fn headers(&self, config: &Config, host_only: bool) -> Vec<(&'static str, String)> {
+        let policy = &config.session_cookie_policy;
+        let max_age_seconds = config.session_token_expiration_seconds;
+        if !policy.enabled {
+            return Vec::new();
+        }
+
+        match &*self.0.lock().unwrap() {
+            Some(SessionCookieChange::Set(session_token)) => {
+                let csrf_token: String = rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(CSRF_TOKEN_LENGTH)
+                    .map(char::from)
+                    .collect();
+                vec![
+                    (
+                        SET_COOKIE_HEADER,
+                        policy.set_cookie(
+                            &policy.name,
+                            session_token,
+                            max_age_seconds,
+                            true,
+                            host_only,
+                        ),
+                    ),
+                    (
+                        SET_COOKIE_HEADER,
+                        policy.set_cookie(
+                            CSRF_COOKIE_NAME,
+                            &csrf_token,
+                            max_age_seconds,
+                            false,
+                            host_only,
+                        ),
+                    ),
+                ]
+            }
+            Some(SessionCookieChange::Clear) => vec![
+                (
+                    SET_COOKIE_HEADER,
+                    policy.set_cookie(&policy.name, "", 0, true, host_only),
+                ),
+                (
+                    SET_COOKIE_HEADER,
+                    policy.set_cookie(CSRF_COOKIE_NAME, "", 0, false, host_only),
+                ),
+            ],
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(enabled: bool) -> SessionCookiePolicy {
+        SessionCookiePolicy {
+            enabled,
+            name: "session".into(),
+            domain: None,
+            secure: true,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    #[test]
+    fn matching_csrf_tokens_allow_mutations() {
+        let cookie = Some("session=token; csrf_token=abc123");
+        let credentials = RequestCredentials::new(&policy(true), None, cookie, Some("abc123"));
+        assert_eq!(credentials.session_token.as_deref(), Some("token"));
+        assert!(credentials.csrf_verified);
+        assert_eq!(credentials.allowed_operations(), AllowedOperations::Any);
+    }
+
+    #[test]
+    fn missing_or_different_csrf_tokens_only_allow_queries() {
+        let cookie = Some("session=token; csrf_token=abc123");
+        for csrf_token in [None, Some("abc124"), Some("abc1234"), Some("")] {
+            let credentials = RequestCredentials::new(&policy(true), None, cookie, csrf_token);
+            assert!(!credentials.csrf_verified);
+            let allowed = credentials.allowed_operations();
+            assert_eq!(allowed, AllowedOperations::QueriesWithoutCsrfToken);
+        }
+
+        // A header can't be matched against a CSRF cookie that wasn't sent.
+        let cookie = Some("session=token");
+        let credentials = RequestCredentials::new(&policy(true), None, cookie, Some("abc123"));
+        assert!(!credentials.csrf_verified);
+    }
+
+    #[test]
+    fn requests_without_session_cookies_need_no_csrf_token() {
+        let credentials = RequestCredentials::new(&policy(true), Some("Bearer token"), None, None);
+        assert_eq!(credentials.session_token.as_deref(), Some("token"));
+        assert!(credentials.csrf_verified);
+
+        let credentials = RequestCredentials::new(&policy(true), None, None, None);
+        assert_eq!(credentials.session_token, None);
+        assert!(credentials.csrf_verified);
+    }
+
+    #[test]
+    fn session_cookies_are_ignored_when_disabled() {
+        let cookie = Some("session=token; csrf_token=abc123");
+        let credentials = RequestCredentials::new(&policy(false), None, cookie, None);
+        assert_eq!(credentials.session_token, None);
+        assert!(credentials.csrf_verified);
+    }
+
+    #[test]
+    fn tokens_are_compared_in_full() {
+        assert!(constant_time_eq("abc123", "abc123"));
+        assert!(!constant_time_eq("abc123", "abc12"));
+        assert!(!constant_time_eq("abc123", "abc124"));
+        assert!(!constant_time_eq("", "a"));
+    }
+
+    #[test]
+    fn host_only_cookies_have_no_domain() {
+        let policy = SessionCookiePolicy {
+            domain: Some("example.com".into()),
+            ..policy(true)
+        };
+        let cookie = policy.set_cookie("session", "token", 60, true, false);
+        assert_eq!(
+            cookie,
+            "session=token; Path=/; Max-Age=60; Domain=example.com; Secure; HttpOnly; SameSite=Lax"
+        );
+        let cookie = policy.set_cookie("session", "token", 60, true, true);
+        assert_eq!(
+            cookie,
+            "session=token; Path=/; Max-Age=60; Secure; HttpOnly; SameSite=Lax"
+        );
+    }
+
+    #[test]
+    fn cookies_are_found_by_exact_name() {
+        let header = "other_session=wrong; session=\"token\"; empty=";
+        assert_eq!(find_cookie(header, "session").as_deref(), Some("token"));
+        assert_eq!(find_cookie(header, "empty"), None);
+        assert_eq!(find_cookie(header, "missing"), None);
+    }
+}
@@ -0,0 +1,223 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Utc};
+use jwt::{SignWithKey, VerifyWithKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::auth::SessionTokenSecret;
+use crate::config::Config;
+use crate::models::User;
+
+/// Default number of pending signups returned when listing them.
+pub const DEFAULT_PAGE_SIZE: i32 = 50;
+/// Maximum number of pending signups returned when listing them.
+pub const MAX_PAGE_SIZE: i32 = 100;
+/// Message of the error returned when a suspicious signup must pass a CAPTCHA.
+pub const CAPTCHA_REQUIRED_ERROR_MESSAGE: &str =
+    "Complete the CAPTCHA to finish creating your account.";
+/// Message of the error returned when a suspicious signup was queued for review.
+pub const SIGNUP_PENDING_REVIEW_ERROR_MESSAGE: &str =
+    "Your account will be created once it has been reviewed.";
+/// Purpose form tokens are signed with, so session tokens can't be passed off as form tokens.
+const FORM_TOKEN_PURPOSE: &str = "signup-form";
+/// Substrings of user agents sent by HTTP libraries and automated browsers rather than people.
+const AUTOMATED_USER_AGENTS: &[&str] = &[
+    "bot",
+    "crawler",
+    "curl",
+    "go-http-client",
+    "headlesschrome",
+    "httpclient",
+    "java/",
+    "libwww",
+    "okhttp",
+    "phantomjs",
+    "python",
+    "scrapy",
+    "selenium",
+    "spider",
+    "wget",
+];
+
+/// Something about a signup suggesting it was made by a bot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignupSignal {
+    /// The honeypot field, which is hidden from people, was filled in.
+    Honeypot,
+    /// No signup form token was sent, so the form probably wasn't loaded.
+    MissingFormToken,
+    /// The signup form token was forged or has expired.
+    InvalidFormToken,
+    /// The signup form was submitted faster than a person could fill it in.
+    SubmittedTooFast,
+    /// The request had no user agent.
+    MissingUserAgent,
+    /// The request's user agent belongs to an HTTP library or automated browser.
+    AutomatedUserAgent,
+}
+
+impl SignupSignal {
+    /// The name the signal is stored and recorded in metrics under.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SignupSignal::Honeypot => "honeypot",
+            SignupSignal::MissingFormToken => "missing-form-token",
+            SignupSignal::InvalidFormToken => "invalid-form-token",
+            SignupSignal::SubmittedTooFast => "submitted-too-fast",
+            SignupSignal::MissingUserAgent => "missing-user-agent",
+            SignupSignal::AutomatedUserAgent => "automated-user-agent",
+        }
+    }
+}
+
+/// What happens to signups with signals suggesting they were made by a bot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignupScreeningAction {
+    /// The signup is queued for an admin to approve or reject.
+    #[default]
+    Review,
+    /// The user is only created once the signup passes a CAPTCHA.
+    Captcha,
+}
+
+impl FromStr for SignupScreeningAction {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "review" => Ok(SignupScreeningAction::Review),
+            "captcha" => Ok(SignupScreeningAction::Captcha),
+            _ => Err(anyhow!("Unknown signup screening action: {}", value)),
+        }
+    }
+}
+
+/// What a client sent along with a signup to show it was made by a person.
+#[derive(Debug, Clone, Default)]
+pub struct SignupEvidence {
+    /// The value of the honeypot field, which people leave empty.
+    pub honeypot: Option<String>,
+    /// The token issued when the signup form was loaded.
+    pub form_token: Option<String>,
+    /// The token issued by the CAPTCHA provider once the CAPTCHA was passed.
+    pub captcha_token: Option<String>,
+}
+
+/// The outcome of an attempt to sign up.
+#[derive(Debug, Clone)]
+pub enum SignupResult {
+    /// The user was created.
+    Created(Box<User>),
+    /// The signup looks suspicious and must pass a CAPTCHA before the user is created.
+    CaptchaRequired,
+    /// The signup looks suspicious and was queued for review. The user is only created once an
+    /// admin approves it.
+    PendingReview,
+}
+
+/// Claims of a signup form token.
+#[derive(Serialize, Deserialize)]
+struct FormTokenClaims {
+    purpose: String,
+    /// Unix timestamp of when the form was loaded.
+    issued_at: i64,
+}
+
+/// Issue a token for a signup form being loaded, to be sent back with the signup so the server
+/// can tell how long the form took to fill in. Tokens are signed with the session token secret.
+pub fn issue_form_token(secret: &SessionTokenSecret, now: DateTime<Utc>) -> String {
+    let claims = FormTokenClaims {
+        purpose: FORM_TOKEN_PURPOSE.into(),
+        issued_at: now.timestamp(),
+    };
+    // Signing claims with an HMAC key can't fail.
+    claims.sign_with_key(secret).unwrap()
+}
+
+/// Get the number of seconds since a signup form token was issued, or none if it is invalid.
+fn form_token_age(token: &str, secret: &SessionTokenSecret, now: DateTime<Utc>) -> Option<i64> {
+    let claims: FormTokenClaims = token.verify_with_key(secret).ok()?;
+    if claims.purpose != FORM_TOKEN_PURPOSE {
+        return None;
+    }
+    Some(now.timestamp() - claims.issued_at)
+}
+
+/// Check a signup for signals suggesting it was made by a bot. Forms submitted before their
+/// token was issued or after it expired count as invalid tokens.
+pub fn screen(
+    config: &Config,
+    evidence: &SignupEvidence,
+    user_agent: Option<&str>,
+    now: DateTime<Utc>,
+) -> Vec<SignupSignal> {
+    let mut signals = Vec::new();
+
+    if evidence
+        .honeypot
+        .as_deref()
+        .is_some_and(|value| !value.is_empty())
+    {
+        signals.push(SignupSignal::Honeypot);
+    }
+
+    match &evidence.form_token {
+        None => signals.push(SignupSignal::MissingFormToken),
+        Some(token) => match form_token_age(token, &config.session_token_secret, now) {
+            Some(age) if age < 0 || age > config.signup_form_token_expiration_seconds.into() => {
+                signals.push(SignupSignal::InvalidFormToken)
+            }
+            Some(age) if age < config.signup_min_form_seconds.into() => {
+                signals.push(SignupSignal::SubmittedTooFast)
+            }
+            Some(_) => {}
+            None => signals.push(SignupSignal::InvalidFormToken),
+        },
+    }
+
+    match user_agent {
+        None => signals.push(SignupSignal::MissingUserAgent),
+        Some(user_agent) if is_automated_user_agent(user_agent) => {
+            signals.push(SignupSignal::AutomatedUserAgent)
+        }
+        Some(_) => {}
+    }
+
+    signals
+}
+
+/// Check if a user agent belongs to an HTTP library or automated browser.
+fn is_automated_user_agent(user_agent: &str) -> bool {
+    let user_agent = user_agent.to_lowercase();
+    user_agent.trim().is_empty()
+        || AUTOMATED_USER_AGENTS
+            .iter()
+            .any(|automated| user_agent.contains(automated))
+}
+
+/// Verify a CAPTCHA token with the CAPTCHA provider's verification endpoint. hCaptcha, reCAPTCHA
+/// and Cloudflare Turnstile all accept the same request. This will return an error if no CAPTCHA
+/// secret is configured or the provider can't be reached.
+pub async fn verify_captcha(config: &Config, token: &str, client_ip: Option<&str>) -> Result<bool> {
+    let secret = config
+        .captcha_secret
+        .as_ref()
+        .ok_or_else(|| anyhow!("A CAPTCHA secret must be set to verify CAPTCHAs."))?;
+
+    let mut form = vec![("secret", secret.as_str()), ("response", token)];
+    if let Some(client_ip) = client_ip {
+        form.push(("remoteip", client_ip));
+    }
+    let mut response = surf::post(&config.captcha_verify_url)
+        .body(surf::Body::from_form(&form).map_err(|error| error.into_inner())?)
+        .await
+        .map_err(|error| error.into_inner())?;
+    let body: Value = response
+        .body_json()
+        .await
+        .map_err(|error| error.into_inner())?;
+
+    Ok(body["success"].as_bool().unwrap_or(false))
+}
@@ -1,7 +1,26 @@
+use std::sync::Arc;
+
 use redis::aio::ConnectionManager;
 use sqlx::PgPool;
 
+use crate::alerts::AlertCounters;
+#[cfg(feature = "async-graphql")]
+use crate::async_schema::{build_async_schema, AsyncSchema};
+use crate::cache::{Cache, Caches, RedisCache};
+use crate::concurrency::ConcurrencyLimiter;
 use crate::config::Config;
+use crate::domains::DomainRegistry;
+#[cfg(feature = "email")]
+use crate::email::Mailer;
+use crate::events::EventPublisher;
+use crate::graphql::SchemaDocument;
+use crate::lifecycle::Lifecycle;
+use crate::metrics::Metrics;
+use crate::regions::Regions;
+#[cfg(not(feature = "async-graphql"))]
+use crate::schema::schema_language;
+use crate::schema::Schema;
+use crate::storage::FileStore;
 
 /// Global shared state for the server. This should be relatively cheap to clone and should be
 /// sharable between threads.
@@ -13,11 +32,114 @@ pub struct State {
     pub db: PgPool,
     /// Redis database connection manager.
     pub redis: ConnectionManager,
+    /// The Redis databases of other regions, which session writes are replicated to.
+    pub regions: Regions,
+    /// Pooled SMTP mailer used to send emails.
+    #[cfg(feature = "email")]
+    pub mailer: Mailer,
+    /// Prometheus metrics describing the server.
+    pub metrics: Metrics,
+    /// Counts of what happened since alerts were last checked, such as server errors.
+    pub alert_counters: AlertCounters,
+    /// Caches shared between requests, invalidated when the rows they were read from change.
+    pub caches: Caches,
+    /// Where cached results are kept.
+    pub cache: Arc<dyn Cache>,
+    /// Where short-lived values that must be shared by every server instance, such as rate limit
+    /// counters, login lockouts and verification codes, are kept. Unlike the cache, this can't be
+    /// turned off.
+    pub store: Arc<dyn Cache>,
+    /// Limits how many calls to each expensive resolver run at once.
+    pub limiter: ConcurrencyLimiter,
+    /// Publisher of domain events, such as users being created or subscriptions changing.
+    pub events: EventPublisher,
+    /// Store private files, such as uploads, are kept in.
+    pub files: Arc<dyn FileStore>,
+    /// Runs the startup and shutdown hooks of the server's subsystems.
+    pub lifecycle: Lifecycle,
+    /// The verified custom hostnames of organizations, which requests are routed by.
+    pub domains: DomainRegistry,
+    /// The executable GraphQL schema.
+    pub schema: Arc<Schema>,
+    /// The schema requests are executed against, in the GraphQL schema language.
+    pub schema_document: Arc<SchemaDocument>,
+    /// The executable async-graphql schema, used in place of the juniper schema.
+    #[cfg(feature = "async-graphql")]
+    pub async_schema: AsyncSchema,
 }
 
 impl State {
     /// Create a new global state object.
-    pub fn new(config: Config, db: PgPool, redis: ConnectionManager) -> Self {
-        Self { config, db, redis }
+    pub fn new(
+        config: Config,
+        db: PgPool,
+        redis: ConnectionManager,
+        #[cfg(feature = "email")] mailer: Mailer,
+        metrics: Metrics,
+        files: Arc<dyn FileStore>,
+        schema: Schema,
+    ) -> Self {
+        let limiter = ConcurrencyLimiter::new(&config.resolver_concurrency_limits);
+        let store: Arc<dyn Cache> = Arc::new(RedisCache::new(metrics.instrument(redis.clone())));
+        #[cfg(feature = "async-graphql")]
+        let async_schema = build_async_schema();
+        #[cfg(not(feature = "async-graphql"))]
+        let schema_document = SchemaDocument::new(schema_language(&schema));
+        #[cfg(feature = "async-graphql")]
+        let schema_document = SchemaDocument::new(async_schema.sdl());
+        Self {
+            config,
+            db,
+            redis,
+            regions: Regions::default(),
+            #[cfg(feature = "email")]
+            mailer,
+            metrics,
+            alert_counters: AlertCounters::default(),
+            caches: Caches::default(),
+            cache: store.clone(),
+            store,
+            limiter,
+            events: EventPublisher::new(),
+            files,
+            lifecycle: Lifecycle::default(),
+            domains: DomainRegistry::default(),
+            schema: Arc::new(schema),
+            schema_document: Arc::new(schema_document),
+            #[cfg(feature = "async-graphql")]
+            async_schema,
+        }
+    }
+
+    /// Count what happens for alerts with counters shared with other parts of the server, such as
+    /// the mailer.
+    pub fn with_alert_counters(mut self, alert_counters: AlertCounters) -> Self {
+        self.alert_counters = alert_counters;
+        self
+    }
+
+    /// Replicate session writes to the Redis databases of other regions.
+    pub fn with_regions(mut self, regions: Regions) -> Self {
+        self.regions = regions;
+        self
+    }
+
+    /// Keep cached results in a cache other than the Redis database.
+    pub fn with_cache(mut self, cache: Arc<dyn Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Keep rate limit counters, login lockouts and verification codes somewhere other than the
+    /// Redis database. The store must be shared by every server instance.
+    pub fn with_store(mut self, store: Arc<dyn Cache>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Run the hooks of a lifecycle's subsystems when the server starts and shuts down.
+    pub fn with_lifecycle(mut self, lifecycle: Lifecycle) -> Self {
+        self.lifecycle = lifecycle;
+        self
     }
 }
@@ -0,0 +1,506 @@
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error, Result};
+use async_std::fs;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tide::http::{Method, Url};
+use tide::{Body, Request, Response, StatusCode};
+
+use crate::config::Config;
+use crate::routes::{RouteModule, Router};
+use crate::state::State;
+
+/// Path prefix private files are downloaded from.
+pub const DOWNLOAD_ROUTE_PREFIX: &str = "/files";
+/// Error message returned for download URLs that have expired or have an invalid signature.
+pub const INVALID_SIGNATURE_ERROR_MESSAGE: &str = "The download URL is invalid or has expired.";
+/// Error message returned for download URLs pointing at files that don't exist.
+pub const FILE_NOT_FOUND_ERROR_MESSAGE: &str = "The requested file does not exist.";
+/// Format of the timestamps requests to S3 are signed with.
+const S3_DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+/// Headers included in the signature of requests to S3.
+const S3_SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+/// Decides where stored files are kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// Files are kept under the storage directory on the server's disk.
+    #[default]
+    Local,
+    /// Files are kept in a bucket of an S3-compatible object store.
+    S3,
+}
+
+impl FromStr for StorageBackend {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "local" => Ok(StorageBackend::Local),
+            "s3" => Ok(StorageBackend::S3),
+            _ => Err(anyhow!("Unknown storage backend: {}", value)),
+        }
+    }
+}
+
+/// Somewhere private files can be stored. Implement this to store files somewhere other than the
+/// local disk or S3.
+#[async_trait]
+pub trait FileStore: Send + Sync {
+    /// A short name for the store, used when logging.
+    fn name(&self) -> &'static str;
+
+    /// Store a file at a path, replacing any file already stored there. Paths are relative to the
+    /// root of the store and must be valid as checked by `is_valid_path`.
+    async fn put(&self, path: &str, contents: &[u8], content_type: &str) -> Result<()>;
+
+    /// Read the file stored at a path. This will return none if no file is stored there.
+    async fn get(&self, path: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Delete the file stored at a path. Deleting a file that doesn't exist is not an error.
+    async fn delete(&self, path: &str) -> Result<()>;
+
+    /// Get a URL the file stored at a path can be downloaded from until it expires. This will
+    /// return none if files can't be downloaded from the store or the path is invalid.
+    fn url(&self, path: &str) -> Option<String>;
+}
+
+/// Specifies if a path is a valid relative path to a private file. Paths may only contain letters,
+/// digits, dashes, underscores, dots and slashes, and can't escape the storage directory.
+pub fn is_valid_path(path: &str) -> bool {
+    !path.is_empty()
+        && path.split('/').all(|component| {
+            !component.is_empty()
+                && component != "."
+                && component != ".."
+                && component
+                    .chars()
+                    .all(|character| character.is_ascii_alphanumeric() || "-_.".contains(character))
+        })
+}
+
+/// Compute the HMAC-SHA256 of a file path and expiry date with a secret.
+fn mac(secret: &str, path: &str, expires: i64) -> Hmac<Sha256> {
+    // HMAC accepts keys of any length, so this can't fail.
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("Invalid HMAC key.");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+/// Sign a file path and expiry date, given as a Unix timestamp, with a secret. The signature is
+/// encoded as URL-safe base64.
+pub fn sign(secret: &str, path: &str, expires: i64) -> String {
+    base64::encode_config(
+        mac(secret, path, expires).finalize().into_bytes(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Check that a signature was produced by signing a file path and expiry date with a secret, and
+/// that the expiry date hasn't passed.
+pub fn verify(secret: &str, path: &str, expires: i64, signature: &str) -> bool {
+    if expires < Utc::now().timestamp() {
+        return false;
+    }
+
+    match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+        // This compares the signatures in constant time.
+        Ok(signature) => mac(secret, path, expires).verify(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Generate a URL, relative to the server, that allows a file to be downloaded for a number of
+/// seconds, signed with a secret.
+fn signed_path(secret: &str, path: &str, expiration_seconds: u32) -> String {
+    let expires = Utc::now().timestamp() + i64::from(expiration_seconds);
+    format!(
+        "{}/{}?expires={}&signature={}",
+        DOWNLOAD_ROUTE_PREFIX,
+        path,
+        expires,
+        sign(secret, path, expires)
+    )
+}
+
+/// Generate a signed URL, relative to the server, that allows a private file to be downloaded
+/// until the configured expiration time passes. The path is relative to the storage directory.
+/// This will return none if signed URLs aren't configured or the path is invalid.
+pub fn signed_url(config: &Config, path: &str) -> Option<String> {
+    let secret = config.storage_url_secret.as_ref()?;
+    if !is_valid_path(path) {
+        return None;
+    }
+
+    Some(signed_path(
+        secret,
+        path,
+        config.signed_url_expiration_seconds,
+    ))
+}
+
+/// Stores files under the storage directory on the server's disk. Files are downloaded through
+/// signed URLs served by the storage route module.
+pub struct LocalFileStore {
+    directory: PathBuf,
+    url_secret: Option<String>,
+    url_expiration_seconds: u32,
+}
+
+impl LocalFileStore {
+    /// Create a store keeping files in the configured storage directory.
+    pub fn new(config: &Config) -> Self {
+        LocalFileStore {
+            directory: PathBuf::from(&config.storage_path),
+            url_secret: config.storage_url_secret.clone(),
+            url_expiration_seconds: config.signed_url_expiration_seconds,
+        }
+    }
+
+    /// Get the location of a file on disk, checking its path can't escape the storage directory.
+    fn file(&self, path: &str) -> Result<PathBuf> {
+        if !is_valid_path(path) {
+            return Err(anyhow!("Invalid file path: {}", path));
+        }
+
+        Ok(self.directory.join(path))
+    }
+}
+
+#[async_trait]
+impl FileStore for LocalFileStore {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn put(&self, path: &str, contents: &[u8], _content_type: &str) -> Result<()> {
+        let file = self.file(path)?;
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        fs::write(&file, contents).await?;
+        Ok(())
+    }
+
+    async fn get(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.file(path)?).await {
+            Ok(contents) => Ok(Some(contents)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn delete(&self, path: &str) -> Result<()> {
+        match fs::remove_file(self.file(path)?).await {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn url(&self, path: &str) -> Option<String> {
+        let secret = self.url_secret.as_ref()?;
+        if !is_valid_path(path) {
+            return None;
+        }
+
+        Some(signed_path(secret, path, self.url_expiration_seconds))
+    }
+}
+
+/// Compute the HMAC-SHA256 of a message with a key.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length, so this can't fail.
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("Invalid HMAC key.");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Encode bytes as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent-encode a query string value as S3 expects, leaving only unreserved characters as is.
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Stores files in a bucket of an S3-compatible object store, such as AWS S3 or MinIO. Requests
+/// are signed with AWS Signature Version 4 and address objects by path, so any bucket name works.
+/// Files are downloaded through presigned URLs pointing at the object store.
+pub struct S3FileStore {
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    url_expiration_seconds: u32,
+}
+
+impl S3FileStore {
+    /// Create a store keeping files in the configured S3 bucket. This will return an error if any
+    /// of the details needed to connect to the bucket are missing.
+    pub fn new(config: &Config) -> Result<Self> {
+        let required = |value: &Option<String>, name: &str| {
+            value
+                .clone()
+                .ok_or_else(|| anyhow!("{} must be set to store files in S3.", name))
+        };
+
+        Ok(S3FileStore {
+            endpoint: Url::parse(&required(&config.s3_endpoint, "S3_ENDPOINT")?)?,
+            bucket: required(&config.s3_bucket, "S3_BUCKET")?,
+            region: required(&config.s3_region, "S3_REGION")?,
+            access_key_id: required(&config.s3_access_key_id, "S3_ACCESS_KEY_ID")?,
+            secret_access_key: required(&config.s3_secret_access_key, "S3_SECRET_ACCESS_KEY")?,
+            // Presigned URLs can't be valid for more than a week.
+            url_expiration_seconds: config.signed_url_expiration_seconds.min(7 * 24 * 60 * 60),
+        })
+    }
+
+    /// Get the URL of the object stored at a path.
+    fn object_url(&self, path: &str) -> Url {
+        let mut url = self.endpoint.clone();
+        url.set_path(&format!("{}/{}", self.bucket, path));
+        url
+    }
+
+    /// Get the host requests are sent to, including the port if it isn't the default one.
+    fn host(&self) -> String {
+        let host = self.endpoint.host_str().unwrap_or_default();
+        match self.endpoint.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.into(),
+        }
+    }
+
+    /// Get the scope of credentials used to sign requests at a time.
+    fn scope(&self, time: DateTime<Utc>) -> String {
+        format!("{}/{}/s3/aws4_request", time.format("%Y%m%d"), self.region)
+    }
+
+    /// Sign a canonical request made at a time, as described by AWS Signature Version 4.
+    fn sign(&self, time: DateTime<Utc>, canonical_request: &str) -> String {
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            time.format(S3_DATE_TIME_FORMAT),
+            self.scope(time),
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let date = time.format("%Y%m%d").to_string();
+        let key = [date.as_str(), &self.region, "s3", "aws4_request"]
+            .iter()
+            .fold(
+                format!("AWS4{}", self.secret_access_key).into_bytes(),
+                |key, part| hmac_sha256(&key, part.as_bytes()),
+            );
+
+        hex(&hmac_sha256(&key, string_to_sign.as_bytes()))
+    }
+
+    /// Send a signed request for the object stored at a path. This will return the body of the
+    /// response, none if the object store responds that the object doesn't exist, or an error if
+    /// it responds with any other unsuccessful status.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<(&[u8], &str)>,
+    ) -> Result<Option<Vec<u8>>> {
+        if !is_valid_path(path) {
+            return Err(anyhow!("Invalid file path: {}", path));
+        }
+
+        let url = self.object_url(path);
+        let time = Utc::now();
+        let date_time = time.format(S3_DATE_TIME_FORMAT).to_string();
+        let contents = body.map(|(contents, _)| contents).unwrap_or_default();
+        let payload_hash = format!("{:x}", Sha256::digest(contents));
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            url.path(),
+            self.host(),
+            payload_hash,
+            date_time,
+            S3_SIGNED_HEADERS,
+            payload_hash
+        );
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id,
+            self.scope(time),
+            S3_SIGNED_HEADERS,
+            self.sign(time, &canonical_request)
+        );
+
+        let mut request = surf::Request::builder(method, url)
+            .header("Authorization", authorization)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", date_time);
+        if let Some((contents, content_type)) = body {
+            request = request
+                .header("Content-Type", content_type)
+                .body(contents.to_vec());
+        }
+        let mut response = surf::client()
+            .send(request)
+            .await
+            .map_err(|error| error.into_inner())?;
+        if response.status() == StatusCode::NotFound {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "S3 request to {} {} failed with status {}",
+                method,
+                path,
+                response.status()
+            ));
+        }
+
+        let contents = response
+            .body_bytes()
+            .await
+            .map_err(|error| error.into_inner())?;
+        Ok(Some(contents))
+    }
+}
+
+#[async_trait]
+impl FileStore for S3FileStore {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    async fn put(&self, path: &str, contents: &[u8], content_type: &str) -> Result<()> {
+        self.send(Method::Put, path, Some((contents, content_type)))
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        self.send(Method::Get, path, None).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<()> {
+        // S3 responds with a success status for objects that don't exist.
+        self.send(Method::Delete, path, None).await?;
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> Option<String> {
+        if !is_valid_path(path) {
+            return None;
+        }
+
+        let mut url = self.object_url(path);
+        let time = Utc::now();
+        // Parameters must be sorted by name, as they are in the canonical request.
+        let query = format!(
+            "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential={}&X-Amz-Date={}&X-Amz-Expires={}&X-Amz-SignedHeaders=host",
+            uri_encode(&format!("{}/{}", self.access_key_id, self.scope(time))),
+            time.format(S3_DATE_TIME_FORMAT),
+            self.url_expiration_seconds
+        );
+        let canonical_request = format!(
+            "GET\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD",
+            url.path(),
+            query,
+            self.host()
+        );
+        let signature = self.sign(time, &canonical_request);
+        url.set_query(Some(&format!("{}&X-Amz-Signature={}", query, signature)));
+
+        Some(url.to_string())
+    }
+}
+
+/// Get the file store defined by the server configuration. This will return an error if S3 is
+/// selected without the details needed to connect to it.
+pub fn file_store(config: &Config) -> Result<Arc<dyn FileStore>> {
+    Ok(match config.storage_backend {
+        StorageBackend::Local => Arc::new(LocalFileStore::new(config)),
+        StorageBackend::S3 => Arc::new(S3FileStore::new(config)?),
+    })
+}
+
+/// Route module serving private files through signed URLs. This is only enabled if a secret for
+/// signing URLs is configured.
+pub struct StorageModule;
+
+impl RouteModule for StorageModule {
+    fn name(&self) -> &'static str {
+        "storage"
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        config.storage_url_secret.is_some()
+    }
+
+    fn mount(&self, router: &mut dyn Router) {
+        router.route(Method::Get, "/files/*path", Arc::new(download));
+    }
+}
+
+/// Query parameters of a signed download URL.
+#[derive(Deserialize)]
+struct DownloadQuery {
+    expires: i64,
+    signature: String,
+}
+
+/// Download a private file through a signed URL. Files are only served if the URL's signature is
+/// valid and it hasn't expired.
+async fn download(request: Request<State>) -> tide::Result {
+    let forbidden =
+        || tide::Error::from_str(StatusCode::Forbidden, INVALID_SIGNATURE_ERROR_MESSAGE);
+    let config = &request.state().config;
+    let secret = config.storage_url_secret.clone().unwrap_or_default();
+    let path = request.param("path")?;
+    let DownloadQuery { expires, signature } = request.query().map_err(|_| forbidden())?;
+    if !is_valid_path(path) || !verify(&secret, path, expires, &signature) {
+        return Err(forbidden());
+    }
+
+    let file = PathBuf::from(&config.storage_path).join(path);
+    let is_file = fs::metadata(&file)
+        .await
+        .map(|metadata| metadata.is_file())
+        .unwrap_or(false);
+    if !is_file {
+        return Err(tide::Error::from_str(
+            StatusCode::NotFound,
+            FILE_NOT_FOUND_ERROR_MESSAGE,
+        ));
+    }
+
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let response = Response::builder(StatusCode::Ok)
+        .header("Cache-Control", "private, no-store")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", name),
+        )
+        .body(Body::from_file(&file).await?);
+
+    Ok(response.build())
+}
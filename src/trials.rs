@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use async_std::task;
+use tide::log;
+
+use crate::executor::Executor;
+use crate::graphql::request_id;
+use crate::state::State;
+
+/// How many days before a trial ends reminder emails are sent, closest to the end first.
+const REMINDER_DAYS: [i32; 2] = [1, 7];
+/// How long to wait between checks for trials that are ending soon.
+const REMINDER_POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically send reminder emails to users whose trial is ending soon. Each check is handled
+/// as its own request, so the emails it sends can be traced back to its log lines. This runs until
+/// the server stops.
+pub async fn run_reminder_worker(state: State) {
+    loop {
+        let executor = Executor::new(state.clone(), request_id(None), None, None);
+        for days in REMINDER_DAYS.iter().copied() {
+            match executor.send_trial_reminders(days).await {
+                Ok(0) => {}
+                Ok(count) => log::info!(
+                    "Reminded {} users of trials ending within {} days (request {})",
+                    count,
+                    days,
+                    executor.request_id()
+                ),
+                Err(error) => log::error!("Failed to send trial reminders: {}", error),
+            }
+        }
+
+        task::sleep(REMINDER_POLL_INTERVAL).await;
+    }
+}
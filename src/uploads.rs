@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use anyhow::{anyhow, Result};
+use futures::stream;
+use juniper::{graphql_scalar, ParseScalarResult, ParseScalarValue, Value};
+use serde_json::Value as JsonValue;
+
+/// Message returned for multipart requests larger than the configured limit.
+pub const UPLOAD_TOO_LARGE_ERROR_MESSAGE: &str = "The request is too large.";
+/// Error code returned for file arguments that weren't sent with the request.
+pub const FILE_NOT_UPLOADED_ERROR_CODE: &str = "file-not-uploaded";
+/// Error message returned for file arguments that weren't sent with the request.
+pub const FILE_NOT_UPLOADED_ERROR_MESSAGE: &str =
+    "The file was not uploaded. Files must be sent in a multipart request.";
+
+/// A file uploaded along with a GraphQL request.
+#[derive(Debug, Clone)]
+pub struct UploadedFile {
+    /// The name of the file on the client, if it was sent.
+    pub file_name: Option<String>,
+    /// The content type the client sent the file with, if any. Clients can send any content type,
+    /// so this shouldn't be trusted.
+    pub content_type: Option<String>,
+    /// The contents of the file.
+    pub contents: Vec<u8>,
+}
+
+/// Files uploaded along with a GraphQL request, by the name of the multipart field they were sent
+/// in.
+pub type Uploads = HashMap<String, UploadedFile>;
+
+/// A file argument of a GraphQL field. This holds the name of the multipart field the file was
+/// sent in, which can be looked up in the request's uploads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upload(pub String);
+
+#[graphql_scalar(
+    description = "A file sent in a multipart request, as described by the GraphQL multipart
+    request specification."
+)]
+impl<S> GraphQLScalar for Upload
+where
+    S: ScalarValue,
+{
+    fn resolve(&self) -> Value {
+        Value::scalar(self.0.clone())
+    }
+
+    fn from_input_value(value: &juniper::InputValue) -> Option<Upload> {
+        value.as_string_value().map(|name| Upload(name.into()))
+    }
+
+    fn from_str<'a>(value: ScalarToken<'a>) -> ParseScalarResult<'a, S> {
+        <String as ParseScalarValue<S>>::from_str(value)
+    }
+}
+
+/// Specifies if a request with a content type is a multipart request that may upload files.
+pub fn is_multipart(content_type: &str) -> bool {
+    content_type
+        .to_ascii_lowercase()
+        .starts_with("multipart/form-data")
+}
+
+/// Set the value at a dot-separated path in a JSON value, such as "variables.file" or
+/// "variables.files.0". This will return an error if nothing exists at the path.
+fn set_path(value: &mut JsonValue, path: &str, new_value: JsonValue) -> Result<()> {
+    let target = path.split('.').try_fold(value, |value, key| match value {
+        JsonValue::Object(object) => object.get_mut(key),
+        JsonValue::Array(array) => key
+            .parse::<usize>()
+            .ok()
+            .and_then(move |index| array.get_mut(index)),
+        _ => None,
+    });
+    match target {
+        Some(target) => {
+            *target = new_value;
+            Ok(())
+        }
+        None => Err(anyhow!("Nothing exists at the path {}", path)),
+    }
+}
+
+/// Parse a multipart request body following the GraphQL multipart request specification. The
+/// "operations" field holds the GraphQL request, and the "map" field lists which of its variables
+/// each file is used for. Those variables are set to the names of the fields holding the files, so
+/// the `Upload` scalar can look them up. This will return the GraphQL request along with the files
+/// it uploads, or an error if the body isn't a valid multipart request.
+pub async fn parse_multipart(content_type: &str, body: Vec<u8>) -> Result<(Vec<u8>, Uploads)> {
+    let boundary = multer::parse_boundary(content_type)?;
+    let body = stream::once(async move { Ok::<_, Infallible>(body) });
+    let mut multipart = multer::Multipart::new(body, boundary);
+
+    let mut operations = None;
+    let mut map = None;
+    let mut files = Uploads::new();
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "operations" => operations = Some(serde_json::from_slice(&field.bytes().await?)?),
+            "map" => {
+                map = Some(serde_json::from_slice::<HashMap<String, Vec<String>>>(
+                    &field.bytes().await?,
+                )?)
+            }
+            _ => {
+                let file_name = field.file_name().map(String::from);
+                let content_type = field.content_type().map(|mime| mime.to_string());
+                let contents = field.bytes().await?.to_vec();
+                files.insert(
+                    name,
+                    UploadedFile {
+                        file_name,
+                        content_type,
+                        contents,
+                    },
+                );
+            }
+        }
+    }
+
+    let mut operations: JsonValue =
+        operations.ok_or_else(|| anyhow!("The operations field is missing"))?;
+    let map = map.ok_or_else(|| anyhow!("The map field is missing"))?;
+    let mut uploads = Uploads::new();
+    for (name, paths) in map {
+        let file = files
+            .remove(&name)
+            .ok_or_else(|| anyhow!("The file {} is missing", name))?;
+        for path in paths {
+            set_path(&mut operations, &path, JsonValue::String(name.clone()))?;
+        }
+        uploads.insert(name, file);
+    }
+
+    Ok((serde_json::to_vec(&operations)?, uploads))
+}
+
+/// An image format avatars can be uploaded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl ImageFormat {
+    /// Detect the format of an image from its contents, ignoring whatever content type it was
+    /// uploaded with. This will return none if the contents aren't an image in a supported format.
+    pub fn detect(contents: &[u8]) -> Option<Self> {
+        if contents.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageFormat::Png)
+        } else if contents.starts_with(b"\xff\xd8\xff") {
+            Some(ImageFormat::Jpeg)
+        } else if contents.starts_with(b"GIF87a") || contents.starts_with(b"GIF89a") {
+            Some(ImageFormat::Gif)
+        } else if contents.len() >= 12 && &contents[..4] == b"RIFF" && &contents[8..12] == b"WEBP" {
+            Some(ImageFormat::Webp)
+        } else {
+            None
+        }
+    }
+
+    /// The extension files in the format are stored with.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Webp => "webp",
+        }
+    }
+
+    /// The content type of files in the format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use async_std::task;
+use chrono::{DateTime, Utc};
+use juniper::graphql_object;
+use redis::AsyncCommands;
+use tide::log;
+use uuid::Uuid;
+
+use crate::billing::Plan;
+use crate::config::Config;
+use crate::metrics::InstrumentedConnection;
+
+/// Redis key of the sorted set holding the user IDs with usage counted in the current billing
+/// period, scored by when the period ends.
+pub const USAGE_PERIODS_KEY: &str = "usage/periods";
+/// Header added to GraphQL responses when the user making the request is close to a usage limit.
+pub const QUOTA_WARNING_HEADER: &str = "X-Quota-Warning";
+/// Error message returned for requests made by users that have reached a usage limit.
+pub const QUOTA_EXCEEDED_ERROR_MESSAGE: &str =
+    "The API call limit of your plan has been reached for the current billing period.";
+/// Error code returned for requests made by users that have reached a usage limit.
+pub const QUOTA_EXCEEDED_ERROR_CODE: &str = "quota-exceeded";
+/// How long usage is counted for when a user has no active subscription with its own billing
+/// period.
+pub const DEFAULT_PERIOD_SECONDS: i64 = 30 * 24 * 60 * 60;
+/// How long to wait between checks for billing periods that have ended.
+const RESET_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Get the Redis key counting a user's API calls in the current billing period.
+pub fn api_calls_key(user_id: Uuid) -> String {
+    format!("usage/api-calls/{}", user_id)
+}
+
+/// How a user's usage compares to the limits of their plan. Statuses are ordered from least to
+/// most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QuotaStatus {
+    /// The user is within the limits of their plan, or isn't subject to any.
+    Within,
+    /// The user has passed the soft limit of their plan and should be warned.
+    SoftLimitExceeded,
+    /// The user has reached the hard limit of their plan. Requests are rejected until the billing
+    /// period resets or the user upgrades.
+    HardLimitExceeded,
+}
+
+/// Limits on the number of API calls a user can make per billing period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsageLimits {
+    /// Requests past this many API calls are sent with a warning header.
+    pub soft: u32,
+    /// Requests past this many API calls are rejected.
+    pub hard: u32,
+}
+
+impl UsageLimits {
+    /// Get the limits of a plan. This will return none if the plan has no limit on API calls.
+    pub fn for_plan(config: &Config, plan: Plan) -> Option<Self> {
+        let hard = plan.api_call_limit(config);
+        if hard == 0 {
+            return None;
+        }
+
+        let soft = u64::from(hard) * u64::from(config.usage_soft_limit_percent) / 100;
+        Some(UsageLimits {
+            soft: soft as u32,
+            hard,
+        })
+    }
+
+    /// Check how a number of API calls compares to the limits.
+    pub fn status(self, api_calls: u32) -> QuotaStatus {
+        if api_calls > self.hard {
+            QuotaStatus::HardLimitExceeded
+        } else if api_calls > self.soft {
+            QuotaStatus::SoftLimitExceeded
+        } else {
+            QuotaStatus::Within
+        }
+    }
+}
+
+/// A user's metered usage in the current billing period.
+#[derive(Debug, Clone)]
+pub struct Usage {
+    /// The plan the user is on.
+    pub plan: Plan,
+    /// The number of API calls made in the current billing period.
+    pub api_calls: u32,
+    /// The limits on API calls of the user's plan, if any.
+    pub limits: Option<UsageLimits>,
+    /// When the current billing period ends and usage is reset.
+    pub period_end: DateTime<Utc>,
+}
+
+#[graphql_object(description = "A user's metered usage in the current billing period.")]
+impl Usage {
+    #[graphql(description = "The plan the user is on.")]
+    pub fn plan(&self) -> Plan {
+        self.plan
+    }
+
+    #[graphql(description = "The number of API calls made in the current billing period.")]
+    pub fn api_calls(&self) -> i32 {
+        self.api_calls as i32
+    }
+
+    #[graphql(
+        description = "The number of API calls after which responses include a warning header.
+        This will be null if the plan has no limit on API calls."
+    )]
+    pub fn api_call_soft_limit(&self) -> Option<i32> {
+        self.limits.map(|limits| limits.soft as i32)
+    }
+
+    #[graphql(
+        description = "The number of API calls after which requests are rejected. This will be
+        null if the plan has no limit on API calls."
+    )]
+    pub fn api_call_limit(&self) -> Option<i32> {
+        self.limits.map(|limits| limits.hard as i32)
+    }
+
+    #[graphql(description = "Date when the current billing period ends and usage is reset.")]
+    pub fn period_end(&self) -> DateTime<Utc> {
+        self.period_end
+    }
+}
+
+/// Periodically reset the usage of users whose billing period has ended. This runs until the
+/// server stops.
+pub async fn run_reset_worker(redis: InstrumentedConnection) {
+    loop {
+        task::sleep(RESET_POLL_INTERVAL).await;
+        if let Err(error) = reset_ended_periods(redis.clone()).await {
+            log::error!("Failed to reset usage: {}", error);
+        }
+    }
+}
+
+/// Reset the usage of every user whose billing period has ended. A new period starts with the
+/// user's next API call.
+async fn reset_ended_periods(mut redis: InstrumentedConnection) -> Result<()> {
+    let user_ids = redis
+        .zrangebyscore::<&str, &str, i64, Vec<String>>(
+            USAGE_PERIODS_KEY,
+            "-inf",
+            Utc::now().timestamp(),
+        )
+        .await?;
+
+    for user_id in user_ids {
+        // Claim the period first, so usage isn't reset twice if several servers are running.
+        let claimed = redis
+            .zrem::<&str, &str, u32>(USAGE_PERIODS_KEY, &user_id)
+            .await?;
+        if claimed == 0 {
+            continue;
+        }
+
+        redis
+            .del::<String, ()>(api_calls_key(user_id.parse()?))
+            .await?;
+        log::info!("Reset usage for user: {}", user_id);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use chrono::{DateTime, Utc};
+use juniper::{graphql_object, GraphQLEnum, GraphQLInputObject};
+
+use crate::context::Context;
+use crate::models::User;
+
+/// Default number of users returned by a search.
+pub const DEFAULT_PAGE_SIZE: i32 = 20;
+/// Maximum number of users returned by a search.
+pub const MAX_PAGE_SIZE: i32 = 100;
+/// Maximum number of fields a search can be sorted by.
+pub const MAX_SORT_FIELDS: usize = 4;
+/// Maximum length of a username prefix or email domain searched for.
+pub const MAX_FILTER_LENGTH: usize = 255;
+/// Maximum length of a full-text search query.
+pub const MAX_QUERY_LENGTH: usize = 255;
+/// Maximum number of words a full-text search query can hold.
+pub const MAX_QUERY_TERMS: usize = 8;
+
+/// Conditions users must meet to be included in a search. Every condition that is set must be met.
+#[derive(GraphQLInputObject, Debug, Clone, Default)]
+#[graphql(
+    description = "Conditions users must meet to be included in a search. Every condition that is
+    set must be met."
+)]
+pub struct UserFilter {
+    #[graphql(description = "Only include users whose username starts with this, ignoring case.")]
+    pub username_prefix: Option<String>,
+    #[graphql(
+        description = "Only include users whose email address is at this domain, such as
+        \"example.com\", ignoring case."
+    )]
+    pub email_domain: Option<String>,
+    #[graphql(description = "Only include users that have, or haven't, verified their email.")]
+    pub email_verified: Option<bool>,
+    #[graphql(description = "Only include users created at or after this time.")]
+    pub created_after: Option<DateTime<Utc>>,
+    #[graphql(description = "Only include users created before this time.")]
+    pub created_before: Option<DateTime<Utc>>,
+    #[graphql(description = "Include users that have been deleted. Defaults to false.")]
+    pub include_deleted: Option<bool>,
+}
+
+/// Fields users can be sorted by in a search.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[graphql(description = "Fields users can be sorted by in a search.")]
+pub enum UserSortField {
+    #[graphql(description = "When the user was created.")]
+    CreatedAt,
+    #[graphql(description = "The user's username.")]
+    Username,
+    #[graphql(description = "The user's email address.")]
+    Email,
+    #[graphql(
+        description = "When the user verified their email. Users that haven't are sorted last."
+    )]
+    EmailVerifiedAt,
+}
+
+impl UserSortField {
+    /// The column users are sorted by.
+    pub fn column(self) -> &'static str {
+        match self {
+            UserSortField::CreatedAt => "created_at",
+            UserSortField::Username => "username",
+            UserSortField::Email => "email",
+            UserSortField::EmailVerifiedAt => "email_verified_at",
+        }
+    }
+}
+
+/// Directions results can be sorted in.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[graphql(description = "Directions results can be sorted in.")]
+pub enum SortDirection {
+    #[graphql(description = "Smallest, earliest or alphabetically first values first.")]
+    Asc,
+    #[graphql(description = "Largest, latest or alphabetically last values first.")]
+    Desc,
+}
+
+impl SortDirection {
+    /// The SQL keyword for the direction.
+    pub fn keyword(self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+/// A field to sort users by, and the direction to sort it in.
+#[derive(GraphQLInputObject, Debug, Clone, Copy, PartialEq, Eq)]
+#[graphql(description = "A field to sort users by, and the direction to sort it in.")]
+pub struct UserSort {
+    #[graphql(description = "The field to sort by.")]
+    pub field: UserSortField,
+    #[graphql(description = "The direction to sort in. Defaults to ascending.")]
+    pub direction: Option<SortDirection>,
+}
+
+/// Escape the characters "LIKE" patterns treat specially, so a value is matched literally.
+pub fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// A user matching a full-text search, along with a snippet showing what matched.
+#[derive(Debug, Clone)]
+pub struct UserSearchHit {
+    /// The user.
+    pub user: User,
+    /// The user's username and email address, with the words that matched highlighted.
+    pub snippet: String,
+}
+
+#[graphql_object(
+    context = Context,
+    description = "A user matching a full-text search, along with a snippet showing what matched."
+)]
+impl UserSearchHit {
+    #[graphql(description = "The user.")]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    #[graphql(
+        description = "The user's username and email address, as \"username <email>\", with the
+        parts of words that matched wrapped in \"<mark>\" tags. The rest of the snippet is
+        HTML-escaped, so it can be shown as HTML."
+    )]
+    pub fn snippet(&self) -> &str {
+        &self.snippet
+    }
+}
+
+/// Split a full-text search query into the lowercase words it searches for. Anything other than a
+/// letter or digit separates words, like it does in the documents users are searched by.
+pub fn query_terms(query: &str) -> Vec<String> {
+    query
+        .split(|character: char| !character.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Build the Postgres "tsquery" matching documents with a word starting with every term. Terms
+/// only hold letters and digits, so they never need quoting.
+pub fn ts_query(terms: &[String]) -> String {
+    terms
+        .iter()
+        .map(|term| format!("{}:*", term))
+        .collect::<Vec<_>>()
+        .join(" & ")
+}
+
+/// Build the snippet shown for a user matching a full-text search. The start of each word that
+/// matched a term is wrapped in "<mark>" tags, and everything else is HTML-escaped.
+pub fn snippet(user: &User, terms: &[String]) -> String {
+    let text = format!("{} <{}>", user.username, user.email);
+    let mut snippet = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(character) = rest.chars().next() {
+        let len = if character.is_alphanumeric() {
+            rest.find(|character: char| !character.is_alphanumeric())
+                .unwrap_or(rest.len())
+        } else {
+            character.len_utf8()
+        };
+        let (word, remainder) = rest.split_at(len);
+        rest = remainder;
+
+        // Highlight as much of the word as the longest term it starts with.
+        let matched = terms
+            .iter()
+            .filter(|term| word.to_lowercase().starts_with(term.as_str()))
+            .map(|term| term.chars().count())
+            .max();
+        match matched {
+            Some(count) => {
+                let end = word
+                    .char_indices()
+                    .nth(count)
+                    .map_or(word.len(), |(index, _)| index);
+                snippet.push_str("<mark>");
+                snippet.push_str(&escape_html(&word[..end]));
+                snippet.push_str("</mark>");
+                snippet.push_str(&escape_html(&word[end..]));
+            }
+            None => snippet.push_str(&escape_html(word)),
+        }
+    }
+
+    snippet
+}
+
+/// Escape the characters HTML treats specially, so text is shown as-is.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,214 @@
+use anyhow::Result;
+use async_std_resolver::proto::op::ResponseCode;
+use async_std_resolver::resolver_from_system_conf;
+use email_address::EmailAddress;
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+
+use crate::schema::InvalidInput;
+
+/// Minimum number of characters in a username.
+pub const MIN_USERNAME_LENGTH: usize = 3;
+/// Maximum number of characters in a username.
+pub const MAX_USERNAME_LENGTH: usize = 32;
+/// Maximum number of characters in an email address, as limited by the SMTP specification.
+pub const MAX_EMAIL_LENGTH: usize = 254;
+/// Message of the error returned when a one-of input object doesn't have exactly one field set.
+pub const ONE_OF_ERROR_MESSAGE: &str = "Exactly one field of the input must be set.";
+/// Error code returned when a one-of input object doesn't have exactly one field set.
+pub const ONE_OF_ERROR_CODE: &str = "invalid-one-of";
+/// Input objects that must have exactly one field set, like a union of inputs. These are marked
+/// with the "@oneOf" directive in the schema language, and validated with `one_of`.
+pub const ONE_OF_INPUT_OBJECTS: &[&str] = &["LoginCredentials"];
+/// Usernames that can't be taken, as they could be mistaken for the server's staff or routes.
+/// Usernames are compared case-insensitively.
+const RESERVED_USERNAMES: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "api",
+    "billing",
+    "graphql",
+    "help",
+    "hostmaster",
+    "me",
+    "moderator",
+    "no-reply",
+    "noreply",
+    "null",
+    "postmaster",
+    "root",
+    "security",
+    "staff",
+    "support",
+    "system",
+    "undefined",
+    "webmaster",
+];
+
+/// Check if a character can be used in a username.
+fn is_username_character(character: char) -> bool {
+    character.is_ascii_alphanumeric() || is_username_separator(character)
+}
+
+/// Check if a character separates the words of a username.
+fn is_username_separator(character: char) -> bool {
+    matches!(character, '_' | '-' | '.')
+}
+
+/// Get the only field set on a one-of input object, given each of its fields converted to a common
+/// type. This will return an error if no fields or more than one field are set.
+pub fn one_of<T, const N: usize>(fields: [Option<T>; N]) -> Result<T, InvalidInput> {
+    let mut set = IntoIterator::into_iter(fields).flatten();
+    match (set.next(), set.next()) {
+        (Some(field), None) => Ok(field),
+        _ => Err(InvalidInput {
+            message: ONE_OF_ERROR_MESSAGE.into(),
+            code: ONE_OF_ERROR_CODE,
+        }),
+    }
+}
+
+/// Validate the format of a username. Usernames are 3 to 32 ASCII letters, digits, underscores,
+/// hyphens and periods, starting and ending with a letter or digit, without separators next to
+/// each other, and can't be reserved. This will return the first problem found with the username,
+/// or none if it is valid.
+pub fn validate_username(username: &str) -> Option<InvalidInput> {
+    if username.is_empty() {
+        return Some(InvalidInput {
+            message: "Username cannot be empty.".into(),
+            code: "username-empty",
+        });
+    }
+
+    let length = username.chars().count();
+    if length < MIN_USERNAME_LENGTH {
+        return Some(InvalidInput {
+            message: "Username must be at least 3 characters long.".into(),
+            code: "username-too-short",
+        });
+    }
+    if length > MAX_USERNAME_LENGTH {
+        return Some(InvalidInput {
+            message: "Username cannot be longer than 32 characters.".into(),
+            code: "username-too-long",
+        });
+    }
+
+    if !username.chars().all(is_username_character) {
+        return Some(InvalidInput {
+            message: "Username can only contain letters, digits, underscores, hyphens and periods."
+                .into(),
+            code: "username-invalid-characters",
+        });
+    }
+
+    let starts_or_ends_with_separator =
+        username.starts_with(is_username_separator) || username.ends_with(is_username_separator);
+    let has_adjacent_separators = username
+        .chars()
+        .zip(username.chars().skip(1))
+        .any(|(first, second)| is_username_separator(first) && is_username_separator(second));
+    if starts_or_ends_with_separator || has_adjacent_separators {
+        return Some(InvalidInput {
+            message: "Username must start and end with a letter or digit, and cannot have \
+            underscores, hyphens or periods next to each other."
+                .into(),
+            code: "username-invalid-format",
+        });
+    }
+
+    let lowercase = username.to_ascii_lowercase();
+    if RESERVED_USERNAMES.contains(&lowercase.as_str()) {
+        return Some(InvalidInput {
+            message: "Username is reserved.".into(),
+            code: "username-reserved",
+        });
+    }
+
+    None
+}
+
+/// Validate the syntax of an email address according to RFC 5322. Addresses must also be
+/// deliverable over the internet, so their domain must have at least two labels. This will return
+/// the first problem found with the address, or none if it is valid.
+pub fn validate_email(email: &str) -> Option<InvalidInput> {
+    if email.is_empty() {
+        return Some(InvalidInput {
+            message: "Email cannot be empty.".into(),
+            code: "email-empty",
+        });
+    }
+
+    if email.chars().count() > MAX_EMAIL_LENGTH {
+        return Some(InvalidInput {
+            message: "Email cannot be longer than 254 characters.".into(),
+            code: "email-too-long",
+        });
+    }
+
+    let address = match email.parse::<EmailAddress>() {
+        Ok(address) => address,
+        Err(_) => {
+            return Some(InvalidInput {
+                message: "Email is not a valid email address.".into(),
+                code: "email-invalid",
+            })
+        }
+    };
+
+    // Addresses such as "user@localhost" or "user@[127.0.0.1]" are valid, but can't be reached.
+    let domain = address.domain();
+    if domain.starts_with('[') || !domain.contains('.') || domain.ends_with('.') {
+        return Some(InvalidInput {
+            message: "Email must use an internet domain name.".into(),
+            code: "email-invalid-domain",
+        });
+    }
+
+    None
+}
+
+/// Check that the domain of a valid email address can receive email, meaning it has MX records
+/// or, failing that, an address record to fall back to as RFC 5321 allows. This will return an
+/// error if the lookup itself failed, such as when DNS servers can't be reached, so callers can
+/// decide whether to let the address through.
+pub async fn validate_email_domain(email: &str) -> Result<Option<InvalidInput>> {
+    let domain = match email.rsplit_once('@') {
+        Some((_, domain)) => format!("{}.", domain),
+        None => return Ok(None),
+    };
+
+    let resolver = resolver_from_system_conf().await?;
+    let has_mail_server = match resolver.mx_lookup(domain.as_str()).await {
+        Ok(lookup) => lookup.iter().next().is_some(),
+        Err(error) if is_no_records(&error) => false,
+        Err(error) => return Err(error.into()),
+    };
+    let has_address = has_mail_server
+        || match resolver.lookup_ip(domain.as_str()).await {
+            Ok(lookup) => lookup.iter().next().is_some(),
+            Err(error) if is_no_records(&error) => false,
+            Err(error) => return Err(error.into()),
+        };
+
+    if !has_address {
+        return Ok(Some(InvalidInput {
+            message: "Email domain cannot receive email.".into(),
+            code: "email-domain-unreachable",
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Check if a DNS lookup failed because the domain has no records of the requested type or
+/// doesn't exist. Servers that failed or refused to answer don't count, as the domain may exist.
+pub fn is_no_records(error: &ResolveError) -> bool {
+    matches!(
+        error.kind(),
+        ResolveErrorKind::NoRecordsFound {
+            response_code: ResponseCode::NoError | ResponseCode::NXDomain,
+            ..
+        }
+    )
+}
@@ -0,0 +1,84 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use futures::future::try_join_all;
+use juniper::parser::parse_document_source;
+use sqlx::{query, PgPool};
+use tide::log;
+use uuid::Uuid;
+
+use crate::executor::Executor;
+use crate::graphql::request_id;
+use crate::persisted_queries::registered_queries;
+use crate::state::State;
+
+/// Prepare the server for traffic before it starts listening, so the first requests after a
+/// deploy aren't slowed down by connecting to the databases or preparing statements. This will
+/// return an error if a database can't be reached, in which case the server shouldn't start.
+pub async fn warm_up(state: &State) -> Result<()> {
+    let start = Instant::now();
+    let count = state
+        .config
+        .warmup_connection_count
+        .min(state.config.database_max_connection_count);
+
+    open_connections(&state.db, count).await?;
+    redis::cmd("PING")
+        .query_async::<_, ()>(&mut state.redis.clone())
+        .await?;
+    try_join_all((0..count).map(|_| prepare_statements(state))).await?;
+    let queries = check_persisted_queries(state).await?;
+
+    log::info!(
+        "Warmed up {} database connections and {} persisted queries in {}ms.",
+        count,
+        queries,
+        start.elapsed().as_millis()
+    );
+
+    Ok(())
+}
+
+/// Open connections to the Postgres database until the pool holds a number of them. Every
+/// connection is held until all are open, so the pool can't hand the same one out twice.
+async fn open_connections(db: &PgPool, count: u32) -> Result<()> {
+    let mut connections = try_join_all((0..count).map(|_| db.acquire())).await?;
+    for connection in &mut connections {
+        query!("SELECT 1 AS one").fetch_one(connection).await?;
+    }
+
+    Ok(())
+}
+
+/// Run the lookups made by most requests, so the connection they run on has their statements
+/// prepared. Nothing is found, as they look up a user that doesn't exist.
+async fn prepare_statements(state: &State) -> Result<()> {
+    let executor = Executor::new(state.clone(), request_id(None), None, None);
+    let id = Uuid::nil();
+    executor.find_user(id).await?;
+    executor.find_user_by_username("").await?;
+    executor.find_users_by_ids(&[id]).await?;
+    executor.find_user_roles(id).await?;
+    executor.find_subscription(id).await?;
+    executor.find_trial(id).await?;
+
+    Ok(())
+}
+
+/// Parse every registered persisted query against the schema, so a query that can't be parsed is
+/// reported at startup rather than when a client first sends it. This will return the number of
+/// registered queries.
+async fn check_persisted_queries(state: &State) -> Result<usize> {
+    let queries = registered_queries(&mut state.redis.clone()).await?;
+    for query in &queries {
+        if let Err(error) = parse_document_source(query, &state.schema.schema) {
+            log::warn!(
+                "Registered persisted query can't be parsed: {}: {}",
+                error,
+                query
+            );
+        }
+    }
+
+    Ok(queries.len())
+}
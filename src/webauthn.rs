@@ -0,0 +1,406 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+use ciborium::value::Value as CborValue;
+use rand::RngCore;
+use ring::signature::{
+    RsaPublicKeyComponents, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, RSA_PKCS1_2048_8192_SHA256,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config::Config;
+
+/// The COSE algorithm identifier for ECDSA with P-256 and SHA-256.
+const ES256: i128 = -7;
+/// The COSE algorithm identifier for RSASSA-PKCS1-v1_5 with SHA-256.
+const RS256: i128 = -257;
+/// Set in the authenticator data flags when the user was present, e.g. touched the authenticator.
+const USER_PRESENT_FLAG: u8 = 0x01;
+/// Set in the authenticator data flags when it includes a newly created credential.
+const ATTESTED_CREDENTIAL_DATA_FLAG: u8 = 0x40;
+/// The length of the RP ID hash, flags and sign count that start all authenticator data.
+const AUTHENTICATOR_DATA_HEADER_LENGTH: usize = 37;
+/// The length of the AAGUID identifying the model of authenticator a credential was created on.
+const AAGUID_LENGTH: usize = 16;
+
+/// What a challenge was issued for. Each challenge can only be used for the ceremony it was issued
+/// for, and only once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ceremony {
+    /// Creating a passkey for the user with the given ID.
+    Registration(Uuid),
+    /// Logging in with a passkey.
+    Login,
+}
+
+impl Display for Ceremony {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        match self {
+            Ceremony::Registration(user_id) => write!(f, "registration/{}", user_id),
+            Ceremony::Login => write!(f, "login"),
+        }
+    }
+}
+
+impl FromStr for Ceremony {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value.split_once('/') {
+            Some(("registration", user_id)) => Ok(Ceremony::Registration(user_id.parse()?)),
+            None if value == "login" => Ok(Ceremony::Login),
+            _ => Err(anyhow!("Invalid WebAuthn ceremony: {}", value)),
+        }
+    }
+}
+
+/// A newly created passkey, checked against the challenge it was created for.
+#[derive(Debug, Clone)]
+pub struct Registration {
+    /// The challenge the client signed.
+    pub challenge: String,
+    /// The base64url-encoded ID the authenticator assigned to the credential.
+    pub credential_id: String,
+    /// The COSE-encoded public key of the credential.
+    pub public_key: Vec<u8>,
+    /// The number of times the credential has been used, according to the authenticator.
+    pub sign_count: u32,
+}
+
+/// An attempt to log in with a passkey. The signature still has to be checked against the public
+/// key stored for the credential.
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    /// The challenge the client signed.
+    pub challenge: String,
+    /// The base64url-encoded ID of the credential used.
+    pub credential_id: String,
+    /// The ID of the user the credential was created for, if the authenticator sent it.
+    pub user_handle: Option<Uuid>,
+    /// The number of times the credential has been used, according to the authenticator. This is
+    /// zero for authenticators that don't count.
+    pub sign_count: u32,
+    /// The authenticator data followed by the hash of the client data, which is what is signed.
+    signed_data: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Assertion {
+    /// Check that the assertion was signed with the private key of a credential.
+    pub fn verify(&self, public_key: &[u8]) -> Result<()> {
+        verify_signature(public_key, &self.signed_data, &self.signature)
+    }
+}
+
+/// A credential sent by the client, as serialized by "PublicKeyCredential.toJSON()".
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Credential<T> {
+    raw_id: String,
+    response: T,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    attestation_object: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+    user_handle: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// The parts of authenticator data the server checks.
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    /// The ID and COSE-encoded public key of a newly created credential.
+    attested_credential: Option<(&'a [u8], &'a [u8])>,
+}
+
+/// Generate a random challenge for a client to sign.
+pub fn generate_challenge() -> String {
+    let mut challenge = [0; 32];
+    rand::thread_rng().fill_bytes(&mut challenge);
+    encode(&challenge)
+}
+
+/// Create the key a challenge can be stored under in the Redis database.
+pub fn challenge_key(challenge: &str) -> String {
+    format!("webauthn/challenge/{}", challenge)
+}
+
+/// Get the options to pass to "navigator.credentials.create()" to create a passkey for a user.
+/// Credentials the user already has are excluded, so an authenticator isn't registered twice.
+pub fn registration_options(
+    config: &Config,
+    challenge: &str,
+    user_id: Uuid,
+    username: &str,
+    existing_credential_ids: &[String],
+) -> Value {
+    json!({
+        "challenge": challenge,
+        "rp": {
+            "id": config.webauthn_rp_id,
+            "name": config.webauthn_rp_name,
+        },
+        "user": {
+            "id": encode(user_id.as_bytes()),
+            "name": username,
+            "displayName": username,
+        },
+        "pubKeyCredParams": [
+            { "type": "public-key", "alg": ES256 as i64 },
+            { "type": "public-key", "alg": RS256 as i64 },
+        ],
+        "timeout": config.webauthn_challenge_expiration_seconds * 1000,
+        "excludeCredentials": credential_descriptors(existing_credential_ids),
+        "authenticatorSelection": {
+            "residentKey": "preferred",
+            "userVerification": "preferred",
+        },
+        "attestation": "none",
+    })
+}
+
+/// Get the options to pass to "navigator.credentials.get()" to log in with a passkey. If no
+/// credentials are allowed, the authenticator offers any passkey it has for the server.
+pub fn login_options(config: &Config, challenge: &str, allowed_credential_ids: &[String]) -> Value {
+    json!({
+        "challenge": challenge,
+        "rpId": config.webauthn_rp_id,
+        "timeout": config.webauthn_challenge_expiration_seconds * 1000,
+        "allowCredentials": credential_descriptors(allowed_credential_ids),
+        "userVerification": "preferred",
+    })
+}
+
+fn credential_descriptors(credential_ids: &[String]) -> Vec<Value> {
+    credential_ids
+        .iter()
+        .map(|id| json!({ "type": "public-key", "id": id }))
+        .collect()
+}
+
+/// Parse a credential returned by "navigator.credentials.create()" and check it was created for
+/// this server by a user that was present. Attestation isn't checked, as none is requested.
+pub fn parse_registration(config: &Config, credential: &str) -> Result<Registration> {
+    let credential = serde_json::from_str::<Credential<AttestationResponse>>(credential)?;
+    let client_data_json = decode(&credential.response.client_data_json)?;
+    let challenge = check_client_data(config, &client_data_json, "webauthn.create")?;
+
+    let attestation_object = decode(&credential.response.attestation_object)?;
+    let attestation_object = ciborium::de::from_reader::<CborValue, _>(&attestation_object[..])
+        .map_err(|error| anyhow!("Invalid attestation object: {}", error))?;
+    let authenticator_data = map_entries(&attestation_object)?
+        .iter()
+        .find(|(key, _)| key.as_text() == Some("authData"))
+        .and_then(|(_, value)| value.as_bytes())
+        .ok_or_else(|| anyhow!("Attestation object is missing authenticator data"))?;
+
+    let authenticator_data = parse_authenticator_data(authenticator_data)?;
+    check_authenticator_data(config, &authenticator_data)?;
+    let (credential_id, public_key) = authenticator_data
+        .attested_credential
+        .ok_or_else(|| anyhow!("Authenticator data is missing the created credential"))?;
+    if encode(credential_id) != credential.raw_id.trim_end_matches('=') {
+        return Err(anyhow!("Credential ID doesn't match authenticator data"));
+    }
+    // Reject keys that could never be used to log in.
+    parse_public_key(public_key)?;
+
+    Ok(Registration {
+        challenge,
+        credential_id: encode(credential_id),
+        public_key: public_key.to_vec(),
+        sign_count: authenticator_data.sign_count,
+    })
+}
+
+/// Parse a credential returned by "navigator.credentials.get()" and check it was used for this
+/// server by a user that was present.
+pub fn parse_assertion(config: &Config, credential: &str) -> Result<Assertion> {
+    let credential = serde_json::from_str::<Credential<AssertionResponse>>(credential)?;
+    let client_data_json = decode(&credential.response.client_data_json)?;
+    let challenge = check_client_data(config, &client_data_json, "webauthn.get")?;
+
+    let mut signed_data = decode(&credential.response.authenticator_data)?;
+    let authenticator_data = parse_authenticator_data(&signed_data)?;
+    check_authenticator_data(config, &authenticator_data)?;
+    let sign_count = authenticator_data.sign_count;
+
+    let user_handle = match credential.response.user_handle.as_deref() {
+        Some(user_handle) if !user_handle.is_empty() => {
+            Some(Uuid::from_slice(&decode(user_handle)?)?)
+        }
+        _ => None,
+    };
+
+    signed_data.extend_from_slice(&Sha256::digest(&client_data_json));
+    Ok(Assertion {
+        challenge,
+        credential_id: credential.raw_id.trim_end_matches('=').into(),
+        user_handle,
+        sign_count,
+        signed_data,
+        signature: decode(&credential.response.signature)?,
+    })
+}
+
+/// Check client data was collected for the expected ceremony on the configured origin, returning
+/// the challenge it includes.
+fn check_client_data(config: &Config, client_data_json: &[u8], kind: &str) -> Result<String> {
+    let client_data = serde_json::from_slice::<ClientData>(client_data_json)?;
+    if client_data.kind != kind {
+        return Err(anyhow!(
+            "Expected client data for {}, not {}",
+            kind,
+            client_data.kind
+        ));
+    }
+    if client_data.origin != config.webauthn_origin {
+        return Err(anyhow!("Unexpected origin: {}", client_data.origin));
+    }
+
+    Ok(client_data.challenge.trim_end_matches('=').into())
+}
+
+fn check_authenticator_data(config: &Config, data: &AuthenticatorData<'_>) -> Result<()> {
+    if data.rp_id_hash != Sha256::digest(config.webauthn_rp_id.as_bytes()).as_slice() {
+        return Err(anyhow!("Credential is for a different RP ID"));
+    }
+    if data.flags & USER_PRESENT_FLAG == 0 {
+        return Err(anyhow!("User wasn't present"));
+    }
+
+    Ok(())
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData<'_>> {
+    if data.len() < AUTHENTICATOR_DATA_HEADER_LENGTH {
+        return Err(anyhow!("Authenticator data is too short"));
+    }
+    let flags = data[32];
+    let mut sign_count = [0; 4];
+    sign_count.copy_from_slice(&data[33..37]);
+
+    let attested_credential = if flags & ATTESTED_CREDENTIAL_DATA_FLAG != 0 {
+        let rest = data
+            .get(AUTHENTICATOR_DATA_HEADER_LENGTH + AAGUID_LENGTH..)
+            .filter(|rest| rest.len() >= 2)
+            .ok_or_else(|| anyhow!("Attested credential data is too short"))?;
+        let id_length = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let id = rest
+            .get(2..2 + id_length)
+            .ok_or_else(|| anyhow!("Credential ID is too short"))?;
+
+        // The public key is followed by any extensions, so its length is only known once parsed.
+        let public_key = &rest[2 + id_length..];
+        let mut remaining = public_key;
+        ciborium::de::from_reader::<CborValue, _>(&mut remaining)
+            .map_err(|error| anyhow!("Invalid credential public key: {}", error))?;
+        Some((id, &public_key[..public_key.len() - remaining.len()]))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: &data[..32],
+        flags,
+        sign_count: u32::from_be_bytes(sign_count),
+        attested_credential,
+    })
+}
+
+/// A public key decoded from its COSE encoding.
+enum PublicKey {
+    /// An uncompressed P-256 point.
+    Es256(Vec<u8>),
+    /// An RSA modulus and exponent.
+    Rs256(Vec<u8>, Vec<u8>),
+}
+
+fn parse_public_key(public_key: &[u8]) -> Result<PublicKey> {
+    let key = ciborium::de::from_reader::<CborValue, _>(public_key)
+        .map_err(|error| anyhow!("Invalid credential public key: {}", error))?;
+    let entries = map_entries(&key)?;
+    let parameter = |label: i128| {
+        entries
+            .iter()
+            .find(|(key, _)| key.as_integer().map(i128::from) == Some(label))
+            .map(|(_, value)| value)
+    };
+    let bytes = |label: i128| {
+        parameter(label)
+            .and_then(CborValue::as_bytes)
+            .ok_or_else(|| anyhow!("Public key is missing parameter {}", label))
+    };
+
+    match parameter(3).and_then(CborValue::as_integer).map(i128::from) {
+        Some(ES256) => {
+            let (x, y) = (bytes(-2)?, bytes(-3)?);
+            if x.len() != 32 || y.len() != 32 {
+                return Err(anyhow!("Invalid P-256 public key"));
+            }
+            let mut point = vec![0x04];
+            point.extend_from_slice(x);
+            point.extend_from_slice(y);
+            Ok(PublicKey::Es256(point))
+        }
+        Some(RS256) => Ok(PublicKey::Rs256(bytes(-1)?.clone(), bytes(-2)?.clone())),
+        Some(algorithm) => Err(anyhow!("Unsupported public key algorithm: {}", algorithm)),
+        None => Err(anyhow!("Public key is missing its algorithm")),
+    }
+}
+
+fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<()> {
+    let result = match parse_public_key(public_key)? {
+        PublicKey::Es256(point) => {
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, point).verify(message, signature)
+        }
+        PublicKey::Rs256(n, e) => {
+            RsaPublicKeyComponents { n, e }.verify(&RSA_PKCS1_2048_8192_SHA256, message, signature)
+        }
+    };
+
+    result.map_err(|_| anyhow!("Invalid signature"))
+}
+
+fn map_entries(value: &CborValue) -> Result<&Vec<(CborValue, CborValue)>> {
+    value.as_map().ok_or_else(|| anyhow!("Expected a CBOR map"))
+}
+
+/// Encode bytes as unpadded base64url, which is how WebAuthn sends binary data over JSON.
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Decode base64url, with or without padding.
+fn decode(value: &str) -> Result<Vec<u8>> {
+    Ok(base64::decode_config(
+        value.trim_end_matches('='),
+        base64::URL_SAFE_NO_PAD,
+    )?)
+}
@@ -0,0 +1,175 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use juniper::GraphQLEnum;
+use serde::Deserialize;
+use sha2::Sha256;
+
+#[cfg(feature = "webhooks")]
+use crate::config::Config;
+
+/// Default number of webhook events returned when listing them.
+pub const DEFAULT_PAGE_SIZE: i32 = 50;
+/// Maximum number of webhook events returned when listing them.
+pub const MAX_PAGE_SIZE: i32 = 100;
+/// Maximum age of a webhook request's signature. Older requests are rejected so captured requests
+/// can't be replayed.
+const SIGNATURE_TOLERANCE_SECONDS: i64 = 300;
+
+/// A service that sends webhook events to the server.
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[graphql(description = "A service that sends webhook events to the server.")]
+pub enum WebhookProvider {
+    #[graphql(description = "Stripe, which sends events about subscriptions.")]
+    Stripe,
+}
+
+impl WebhookProvider {
+    /// The name the provider is stored under in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookProvider::Stripe => "stripe",
+        }
+    }
+
+    /// How the provider signs webhook requests.
+    pub fn signature_scheme(self) -> SignatureScheme {
+        match self {
+            WebhookProvider::Stripe => SignatureScheme::TimestampedHmacSha256 {
+                header: "Stripe-Signature",
+            },
+        }
+    }
+
+    /// Get the secret the provider signs webhook requests with, or none if it isn't configured.
+    #[cfg(feature = "webhooks")]
+    pub fn secret(self, config: &Config) -> Option<&str> {
+        match self {
+            WebhookProvider::Stripe => config.stripe_webhook_secret.as_deref(),
+        }
+    }
+
+    /// Get the ID and kind of a webhook event from its payload. Providers resend events with the
+    /// same ID, so the ID is used to only process each event once.
+    pub fn parse_event(self, payload: &[u8]) -> Result<(String, String)> {
+        match self {
+            WebhookProvider::Stripe => {
+                let event: StripeEventHeader = serde_json::from_slice(payload)?;
+                Ok((event.id, event.kind))
+            }
+        }
+    }
+}
+
+impl Display for WebhookProvider {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FormatResult {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for WebhookProvider {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "stripe" => Ok(WebhookProvider::Stripe),
+            _ => Err(anyhow!("Unknown webhook provider: {}", value)),
+        }
+    }
+}
+
+/// The fields every Stripe event has that identify it.
+#[derive(Debug, Deserialize)]
+struct StripeEventHeader {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// A way webhook requests are signed, so the server can tell they were sent by the provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// The header holds a timestamp and one or more HMAC-SHA256 signatures of the timestamp and
+    /// payload, made with the webhook's signing secret, as in "t=...,v1=...". Stripe signs
+    /// webhooks this way.
+    TimestampedHmacSha256 {
+        /// The header the signatures are sent in.
+        header: &'static str,
+    },
+}
+
+impl SignatureScheme {
+    /// The header the signature of a request is sent in.
+    pub fn header(self) -> &'static str {
+        match self {
+            SignatureScheme::TimestampedHmacSha256 { header } => header,
+        }
+    }
+
+    /// Verify the signature of a webhook request, given the value of its signature header.
+    /// Signatures are compared in constant time, and timestamped signatures older than five
+    /// minutes are rejected.
+    pub fn verify(self, payload: &[u8], signature: &str, secret: &str, now: DateTime<Utc>) -> bool {
+        match self {
+            SignatureScheme::TimestampedHmacSha256 { .. } => {
+                verify_timestamped_hmac_sha256(payload, signature, secret, now)
+            }
+        }
+    }
+}
+
+/// Verify a timestamped HMAC-SHA256 signature header, such as "t=...,v1=...,v1=...". Any of the
+/// signatures may match, so secrets can be rolled without dropping events.
+fn verify_timestamped_hmac_sha256(
+    payload: &[u8],
+    header: &str,
+    secret: &str,
+    now: DateTime<Utc>,
+) -> bool {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+            Some(("v1", value)) => signatures.push(value),
+            _ => {}
+        }
+    }
+
+    let timestamp = match timestamp {
+        Some(timestamp) if (now.timestamp() - timestamp).abs() <= SIGNATURE_TOLERANCE_SECONDS => {
+            timestamp
+        }
+        _ => return false,
+    };
+
+    signatures
+        .into_iter()
+        .filter_map(decode_hex)
+        .any(|signature| {
+            let mut mac = match Hmac::<Sha256>::new_varkey(secret.as_bytes()) {
+                Ok(mac) => mac,
+                Err(_) => return false,
+            };
+            mac.update(timestamp.to_string().as_bytes());
+            mac.update(b".");
+            mac.update(payload);
+            // This compares the signatures in constant time.
+            mac.verify(&signature).is_ok()
+        })
+}
+
+/// Decode a hex string into bytes. This will return none if the string isn't valid hex.
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+        .collect()
+}